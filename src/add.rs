@@ -0,0 +1,122 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use walkdir::WalkDir;
+
+use crate::{
+    check_ignore_path, gitignore_read, index_entry_stat_clean, index_read, index_write,
+    object_hash, path_matches, repo_find, repo_relative_path, worktree_file_mode, Index,
+    IndexEntry, Repository,
+};
+
+pub fn add(paths: &[PathBuf]) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let specs = paths
+        .iter()
+        .map(|p| repo_relative_path(&repo, p))
+        .collect::<Result<Vec<_>>>()?;
+    let mut index = index_read(&repo)?;
+    if index_add_paths(&repo, &mut index, &specs)? {
+        index_write(&repo, &index)?;
+    }
+    Ok(())
+}
+
+/// Stages the worktree state of everything under `specs`: new and modified
+/// files are hashed into the object database, and tracked files that are
+/// gone are removed from the index. Staging a path resolves its conflicts.
+/// Files whose stat data matches their entry aren't read at all. Returns
+/// whether the index changed and needs writing.
+pub fn index_add_paths(repo: &Repository, index: &mut Index, specs: &[String]) -> Result<bool> {
+    let ignore = gitignore_read(repo)?;
+    // The entries are sorted by name, so tracked paths are found by bisection.
+    let find = |index: &Index, name: &str| {
+        index
+            .entries
+            .binary_search_by(|e| e.name.as_str().cmp(name))
+            .ok()
+    };
+    let mut files = Vec::new();
+    for spec in specs {
+        let full_path = repo.worktree.join(spec);
+        let tracked = index.entries.iter().any(|e| path_matches(spec, &e.name));
+        if fs::symlink_metadata(&full_path).is_err() {
+            if !tracked {
+                bail!("pathspec '{}' did not match any files", spec);
+            }
+            continue;
+        }
+        for entry in WalkDir::new(&full_path).sort_by_file_name() {
+            let entry = entry?;
+            let path = entry.path();
+            if path.starts_with(&repo.gitdir)
+                || path.starts_with(repo.worktree.join(".git"))
+                || entry.file_type().is_dir()
+            {
+                continue;
+            }
+            let rel = path
+                .strip_prefix(&repo.worktree)?
+                .to_string_lossy()
+                .to_string();
+            let is_tracked = find(index, &rel).is_some();
+            if !is_tracked && check_ignore_path(&ignore, Path::new(&rel)) {
+                continue;
+            }
+            files.push(rel);
+        }
+    }
+
+    // Tracked files that no longer exist are staged as deletions, except
+    // those sparse checkout leaves out.
+    let count = index.entries.len();
+    index.entries.retain(|e| {
+        !specs.iter().any(|s| path_matches(s, &e.name))
+            || e.flag_skip_worktree
+            || fs::symlink_metadata(repo.worktree.join(&e.name)).is_ok()
+    });
+    let mut changed = index.entries.len() != count;
+
+    let mut staged = Vec::new();
+    for name in files {
+        let full_path = repo.worktree.join(&name);
+        let meta = fs::symlink_metadata(&full_path)?;
+        let mode = worktree_file_mode(&meta);
+        // A lone stage 0 entry with the same mode and stat data is up to date.
+        let current = find(index, &name).filter(|&i| {
+            index.entries[i].flag_stage == 0
+                && index
+                    .entries
+                    .get(i + 1)
+                    .is_none_or(|next| next.name != name)
+        });
+        if current.is_some_and(|i| {
+            let entry = &index.entries[i];
+            entry.tree_mode() == mode && index_entry_stat_clean(repo, entry, &meta)
+        }) {
+            continue;
+        }
+        let sha = if meta.file_type().is_symlink() {
+            let target = fs::read_link(&full_path)?;
+            object_hash(target.to_string_lossy().as_bytes(), b"blob", Some(repo))?
+        } else {
+            object_hash(File::open(&full_path)?, b"blob", Some(repo))?
+        };
+        staged.push(IndexEntry::from_file(repo, &name, &sha, &mode)?);
+    }
+    if staged.is_empty() {
+        return Ok(changed);
+    }
+    changed = true;
+    let names: HashSet<&str> = staged.iter().map(|e| e.name.as_str()).collect();
+    index.entries.retain(|e| !names.contains(e.name.as_str()));
+    index.entries.extend(staged);
+    index
+        .entries
+        .sort_by(|a, b| a.name.cmp(&b.name).then(a.flag_stage.cmp(&b.flag_stage)));
+    Ok(changed)
+}
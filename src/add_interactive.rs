@@ -0,0 +1,405 @@
+use std::{io::Write, path::Path};
+
+use anyhow::Result;
+
+use crate::{
+    diff_entry_content, diff_file, diff_map_index, diff_map_tree, diff_map_worktree, hunk_ranges,
+    hunk_render, index_add_paths, index_read, index_write, is_binary, menu_choose, menu_command,
+    myers_diff, object_write_raw, prompt_choice, ref_resolve, repo_find, restore_paths,
+    split_lines, status_untracked, tree_mode_split, DiffEntry, DiffMap, DiffOp, IgnoreSubmodules,
+    IndexEntry, Repository,
+};
+
+const ADD_COMMANDS: [&str; 8] = [
+    "status",
+    "update",
+    "revert",
+    "add untracked",
+    "patch",
+    "diff",
+    "quit",
+    "help",
+];
+
+/// The header of the file lists, aligned with their numbered lines.
+const STATUS_HEADER: &str = "          staged     unstaged path";
+
+/// A tracked file with staged or unstaged changes, as `add -i` lists it.
+struct FileStatus {
+    path: String,
+    head: Option<DiffEntry>,
+    index: Option<DiffEntry>,
+    worktree: Option<DiffEntry>,
+}
+
+impl FileStatus {
+    fn staged(&self) -> bool {
+        self.head != self.index
+    }
+
+    fn unstaged(&self) -> bool {
+        self.index != self.worktree
+    }
+}
+
+/// `add -i`: a menu to review and stage changes file by file or hunk by
+/// hunk.
+pub fn add_interactive() -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    add_status_print(&repo, &add_files(&repo)?)?;
+    loop {
+        let Some(command) = menu_command(&ADD_COMMANDS, 4, "What now")? else {
+            return Ok(());
+        };
+        let files = add_files(&repo)?;
+        match ADD_COMMANDS[command] {
+            "status" => add_status_print(&repo, &files)?,
+            "update" => {
+                let paths = add_choose(&repo, &files, FileStatus::unstaged, "Update")?;
+                let mut index = index_read(&repo)?;
+                index_add_paths(&repo, &mut index, &paths)?;
+                index_write(&repo, &index)?;
+                println!(
+                    "updated {} path{}",
+                    paths.len(),
+                    if paths.len() == 1 { "" } else { "s" }
+                );
+            }
+            "revert" => {
+                let paths = add_choose(&repo, &files, FileStatus::staged, "Revert")?;
+                add_revert(&repo, &paths)?;
+                println!(
+                    "reverted {} path{}",
+                    paths.len(),
+                    if paths.len() == 1 { "" } else { "s" }
+                );
+            }
+            "add untracked" => {
+                let untracked = status_untracked(&repo, &index_read(&repo)?)?;
+                if untracked.is_empty() {
+                    println!("No untracked files.");
+                    continue;
+                }
+                let chosen = menu_choose("", &untracked, "Add untracked")?;
+                let paths: Vec<String> = chosen.into_iter().map(|i| untracked[i].clone()).collect();
+                let mut index = index_read(&repo)?;
+                index_add_paths(&repo, &mut index, &paths)?;
+                index_write(&repo, &index)?;
+                println!(
+                    "added {} path{}",
+                    paths.len(),
+                    if paths.len() == 1 { "" } else { "s" }
+                );
+            }
+            "patch" => {
+                let paths = add_choose(&repo, &files, FileStatus::unstaged, "Patch update")?;
+                add_patch(&repo, &paths)?;
+            }
+            "diff" => {
+                let paths = add_choose(&repo, &files, FileStatus::staged, "Review diff")?;
+                let mut out = Vec::new();
+                for file in files.iter().filter(|f| paths.contains(&f.path)) {
+                    out.extend(diff_file(
+                        &repo,
+                        &file.path,
+                        file.head.as_ref(),
+                        file.index.as_ref(),
+                        3,
+                        false,
+                    )?);
+                }
+                std::io::stdout().write_all(&out)?;
+            }
+            "quit" => {
+                println!("Bye.");
+                return Ok(());
+            }
+            _ => println!(
+                "status        - show paths with changes\n\
+                 update        - add working tree state to the staged set of changes\n\
+                 revert        - revert staged set of changes back to the HEAD version\n\
+                 patch         - pick hunks and update selectively\n\
+                 diff          - view diff between HEAD and index\n\
+                 add untracked - add contents of untracked files to the staged set of changes"
+            ),
+        }
+    }
+}
+
+/// `add -p`: stages hunks of every file with unstaged changes.
+pub fn add_patch_all() -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let paths: Vec<String> = add_files(&repo)?
+        .into_iter()
+        .filter(FileStatus::unstaged)
+        .map(|f| f.path)
+        .collect();
+    if paths.is_empty() {
+        println!("No changes.");
+    }
+    add_patch(&repo, &paths)
+}
+
+/// Every tracked file that differs between HEAD, the index and the
+/// worktree.
+fn add_files(repo: &Repository) -> Result<Vec<FileStatus>> {
+    let head = match ref_resolve(repo, "HEAD")? {
+        Some(sha) => diff_map_tree(repo, &sha)?,
+        None => DiffMap::new(),
+    };
+    let index = diff_map_index(repo)?;
+    let worktree = diff_map_worktree(repo, IgnoreSubmodules::None)?;
+    let mut paths: Vec<&String> = head.keys().chain(index.keys()).collect();
+    paths.sort();
+    paths.dedup();
+    Ok(paths
+        .into_iter()
+        .map(|path| FileStatus {
+            path: path.clone(),
+            head: head.get(path).cloned(),
+            index: index.get(path).cloned(),
+            worktree: worktree.get(path).cloned(),
+        })
+        .filter(|f| f.staged() || f.unstaged())
+        .collect())
+}
+
+/// "+added/-deleted" between two sides, "unchanged"/`same` when equal.
+fn add_numstat(
+    repo: &Repository,
+    path: &str,
+    old: Option<&DiffEntry>,
+    new: Option<&DiffEntry>,
+    same: &str,
+) -> Result<String> {
+    if old == new {
+        return Ok(same.to_string());
+    }
+    let content = |e: Option<&DiffEntry>| match e {
+        Some(e) => diff_entry_content(repo, path, e),
+        None => Ok(Vec::new()),
+    };
+    let (a, b) = (content(old)?, content(new)?);
+    if is_binary(&a) || is_binary(&b) {
+        return Ok("binary".to_string());
+    }
+    let ops = myers_diff(&split_lines(&a), &split_lines(&b));
+    let added = ops
+        .iter()
+        .filter(|op| matches!(op, DiffOp::Insert(_)))
+        .count();
+    let deleted = ops
+        .iter()
+        .filter(|op| matches!(op, DiffOp::Delete(_)))
+        .count();
+    Ok(format!("+{}/-{}", added, deleted))
+}
+
+fn add_status_lines(repo: &Repository, files: &[&FileStatus]) -> Result<Vec<String>> {
+    files
+        .iter()
+        .map(|f| {
+            let staged = add_numstat(
+                repo,
+                &f.path,
+                f.head.as_ref(),
+                f.index.as_ref(),
+                "unchanged",
+            )?;
+            let unstaged = add_numstat(
+                repo,
+                &f.path,
+                f.index.as_ref(),
+                f.worktree.as_ref(),
+                "nothing",
+            )?;
+            Ok(format!("{:>12} {:>12} {}", staged, unstaged, f.path))
+        })
+        .collect()
+}
+
+fn add_status_print(repo: &Repository, files: &[FileStatus]) -> Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    println!(" {}", STATUS_HEADER);
+    let files: Vec<&FileStatus> = files.iter().collect();
+    for (i, line) in add_status_lines(repo, &files)?.iter().enumerate() {
+        println!("{:>3}: {}", i + 1, line);
+    }
+    println!();
+    Ok(())
+}
+
+/// Lets the user pick among the files `filter` keeps. Returns their paths.
+fn add_choose(
+    repo: &Repository,
+    files: &[FileStatus],
+    filter: fn(&FileStatus) -> bool,
+    prompt: &str,
+) -> Result<Vec<String>> {
+    let files: Vec<&FileStatus> = files.iter().filter(|f| filter(f)).collect();
+    if files.is_empty() {
+        println!("No changes.");
+        return Ok(Vec::new());
+    }
+    let lines = add_status_lines(repo, &files)?;
+    let chosen = menu_choose(STATUS_HEADER, &lines, prompt)?;
+    Ok(chosen.into_iter().map(|i| files[i].path.clone()).collect())
+}
+
+/// Puts the HEAD version of `paths` back into the index, dropping paths
+/// HEAD doesn't have.
+fn add_revert(repo: &Repository, paths: &[String]) -> Result<()> {
+    if ref_resolve(repo, "HEAD")?.is_some() {
+        return restore_paths(repo, paths, Some("HEAD"), true, false);
+    }
+    let mut index = index_read(repo)?;
+    index.entries.retain(|e| !paths.contains(&e.name));
+    index_write(repo, &index)
+}
+
+/// Offers the hunks between the index and the worktree of each of `paths`
+/// one at a time and stages the accepted ones.
+fn add_patch(repo: &Repository, paths: &[String]) -> Result<()> {
+    let worktree = diff_map_worktree(repo, IgnoreSubmodules::None)?;
+    'files: for path in paths {
+        let mut index = index_read(repo)?;
+        let Some(pos) = index.entries.iter().position(|e| &e.name == path) else {
+            continue;
+        };
+        let Some(new) = worktree.get(path) else {
+            println!("Only deletions of {} can be staged with update.", path);
+            continue;
+        };
+        let entry = &index.entries[pos];
+        let old = DiffEntry {
+            mode: entry.tree_mode(),
+            sha: entry.sha.clone(),
+            worktree: false,
+            dirty: false,
+        };
+        let (old_data, new_data) = (
+            diff_entry_content(repo, path, &old)?,
+            diff_entry_content(repo, path, new)?,
+        );
+        if is_binary(&old_data) || is_binary(&new_data) {
+            println!("Cannot stage hunks of binary file {}.", path);
+            continue;
+        }
+        let (a, b) = (split_lines(&old_data), split_lines(&new_data));
+        let ops = myers_diff(&a, &b);
+        let hunks = hunk_ranges(&ops, 3);
+        if hunks.is_empty() {
+            continue;
+        }
+
+        println!(
+            "diff --git a/{} b/{}\n--- a/{}\n+++ b/{}",
+            path, path, path, path
+        );
+        let mut accepted = vec![false; hunks.len()];
+        let mut quit = false;
+        let mut i = 0;
+        while i < hunks.len() {
+            std::io::stdout().write_all(&hunk_render(&ops, hunks[i].clone(), &a, &b))?;
+            let prompt = format!(
+                "({}/{}) Stage this hunk [y,n,q,a,d,?]? ",
+                i + 1,
+                hunks.len()
+            );
+            let Some(answer) = prompt_choice(&prompt, "ynqad?")? else {
+                quit = true;
+                break;
+            };
+            match answer {
+                'y' => accepted[i] = true,
+                'n' => {}
+                'q' => {
+                    quit = true;
+                    break;
+                }
+                'a' => {
+                    accepted[i..].fill(true);
+                    break;
+                }
+                'd' => break,
+                _ => {
+                    println!(
+                        "y - stage this hunk\n\
+                         n - do not stage this hunk\n\
+                         q - quit; do not stage this hunk or any of the remaining ones\n\
+                         a - stage this hunk and all later hunks in the file\n\
+                         d - do not stage this hunk or any of the later hunks in the file\n\
+                         ? - print help"
+                    );
+                    continue;
+                }
+            }
+            i += 1;
+        }
+
+        if accepted.contains(&true) {
+            let staged = hunks_apply(&ops, &hunks, &accepted, &a, &b);
+            let sha = object_write_raw(b"blob", &staged, Some(repo))?;
+            let (mode_type, mode_perms) = tree_mode_split(&old.mode)?;
+            // No stat data, so the worktree file is rehashed next time.
+            index.entries[pos] = IndexEntry {
+                mode_type,
+                mode_perms,
+                sha,
+                name: path.clone(),
+                ..Default::default()
+            };
+            index_write(repo, &index)?;
+        }
+        if quit {
+            break 'files;
+        }
+    }
+    Ok(())
+}
+
+/// The old content with the `accepted` hunks of the edit script applied.
+fn hunks_apply(
+    ops: &[DiffOp],
+    hunks: &[std::ops::Range<usize>],
+    accepted: &[bool],
+    a: &[&[u8]],
+    b: &[&[u8]],
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (n, op) in ops.iter().enumerate() {
+        let apply = hunks
+            .iter()
+            .zip(accepted)
+            .any(|(range, &yes)| yes && range.contains(&n));
+        match (*op, apply) {
+            (DiffOp::Equal(i, _), _) | (DiffOp::Delete(i), false) => out.extend_from_slice(a[i]),
+            (DiffOp::Insert(j), true) => out.extend_from_slice(b[j]),
+            _ => {}
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hunks_apply;
+    use crate::{hunk_ranges, myers_diff, split_lines};
+
+    #[test]
+    fn test_hunks_apply() {
+        let old = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = b"one\n2\n3\n4\n5\n6\n7\n8\n9\nten\n";
+        let (a, b) = (split_lines(old), split_lines(new));
+        let ops = myers_diff(&a, &b);
+        let hunks = hunk_ranges(&ops, 3);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(
+            hunks_apply(&ops, &hunks, &[false, true], &a, &b),
+            b"1\n2\n3\n4\n5\n6\n7\n8\n9\nten\n"
+        );
+        assert_eq!(hunks_apply(&ops, &hunks, &[true, true], &a, &b), new);
+    }
+}
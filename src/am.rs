@@ -0,0 +1,436 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    apply_patches, checkout_tree, commit_create, date_now, date_parse_rfc2822, file_write_locked,
+    gc_auto, head_update, index_read, index_write_tree, object_find, patch_parse, ref_resolve,
+    repo_dir, repo_file, repo_find, reset_to, stripspace_text, ApplyOptions, Repository, ResetMode,
+};
+
+const AM_DIR: &str = "rebase-apply";
+
+/// A patch mail, split into what the commit needs.
+pub struct MailPatch {
+    /// "Name <email>".
+    pub author: String,
+    pub date: Option<(i64, String)>,
+    pub subject: String,
+    /// The message below the subject, cleaned up.
+    pub body: String,
+    /// The diff, from its first line on.
+    pub patch: Vec<u8>,
+}
+
+impl MailPatch {
+    pub fn message(&self) -> String {
+        match self.body.is_empty() {
+            true => format!("{}\n", self.subject),
+            false => format!("{}\n\n{}", self.subject, self.body),
+        }
+    }
+}
+
+/// Applies the patch mails in `paths`, mailboxes or single messages, one
+/// commit each, keeping their authors. When one does not apply, stops
+/// for the user to fix it up and `cont`inue, `skip` it or `abort`.
+pub fn am(paths: &[PathBuf], three_way: bool, abort: bool, cont: bool, skip: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let in_progress = repo_dir(&repo, PathBuf::from(AM_DIR), false)?.is_some();
+
+    if abort || cont || skip {
+        if !in_progress {
+            bail!("Resolve operation not in progress, we are not resuming.");
+        }
+        return if abort {
+            am_abort(&repo)
+        } else if skip {
+            am_skip(&repo)
+        } else {
+            am_continue(&repo)
+        };
+    }
+    if in_progress {
+        bail!(
+            "previous rebase directory .git/{} still exists but mbox given.\n\
+             Use \"rit am --continue\", \"rit am --skip\" or \"rit am --abort\".",
+            AM_DIR
+        );
+    }
+    if paths.is_empty() {
+        bail!("Usage: rit am <mbox>...");
+    }
+
+    let mut mails = Vec::new();
+    for path in paths {
+        let data =
+            fs::read(path).map_err(|e| anyhow!("could not open '{}': {}", path.display(), e))?;
+        mails.extend(mbox_split(&data).into_iter().map(<[u8]>::to_vec));
+    }
+    if mails.is_empty() {
+        bail!("Patch format detection failed.");
+    }
+    if let Some(head) = ref_resolve(&repo, "HEAD")? {
+        let tree = index_write_tree(&repo, &index_read(&repo)?)?;
+        if object_find(&repo, &head, Some(b"tree"), true)?.as_deref() != Some(tree.as_str()) {
+            bail!("Dirty index: cannot apply patches");
+        }
+        am_state_write(&repo, "orig-head", &head)?;
+    }
+    for (i, mail) in mails.iter().enumerate() {
+        fs::write(am_state_path(&repo, &format!("{:04}", i + 1))?, mail)?;
+    }
+    am_state_write(&repo, "next", "1")?;
+    am_state_write(&repo, "last", &mails.len().to_string())?;
+    if three_way {
+        am_state_write(&repo, "threeway", "")?;
+    }
+    am_run(&repo)
+}
+
+fn am_state_path(repo: &Repository, name: &str) -> Result<PathBuf> {
+    repo_file(repo, PathBuf::from(AM_DIR).join(name), true)
+}
+
+fn am_state_write(repo: &Repository, name: &str, value: &str) -> Result<()> {
+    let value = if value.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n", value)
+    };
+    file_write_locked(&am_state_path(repo, name)?, value.as_bytes(), false)?;
+    Ok(())
+}
+
+fn am_state_read(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let path = am_state_path(repo, name)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?.trim_end().to_string()))
+}
+
+/// The position of the current patch, and of the last.
+fn am_position(repo: &Repository) -> Result<(usize, usize)> {
+    let read = |name| -> Result<usize> {
+        am_state_read(repo, name)?
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| anyhow!("am state is missing '{}'", name))
+    };
+    Ok((read("next")?, read("last")?))
+}
+
+fn am_current(repo: &Repository, next: usize) -> Result<MailPatch> {
+    let data = fs::read(am_state_path(repo, &format!("{:04}", next))?)?;
+    mail_parse(&data)
+}
+
+/// Applies and commits the patches from the current one on.
+fn am_run(repo: &Repository) -> Result<()> {
+    let three_way = am_state_path(repo, "threeway")?.exists();
+    let (mut next, last) = am_position(repo)?;
+    while next <= last {
+        let mail = am_current(repo, next)?;
+        println!("Applying: {}", mail.subject);
+        let applied = patch_parse(&mail.patch).and_then(|patches| {
+            if patches.is_empty() {
+                bail!("No changes -- Patch already applied.");
+            }
+            let opts = ApplyOptions {
+                index: true,
+                three_way,
+                ..Default::default()
+            };
+            apply_patches(repo, &patches, &opts)
+        });
+        if let Err(e) = applied {
+            eprintln!("error: {}", e);
+            bail!(
+                "Patch failed at {:04} {}\n\
+                 When you have resolved this problem, run \"rit am --continue\".\n\
+                 If you prefer to skip this patch, run \"rit am --skip\" instead.\n\
+                 To restore the original branch and stop patching, run \"rit am --abort\".",
+                next,
+                mail.subject
+            );
+        }
+        am_commit(repo, &mail)?;
+        next += 1;
+        am_state_write(repo, "next", &next.to_string())?;
+    }
+    fs::remove_dir_all(repo.repo_path(PathBuf::from(AM_DIR)))?;
+    gc_auto(repo);
+    Ok(())
+}
+
+/// Commits the index on HEAD with the mail's author and message.
+fn am_commit(repo: &Repository, mail: &MailPatch) -> Result<()> {
+    let tree = index_write_tree(repo, &index_read(repo)?)?;
+    let head = ref_resolve(repo, "HEAD")?;
+    let date = match &mail.date {
+        Some((time, tz)) => format!("{} {}", time, tz),
+        None => date_now()?,
+    };
+    let author = format!("{} {}", mail.author, date);
+    let message = mail.message();
+    let parents: Vec<String> = head.into_iter().collect();
+    let sha = commit_create(repo, &tree, &parents, Some(&author), &message)?;
+    head_update(repo, &sha, &format!("am: {}", mail.subject))
+}
+
+/// Commits the user's resolution of the patch that stopped, then goes on.
+fn am_continue(repo: &Repository) -> Result<()> {
+    let (next, _) = am_position(repo)?;
+    let mail = am_current(repo, next)?;
+    let index = index_read(repo)?;
+    if index.entries.iter().any(|e| e.flag_stage >> 12 != 0) {
+        bail!("You still have unmerged paths in your index.\nYou might want to 'rit add' them.");
+    }
+    let tree = index_write_tree(repo, &index)?;
+    let head_tree = match ref_resolve(repo, "HEAD")? {
+        Some(head) => object_find(repo, &head, Some(b"tree"), true)?,
+        None => None,
+    };
+    if head_tree.as_deref() == Some(tree.as_str()) {
+        bail!(
+            "Applying: {}\n\
+             No changes - did you forget to use 'rit add'?\n\
+             If there is nothing left to stage, chances are that something else\n\
+             already introduced the same changes; you might want to skip this patch.",
+            mail.subject
+        );
+    }
+    println!("Applying: {}", mail.subject);
+    am_commit(repo, &mail)?;
+    am_state_write(repo, "next", &(next + 1).to_string())?;
+    am_run(repo)
+}
+
+/// Drops whatever the patch that stopped left behind and goes on.
+fn am_skip(repo: &Repository) -> Result<()> {
+    let (next, _) = am_position(repo)?;
+    if let Some(head) = ref_resolve(repo, "HEAD")? {
+        checkout_tree(repo, &head, true)?;
+    }
+    am_state_write(repo, "next", &(next + 1).to_string())?;
+    am_run(repo)
+}
+
+/// Puts the branch back where it was before `am` started.
+fn am_abort(repo: &Repository) -> Result<()> {
+    if let Some(orig_head) = am_state_read(repo, "orig-head")? {
+        reset_to(repo, &orig_head, ResetMode::Hard, "am --abort")?;
+    }
+    fs::remove_dir_all(repo.repo_path(PathBuf::from(AM_DIR)))?;
+    Ok(())
+}
+
+/// Splits a mailbox at its "From " lines. Anything that isn't one is a
+/// single message.
+pub fn mbox_split(data: &[u8]) -> Vec<&[u8]> {
+    if !data.starts_with(b"From ") {
+        return match data.is_empty() {
+            true => Vec::new(),
+            false => vec![data],
+        };
+    }
+    let mut starts = vec![0];
+    let mut pos = 0;
+    while let Some(len) = data[pos..].iter().position(|&b| b == b'\n') {
+        pos += len + 1;
+        if data[pos..].starts_with(b"From ") {
+            starts.push(pos);
+        }
+    }
+    starts.push(data.len());
+    starts.windows(2).map(|w| &data[w[0]..w[1]]).collect()
+}
+
+/// Splits a patch mail into author, date, subject, message and diff. Lines
+/// at the top of the body like "From: ..." override the headers, as for
+/// patches sent on someone else's behalf.
+pub fn mail_parse(data: &[u8]) -> Result<MailPatch> {
+    let text = String::from_utf8_lossy(data);
+    let mut lines = text.split_inclusive('\n').peekable();
+    if lines.peek().is_some_and(|l| l.starts_with("From ")) {
+        lines.next();
+    }
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in lines.by_ref() {
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.is_empty() {
+            break;
+        }
+        match (line.starts_with([' ', '\t']), headers.last_mut()) {
+            (true, Some((_, value))) => {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            _ => {
+                let (name, value) = line.split_once(':').unwrap_or((line, ""));
+                headers.push((name.to_lowercase(), value.trim().to_string()));
+            }
+        }
+    }
+    let mut body: Vec<&str> = lines.collect();
+    // In-body headers, then a blank line.
+    while let Some((name, value)) = body.first().and_then(|l| l.split_once(": ")) {
+        let name = name.to_lowercase();
+        if !matches!(name.as_str(), "from" | "date" | "subject") {
+            break;
+        }
+        headers.push((name, value.trim().to_string()));
+        body.remove(0);
+        if body.first().is_some_and(|l| l.trim().is_empty()) {
+            body.remove(0);
+        }
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| mail_header_decode(v))
+    };
+
+    let from =
+        header("from").ok_or_else(|| anyhow!("Patch does not have a valid e-mail address."))?;
+    let author = match from.rsplit_once('<') {
+        Some((name, email)) => {
+            let name = name.trim().trim_matches('"');
+            let email = email.trim_end_matches('>');
+            format!("{} <{}>", if name.is_empty() { email } else { name }, email)
+        }
+        None => format!("{} <{}>", from, from),
+    };
+
+    let diff_start = body
+        .iter()
+        .position(|l| {
+            l.starts_with("diff --git ") || l.starts_with("Index: ") || l.starts_with("--- ")
+        })
+        .unwrap_or(body.len());
+    let message_end = body[..diff_start]
+        .iter()
+        .position(|l| l.trim_end() == "---")
+        .unwrap_or(diff_start);
+    Ok(MailPatch {
+        author,
+        date: header("date").and_then(|d| date_parse_rfc2822(&d)),
+        subject: mail_subject_clean(&header("subject").unwrap_or_default()),
+        body: stripspace_text(&body[..message_end].concat(), None),
+        patch: body[diff_start..].concat().into_bytes(),
+    })
+}
+
+/// Drops the "Re:" and "[PATCH ...]" prefixes of a mail subject.
+fn mail_subject_clean(subject: &str) -> String {
+    let mut subject = subject.trim();
+    loop {
+        if let Some(rest) = subject.strip_prefix('[').and_then(|s| s.split_once(']')) {
+            subject = rest.1.trim_start();
+        } else if subject.len() >= 3 && subject[..3].eq_ignore_ascii_case("re:") {
+            subject = subject[3..].trim_start();
+        } else {
+            return subject.to_string();
+        }
+    }
+}
+
+/// Decodes the "=?charset?q?...?=" encoded words of a header, which
+/// format-patch uses for names that are not ASCII. Only UTF-8 and the
+/// Q encoding are understood; anything else is left alone.
+fn mail_header_decode(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let mut parts = rest[start + 2..].splitn(3, '?');
+        let (Some(charset), Some(encoding), Some(tail)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            break;
+        };
+        let Some(end) = tail.find("?=") else { break };
+        if !charset.eq_ignore_ascii_case("utf-8") || !encoding.eq_ignore_ascii_case("q") {
+            break;
+        }
+        // Whitespace between encoded words doesn't count.
+        let before = &rest[..start];
+        if !(after_word && before.trim().is_empty()) {
+            out.push_str(before);
+        }
+        let mut bytes = Vec::new();
+        let mut encoded = tail[..end].bytes();
+        while let Some(b) = encoded.next() {
+            match b {
+                b'_' => bytes.push(b' '),
+                b'=' => {
+                    let hex: Vec<u8> = encoded.by_ref().take(2).collect();
+                    let hex = std::str::from_utf8(&hex).unwrap_or_default();
+                    bytes.push(u8::from_str_radix(hex, 16).unwrap_or(b'?'));
+                }
+                b => bytes.push(b),
+            }
+        }
+        out.push_str(&String::from_utf8_lossy(&bytes));
+        after_word = true;
+        rest = &tail[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mail_parse, mbox_split};
+
+    #[test]
+    fn test_mail_parse() {
+        let mbox = b"From 1234 Mon Sep 17 00:00:00 2001\n\
+            From: =?UTF-8?q?J=C3=B6rg=20M?= <jm@example.com>\n\
+            Date: Tue, 3 Oct 2023 12:00:00 +0200\n\
+            Subject: [PATCH 1/2] Fix the\n \
+            frobnicator\n\
+            \n\
+            It was broken.   \n\
+            \n\
+            ---\n \
+            f | 2 +-\n\
+            \n\
+            diff --git a/f b/f\n\
+            --- a/f\n\
+            +++ b/f\n\
+            @@ -1 +1 @@\n\
+            -a\n\
+            +b\n\
+            -- \n\
+            2.40.0\n\
+            \n\
+            From 5678 Mon Sep 17 00:00:00 2001\n\
+            From: x@example.com\n\
+            Subject: Re: [PATCH 2/2] Second\n\
+            \n\
+            From: Other <o@example.com>\n\
+            \n\
+            diff --git a/g b/g\n";
+        let mails = mbox_split(mbox);
+        assert_eq!(mails.len(), 2);
+
+        let first = mail_parse(mails[0]).unwrap();
+        assert_eq!(first.author, "Jörg M <jm@example.com>");
+        assert_eq!(first.date, Some((1696327200, "+0200".to_string())));
+        assert_eq!(first.subject, "Fix the frobnicator");
+        assert_eq!(first.message(), "Fix the frobnicator\n\nIt was broken.\n");
+        assert!(first.patch.starts_with(b"diff --git a/f b/f\n"));
+
+        let second = mail_parse(mails[1]).unwrap();
+        assert_eq!(second.author, "Other <o@example.com>");
+        assert_eq!(second.subject, "Second");
+        assert_eq!(second.message(), "Second\n");
+    }
+}
@@ -0,0 +1,424 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    object_find, object_read, Commit, CommitGraph, Repository, GENERATION_NUMBER_INFINITY,
+};
+
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+#[derive(Clone)]
+struct CommitNode {
+    parents: Vec<String>,
+    generation: u64,
+    time: i64,
+}
+
+/// Answers reachability questions about commits. Parents and generation
+/// numbers come from the commit-graph when it covers a commit and from the
+/// object database otherwise; commits outside the graph have an infinite
+/// generation, which simply disables pruning below them.
+pub struct Ancestry<'a> {
+    repo: &'a Repository,
+    graph: Option<CommitGraph>,
+    nodes: HashMap<String, CommitNode>,
+    contains_target: Option<String>,
+    contains_memo: HashMap<String, bool>,
+}
+
+impl<'a> Ancestry<'a> {
+    pub fn new(repo: &'a Repository) -> Result<Self> {
+        Ok(Self {
+            repo,
+            graph: CommitGraph::load(repo)?,
+            nodes: HashMap::new(),
+            contains_target: None,
+            contains_memo: HashMap::new(),
+        })
+    }
+
+    fn node(&mut self, sha: &str) -> Result<CommitNode> {
+        if let Some(node) = self.nodes.get(sha) {
+            return Ok(node.clone());
+        }
+        let from_graph = self.graph.as_ref().and_then(|g| {
+            g.lookup(sha).map(|i| CommitNode {
+                parents: g.parents[i]
+                    .iter()
+                    .map(|&p| g.oids[p as usize].clone())
+                    .collect(),
+                generation: g.generations[i],
+                time: g.commit_times[i],
+            })
+        });
+        let node = match from_graph {
+            Some(node) => node,
+            None => {
+                let obj = object_read(self.repo, sha)?;
+                let commit = obj
+                    .as_any()
+                    .downcast_ref::<Commit>()
+                    .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+                CommitNode {
+                    parents: commit.parents(),
+                    generation: GENERATION_NUMBER_INFINITY,
+                    time: commit.commit_time(),
+                }
+            }
+        };
+        self.nodes.insert(sha.to_string(), node.clone());
+        Ok(node)
+    }
+
+    /// The parents of `sha`, in order.
+    pub fn parents(&mut self, sha: &str) -> Result<Vec<String>> {
+        Ok(self.node(sha)?.parents)
+    }
+
+    /// The committer time of `sha`.
+    pub fn time(&mut self, sha: &str) -> Result<i64> {
+        Ok(self.node(sha)?.time)
+    }
+
+    /// The generation number of `sha` (its corrected commit date when the
+    /// commit-graph has generation data).
+    pub fn generation(&mut self, sha: &str) -> Result<u64> {
+        Ok(self.node(sha)?.generation)
+    }
+
+    /// Whether `x` can be skipped when looking for an ancestor with
+    /// generation `min`: generations strictly increase from parent to child,
+    /// so nothing below a commit at or under `min` can be that ancestor.
+    fn below(generation: u64, min: u64) -> bool {
+        generation != GENERATION_NUMBER_INFINITY && generation <= min
+    }
+
+    /// Returns whether `ancestor` is reachable from `descendant`.
+    pub fn is_ancestor(&mut self, ancestor: &str, descendant: &str) -> Result<bool> {
+        let min = self.generation(ancestor)?;
+        let mut seen = std::collections::HashSet::new();
+        let mut stack = vec![descendant.to_string()];
+        while let Some(sha) = stack.pop() {
+            if sha == ancestor {
+                return Ok(true);
+            }
+            if !seen.insert(sha.clone()) {
+                continue;
+            }
+            let node = self.node(&sha)?;
+            if Self::below(node.generation, min) {
+                continue;
+            }
+            stack.extend(node.parents);
+        }
+        Ok(false)
+    }
+
+    /// Like `is_ancestor(commit, tip)`, but remembers intermediate answers
+    /// so asking about many tips for the same `commit` (as `--contains`
+    /// does) visits each commit at most once.
+    pub fn contains(&mut self, tip: &str, commit: &str) -> Result<bool> {
+        if self.contains_target.as_deref() != Some(commit) {
+            self.contains_target = Some(commit.to_string());
+            self.contains_memo.clear();
+        }
+        let min = self.generation(commit)?;
+
+        let mut stack = vec![(tip.to_string(), false)];
+        while let Some((sha, expanded)) = stack.pop() {
+            if self.contains_memo.contains_key(&sha) {
+                continue;
+            }
+            if sha == commit {
+                self.contains_memo.insert(sha, true);
+                continue;
+            }
+            let node = self.node(&sha)?;
+            if Self::below(node.generation, min) {
+                self.contains_memo.insert(sha, false);
+                continue;
+            }
+            if expanded {
+                let found = node
+                    .parents
+                    .iter()
+                    .any(|p| self.contains_memo.get(p) == Some(&true));
+                self.contains_memo.insert(sha, found);
+                continue;
+            }
+            stack.push((sha, true));
+            for p in node.parents {
+                if !self.contains_memo.contains_key(&p) {
+                    stack.push((p, false));
+                }
+            }
+        }
+        Ok(self.contains_memo[tip])
+    }
+
+    /// Returns the best common ancestors of `a` and `b`.
+    pub fn merge_bases(&mut self, a: &str, b: &str) -> Result<Vec<String>> {
+        if a == b {
+            return Ok(vec![a.to_string()]);
+        }
+
+        // Paint down from both sides, newest generation first; a commit
+        // painted from both sides is a candidate, and everything below a
+        // candidate becomes stale.
+        let mut flags: HashMap<String, u8> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        for (sha, flag) in [(a, PARENT1), (b, PARENT2)] {
+            let node = self.node(sha)?;
+            *flags.entry(sha.to_string()).or_default() |= flag;
+            queue.push((node.generation, node.time, sha.to_string()));
+        }
+
+        let mut result = Vec::new();
+        while queue.iter().any(|(_, _, sha)| flags[sha] & STALE == 0) {
+            let (_, _, sha) = queue.pop().unwrap();
+            let mut f = flags[&sha] & (PARENT1 | PARENT2 | STALE);
+            if f == PARENT1 | PARENT2 {
+                if flags[&sha] & RESULT == 0 {
+                    *flags.get_mut(&sha).unwrap() |= RESULT;
+                    result.push(sha.clone());
+                }
+                f |= STALE;
+            }
+            for p in self.node(&sha)?.parents {
+                let pf = flags.entry(p.clone()).or_default();
+                if *pf & f == f {
+                    continue;
+                }
+                *pf |= f;
+                let node = self.node(&p)?;
+                queue.push((node.generation, node.time, p));
+            }
+        }
+
+        let candidates: Vec<String> = result
+            .into_iter()
+            .filter(|c| flags[c] & STALE == 0)
+            .collect();
+        self.independent(&candidates)
+    }
+
+    /// The best common ancestors of `a` and a merge of all of `others`, as
+    /// `git merge-base a b c` finds them.
+    pub fn merge_bases_many(&mut self, a: &str, others: &[String]) -> Result<Vec<String>> {
+        let mut candidates: Vec<String> = Vec::new();
+        for other in others {
+            for base in self.merge_bases(a, other)? {
+                if !candidates.contains(&base) {
+                    candidates.push(base);
+                }
+            }
+        }
+        self.independent(&candidates)
+    }
+
+    /// `commits` without those reachable from another one of them.
+    fn independent(&mut self, commits: &[String]) -> Result<Vec<String>> {
+        let mut ret = Vec::new();
+        for c in commits {
+            let mut redundant = false;
+            for other in commits {
+                if other != c && self.is_ancestor(c, other)? {
+                    redundant = true;
+                    break;
+                }
+            }
+            if !redundant {
+                ret.push(c.clone());
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// A walk over the commits revisions select, newest first by committer
+/// date: those reachable from any plain revision but not from any `^rev`.
+/// `a..b` stands for `^a b`, and an empty side of it for HEAD.
+pub struct RevWalk<'a> {
+    ancestry: Ancestry<'a>,
+    seen: HashSet<String>,
+    /// Newest first; commits of the same date come out in the order they
+    /// were queued, as in git, rather than by sha.
+    queue: BinaryHeap<(i64, Reverse<u64>, String)>,
+    queued: u64,
+    since: Option<i64>,
+    until: Option<i64>,
+}
+
+impl<'a> RevWalk<'a> {
+    pub fn new(repo: &'a Repository, revs: &[String]) -> Result<Self> {
+        let resolve = |name: &str| {
+            let name = if name.is_empty() { "HEAD" } else { name };
+            object_find(repo, name, Some(b"commit"), true)?
+                .ok_or_else(|| anyhow!("bad revision '{}'", name))
+        };
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        for rev in revs {
+            if let Some((from, to)) = rev.split_once("..") {
+                exclude.push(resolve(from)?);
+                include.push(resolve(to)?);
+            } else if let Some(rev) = rev.strip_prefix('^') {
+                exclude.push(resolve(rev)?);
+            } else {
+                include.push(resolve(rev)?);
+            }
+        }
+
+        let mut ancestry = Ancestry::new(repo)?;
+        let mut seen = HashSet::new();
+        let mut stack = exclude;
+        while let Some(sha) = stack.pop() {
+            if seen.insert(sha.clone()) {
+                stack.extend(ancestry.parents(&sha)?);
+            }
+        }
+        let mut walk = Self {
+            ancestry,
+            seen,
+            queue: BinaryHeap::new(),
+            queued: 0,
+            since: None,
+            until: None,
+        };
+        for sha in include {
+            if walk.seen.insert(sha.clone()) {
+                walk.push(sha)?;
+            }
+        }
+        Ok(walk)
+    }
+
+    fn push(&mut self, sha: String) -> Result<()> {
+        let time = self.ancestry.time(&sha)?;
+        self.queue.push((time, Reverse(self.queued), sha));
+        self.queued += 1;
+        Ok(())
+    }
+
+    /// Stops at commits older than `time`, leaving them and what lies only
+    /// below them out.
+    pub fn since(mut self, time: i64) -> Self {
+        self.since = Some(time);
+        self
+    }
+
+    /// Leaves out commits newer than `time`, still walking past them.
+    pub fn until(mut self, time: i64) -> Self {
+        self.until = Some(time);
+        self
+    }
+
+    fn step(&mut self) -> Result<Option<String>> {
+        while let Some((time, _, sha)) = self.queue.pop() {
+            if self.since.is_some_and(|since| time < since) {
+                continue;
+            }
+            for parent in self.ancestry.parents(&sha)? {
+                if self.seen.insert(parent.clone()) {
+                    self.push(parent)?;
+                }
+            }
+            if self.until.is_some_and(|until| time > until) {
+                continue;
+            }
+            return Ok(Some(sha));
+        }
+        Ok(None)
+    }
+}
+
+impl Iterator for RevWalk<'_> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.step().transpose()
+    }
+}
+
+/// The commits `revs` select, newest first, as `RevWalk` finds them.
+pub fn rev_walk(repo: &Repository, revs: &[String]) -> Result<Vec<String>> {
+    RevWalk::new(repo, revs)?.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, slice};
+
+    use crate::{
+        commit_graph_write, ref_create, rev_walk,
+        test_utils::{make_commit_at, test_repo},
+        Ancestry,
+    };
+
+    #[test]
+    fn test_ancestry_with_and_without_graph() {
+        let repo = test_repo("ancestry");
+        let root = make_commit_at(&repo, "root", &[], 1000);
+        let left = make_commit_at(&repo, "left", &[&root], 2000);
+        let right = make_commit_at(&repo, "right", &[&root], 900);
+        let merge = make_commit_at(&repo, "merge", &[&left, &right], 3000);
+        let tip = make_commit_at(&repo, "tip", &[&right], 4000);
+        ref_create(&repo, "heads/master", &merge).unwrap();
+        ref_create(&repo, "heads/topic", &tip).unwrap();
+
+        for with_graph in [false, true] {
+            if with_graph {
+                commit_graph_write(&repo).unwrap();
+            }
+            let mut ancestry = Ancestry::new(&repo).unwrap();
+            assert!(ancestry.is_ancestor(&root, &merge).unwrap());
+            assert!(ancestry.is_ancestor(&right, &merge).unwrap());
+            assert!(!ancestry.is_ancestor(&left, &tip).unwrap());
+            assert!(ancestry.contains(&merge, &right).unwrap());
+            assert!(ancestry.contains(&tip, &right).unwrap());
+            assert!(!ancestry.contains(&tip, &left).unwrap());
+            assert_eq!(
+                ancestry.merge_bases(&merge, &tip).unwrap(),
+                vec![right.clone()]
+            );
+            assert_eq!(
+                ancestry.merge_bases(&left, &tip).unwrap(),
+                vec![root.clone()]
+            );
+            assert_eq!(
+                ancestry
+                    .merge_bases_many(&left, &[tip.clone(), merge.clone()])
+                    .unwrap(),
+                vec![left.clone()]
+            );
+        }
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_rev_walk_date_ties() {
+        let repo = test_repo("rev-walk-ties");
+        let a = make_commit_at(&repo, "a", &[], 1000);
+        let b = make_commit_at(&repo, "b", &[], 1000);
+        // With equal dates, parents come out in the order they are listed,
+        // whichever sha is larger.
+        let ab = make_commit_at(&repo, "ab", &[&a, &b], 1000);
+        let ba = make_commit_at(&repo, "ba", &[&b, &a], 1000);
+        assert_eq!(
+            rev_walk(&repo, slice::from_ref(&ab)).unwrap(),
+            vec![ab, a.clone(), b.clone()]
+        );
+        assert_eq!(
+            rev_walk(&repo, slice::from_ref(&ba)).unwrap(),
+            vec![ba, b, a]
+        );
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
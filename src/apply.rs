@@ -0,0 +1,737 @@
+use std::{
+    fs,
+    io::{self, Read},
+    os::unix::fs::PermissionsExt,
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    blob_read, index_entry_modified, index_read, index_write, merge_file, object_resolve,
+    object_write_raw, repo_find, split_lines, tree_mode_split, Index, IndexEntry, Repository,
+};
+
+/// One hunk of a unified diff.
+pub struct PatchHunk {
+    /// The first line of the hunk in the old and the new file, counting
+    /// from 1; for an empty side, the line it comes after.
+    pub old_start: usize,
+    pub new_start: usize,
+    /// The lines with their ' ', '-' or '+' marker, line terminators kept.
+    pub lines: Vec<(u8, Vec<u8>)>,
+}
+
+/// The lines of a hunk that it expects (`b'-'`) or leaves (`b'+'`).
+fn hunk_image(lines: &[(u8, Vec<u8>)], side: u8) -> Vec<&[u8]> {
+    lines
+        .iter()
+        .filter(|(marker, _)| *marker == b' ' || *marker == side)
+        .map(|(_, line)| line.as_slice())
+        .collect()
+}
+
+/// The changes a patch makes to one file. A missing old path means the
+/// file is created, a missing new path that it is deleted.
+#[derive(Default)]
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
+    /// The blobs on the `index` line, usually abbreviated.
+    pub old_sha: Option<String>,
+    pub new_sha: Option<String>,
+    pub hunks: Vec<PatchHunk>,
+}
+
+impl FilePatch {
+    /// The patch that undoes this one.
+    pub fn reversed(&self) -> FilePatch {
+        let hunks = self.hunks.iter().map(|hunk| PatchHunk {
+            old_start: hunk.new_start,
+            new_start: hunk.old_start,
+            lines: hunk
+                .lines
+                .iter()
+                .map(|(marker, line)| {
+                    let marker = match marker {
+                        b'-' => b'+',
+                        b'+' => b'-',
+                        marker => *marker,
+                    };
+                    (marker, line.clone())
+                })
+                .collect(),
+        });
+        FilePatch {
+            old_path: self.new_path.clone(),
+            new_path: self.old_path.clone(),
+            old_mode: self.new_mode.clone(),
+            new_mode: self.old_mode.clone(),
+            old_sha: self.new_sha.clone(),
+            new_sha: self.old_sha.clone(),
+            hunks: hunks.collect(),
+        }
+    }
+}
+
+/// How `apply` applies a patch.
+#[derive(Default)]
+pub struct ApplyOptions {
+    /// Apply to the index and worktree both, which must agree on the
+    /// files the patch touches.
+    pub index: bool,
+    /// Apply to the index only, which need not match the worktree.
+    pub cached: bool,
+    /// Only see that the patch applies.
+    pub check: bool,
+    /// Undo the patch instead.
+    pub reverse: bool,
+    /// How few lines of context may match when a hunk doesn't apply with
+    /// all of its context. None requires all of it.
+    pub min_context: Option<usize>,
+    /// Like `index`, falling back to a three-way merge with the blobs the
+    /// patch was made from when the context does not match.
+    pub three_way: bool,
+}
+
+/// What applying a patch does to one file.
+struct ApplyResult<'a> {
+    patch: &'a FilePatch,
+    content: Option<Vec<u8>>,
+    mode: String,
+    /// For a three-way merge that conflicted, the base, ours and theirs
+    /// blobs for the index stages.
+    conflict: Option<[String; 3]>,
+    merged: bool,
+}
+
+/// Applies the patch in `patch_path` ("-" for stdin) to the worktree, or
+/// to the index and worktree as `opts` asks.
+pub fn apply(patch_path: &Path, opts: &ApplyOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let mut data = Vec::new();
+    if patch_path == Path::new("-") {
+        io::stdin().read_to_end(&mut data)?;
+    } else {
+        data = fs::read(patch_path)?;
+    }
+    let patches = patch_parse(&data)?;
+    if patches.is_empty() {
+        bail!("No valid patches in input");
+    }
+    apply_patches(&repo, &patches, opts)
+}
+
+/// Applies `patches`. Nothing is changed unless every file applies, or
+/// with `three_way` merges; files merged with conflicts are written with
+/// markers and their stages, and reported in the error.
+pub fn apply_patches(repo: &Repository, patches: &[FilePatch], opts: &ApplyOptions) -> Result<()> {
+    let reversed: Vec<FilePatch>;
+    let patches = match opts.reverse {
+        true => {
+            reversed = patches.iter().map(FilePatch::reversed).collect();
+            &reversed
+        }
+        false => patches,
+    };
+    let mut index = match opts.index || opts.cached || opts.three_way {
+        true => Some(index_read(repo)?),
+        false => None,
+    };
+    let mut results = Vec::new();
+    for patch in patches {
+        results.push(apply_file(repo, patch, index.as_ref(), opts)?);
+    }
+    if opts.check {
+        return Ok(());
+    }
+
+    let mut conflicts = 0;
+    for result in &results {
+        let patch = result.patch;
+        if let Some(old) = &patch.old_path {
+            if patch.new_path.as_ref() != Some(old) {
+                if !opts.cached {
+                    fs::remove_file(repo.worktree.join(old))?;
+                }
+                if let Some(index) = &mut index {
+                    index.entries.retain(|e| &e.name != old);
+                }
+            }
+        }
+        let (Some(path), Some(content)) = (&patch.new_path, &result.content) else {
+            continue;
+        };
+        if !opts.cached {
+            let dest = repo.worktree.join(path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&dest, content)?;
+            let perms = if result.mode == "100755" {
+                0o755
+            } else {
+                0o644
+            };
+            fs::set_permissions(&dest, fs::Permissions::from_mode(perms))?;
+        }
+
+        let Some(index) = &mut index else {
+            continue;
+        };
+        index.entries.retain(|e| &e.name != path);
+        match &result.conflict {
+            None => {
+                let sha = object_write_raw(b"blob", content, Some(repo))?;
+                let entry = match opts.cached {
+                    true => apply_index_entry(path, &sha, &result.mode, 0)?,
+                    false => IndexEntry::from_file(repo, path, &sha, result.mode.as_bytes())?,
+                };
+                index.entries.push(entry);
+                if result.merged {
+                    eprintln!("Applied patch to '{}' cleanly.", path);
+                }
+            }
+            Some(stages) => {
+                for (stage, sha) in (1..).zip(stages) {
+                    let entry = apply_index_entry(path, sha, &result.mode, stage)?;
+                    index.entries.push(entry);
+                }
+                eprintln!("Applied patch to '{}' with conflicts.", path);
+                println!("U {}", path);
+                conflicts += 1;
+            }
+        }
+    }
+    if let Some(index) = &mut index {
+        index
+            .entries
+            .sort_by(|a, b| a.name.cmp(&b.name).then(a.flag_stage.cmp(&b.flag_stage)));
+        index_write(repo, index)?;
+    }
+    if conflicts > 0 {
+        bail!("{} file(s) applied with conflicts", conflicts);
+    }
+    Ok(())
+}
+
+/// An index entry without the stat data of a worktree file, which is
+/// left to be refreshed.
+fn apply_index_entry(path: &str, sha: &str, mode: &str, stage: u16) -> Result<IndexEntry> {
+    let (mode_type, mode_perms) = tree_mode_split(mode.as_bytes())?;
+    Ok(IndexEntry {
+        mode_type,
+        mode_perms,
+        sha: sha.to_string(),
+        name: path.to_string(),
+        flag_stage: stage << 12,
+        ..Default::default()
+    })
+}
+
+/// Works out the new content of the file `patch` changes, from the
+/// worktree or, given the `index`, from the index.
+fn apply_file<'a>(
+    repo: &Repository,
+    patch: &'a FilePatch,
+    index: Option<&Index>,
+    opts: &ApplyOptions,
+) -> Result<ApplyResult<'a>> {
+    let name = patch
+        .new_path
+        .as_ref()
+        .or(patch.old_path.as_ref())
+        .ok_or_else(|| anyhow!("patch names no file"))?;
+    let entry = |path: &str| index.and_then(|i| i.entries.iter().find(|e| e.name == path));
+
+    let (current, old_mode) = match &patch.old_path {
+        Some(path) if index.is_some() => {
+            let entry = entry(path).ok_or_else(|| anyhow!("{}: does not exist in index", path))?;
+            if !opts.cached && index_entry_modified(repo, entry)? {
+                bail!("{}: does not match index", path);
+            }
+            let mode = String::from_utf8_lossy(&entry.tree_mode()).to_string();
+            (blob_read(repo, &entry.sha)?, Some(mode))
+        }
+        Some(path) => {
+            let full = repo.worktree.join(path);
+            let data =
+                fs::read(&full).map_err(|_| anyhow!("{}: No such file or directory", path))?;
+            let exec = fs::metadata(&full)?.permissions().mode() & 0o111 != 0;
+            (
+                data,
+                Some(if exec { "100755" } else { "100644" }.to_string()),
+            )
+        }
+        None => {
+            if !opts.cached && repo.worktree.join(name).exists() {
+                bail!("{}: already exists in working directory", name);
+            }
+            if entry(name).is_some() {
+                bail!("{}: already exists in index", name);
+            }
+            (Vec::new(), None)
+        }
+    };
+    let mode = patch
+        .new_mode
+        .clone()
+        .or(old_mode)
+        .unwrap_or_else(|| "100644".to_string());
+
+    let mut result = ApplyResult {
+        patch,
+        content: None,
+        mode,
+        conflict: None,
+        merged: false,
+    };
+    let content = match patch_hunks_apply(&current, &patch.hunks, opts.min_context) {
+        Ok(content) => content,
+        Err(line) if opts.three_way => {
+            eprintln!("error: patch failed: {}:{}", name, line);
+            eprintln!("Falling back to three-way merge...");
+            let (base, theirs) = apply_preimages(repo, patch)
+                .ok_or_else(|| anyhow!("{}: patch does not apply", name))?;
+            let merged = merge_file(&base, &current, &theirs, "ours", "theirs");
+            if merged.conflicts > 0 {
+                result.conflict = Some([
+                    object_write_raw(b"blob", &base, Some(repo))?,
+                    object_write_raw(b"blob", &current, Some(repo))?,
+                    object_write_raw(b"blob", &theirs, Some(repo))?,
+                ]);
+            }
+            result.merged = true;
+            merged.content
+        }
+        Err(line) => bail!(
+            "patch failed: {}:{}\n{}: patch does not apply",
+            name,
+            line,
+            name
+        ),
+    };
+    if patch.new_path.is_none() {
+        if !content.is_empty() {
+            bail!("{}: removal patch leaves file contents", name);
+        }
+    } else {
+        result.content = Some(content);
+    }
+    Ok(result)
+}
+
+/// The file the patch was made from, found in the object database by the
+/// blob name on its `index` line, and the patch applied to it.
+fn apply_preimages(repo: &Repository, patch: &FilePatch) -> Option<(Vec<u8>, Vec<u8>)> {
+    let sha = patch.old_sha.as_ref()?;
+    let base = match object_resolve(repo, sha).ok()?.as_slice() {
+        [sha] => blob_read(repo, sha).ok()?,
+        _ => {
+            eprintln!("error: repository lacks the necessary blob to perform 3-way merge.");
+            return None;
+        }
+    };
+    let theirs = patch_hunks_apply(&base, &patch.hunks, None).ok()?;
+    Some((base, theirs))
+}
+
+/// Applies `hunks` to `content`. Each hunk must find its context and
+/// removed lines verbatim, at the line it names or the nearest place
+/// after the previous hunk; a hunk without leading or trailing context
+/// must also sit at the start or end of the file. With `min_context`, a
+/// hunk that doesn't fit is tried again unanchored, then with context
+/// lines dropped from its longer end down to that many. On failure
+/// returns the line the first hunk that did not apply names.
+pub fn patch_hunks_apply(
+    content: &[u8],
+    hunks: &[PatchHunk],
+    min_context: Option<usize>,
+) -> Result<Vec<u8>, usize> {
+    let lines = split_lines(content);
+    let mut out = Vec::new();
+    let mut pos = 0;
+    let mut offset: isize = 0;
+    for hunk in hunks {
+        let context = |lines: &mut dyn Iterator<Item = &(u8, Vec<u8>)>| {
+            lines.take_while(|(m, _)| *m == b' ').count()
+        };
+        let mut leading = context(&mut hunk.lines.iter());
+        let mut trailing = context(&mut hunk.lines.iter().rev());
+        let mut anchored = leading + trailing > 0;
+        let named = match hunk_image(&hunk.lines, b'-').is_empty() {
+            true => hunk.old_start,
+            false => hunk.old_start.saturating_sub(1),
+        };
+        // The lines of the hunk still in play, as context is dropped.
+        let (mut first, mut last) = (0, hunk.lines.len());
+
+        let found = loop {
+            let old = hunk_image(&hunk.lines[first..last], b'-');
+            let at_start = anchored && leading == 0 && hunk.old_start <= 1;
+            let at_end = anchored && trailing == 0;
+            let want = ((named + first) as isize + offset).max(pos as isize) as usize;
+            let fits = |at: usize| {
+                at >= pos
+                    && at + old.len() <= lines.len()
+                    && lines[at..at + old.len()] == old[..]
+                    && (!at_start || at == 0)
+                    && (!at_end || at + old.len() == lines.len())
+            };
+            let found = (0..=lines.len()).find_map(|d| {
+                [Some(want + d), want.checked_sub(d)]
+                    .into_iter()
+                    .flatten()
+                    .find(|&at| fits(at))
+            });
+            if found.is_some() {
+                break found;
+            }
+            let Some(min_context) = min_context else {
+                break None;
+            };
+            if leading <= min_context && trailing <= min_context {
+                break None;
+            }
+            if anchored {
+                anchored = false;
+                continue;
+            }
+            if leading >= trailing {
+                (first, leading) = (first + 1, leading - 1);
+            }
+            if trailing > leading {
+                (last, trailing) = (last - 1, trailing - 1);
+            }
+        };
+        let Some(at) = found else {
+            return Err(hunk.old_start);
+        };
+        for line in &lines[pos..at] {
+            out.extend_from_slice(line);
+        }
+        for line in hunk_image(&hunk.lines[first..last], b'+') {
+            out.extend_from_slice(line);
+        }
+        pos = at + hunk_image(&hunk.lines[first..last], b'-').len();
+        offset = at as isize - (named + first) as isize;
+    }
+    for line in &lines[pos..] {
+        out.extend_from_slice(line);
+    }
+    Ok(out)
+}
+
+/// Parses the file patches of a unified diff, git's extended headers
+/// included. Anything before the first diff, like a commit message, is
+/// skipped. Paths lose their first component, the "a/" and "b/".
+pub fn patch_parse(data: &[u8]) -> Result<Vec<FilePatch>> {
+    let lines = split_lines(data);
+    let text = |line: &[u8]| {
+        String::from_utf8_lossy(line)
+            .trim_end_matches(['\n', '\r'])
+            .to_string()
+    };
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = text(lines[i]);
+        let mut patch = FilePatch::default();
+        if let Some(names) = line.strip_prefix("diff --git ") {
+            let name = patch_header_name(names);
+            patch.old_path = name.clone();
+            patch.new_path = name;
+            i += 1;
+            while i < lines.len()
+                && !lines[i].starts_with(b"diff --git ")
+                && !lines[i].starts_with(b"@@ ")
+            {
+                let line = text(lines[i]);
+                let field = |prefix: &str| line.strip_prefix(prefix).map(str::to_string);
+                if let Some(mode) = field("old mode ") {
+                    patch.old_mode = Some(mode);
+                } else if let Some(mode) = field("new mode ") {
+                    patch.new_mode = Some(mode);
+                } else if let Some(mode) = field("deleted file mode ") {
+                    patch.old_mode = Some(mode);
+                    patch.new_path = None;
+                } else if let Some(mode) = field("new file mode ") {
+                    patch.new_mode = Some(mode);
+                    patch.old_path = None;
+                } else if let Some(path) = field("rename from ").or_else(|| field("copy from ")) {
+                    patch.old_path = Some(path);
+                } else if let Some(path) = field("rename to ").or_else(|| field("copy to ")) {
+                    patch.new_path = Some(path);
+                } else if let Some(index) = field("index ") {
+                    let (shas, mode) = index.split_once(' ').unwrap_or((&index, ""));
+                    if let Some((old, new)) = shas.split_once("..") {
+                        // All zeros stands for a file that doesn't exist.
+                        let blob =
+                            |sha: &str| Some(sha.to_string()).filter(|s| s.contains(|c| c != '0'));
+                        patch.old_sha = blob(old);
+                        patch.new_sha = blob(new);
+                    }
+                    if !mode.is_empty() {
+                        patch.old_mode.get_or_insert(mode.to_string());
+                        patch.new_mode.get_or_insert(mode.to_string());
+                    }
+                } else if let Some(path) = field("--- ") {
+                    patch.old_path = patch_path(&path);
+                } else if let Some(path) = field("+++ ") {
+                    patch.new_path = patch_path(&path);
+                } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+                    bail!("binary patches are not supported");
+                }
+                i += 1;
+            }
+        } else if line.starts_with("--- ")
+            && lines.get(i + 1).is_some_and(|l| l.starts_with(b"+++ "))
+        {
+            patch.old_path = patch_path(&line[4..]);
+            patch.new_path = patch_path(&text(lines[i + 1])[4..]);
+            i += 2;
+        } else {
+            i += 1;
+            continue;
+        }
+
+        while let Some(header) = lines
+            .get(i)
+            .map(|l| text(l))
+            .filter(|l| l.starts_with("@@ "))
+        {
+            let (hunk, next) = patch_hunk_parse(&lines, i, &header)?;
+            patch.hunks.push(hunk);
+            i = next;
+        }
+        ret.push(patch);
+    }
+    Ok(ret)
+}
+
+/// Parses the hunk whose `header` is `lines[start]`. Returns it with the
+/// index of the line after it.
+fn patch_hunk_parse(lines: &[&[u8]], start: usize, header: &str) -> Result<(PatchHunk, usize)> {
+    let bad = || anyhow!("corrupt patch at line {}", start + 1);
+    let mut ranges = header
+        .strip_prefix("@@ -")
+        .and_then(|h| h.split_once(" @@"))
+        .ok_or_else(bad)?
+        .0
+        .split(" +")
+        .map(|range| -> Result<(usize, usize)> {
+            let (first, count) = range.split_once(',').unwrap_or((range, "1"));
+            Ok((
+                first.parse().map_err(|_| bad())?,
+                count.parse().map_err(|_| bad())?,
+            ))
+        });
+    let (old_start, mut old_left) = ranges.next().ok_or_else(bad)??;
+    let (new_start, mut new_left) = ranges.next().ok_or_else(bad)??;
+
+    let mut hunk = PatchHunk {
+        old_start,
+        new_start,
+        lines: Vec::new(),
+    };
+    let mut i = start + 1;
+    while old_left > 0 || new_left > 0 {
+        let line = lines.get(i).ok_or_else(bad)?;
+        // Some mailers strip the space off empty context lines.
+        let (marker, content) = match line.split_first() {
+            Some((b'\n', _)) => (b' ', b"\n".as_slice()),
+            Some((&marker, content)) => (marker, content),
+            None => return Err(bad()),
+        };
+        match marker {
+            b' ' if old_left > 0 && new_left > 0 => {
+                (old_left, new_left) = (old_left - 1, new_left - 1)
+            }
+            b'-' if old_left > 0 => old_left -= 1,
+            b'+' if new_left > 0 => new_left -= 1,
+            b'\\' => {}
+            _ => return Err(bad()),
+        }
+        if marker == b'\\' {
+            patch_strip_newline(&mut hunk);
+        } else {
+            hunk.lines.push((marker, content.to_vec()));
+        }
+        i += 1;
+    }
+    if lines.get(i).is_some_and(|l| l.starts_with(b"\\")) {
+        patch_strip_newline(&mut hunk);
+        i += 1;
+    }
+    Ok((hunk, i))
+}
+
+/// "\ No newline at end of file": the line before has no terminator.
+fn patch_strip_newline(hunk: &mut PatchHunk) {
+    if let Some((_, line)) = hunk.lines.last_mut() {
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+    }
+}
+
+/// The path of a `---` or `+++` line, without its first component; None
+/// for /dev/null.
+fn patch_path(field: &str) -> Option<String> {
+    let path = field.split('\t').next().unwrap_or(field).trim_end();
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(
+        path.split_once('/')
+            .map_or(path, |(_, rest)| rest)
+            .to_string(),
+    )
+}
+
+/// The path in `a/<path> b/<path>`, when both halves name the same file.
+fn patch_header_name(names: &str) -> Option<String> {
+    let len = names.len().checked_sub(5)? / 2;
+    let (a, b) = (names.get(..len + 2)?, names.get(len + 3..)?);
+    let (a, b) = (a.strip_prefix("a/")?, b.strip_prefix("b/")?);
+    (a == b).then(|| a.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{apply_patches, patch_hunks_apply, patch_parse, ApplyOptions};
+    use crate::{
+        blob_read, checkout_tree, index_read, ref_create,
+        test_utils::{make_commit, test_repo},
+    };
+
+    #[test]
+    fn test_patch_apply() {
+        let patch = b"From 1234 Mon Sep 17 00:00:00 2001\n\
+            Subject: [PATCH] change\n\
+            \n\
+            diff --git a/dir/f b/dir/f\n\
+            index 83db48f..bf269f4 100644\n\
+            --- a/dir/f\n\
+            +++ b/dir/f\n\
+            @@ -2,3 +2,3 @@\n\
+            \x202\n\
+            -3\n\
+            +three\n\
+            \x204\n\
+            @@ -9,2 +9,2 @@\n\
+            \x209\n\
+            -10\n\
+            +ten\n\
+            \\ No newline at end of file\n\
+            diff --git a/new b/new\n\
+            new file mode 100755\n\
+            index 0000000..e69de29\n";
+        let patches = patch_parse(patch).unwrap();
+        assert_eq!(patches.len(), 2);
+        let f = &patches[0];
+        assert_eq!(f.old_path.as_deref(), Some("dir/f"));
+        assert_eq!(f.old_sha.as_deref(), Some("83db48f"));
+        assert_eq!(f.new_mode.as_deref(), Some("100644"));
+        assert_eq!(f.hunks.len(), 2);
+        assert_eq!(
+            (patches[1].old_path.as_ref(), patches[1].old_sha.as_ref()),
+            (None, None)
+        );
+        assert_eq!(patches[1].new_mode.as_deref(), Some("100755"));
+
+        let old = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = patch_hunks_apply(old, &f.hunks, None).unwrap();
+        assert_eq!(new, b"1\n2\nthree\n4\n5\n6\n7\n8\n9\nten");
+        assert_eq!(
+            patch_hunks_apply(&new, &f.reversed().hunks, None).unwrap(),
+            old
+        );
+        // Lines added above shift the hunks, which still apply.
+        let shifted = b"0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        assert!(patch_hunks_apply(shifted, &f.hunks, None).is_ok());
+        // The second hunk has no trailing context, so it must end the file.
+        let longer = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n";
+        assert_eq!(patch_hunks_apply(longer, &f.hunks, None), Err(9));
+        // Fuzz lets a hunk apply past context that changed.
+        let changed = b"1\n2\n3\n4\n5\n6\n7\n8\nnine\n10\n";
+        assert_eq!(patch_hunks_apply(changed, &f.hunks, Some(1)), Err(9));
+        assert!(patch_hunks_apply(changed, &f.hunks, Some(0)).is_ok());
+    }
+
+    #[test]
+    fn test_apply_patches() {
+        let repo = test_repo("apply");
+        let sha = make_commit(&repo, "1\n2\n3\n4\n5\n", &[]);
+        checkout_tree(&repo, &sha, true).unwrap();
+        ref_create(&repo, "heads/master", &sha).unwrap();
+        let patches = patch_parse(
+            b"diff --git a/file b/file\n\
+            --- a/file\n\
+            +++ b/file\n\
+            @@ -2,3 +2,3 @@\n\
+            \x202\n\
+            -3\n\
+            +three\n\
+            \x204\n\
+            diff --git a/added b/added\n\
+            new file mode 100644\n\
+            --- /dev/null\n\
+            +++ b/added\n\
+            @@ -0,0 +1 @@\n\
+            +hello\n",
+        )
+        .unwrap();
+        let read = |name: &str| fs::read(repo.worktree.join(name)).ok();
+        let original = b"1\n2\n3\n4\n5\n".to_vec();
+        let patched = b"1\n2\nthree\n4\n5\n".to_vec();
+
+        let check = ApplyOptions {
+            check: true,
+            ..Default::default()
+        };
+        apply_patches(&repo, &patches, &check).unwrap();
+        assert_eq!(read("file"), Some(original.clone()));
+        assert_eq!(read("added"), None);
+
+        apply_patches(&repo, &patches, &ApplyOptions::default()).unwrap();
+        assert_eq!(read("file"), Some(patched.clone()));
+        assert_eq!(read("added"), Some(b"hello\n".to_vec()));
+        let reverse = ApplyOptions {
+            reverse: true,
+            ..Default::default()
+        };
+        apply_patches(&repo, &patches, &reverse).unwrap();
+        assert_eq!(read("file"), Some(original.clone()));
+        assert_eq!(read("added"), None);
+
+        // Context that doesn't match fails the whole patch, before the
+        // file it would create is written.
+        fs::write(repo.worktree.join("file"), "1\n2\n3\nfour\n5\n").unwrap();
+        let err = apply_patches(&repo, &patches, &ApplyOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("file: patch does not apply"));
+        assert_eq!(read("file"), Some(b"1\n2\n3\nfour\n5\n".to_vec()));
+        assert_eq!(read("added"), None);
+
+        // The index has the original content, so the patch applies there.
+        let cached = ApplyOptions {
+            cached: true,
+            ..Default::default()
+        };
+        apply_patches(&repo, &patches, &cached).unwrap();
+        let index = index_read(&repo).unwrap();
+        let staged = |name: &str| {
+            let entry = index.entries.iter().find(|e| e.name == name).unwrap();
+            blob_read(&repo, &entry.sha).unwrap()
+        };
+        assert_eq!(staged("file"), patched);
+        assert_eq!(staged("added"), b"hello\n");
+        assert_eq!(read("file"), Some(b"1\n2\n3\nfour\n5\n".to_vec()));
+        assert_eq!(read("added"), None);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
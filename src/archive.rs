@@ -0,0 +1,405 @@
+use std::{
+    fs::File,
+    io::Write,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use flate2::{write::DeflateEncoder, Compression, Crc};
+
+use crate::{
+    blob_read, date_local, ident_parse, kvlm_get_str, object_find, object_read, repo_find,
+    tree_walk, Commit, Repository, Tree, TreeLeaf,
+};
+
+const TAR_BLOCK: usize = 512;
+/// git pads tar archives to whole records of 20 blocks.
+const TAR_RECORD: usize = 20 * TAR_BLOCK;
+/// git's default `tar.umask`, applied to the modes in both formats.
+const ARCHIVE_UMASK: u32 = 0o002;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+/// Writes the tree of `tree_ish` as an archive to `output`, or to stdout.
+/// Without a `format`, it follows the extension of `output` and is tar
+/// otherwise.
+pub fn archive(format: Option<ArchiveFormat>, output: Option<&Path>, tree_ish: &str) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let format = format.unwrap_or(match output.and_then(|o| o.extension()) {
+        Some(ext) if ext == "zip" => ArchiveFormat::Zip,
+        _ => ArchiveFormat::Tar,
+    });
+    match output {
+        Some(path) => archive_write(&repo, tree_ish, format, &mut File::create(path)?),
+        None => archive_write(&repo, tree_ish, format, &mut std::io::stdout().lock()),
+    }
+}
+
+/// Streams the archive of `tree_ish` into `out`, entry by entry. A commit
+/// (or a tag of one) dates the entries and is recorded in the archive as
+/// git does; a bare tree gets the current time.
+pub fn archive_write(
+    repo: &Repository,
+    tree_ish: &str,
+    format: ArchiveFormat,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let tree_sha = object_find(repo, tree_ish, Some(b"tree"), true)?
+        .ok_or_else(|| anyhow!("not a tree object: {}", tree_ish))?;
+    let commit = object_find(repo, tree_ish, Some(b"commit"), true)?;
+    let (mtime, tz) = match &commit {
+        Some(sha) => {
+            let obj = object_read(repo, sha)?;
+            let commit = obj.as_any().downcast_ref::<Commit>().unwrap();
+            let committer = kvlm_get_str(&commit.kvlm, b"committer").unwrap_or_default();
+            match ident_parse(&committer) {
+                Some((_, time, tz)) => (time, tz.to_string()),
+                None => (0, "+0000".to_string()),
+            }
+        }
+        None => (
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+            "+0000".to_string(),
+        ),
+    };
+
+    let obj = object_read(repo, &tree_sha)?;
+    let tree = obj
+        .as_any()
+        .downcast_ref::<Tree>()
+        .ok_or_else(|| anyhow!("Object {} is not a tree", tree_sha))?;
+    match format {
+        ArchiveFormat::Tar => {
+            let mut written = 0;
+            if let Some(sha) = &commit {
+                let mut records = Vec::new();
+                pax_record(&mut records, "comment", sha.as_bytes());
+                let header = tar_header("pax_global_header", 0o666, records.len(), mtime, b'g')?;
+                written += tar_write(out, &header, &records)?;
+            }
+            tree_walk(repo, tree, "", &mut |path, item| {
+                written += tar_entry(repo, out, path, item, mtime)?;
+                Ok(())
+            })?;
+            // At least two zero blocks end the archive, then pad the record.
+            let mut tail = TAR_RECORD - written % TAR_RECORD;
+            if tail < 2 * TAR_BLOCK {
+                tail += TAR_RECORD;
+            }
+            out.write_all(&vec![0; tail])?;
+        }
+        ArchiveFormat::Zip => {
+            let mut zip = ZipWriter {
+                out,
+                offset: 0,
+                central: Vec::new(),
+                entries: 0,
+                dos_time: dos_datetime(mtime, &tz),
+            };
+            tree_walk(repo, tree, "", &mut |path, item| {
+                zip.entry(repo, path, item)
+            })?;
+            zip.finish(commit.as_deref().unwrap_or(""))?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn leaf_mode(item: &TreeLeaf) -> Result<u32> {
+    let mode = std::str::from_utf8(&item.mode)?;
+    u32::from_str_radix(mode, 8).map_err(|_| anyhow!("Weird tree leaf mode {:?}", mode))
+}
+
+fn is_dir(mode: u32) -> bool {
+    // Submodules are archived as empty directories, like git does.
+    matches!(mode >> 12, 0o04 | 0o16)
+}
+
+fn is_symlink(mode: u32) -> bool {
+    mode >> 12 == 0o12
+}
+
+/// Writes the header and content of one tree entry, returning the number of
+/// bytes written.
+fn tar_entry(
+    repo: &Repository,
+    out: &mut dyn Write,
+    path: &str,
+    item: &TreeLeaf,
+    mtime: i64,
+) -> Result<usize> {
+    let mode = leaf_mode(item)?;
+    let (name, mode, typeflag, data) = if is_dir(mode) {
+        (
+            format!("{}/", path),
+            0o777 & !ARCHIVE_UMASK,
+            b'5',
+            Vec::new(),
+        )
+    } else if is_symlink(mode) {
+        (path.to_string(), 0o777, b'2', blob_read(repo, &item.sha)?)
+    } else {
+        let perm = if mode & 0o100 != 0 { 0o777 } else { 0o666 };
+        (
+            path.to_string(),
+            perm & !ARCHIVE_UMASK,
+            b'0',
+            blob_read(repo, &item.sha)?,
+        )
+    };
+
+    // Names and link targets that don't fit the header go in a pax header.
+    let mut records = Vec::new();
+    let (prefix, short) = match tar_name_split(&name) {
+        Some(split) => split,
+        None => {
+            pax_record(&mut records, "path", name.as_bytes());
+            ("", "")
+        }
+    };
+    if typeflag == b'2' && data.len() > 100 {
+        pax_record(&mut records, "linkpath", &data);
+    }
+    let mut written = 0;
+    if !records.is_empty() {
+        let pax_name = format!("{}.paxheader", item.sha);
+        let header = tar_header(&pax_name, 0o666, records.len(), mtime, b'x')?;
+        written += tar_write(out, &header, &records)?;
+    }
+
+    let fallback = format!("{}.data", item.sha);
+    let short = if short.is_empty() { &fallback } else { short };
+    let size = if typeflag == b'0' { data.len() } else { 0 };
+    let mut header = tar_header(short, mode, size, mtime, typeflag)?;
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    if typeflag == b'2' && data.len() <= 100 {
+        header[157..157 + data.len()].copy_from_slice(&data);
+    }
+    tar_checksum(&mut header);
+    let content: &[u8] = if typeflag == b'0' { &data } else { &[] };
+    written += tar_write(out, &header, content)?;
+    Ok(written)
+}
+
+/// Splits a name too long for the 100-byte name field into the ustar
+/// prefix and name, with as much as fits in the prefix like git, or
+/// returns None if it can't be split.
+fn tar_name_split(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    let trimmed = name.trim_end_matches('/');
+    trimmed
+        .char_indices()
+        .filter(|&(i, c)| c == '/' && i <= 155 && name.len() - i - 1 <= 100)
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .next_back()
+}
+
+/// A ustar header with an empty prefix and link name, already summed.
+fn tar_header(name: &str, mode: u32, size: usize, mtime: i64, typeflag: u8) -> Result<[u8; 512]> {
+    if name.len() > 100 {
+        bail!("tar entry name too long: {}", name);
+    }
+    let mut h = [0u8; TAR_BLOCK];
+    h[..name.len()].copy_from_slice(name.as_bytes());
+    let octal = |field: &mut [u8], value: u64| {
+        let s = format!("{:0width$o}", value, width = field.len() - 1);
+        field[..s.len()].copy_from_slice(s.as_bytes());
+    };
+    octal(&mut h[100..108], u64::from(mode & 0o7777));
+    octal(&mut h[108..116], 0);
+    octal(&mut h[116..124], 0);
+    octal(&mut h[124..136], size as u64);
+    octal(&mut h[136..148], mtime.max(0) as u64);
+    h[156] = typeflag;
+    h[257..263].copy_from_slice(b"ustar\0");
+    h[263..265].copy_from_slice(b"00");
+    h[265..269].copy_from_slice(b"root");
+    h[297..301].copy_from_slice(b"root");
+    octal(&mut h[329..337], 0);
+    octal(&mut h[337..345], 0);
+    tar_checksum(&mut h);
+    Ok(h)
+}
+
+fn tar_checksum(h: &mut [u8; 512]) {
+    h[148..156].fill(b' ');
+    let sum: u32 = h.iter().map(|&b| u32::from(b)).sum();
+    let s = format!("{:07o}\0", sum);
+    h[148..156].copy_from_slice(s.as_bytes());
+}
+
+/// Writes a header and its content padded to whole blocks.
+fn tar_write(out: &mut dyn Write, header: &[u8; 512], content: &[u8]) -> Result<usize> {
+    out.write_all(header)?;
+    out.write_all(content)?;
+    let pad = (TAR_BLOCK - content.len() % TAR_BLOCK) % TAR_BLOCK;
+    out.write_all(&vec![0; pad])?;
+    Ok(TAR_BLOCK + content.len() + pad)
+}
+
+/// Appends a pax `<length> <key>=<value>\n` record, the length counting
+/// itself.
+fn pax_record(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+    let body = key.len() + value.len() + 3;
+    let mut len = body;
+    loop {
+        let total = body + len.to_string().len();
+        if total == len {
+            break;
+        }
+        len = total;
+    }
+    out.extend(format!("{} {}=", len, key).into_bytes());
+    out.extend_from_slice(value);
+    out.push(b'\n');
+}
+
+/// The MS-DOS (time, date) pair zip stores, in the timezone `tz`.
+fn dos_datetime(time: i64, tz: &str) -> (u16, u16) {
+    let d = date_local(time, tz);
+    if d.year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let dos_time = (d.secs / 3600) << 11 | (d.secs / 60 % 60) << 5 | (d.secs % 60 / 2);
+    let dos_date = (d.year - 1980) << 9 | d.month << 5 | d.day;
+    (dos_time as u16, dos_date as u16)
+}
+
+/// Writes zip entries as they come and the central directory at the end.
+struct ZipWriter<'a> {
+    out: &'a mut dyn Write,
+    offset: usize,
+    central: Vec<u8>,
+    entries: usize,
+    dos_time: (u16, u16),
+}
+
+impl ZipWriter<'_> {
+    fn entry(&mut self, repo: &Repository, path: &str, item: &TreeLeaf) -> Result<()> {
+        let mode = leaf_mode(item)?;
+        let (name, attr, data) = if is_dir(mode) {
+            // The low byte holds the MS-DOS directory flag.
+            let attr = (0o40777 & !ARCHIVE_UMASK) << 16 | 0x10;
+            (format!("{}/", path), attr, Vec::new())
+        } else if is_symlink(mode) {
+            (
+                path.to_string(),
+                0o120777 << 16,
+                blob_read(repo, &item.sha)?,
+            )
+        } else {
+            let perm = if mode & 0o100 != 0 { 0o777 } else { 0o666 };
+            let attr = (0o100000 | (perm & !ARCHIVE_UMASK)) << 16;
+            (path.to_string(), attr, blob_read(repo, &item.sha)?)
+        };
+
+        let mut crc = Crc::new();
+        crc.update(&data);
+        let (method, stored) = if data.is_empty() || is_symlink(mode) {
+            (0u16, data.clone())
+        } else {
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&data)?;
+            (8, encoder.finish()?)
+        };
+        if self.offset > u32::MAX as usize || data.len() > u32::MAX as usize {
+            bail!("archive too large for zip without zip64");
+        }
+        // Bit 11: the name is UTF-8.
+        let flags: u16 = if name.is_ascii() { 0 } else { 0x800 };
+        let (time, date) = self.dos_time;
+
+        let mut local = Vec::new();
+        local.extend(0x04034b50u32.to_le_bytes());
+        local.extend(20u16.to_le_bytes());
+        local.extend(flags.to_le_bytes());
+        local.extend(method.to_le_bytes());
+        local.extend(time.to_le_bytes());
+        local.extend(date.to_le_bytes());
+        local.extend(crc.sum().to_le_bytes());
+        local.extend((stored.len() as u32).to_le_bytes());
+        local.extend((data.len() as u32).to_le_bytes());
+        local.extend((name.len() as u16).to_le_bytes());
+        local.extend(0u16.to_le_bytes());
+        local.extend(name.as_bytes());
+
+        let c = &mut self.central;
+        c.extend(0x02014b50u32.to_le_bytes());
+        // Made by unix, version 2.3, so that the modes are honored.
+        c.extend(0x0317u16.to_le_bytes());
+        c.extend_from_slice(&local[4..30]);
+        c.extend(0u16.to_le_bytes());
+        c.extend(0u16.to_le_bytes());
+        c.extend(0u16.to_le_bytes());
+        c.extend(attr.to_le_bytes());
+        c.extend((self.offset as u32).to_le_bytes());
+        c.extend(name.as_bytes());
+
+        self.out.write_all(&local)?;
+        self.out.write_all(&stored)?;
+        self.offset += local.len() + stored.len();
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Writes the central directory and its end record, with `comment` as
+    /// the archive comment.
+    fn finish(&mut self, comment: &str) -> Result<()> {
+        if self.entries > u16::MAX as usize {
+            bail!("too many entries for zip without zip64");
+        }
+        let mut end = Vec::new();
+        end.extend(0x06054b50u32.to_le_bytes());
+        end.extend(0u16.to_le_bytes());
+        end.extend(0u16.to_le_bytes());
+        end.extend((self.entries as u16).to_le_bytes());
+        end.extend((self.entries as u16).to_le_bytes());
+        end.extend((self.central.len() as u32).to_le_bytes());
+        end.extend((self.offset as u32).to_le_bytes());
+        end.extend((comment.len() as u16).to_le_bytes());
+        end.extend(comment.as_bytes());
+        self.out.write_all(&self.central)?;
+        self.out.write_all(&end)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        archive_write,
+        test_utils::{make_commit, test_repo},
+        ArchiveFormat,
+    };
+
+    #[test]
+    fn test_archive_tar() {
+        let repo = test_repo("archive");
+        let commit = make_commit(&repo, "hello\n", &[]);
+        let mut out = Vec::new();
+        archive_write(&repo, &commit, ArchiveFormat::Tar, &mut out).unwrap();
+        assert_eq!(out.len() % 10240, 0);
+
+        // The pax global header carries the commit id.
+        assert_eq!(out[156], b'g');
+        let comment = format!("52 comment={}\n", commit);
+        assert_eq!(&out[512..512 + comment.len()], comment.as_bytes());
+
+        let file = &out[1024..1536];
+        assert_eq!(&file[..5], b"file\0");
+        assert_eq!(&file[100..108], b"0000664\0");
+        assert_eq!(&file[124..136], b"00000000006\0");
+        assert_eq!(&out[1536..1542], b"hello\n");
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, BufRead},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use indexmap::{IndexMap, IndexSet};
+
+use crate::{
+    blob_read, config_global_path, index_read, repo_find, repo_relative_path, Index, Repository,
+};
+
+/// The state of an attribute that a .gitattributes line gave a path.
+/// Attributes no line mentions are unspecified and absent from an
+/// `AttrMap`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AttrValue {
+    /// `attr`
+    Set,
+    /// `-attr`
+    Unset,
+    /// `attr=value`
+    Value(String),
+}
+
+impl fmt::Display for AttrValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AttrValue::Set => write!(f, "set"),
+            AttrValue::Unset => write!(f, "unset"),
+            AttrValue::Value(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// The attributes of a path, by name, in the order git lists them: the
+/// order the attributes were first mentioned in.
+pub type AttrMap = IndexMap<String, AttrValue>;
+
+/// One assignment on a .gitattributes line; `None` is `!attr`, which
+/// takes the attribute back to unspecified.
+type AttrAssign = (String, Option<AttrValue>);
+
+struct AttrRule {
+    pattern: String,
+    assigns: Vec<AttrAssign>,
+}
+
+/// The rules of one attributes file and the directory its patterns are
+/// relative to.
+struct AttrFile {
+    base: String,
+    rules: Vec<AttrRule>,
+}
+
+/// What the attributes files parsed so far define: macros, and every
+/// attribute name in the order it was first mentioned.
+struct AttrDefs {
+    macros: HashMap<String, Vec<AttrAssign>>,
+    names: IndexSet<String>,
+}
+
+/// Where `check-attr` takes its paths from and what it prints for them.
+#[derive(Default)]
+pub struct CheckAttrOptions {
+    /// Print every attribute set on each path instead of the ones asked.
+    pub all: bool,
+    /// Read .gitattributes files from the index only, not the worktree.
+    pub cached: bool,
+    /// Read the paths from stdin, one per line.
+    pub stdin: bool,
+}
+
+/// Prints the attributes `attrs` of each path. Without `--`, as in git,
+/// the first word is the attribute and the rest are paths; with `--all`
+/// every word is a path.
+pub fn check_attr(attrs: &[String], paths: &[String], opts: &CheckAttrOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let (attrs, mut paths) = match (paths.is_empty(), opts.all, opts.stdin) {
+        (false, _, _) | (true, false, true) => (attrs, paths.to_vec()),
+        (true, true, _) => (&[][..], attrs.to_vec()),
+        (true, false, false) => match attrs.split_first() {
+            Some((first, rest)) => (std::slice::from_ref(first), rest.to_vec()),
+            None => bail!("usage: check-attr [--all | <attr>...] [--] <path>..."),
+        },
+    };
+    if opts.all && !attrs.is_empty() {
+        bail!("--all and attributes can't be given together");
+    }
+    if !opts.all && attrs.is_empty() {
+        bail!("no attribute specified");
+    }
+    if opts.stdin {
+        paths.extend(io::stdin().lock().lines().collect::<io::Result<Vec<_>>>()?);
+    }
+    for path in &paths {
+        let rel = repo_relative_path(&repo, Path::new(path))?;
+        let map = attr_query_from(&repo, &rel, opts.cached)?;
+        if opts.all {
+            for (name, value) in &map {
+                println!("{}: {}: {}", path, name, value);
+            }
+            continue;
+        }
+        for name in attrs {
+            match map.get(name) {
+                Some(value) => println!("{}: {}: {}", path, name, value),
+                None => println!("{}: {}: unspecified", path, name),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolves the attributes of the repository-relative `path`.
+///
+/// As in git, the sources are, from lowest to highest precedence: the
+/// global attributes file, the .gitattributes of each directory from the
+/// root down to the path's own, and .git/info/attributes. Later lines win
+/// over earlier ones within a file.
+pub fn attr_query(repo: &Repository, path: &str) -> Result<AttrMap> {
+    attr_query_from(repo, path, false)
+}
+
+/// `attr_query`, with the .gitattributes files read from the index alone
+/// when `cached`.
+fn attr_query_from(repo: &Repository, path: &str, cached: bool) -> Result<AttrMap> {
+    let mut defs = AttrDefs {
+        macros: HashMap::new(),
+        names: IndexSet::new(),
+    };
+    attr_parse("[attr]binary -diff -merge -text", "", &mut defs);
+
+    let mut files = Vec::new();
+    if let Some(global) = attr_global_path(repo) {
+        if let Ok(content) = fs::read_to_string(global) {
+            files.push(attr_parse(&content, "", &mut defs));
+        }
+    }
+    let index = match repo.repo_path(PathBuf::from("index")).exists() {
+        true => Some(index_read(repo)?),
+        false => None,
+    };
+    let mut dir = String::new();
+    let components: Vec<&str> = path.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        if let Some(content) = attr_file_read(repo, index.as_ref(), &dir, cached)? {
+            files.push(attr_parse(&content, &dir, &mut defs));
+        }
+        if i + 1 == components.len() {
+            break;
+        }
+        dir = match dir.as_str() {
+            "" => component.to_string(),
+            _ => format!("{}/{}", dir, component),
+        };
+    }
+    if let Ok(content) = fs::read_to_string(repo.repo_path(PathBuf::from("info/attributes"))) {
+        files.push(attr_parse(&content, "", &mut defs));
+    }
+
+    let mut map = AttrMap::new();
+    for file in &files {
+        for rule in &file.rules {
+            if attr_pattern_matches(&rule.pattern, &file.base, path) {
+                attr_apply(&mut map, &rule.assigns, &defs.macros);
+            }
+        }
+    }
+    map.sort_by_cached_key(|name, _| defs.names.get_index_of(name));
+    Ok(map)
+}
+
+/// `core.attributesFile`, defaulting to $XDG_CONFIG_HOME/git/attributes.
+fn attr_global_path(repo: &Repository) -> Option<PathBuf> {
+    if let Some(path) = repo.config_get("core", "attributesFile") {
+        return Some(match path.strip_prefix("~/") {
+            Some(rest) => config_global_path()?.parent()?.join(rest),
+            None => PathBuf::from(path),
+        });
+    }
+    let config = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(config.join("git/attributes"))
+}
+
+/// The .gitattributes of `dir`, from the worktree or, if it is not checked
+/// out or `cached`, from the index.
+fn attr_file_read(
+    repo: &Repository,
+    index: Option<&Index>,
+    dir: &str,
+    cached: bool,
+) -> Result<Option<String>> {
+    let name = match dir {
+        "" => ".gitattributes".to_string(),
+        _ => format!("{}/.gitattributes", dir),
+    };
+    if !cached {
+        if let Ok(content) = fs::read(repo.worktree.join(&name)) {
+            return Ok(Some(String::from_utf8_lossy(&content).to_string()));
+        }
+    }
+    match index.and_then(|i| i.entries.iter().find(|e| e.name == name)) {
+        Some(entry) => Ok(Some(
+            String::from_utf8_lossy(&blob_read(repo, &entry.sha)?).to_string(),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Parses an attributes file. `[attr]<name>` lines define macros, which
+/// only the top-level files may do.
+fn attr_parse(content: &str, base: &str, defs: &mut AttrDefs) -> AttrFile {
+    let mut rules = Vec::new();
+    for line in content.lines() {
+        let mut words = line.split_whitespace();
+        let Some(pattern) = words.next() else {
+            continue;
+        };
+        if pattern.starts_with('#') {
+            continue;
+        }
+        let macro_name = pattern.strip_prefix("[attr]");
+        if let Some(name) = macro_name {
+            defs.names.insert(name.to_string());
+        }
+        let assigns: Vec<AttrAssign> = words.map(attr_assign_parse).collect();
+        for (name, _) in &assigns {
+            defs.names.insert(name.clone());
+        }
+        if let Some(name) = macro_name {
+            if base.is_empty() {
+                defs.macros.insert(name.to_string(), assigns);
+            }
+            continue;
+        }
+        // Negative patterns are forbidden in attributes files.
+        if pattern.starts_with('!') {
+            continue;
+        }
+        rules.push(AttrRule {
+            pattern: pattern.to_string(),
+            assigns,
+        });
+    }
+    AttrFile {
+        base: base.to_string(),
+        rules,
+    }
+}
+
+fn attr_assign_parse(word: &str) -> AttrAssign {
+    if let Some(name) = word.strip_prefix('-') {
+        (name.to_string(), Some(AttrValue::Unset))
+    } else if let Some(name) = word.strip_prefix('!') {
+        (name.to_string(), None)
+    } else if let Some((name, value)) = word.split_once('=') {
+        (name.to_string(), Some(AttrValue::Value(value.to_string())))
+    } else {
+        (word.to_string(), Some(AttrValue::Set))
+    }
+}
+
+/// Applies assignments in order. Setting a macro also applies what it
+/// stands for.
+fn attr_apply(
+    map: &mut AttrMap,
+    assigns: &[AttrAssign],
+    macros: &HashMap<String, Vec<AttrAssign>>,
+) {
+    for (name, value) in assigns {
+        match value {
+            Some(value) => {
+                map.insert(name.clone(), value.clone());
+            }
+            None => {
+                map.shift_remove(name);
+            }
+        }
+        if *value == Some(AttrValue::Set) {
+            if let Some(expansion) = macros.get(name) {
+                attr_apply(map, expansion, macros);
+            }
+        }
+    }
+}
+
+/// Whether a pattern from the attributes file of `base` matches `path`. A
+/// pattern without a slash matches the file name at any depth below
+/// `base`; one with a slash matches the path relative to `base`.
+fn attr_pattern_matches(pattern: &str, base: &str, path: &str) -> bool {
+    let rel = match base {
+        "" => path,
+        _ => match path.strip_prefix(base).and_then(|p| p.strip_prefix('/')) {
+            Some(rel) => rel,
+            None => return false,
+        },
+    };
+    let options = glob::MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: false,
+    };
+    let Ok(glob) = glob::Pattern::new(pattern.trim_start_matches('/')) else {
+        return false;
+    };
+    if pattern.contains('/') {
+        glob.matches_with(rel, options)
+    } else {
+        let name = rel.rsplit('/').next().unwrap_or(rel);
+        glob.matches_with(name, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{attr_query, test_utils::test_repo, AttrValue};
+
+    #[test]
+    fn test_attr_query_stacking() {
+        let repo = test_repo("attr");
+        fs::write(
+            repo.worktree.join(".gitattributes"),
+            "*.txt text eol=crlf\n*.png binary\n/docs/*.md diff=markdown\n",
+        )
+        .unwrap();
+        fs::create_dir_all(repo.worktree.join("docs/sub")).unwrap();
+        fs::write(
+            repo.worktree.join("docs/.gitattributes"),
+            "*.txt -text !eol\n",
+        )
+        .unwrap();
+        fs::create_dir_all(repo.gitdir.join("info")).unwrap();
+        fs::write(repo.gitdir.join("info/attributes"), "a.png diff\n").unwrap();
+
+        let map = attr_query(&repo, "x/notes.txt").unwrap();
+        assert_eq!(map.get("text"), Some(&AttrValue::Set));
+        assert_eq!(map.get("eol"), Some(&AttrValue::Value("crlf".to_string())));
+
+        let map = attr_query(&repo, "docs/sub/notes.txt").unwrap();
+        assert_eq!(map.get("text"), Some(&AttrValue::Unset));
+        assert_eq!(map.get("eol"), None);
+
+        assert!(attr_query(&repo, "docs/a.md").unwrap().contains_key("diff"));
+        assert!(attr_query(&repo, "docs/sub/a.md").unwrap().is_empty());
+
+        let map = attr_query(&repo, "b.png").unwrap();
+        assert_eq!(map.get("binary"), Some(&AttrValue::Set));
+        assert_eq!(map.get("diff"), Some(&AttrValue::Unset));
+        let map = attr_query(&repo, "a.png").unwrap();
+        assert_eq!(map.get("diff"), Some(&AttrValue::Set));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
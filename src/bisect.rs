@@ -0,0 +1,297 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_get_active, checkout_tree, commit_subject, head_attach, head_detach, head_log_checkout,
+    head_position, object_find, ref_create, ref_list_flat, ref_resolve, repo_dir, repo_file,
+    repo_find, Ancestry, Repository,
+};
+
+/// Starts a bisection from the current HEAD, which `bisect_reset` returns
+/// to. The bad and good commits can be given right away.
+pub fn bisect_start(revs: &[String]) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    // Resolve everything before writing anything, so a typo leaves no
+    // half-recorded state behind.
+    let shas = revs
+        .iter()
+        .map(|r| bisect_resolve(&repo, r))
+        .collect::<Result<Vec<_>>>()?;
+    let start_path = repo_file(&repo, PathBuf::from("BISECT_START"), false)?;
+    let start = if start_path.exists() {
+        // Restarting keeps the original HEAD to go back to.
+        let start = fs::read_to_string(&start_path)?;
+        bisect_clean_state(&repo)?;
+        start.trim_end().to_string()
+    } else {
+        let head = ref_resolve(&repo, "HEAD")?
+            .ok_or_else(|| anyhow!("Bisecting needs a commit to start from"))?;
+        branch_get_active(&repo)?.unwrap_or(head)
+    };
+    fs::write(&start_path, format!("{}\n", start))?;
+    bisect_log(&repo, &format!("rit bisect start {}", revs.join(" ")))?;
+
+    if let Some((bad, good)) = shas.split_first() {
+        bisect_write(&repo, "bad", bad)?;
+        for sha in good {
+            bisect_write(&repo, "good", sha)?;
+        }
+    }
+    bisect_next(&repo)
+}
+
+/// Marks `revs` (HEAD if empty) as `term`, "good" or "bad", and checks out
+/// the next commit to test.
+pub fn bisect_mark(term: &str, revs: &[String]) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    if !repo_file(&repo, PathBuf::from("BISECT_START"), false)?.exists() {
+        bail!("You need to start by \"rit bisect start\"");
+    }
+    let revs = match revs {
+        [] => vec!["HEAD".to_string()],
+        _ => revs.to_vec(),
+    };
+    if term == "bad" && revs.len() > 1 {
+        bail!("'rit bisect bad' can take only one argument.");
+    }
+    for rev in &revs {
+        let sha = bisect_resolve(&repo, rev)?;
+        bisect_write(&repo, term, &sha)?;
+    }
+    bisect_next(&repo)
+}
+
+/// Ends the bisection and goes back to where `bisect_start` was run.
+pub fn bisect_reset() -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let start_path = repo_file(&repo, PathBuf::from("BISECT_START"), false)?;
+    if !start_path.exists() {
+        println!("We are not bisecting.");
+        return Ok(());
+    }
+    let start = fs::read_to_string(&start_path)?.trim_end().to_string();
+    let branch_ref = format!("refs/heads/{}", start);
+    let from = head_position(&repo)?;
+    match ref_resolve(&repo, &branch_ref)? {
+        Some(sha) => {
+            checkout_tree(&repo, &sha, false)?;
+            head_attach(&repo, &start)?;
+            head_log_checkout(&repo, &from, &start, &sha)?;
+        }
+        None => {
+            checkout_tree(&repo, &start, false)?;
+            head_detach(&repo, &start)?;
+            head_log_checkout(&repo, &from, &start, &start)?;
+        }
+    }
+    bisect_clean_state(&repo)
+}
+
+fn bisect_resolve(repo: &Repository, rev: &str) -> Result<String> {
+    object_find(repo, rev, Some(b"commit"), true)?.ok_or_else(|| anyhow!("Bad rev input: {}", rev))
+}
+
+/// Records `sha` as bad (`refs/bisect/bad`) or good
+/// (`refs/bisect/good-<sha>`), like git does.
+fn bisect_write(repo: &Repository, term: &str, sha: &str) -> Result<()> {
+    let name = match term {
+        "bad" => "bisect/bad".to_string(),
+        _ => format!("bisect/good-{}", sha),
+    };
+    repo_dir(repo, PathBuf::from("refs/bisect"), true)?;
+    ref_create(repo, &name, sha)?;
+    bisect_log(
+        repo,
+        &format!(
+            "# {}: [{}] {}\nrit bisect {} {}",
+            term,
+            sha,
+            commit_subject(repo, sha)?,
+            term,
+            sha
+        ),
+    )
+}
+
+fn bisect_log(repo: &Repository, line: &str) -> Result<()> {
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(repo_file(repo, PathBuf::from("BISECT_LOG"), false)?)?;
+    writeln!(f, "{}", line.trim_end())?;
+    Ok(())
+}
+
+fn bisect_clean_state(repo: &Repository) -> Result<()> {
+    for file in ["BISECT_START", "BISECT_LOG"] {
+        let path = repo_file(repo, PathBuf::from(file), false)?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    let refs = repo.repo_path(PathBuf::from("refs/bisect"));
+    if refs.exists() {
+        fs::remove_dir_all(refs)?;
+    }
+    Ok(())
+}
+
+/// Checks out the commit that best splits the remaining candidates, or
+/// reports the first bad commit once it is the only one left.
+fn bisect_next(repo: &Repository) -> Result<()> {
+    let bad = ref_resolve(repo, "refs/bisect/bad")?;
+    let good: Vec<String> = ref_list_flat(repo, None, None)?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with("bisect/good-"))
+        .map(|(_, sha)| sha)
+        .collect();
+    let bad = match (bad, good.len()) {
+        (Some(bad), 1..) => bad,
+        (Some(_), 0) => {
+            println!("status: waiting for good commit(s), bad commit known");
+            return Ok(());
+        }
+        (None, 0) => {
+            println!("status: waiting for both good and bad commits");
+            return Ok(());
+        }
+        (None, n) => {
+            println!("status: waiting for bad commit, {} good commit(s) known", n);
+            return Ok(());
+        }
+    };
+
+    let mut ancestry = Ancestry::new(repo)?;
+    let candidates = bisect_candidates(&mut ancestry, &bad, &good)?;
+    if candidates.is_empty() {
+        bail!("Some good revs are not ancestors of the bad rev.");
+    }
+    if candidates.len() == 1 {
+        println!("{} is the first bad commit", bad);
+        println!("    {}", commit_subject(repo, &bad)?);
+        return Ok(());
+    }
+
+    let (next, reaches) = bisect_midpoint(&mut ancestry, &candidates)?;
+    let all = candidates.len();
+    let left = all - reaches - 1;
+    let steps = bisect_steps(all);
+    println!(
+        "Bisecting: {} revision{} left to test after this (roughly {} step{})",
+        left,
+        if left == 1 { "" } else { "s" },
+        steps,
+        if steps == 1 { "" } else { "s" }
+    );
+    let from = head_position(repo)?;
+    checkout_tree(repo, &next, false)?;
+    head_detach(repo, &next)?;
+    head_log_checkout(repo, &from, &next, &next)?;
+    println!("[{}] {}", next, commit_subject(repo, &next)?);
+    Ok(())
+}
+
+/// The commits that may have introduced the change: those reachable from
+/// `bad` but not from any good commit, in breadth-first order from `bad`.
+fn bisect_candidates(ancestry: &mut Ancestry, bad: &str, good: &[String]) -> Result<Vec<String>> {
+    let mut excluded: HashSet<String> = HashSet::new();
+    let mut queue: VecDeque<String> = good.iter().cloned().collect();
+    while let Some(sha) = queue.pop_front() {
+        if excluded.insert(sha.clone()) {
+            queue.extend(ancestry.parents(&sha)?);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut ret = Vec::new();
+    let mut queue = VecDeque::from([bad.to_string()]);
+    while let Some(sha) = queue.pop_front() {
+        if excluded.contains(&sha) || !seen.insert(sha.clone()) {
+            continue;
+        }
+        queue.extend(ancestry.parents(&sha)?);
+        ret.push(sha);
+    }
+    Ok(ret)
+}
+
+/// Picks the candidate whose number of candidate ancestors (itself
+/// included) is closest to half of all candidates, returning it with that
+/// count. Whichever way it is marked, about half of the candidates go.
+fn bisect_midpoint(ancestry: &mut Ancestry, candidates: &[String]) -> Result<(String, usize)> {
+    let set: HashSet<&String> = candidates.iter().collect();
+    let all = candidates.len();
+    let mut best: Option<(usize, &String, usize)> = None;
+    // The first candidate is the bad commit, which tells us nothing new.
+    for sha in &candidates[1..] {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([sha.clone()]);
+        while let Some(c) = queue.pop_front() {
+            if set.contains(&c) && seen.insert(c.clone()) {
+                queue.extend(ancestry.parents(&c)?);
+            }
+        }
+        let weight = seen.len();
+        let distance = weight.min(all - weight);
+        // Between equally good splits, git prefers the older commit.
+        if best.is_none_or(|(d, _, w)| distance > d || (distance == d && weight < w)) {
+            best = Some((distance, sha, weight));
+        }
+    }
+    let (_, sha, weight) = best.unwrap();
+    Ok((sha.clone(), weight))
+}
+
+/// Roughly how many more steps bisecting `all` candidates takes, with
+/// git's estimate.
+fn bisect_steps(all: usize) -> usize {
+    if all < 3 {
+        return 0;
+    }
+    let n = all.ilog2() as usize;
+    let e = 1 << n;
+    if e < 3 * (all - e) {
+        n
+    } else {
+        n - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{bisect_candidates, bisect_midpoint};
+    use crate::{
+        test_utils::{make_commit, test_repo},
+        Ancestry,
+    };
+
+    #[test]
+    fn test_bisect_midpoint() {
+        let repo = test_repo("bisect");
+        let mut commits = vec![make_commit(&repo, "0", &[])];
+        for i in 1..8 {
+            let parent = commits[i - 1].clone();
+            commits.push(make_commit(&repo, &i.to_string(), &[&parent]));
+        }
+
+        let mut ancestry = Ancestry::new(&repo).unwrap();
+        let candidates =
+            bisect_candidates(&mut ancestry, &commits[7], &[commits[1].clone()]).unwrap();
+        assert_eq!(candidates.len(), 6);
+        assert_eq!(candidates[0], commits[7]);
+        assert!(!candidates.contains(&commits[1]));
+
+        let (mid, weight) = bisect_midpoint(&mut ancestry, &candidates).unwrap();
+        assert_eq!(mid, commits[4]);
+        assert_eq!(weight, 3);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,403 @@
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    blob_read, date_format_iso, ident_parse, kvlm_get_str, myers_diff, object_find, object_read,
+    repo_find, repo_relative_path, sha_short, split_lines, Commit, DiffOp, Repository, Tree,
+};
+
+/// Where a line of the blamed file comes from.
+pub struct BlameLine {
+    /// The commit that introduced the line.
+    pub commit: String,
+    /// The line's number (1-based) in that commit's version of the file.
+    pub orig_line: usize,
+}
+
+pub fn blame(
+    path: &Path,
+    range: Option<&str>,
+    rev: &str,
+    ignore_whitespace: bool,
+    incremental: bool,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let path = repo_relative_path(&repo, path)?;
+    let tip = object_find(&repo, rev, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("bad revision '{}'", rev))?;
+    let blob = blob_at(&repo, &tip, &path)?
+        .ok_or_else(|| anyhow!("no such path '{}' in {}", path, rev))?;
+    let content = blob_read(&repo, &blob)?;
+    let lines = split_lines(&content);
+
+    let (start, end) = match range {
+        Some(r) => blame_range_parse(r, lines.len())?,
+        None => (1, lines.len()),
+    };
+    if incremental {
+        return blame_incremental(&repo, &tip, &path, start - 1..end, ignore_whitespace);
+    }
+    let blamed = blame_lines(&repo, &tip, &path, start - 1..end, ignore_whitespace)?;
+
+    // Like git, authors are padded to the longest name and line numbers
+    // to the widest number shown.
+    let mut infos = HashMap::new();
+    for line in &blamed {
+        if !infos.contains_key(&line.commit) {
+            infos.insert(line.commit.clone(), blame_commit_info(&repo, &line.commit)?);
+        }
+    }
+    let name_width = infos
+        .values()
+        .map(|i| i.0.chars().count())
+        .max()
+        .unwrap_or(0);
+    let num_width = end.to_string().len();
+
+    let mut out = Vec::new();
+    for (i, line) in blamed.iter().enumerate() {
+        let (author, date, boundary) = &infos[&line.commit];
+        let id = if *boundary {
+            format!("^{}", sha_short(&repo, &line.commit))
+        } else {
+            line.commit[..8].to_string()
+        };
+        write!(
+            out,
+            "{} ({:<nw$} {} {:>lw$}) ",
+            id,
+            author,
+            date,
+            start + i,
+            nw = name_width,
+            lw = num_width
+        )?;
+        out.extend_from_slice(lines[start - 1 + i]);
+        if !out.ends_with(b"\n") {
+            out.push(b'\n');
+        }
+    }
+    std::io::stdout().write_all(&out)?;
+    Ok(())
+}
+
+/// Prints blame in git's `--incremental` format as the walk finds each
+/// group of lines: `<commit> <orig line> <final line> <count>`, the
+/// commit's details the first time it shows up, then the file name.
+fn blame_incremental(
+    repo: &Repository,
+    tip: &str,
+    path: &str,
+    lines: std::ops::Range<usize>,
+    ignore_whitespace: bool,
+) -> Result<()> {
+    let first = lines.start;
+    let mut shown = HashSet::new();
+    let mut stdout = std::io::stdout();
+    blame_walk(
+        repo,
+        tip,
+        path,
+        lines,
+        ignore_whitespace,
+        |sha, mut found| {
+            found.sort();
+            let mut out = Vec::new();
+            let mut i = 0;
+            while i < found.len() {
+                // A group is a run that is contiguous on both sides.
+                let (slot, orig) = found[i];
+                let mut n = 1;
+                while i + n < found.len() && found[i + n] == (slot + n, orig + n) {
+                    n += 1;
+                }
+                i += n;
+                writeln!(out, "{} {} {} {}", sha, orig + 1, first + slot + 1, n)?;
+                if shown.insert(sha.to_string()) {
+                    out.extend(blame_commit_details(repo, sha)?);
+                }
+                out.extend(blame_commit_origin(repo, sha, path)?);
+                writeln!(out, "filename {}", path)?;
+            }
+            stdout.write_all(&out)?;
+            stdout.flush()?;
+            Ok(())
+        },
+    )
+}
+
+/// The author, committer and summary lines of the porcelain formats.
+fn blame_commit_details(repo: &Repository, sha: &str) -> Result<Vec<u8>> {
+    let obj = object_read(repo, sha)?;
+    let commit = obj
+        .as_any()
+        .downcast_ref::<Commit>()
+        .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+    let mut out = Vec::new();
+    for role in ["author", "committer"] {
+        let ident = kvlm_get_str(&commit.kvlm, role.as_bytes()).unwrap_or_default();
+        let Some((who, time, tz)) = ident_parse(&ident) else {
+            continue;
+        };
+        let (name, mail) = match who.split_once(" <") {
+            Some((name, mail)) => (name, format!("<{}", mail)),
+            None => (who, String::new()),
+        };
+        writeln!(out, "{} {}", role, name)?;
+        writeln!(out, "{}-mail {}", role, mail)?;
+        writeln!(out, "{}-time {}", role, time)?;
+        writeln!(out, "{}-tz {}", role, tz)?;
+    }
+    writeln!(
+        out,
+        "summary {}",
+        commit.message().lines().next().unwrap_or("")
+    )?;
+    Ok(out)
+}
+
+/// Where lines of `sha` were before it, repeated for every group: the root
+/// `boundary`, or the `previous` commit with the file.
+fn blame_commit_origin(repo: &Repository, sha: &str, path: &str) -> Result<Vec<u8>> {
+    let obj = object_read(repo, sha)?;
+    let commit = obj
+        .as_any()
+        .downcast_ref::<Commit>()
+        .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+    let mut out = Vec::new();
+    if commit.parents().is_empty() {
+        writeln!(out, "boundary")?;
+    }
+    for parent in commit.parents() {
+        if blob_at(repo, &parent, path)?.is_some() {
+            writeln!(out, "previous {} {}", parent, path)?;
+            break;
+        }
+    }
+    Ok(out)
+}
+
+/// Parses `-L` as `start,end`, `start,+count` or `start` (to the end of
+/// the file) into an inclusive 1-based range.
+fn blame_range_parse(range: &str, len: usize) -> Result<(usize, usize)> {
+    let bad = || anyhow!("invalid -L range '{}'", range);
+    let (start, end) = range.split_once(',').unwrap_or((range, ""));
+    let start: usize = start.parse().map_err(|_| bad())?;
+    let end = match end {
+        "" => len,
+        e => match e.strip_prefix('+') {
+            Some(count) => start + count.parse::<usize>().map_err(|_| bad())? - 1,
+            None => e.parse().map_err(|_| bad())?,
+        },
+    };
+    if start == 0 || start > end {
+        bail!("invalid -L range '{}'", range);
+    }
+    if end > len {
+        bail!("file has only {} lines", len);
+    }
+    Ok((start, end))
+}
+
+/// The author, formatted author date and whether `sha` is a root commit.
+fn blame_commit_info(repo: &Repository, sha: &str) -> Result<(String, String, bool)> {
+    let obj = object_read(repo, sha)?;
+    let commit = obj
+        .as_any()
+        .downcast_ref::<Commit>()
+        .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+    let author = kvlm_get_str(&commit.kvlm, b"author").unwrap_or_default();
+    let (name, date) = match ident_parse(&author) {
+        Some((who, time, tz)) => {
+            let name = who.split(" <").next().unwrap_or(who).to_string();
+            (name, date_format_iso(time, tz))
+        }
+        None => (author.clone(), String::new()),
+    };
+    Ok((name, date, commit.parents().is_empty()))
+}
+
+/// Attributes the lines `lines` (0-based) of `path` as of `tip` to the
+/// commits that last changed them. With `ignore_whitespace`, a line that
+/// only changed in whitespace keeps its older origin, like `blame -w`.
+pub fn blame_lines(
+    repo: &Repository,
+    tip: &str,
+    path: &str,
+    lines: std::ops::Range<usize>,
+    ignore_whitespace: bool,
+) -> Result<Vec<BlameLine>> {
+    let mut result: Vec<Option<BlameLine>> = lines.clone().map(|_| None).collect();
+    blame_walk(repo, tip, path, lines, ignore_whitespace, |sha, found| {
+        for (slot, line) in found {
+            result[slot] = Some(BlameLine {
+                commit: sha.to_string(),
+                orig_line: line + 1,
+            });
+        }
+        Ok(())
+    })?;
+    Ok(result.into_iter().map(|l| l.unwrap()).collect())
+}
+
+/// Does the work of `blame_lines`, handing each commit's lines to `found`
+/// as (index into `lines`, 0-based line in that commit) as soon as the
+/// commit is known to have introduced them.
+///
+/// Starting from `tip`, each commit passes the lines it shares with a
+/// parent (by diffing the two versions of the file) on to that parent and
+/// keeps the rest. Commits are visited newest first so that a commit is
+/// only processed once all of its children have handed their lines down.
+fn blame_walk(
+    repo: &Repository,
+    tip: &str,
+    path: &str,
+    lines: std::ops::Range<usize>,
+    ignore_whitespace: bool,
+    mut found: impl FnMut(&str, Vec<(usize, usize)>) -> Result<()>,
+) -> Result<()> {
+    let normalize = |content: &[u8]| -> Vec<Vec<u8>> {
+        split_lines(content)
+            .into_iter()
+            .map(|l| match ignore_whitespace {
+                true => l
+                    .iter()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .copied()
+                    .collect(),
+                false => l.to_vec(),
+            })
+            .collect()
+    };
+    // For each commit still to visit: (index into result, line in commit).
+    let mut pending: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+    pending.insert(tip.to_string(), lines.clone().enumerate().collect());
+    let mut queue = BinaryHeap::new();
+    queue.push((commit_time(repo, tip)?, tip.to_string()));
+
+    while let Some((_, sha)) = queue.pop() {
+        let Some(mut remaining) = pending.remove(&sha) else {
+            continue;
+        };
+        let blob = blob_at(repo, &sha, path)?.unwrap();
+        let own = normalize(&blob_read(repo, &blob)?);
+
+        let obj = object_read(repo, &sha)?;
+        let commit = obj
+            .as_any()
+            .downcast_ref::<Commit>()
+            .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+        for parent in commit.parents() {
+            if remaining.is_empty() {
+                break;
+            }
+            let Some(parent_blob) = blob_at(repo, &parent, path)? else {
+                continue;
+            };
+            let passed: Vec<(usize, usize)> = if parent_blob == blob {
+                std::mem::take(&mut remaining)
+            } else {
+                let theirs = normalize(&blob_read(repo, &parent_blob)?);
+                let mut to_parent = HashMap::new();
+                for op in myers_diff(&theirs, &own) {
+                    if let DiffOp::Equal(i, j) = op {
+                        to_parent.insert(j, i);
+                    }
+                }
+                let mut passed = Vec::new();
+                remaining.retain(|&(slot, line)| match to_parent.get(&line) {
+                    Some(&i) => {
+                        passed.push((slot, i));
+                        false
+                    }
+                    None => true,
+                });
+                passed
+            };
+            if !passed.is_empty() {
+                if !pending.contains_key(&parent) {
+                    queue.push((commit_time(repo, &parent)?, parent.clone()));
+                }
+                pending.entry(parent).or_default().extend(passed);
+            }
+        }
+
+        if !remaining.is_empty() {
+            found(&sha, remaining)?;
+        }
+    }
+    Ok(())
+}
+
+fn commit_time(repo: &Repository, sha: &str) -> Result<i64> {
+    let obj = object_read(repo, sha)?;
+    let commit = obj
+        .as_any()
+        .downcast_ref::<Commit>()
+        .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+    Ok(commit.commit_time())
+}
+
+/// The blob at `path` in the tree of `commit`, if there is a file there.
+fn blob_at(repo: &Repository, commit: &str, path: &str) -> Result<Option<String>> {
+    let mut sha = object_find(repo, commit, Some(b"tree"), true)?
+        .ok_or_else(|| anyhow!("{} has no tree", commit))?;
+    let components: Vec<&str> = path.split('/').collect();
+    for (i, name) in components.iter().enumerate() {
+        let obj = object_read(repo, &sha)?;
+        let tree = obj
+            .as_any()
+            .downcast_ref::<Tree>()
+            .ok_or_else(|| anyhow!("Object {} is not a tree", sha))?;
+        let Some(item) = tree.items.iter().find(|l| l.path == *name) else {
+            return Ok(None);
+        };
+        let is_tree = item.mode.starts_with(b"04");
+        if is_tree == (i + 1 == components.len()) || item.mode.starts_with(b"16") {
+            return Ok(None);
+        }
+        sha = item.sha.clone();
+    }
+    Ok(Some(sha))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        blame_lines,
+        test_utils::{make_commit_at, test_repo},
+    };
+
+    #[test]
+    fn test_blame_lines() {
+        let repo = test_repo("blame");
+        let first = make_commit_at(&repo, "a\nb\nc\n", &[], 1000);
+        let second = make_commit_at(&repo, "a\nB\nc\nd\n", &[&first], 2000);
+        let third = make_commit_at(&repo, "x\na\nB\nc\nd\n", &[&second], 3000);
+
+        let blamed = blame_lines(&repo, &third, "file", 0..5, false).unwrap();
+        let commits: Vec<&str> = blamed.iter().map(|l| l.commit.as_str()).collect();
+        assert_eq!(commits, [&third, &first, &second, &first, &second]);
+        let orig: Vec<usize> = blamed.iter().map(|l| l.orig_line).collect();
+        assert_eq!(orig, [1, 1, 2, 3, 4]);
+
+        let blamed = blame_lines(&repo, &third, "file", 3..5, false).unwrap();
+        assert_eq!(blamed[0].commit, first);
+        assert_eq!(blamed[1].commit, second);
+
+        // Only whitespace changed on the second line.
+        let fourth = make_commit_at(&repo, "x\na\n  B\nc\nd\n", &[&third], 4000);
+        let blamed = blame_lines(&repo, &fourth, "file", 2..3, false).unwrap();
+        assert_eq!(blamed[0].commit, fourth);
+        let blamed = blame_lines(&repo, &fourth, "file", 2..3, true).unwrap();
+        assert_eq!(blamed[0].commit, second);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -1,6 +1,8 @@
 use std::any::Any;
 
-use crate::Object;
+use anyhow::{anyhow, Result};
+
+use crate::{object_read, Object, Repository};
 
 pub struct Blob {
     pub blobdata: Vec<u8>,
@@ -18,6 +20,16 @@ impl Blob {
     }
 }
 
+/// Reads the content of the blob `sha`.
+pub fn blob_read(repo: &Repository, sha: &str) -> Result<Vec<u8>> {
+    let obj = object_read(repo, sha)?;
+    let blob = obj
+        .as_any()
+        .downcast_ref::<Blob>()
+        .ok_or_else(|| anyhow!("Object {} is not a blob", sha))?;
+    Ok(blob.blobdata.clone())
+}
+
 impl Object for Blob {
     fn fmt(&self) -> &'static [u8] {
         b"blob"
@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use indexmap::IndexMap;
+
+use crate::{
+    abbrev_len, branch_get_active, branch_name_valid, commit_subject, object_find, ref_create,
+    ref_delete, ref_list_flat, ref_resolve, reflog_append, reflog_write, repo_file, repo_find,
+    sha_abbrev, sha_short, worktree_branch_holder, Ancestry, Repository,
+};
+
+/// Which branches to list, mirroring git's filters. Each commit is given
+/// as a name and resolved before filtering.
+#[derive(Default)]
+pub struct BranchFilter {
+    /// Only branches whose tip contains this commit.
+    pub contains: Option<String>,
+    /// Only branches whose tip is reachable from this commit.
+    pub merged: Option<String>,
+    /// Only branches whose tip is not reachable from this commit.
+    pub no_merged: Option<String>,
+}
+
+pub fn branch(
+    name: Option<String>,
+    start_point: &str,
+    delete: bool,
+    force_delete: bool,
+    filter: &BranchFilter,
+    verbose: bool,
+    abbrev: Option<usize>,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let abbrev = abbrev_len(&repo, abbrev);
+
+    match name {
+        Some(name) if delete || force_delete => branch_delete(&repo, &name, force_delete),
+        Some(name) => branch_create(&repo, &name, start_point),
+        None => {
+            let active = branch_get_active(&repo)?;
+            let branches = branch_list(&repo, filter)?;
+            let width = branches.keys().map(|name| name.len()).max().unwrap_or(0);
+            for (name, tip) in &branches {
+                let marker = if Some(name) == active.as_ref() {
+                    "*"
+                } else {
+                    " "
+                };
+                if verbose {
+                    println!(
+                        "{} {:<width$} {} {}",
+                        marker,
+                        name,
+                        sha_abbrev(&repo, tip, abbrev),
+                        commit_subject(&repo, tip)?
+                    );
+                } else {
+                    println!("{} {}", marker, name);
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Returns the local branches (name -> tip) that pass `filter`.
+pub fn branch_list(repo: &Repository, filter: &BranchFilter) -> Result<IndexMap<String, String>> {
+    let heads = ref_list_flat(repo, Some(repo.repo_path("refs/heads".into())), None)?;
+
+    let resolve = |name: &Option<String>| -> Result<Option<String>> {
+        match name {
+            Some(n) => Ok(Some(
+                object_find(repo, n, Some(b"commit"), true)?
+                    .ok_or_else(|| anyhow!("malformed object name {}", n))?,
+            )),
+            None => Ok(None),
+        }
+    };
+    let contains = resolve(&filter.contains)?;
+    let merged = resolve(&filter.merged)?;
+    let no_merged = resolve(&filter.no_merged)?;
+
+    let mut ancestry = Ancestry::new(repo)?;
+    let mut ret = IndexMap::new();
+    for (name, tip) in heads {
+        if let Some(commit) = &contains {
+            if !ancestry.contains(&tip, commit)? {
+                continue;
+            }
+        }
+        if let Some(commit) = &merged {
+            if !ancestry.is_ancestor(&tip, commit)? {
+                continue;
+            }
+        }
+        if let Some(commit) = &no_merged {
+            if ancestry.is_ancestor(&tip, commit)? {
+                continue;
+            }
+        }
+        ret.insert(name, tip);
+    }
+    Ok(ret)
+}
+
+fn branch_create(repo: &Repository, name: &str, start_point: &str) -> Result<()> {
+    if !branch_name_valid(name) {
+        bail!("'{}' is not a valid branch name", name);
+    }
+    if ref_resolve(repo, &format!("refs/heads/{}", name))?.is_some() {
+        bail!("A branch named '{}' already exists.", name);
+    }
+    let sha = object_find(repo, start_point, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("Not a valid object name: '{}'.", start_point))?;
+    repo_file(repo, PathBuf::from(format!("refs/heads/{}", name)), true)?;
+    ref_create(repo, &format!("heads/{}", name), &sha)?;
+    reflog_append(
+        repo,
+        &format!("refs/heads/{}", name),
+        None,
+        &sha,
+        &format!("branch: Created from {}", start_point),
+    )
+}
+
+fn branch_delete(repo: &Repository, name: &str, force: bool) -> Result<()> {
+    let tip = ref_resolve(repo, &format!("refs/heads/{}", name))?
+        .ok_or_else(|| anyhow!("branch '{}' not found.", name))?;
+    if let Some(holder) = worktree_branch_holder(repo, name)? {
+        bail!(
+            "Cannot delete branch '{}' checked out at '{}'",
+            name,
+            holder.display()
+        );
+    }
+    if !force {
+        if let Some(head) = ref_resolve(repo, "HEAD")? {
+            if !Ancestry::new(repo)?.is_ancestor(&tip, &head)? {
+                bail!(
+                    "The branch '{}' is not fully merged.\n\
+                     If you are sure you want to delete it, run 'rit branch -D {}'.",
+                    name,
+                    name
+                );
+            }
+        }
+    }
+    ref_delete(repo, &format!("refs/heads/{}", name))?;
+    reflog_write(repo, &format!("refs/heads/{}", name), &[])?;
+    println!("Deleted branch {} (was {}).", name, sha_short(repo, &tip));
+    Ok(())
+}
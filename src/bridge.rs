@@ -0,0 +1,390 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    commit_create_as, diff_trees, file_write_locked, object_read, object_write, ref_create,
+    ref_resolve, tree_from_leaves, tree_to_leaves, Blob, Commit, Repository, TreeLeaf,
+};
+
+/// The number a bridge names a commit by, as in git's fast-import
+/// streams, so that it can refer to commits before or without knowing
+/// their ids.
+pub type Mark = u64;
+
+/// The marks handed out so far and the commits they stand for. Saved
+/// between runs, in the ":<mark> <sha>" lines of git's marks files, they
+/// make bridging incremental: commits already marked are not exported
+/// again, and imports can build on the commits of earlier ones.
+#[derive(Default)]
+pub struct Marks {
+    shas: BTreeMap<Mark, String>,
+    marks: HashMap<String, Mark>,
+}
+
+impl Marks {
+    /// Reads a marks file; one that doesn't exist yet holds no marks.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut marks = Self::default();
+        let Ok(content) = fs::read_to_string(path) else {
+            return Ok(marks);
+        };
+        for line in content.lines() {
+            let parsed = line
+                .strip_prefix(':')
+                .and_then(|l| l.split_once(' '))
+                .and_then(|(mark, sha)| Some((mark.parse().ok()?, sha)));
+            let Some((mark, sha)) = parsed else {
+                bail!("corrupt marks file {}: '{}'", path.display(), line);
+            };
+            marks.insert(mark, sha);
+        }
+        Ok(marks)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content: String = self
+            .shas
+            .iter()
+            .map(|(mark, sha)| format!(":{} {}\n", mark, sha))
+            .collect();
+        file_write_locked(path, content.as_bytes(), false)?;
+        Ok(())
+    }
+
+    /// The commit `mark` stands for.
+    pub fn sha(&self, mark: Mark) -> Option<&str> {
+        self.shas.get(&mark).map(String::as_str)
+    }
+
+    /// The mark of the commit `sha`, if it has one.
+    pub fn mark(&self, sha: &str) -> Option<Mark> {
+        self.marks.get(sha).copied()
+    }
+
+    pub fn insert(&mut self, mark: Mark, sha: &str) {
+        self.shas.insert(mark, sha.to_string());
+        self.marks.insert(sha.to_string(), mark);
+    }
+
+    /// Marks `sha` with the next free mark.
+    fn assign(&mut self, sha: &str) -> Mark {
+        let mark = self.shas.keys().next_back().map_or(1, |last| last + 1);
+        self.insert(mark, sha);
+        mark
+    }
+}
+
+/// A change to one file, against the commit's first parent.
+#[derive(Clone)]
+pub enum FileChange {
+    /// The file at `path` has this mode and content. For a submodule
+    /// (mode 160000) the content is the hex id of its commit.
+    Modify {
+        path: String,
+        mode: Vec<u8>,
+        content: Vec<u8>,
+    },
+    Delete {
+        path: String,
+    },
+}
+
+/// A commit as it crosses a bridge: who made it and what it changed, with
+/// its parents named by their marks.
+#[derive(Clone)]
+pub struct BridgeCommit {
+    pub mark: Mark,
+    /// The ref it is exported from or imported to, e.g. "refs/heads/main".
+    pub refname: String,
+    /// Identities with their date, as in commit objects:
+    /// "Name <email> 1700000000 +0100".
+    pub author: String,
+    pub committer: String,
+    pub message: String,
+    pub parents: Vec<Mark>,
+    pub changes: Vec<FileChange>,
+}
+
+/// Where `history_export` sends commits: a bridge writing them into
+/// another system.
+pub trait HistoryExporter {
+    /// Takes the next commit, whose parents it has already been given
+    /// (or was in an earlier, marked run).
+    fn commit(&mut self, commit: &BridgeCommit) -> Result<()>;
+
+    /// Takes where each exported ref ends up, once all commits are in.
+    fn reset(&mut self, _refname: &str, _mark: Mark) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Where `history_import` gets commits from: a bridge reading another
+/// system's history.
+pub trait HistoryImporter {
+    /// The next commit to write, after its parents, or None once there
+    /// are no more.
+    fn next_commit(&mut self) -> Result<Option<BridgeCommit>>;
+}
+
+/// Hands the history of `refnames` to `exporter`, parents first. Commits
+/// that already have a mark are left out, and so are the parents of the
+/// others that lie beyond them; a commit without any marked or exported
+/// parent carries its whole tree as changes. Returns the number of
+/// commits exported.
+pub fn history_export(
+    repo: &Repository,
+    refnames: &[String],
+    marks: &mut Marks,
+    exporter: &mut dyn HistoryExporter,
+) -> Result<usize> {
+    let mut count = 0;
+    let mut tips = Vec::new();
+    for refname in refnames {
+        let tip = ref_resolve(repo, refname)?.ok_or_else(|| anyhow!("{}: no such ref", refname))?;
+        for sha in bridge_unmarked_commits(repo, &tip, marks)? {
+            let obj = object_read(repo, &sha)?;
+            let commit = obj
+                .as_any()
+                .downcast_ref::<Commit>()
+                .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+            let parents: Vec<Mark> = commit
+                .parents()
+                .iter()
+                .filter_map(|p| marks.mark(p))
+                .collect();
+            let base = match commit.parents().first() {
+                Some(first) if marks.mark(first).is_some() => commit_tree(repo, first)?,
+                _ => None,
+            };
+            let ident = |key: &[u8]| crate::kvlm_get_str(&commit.kvlm, key).unwrap_or_default();
+            let exported = BridgeCommit {
+                mark: marks.assign(&sha),
+                refname: refname.clone(),
+                author: ident(b"author"),
+                committer: ident(b"committer"),
+                message: commit.message(),
+                parents,
+                changes: bridge_changes(repo, base.as_deref(), commit.tree().as_deref())?,
+            };
+            exporter.commit(&exported)?;
+            count += 1;
+        }
+        tips.push((refname, tip));
+    }
+    for (refname, tip) in tips {
+        if let Some(mark) = marks.mark(&tip) {
+            exporter.reset(refname, mark)?;
+        }
+    }
+    Ok(count)
+}
+
+/// Writes each commit `importer` yields on top of its first parent's tree
+/// and points its ref at it, recording its mark. Commits whose mark is
+/// already known were imported before and are skipped. Returns the number
+/// of commits written.
+pub fn history_import(
+    repo: &Repository,
+    marks: &mut Marks,
+    importer: &mut dyn HistoryImporter,
+) -> Result<usize> {
+    let mut count = 0;
+    while let Some(commit) = importer.next_commit()? {
+        if let Some(sha) = marks.sha(commit.mark) {
+            ref_create(repo, refname_relative(&commit.refname)?, sha)?;
+            continue;
+        }
+        let parents = commit
+            .parents
+            .iter()
+            .map(|&mark| {
+                marks
+                    .sha(mark)
+                    .map(str::to_string)
+                    .ok_or_else(|| anyhow!("mark :{} not declared", mark))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut leaves: BTreeMap<String, TreeLeaf> = match parents.first() {
+            Some(first) => tree_to_leaves(repo, first, "")?
+                .into_iter()
+                .map(|leaf| (leaf.path.clone(), leaf))
+                .collect(),
+            None => BTreeMap::new(),
+        };
+        for change in &commit.changes {
+            match change {
+                FileChange::Modify {
+                    path,
+                    mode,
+                    content,
+                } => {
+                    let sha = match mode.starts_with(b"16") {
+                        true => String::from_utf8(content.clone())?,
+                        false => object_write(&Blob::new(content), Some(repo))?,
+                    };
+                    let leaf = TreeLeaf {
+                        mode: mode.clone(),
+                        path: path.clone(),
+                        sha,
+                    };
+                    leaves.insert(path.clone(), leaf);
+                }
+                FileChange::Delete { path } => {
+                    leaves.remove(path);
+                }
+            }
+        }
+        let leaves: Vec<TreeLeaf> = leaves.into_values().collect();
+        let tree = tree_from_leaves(repo, &leaves)?;
+        let sha = commit_create_as(
+            repo,
+            &tree,
+            &parents,
+            Some(&commit.author),
+            Some(&commit.committer),
+            &commit.message,
+        )?;
+        marks.insert(commit.mark, &sha);
+        ref_create(repo, refname_relative(&commit.refname)?, &sha)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// The commits reachable from `tip` without passing a marked one, each
+/// after its parents.
+fn bridge_unmarked_commits(repo: &Repository, tip: &str, marks: &Marks) -> Result<Vec<String>> {
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack = vec![(tip.to_string(), false)];
+    while let Some((sha, parents_done)) = stack.pop() {
+        if parents_done {
+            order.push(sha);
+            continue;
+        }
+        if marks.mark(&sha).is_some() || !seen.insert(sha.clone()) {
+            continue;
+        }
+        let obj = object_read(repo, &sha)?;
+        let commit = obj
+            .as_any()
+            .downcast_ref::<Commit>()
+            .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+        stack.push((sha.clone(), true));
+        for parent in commit.parents().into_iter().rev() {
+            stack.push((parent, false));
+        }
+    }
+    Ok(order)
+}
+
+fn commit_tree(repo: &Repository, sha: &str) -> Result<Option<String>> {
+    let obj = object_read(repo, sha)?;
+    Ok(obj.as_any().downcast_ref::<Commit>().and_then(Commit::tree))
+}
+
+/// The files that differ between two trees, with the new content of each.
+fn bridge_changes(
+    repo: &Repository,
+    old: Option<&str>,
+    new: Option<&str>,
+) -> Result<Vec<FileChange>> {
+    let mut changes = Vec::new();
+    for change in diff_trees(repo, old, new)? {
+        let Some(leaf) = change.new else {
+            changes.push(FileChange::Delete { path: change.path });
+            continue;
+        };
+        let content = match leaf.mode.starts_with(b"16") {
+            true => leaf.sha.into_bytes(),
+            false => {
+                let obj = object_read(repo, &leaf.sha)?;
+                let blob = obj
+                    .as_any()
+                    .downcast_ref::<Blob>()
+                    .ok_or_else(|| anyhow!("Object {} is not a blob", leaf.sha))?;
+                blob.blobdata.clone()
+            }
+        };
+        changes.push(FileChange::Modify {
+            path: change.path,
+            mode: leaf.mode,
+            content,
+        });
+    }
+    Ok(changes)
+}
+
+fn refname_relative(refname: &str) -> Result<&str> {
+    refname
+        .strip_prefix("refs/")
+        .ok_or_else(|| anyhow!("'{}' is not a full ref name", refname))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::{history_export, history_import, BridgeCommit, Marks};
+    use crate::{
+        object_read, ref_create, ref_resolve,
+        test_utils::{make_commit, test_repo},
+        Commit, HistoryExporter, HistoryImporter,
+    };
+
+    /// Bridges to a "system" that is just a list of commits.
+    #[derive(Default)]
+    struct Recorder(Vec<BridgeCommit>);
+
+    impl HistoryExporter for Recorder {
+        fn commit(&mut self, commit: &BridgeCommit) -> Result<()> {
+            self.0.push(commit.clone());
+            Ok(())
+        }
+    }
+
+    impl HistoryImporter for Recorder {
+        fn next_commit(&mut self) -> Result<Option<BridgeCommit>> {
+            Ok((!self.0.is_empty()).then(|| self.0.remove(0)))
+        }
+    }
+
+    #[test]
+    fn test_history_bridge() {
+        let repo = test_repo("bridge-from");
+        let one = make_commit(&repo, "one", &[]);
+        let two = make_commit(&repo, "two", &[&one]);
+        ref_create(&repo, "heads/main", &two).unwrap();
+
+        let refs = ["refs/heads/main".to_string()];
+        let mut marks = Marks::default();
+        let mut recorder = Recorder::default();
+        assert_eq!(
+            history_export(&repo, &refs, &mut marks, &mut recorder).unwrap(),
+            2
+        );
+        assert_eq!(recorder.0[1].parents, [recorder.0[0].mark]);
+        // Marked commits are not exported again.
+        let three = make_commit(&repo, "three", &[&two]);
+        ref_create(&repo, "heads/main", &three).unwrap();
+        let mut more = Recorder::default();
+        history_export(&repo, &refs, &mut marks, &mut more).unwrap();
+        assert_eq!(more.0.len(), 1);
+        recorder.0.extend(more.0);
+
+        // Importing the same commits recreates them exactly.
+        let other = test_repo("bridge-to");
+        let mut other_marks = Marks::default();
+        history_import(&other, &mut other_marks, &mut recorder).unwrap();
+        let tip = ref_resolve(&other, "refs/heads/main").unwrap().unwrap();
+        assert_eq!(tip, three);
+        let obj = object_read(&other, &tip).unwrap();
+        let commit = obj.as_any().downcast_ref::<Commit>().unwrap();
+        assert_eq!(commit.parents(), [two]);
+    }
+}
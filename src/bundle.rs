@@ -0,0 +1,344 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    commit_subject, object_find, object_read, pack_unpack, pack_write, reachable_objects,
+    ref_list_flat, ref_resolve, repo_find, rev_walk, Ancestry, Repository,
+};
+
+const BUNDLE_V2: &[u8] = b"# v2 git bundle\n";
+const BUNDLE_V3: &[u8] = b"# v3 git bundle\n";
+
+/// A bundle file: the refs it carries, the commits the receiving
+/// repository must already have, and a pack of everything in between.
+pub struct Bundle {
+    /// The commits it was cut off at, with their subjects.
+    pub prerequisites: Vec<(String, String)>,
+    /// The refs, as (name, sha).
+    pub refs: Vec<(String, String)>,
+    pub pack: Vec<u8>,
+}
+
+impl Bundle {
+    /// Whether the file at `path` starts like a bundle.
+    pub fn is_bundle(path: &Path) -> bool {
+        let mut signature = [0; BUNDLE_V2.len()];
+        fs::File::open(path)
+            .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut signature))
+            .is_ok_and(|_| signature == BUNDLE_V2 || signature == BUNDLE_V3)
+    }
+
+    pub fn read(path: &Path) -> Result<Bundle> {
+        let data = fs::read(path).map_err(|_| anyhow!("could not open '{}'", path.display()))?;
+        Bundle::parse(&data).ok_or_else(|| {
+            anyhow!(
+                "'{}' does not look like a v2 or v3 bundle file",
+                path.display()
+            )
+        })
+    }
+
+    /// Parses the header lines up to the blank one, then takes the rest as
+    /// the pack. Only sha1 bundles are understood.
+    pub fn parse(data: &[u8]) -> Option<Bundle> {
+        let v3 = data.starts_with(BUNDLE_V3);
+        if !v3 && !data.starts_with(BUNDLE_V2) {
+            return None;
+        }
+        let mut bundle = Bundle {
+            prerequisites: Vec::new(),
+            refs: Vec::new(),
+            pack: Vec::new(),
+        };
+        let mut pos = BUNDLE_V2.len();
+        loop {
+            let len = data[pos..].iter().position(|&b| b == b'\n')?;
+            let line = std::str::from_utf8(&data[pos..pos + len]).ok()?;
+            pos += len + 1;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(capability) = line.strip_prefix('@').filter(|_| v3) {
+                if capability.starts_with("object-format=") && capability != "object-format=sha1" {
+                    return None;
+                }
+                continue;
+            }
+            let (prerequisite, line) = match line.strip_prefix('-') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let (sha, rest) = line.split_once(' ').unwrap_or((line, ""));
+            if sha.len() != 40 || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            match prerequisite {
+                true => bundle
+                    .prerequisites
+                    .push((sha.to_string(), rest.to_string())),
+                false => bundle.refs.push((rest.to_string(), sha.to_string())),
+            }
+        }
+        bundle.pack = data[pos..].to_vec();
+        Some(bundle)
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let mut out = BUNDLE_V2.to_vec();
+        for (sha, subject) in &self.prerequisites {
+            out.extend_from_slice(format!("-{} {}\n", sha, subject).as_bytes());
+        }
+        for (name, sha) in &self.refs {
+            out.extend_from_slice(format!("{} {}\n", sha, name).as_bytes());
+        }
+        out.push(b'\n');
+        out.extend_from_slice(&self.pack);
+        out
+    }
+
+    /// Checks the pack's checksum and that `repo` has every prerequisite.
+    pub fn verify(&self, repo: &Repository) -> Result<()> {
+        let sound = match self.pack.len().checked_sub(20) {
+            Some(end) => Sha1::digest(&self.pack[..end]).as_slice() == &self.pack[end..],
+            None => false,
+        };
+        if !sound {
+            bail!("bundle pack is corrupt");
+        }
+        let missing: Vec<&(String, String)> = self
+            .prerequisites
+            .iter()
+            .filter(|(sha, _)| object_read(repo, sha).is_err())
+            .collect();
+        if !missing.is_empty() {
+            let mut message = "Repository lacks these prerequisite commits:".to_string();
+            for (sha, subject) in missing {
+                message.push_str(&format!("\n{} {}", sha, subject));
+            }
+            bail!(message);
+        }
+        Ok(())
+    }
+
+    /// Verifies the bundle and writes its objects into `repo`.
+    pub fn unpack(&self, repo: &Repository) -> Result<()> {
+        self.verify(repo)?;
+        pack_unpack(repo, &self.pack)?;
+        Ok(())
+    }
+}
+
+/// Writes a bundle to `path` with the refs `revs` name and the history
+/// behind them. `A..B` and `^A` leave out what A reaches, making A's
+/// boundary commits prerequisites; `--all` takes every ref and HEAD.
+pub fn bundle_create(path: &Path, revs: &[String]) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let mut refs: Vec<(String, String)> = Vec::new();
+    let mut exclude: Vec<String> = Vec::new();
+    let add_ref = |refs: &mut Vec<(String, String)>, name: String, sha: String| {
+        if !refs.iter().any(|(n, _)| *n == name) {
+            refs.push((name, sha));
+        }
+    };
+    for rev in revs {
+        if rev == "--all" {
+            for (name, sha) in ref_list_flat(&repo, None, Some("refs"))? {
+                add_ref(&mut refs, name, sha);
+            }
+            if let Some(sha) = ref_resolve(&repo, "HEAD")? {
+                add_ref(&mut refs, "HEAD".to_string(), sha);
+            }
+            continue;
+        }
+        let resolve = |name: &str, fmt: Option<&[u8]>| {
+            let name = if name.is_empty() { "HEAD" } else { name };
+            object_find(&repo, name, fmt, true)?.ok_or_else(|| anyhow!("bad revision '{}'", name))
+        };
+        let tip = match rev.split_once("..") {
+            Some((from, to)) => {
+                exclude.push(resolve(from, Some(b"commit"))?);
+                if to.is_empty() {
+                    "HEAD"
+                } else {
+                    to
+                }
+            }
+            None => match rev.strip_prefix('^') {
+                Some(from) => {
+                    exclude.push(resolve(from, Some(b"commit"))?);
+                    continue;
+                }
+                None => rev.as_str(),
+            },
+        };
+        match bundle_ref_name(&repo, tip)? {
+            Some(name) => {
+                let sha =
+                    ref_resolve(&repo, &name)?.ok_or_else(|| anyhow!("bad revision '{}'", tip))?;
+                add_ref(&mut refs, name, sha);
+            }
+            None => {
+                resolve(tip, None)?;
+                eprintln!("warning: ignoring '{}', which is not a ref", tip);
+            }
+        }
+    }
+
+    // The commits the bundle carries, then the ones right outside it.
+    let tips: Vec<String> = refs.iter().map(|(_, sha)| sha.clone()).collect();
+    let mut walk: Vec<String> = exclude.iter().map(|sha| format!("^{}", sha)).collect();
+    for sha in &tips {
+        walk.extend(
+            object_find(&repo, sha, Some(b"commit"), true)
+                .ok()
+                .flatten(),
+        );
+    }
+    let commits: HashSet<String> = rev_walk(&repo, &walk)?.into_iter().collect();
+    let mut ancestry = Ancestry::new(&repo)?;
+    let mut prerequisites: Vec<String> = Vec::new();
+    for sha in &commits {
+        for parent in ancestry.parents(sha)? {
+            if !commits.contains(&parent) && !prerequisites.contains(&parent) {
+                prerequisites.push(parent);
+            }
+        }
+    }
+    prerequisites.sort();
+
+    let known = reachable_objects(&repo, &exclude)?;
+    let mut objects: Vec<String> = reachable_objects(&repo, &tips)?
+        .into_iter()
+        .filter(|sha| !known.contains(sha))
+        .collect();
+    objects.sort();
+    if refs.is_empty() || objects.is_empty() {
+        bail!("Refusing to create empty bundle.");
+    }
+    let mut bundle = Bundle {
+        prerequisites: Vec::new(),
+        refs,
+        pack: pack_write(&repo, &objects)?,
+    };
+    for sha in prerequisites {
+        let subject = commit_subject(&repo, &sha)?;
+        bundle.prerequisites.push((sha, subject));
+    }
+    fs::write(path, bundle.write())?;
+    Ok(())
+}
+
+/// The full name of the ref `name` refers to, as `rev-parse
+/// --symbolic-full-name` gives it; None for anything but a ref.
+fn bundle_ref_name(repo: &Repository, name: &str) -> Result<Option<String>> {
+    if name == "HEAD" {
+        return Ok(Some(name.to_string()));
+    }
+    for candidate in [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+    ] {
+        if candidate.starts_with("refs/") && ref_resolve(repo, &candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Checks that the bundle at `path` is sound and could be unbundled here,
+/// then describes it.
+pub fn bundle_verify(path: &Path, quiet: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let bundle = Bundle::read(path)?;
+    bundle.verify(&repo)?;
+    println!("{} is okay", path.display());
+    if quiet {
+        return Ok(());
+    }
+    let count = |n: usize, what: &str| match n {
+        1 => format!("this {}", what),
+        _ => format!("these {} {}s", n, what),
+    };
+    println!("The bundle contains {}:", count(bundle.refs.len(), "ref"));
+    for (name, sha) in &bundle.refs {
+        println!("{} {}", sha, name);
+    }
+    if bundle.prerequisites.is_empty() {
+        println!("The bundle records a complete history.");
+    } else {
+        println!(
+            "The bundle requires {}:",
+            count(bundle.prerequisites.len(), "ref")
+        );
+        for (sha, _) in &bundle.prerequisites {
+            println!("{} ", sha);
+        }
+    }
+    println!("The bundle uses this hash algorithm: sha1");
+    Ok(())
+}
+
+/// Prints the refs in the bundle at `path`.
+pub fn bundle_list_heads(path: &Path) -> Result<()> {
+    for (name, sha) in Bundle::read(path)?.refs {
+        println!("{} {}", sha, name);
+    }
+    Ok(())
+}
+
+/// Writes the objects of the bundle at `path` into the repository and
+/// prints its refs, leaving it to the caller to update any.
+pub fn bundle_unbundle(path: &Path) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let bundle = Bundle::read(path)?;
+    bundle.unpack(&repo)?;
+    for (name, sha) in &bundle.refs {
+        println!("{} {}", sha, name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::Bundle;
+    use crate::{
+        object_read, pack_write, reachable_objects,
+        test_utils::{make_commit, test_repo},
+    };
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let source = test_repo("bundle-source");
+        let first = make_commit(&source, "one", &[]);
+        let second = make_commit(&source, "two", &[&first]);
+        let mut objects: Vec<String> = reachable_objects(&source, std::slice::from_ref(&second))
+            .unwrap()
+            .into_iter()
+            .collect();
+        objects.sort();
+        let bundle = Bundle {
+            prerequisites: vec![(first.clone(), "one".to_string())],
+            refs: vec![("refs/heads/master".to_string(), second.clone())],
+            pack: pack_write(&source, &objects).unwrap(),
+        };
+        let parsed = Bundle::parse(&bundle.write()).unwrap();
+        assert_eq!(parsed.prerequisites, bundle.prerequisites);
+        assert_eq!(parsed.refs, bundle.refs);
+        assert!(Bundle::parse(b"# v2 git bundle\nnot a sha\n\n").is_none());
+
+        let dest = test_repo("bundle-dest");
+        let err = parsed.unpack(&dest).unwrap_err().to_string();
+        assert!(err.contains(&first));
+        parsed.unpack(&source).unwrap();
+        assert!(object_read(&source, &second).is_ok());
+        fs::remove_dir_all(&source.worktree).unwrap();
+        fs::remove_dir_all(&dest.worktree).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{ls_tree_inner, object_find, object_info, object_read, object_read_raw, repo_find};
+
+/// What `cat_file_show` prints of an object.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CatFileShow {
+    /// Its type.
+    Type,
+    /// The size of its content.
+    Size,
+    /// Its content, trees listed as `ls-tree` lists them.
+    Pretty,
+}
+
+/// Prints `object`, which must be of type `fmt` once tags and commits are
+/// peeled to it.
+pub fn cat_file(object: &str, fmt: Option<&[u8]>) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let sha = object_find(&repo, object, fmt, true)?.unwrap();
+    let obj = object_read(&repo, &sha)?;
+    std::io::stdout().write_all(&obj.serialize())?;
+    Ok(())
+}
+
+/// Prints the type, size or content of `object`, whatever its type.
+pub fn cat_file_show(object: &str, show: CatFileShow) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let sha = object_find(&repo, object, None, false)?
+        .ok_or_else(|| anyhow!("Not a valid object name {}", object))?;
+    let info = object_info(&repo, &sha)?;
+    match show {
+        CatFileShow::Type => println!("{}", String::from_utf8_lossy(info.fmt)),
+        CatFileShow::Size => println!("{}", info.size),
+        CatFileShow::Pretty if info.fmt == b"tree" => ls_tree_inner(&repo, &sha, false, 0, "")?,
+        CatFileShow::Pretty => io::stdout().write_all(&object_read_raw(&repo, &sha)?.1)?,
+    }
+    Ok(())
+}
+
+/// Answers one object name per line of stdin with `<sha> <type> <size>`,
+/// followed with `contents` by the object's content and a newline, or with
+/// `<name> missing` for names that don't resolve. Each answer is flushed
+/// as soon as it is written, so that a caller can read it before sending
+/// the next name.
+pub fn cat_file_batch(contents: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let mut out = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        let name = line?;
+        let found = match object_find(&repo, &name, None, false) {
+            Ok(Some(sha)) => object_info(&repo, &sha).ok().map(|info| (sha, info)),
+            _ => None,
+        };
+        let Some((sha, info)) = found else {
+            writeln!(out, "{} missing", name)?;
+            out.flush()?;
+            continue;
+        };
+        writeln!(
+            out,
+            "{} {} {}",
+            sha,
+            String::from_utf8_lossy(info.fmt),
+            info.size
+        )?;
+        if contents {
+            out.write_all(&object_read_raw(&repo, &sha)?.1)?;
+            writeln!(out)?;
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use anyhow::Result;
+
+use crate::{branch_name_valid, refname_valid};
+
+/// Checks whether `name` is a valid ref name, as `refname_valid` does.
+/// With `branch` it must be a valid branch name instead, and is printed.
+/// With `normalize`, repeated slashes and a leading one are dropped first,
+/// and the result printed. Invalid names print nothing: the answer is the
+/// return value alone.
+pub fn check_ref_format(
+    name: &str,
+    branch: bool,
+    allow_onelevel: bool,
+    normalize: bool,
+) -> Result<bool> {
+    if branch {
+        if !branch_name_valid(name) {
+            return Ok(false);
+        }
+        println!("{}", name);
+        return Ok(true);
+    }
+    let mut normalized = String::new();
+    for c in name.trim_start_matches(|c| normalize && c == '/').chars() {
+        if !(normalize && c == '/' && normalized.ends_with('/')) {
+            normalized.push(c);
+        }
+    }
+    if !refname_valid(&normalized, allow_onelevel) {
+        return Ok(false);
+    }
+    if normalize {
+        println!("{}", normalized);
+    }
+    Ok(true)
+}
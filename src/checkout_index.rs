@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    checkout_file_to, index_entry_modified, index_read, index_write, repo_find, repo_relative_path,
+    Index, IndexEntry, Repository, TreeLeaf,
+};
+
+/// How `index_checkout` writes files.
+#[derive(Default)]
+pub struct CheckoutIndexOptions {
+    /// Overwrite files that already exist.
+    pub force: bool,
+    /// Write each file to this prefix followed by its path instead of into
+    /// the worktree, e.g. "export/" or ".merged-".
+    pub prefix: Option<String>,
+}
+
+/// Copies the files of `paths`, or with `all` of every entry, from the
+/// index to the worktree.
+pub fn checkout_index(paths: &[PathBuf], all: bool, opts: &CheckoutIndexOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let mut index = index_read(&repo)?;
+    let names = match all {
+        // Unmerged paths and those sparse checkout leaves out are skipped.
+        true => index
+            .entries
+            .iter()
+            .filter(|e| e.flag_stage == 0 && !e.flag_skip_worktree)
+            .map(|e| e.name.clone())
+            .collect(),
+        false => paths
+            .iter()
+            .map(|p| repo_relative_path(&repo, p))
+            .collect::<Result<Vec<_>>>()?,
+    };
+    let skipped = index_checkout(&repo, &mut index, &names, opts)?;
+    if opts.prefix.is_none() {
+        index_write(&repo, &index)?;
+    }
+    if !all && !skipped.is_empty() {
+        bail!("files in the way were not checked out; -f overwrites them");
+    }
+    Ok(())
+}
+
+/// Writes the index entries named `names` out as files, with their modes,
+/// and returns the names of those left alone because a file was in the
+/// way. Without a prefix, files that are already up to date are left as
+/// they are, and the index takes the stat data of those written.
+pub fn index_checkout(
+    repo: &Repository,
+    index: &mut Index,
+    names: &[String],
+    opts: &CheckoutIndexOptions,
+) -> Result<Vec<String>> {
+    let mut skipped = Vec::new();
+    for name in names {
+        let Some(pos) = index.entries.iter().position(|e| e.name == *name) else {
+            bail!("{} is not in the cache", name);
+        };
+        let entry = &index.entries[pos];
+        if entry.flag_stage != 0 {
+            bail!("{} is unmerged", name);
+        }
+        let dest = match &opts.prefix {
+            Some(prefix) => PathBuf::from(format!("{}{}", prefix, name)),
+            None => repo.worktree.join(name),
+        };
+        if !opts.force && dest.symlink_metadata().is_ok() {
+            if opts.prefix.is_some() || index_entry_modified(repo, entry)? {
+                eprintln!("{} already exists, no checkout", name);
+                skipped.push(name.clone());
+            }
+            continue;
+        }
+        let leaf = TreeLeaf {
+            mode: entry.tree_mode(),
+            path: name.clone(),
+            sha: entry.sha.clone(),
+        };
+        checkout_file_to(repo, &leaf, &dest)?;
+        if opts.prefix.is_none() && entry.mode_type != 0b1110 {
+            let fresh = IndexEntry::from_file(repo, name, &leaf.sha, &leaf.mode)?;
+            index.entries[pos] = IndexEntry {
+                flag_assume_valid: entry.flag_assume_valid,
+                ..fresh
+            };
+        }
+    }
+    Ok(skipped)
+}
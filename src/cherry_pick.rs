@@ -0,0 +1,235 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_get_active, commit_create_as, commit_subject, file_write_locked, gc_auto, head_update,
+    ident_default, ident_parse, kvlm_get_str, merge_checkout, merge_conflicts_print, merge_trees,
+    object_find, object_read, ref_resolve, repo_file, repo_find, sha_short, status_dirty_paths,
+    Commit, MergeConflict, Repository,
+};
+
+/// What happened when replaying a commit onto HEAD.
+pub enum PickOutcome {
+    /// The change applied cleanly and was committed as this sha.
+    Committed(String),
+    /// The change is already in HEAD, so nothing was committed.
+    Empty,
+    /// The change left these paths in conflict in the index and worktree.
+    Conflicts(Vec<MergeConflict>),
+}
+
+/// How the dates of a replayed commit are set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PickDates {
+    /// The author date is kept; the committer date is now.
+    #[default]
+    Keep,
+    /// The committer date is the author date too, so that replaying the
+    /// same commits gives the same shas.
+    CommitterIsAuthor,
+    /// The author date is reset to now, like the committer date.
+    Ignore,
+}
+
+/// The author and committer lines for replaying a commit by `author`.
+pub fn pick_idents(repo: &Repository, author: &str, dates: PickDates) -> Result<(String, String)> {
+    let committer = ident_default(repo, "COMMITTER")?;
+    let (Some((who, time, tz)), Some((me, now, now_tz))) =
+        (ident_parse(author), ident_parse(&committer))
+    else {
+        return Ok((author.to_string(), committer));
+    };
+    Ok(match dates {
+        PickDates::Keep => (author.to_string(), committer),
+        PickDates::CommitterIsAuthor => (author.to_string(), format!("{} {} {}", me, time, tz)),
+        PickDates::Ignore => (format!("{} {} {}", who, now, now_tz), committer),
+    })
+}
+
+/// Applies the change `sha` made relative to its first parent onto HEAD's
+/// tree, index and worktree with a three-way merge. On success a commit
+/// with the original author and message is created on HEAD, dated as
+/// `dates` says.
+pub fn pick_commit(repo: &Repository, sha: &str, dates: PickDates) -> Result<PickOutcome> {
+    let obj = object_read(repo, sha)?;
+    let commit = obj
+        .as_any()
+        .downcast_ref::<Commit>()
+        .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+    let head = ref_resolve(repo, "HEAD")?.ok_or_else(|| anyhow!("No commits yet on HEAD"))?;
+
+    let tree_of = |sha: &str| -> Result<String> {
+        object_find(repo, sha, Some(b"tree"), true)?.ok_or_else(|| anyhow!("{} has no tree", sha))
+    };
+    let base = match commit.parents().first() {
+        Some(parent) => Some(tree_of(parent)?),
+        None => None,
+    };
+    let head_tree = tree_of(&head)?;
+    let message = commit.message();
+    let label = format!(
+        "{} ({})",
+        sha_short(repo, sha),
+        message.lines().next().unwrap_or_default()
+    );
+
+    let merged = merge_trees(
+        repo,
+        base.as_deref(),
+        &head_tree,
+        &tree_of(sha)?,
+        "HEAD",
+        &label,
+    )?;
+    let tree = merge_checkout(repo, &merged)?;
+    if !merged.conflicts.is_empty() {
+        return Ok(PickOutcome::Conflicts(merged.conflicts));
+    }
+    if tree == head_tree {
+        return Ok(PickOutcome::Empty);
+    }
+
+    let author = kvlm_get_str(&commit.kvlm, b"author").unwrap_or_default();
+    let (author, committer) = pick_idents(repo, &author, dates)?;
+    let new = commit_create_as(
+        repo,
+        &tree,
+        &[head],
+        Some(&author),
+        Some(&committer),
+        &message,
+    )?;
+    head_update(
+        repo,
+        &new,
+        &format!("cherry-pick: {}", message.lines().next().unwrap_or("")),
+    )?;
+    Ok(PickOutcome::Committed(new))
+}
+
+pub fn cherry_pick(name: &str, dates: PickDates) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    cherry_pick_rev(&repo, name, dates)
+}
+
+/// Cherry-picks the commit `name` onto HEAD. On conflict, CHERRY_PICK_HEAD
+/// and MERGE_MSG are left behind for `rit commit` to conclude it.
+pub fn cherry_pick_rev(repo: &Repository, name: &str, dates: PickDates) -> Result<()> {
+    for (state, what) in [("CHERRY_PICK_HEAD", "cherry-pick"), ("MERGE_HEAD", "merge")] {
+        if repo_file(repo, PathBuf::from(state), false)?.exists() {
+            bail!("You have not concluded your {} ({} exists).", what, state);
+        }
+    }
+    let dirty = status_dirty_paths(repo)?;
+    if !dirty.is_empty() {
+        bail!(
+            "Your local changes would be overwritten by cherry-pick:\n\t{}",
+            dirty.join("\n\t")
+        );
+    }
+
+    let sha = object_find(repo, name, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("bad revision '{}'", name))?;
+    let obj = object_read(repo, &sha)?;
+    let commit = obj
+        .as_any()
+        .downcast_ref::<Commit>()
+        .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+    if commit.parents().len() > 1 {
+        bail!("commit {} is a merge, which cannot be cherry-picked", sha);
+    }
+    let subject = commit_subject(repo, &sha)?;
+
+    match pick_commit(repo, &sha, dates)? {
+        PickOutcome::Committed(new) => {
+            let branch = branch_get_active(repo)?.unwrap_or_else(|| "detached HEAD".to_string());
+            println!("[{} {}] {}", branch, sha_short(repo, &new), subject);
+            gc_auto(repo);
+            Ok(())
+        }
+        PickOutcome::Empty => bail!(
+            "The changes of {} are already in HEAD; nothing to commit.",
+            sha_short(repo, &sha)
+        ),
+        PickOutcome::Conflicts(conflicts) => {
+            merge_conflicts_print(&conflicts);
+            file_write_locked(
+                &repo_file(repo, PathBuf::from("CHERRY_PICK_HEAD"), false)?,
+                format!("{}\n", sha).as_bytes(),
+                repo.fsync.refs,
+            )?;
+            file_write_locked(
+                &repo_file(repo, PathBuf::from("MERGE_MSG"), false)?,
+                commit.message().as_bytes(),
+                false,
+            )?;
+            bail!(
+                "could not apply {}... {}\n\
+                 After resolving the conflicts, mark them with \"rit add <paths>\"\n\
+                 and run \"rit commit\".",
+                sha_short(repo, &sha),
+                subject
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{cherry_pick_rev, PickDates};
+    use crate::{
+        checkout_tree, head_update, kvlm_get_str, object_read, ref_create, ref_resolve,
+        test_utils::{make_commit, make_commit_at, test_repo},
+        Commit,
+    };
+
+    #[test]
+    fn test_cherry_pick() {
+        let repo = test_repo("cherry-pick");
+        let base = make_commit(&repo, "one", &[]);
+        let side = make_commit_at(&repo, "two", &[&base], 1000000000);
+        checkout_tree(&repo, &base, true).unwrap();
+        ref_create(&repo, "heads/master", &base).unwrap();
+        let author = |sha: &str| {
+            let obj = object_read(&repo, sha).unwrap();
+            let commit = obj.as_any().downcast_ref::<Commit>().unwrap();
+            (kvlm_get_str(&commit.kvlm, b"author"), commit.parents())
+        };
+
+        // A clean pick commits the change with its author on HEAD.
+        cherry_pick_rev(&repo, &side, PickDates::Keep).unwrap();
+        let picked = ref_resolve(&repo, "HEAD").unwrap().unwrap();
+        assert_ne!(picked, side);
+        assert_eq!(author(&picked), (author(&side).0, vec![base.clone()]));
+        assert_eq!(
+            fs::read_to_string(repo.worktree.join("file")).unwrap(),
+            "two"
+        );
+        assert!(cherry_pick_rev(&repo, &side, PickDates::Keep).is_err());
+
+        // A conflicting one stops with the state for `rit commit`.
+        let other = make_commit(&repo, "three", &[&base]);
+        checkout_tree(&repo, &other, true).unwrap();
+        head_update(&repo, &other, "reset").unwrap();
+        let err = cherry_pick_rev(&repo, &side, PickDates::Keep).unwrap_err();
+        assert!(err.to_string().starts_with("could not apply"));
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(other));
+        assert_eq!(
+            fs::read_to_string(repo.gitdir.join("CHERRY_PICK_HEAD")).unwrap(),
+            format!("{}\n", side)
+        );
+        assert_eq!(
+            fs::read_to_string(repo.gitdir.join("MERGE_MSG")).unwrap(),
+            "two\n"
+        );
+        let file = fs::read_to_string(repo.worktree.join("file")).unwrap();
+        assert!(file.starts_with("<<<<<<< HEAD\nthree"));
+        assert!(file.contains("two"));
+        let err = cherry_pick_rev(&repo, &side, PickDates::Keep).unwrap_err();
+        assert!(err.to_string().contains("CHERRY_PICK_HEAD exists"));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use glob::Pattern;
+
+use crate::{
+    check_ignore_path, gitignore_read, index_read, menu_choose, menu_command, path_matches,
+    prompt_line, prompt_yes_no, repo_find, repo_relative_path, Ignore, Repository,
+};
+
+/// How `clean` picks and removes untracked files.
+#[derive(Default)]
+pub struct CleanOptions {
+    pub force: bool,
+    pub dry_run: bool,
+    /// Also remove untracked directories.
+    pub dirs: bool,
+    /// Also remove ignored files.
+    pub ignored: bool,
+    /// Only remove ignored files.
+    pub only_ignored: bool,
+    /// Let the user review what goes first.
+    pub interactive: bool,
+    pub quiet: bool,
+}
+
+pub fn clean(paths: &[PathBuf], opts: &CleanOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let require_force = repo
+        .config_get_bool("clean", "requireForce")?
+        .unwrap_or(true);
+    if require_force && !opts.force && !opts.dry_run && !opts.interactive {
+        bail!(
+            "clean.requireForce defaults to true and neither -i, -n, nor -f given; \
+             refusing to clean"
+        );
+    }
+    let specs = paths
+        .iter()
+        .map(|p| repo_relative_path(&repo, p))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut items = clean_candidates(&repo, opts)?;
+    if !specs.is_empty() {
+        items.retain(|item| {
+            let path = item.trim_end_matches('/');
+            specs.iter().any(|s| path_matches(s, path))
+        });
+    }
+    if opts.interactive && !opts.dry_run {
+        match clean_interactive(items)? {
+            Some(chosen) => items = chosen,
+            None => return Ok(()),
+        }
+    }
+
+    for item in items {
+        if opts.dry_run {
+            println!("Would remove {}", item);
+            continue;
+        }
+        if !opts.quiet {
+            println!("Removing {}", item);
+        }
+        let path = repo.worktree.join(&item);
+        match item.ends_with('/') {
+            true => fs::remove_dir_all(path)?,
+            false => fs::remove_file(path)?,
+        }
+    }
+    Ok(())
+}
+
+/// The untracked paths `opts` selects, directories with a trailing slash.
+fn clean_candidates(repo: &Repository, opts: &CleanOptions) -> Result<Vec<String>> {
+    let index = index_read(repo)?;
+    let tracked: HashSet<&str> = index.entries.iter().map(|e| e.name.as_str()).collect();
+    let mut tracked_dirs = HashSet::new();
+    for name in &tracked {
+        let mut dir = Path::new(name).parent();
+        while let Some(d) = dir.filter(|d| !d.as_os_str().is_empty()) {
+            tracked_dirs.insert(d.to_string_lossy().to_string());
+            dir = d.parent();
+        }
+    }
+    let walk = CleanWalk {
+        repo,
+        ignore: gitignore_read(repo)?,
+        tracked,
+        tracked_dirs,
+        opts,
+    };
+    let mut items = Vec::new();
+    walk.dir("", false, &mut items)?;
+    Ok(items)
+}
+
+struct CleanWalk<'a> {
+    repo: &'a Repository,
+    ignore: Ignore,
+    tracked: HashSet<&'a str>,
+    tracked_dirs: HashSet<String>,
+    opts: &'a CleanOptions,
+}
+
+impl CleanWalk<'_> {
+    /// Collects what goes in the directory `rel` into `items`. Returns
+    /// whether everything in it goes, so that an untracked directory can be
+    /// removed as a whole.
+    fn dir(&self, rel: &str, ignored: bool, items: &mut Vec<String>) -> Result<bool> {
+        let mut entries: Vec<_> = fs::read_dir(self.repo.worktree.join(rel))?
+            .filter_map(|e| e.ok())
+            .collect();
+        entries.sort_by_key(|e| e.file_name());
+        let mut all = true;
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == ".git" {
+                all = false;
+                continue;
+            }
+            let path = match rel {
+                "" => name,
+                _ => format!("{}/{}", rel, name),
+            };
+            let is_ignored = ignored || check_ignore_path(&self.ignore, Path::new(&path));
+            if entry.file_type()?.is_dir() && !self.tracked.contains(path.as_str()) {
+                if self.tracked_dirs.contains(&path) {
+                    self.dir(&path, is_ignored, items)?;
+                    all = false;
+                    continue;
+                }
+                // Nested repositories are never cleaned.
+                if !self.opts.dirs || entry.path().join(".git").exists() {
+                    all = false;
+                    continue;
+                }
+                let mut inner = Vec::new();
+                if self.dir(&path, is_ignored, &mut inner)? {
+                    items.push(format!("{}/", path));
+                } else {
+                    items.extend(inner);
+                    all = false;
+                }
+                continue;
+            }
+            let wanted = if self.tracked.contains(path.as_str()) {
+                false
+            } else if self.opts.only_ignored {
+                is_ignored
+            } else {
+                self.opts.ignored || !is_ignored
+            };
+            if wanted {
+                items.push(path);
+            } else {
+                all = false;
+            }
+        }
+        Ok(all)
+    }
+}
+
+/// The menu of `clean -i`. Returns the items to remove, or None to quit.
+fn clean_interactive(mut items: Vec<String>) -> Result<Option<Vec<String>>> {
+    const COMMANDS: [&str; 6] = [
+        "clean",
+        "filter by pattern",
+        "select by numbers",
+        "ask each",
+        "quit",
+        "help",
+    ];
+    loop {
+        if items.is_empty() {
+            println!("No more files to clean, exiting.");
+            return Ok(None);
+        }
+        println!(
+            "Would remove the following item{}:",
+            if items.len() == 1 { "" } else { "s" }
+        );
+        for item in &items {
+            println!("  {}", item);
+        }
+        let Some(command) = menu_command(&COMMANDS, 3, "What now")? else {
+            return Ok(None);
+        };
+        match COMMANDS[command] {
+            "clean" => return Ok(Some(items)),
+            "filter by pattern" => clean_filter(&mut items)?,
+            "select by numbers" => {
+                let chosen = menu_choose("", &items, "Select items to delete")?;
+                items = chosen.into_iter().map(|i| items[i].clone()).collect();
+            }
+            "ask each" => {
+                let mut kept = Vec::new();
+                for item in items {
+                    if prompt_yes_no(&format!("Remove {} [y/N]? ", item), false)? {
+                        kept.push(item);
+                    }
+                }
+                return Ok(Some(kept));
+            }
+            "quit" => {
+                println!("Bye.");
+                return Ok(None);
+            }
+            _ => println!(
+                "clean               - start cleaning\n\
+                 filter by pattern   - exclude items from deletion\n\
+                 select by numbers   - select items to be deleted by numbers\n\
+                 ask each            - confirm each deletion (like \"rm -i\")\n\
+                 quit                - stop cleaning\n\
+                 help                - this screen\n\
+                 ?                   - help for prompt selection"
+            ),
+        }
+    }
+}
+
+/// Drops the items matching the glob patterns the user enters, until an
+/// empty line.
+fn clean_filter(items: &mut Vec<String>) -> Result<()> {
+    while !items.is_empty() {
+        for item in items.iter() {
+            println!("  {}", item);
+        }
+        let Some(input) = prompt_line("Input ignore patterns>> ")? else {
+            return Ok(());
+        };
+        if input.is_empty() {
+            return Ok(());
+        }
+        for word in input.split_whitespace() {
+            let pattern = Pattern::new(word)?;
+            let before = items.len();
+            items.retain(|item| {
+                let path = item.trim_end_matches('/');
+                let base = path.rsplit('/').next().unwrap_or(path);
+                !pattern.matches(path) && !pattern.matches(base)
+            });
+            if items.len() == before {
+                println!("WARNING: Cannot find items matched by: {}", word);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{clean_candidates, CleanOptions};
+    use crate::{
+        checkout_tree, ref_create,
+        test_utils::{make_commit, test_repo},
+    };
+
+    #[test]
+    fn test_clean_excludes() {
+        let mut repo = test_repo("clean-excludes");
+        let sha = make_commit(&repo, "one", &[]);
+        checkout_tree(&repo, &sha, true).unwrap();
+        ref_create(&repo, "heads/master", &sha).unwrap();
+
+        // None of these rules are in the index.
+        let excludes = repo.gitdir.join("my-excludes");
+        fs::write(&excludes, "*.bak\n").unwrap();
+        repo.config_set("core", "excludesFile", &excludes.display().to_string())
+            .unwrap();
+        fs::create_dir_all(repo.gitdir.join("info")).unwrap();
+        fs::write(repo.gitdir.join("info/exclude"), "*.tmp\n").unwrap();
+        fs::write(repo.worktree.join(".gitignore"), "*.log\n").unwrap();
+        fs::create_dir(repo.worktree.join("sub")).unwrap();
+        fs::write(repo.worktree.join("sub/.gitignore"), "*.out\n").unwrap();
+        for name in ["a.log", "b.tmp", "c.bak", "d.txt", "sub/e.out", "sub/f.txt"] {
+            fs::write(repo.worktree.join(name), "").unwrap();
+        }
+
+        let opts = CleanOptions {
+            dirs: true,
+            ..Default::default()
+        };
+        let untracked = clean_candidates(&repo, &opts).unwrap();
+        assert_eq!(
+            untracked,
+            [".gitignore", "d.txt", "sub/.gitignore", "sub/f.txt"]
+        );
+        let opts = CleanOptions {
+            dirs: true,
+            only_ignored: true,
+            ..Default::default()
+        };
+        let ignored = clean_candidates(&repo, &opts).unwrap();
+        assert_eq!(ignored, ["a.log", "b.tmp", "c.bak", "sub/e.out"]);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -1,19 +1,69 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand, ValueEnum};
 
 use crate::{
-    cat_file, check_ignore, checkout, hash_object, log, ls_files, ls_tree, repo_create, rev_parse,
-    rm, show_ref, status, tag,
+    add, add_interactive, add_patch_all, am, apply, archive, bisect_mark, bisect_reset,
+    bisect_start, blame, branch, bundle_create, bundle_list_heads, bundle_unbundle, bundle_verify,
+    cat_file, cat_file_batch, cat_file_show, check_attr, check_ignore, check_ref_format, checkout,
+    checkout_index, cherry_pick, clean, clone, clone_resume, commit, commit_graph, commit_tree,
+    config, count_objects, describe, diff, difftool, fetch, format_patch, fsck, gc, grep,
+    hash_object, index, interpret_trailers, log, ls_files, ls_remote, ls_tree,
+    maintenance_register, maintenance_run, maintenance_start, maintenance_stop,
+    maintenance_unregister, merge, merge_base, merge_base_is_ancestor, name_rev, notes_add,
+    notes_list, notes_remove, notes_show, profile_enable, profile_report, prune, pull, push,
+    range_diff, read_tree, rebase, reflog, reflog_expire, remote_add, remote_list, remote_remove,
+    remote_rename, remote_set_url, remote_show, repo_create, rerere, reset, restore, rev_list,
+    rev_parse, revert, rm, serve, shortlog, show, show_ref, sparse_checkout, stash_apply,
+    stash_drop, stash_list, stash_push, stats, status, stripspace, submodule_init,
+    submodule_status_print, submodule_update, switch, symbolic_ref, tag, tag_delete, tag_show_list,
+    tag_verify, undo, update_index, update_ref, verify_pack, worktree_add, worktree_list,
+    worktree_lock, worktree_prune, worktree_remove, worktree_repair, worktree_unlock, write_tree,
+    ApplyOptions, ArchiveFormat, BranchFilter, CatFileShow, CheckAttrOptions, CheckoutIndexOptions,
+    CleanOptions, ConfigOptions, DifftoolOptions, FormatPatchOptions, IgnoreSubmodules, LogFormat,
+    LogOptions, LsRemoteOptions, PickDates, Pickaxe, RerereAction, ResetMode, RevListOptions,
+    RmOptions, ShowRefOptions, SparseCheckoutAction, TagFilter, TagOptions, UpdateIndexOptions,
 };
 
 #[derive(Parser)]
 #[command(name="rit", version, about, long_about = None)]
 pub struct Cli {
+    /// Run as if started in <path>
+    #[arg(short = 'C', global = true, value_name = "path")]
+    directory: Option<PathBuf>,
+    /// Use <path> as the repository's git directory
+    #[arg(long, global = true, value_name = "path")]
+    git_dir: Option<PathBuf>,
+    /// Use <path> as the root of the working tree
+    #[arg(long, global = true, value_name = "path")]
+    work_tree: Option<PathBuf>,
+    /// Re-hash every object read and fail on content that doesn't match its id
+    #[arg(long, global = true)]
+    strict_hash_verification: bool,
+    /// Print how long each phase of the command took to stderr
+    #[arg(long, global = true)]
+    profile: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
+/// The `--abbrev[=<n>]` option of the commands that print object names.
+#[derive(Args)]
+struct AbbrevArg {
+    /// Shorten object names to <n> hex digits, or as many as core.abbrev
+    /// says if <n> is omitted (0 for full names, or describe's tag alone)
+    #[arg(long, value_name = "n", num_args = 0..=1, require_equals = true)]
+    abbrev: Option<Option<usize>>,
+}
+
+impl AbbrevArg {
+    /// The length asked for: `absent` without the option, None (as
+    /// core.abbrev says) for a bare `--abbrev`.
+    fn len(&self, absent: Option<usize>) -> Option<usize> {
+        self.abbrev.unwrap_or(absent)
+    }
+}
+
 #[derive(Debug, Clone, ValueEnum)]
 enum ObjectType {
     Blob,
@@ -33,16 +83,418 @@ impl ObjectType {
     }
 }
 
+#[derive(Debug, Clone, ValueEnum)]
+enum ObjectFormat {
+    Sha1,
+    Sha256,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum ArchiveType {
+    Tar,
+    Zip,
+}
+
+impl ArchiveType {
+    fn format(&self) -> ArchiveFormat {
+        match self {
+            ArchiveType::Tar => ArchiveFormat::Tar,
+            ArchiveType::Zip => ArchiveFormat::Zip,
+        }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum BisectAction {
+    /// Start bisecting, optionally with the bad and good commits
+    Start,
+    /// Mark commits (HEAD by default) as containing the change
+    Bad,
+    /// Mark commits (HEAD by default) as predating the change
+    Good,
+    /// Stop bisecting and go back to the original HEAD
+    Reset,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum CommitGraphAction {
+    /// Write a commit-graph for all commits reachable from refs
+    Write,
+    /// Check the commit-graph against the object database
+    Verify,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum IndexAction {
+    /// Check that the index parses and points at existing objects
+    Verify,
+    /// Replace the index with HEAD's tree, dropping staged changes
+    Rebuild,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum BundleAction {
+    /// Write a bundle with the given refs and the history behind them
+    Create,
+    /// Check that a bundle is sound and applies to this repository
+    Verify,
+    /// List the refs in a bundle
+    ListHeads,
+    /// Store the objects in a bundle and print its refs
+    Unbundle,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum MaintenanceAction {
+    /// Register the repository and schedule hourly maintenance
+    Start,
+    /// Remove the maintenance schedule
+    Stop,
+    /// Add the repository to the global maintenance.repo list
+    Register,
+    /// Remove the repository from the global maintenance.repo list
+    Unregister,
+    /// Run the maintenance tasks now
+    Run,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum NotesAction {
+    /// Attach a note to an object
+    Add,
+    /// Show the note of an object
+    Show,
+    /// List notes, or the note blob of one object
+    List,
+    /// Remove the note of an object
+    Remove,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum WorktreeAction {
+    /// Create a worktree at <path> and check out <commit-ish> in it
+    Add,
+    /// List the main worktree and the linked ones
+    List,
+    /// Delete a linked worktree
+    Remove,
+    /// Forget linked worktrees whose directory is gone
+    Prune,
+    /// Keep a linked worktree from being pruned, moved or removed
+    Lock,
+    /// Allow a locked worktree to be pruned, moved or removed again
+    Unlock,
+    /// Fix the links to worktrees after moving them or the repository
+    Repair,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum RemoteAction {
+    /// Add a remote <name> for the repository at <url>
+    Add,
+    /// Remove a remote and its remote-tracking branches
+    #[value(alias = "rm")]
+    Remove,
+    /// Rename a remote, its remote-tracking branches and its settings
+    Rename,
+    /// Change the URL of a remote
+    SetUrl,
+    /// Show what a remote has and how local branches track it
+    Show,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum StashAction {
+    /// Save local changes and reset to HEAD
+    Push,
+    /// List the stash entries
+    List,
+    /// Apply an entry and keep it
+    Apply,
+    /// Apply an entry and drop it
+    Pop,
+    /// Remove an entry
+    Drop,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum RerereCommand {
+    /// List the conflicted paths whose resolution will be recorded
+    Status,
+    /// Forget the conflicts of the merge in progress
+    Clear,
+    /// Prune old entries of rr-cache
+    Gc,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum SparseCheckoutCommand {
+    /// Turn sparse checkout on, with only the top directory's files
+    Init,
+    /// Check out only these directories (or patterns, with --no-cone)
+    Set,
+    /// List the directories (or patterns) being checked out
+    List,
+    /// Turn sparse checkout off and check every file out again
+    Disable,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum SubmoduleAction {
+    /// Show the commit checked out in each submodule
+    Status,
+    /// Register submodules from .gitmodules in the repository's config
+    Init,
+    /// Clone missing submodules and check out the recorded commits
+    Update,
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+enum SubmoduleIgnore {
+    /// Report new commits, modified and untracked content
+    None,
+    /// Don't count untracked files in submodules as changes
+    Untracked,
+    /// Only report submodules with another commit checked out
+    Dirty,
+    /// Ignore submodules altogether
+    All,
+}
+
+impl SubmoduleIgnore {
+    fn mode(&self) -> IgnoreSubmodules {
+        match self {
+            SubmoduleIgnore::None => IgnoreSubmodules::None,
+            SubmoduleIgnore::Untracked => IgnoreSubmodules::Untracked,
+            SubmoduleIgnore::Dirty => IgnoreSubmodules::Dirty,
+            SubmoduleIgnore::All => IgnoreSubmodules::All,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ReflogAction {
+    /// Show the log of a ref (the default)
+    Show {
+        /// The ref whose log to show (HEAD if omitted)
+        refname: Option<String>,
+    },
+    /// Drop old entries, as gc.reflogExpire and gc.reflogExpireUnreachable say
+    Expire {
+        /// Drop entries older than <time>, e.g. "2.weeks.ago", "now" or "never"
+        #[arg(long, value_name = "time")]
+        expire: Option<String>,
+        /// Drop entries the ref no longer reaches that are older than <time>
+        #[arg(long, value_name = "time")]
+        expire_unreachable: Option<String>,
+        /// Expire the reflogs of every ref
+        #[arg(long)]
+        all: bool,
+        /// Only list the entries that would be dropped
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// The refs whose reflogs to expire
+        #[arg(required_unless_present = "all")]
+        refs: Vec<String>,
+    },
+}
+
 #[derive(Subcommand)]
 enum Commands {
-    Add,
+    /// Add file contents to the index.
+    Add {
+        /// Pick what to stage from a menu
+        #[arg(short, long, conflicts_with = "patch")]
+        interactive: bool,
+        /// Pick the hunks to stage one by one
+        #[arg(short, long)]
+        patch: bool,
+        /// Files or directories to stage
+        #[arg(required_unless_present_any = ["interactive", "patch"], value_parser = clap::value_parser!(PathBuf))]
+        paths: Vec<PathBuf>,
+    },
+    /// Apply a series of patches from a mailbox.
+    Am {
+        /// Fall back to a three-way merge when a patch does not apply
+        #[arg(short = '3', long = "3way")]
+        three_way: bool,
+        /// Restore the original branch and stop patching
+        #[arg(long, conflicts_with_all = ["cont", "skip"])]
+        abort: bool,
+        /// Commit the resolved patch and go on with the rest
+        #[arg(long = "continue", conflicts_with = "skip")]
+        cont: bool,
+        /// Skip the patch that failed to apply
+        #[arg(long)]
+        skip: bool,
+        /// The mailboxes or patch files
+        mbox: Vec<PathBuf>,
+    },
+    /// Apply a patch to files.
+    Apply {
+        /// Apply to the index as well as the worktree
+        #[arg(long)]
+        index: bool,
+        /// Apply to the index only, leaving the worktree alone
+        #[arg(long)]
+        cached: bool,
+        /// Only check that the patch applies, changing nothing
+        #[arg(long)]
+        check: bool,
+        /// Apply the patch in reverse, undoing it
+        #[arg(short = 'R', long)]
+        reverse: bool,
+        /// Let hunks apply with only <n> lines of context matching (git's
+        /// -C, which here is taken by the global option)
+        #[arg(long = "context", value_name = "n")]
+        context: Option<usize>,
+        /// Apply to the index too, and fall back to a three-way merge with
+        /// the blobs the patch names when it does not apply
+        #[arg(short = '3', long = "3way")]
+        three_way: bool,
+        /// The patch file, or - for stdin
+        #[arg(default_value = "-")]
+        patch: PathBuf,
+    },
+    /// Create a tar or zip archive of a tree.
+    Archive {
+        /// The archive format (from the -o extension if omitted, else tar)
+        #[arg(long, value_enum)]
+        format: Option<ArchiveType>,
+        /// Write the archive to <file> instead of stdout
+        #[arg(short = 'o', long, value_name = "file")]
+        output: Option<PathBuf>,
+        /// The commit or tree to archive
+        tree_ish: String,
+    },
+    /// Use binary search to find the commit that introduced a change.
+    Bisect {
+        action: BisectAction,
+        /// For start: the bad commit then good ones; for good/bad: the
+        /// commits to mark
+        revs: Vec<String>,
+    },
+    /// Show what revision and author last modified each line of a file.
+    Blame {
+        /// Only annotate lines <start>,<end> (or <start>,+<count>)
+        #[arg(short = 'L', value_name = "range")]
+        range: Option<String>,
+        /// Ignore whitespace when matching lines to their origin
+        #[arg(short = 'w')]
+        ignore_whitespace: bool,
+        /// Show results in a machine-readable format as they are found
+        #[arg(long)]
+        incremental: bool,
+        /// The file to annotate
+        path: PathBuf,
+        /// The revision to annotate the file at
+        #[arg(default_value = "HEAD")]
+        rev: String,
+    },
+    /// Move objects and refs by archive.
+    Bundle {
+        action: BundleAction,
+        /// With verify, only print whether the bundle is okay
+        #[arg(short, long)]
+        quiet: bool,
+        /// The bundle file
+        file: PathBuf,
+        /// With create, the refs to bundle, `--all`, and `^rev` or
+        /// `rev..ref` to leave out what the receiver has
+        #[arg(allow_hyphen_values = true)]
+        revs: Vec<String>,
+    },
+    /// List, create, or delete branches.
+    Branch {
+        /// Delete the branch (it must be merged into HEAD)
+        #[arg(short = 'd', long, default_value_t = false)]
+        delete: bool,
+        /// Delete the branch even if it is not merged
+        #[arg(short = 'D', default_value_t = false)]
+        force_delete: bool,
+        /// Only list branches that contain the commit (HEAD if omitted)
+        #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+        contains: Option<String>,
+        /// Only list branches whose tips are reachable from the commit (HEAD if omitted)
+        #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+        merged: Option<String>,
+        /// Only list branches whose tips are not reachable from the commit (HEAD if omitted)
+        #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+        no_merged: Option<String>,
+        /// Show the tip commit of each branch
+        #[arg(short = 'v', long)]
+        verbose: bool,
+        #[command(flatten)]
+        abbrev: AbbrevArg,
+        /// The branch to create or delete
+        name: Option<String>,
+        /// The commit the new branch will point to
+        #[arg(default_value = "HEAD")]
+        start_point: String,
+    },
     /// Provide content of repository objects
     CatFile {
-        #[arg(value_name = "type", help = "Specify the type", value_enum)]
-        object_type: ObjectType,
-        /// The object to display
-        #[arg(value_name = "object")]
-        object: String,
+        /// Print the object's type
+        #[arg(short = 't', group = "show")]
+        show_type: bool,
+        /// Print the size of the object's content
+        #[arg(short = 's', group = "show")]
+        show_size: bool,
+        /// Print the object's content, whatever its type
+        #[arg(short = 'p', group = "show")]
+        pretty: bool,
+        /// Print the id, type, size and content of each object named on stdin
+        #[arg(long, group = "show")]
+        batch: bool,
+        /// Print the id, type and size of each object named on stdin
+        #[arg(long, group = "show")]
+        batch_check: bool,
+        /// <type> <object>, or only <object> with -t, -s or -p
+        #[arg(value_name = "args", num_args = 0..=2)]
+        args: Vec<String>,
+    },
+    /// Apply the change introduced by an existing commit.
+    CherryPick {
+        /// Date the new commit with the author date of the picked one
+        #[arg(long, conflicts_with = "ignore_date")]
+        committer_date_is_author_date: bool,
+        /// Give the new commit the current time as its author date
+        #[arg(long)]
+        ignore_date: bool,
+        /// The commit to pick
+        commit: String,
+    },
+    /// Display gitattributes information.
+    CheckAttr {
+        /// List all attributes that are set on the paths
+        #[arg(short, long)]
+        all: bool,
+        /// Only consider .gitattributes in the index
+        #[arg(long)]
+        cached: bool,
+        /// Read the paths from stdin, one per line
+        #[arg(long)]
+        stdin: bool,
+        /// Attributes to look up; without --, the first is the attribute
+        /// and the rest are paths (all paths, with --all)
+        attrs: Vec<String>,
+        /// Paths to look the attributes up for
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// Check that a name is a valid ref name.
+    CheckRefFormat {
+        /// Check a branch name instead, and print it
+        #[arg(long)]
+        branch: bool,
+        /// Accept names with a single component, like "HEAD"
+        #[arg(long)]
+        allow_onelevel: bool,
+        /// Drop repeated and leading slashes first, and print the result
+        #[arg(long)]
+        normalize: bool,
+        #[arg(allow_hyphen_values = true)]
+        name: String,
     },
     /// Check path(s) against ignore rules.
     CheckIgnore {
@@ -50,13 +502,192 @@ enum Commands {
         #[arg(required = true, num_args = 1.., value_parser = clap::value_parser!(PathBuf))]
         path: Vec<PathBuf>,
     },
-    Commit,
-    /// Checkout a commit inside of a directory.
+    /// Remove untracked files from the working tree.
+    Clean {
+        /// Actually remove the files
+        #[arg(short, long)]
+        force: bool,
+        /// Only show what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Also remove untracked directories
+        #[arg(short = 'd')]
+        dirs: bool,
+        /// Also remove ignored files
+        #[arg(short = 'x', conflicts_with = "only_ignored")]
+        ignored: bool,
+        /// Only remove ignored files
+        #[arg(short = 'X')]
+        only_ignored: bool,
+        /// Pick what to remove from a menu
+        #[arg(short, long)]
+        interactive: bool,
+        /// Don't report the files removed
+        #[arg(short, long)]
+        quiet: bool,
+        /// Only clean these paths
+        paths: Vec<PathBuf>,
+    },
+    /// Clone a repository into a new directory.
+    Clone {
+        /// Finish the interrupted clone in the directory given instead of a URL
+        #[arg(long, conflicts_with = "dir")]
+        resume: bool,
+        /// The repository to clone: a path or an http:// URL
+        url: String,
+        /// The directory to clone into (defaults to the repository's name)
+        dir: Option<PathBuf>,
+    },
+    /// Record the staged changes as a new commit.
+    Commit {
+        /// The commit message (defaults to MERGE_MSG while merging)
+        #[arg(short = 'm', long)]
+        message: Option<String>,
+        /// Make a "fixup!" commit for `rebase --autosquash` to fold into <commit>
+        #[arg(long, value_name = "commit", conflicts_with = "squash")]
+        fixup: Option<String>,
+        /// Make a "squash!" commit, whose message is kept when folded in
+        #[arg(long, value_name = "commit")]
+        squash: Option<String>,
+    },
+    /// Write and verify the commit-graph file.
+    CommitGraph {
+        #[arg(value_enum)]
+        action: CommitGraphAction,
+    },
+    /// Write a commit of a tree and print its id, without moving any ref.
+    CommitTree {
+        tree: String,
+        /// A parent of the commit; repeat for a merge
+        #[arg(short = 'p', value_name = "parent")]
+        parents: Vec<String>,
+        /// A paragraph of the message; without -m or -F it is read from stdin
+        #[arg(short = 'm', value_name = "message")]
+        messages: Vec<String>,
+        /// Read the message from <file>, or stdin for "-"
+        #[arg(short = 'F', value_name = "file", conflicts_with = "messages")]
+        file: Option<PathBuf>,
+    },
+    /// Checkout a commit inside of a directory, or switch to it in place.
     Checkout {
         /// The commit or tree to checkout.
         commit: String,
-        /// The EMPTY directory to checkout on.
-        path: PathBuf,
+        /// The EMPTY directory to checkout on. Defaults to the worktree.
+        path: Option<PathBuf>,
+    },
+    /// Copy files from the index to the worktree.
+    CheckoutIndex {
+        /// Check out every file in the index
+        #[arg(short, long, conflicts_with = "paths")]
+        all: bool,
+        /// Overwrite files that already exist
+        #[arg(short, long)]
+        force: bool,
+        /// Write each file to <prefix><path> instead
+        #[arg(long, value_name = "prefix")]
+        prefix: Option<String>,
+        paths: Vec<PathBuf>,
+    },
+    /// Name a commit after the nearest tag it contains.
+    Describe {
+        /// Use lightweight tags too, not only annotated ones
+        #[arg(long)]
+        tags: bool,
+        #[command(flatten)]
+        abbrev: AbbrevArg,
+        #[arg(value_name = "commit", default_value = "HEAD")]
+        rev: String,
+    },
+    /// Show changes between the index, the worktree and commits.
+    Diff {
+        /// Compare the index against HEAD (or the given commit)
+        #[arg(long, visible_alias = "cached", default_value_t = false)]
+        staged: bool,
+        /// Lines of context around each change
+        #[arg(short = 'U', long = "unified", default_value_t = 3)]
+        context: usize,
+        /// Don't run external diff drivers
+        #[arg(long)]
+        no_ext_diff: bool,
+        /// How much of the submodules' state to report
+        #[arg(
+            long,
+            value_name = "when",
+            num_args = 0..=1,
+            require_equals = true,
+            default_value = "none",
+            default_missing_value = "all"
+        )]
+        ignore_submodules: SubmoduleIgnore,
+        /// List changed paths with their modes and object names instead of a patch
+        #[arg(long)]
+        raw: bool,
+        #[command(flatten)]
+        abbrev: AbbrevArg,
+        /// Compare the worktree (or index) against one commit, or two commits
+        /// against each other
+        #[arg(num_args = 0..=2)]
+        commits: Vec<String>,
+    },
+    /// Show changes between the index, the worktree and commits with an
+    /// external diff tool.
+    Difftool {
+        /// Compare the index against HEAD (or the given commit)
+        #[arg(long, visible_alias = "cached", default_value_t = false)]
+        staged: bool,
+        /// The tool to use instead of diff.tool
+        #[arg(short = 't', long)]
+        tool: Option<String>,
+        /// Compare two directories holding every changed file at once
+        #[arg(short = 'd', long)]
+        dir_diff: bool,
+        /// Launch the tool without asking first
+        #[arg(short = 'y', long, conflicts_with = "prompt")]
+        no_prompt: bool,
+        /// Ask before launching the tool on each file
+        #[arg(long)]
+        prompt: bool,
+        /// Compare the worktree (or index) against one commit, or two commits
+        /// against each other
+        #[arg(num_args = 0..=2)]
+        commits: Vec<String>,
+    },
+    /// Count loose objects and the disk space they take.
+    CountObjects {
+        /// Also report packs, objects that could be pruned and garbage
+        #[arg(short, long)]
+        verbose: bool,
+        /// Print sizes in human readable units
+        #[arg(short = 'H', long)]
+        human_readable: bool,
+    },
+    /// Verify the connectivity and validity of objects in the database.
+    Fsck {
+        /// Write dangling objects into .git/lost-found
+        #[arg(long)]
+        lost_found: bool,
+    },
+    /// Clean up unnecessary files and optimize the repository.
+    Gc {
+        /// Only run if the repository exceeds gc.auto or gc.autoPackLimit
+        #[arg(long, default_value_t = false)]
+        auto: bool,
+    },
+    /// Print lines of tracked files matching a pattern.
+    Grep {
+        /// Search the index (the default; the worktree is never searched)
+        #[arg(long, visible_alias = "staged")]
+        cached: bool,
+        /// Match case-insensitively
+        #[arg(short, long)]
+        ignore_case: bool,
+        /// Prefix each line with its line number
+        #[arg(short = 'n', long)]
+        line_number: bool,
+        /// The regular expression to look for
+        pattern: String,
+        /// Search this tree-ish instead of the index
+        tree_ish: Option<String>,
     },
     /// Compute object ID and optionally creates a blob from a file
     HashObject {
@@ -73,17 +704,92 @@ enum Commands {
         #[arg(short = 'w', value_name = "write", default_value_t = false)]
         write: bool,
 
-        /// Read object from path
+        /// Hash the content read from stdin, before any paths
+        #[arg(long, conflicts_with = "stdin_paths")]
+        stdin: bool,
+        /// Read the paths to hash from stdin, one per line
+        #[arg(long, conflicts_with = "paths")]
+        stdin_paths: bool,
+        /// Files to hash
         #[arg(value_name = "path")]
-        path: PathBuf,
+        paths: Vec<PathBuf>,
+    },
+    /// Verify the index file or rebuild it from HEAD.
+    Index {
+        #[arg(value_enum)]
+        action: IndexAction,
     },
     /// Initialize a new, empty repository.
     Init {
         /// Where to create the repository.
         path: PathBuf,
     },
+    /// Add or parse trailers such as Signed-off-by in commit messages.
+    InterpretTrailers {
+        /// A trailer to add, as "Key: value" or "Key=value"
+        #[arg(long = "trailer", value_name = "trailer")]
+        trailers: Vec<String>,
+        /// Rewrite the files instead of printing the result
+        #[arg(long)]
+        in_place: bool,
+        /// Print only the trailers
+        #[arg(long)]
+        only_trailers: bool,
+        /// List the trailers already there, one per line
+        #[arg(long, conflicts_with = "trailers")]
+        parse: bool,
+        /// Messages to work on, instead of stdin
+        files: Vec<String>,
+    },
+    /// Download objects and refs from another repository.
+    Fetch {
+        /// The remote to fetch from (defaults to the upstream's remote or origin)
+        remote: Option<String>,
+        /// The refs to fetch instead of the remote's configured refspecs
+        refspecs: Vec<String>,
+    },
+    /// Prepare commits as patch mails for `am`.
+    FormatPatch {
+        /// Write the patches to stdout instead of a file each
+        #[arg(long)]
+        stdout: bool,
+        /// Number the subjects ("[PATCH n/m]") even for a single patch
+        #[arg(short = 'n', long)]
+        numbered: bool,
+        /// Never number the subjects
+        #[arg(short = 'N', long, conflicts_with = "numbered")]
+        no_numbered: bool,
+        /// Write the files into <dir>
+        #[arg(short = 'o', long = "output-directory", value_name = "dir")]
+        output_directory: Option<PathBuf>,
+        /// The commits after <since> up to HEAD, or a <since>..<until> range
+        range: String,
+    },
     /// Display history of a given commit.
     Log {
+        /// Only show commits that change the number of occurrences of the string
+        #[arg(short = 'S', value_name = "string", conflicts_with = "regex")]
+        string: Option<String>,
+        /// Only show commits whose added or removed lines match the regex
+        #[arg(short = 'G', value_name = "regex")]
+        regex: Option<String>,
+        #[command(flatten)]
+        abbrev: AbbrevArg,
+        /// Draw the history as a Graphviz graph
+        #[arg(long, conflicts_with = "oneline")]
+        graphviz: bool,
+        /// Show each commit on one line: its short id and subject
+        #[arg(long, conflicts_with_all = ["pretty", "format"])]
+        oneline: bool,
+        /// How to show commits: oneline, medium, format:<fmt> or tformat:<fmt>
+        #[arg(long, value_name = "format", conflicts_with = "format")]
+        pretty: Option<String>,
+        /// Show each commit as <fmt>, with placeholders like %H, %an and %s
+        #[arg(long, value_name = "fmt")]
+        format: Option<String>,
+        /// Show at most <count> commits
+        #[arg(short = 'n', long, value_name = "count")]
+        max_count: Option<usize>,
         /// Commit to start at.
         #[arg(default_value = "HEAD")]
         commit: String,
@@ -94,103 +800,1498 @@ enum Commands {
         #[arg(short = 'v', default_value_t = false)]
         verbose: bool,
     },
+    /// List the refs a remote repository has, without fetching anything.
+    LsRemote {
+        /// Only branches
+        #[arg(long)]
+        heads: bool,
+        /// Only tags
+        #[arg(long)]
+        tags: bool,
+        /// Leave out peeled tags and HEAD
+        #[arg(long)]
+        refs: bool,
+        /// Show the ref HEAD points to too
+        #[arg(long)]
+        symref: bool,
+        /// A configured remote or a URL (defaults to the current branch's remote)
+        remote: Option<String>,
+        /// Only refs ending in one of these
+        patterns: Vec<String>,
+    },
     /// Pretty-print a tree object.
     LsTree {
         /// Recurse into sub-trees
         #[arg(short)]
         recursive: bool,
+        #[command(flatten)]
+        abbrev: AbbrevArg,
         /// A tree-ish object.
         tree: String,
     },
+    /// Run tasks to optimize repository data, now or on a schedule.
+    Maintenance {
+        #[arg(value_enum)]
+        action: MaintenanceAction,
+        /// With run, only run the tasks the repository needs
+        #[arg(long, default_value_t = false)]
+        auto: bool,
+        /// With run, maintain every registered repository
+        #[arg(long, default_value_t = false)]
+        registered: bool,
+        /// With run, only run this task: loose-objects, reflog-expire,
+        /// commit-graph or lock-cleanup (repeatable)
+        #[arg(long = "task", value_name = "task")]
+        tasks: Vec<String>,
+        /// With start, one of auto, crontab, systemd-timer or launchctl
+        #[arg(long, default_value = "auto")]
+        scheduler: String,
+    },
+    /// Join another branch into the current one.
+    Merge {
+        /// The branch or commit to merge
+        commit: String,
+    },
+    /// Find the best common ancestors of commits.
+    MergeBase {
+        /// Print every best common ancestor, not just one
+        #[arg(short, long)]
+        all: bool,
+        /// Check that the first commit is an ancestor of the second
+        #[arg(long, conflicts_with = "all")]
+        is_ancestor: bool,
+        /// The commits; the ancestors are those of the first and a merge
+        /// of the others
+        #[arg(required = true, num_args = 2..)]
+        commits: Vec<String>,
+    },
+    /// Find symbolic names for commits, relative to refs.
+    NameRev {
+        /// Only name commits after tags
+        #[arg(long)]
+        tags: bool,
+        /// Print only the names, not the revisions given
+        #[arg(long)]
+        name_only: bool,
+        /// The commits to name
+        #[arg(required = true)]
+        revs: Vec<String>,
+    },
+    /// Attach notes to commits without changing them.
+    Notes {
+        action: NotesAction,
+        /// The note's text; several are joined as paragraphs
+        #[arg(short = 'm', long = "message", value_name = "msg")]
+        messages: Vec<String>,
+        /// Replace an existing note when adding
+        #[arg(short = 'f', long)]
+        force: bool,
+        /// The annotated object (HEAD if omitted, except for list)
+        object: Option<String>,
+    },
+    /// Delete unreachable loose objects.
+    Prune {
+        /// Only list what would be deleted
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// List the deleted objects
+        #[arg(short, long)]
+        verbose: bool,
+        /// Only delete objects older than this, e.g. 2.weeks.ago
+        #[arg(long)]
+        expire: Option<String>,
+    },
+    /// Fetch the upstream of the current branch and merge it in.
+    Pull {
+        /// Rebase onto the upstream instead of merging it
+        #[arg(short, long)]
+        rebase: bool,
+        /// The remote to pull from: a configured one, a URL, or "." for this repository
+        remote: Option<String>,
+        /// The branch of the remote to merge (defaults to the upstream)
+        branches: Vec<String>,
+    },
+    /// Get and set repository or global options.
+    Config {
+        /// Use only the global ~/.gitconfig
+        #[arg(long)]
+        global: bool,
+        /// Use only the repository's config
+        #[arg(long)]
+        local: bool,
+        /// Print the value of <key>
+        #[arg(long)]
+        get: bool,
+        /// Remove <key>
+        #[arg(long)]
+        unset: bool,
+        /// List every variable with its value
+        #[arg(short, long)]
+        list: bool,
+        /// The variable, as section[.subsection].name
+        #[arg(required_unless_present = "list")]
+        key: Option<String>,
+        /// The value to set <key> to
+        value: Option<String>,
+    },
+    /// Manage the set of tracked repositories.
+    Remote {
+        /// What to do; without one, list the remotes
+        action: Option<RemoteAction>,
+        /// Show the remotes' URLs
+        #[arg(short, long)]
+        verbose: bool,
+        /// With set-url, change the push URL instead
+        #[arg(long)]
+        push: bool,
+        /// The remote to act on
+        #[arg(required_if_eq_any = [
+            ("action", "add"),
+            ("action", "remove"),
+            ("action", "rename"),
+            ("action", "set-url"),
+            ("action", "show"),
+        ])]
+        name: Option<String>,
+        /// With add and set-url the URL, with rename the new name
+        #[arg(required_if_eq_any = [
+            ("action", "add"),
+            ("action", "rename"),
+            ("action", "set-url"),
+        ])]
+        target: Option<String>,
+    },
+    /// Update remote refs along with the objects they need.
+    Push {
+        /// The remote or URL to push to, by default the current branch's
+        /// upstream remote
+        remote: Option<String>,
+        /// What to push, as [+]<src>[:<dst>]; by default the current branch
+        refspecs: Vec<String>,
+        /// Allow updates that are not fast-forwards
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Compare two versions of a series of commits, such as before and after a rebase.
+    RangeDiff {
+        /// How costly, in percent of its size, a patch counts as being added or dropped
+        /// rather than changed
+        #[arg(long, default_value_t = 60, value_name = "factor")]
+        creation_factor: usize,
+        /// <base1>..<rev1> <base2>..<rev2>, <rev1>...<rev2>, or <base> <rev1> <rev2>
+        #[arg(required = true, num_args = 1..=3)]
+        ranges: Vec<String>,
+    },
+    /// Read trees into the index, merging them with -m.
+    ReadTree {
+        /// Merge two trees as a switch between them does, or three as a merge
+        #[arg(short)]
+        merge: bool,
+        /// Add the tree under this directory of the index instead
+        #[arg(long, value_name = "dir", conflicts_with = "merge")]
+        prefix: Option<String>,
+        /// <tree-ish>, or with -m <from> <to> or <base> <ours> <theirs>
+        #[arg(required = true, num_args = 1..=3)]
+        trees: Vec<String>,
+    },
+    /// Replay the current branch's commits on top of another commit.
+    Rebase {
+        /// Abort the rebase and restore the original branch
+        #[arg(long, default_value_t = false)]
+        abort: bool,
+        /// Continue the rebase after resolving conflicts
+        #[arg(long = "continue", default_value_t = false)]
+        cont: bool,
+        /// Date each replayed commit with its author date
+        #[arg(long, conflicts_with = "ignore_date")]
+        committer_date_is_author_date: bool,
+        /// Give the replayed commits the current time as their author date
+        #[arg(long)]
+        ignore_date: bool,
+        /// Fold "fixup!" and "squash!" commits into the commits they name
+        #[arg(long)]
+        autosquash: bool,
+        /// The commit to rebase onto
+        #[arg(required_unless_present_any = ["abort", "cont"])]
+        upstream: Option<String>,
+    },
+    /// Show where a ref has pointed, newest first.
+    #[command(args_conflicts_with_subcommands = true)]
+    Reflog {
+        #[command(subcommand)]
+        action: Option<ReflogAction>,
+        /// The ref whose log to show (HEAD if omitted)
+        refname: Option<String>,
+    },
+    /// Reuse recorded resolutions of conflicts (with rerere.enabled).
+    Rerere {
+        /// What to do instead of recording the resolutions made so far
+        #[arg(value_enum)]
+        action: Option<RerereCommand>,
+    },
+    /// Reset current HEAD to the specified state.
+    Reset {
+        /// Only move the branch, keeping the index and worktree
+        #[arg(long, default_value_t = false, conflicts_with_all = ["mixed", "hard"])]
+        soft: bool,
+        /// Reset the index but not the worktree (the default)
+        #[arg(long, default_value_t = false, conflicts_with = "hard")]
+        mixed: bool,
+        /// Reset the index and the worktree
+        #[arg(long, default_value_t = false)]
+        hard: bool,
+        /// The commit to reset to (defaults to HEAD), optionally followed by
+        /// paths whose index entries to reset
+        args: Vec<String>,
+    },
+    /// Restore worktree files or index entries.
+    Restore {
+        /// Take the content from this tree-ish instead of the index (or HEAD with --staged)
+        #[arg(short = 's', long)]
+        source: Option<String>,
+        /// Restore the index
+        #[arg(short = 'S', long, default_value_t = false)]
+        staged: bool,
+        /// Restore the worktree (the default unless --staged is given)
+        #[arg(short = 'W', long, default_value_t = false)]
+        worktree: bool,
+        /// Paths to restore
+        #[arg(required = true, num_args = 1.., value_parser = clap::value_parser!(PathBuf))]
+        path: Vec<PathBuf>,
+    },
+    /// Revert the change introduced by an existing commit.
+    Revert {
+        /// The commit to revert
+        commit: String,
+    },
+    /// List commits, newest first.
+    RevList {
+        /// Also start from every ref and HEAD
+        #[arg(long)]
+        all: bool,
+        /// Print the number of commits instead
+        #[arg(long)]
+        count: bool,
+        /// Stop after this many commits
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+        /// Only commits more recent than this date, e.g. "2.weeks.ago"
+        #[arg(long, visible_alias = "after")]
+        since: Option<String>,
+        /// Only commits older than this date
+        #[arg(long, visible_alias = "before")]
+        until: Option<String>,
+        /// Commits to start from; ^rev and a..b exclude what rev and a reach
+        revs: Vec<String>,
+    },
     /// Parse revision (or other objects) identifiers
     RevParse {
         /// The name to parse
         name: String,
         #[arg(value_name = "type", help = "Specify the type", value_enum, default_value=None)]
         object_type: Option<ObjectType>,
+        /// Print the object's name in this format; sha256 needs
+        /// extensions.compatObjectFormat
+        #[arg(long, value_enum, default_value = "sha1")]
+        output_object_format: ObjectFormat,
     },
     /// Remove files from the working tree and the index.
     Rm {
-        /// Files to remove
+        /// Allow recursive removal when a leading directory name is given
+        #[arg(short = 'r')]
+        recursive: bool,
+        /// Remove files even if they have local or staged changes
+        #[arg(short = 'f', long)]
+        force: bool,
+        /// Only remove from the index, keeping the worktree files
+        #[arg(long)]
+        cached: bool,
+        /// Exit successfully even if no files matched
+        #[arg(long)]
+        ignore_unmatch: bool,
+        /// Only show which files would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Don't list the removed files
+        #[arg(short = 'q', long)]
+        quiet: bool,
+        /// Files to remove; directories with -r, and globs such as '*.txt'
         #[arg(required = true, num_args = 1.., value_parser = clap::value_parser!(PathBuf))]
         path: Vec<PathBuf>,
     },
+    /// Host the repositories under a directory for git clients.
+    Serve {
+        /// Serve git's smart HTTP protocol
+        #[arg(long, default_value_t = false)]
+        http: bool,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Require HTTP basic auth with these credentials, which also
+        /// allows pushing to repositories without http.receivepack set
+        #[arg(long, value_name = "user:password")]
+        auth: Option<String>,
+        /// Directory holding the repositories to serve
+        root: PathBuf,
+    },
+    /// Show a commit with its diff, a tag, a tree or a blob.
+    Show {
+        /// The object to show
+        #[arg(default_value = "HEAD")]
+        object: String,
+    },
+    /// Summarize commits by author.
+    Shortlog {
+        /// Sort authors by their number of commits
+        #[arg(short = 'n', long)]
+        numbered: bool,
+        /// Only show each author's commit count
+        #[arg(short = 's', long)]
+        summary: bool,
+        /// Revisions or ranges (`a..b`, `^a`); HEAD if none
+        #[arg(value_name = "revision-range")]
+        revs: Vec<String>,
+    },
     /// List references.
-    ShowRef,
+    ShowRef {
+        /// Only branches
+        #[arg(long)]
+        heads: bool,
+        /// Only tags
+        #[arg(long)]
+        tags: bool,
+        /// Only print the object names
+        #[arg(short = 's', long)]
+        hash: bool,
+        /// Read refs from stdin and print those that don't exist here,
+        /// optionally only those starting with the given prefix
+        #[arg(long, value_name = "pattern", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+        exclude_existing: Option<String>,
+        /// Only refs ending in one of these, in whole path components
+        patterns: Vec<String>,
+    },
+    /// Check out only part of the tracked files.
+    SparseCheckout {
+        #[arg(value_enum)]
+        action: SparseCheckoutCommand,
+        /// Take gitignore-style patterns rather than directories
+        #[arg(long)]
+        no_cone: bool,
+        /// With set, the directories (or patterns) to check out
+        patterns: Vec<String>,
+    },
+    /// Stash the changes in a dirty working directory away.
+    Stash {
+        #[arg(value_enum, default_value = "push")]
+        action: StashAction,
+        /// With push, a description for the entry
+        #[arg(short = 'm', long)]
+        message: Option<String>,
+        /// The entry to apply, pop or drop, e.g. stash@{1} (the newest if omitted)
+        stash: Option<String>,
+    },
+    /// Summarize the repository's history and object database.
+    Stats {
+        /// How many of the largest blobs to list
+        #[arg(short = 'n', long, default_value_t = 10)]
+        largest: usize,
+    },
+    /// Remove unnecessary whitespace from a message on stdin.
+    Stripspace {
+        /// Also drop lines starting with the comment character
+        #[arg(short, long, conflicts_with = "comment_lines")]
+        strip_comments: bool,
+        /// Prefix each line with the comment character instead
+        #[arg(short, long)]
+        comment_lines: bool,
+    },
+    /// Read or change what a symbolic ref such as HEAD points to.
+    SymbolicRef {
+        /// Reason for the change, for the reflog
+        #[arg(short = 'm', value_name = "reason")]
+        message: Option<String>,
+        /// Print the target without its refs/heads/-like prefix
+        #[arg(long)]
+        short: bool,
+        /// The symbolic ref
+        name: String,
+        /// The ref to point it to
+        target: Option<String>,
+    },
+    /// Initialize, update or inspect submodules.
+    Submodule {
+        #[arg(value_enum, default_value = "status")]
+        action: SubmoduleAction,
+        /// With update, initialize submodules that are not yet
+        #[arg(long)]
+        init: bool,
+        /// Only the submodules at these paths
+        paths: Vec<PathBuf>,
+    },
     /// Show the working tree status.
-    Status,
+    Status {
+        /// How much of the submodules' state to report
+        #[arg(
+            long,
+            value_name = "when",
+            num_args = 0..=1,
+            require_equals = true,
+            default_value = "none",
+            default_missing_value = "all"
+        )]
+        ignore_submodules: SubmoduleIgnore,
+    },
+    /// Switch branches, updating the index and worktree in place.
+    Switch {
+        /// Create the branch at HEAD before switching to it
+        #[arg(short = 'c', default_value_t = false)]
+        create: bool,
+        /// Detach HEAD at the given commit
+        #[arg(long, default_value_t = false)]
+        detach: bool,
+        /// The branch (or commit, with --detach) to switch to
+        branch: String,
+    },
     /// List and create tags.
     Tag {
-        /// Whether to create a tag object
-        #[arg(short = 'a', default_value_t = false)]
-        crate_tag_object: bool,
-        /// The new tag's name
-        name: Option<String>,
-        /// The object the new tag will point to
-        #[arg(value_name = "object", default_value = "HEAD")]
-        obj: String,
+        /// Write an annotated tag object
+        #[arg(short = 'a', long, default_value_t = false)]
+        annotate: bool,
+        /// The annotated tag's message; several are joined as paragraphs
+        #[arg(short = 'm', long = "message", value_name = "msg")]
+        messages: Vec<String>,
+        /// Replace an existing tag
+        #[arg(short = 'f', long)]
+        force: bool,
+        /// List tags, those matching the patterns if any
+        #[arg(short = 'l', long, conflicts_with_all = ["delete", "verify"])]
+        list: bool,
+        /// Delete the named tags
+        #[arg(short = 'd', long, conflicts_with = "verify")]
+        delete: bool,
+        /// Verify the GPG signature of the named tags
+        #[arg(short = 'v', long)]
+        verify: bool,
+        /// Only list tags that contain the commit (HEAD if omitted)
+        #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+        contains: Option<String>,
+        /// Only list tags that point at the object (HEAD if omitted)
+        #[arg(long, value_name = "object", num_args = 0..=1, default_missing_value = "HEAD")]
+        points_at: Option<String>,
+        /// Sort by `refname` or `version:refname`; prefix with `-` to reverse
+        #[arg(long, value_name = "key", allow_hyphen_values = true)]
+        sort: Option<String>,
+        /// The new tag's name and the object it points to (HEAD if
+        /// omitted), the tags to delete or verify, or patterns to list
+        args: Vec<String>,
+    },
+    /// Take back the last commit, reset, merge or rebase using HEAD's reflog.
+    Undo {
+        /// Don't ask for confirmation
+        #[arg(short = 'y', long)]
+        yes: bool,
+        /// Restore the worktree too, discarding local changes
+        #[arg(long)]
+        hard: bool,
+    },
+    /// Change the index directly, or how it is kept.
+    UpdateIndex {
+        /// Write the index split: only the changes against a shared index
+        #[arg(long, overrides_with = "no_split_index")]
+        split_index: bool,
+        /// Write the whole index again
+        #[arg(long)]
+        no_split_index: bool,
+        /// Add paths that aren't in the index yet
+        #[arg(long)]
+        add: bool,
+        /// Remove paths that are gone from the worktree
+        #[arg(long)]
+        remove: bool,
+        /// Take the stat data of entries whose files haven't changed
+        #[arg(long)]
+        refresh: bool,
+        /// Set the entry of <path> to the object <sha> with <mode>
+        #[arg(long, value_name = "mode>,<sha>,<path")]
+        cacheinfo: Vec<String>,
+        /// Mark the paths as unchanged, so their files are not checked
+        #[arg(long, overrides_with = "no_assume_unchanged")]
+        assume_unchanged: bool,
+        /// Check the files of the paths again
+        #[arg(long)]
+        no_assume_unchanged: bool,
+        paths: Vec<PathBuf>,
+    },
+    /// Update the object a ref points to, safely.
+    UpdateRef {
+        /// Delete the ref, if it is at <old> when given
+        #[arg(short)]
+        delete: bool,
+        /// Update a symbolic ref itself instead of the ref it points to
+        #[arg(long)]
+        no_deref: bool,
+        /// Read update, create, delete and verify commands from stdin and
+        /// apply them all or none
+        #[arg(long)]
+        stdin: bool,
+        /// Reason for the update, for the reflog
+        #[arg(short = 'm', value_name = "reason")]
+        message: Option<String>,
+        /// <ref> <new> [<old>], or <ref> [<old>] with -d
+        #[arg(num_args = 0..=3)]
+        args: Vec<String>,
+    },
+    /// Validate packed archive files.
+    VerifyPack {
+        /// List each object and the delta chain lengths
+        #[arg(short, long)]
+        verbose: bool,
+        /// Only print the delta chain lengths
+        #[arg(short = 's', long)]
+        stat_only: bool,
+        /// The packs, by their .idx or .pack files
+        #[arg(required = true)]
+        packs: Vec<PathBuf>,
+    },
+    /// Write the trees of the index and print the root tree's id.
+    WriteTree {
+        /// Print the id of the tree for this directory instead
+        #[arg(long, value_name = "dir")]
+        prefix: Option<String>,
+        /// Allow entries whose objects aren't in the repository
+        #[arg(long)]
+        missing_ok: bool,
     },
+    /// Manage multiple worktrees attached to the same repository.
+    Worktree {
+        action: WorktreeAction,
+        /// With add, create a new branch for the worktree
+        #[arg(short = 'b', value_name = "new-branch")]
+        new_branch: Option<String>,
+        /// With add, detach HEAD in the new worktree
+        #[arg(long)]
+        detach: bool,
+        /// Check out a branch in use elsewhere, or remove a dirty or locked worktree
+        #[arg(short = 'f', long)]
+        force: bool,
+        /// With prune, only report what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// With prune, report what is removed
+        #[arg(short = 'v', long)]
+        verbose: bool,
+        /// With lock, why the worktree is locked
+        #[arg(long)]
+        reason: Option<String>,
+        /// The worktree to add, remove, lock, unlock or repair
+        #[arg(required_if_eq_any = [
+            ("action", "add"),
+            ("action", "remove"),
+            ("action", "lock"),
+            ("action", "unlock"),
+        ])]
+        path: Option<PathBuf>,
+        /// With add, the commit or branch to check out (HEAD by default)
+        commit_ish: Option<String>,
+    },
+}
+
+/// The `--committer-date-is-author-date` and `--ignore-date` flags of
+/// rebase and cherry-pick, which clap keeps from both being set.
+/// Ends a command that stopped for the user to step in, e.g. to resolve
+/// conflicts, as git does: with its message and exit status 1, not a panic.
+fn exit_on_error(result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        eprintln!("{:#}", e);
+        std::process::exit(1);
+    }
+}
+
+fn pick_dates(committer_date_is_author_date: bool, ignore_date: bool) -> PickDates {
+    if committer_date_is_author_date {
+        PickDates::CommitterIsAuthor
+    } else if ignore_date {
+        PickDates::Ignore
+    } else {
+        PickDates::Keep
+    }
 }
 
 pub fn start() {
     let cli = Cli::parse();
+    if let Some(dir) = &cli.directory {
+        std::env::set_current_dir(dir).unwrap();
+    }
+    // Commands find the repository through repo_find, which honors these.
+    if let Some(git_dir) = &cli.git_dir {
+        std::env::set_var("GIT_DIR", git_dir);
+    }
+    if let Some(work_tree) = &cli.work_tree {
+        std::env::set_var("GIT_WORK_TREE", work_tree);
+    }
+    if cli.strict_hash_verification {
+        std::env::set_var("GIT_STRICT_HASH_VERIFICATION", "1");
+    }
+    if cli.profile {
+        profile_enable();
+    }
 
     match cli.command {
-        Commands::Add => todo!(),
-        Commands::Commit => todo!(),
-        Commands::ShowRef => {
-            show_ref().unwrap();
+        Commands::Add {
+            interactive,
+            patch,
+            paths,
+        } => {
+            if interactive {
+                add_interactive().unwrap();
+            } else if patch {
+                add_patch_all().unwrap();
+            } else {
+                add(&paths).unwrap();
+            }
+        }
+        Commands::Commit {
+            message,
+            fixup,
+            squash,
+        } => {
+            commit(message, fixup.as_deref(), squash.as_deref()).unwrap();
+        }
+        Commands::ShowRef {
+            heads,
+            tags,
+            hash,
+            exclude_existing,
+            patterns,
+        } => {
+            let opts = ShowRefOptions {
+                heads,
+                tags,
+                hash,
+                exclude_existing,
+            };
+            show_ref(&patterns, &opts).unwrap();
+        }
+        Commands::SparseCheckout {
+            action,
+            no_cone,
+            patterns,
+        } => {
+            let action = match action {
+                SparseCheckoutCommand::Init => SparseCheckoutAction::Init,
+                SparseCheckoutCommand::Set => SparseCheckoutAction::Set(patterns),
+                SparseCheckoutCommand::List => SparseCheckoutAction::List,
+                SparseCheckoutCommand::Disable => SparseCheckoutAction::Disable,
+            };
+            sparse_checkout(action, !no_cone).unwrap();
+        }
+        Commands::Stash {
+            action,
+            message,
+            stash,
+        } => match action {
+            StashAction::Push => stash_push(message.as_deref()).unwrap(),
+            StashAction::List => stash_list().unwrap(),
+            StashAction::Apply => exit_on_error(stash_apply(stash.as_deref(), false)),
+            StashAction::Pop => exit_on_error(stash_apply(stash.as_deref(), true)),
+            StashAction::Drop => stash_drop(stash.as_deref()).unwrap(),
+        },
+        Commands::Show { object } => {
+            show(&object).unwrap();
+        }
+        Commands::Shortlog {
+            numbered,
+            summary,
+            revs,
+        } => {
+            shortlog(numbered, summary, &revs).unwrap();
+        }
+        Commands::Stats { largest } => {
+            stats(largest).unwrap();
+        }
+        Commands::Stripspace {
+            strip_comments,
+            comment_lines,
+        } => {
+            stripspace(strip_comments, comment_lines).unwrap();
+        }
+        Commands::SymbolicRef {
+            message,
+            short,
+            name,
+            target,
+        } => {
+            symbolic_ref(&name, target.as_deref(), message.as_deref(), short).unwrap();
         }
-        Commands::Status => {
-            status().unwrap();
+        Commands::Submodule {
+            action,
+            init,
+            paths,
+        } => match action {
+            SubmoduleAction::Status => submodule_status_print(&paths).unwrap(),
+            SubmoduleAction::Init => submodule_init(&paths).unwrap(),
+            SubmoduleAction::Update => submodule_update(init, &paths).unwrap(),
+        },
+        Commands::Status { ignore_submodules } => {
+            status(ignore_submodules.mode()).unwrap();
         }
         Commands::Init { path } => {
             repo_create(path).unwrap();
         }
+        Commands::InterpretTrailers {
+            trailers,
+            in_place,
+            only_trailers,
+            parse,
+            files,
+        } => {
+            interpret_trailers(&trailers, &files, in_place, only_trailers, parse).unwrap();
+        }
         Commands::CatFile {
-            object_type,
-            object,
+            show_type,
+            show_size,
+            pretty,
+            batch,
+            batch_check,
+            args,
         } => {
-            cat_file(&object, Some(object_type.as_bytes())).unwrap();
+            let show = match (show_type, show_size, pretty) {
+                (true, _, _) => Some(CatFileShow::Type),
+                (_, true, _) => Some(CatFileShow::Size),
+                (_, _, true) => Some(CatFileShow::Pretty),
+                _ => None,
+            };
+            let usage = || {
+                Cli::command()
+                    .error(
+                        ErrorKind::WrongNumberOfValues,
+                        "cat-file takes <type> <object>, <object> with -t, -s or -p, \
+                         or nothing with --batch or --batch-check",
+                    )
+                    .exit()
+            };
+            match (show, args.as_slice()) {
+                (_, []) if batch || batch_check => cat_file_batch(batch).unwrap(),
+                _ if batch || batch_check => usage(),
+                (Some(show), [object]) => cat_file_show(object, show).unwrap(),
+                (None, [object_type, object]) => {
+                    let object_type =
+                        ObjectType::from_str(object_type, false).unwrap_or_else(|e| {
+                            Cli::command().error(ErrorKind::InvalidValue, e).exit()
+                        });
+                    cat_file(object, Some(object_type.as_bytes())).unwrap();
+                }
+                _ => usage(),
+            }
+        }
+        Commands::Gc { auto } => {
+            gc(auto).unwrap();
+        }
+        Commands::Prune {
+            dry_run,
+            verbose,
+            expire,
+        } => {
+            prune(dry_run, verbose, expire.as_deref()).unwrap();
         }
         Commands::HashObject {
             object_type,
             write,
-            path,
+            stdin,
+            stdin_paths,
+            paths,
+        } => {
+            hash_object(&paths, stdin, stdin_paths, object_type.as_bytes(), write).unwrap();
+        }
+        Commands::Log {
+            string,
+            regex,
+            abbrev,
+            graphviz,
+            oneline,
+            pretty,
+            format,
+            max_count,
+            commit,
+        } => {
+            let pickaxe = match (string, regex) {
+                (Some(s), _) => Some(Pickaxe::Count(s)),
+                (_, Some(r)) => Some(Pickaxe::Regex(regex::bytes::Regex::new(&r).unwrap())),
+                _ => None,
+            };
+            let format = match (oneline, pretty.as_deref(), format) {
+                (true, _, _) => LogFormat::Oneline,
+                (_, Some("oneline"), _) => LogFormat::Custom {
+                    format: "%H %s".to_string(),
+                    terminator: true,
+                },
+                (_, Some("medium"), _) | (_, None, None) => LogFormat::Medium,
+                (_, Some(pretty), _) => match pretty.split_once(':') {
+                    Some(("format", format)) => LogFormat::Custom {
+                        format: format.to_string(),
+                        terminator: false,
+                    },
+                    Some(("tformat", format)) => LogFormat::Custom {
+                        format: format.to_string(),
+                        terminator: true,
+                    },
+                    _ if pretty.contains('%') => LogFormat::Custom {
+                        format: pretty.to_string(),
+                        terminator: true,
+                    },
+                    _ => Cli::command()
+                        .error(
+                            ErrorKind::InvalidValue,
+                            format!("invalid --pretty format: {}", pretty),
+                        )
+                        .exit(),
+                },
+                (_, None, Some(format)) => LogFormat::Custom {
+                    format,
+                    terminator: true,
+                },
+            };
+            let opts = LogOptions {
+                graphviz,
+                format,
+                max_count,
+                pickaxe,
+                abbrev: abbrev.len(None),
+            };
+            log(&commit, &opts).unwrap();
+        }
+        Commands::LsRemote {
+            heads,
+            tags,
+            refs,
+            symref,
+            remote,
+            patterns,
+        } => {
+            let opts = LsRemoteOptions {
+                heads,
+                tags,
+                refs,
+                symref,
+            };
+            ls_remote(remote.as_deref(), &patterns, &opts).unwrap();
+        }
+        Commands::LsTree {
+            recursive,
+            abbrev,
+            tree,
         } => {
-            println!(
-                "{}",
-                hash_object(&path, object_type.as_bytes(), write,).unwrap()
-            );
+            ls_tree(&tree, recursive, abbrev.len(Some(0))).unwrap();
         }
-        Commands::Log { commit } => {
-            log(&commit).unwrap();
+        Commands::CherryPick {
+            committer_date_is_author_date,
+            ignore_date,
+            commit,
+        } => {
+            let dates = pick_dates(committer_date_is_author_date, ignore_date);
+            exit_on_error(cherry_pick(&commit, dates));
+        }
+        Commands::Fetch { remote, refspecs } => {
+            fetch(remote.as_deref(), &refspecs).unwrap();
         }
-        Commands::LsTree { recursive, tree } => {
-            ls_tree(&tree, recursive).unwrap();
+        Commands::FormatPatch {
+            stdout,
+            numbered,
+            no_numbered,
+            output_directory,
+            range,
+        } => {
+            let opts = FormatPatchOptions {
+                stdout,
+                numbered: (numbered || no_numbered).then_some(numbered),
+                output_dir: output_directory,
+            };
+            format_patch(&range, &opts).unwrap();
+        }
+        Commands::Clean {
+            force,
+            dry_run,
+            dirs,
+            ignored,
+            only_ignored,
+            interactive,
+            quiet,
+            paths,
+        } => {
+            let opts = CleanOptions {
+                force,
+                dry_run,
+                dirs,
+                ignored,
+                only_ignored,
+                interactive,
+                quiet,
+            };
+            clean(&paths, &opts).unwrap();
         }
+        Commands::Clone { resume, url, dir } => match resume {
+            true => clone_resume(Path::new(&url)).unwrap(),
+            false => clone(&url, dir.as_deref()).unwrap(),
+        },
         Commands::Checkout { commit, path } => {
-            checkout(&commit, &path).unwrap();
+            checkout(&commit, path.as_ref()).unwrap();
+        }
+        Commands::CheckoutIndex {
+            all,
+            force,
+            prefix,
+            paths,
+        } => {
+            let opts = CheckoutIndexOptions { force, prefix };
+            checkout_index(&paths, all, &opts).unwrap();
+        }
+        Commands::Undo { yes, hard } => {
+            undo(yes, hard).unwrap();
+        }
+        Commands::UpdateRef {
+            delete,
+            no_deref,
+            stdin,
+            message,
+            args,
+        } => {
+            update_ref(&args, delete, no_deref, stdin, message.as_deref()).unwrap();
+        }
+        Commands::UpdateIndex {
+            split_index,
+            no_split_index,
+            add,
+            remove,
+            refresh,
+            cacheinfo,
+            assume_unchanged,
+            no_assume_unchanged,
+            paths,
+        } => {
+            let opts = UpdateIndexOptions {
+                split_index: (split_index || no_split_index).then_some(split_index),
+                add,
+                remove,
+                refresh,
+                cacheinfo,
+                assume_unchanged: (assume_unchanged || no_assume_unchanged)
+                    .then_some(assume_unchanged),
+            };
+            update_index(&paths, &opts).unwrap();
+        }
+        Commands::VerifyPack {
+            verbose,
+            stat_only,
+            packs,
+        } => {
+            verify_pack(&packs, verbose, stat_only).unwrap();
+        }
+        Commands::Worktree {
+            action,
+            new_branch,
+            detach,
+            force,
+            dry_run,
+            verbose,
+            reason,
+            path,
+            commit_ish,
+        } => {
+            let paths = Vec::from_iter(path.clone());
+            let path = path.unwrap_or_default();
+            match action {
+                WorktreeAction::Add => worktree_add(
+                    &path,
+                    new_branch.as_deref(),
+                    detach,
+                    force,
+                    commit_ish.as_deref(),
+                )
+                .unwrap(),
+                WorktreeAction::List => worktree_list().unwrap(),
+                WorktreeAction::Remove => worktree_remove(&path, force).unwrap(),
+                WorktreeAction::Prune => worktree_prune(dry_run, verbose).unwrap(),
+                WorktreeAction::Lock => worktree_lock(&path, reason.as_deref()).unwrap(),
+                WorktreeAction::Unlock => worktree_unlock(&path).unwrap(),
+                WorktreeAction::Repair => worktree_repair(&paths).unwrap(),
+            }
+        }
+        Commands::WriteTree { prefix, missing_ok } => {
+            write_tree(prefix.as_deref(), missing_ok).unwrap();
         }
         Commands::Tag {
-            crate_tag_object,
-            name,
-            obj,
+            annotate,
+            messages,
+            force,
+            list,
+            delete,
+            verify,
+            contains,
+            points_at,
+            sort,
+            args,
         } => {
-            tag(crate_tag_object, name, &obj).unwrap();
+            let listing = contains.is_some() || points_at.is_some() || sort.is_some();
+            if delete {
+                tag_delete(&args).unwrap();
+            } else if verify {
+                tag_verify(&args).unwrap();
+            } else if list || listing || args.is_empty() {
+                let filter = TagFilter {
+                    patterns: args,
+                    contains,
+                    points_at,
+                    sort,
+                };
+                tag_show_list(&filter).unwrap();
+            } else {
+                let opts = TagOptions {
+                    annotate,
+                    messages,
+                    force,
+                };
+                tag(&args, &opts).unwrap();
+            }
         }
-        Commands::RevParse { object_type, name } => {
+        Commands::Revert { commit } => {
+            exit_on_error(revert(&commit));
+        }
+        Commands::RevList {
+            all,
+            count,
+            max_count,
+            since,
+            until,
+            revs,
+        } => {
+            let opts = RevListOptions {
+                all,
+                count,
+                max_count,
+                since,
+                until,
+            };
+            rev_list(&revs, &opts).unwrap();
+        }
+        Commands::RevParse {
+            object_type,
+            name,
+            output_object_format,
+        } => {
             let fmt = object_type.map(|v| v.as_bytes());
-            rev_parse(&name, fmt).unwrap();
+            let sha256 = matches!(output_object_format, ObjectFormat::Sha256);
+            rev_parse(&name, fmt, sha256).unwrap();
         }
         Commands::LsFiles { verbose } => {
             ls_files(verbose).unwrap();
         }
+        Commands::CheckAttr {
+            all,
+            cached,
+            stdin,
+            attrs,
+            paths,
+        } => {
+            let opts = CheckAttrOptions { all, cached, stdin };
+            check_attr(&attrs, &paths, &opts).unwrap();
+        }
+        Commands::CheckRefFormat {
+            branch,
+            allow_onelevel,
+            normalize,
+            name,
+        } => {
+            if !check_ref_format(&name, branch, allow_onelevel, normalize).unwrap() {
+                std::process::exit(1);
+            }
+        }
         Commands::CheckIgnore { path } => {
             check_ignore(&path).unwrap();
         }
-        Commands::Rm { path } => {
-            rm(&path).unwrap();
+        Commands::Rm {
+            recursive,
+            force,
+            cached,
+            ignore_unmatch,
+            dry_run,
+            quiet,
+            path,
+        } => {
+            let opts = RmOptions {
+                recursive,
+                force,
+                cached,
+                ignore_unmatch,
+                dry_run,
+                quiet,
+            };
+            rm(&path, &opts).unwrap();
+        }
+        Commands::Describe { tags, abbrev, rev } => {
+            describe(tags, abbrev.len(None), &rev).unwrap();
+        }
+        Commands::Diff {
+            staged,
+            context,
+            no_ext_diff,
+            commits,
+            ignore_submodules,
+            raw,
+            abbrev,
+        } => {
+            diff(
+                staged,
+                context,
+                &commits,
+                ignore_submodules.mode(),
+                !no_ext_diff,
+                raw,
+                abbrev.len(None),
+            )
+            .unwrap();
+        }
+        Commands::Difftool {
+            staged,
+            tool,
+            dir_diff,
+            no_prompt,
+            prompt,
+            commits,
+        } => {
+            let opts = DifftoolOptions {
+                staged,
+                tool,
+                dir_diff,
+                prompt: match (prompt, no_prompt) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                },
+            };
+            difftool(&commits, &opts).unwrap();
+        }
+        Commands::Grep {
+            cached: _,
+            ignore_case,
+            line_number,
+            pattern,
+            tree_ish,
+        } => {
+            grep(&pattern, tree_ish.as_deref(), ignore_case, line_number).unwrap();
+        }
+        Commands::CountObjects {
+            verbose,
+            human_readable,
+        } => {
+            count_objects(verbose, human_readable).unwrap();
+        }
+        Commands::Fsck { lost_found } => {
+            fsck(lost_found).unwrap();
+        }
+        Commands::Bisect { action, revs } => match action {
+            BisectAction::Start => bisect_start(&revs).unwrap(),
+            BisectAction::Bad => bisect_mark("bad", &revs).unwrap(),
+            BisectAction::Good => bisect_mark("good", &revs).unwrap(),
+            BisectAction::Reset => bisect_reset().unwrap(),
+        },
+        Commands::Am {
+            three_way,
+            abort,
+            cont,
+            skip,
+            mbox,
+        } => {
+            exit_on_error(am(&mbox, three_way, abort, cont, skip));
+        }
+        Commands::Apply {
+            index,
+            cached,
+            check,
+            reverse,
+            context,
+            three_way,
+            patch,
+        } => {
+            let opts = ApplyOptions {
+                index,
+                cached,
+                check,
+                reverse,
+                min_context: context,
+                three_way,
+            };
+            apply(&patch, &opts).unwrap();
+        }
+        Commands::Archive {
+            format,
+            output,
+            tree_ish,
+        } => {
+            archive(format.map(|f| f.format()), output.as_deref(), &tree_ish).unwrap();
+        }
+        Commands::Blame {
+            range,
+            ignore_whitespace,
+            incremental,
+            path,
+            rev,
+        } => {
+            blame(
+                &path,
+                range.as_deref(),
+                &rev,
+                ignore_whitespace,
+                incremental,
+            )
+            .unwrap();
+        }
+        Commands::Bundle {
+            action,
+            quiet,
+            file,
+            revs,
+        } => match action {
+            BundleAction::Create => bundle_create(&file, &revs).unwrap(),
+            BundleAction::Verify => bundle_verify(&file, quiet).unwrap(),
+            BundleAction::ListHeads => bundle_list_heads(&file).unwrap(),
+            BundleAction::Unbundle => bundle_unbundle(&file).unwrap(),
+        },
+        Commands::Branch {
+            delete,
+            force_delete,
+            contains,
+            merged,
+            no_merged,
+            verbose,
+            abbrev,
+            name,
+            start_point,
+        } => {
+            let filter = BranchFilter {
+                contains,
+                merged,
+                no_merged,
+            };
+            branch(
+                name,
+                &start_point,
+                delete,
+                force_delete,
+                &filter,
+                verbose,
+                abbrev.len(None),
+            )
+            .unwrap();
+        }
+        Commands::Index { action } => {
+            index(matches!(action, IndexAction::Rebuild)).unwrap();
+        }
+        Commands::CommitGraph { action } => {
+            let write = matches!(action, CommitGraphAction::Write);
+            commit_graph(write, !write).unwrap();
+        }
+        Commands::CommitTree {
+            tree,
+            parents,
+            messages,
+            file,
+        } => {
+            commit_tree(&tree, &parents, &messages, file.as_ref()).unwrap();
+        }
+        Commands::Maintenance {
+            action,
+            auto,
+            registered,
+            tasks,
+            scheduler,
+        } => match action {
+            MaintenanceAction::Start => maintenance_start(&scheduler).unwrap(),
+            MaintenanceAction::Stop => maintenance_stop().unwrap(),
+            MaintenanceAction::Register => maintenance_register().unwrap(),
+            MaintenanceAction::Unregister => maintenance_unregister().unwrap(),
+            MaintenanceAction::Run => maintenance_run(&tasks, auto, registered).unwrap(),
+        },
+        Commands::Merge { commit } => {
+            exit_on_error(merge(&commit));
+        }
+        Commands::MergeBase {
+            all,
+            is_ancestor,
+            commits,
+        } => match (is_ancestor, commits.as_slice()) {
+            (true, [ancestor, descendant]) => {
+                if !merge_base_is_ancestor(ancestor, descendant).unwrap() {
+                    std::process::exit(1);
+                }
+            }
+            (true, _) => Cli::command()
+                .error(
+                    ErrorKind::WrongNumberOfValues,
+                    "--is-ancestor takes exactly two commits",
+                )
+                .exit(),
+            (false, _) => merge_base(&commits, all).unwrap(),
+        },
+        Commands::NameRev {
+            tags,
+            name_only,
+            revs,
+        } => {
+            name_rev(&revs, tags, name_only).unwrap();
+        }
+        Commands::Notes {
+            action,
+            messages,
+            force,
+            object,
+        } => {
+            let target = object.as_deref().unwrap_or("HEAD");
+            match action {
+                NotesAction::Add => notes_add(&messages, force, target).unwrap(),
+                NotesAction::Show => notes_show(target).unwrap(),
+                NotesAction::List => notes_list(object.as_deref()).unwrap(),
+                NotesAction::Remove => notes_remove(target).unwrap(),
+            }
+        }
+        Commands::Pull {
+            rebase,
+            remote,
+            branches,
+        } => {
+            exit_on_error(pull(remote.as_deref(), &branches, rebase));
+        }
+        Commands::Config {
+            global,
+            local,
+            get,
+            unset,
+            list,
+            key,
+            value,
+        } => {
+            let opts = ConfigOptions {
+                global,
+                local,
+                get,
+                unset,
+                list,
+            };
+            config(key.as_deref(), value.as_deref(), &opts).unwrap();
+        }
+        Commands::Remote {
+            action,
+            verbose,
+            push,
+            name,
+            target,
+        } => {
+            let name = name.unwrap_or_default();
+            let target = target.unwrap_or_default();
+            match action {
+                None => remote_list(verbose).unwrap(),
+                Some(RemoteAction::Add) => remote_add(&name, &target).unwrap(),
+                Some(RemoteAction::Remove) => remote_remove(&name).unwrap(),
+                Some(RemoteAction::Rename) => remote_rename(&name, &target).unwrap(),
+                Some(RemoteAction::SetUrl) => remote_set_url(&name, &target, push).unwrap(),
+                Some(RemoteAction::Show) => remote_show(&name).unwrap(),
+            }
+        }
+        Commands::Push {
+            remote,
+            refspecs,
+            force,
+        } => {
+            push(remote.as_deref(), &refspecs, force).unwrap();
+        }
+        Commands::RangeDiff {
+            creation_factor,
+            ranges,
+        } => {
+            range_diff(&ranges, creation_factor).unwrap();
+        }
+        Commands::ReadTree {
+            merge,
+            prefix,
+            trees,
+        } => {
+            read_tree(&trees, merge, prefix.as_deref()).unwrap();
+        }
+        Commands::Rebase {
+            abort,
+            cont,
+            committer_date_is_author_date,
+            ignore_date,
+            autosquash,
+            upstream,
+        } => {
+            let dates = pick_dates(committer_date_is_author_date, ignore_date);
+            exit_on_error(rebase(upstream.as_deref(), abort, cont, dates, autosquash));
+        }
+        Commands::Reflog { action, refname } => match action {
+            Some(ReflogAction::Show { refname }) => reflog(refname.as_deref()).unwrap(),
+            Some(ReflogAction::Expire {
+                expire,
+                expire_unreachable,
+                all,
+                dry_run,
+                refs,
+            }) => reflog_expire(
+                &refs,
+                all,
+                expire.as_deref(),
+                expire_unreachable.as_deref(),
+                dry_run,
+            )
+            .unwrap(),
+            None => reflog(refname.as_deref()).unwrap(),
+        },
+        Commands::Rerere { action } => {
+            let action = match action {
+                None => RerereAction::Record,
+                Some(RerereCommand::Status) => RerereAction::Status,
+                Some(RerereCommand::Clear) => RerereAction::Clear,
+                Some(RerereCommand::Gc) => RerereAction::Gc,
+            };
+            rerere(action).unwrap();
+        }
+        Commands::Reset {
+            soft,
+            mixed: _,
+            hard,
+            args,
+        } => {
+            let mode = if soft {
+                ResetMode::Soft
+            } else if hard {
+                ResetMode::Hard
+            } else {
+                ResetMode::Mixed
+            };
+            reset(mode, &args).unwrap();
+        }
+        Commands::Restore {
+            source,
+            staged,
+            worktree,
+            path,
+        } => {
+            restore(&path, source.as_deref(), staged, worktree).unwrap();
+        }
+        Commands::Serve {
+            http,
+            listen,
+            auth,
+            root,
+        } => {
+            serve(http, &root, &listen, auth.as_deref()).unwrap();
+        }
+        Commands::Switch {
+            create,
+            detach,
+            branch,
+        } => {
+            switch(&branch, create, detach).unwrap();
         }
     }
+    profile_report();
 }
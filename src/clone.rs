@@ -0,0 +1,460 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    checkout_tree, fetch_pack, fetch_pack_receive, fetch_pack_receive_deepen, file_write_locked,
+    gc_auto, head_attach, head_detach, pack_unpack, reflog_append, repo_create, repo_file,
+    repo_open, symref_write, url_rewrite, FetchDepth, GitUrl, RemoteRefs, Repository, Transport,
+    UrlScheme,
+};
+
+/// Clones the repository at `url` into `dir`, which defaults to the last
+/// component of the URL without its `.git` suffix.
+pub fn clone(url: &str, dir: Option<&Path>) -> Result<()> {
+    let dir = match dir {
+        Some(dir) => dir.to_path_buf(),
+        None => PathBuf::from(clone_dir_name(url)),
+    };
+    if dir.exists() && (!dir.is_dir() || fs::read_dir(&dir)?.next().is_some()) {
+        bail!(
+            "destination path '{}' already exists and is not an empty directory.",
+            dir.display()
+        );
+    }
+
+    let mut transport = Transport::open(&url_rewrite(None, url, false), None)?;
+    // Local sources are recorded by absolute path, so the clone can move.
+    // URLs are recorded as given, to be rewritten again on each use.
+    let url = match &transport {
+        Transport::Local(source) if GitUrl::parse(url)?.scheme == UrlScheme::Local => {
+            source.worktree.display().to_string()
+        }
+        Transport::Bundle(_) => fs::canonicalize(&GitUrl::parse(url)?.path)?
+            .display()
+            .to_string(),
+        _ => url.to_string(),
+    };
+    eprintln!("Cloning into '{}'...", dir.display());
+
+    let remote = transport.advertise("git-upload-pack")?;
+
+    let existed = dir.exists();
+    let mut interrupted = false;
+    let result = repo_create(dir.clone())
+        .and_then(|_| repo_open(&dir))
+        .and_then(|mut repo| {
+            clone_state_write(&repo, &url, &remote)?;
+            if let Err(e) = clone_fetch(&repo, &mut transport, &remote, CLONE_DEPTH_STEP) {
+                interrupted = true;
+                return Err(e);
+            }
+            clone_finish(&mut repo, &remote, &url)?;
+            gc_auto(&repo);
+            Ok(())
+        });
+    if result.is_err() && interrupted {
+        eprintln!(
+            "hint: The transfer was interrupted; 'rit clone --resume {}' picks it up\n\
+             hint: where it stopped.",
+            dir.display()
+        );
+    } else if result.is_err() {
+        // Don't leave a half-made clone behind.
+        let _ = match existed {
+            true => fs::remove_dir_all(dir.join(".git")),
+            false => fs::remove_dir_all(&dir),
+        };
+    }
+    result
+}
+
+/// Finishes the clone in `dir` that was interrupted while fetching, asking
+/// for the refs negotiated then and only for what was not received yet.
+pub fn clone_resume(dir: &Path) -> Result<()> {
+    let mut repo = repo_open(dir)?;
+    let state = clone_state_read(&repo)?
+        .ok_or_else(|| anyhow!("no interrupted clone to resume in '{}'", dir.display()))?;
+    let mut transport = Transport::open(&url_rewrite(None, &state.url, false), None)?;
+    eprintln!("Resuming clone into '{}'...", dir.display());
+    clone_fetch(&repo, &mut transport, &state.remote, CLONE_DEPTH_STEP)?;
+    clone_finish(&mut repo, &state.remote, &state.url)?;
+    gc_auto(&repo);
+    Ok(())
+}
+
+/// How many tips are asked for at a time after the first. Each batch's pack
+/// is a checkpoint `clone_resume` starts again from.
+const CLONE_BATCH: usize = 16;
+
+/// How many commits deeper each step through the first tip's history goes,
+/// when the remote can send history to a depth. Each step is a checkpoint
+/// too, so a long history is not one pack that must arrive whole.
+const CLONE_DEPTH_STEP: usize = 1000;
+
+/// The state of a clone in progress, kept in $GIT_DIR/clone-resume until
+/// it is done: where from, the refs the remote advertised, and the tips
+/// whose objects have been received, or also unpacked when `done`. While
+/// the first tip's history comes in steps, `depth` is how far it reaches
+/// and `shallow` the commits whose parents are still missing.
+struct CloneState {
+    url: String,
+    remote: RemoteRefs,
+    received: Vec<String>,
+    done: Vec<String>,
+    depth: usize,
+    shallow: Vec<String>,
+}
+
+fn clone_state_write(repo: &Repository, url: &str, remote: &RemoteRefs) -> Result<()> {
+    let mut state = format!("url {}\n", url);
+    for cap in &remote.caps {
+        state.push_str(&format!("cap {}\n", cap));
+    }
+    for (name, sha) in &remote.refs {
+        state.push_str(&format!("ref {} {}\n", sha, name));
+    }
+    let path = repo_file(repo, PathBuf::from("clone-resume"), false)?;
+    file_write_locked(&path, state.as_bytes(), false)
+}
+
+fn clone_state_read(repo: &Repository) -> Result<Option<CloneState>> {
+    let path = repo_file(repo, PathBuf::from("clone-resume"), false)?;
+    let Ok(data) = fs::read_to_string(path) else {
+        return Ok(None);
+    };
+    let mut state = CloneState {
+        url: String::new(),
+        remote: RemoteRefs {
+            refs: Vec::new(),
+            caps: Vec::new(),
+        },
+        received: Vec::new(),
+        done: Vec::new(),
+        depth: 0,
+        shallow: Vec::new(),
+    };
+    for line in data.lines() {
+        match line.split_once(' ') {
+            Some(("url", url)) => state.url = url.to_string(),
+            Some(("cap", cap)) => state.remote.caps.push(cap.to_string()),
+            Some(("ref", r)) => {
+                let (sha, name) = r
+                    .split_once(' ')
+                    .ok_or_else(|| anyhow!("bad ref in clone-resume: {}", r))?;
+                state.remote.refs.push((name.to_string(), sha.to_string()));
+            }
+            Some(("received", sha)) => state.received.push(sha.to_string()),
+            Some(("done", sha)) => state.done.push(sha.to_string()),
+            Some(("shallow", sha)) => state.shallow.push(sha.to_string()),
+            Some(("unshallow", sha)) => state.shallow.retain(|s| s != sha),
+            Some(("deepened", n)) => state.depth = n.parse()?,
+            _ => bail!("bad line in clone-resume: {}", line),
+        }
+    }
+    Ok(Some(state))
+}
+
+/// Records that the objects of the tips `shas` have been `what`.
+fn clone_state_append(repo: &Repository, what: &str, shas: &[String]) -> Result<()> {
+    let path = repo_file(repo, PathBuf::from("clone-resume"), false)?;
+    let mut f = fs::OpenOptions::new().append(true).open(path)?;
+    for sha in shas {
+        writeln!(f, "{} {}", what, sha)?;
+    }
+    Ok(())
+}
+
+/// Fetches the objects of the remote's HEAD, branches and tags. HEAD's
+/// history comes first on its own, `depth_step` commits at a time when the
+/// remote allows, the other tips in batches after it. Each pack is kept in
+/// $GIT_DIR/clone-pack until it is unpacked and the clone's state says so.
+/// What an earlier, interrupted attempt got is not asked for again.
+fn clone_fetch(
+    repo: &Repository,
+    transport: &mut Transport,
+    remote: &RemoteRefs,
+    depth_step: usize,
+) -> Result<()> {
+    let mut wants: Vec<String> = Vec::new();
+    for (name, sha) in &remote.refs {
+        if (name == "HEAD" || name.starts_with("refs/heads/") || name.starts_with("refs/tags/"))
+            && !name.ends_with("^{}")
+            && !wants.contains(sha)
+        {
+            wants.push(sha.clone());
+        }
+    }
+    if let Transport::Bundle(_) = transport {
+        return fetch_pack(repo, transport, &wants, &[]);
+    }
+
+    let state = clone_state_read(repo)?.ok_or_else(|| anyhow!("clone state is missing"))?;
+    let mut done = state.done;
+    let pack_path = repo_file(repo, PathBuf::from("clone-pack"), false)?;
+    if pack_path.is_file() {
+        // Received in full before the interruption: no need to ask again.
+        pack_unpack(repo, &fs::read(&pack_path)?)?;
+        let received: Vec<String> = state
+            .received
+            .into_iter()
+            .filter(|sha| !done.contains(sha))
+            .collect();
+        clone_state_append(repo, "done", &received)?;
+        done.extend(received);
+        fs::remove_file(&pack_path)?;
+    }
+
+    let pending: Vec<String> = wants.into_iter().filter(|w| !done.contains(w)).collect();
+    let mut rest = &pending[..];
+    if done.is_empty() && remote.has_cap("shallow") {
+        if let Some((first, tail)) = rest.split_first() {
+            let mut depth = state.depth;
+            let mut shallow = state.shallow;
+            // Until a first step is in, there is nothing to have.
+            while depth == 0 || !shallow.is_empty() {
+                let haves = match depth {
+                    0 => Vec::new(),
+                    _ => vec![first.clone()],
+                };
+                depth += depth_step;
+                let (pack, update) = fetch_pack_receive_deepen(
+                    transport,
+                    std::slice::from_ref(first),
+                    &haves,
+                    Some(&FetchDepth {
+                        depth,
+                        shallow: &shallow,
+                    }),
+                )?;
+                file_write_locked(&pack_path, &pack, false)?;
+                clone_state_append(repo, "shallow", &update.shallow)?;
+                clone_state_append(repo, "unshallow", &update.unshallow)?;
+                clone_state_append(repo, "deepened", &[depth.to_string()])?;
+                pack_unpack(repo, &pack)?;
+                fs::remove_file(&pack_path)?;
+                shallow.retain(|sha| !update.unshallow.contains(sha));
+                shallow.extend(update.shallow);
+            }
+            clone_state_append(repo, "done", std::slice::from_ref(first))?;
+            done.push(first.clone());
+            rest = tail;
+        }
+    }
+    while !rest.is_empty() {
+        let size = match done.is_empty() {
+            true => 1,
+            false => CLONE_BATCH,
+        };
+        let (batch, tail) = rest.split_at(size.min(rest.len()));
+        let pack = fetch_pack_receive(transport, batch, &done)?;
+        file_write_locked(&pack_path, &pack, false)?;
+        clone_state_append(repo, "received", batch)?;
+        pack_unpack(repo, &pack)?;
+        clone_state_append(repo, "done", batch)?;
+        done.extend_from_slice(batch);
+        fs::remove_file(&pack_path)?;
+        rest = tail;
+    }
+    Ok(())
+}
+
+/// Configures origin, writes the refs and checks out the remote's HEAD,
+/// once all the objects are there.
+fn clone_finish(repo: &mut Repository, remote: &RemoteRefs, url: &str) -> Result<()> {
+    repo.config_set("remote \"origin\"", "url", url)?;
+    repo.config_set(
+        "remote \"origin\"",
+        "fetch",
+        "+refs/heads/*:refs/remotes/origin/*",
+    )?;
+    let message = format!("clone: from {}", url);
+    clone_refs_write(repo, remote, &message)?;
+    fs::remove_file(repo_file(repo, PathBuf::from("clone-resume"), false)?)?;
+
+    let Some(head) = remote.get("HEAD") else {
+        eprintln!("warning: You appear to have cloned an empty repository.");
+        return Ok(());
+    };
+    match remote.head_branch() {
+        Some(target) => {
+            let branch = target.trim_start_matches("refs/heads/");
+            let sha = remote.get(&target).unwrap_or(head);
+            let section = format!("branch \"{}\"", branch);
+            repo.config_set(&section, "remote", "origin")?;
+            repo.config_set(&section, "merge", &target)?;
+            symref_write(
+                repo,
+                "refs/remotes/origin/HEAD",
+                &format!("refs/remotes/origin/{}", branch),
+                None,
+            )?;
+            // Check out from the unborn branch before it gets its commit.
+            head_attach(repo, branch)?;
+            checkout_tree(repo, sha, true)?;
+            let path = repo_file(repo, PathBuf::from(&target), true)?;
+            file_write_locked(&path, format!("{}\n", sha).as_bytes(), repo.fsync.refs)?;
+            reflog_append(repo, &target, None, sha, &message)?;
+            reflog_append(repo, "HEAD", None, sha, &message)?;
+        }
+        None => {
+            checkout_tree(repo, head, true)?;
+            head_detach(repo, head)?;
+            reflog_append(repo, "HEAD", None, head, &message)?;
+        }
+    }
+    Ok(())
+}
+
+/// Records the remote's branches as remote-tracking refs and copies its tags.
+fn clone_refs_write(repo: &Repository, remote: &RemoteRefs, message: &str) -> Result<()> {
+    for (name, sha) in &remote.refs {
+        let local = if let Some(branch) = name.strip_prefix("refs/heads/") {
+            format!("refs/remotes/origin/{}", branch)
+        } else if name.starts_with("refs/tags/") && !name.ends_with("^{}") {
+            name.clone()
+        } else {
+            continue;
+        };
+        let path = repo_file(repo, PathBuf::from(&local), true)?;
+        file_write_locked(&path, format!("{}\n", sha).as_bytes(), repo.fsync.refs)?;
+        if !local.starts_with("refs/tags/") {
+            reflog_append(repo, &local, None, sha, message)?;
+        }
+    }
+    Ok(())
+}
+
+/// The directory `git clone <url>` creates: the URL's last component,
+/// without a trailing `.git` or `/.git`.
+pub fn clone_dir_name(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix("/.git").unwrap_or(url);
+    let name = url.rsplit(['/', ':']).next().unwrap_or(url);
+    name.strip_suffix(".git").unwrap_or(name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{
+        clone, clone_dir_name, clone_fetch, clone_resume, clone_state_append, clone_state_write,
+    };
+    use crate::{
+        fetch_pack_receive, fetch_pack_receive_deepen, object_read, pack_unpack, ref_create,
+        ref_resolve, repo_open,
+        test_utils::{make_commit, test_repo},
+        FetchDepth, Transport,
+    };
+
+    #[test]
+    fn test_clone() {
+        assert_eq!(clone_dir_name("http://host/org/app.git"), "app");
+        assert_eq!(clone_dir_name("../src/.git"), "src");
+        assert_eq!(clone_dir_name("git@host:app.git/"), "app");
+
+        let source = test_repo("clone-source");
+        let sha = make_commit(&source, "hello", &[]);
+        ref_create(&source, "heads/master", &sha).unwrap();
+        let dest = source
+            .worktree
+            .with_file_name(format!("rgit-clone-dest-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dest);
+
+        clone(&source.worktree.display().to_string(), Some(&dest)).unwrap();
+        let repo = repo_open(&dest).unwrap();
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(sha.clone()));
+        assert_eq!(
+            ref_resolve(&repo, "refs/remotes/origin/HEAD").unwrap(),
+            Some(sha)
+        );
+        assert_eq!(fs::read_to_string(dest.join("file")).unwrap(), "hello");
+        assert_eq!(
+            repo.config_get("branch \"master\"", "merge").as_deref(),
+            Some("refs/heads/master")
+        );
+        assert!(clone(&source.worktree.display().to_string(), Some(&dest)).is_err());
+    }
+
+    #[test]
+    fn test_clone_resume() {
+        let source = test_repo("clone-resume-source");
+        let first = make_commit(&source, "one", &[]);
+        let second = make_commit(&source, "two", &[&first]);
+        ref_create(&source, "heads/master", &first).unwrap();
+        ref_create(&source, "heads/side", &second).unwrap();
+        let url = source.worktree.display().to_string();
+
+        // Interrupted after receiving HEAD's pack, before unpacking it.
+        let dest = test_repo("clone-resume-dest");
+        let mut transport = Transport::open(&url, None).unwrap();
+        let remote = transport.advertise("git-upload-pack").unwrap();
+        clone_state_write(&dest, &url, &remote).unwrap();
+        let pack = fetch_pack_receive(&mut transport, std::slice::from_ref(&first), &[]).unwrap();
+        fs::write(dest.gitdir.join("clone-pack"), pack).unwrap();
+        clone_state_append(&dest, "received", std::slice::from_ref(&first)).unwrap();
+
+        clone_resume(&dest.worktree).unwrap();
+        let repo = repo_open(&dest.worktree).unwrap();
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(first));
+        assert_eq!(
+            ref_resolve(&repo, "refs/remotes/origin/side").unwrap(),
+            Some(second.clone())
+        );
+        assert!(object_read(&repo, &second).is_ok());
+        assert!(!dest.gitdir.join("clone-resume").exists());
+        assert!(!dest.gitdir.join("clone-pack").exists());
+        assert!(clone_resume(&dest.worktree).is_err());
+    }
+
+    #[test]
+    fn test_clone_deepen() {
+        let source = test_repo("clone-deepen-source");
+        let first = make_commit(&source, "one", &[]);
+        let second = make_commit(&source, "two", &[&first]);
+        let third = make_commit(&source, "three", &[&second]);
+        ref_create(&source, "heads/master", &third).unwrap();
+        let url = source.worktree.display().to_string();
+
+        // Interrupted after the first step through HEAD's history.
+        let dest = test_repo("clone-deepen-dest");
+        let mut transport = Transport::open(&url, None).unwrap();
+        let remote = transport.advertise("git-upload-pack").unwrap();
+        clone_state_write(&dest, &url, &remote).unwrap();
+        let head = std::slice::from_ref(&third);
+        let depth = FetchDepth {
+            depth: 1,
+            shallow: &[],
+        };
+        let (pack, update) =
+            fetch_pack_receive_deepen(&mut transport, head, &[], Some(&depth)).unwrap();
+        assert_eq!(update.shallow, vec![third.clone()]);
+        pack_unpack(&dest, &pack).unwrap();
+        clone_state_append(&dest, "shallow", head).unwrap();
+        clone_state_append(&dest, "deepened", &["1".to_string()]).unwrap();
+        assert!(object_read(&dest, &second).is_err());
+
+        // Resumed one commit at a time: only what is missing is asked for.
+        clone_fetch(&dest, &mut transport, &remote, 1).unwrap();
+        let state = fs::read_to_string(dest.gitdir.join("clone-resume")).unwrap();
+        assert!(state.contains(&format!(
+            "shallow {}\nunshallow {}\ndeepened 2\n",
+            second, third
+        )));
+        assert!(state.contains(&format!(
+            "unshallow {}\ndeepened 3\ndone {}\n",
+            second, third
+        )));
+
+        clone_resume(&dest.worktree).unwrap();
+        let repo = repo_open(&dest.worktree).unwrap();
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(third));
+        assert!(object_read(&repo, &first).is_ok());
+        assert!(!dest.gitdir.join("clone-resume").exists());
+    }
+}
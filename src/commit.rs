@@ -1,6 +1,17 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
 use indexmap::IndexMap;
 
-use crate::Object;
+use crate::{
+    branch_get_active, commit_subject, expiry_parse, gc_auto, head_update, index_read,
+    index_write_tree, object_find, object_read, object_write, ref_resolve, repo_file, repo_find,
+    rerere_finish, sha_short, stripspace_text, trailers_parse, Object, Repository, Trailer,
+};
 
 /// We use an IndexMap to preserve insertion order. Keys are of type Option<Vec<u8>>:
 /// - Some(key) holds header fields (like "tree", "parent", "author", etc.).
@@ -17,9 +28,10 @@ pub fn kvlm_parse(raw: &[u8]) -> Kvlm {
         let spc_rel = raw[pos..].iter().position(|&b| b == b' ');
         let nl_rel = raw[pos..].iter().position(|&b| b == b'\n');
         if spc_rel.is_none() || (nl_rel.is_some() && nl_rel.unwrap() < spc_rel.unwrap()) {
-            assert_eq!(spc_rel, nl_rel);
+            assert_eq!(nl_rel, Some(0));
             let msg = raw[pos + 1..].to_vec();
             dict.insert(None, vec![msg]);
+            break;
         }
 
         let spc = pos + spc_rel.unwrap();
@@ -123,6 +135,531 @@ impl Commit {
         let kvlm = kvlm_parse(data);
         Self { kvlm }
     }
+
+    /// The sha of the tree this commit records.
+    pub fn tree(&self) -> Option<String> {
+        kvlm_get_str(&self.kvlm, b"tree")
+    }
+
+    /// The committer timestamp, in seconds since the epoch.
+    pub fn commit_time(&self) -> i64 {
+        kvlm_get_str(&self.kvlm, b"committer")
+            .and_then(|c| ident_parse(&c).map(|(_, time, _)| time))
+            .unwrap_or(0)
+    }
+
+    /// The commit message, including its trailing newline.
+    pub fn message(&self) -> String {
+        self.kvlm
+            .get(&None)
+            .and_then(|v| v.first())
+            .map(|m| String::from_utf8_lossy(m).to_string())
+            .unwrap_or_default()
+    }
+
+    /// The trailers at the end of the message, such as Signed-off-by.
+    pub fn trailers(&self) -> Vec<Trailer> {
+        trailers_parse(&self.message(), None)
+    }
+
+    /// Who wrote the change, and when.
+    pub fn author(&self) -> Option<Signature> {
+        Signature::parse(&kvlm_get_str(&self.kvlm, b"author")?)
+    }
+
+    /// Who made the commit, and when.
+    pub fn committer(&self) -> Option<Signature> {
+        Signature::parse(&kvlm_get_str(&self.kvlm, b"committer")?)
+    }
+
+    /// The shas of all parents, in order.
+    pub fn parents(&self) -> Vec<String> {
+        self.kvlm
+            .get(&Some(b"parent".to_vec()))
+            .map(|v| {
+                v.iter()
+                    .map(|p| String::from_utf8_lossy(p).to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// An identity line of a commit or tag, such as its author.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the epoch.
+    pub time: i64,
+    /// The offset from UTC it was made at, like "+0100".
+    pub tz: String,
+}
+
+impl Signature {
+    /// Parses "Name <email> 1262304123 +0100".
+    pub fn parse(ident: &str) -> Option<Self> {
+        let (who, time, tz) = ident_parse(ident)?;
+        let (name, email) = who.split_once('<')?;
+        Some(Self {
+            name: name.trim_end().to_string(),
+            email: email.strip_suffix('>')?.to_string(),
+            time,
+            tz: tz.to_string(),
+        })
+    }
+}
+
+/// Splits an identity line like "Name <email> 1262304123 +0100" into the
+/// person, the unix timestamp and the timezone offset.
+pub fn ident_parse(ident: &str) -> Option<(&str, i64, &str)> {
+    let mut parts = ident.rsplitn(3, ' ');
+    let tz = parts.next()?;
+    let time = parts.next()?.parse().ok()?;
+    let who = parts.next()?;
+    Some((who, time, tz))
+}
+
+/// Formats a timestamp in its own timezone like git's iso date, e.g.
+/// "2010-01-01 01:02:03 +0100".
+pub fn date_format_iso(time: i64, tz: &str) -> String {
+    let d = date_local(time, tz);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+        d.year,
+        d.month,
+        d.day,
+        d.secs / 3600,
+        d.secs % 3600 / 60,
+        d.secs % 60,
+        tz
+    )
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats a timestamp in its own timezone like git's default date, e.g.
+/// "Fri Jan 1 01:02:03 2010 +0100".
+pub fn date_format_default(time: i64, tz: &str) -> String {
+    let d = date_local(time, tz);
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {}",
+        WEEKDAYS[d.weekday()],
+        MONTHS[d.month as usize - 1],
+        d.day,
+        d.secs / 3600,
+        d.secs % 3600 / 60,
+        d.secs % 60,
+        d.year,
+        tz
+    )
+}
+
+/// Formats a timestamp in its own timezone as mail headers carry it, e.g.
+/// "Fri, 1 Jan 2010 01:02:03 +0100".
+pub fn date_format_rfc2822(time: i64, tz: &str) -> String {
+    let d = date_local(time, tz);
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} {}",
+        WEEKDAYS[d.weekday()],
+        d.day,
+        MONTHS[d.month as usize - 1],
+        d.year,
+        d.secs / 3600,
+        d.secs % 3600 / 60,
+        d.secs % 60,
+        tz
+    )
+}
+
+/// A timestamp broken down in the timezone `tz`.
+pub struct LocalDate {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    /// Days since the epoch.
+    pub days: i64,
+    /// Seconds since midnight.
+    pub secs: i64,
+}
+
+impl LocalDate {
+    /// The day of the week, 0 for Sunday.
+    pub fn weekday(&self) -> usize {
+        // The epoch was a Thursday.
+        (self.days + 4).rem_euclid(7) as usize
+    }
+}
+
+pub fn date_local(time: i64, tz: &str) -> LocalDate {
+    let sign = if tz.starts_with('-') { -1 } else { 1 };
+    let digits = tz.trim_start_matches(['+', '-']);
+    let offset = match (digits.get(..2), digits.get(2..4)) {
+        (Some(h), Some(m)) => {
+            sign * (h.parse::<i64>().unwrap_or(0) * 3600 + m.parse::<i64>().unwrap_or(0) * 60)
+        }
+        _ => 0,
+    };
+    let local = time + offset;
+    let (days, secs) = (local.div_euclid(86400), local.rem_euclid(86400));
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    LocalDate {
+        year,
+        month,
+        day,
+        days,
+        secs,
+    }
+}
+
+/// Parses an RFC 2822 date as mail headers carry it, e.g. "Mon, 17 Sep
+/// 2001 10:00:00 +0200", into a timestamp and its timezone.
+pub fn date_parse_rfc2822(date: &str) -> Option<(i64, String)> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let date = date.split_once(',').map_or(date, |(_, rest)| rest);
+    let mut words = date.split_whitespace();
+    let day: i64 = words.next()?.parse().ok()?;
+    let month = words.next()?.to_lowercase();
+    let month = MONTHS.iter().position(|m| month.starts_with(m))? as i64 + 1;
+    let year: i64 = words.next()?.parse().ok()?;
+    let mut clock = words.next()?.split(':').map(|n| n.parse::<i64>());
+    let (hour, minute) = (clock.next()?.ok()?, clock.next()?.ok()?);
+    let second = clock.next().map_or(Some(0), |n| n.ok())?;
+    let tz = match words.next() {
+        Some(tz)
+            if tz.len() == 5
+                && tz.starts_with(['+', '-'])
+                && tz[1..].bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            tz.to_string()
+        }
+        _ => "+0000".to_string(),
+    };
+
+    let days = date_days_from_civil(year, month, day);
+    let sign = if tz.starts_with('-') { -1 } else { 1 };
+    let offset = sign
+        * (tz.get(1..3)?.parse::<i64>().ok()? * 3600 + tz.get(3..5)?.parse::<i64>().ok()? * 60);
+    let time = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some((time, tz))
+}
+
+/// Days since the epoch from a civil date (Howard Hinnant's algorithm).
+fn date_days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Parses a date as `--since` and `--until` take it: "2.weeks.ago" (or
+/// "2 weeks ago") and "now", a timestamp like "@1262304123", an RFC 2822
+/// date, or "2010-01-01" with an optional "12:30[:00]" time, read as UTC.
+pub fn date_parse_approx(value: &str, now: SystemTime) -> Option<i64> {
+    let value = value.trim();
+    if let Some(secs) = value.strip_prefix('@') {
+        return secs.parse().ok();
+    }
+    if let Some((time, _)) = date_parse_rfc2822(value) {
+        return Some(time);
+    }
+    let (date, clock) = value.split_once(['T', ' ']).unwrap_or((value, "00:00"));
+    if date.len() == 10 && date.as_bytes()[4] == b'-' {
+        let mut ymd = date.split('-').map(|n| n.parse::<i64>().ok());
+        let (year, month, day) = (ymd.next()??, ymd.next()??, ymd.next()??);
+        let mut hms = clock.split(':').map(|n| n.parse::<i64>().ok());
+        let (hour, minute) = (hms.next()??, hms.next()??);
+        let second = hms.next().unwrap_or(Some(0))?;
+        let days = date_days_from_civil(year, month, day);
+        return Some(days * 86400 + hour * 3600 + minute * 60 + second);
+    }
+    let time = expiry_parse(&value.replace(' ', "."), now).ok()??;
+    Some(time.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64)
+}
+
+/// Parses a date as GIT_AUTHOR_DATE and GIT_COMMITTER_DATE take it into a
+/// timestamp and its timezone: git's own "1262304123 +0100" (optionally
+/// with a leading '@'), an RFC 2822 date, or an ISO 8601 one like
+/// "2010-01-01T01:02:03+01:00", read as UTC without a timezone.
+pub fn date_parse_ident(value: &str) -> Option<(i64, String)> {
+    let value = value.trim();
+    let tz_valid = |tz: &str| {
+        tz.len() == 5 && tz.starts_with(['+', '-']) && tz[1..].bytes().all(|b| b.is_ascii_digit())
+    };
+    // An RFC 2822 date without its weekday starts with a number too.
+    let (secs, tz) = value.split_once(' ').unwrap_or((value, "+0000"));
+    if let Ok(time) = secs.trim_start_matches('@').parse::<i64>() {
+        if tz_valid(tz) {
+            return Some((time, tz.to_string()));
+        }
+    }
+    if value.starts_with(|c: char| c.is_ascii_digit()) && value.get(4..5) == Some("-") {
+        return date_parse_iso8601(value);
+    }
+    date_parse_rfc2822(value).filter(|(_, tz)| tz_valid(tz))
+}
+
+/// The current time as an identity line carries it, e.g. "1262304123
+/// +0100", in the local timezone.
+pub fn date_now() -> Result<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    Ok(format!("{} {}", now, date_local_offset(now)))
+}
+
+/// The offset of the local timezone from UTC at `time`, as "+HHMM", taking
+/// daylight saving time into account. UTC if it cannot be told.
+pub fn date_local_offset(time: i64) -> String {
+    let time = time as libc::time_t;
+    // SAFETY: an all-zero `tm` is valid, and localtime_r only writes to
+    // the `tm` it is given.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let found = unsafe { !libc::localtime_r(&time, &mut tm).is_null() };
+    let offset = if found { tm.tm_gmtoff as i64 } else { 0 };
+    let sign = if offset < 0 { '-' } else { '+' };
+    let minutes = offset.abs() / 60;
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Parses "YYYY-MM-DD[T| ]HH:MM[:SS]" followed by "Z", "+HH", "+HHMM" or
+/// "+HH:MM", perhaps after a space, or by nothing for UTC.
+fn date_parse_iso8601(value: &str) -> Option<(i64, String)> {
+    let (date, rest) = (value.get(..10)?, value.get(10..)?);
+    let rest = rest.strip_prefix(['T', ' '])?;
+    let mut ymd = date.split('-').map(|n| n.parse::<i64>().ok());
+    let (year, month, day) = (ymd.next()??, ymd.next()??, ymd.next()??);
+    let tz_at = rest.find(['Z', '+', '-', ' ']).unwrap_or(rest.len());
+    let (clock, tz) = (&rest[..tz_at], rest[tz_at..].trim());
+    let mut hms = clock.split(':').map(|n| n.parse::<i64>().ok());
+    let (hour, minute) = (hms.next()??, hms.next()??);
+    let second = hms.next().unwrap_or(Some(0))?;
+    if hms.next().is_some()
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let tz = match tz {
+        "" | "Z" => "+0000".to_string(),
+        _ => {
+            let (sign, digits) = tz.split_at(1);
+            let digits = digits.replacen(':', "", 1);
+            let digits = if digits.len() == 2 {
+                digits + "00"
+            } else {
+                digits
+            };
+            if !matches!(sign, "+" | "-")
+                || digits.len() != 4
+                || !digits.bytes().all(|b| b.is_ascii_digit())
+            {
+                return None;
+            }
+            format!("{}{}", sign, digits)
+        }
+    };
+    let sign = if tz.starts_with('-') { -1 } else { 1 };
+    let offset = sign * (tz[1..3].parse::<i64>().ok()? * 3600 + tz[3..5].parse::<i64>().ok()? * 60);
+    let days = date_days_from_civil(year, month, day);
+    let time = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some((time, tz))
+}
+
+/// Returns the first value of `key` as a string, if present.
+pub fn kvlm_get_str(kvlm: &Kvlm, key: &[u8]) -> Option<String> {
+    kvlm.get(&Some(key.to_vec()))
+        .and_then(|v| v.first())
+        .map(|v| String::from_utf8_lossy(v).to_string())
+}
+
+/// Builds the identity line for `role` ("AUTHOR" or "COMMITTER") from the
+/// GIT_<role>_NAME, GIT_<role>_EMAIL and GIT_<role>_DATE variables, falling
+/// back to user.name and user.email and the current local time.
+pub fn ident_default(repo: &Repository, role: &str) -> Result<String> {
+    let var = |what: &str| std::env::var(format!("GIT_{}_{}", role, what)).ok();
+    let name = var("NAME").or_else(|| repo.config_get("user", "name"));
+    let email = var("EMAIL").or_else(|| repo.config_get("user", "email"));
+    let (Some(name), Some(email)) = (name, email) else {
+        bail!(
+            "{} identity unknown: set user.name and user.email",
+            role.to_lowercase()
+        );
+    };
+    let date = match var("DATE") {
+        Some(date) => {
+            let (time, tz) =
+                date_parse_ident(&date).ok_or_else(|| anyhow!("invalid date format: {}", date))?;
+            format!("{} {}", time, tz)
+        }
+        None => date_now()?,
+    };
+    Ok(format!("{} <{}> {}", name, email, date))
+}
+
+/// Writes a commit of `tree` with the given parents and message. The
+/// author defaults to the current identity; the committer always is.
+pub fn commit_create(
+    repo: &Repository,
+    tree: &str,
+    parents: &[String],
+    author: Option<&str>,
+    message: &str,
+) -> Result<String> {
+    commit_create_as(repo, tree, parents, author, None, message)
+}
+
+/// Like `commit_create`, with the committer also given rather than the
+/// current identity.
+pub fn commit_create_as(
+    repo: &Repository,
+    tree: &str,
+    parents: &[String],
+    author: Option<&str>,
+    committer: Option<&str>,
+    message: &str,
+) -> Result<String> {
+    let mut commit = Commit::default();
+    commit
+        .kvlm
+        .insert(Some(b"tree".to_vec()), vec![tree.as_bytes().to_vec()]);
+    if !parents.is_empty() {
+        commit.kvlm.insert(
+            Some(b"parent".to_vec()),
+            parents.iter().map(|p| p.as_bytes().to_vec()).collect(),
+        );
+    }
+    commit.kvlm.insert(
+        Some(b"author".to_vec()),
+        vec![match author {
+            Some(a) => a.as_bytes().to_vec(),
+            None => ident_default(repo, "AUTHOR")?.into_bytes(),
+        }],
+    );
+    commit.kvlm.insert(
+        Some(b"committer".to_vec()),
+        vec![match committer {
+            Some(c) => c.as_bytes().to_vec(),
+            None => ident_default(repo, "COMMITTER")?.into_bytes(),
+        }],
+    );
+    let mut message = message.to_string();
+    if !message.ends_with('\n') {
+        message.push('\n');
+    }
+    commit.kvlm.insert(None, vec![message.into_bytes()]);
+    object_write(&commit, Some(repo))
+}
+
+pub fn commit(message: Option<String>, fixup: Option<&str>, squash: Option<&str>) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let index = index_read(&repo)?;
+    let tree = index_write_tree(&repo, &index)?;
+
+    let head = ref_resolve(&repo, "HEAD")?;
+    let merge_head_path = repo_file(&repo, PathBuf::from("MERGE_HEAD"), false)?;
+    let merge_head = fs::read_to_string(&merge_head_path)
+        .ok()
+        .map(|s| s.trim().to_string());
+
+    if merge_head.is_none() {
+        if let Some(head) = &head {
+            if object_find(&repo, head, Some(b"tree"), true)?.as_deref() == Some(tree.as_str()) {
+                bail!("nothing to commit, working tree clean");
+            }
+        }
+    }
+
+    // A fixup or squash commit names the commit it amends by its subject,
+    // for `rebase --autosquash` to find; a message given goes below.
+    let marker = match (fixup, squash) {
+        (Some(target), _) => Some(("fixup", target)),
+        (_, Some(target)) => Some(("squash", target)),
+        _ => None,
+    };
+    let message = match (marker, message) {
+        (Some((kind, target)), message) => {
+            let sha = object_find(&repo, target, Some(b"commit"), true)?
+                .ok_or_else(|| anyhow!("could not lookup commit '{}'", target))?;
+            let subject = commit_subject(&repo, &sha)?;
+            match message {
+                Some(m) => format!("{}! {}\n\n{}", kind, subject, m),
+                None => format!("{}! {}\n", kind, subject),
+            }
+        }
+        (None, Some(m)) => m,
+        // Concluding a merge, cherry-pick or revert reuses its prepared
+        // message.
+        (None, None) => fs::read_to_string(repo_file(&repo, PathBuf::from("MERGE_MSG"), false)?)
+            .unwrap_or_default(),
+    };
+    let message = stripspace_text(&message, None);
+    if message.is_empty() {
+        bail!("Aborting commit due to empty commit message.");
+    }
+    // A conflicted cherry-pick keeps the author of the picked commit.
+    let author =
+        match fs::read_to_string(repo_file(&repo, PathBuf::from("CHERRY_PICK_HEAD"), false)?) {
+            Ok(picked) => object_read(&repo, picked.trim())?
+                .as_any()
+                .downcast_ref::<Commit>()
+                .and_then(|c| kvlm_get_str(&c.kvlm, b"author")),
+            Err(_) => None,
+        };
+    let parents: Vec<String> = head.iter().chain(merge_head.iter()).cloned().collect();
+    let sha = commit_create(&repo, &tree, &parents, author.as_deref(), &message)?;
+    let kind = match (&head, &merge_head) {
+        (None, _) => "commit (initial)",
+        (_, Some(_)) => "commit (merge)",
+        _ => "commit",
+    };
+    let subject = message.lines().next().unwrap_or("");
+    head_update(&repo, &sha, &format!("{}: {}", kind, subject))?;
+
+    rerere_finish(&repo)?;
+    for state in ["MERGE_HEAD", "MERGE_MSG", "CHERRY_PICK_HEAD", "REVERT_HEAD"] {
+        let path = repo_file(&repo, PathBuf::from(state), false)?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let branch = branch_get_active(&repo)?.unwrap_or_else(|| "detached HEAD".to_string());
+    let root = if parents.is_empty() {
+        " (root-commit)"
+    } else {
+        ""
+    };
+    println!(
+        "[{}{} {}] {}",
+        branch,
+        root,
+        sha_short(&repo, &sha),
+        message.lines().next().unwrap_or_default()
+    );
+    gc_auto(&repo);
+    Ok(())
 }
 
 impl Object for Commit {
@@ -141,7 +678,123 @@ impl Object for Commit {
 
 #[cfg(test)]
 mod tests {
-    use crate::commit::continuation_line_optmize;
+    use std::{
+        fs,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{
+        commit::{
+            continuation_line_optmize, date_format_default, date_format_iso, date_local_offset,
+            date_parse_approx, date_parse_ident, ident_default, Signature,
+        },
+        test_utils::test_repo,
+    };
+
+    #[test]
+    fn test_date_format_iso() {
+        assert_eq!(
+            date_format_iso(1262304123, "+0100"),
+            "2010-01-01 01:02:03 +0100"
+        );
+        assert_eq!(
+            date_format_iso(951782400, "+0000"),
+            "2000-02-29 00:00:00 +0000"
+        );
+        assert_eq!(date_format_iso(0, "-0130"), "1969-12-31 22:30:00 -0130");
+    }
+
+    #[test]
+    fn test_date_format_default() {
+        assert_eq!(
+            date_format_default(1262304123, "+0100"),
+            "Fri Jan 1 01:02:03 2010 +0100"
+        );
+        assert_eq!(
+            date_format_default(0, "-0130"),
+            "Wed Dec 31 22:30:00 1969 -0130"
+        );
+    }
+
+    #[test]
+    fn test_signature_parse() {
+        assert_eq!(
+            Signature::parse("A U Thor <a@example.com> 1262304123 +0100"),
+            Some(Signature {
+                name: "A U Thor".to_string(),
+                email: "a@example.com".to_string(),
+                time: 1262304123,
+                tz: "+0100".to_string(),
+            })
+        );
+        assert_eq!(Signature::parse("A U Thor 1262304123 +0100"), None);
+    }
+
+    #[test]
+    fn test_date_parse_approx() {
+        let now = UNIX_EPOCH + Duration::from_secs(1262304123);
+        assert_eq!(date_parse_approx("@1000", now), Some(1000));
+        assert_eq!(date_parse_approx("2010-01-01", now), Some(1262304000));
+        assert_eq!(
+            date_parse_approx("2010-01-01 01:02:03", now),
+            Some(1262307723)
+        );
+        assert_eq!(
+            date_parse_approx("Fri, 1 Jan 2010 01:02:03 +0100", now),
+            Some(1262304123)
+        );
+        assert_eq!(date_parse_approx("2 hours ago", now), Some(1262296923));
+        assert_eq!(date_parse_approx("now", now), Some(1262304123));
+        assert_eq!(date_parse_approx("someday", now), None);
+    }
+
+    #[test]
+    fn test_date_parse_ident() {
+        let parsed = |time: i64, tz: &str| Some((time, tz.to_string()));
+        assert_eq!(
+            date_parse_ident("1262304123 +0100"),
+            parsed(1262304123, "+0100")
+        );
+        assert_eq!(date_parse_ident("@1262304123"), parsed(1262304123, "+0000"));
+        assert_eq!(
+            date_parse_ident("Fri, 1 Jan 2010 01:02:03 +0100"),
+            parsed(1262304123, "+0100")
+        );
+        assert_eq!(
+            date_parse_ident("2010-01-01T01:02:03+01:00"),
+            parsed(1262304123, "+0100")
+        );
+        assert_eq!(
+            date_parse_ident("2010-01-01 00:02:03 -0100"),
+            parsed(1262307723, "-0100")
+        );
+        assert_eq!(
+            date_parse_ident("2010-01-01T00:02:03Z"),
+            parsed(1262304123, "+0000")
+        );
+        assert_eq!(
+            date_parse_ident("12 Jan 2010 10:00:00 +0100"),
+            parsed(1263286800, "+0100")
+        );
+        assert_eq!(
+            date_parse_ident("Tue, 12 Jan 2010 10:00:00 +0é0"),
+            parsed(1263290400, "+0000")
+        );
+        assert_eq!(date_parse_ident("yesterday"), None);
+        assert_eq!(date_parse_ident("1262304123 CET"), None);
+        assert_eq!(date_parse_ident("2010-13-01T00:00:00"), None);
+    }
+
+    #[test]
+    fn test_ident_default_local_offset() {
+        let repo = test_repo("ident-offset");
+        let ident = ident_default(&repo, "COMMITTER").unwrap();
+        let mut date = ident.rsplitn(3, ' ');
+        let (tz, time) = (date.next().unwrap(), date.next().unwrap());
+        assert_eq!(tz, date_local_offset(time.parse().unwrap()));
+        assert!(tz.len() == 5 && tz.starts_with(['+', '-']), "{}", tz);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
 
     #[test]
     fn test_continuation_line_optmize() {
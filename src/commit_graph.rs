@@ -0,0 +1,475 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    file_write_locked, object_read, ref_list_flat, ref_resolve, repo_dir, repo_file, repo_find,
+    Commit, Repository, Tag,
+};
+
+/// Generation of commits that are not in the commit-graph.
+pub const GENERATION_NUMBER_INFINITY: u64 = u64::MAX;
+
+const GRAPH_PARENT_NONE: u32 = 0x7000_0000;
+const GRAPH_EXTRA_EDGES: u32 = 0x8000_0000;
+const GRAPH_LAST_EDGE: u32 = 0x8000_0000;
+const GENERATION_OVERFLOW: u32 = 0x8000_0000;
+
+/// An in-memory copy of `.git/objects/info/commit-graph`. Commits are
+/// addressed by their position in the sorted oid list.
+pub struct CommitGraph {
+    pub oids: Vec<String>,
+    pub trees: Vec<String>,
+    pub parents: Vec<Vec<u32>>,
+    pub topo_levels: Vec<u32>,
+    pub commit_times: Vec<i64>,
+    /// Corrected commit dates when the file has generation data (v2),
+    /// topological levels otherwise.
+    pub generations: Vec<u64>,
+}
+
+pub fn commit_graph(write: bool, verify: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    if write {
+        let count = commit_graph_write(&repo)?;
+        println!("Wrote commit-graph with {} commits", count);
+    }
+    if verify {
+        commit_graph_verify(&repo)?;
+    }
+    Ok(())
+}
+
+fn commit_graph_path(repo: &Repository) -> Result<PathBuf> {
+    repo_file(repo, PathBuf::from("objects/info/commit-graph"), true)
+}
+
+/// The commits every ref (and HEAD) points to, peeling tags.
+fn commit_graph_tips(repo: &Repository) -> Result<Vec<String>> {
+    let mut tips = Vec::new();
+    if let Some(sha) = ref_resolve(repo, "HEAD")? {
+        tips.push(sha);
+    }
+    if repo_dir(repo, PathBuf::from("refs"), false)?.is_some() {
+        tips.extend(ref_list_flat(repo, None, Some("refs"))?.into_values());
+    }
+
+    let mut ret = Vec::new();
+    for mut sha in tips {
+        loop {
+            let obj = match object_read(repo, &sha) {
+                Ok(obj) => obj,
+                Err(_) => break,
+            };
+            if let Some(tag) = obj.as_any().downcast_ref::<Tag>() {
+                match tag.object() {
+                    Some(target) => sha = target,
+                    None => break,
+                }
+            } else {
+                if obj.fmt() == b"commit" {
+                    ret.push(sha);
+                }
+                break;
+            }
+        }
+    }
+    Ok(ret)
+}
+
+struct GraphCommit {
+    tree: String,
+    parents: Vec<String>,
+    time: i64,
+}
+
+/// Computes the topological level (generation v1) and the corrected commit
+/// date (generation v2) of every commit. A commit's corrected date is its
+/// own date, or one more than the largest corrected date of its parents if
+/// that is later, so it always strictly increases from parent to child even
+/// when clocks are skewed.
+fn compute_generations(
+    oids: &[String],
+    commits: &HashMap<String, GraphCommit>,
+) -> (Vec<u32>, Vec<u64>) {
+    let pos: HashMap<&str, usize> = oids
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.as_str(), i))
+        .collect();
+    let mut levels = vec![0u32; oids.len()];
+    let mut dates = vec![0u64; oids.len()];
+
+    for start in 0..oids.len() {
+        if levels[start] != 0 {
+            continue;
+        }
+        // Iterative post-order walk, so deep histories do not overflow.
+        let mut stack = vec![start];
+        while let Some(&i) = stack.last() {
+            let commit = &commits[&oids[i]];
+            let pending: Vec<usize> = commit
+                .parents
+                .iter()
+                .filter_map(|p| pos.get(p.as_str()).copied())
+                .filter(|&p| levels[p] == 0)
+                .collect();
+            if !pending.is_empty() {
+                stack.extend(pending);
+                continue;
+            }
+            stack.pop();
+            if levels[i] != 0 {
+                continue;
+            }
+            let mut level = 1;
+            let mut date = commit.time.max(0) as u64;
+            for p in commit.parents.iter().filter_map(|p| pos.get(p.as_str())) {
+                level = level.max(levels[*p] + 1);
+                date = date.max(dates[*p] + 1);
+            }
+            levels[i] = level;
+            dates[i] = date;
+        }
+    }
+    (levels, dates)
+}
+
+/// Writes a commit-graph covering every commit reachable from the refs and
+/// returns the number of commits it holds.
+pub fn commit_graph_write(repo: &Repository) -> Result<usize> {
+    let mut commits: HashMap<String, GraphCommit> = HashMap::new();
+    let mut stack = commit_graph_tips(repo)?;
+    while let Some(sha) = stack.pop() {
+        if commits.contains_key(&sha) {
+            continue;
+        }
+        let obj = object_read(repo, &sha)?;
+        let commit = obj
+            .as_any()
+            .downcast_ref::<Commit>()
+            .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+        let parents = commit.parents();
+        stack.extend(parents.iter().cloned());
+        commits.insert(
+            sha,
+            GraphCommit {
+                tree: commit
+                    .tree()
+                    .ok_or_else(|| anyhow!("Commit missing tree field"))?,
+                parents,
+                time: commit.commit_time(),
+            },
+        );
+    }
+
+    let mut oids: Vec<String> = commits.keys().cloned().collect();
+    oids.sort();
+    let pos: HashMap<&str, u32> = oids
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.as_str(), i as u32))
+        .collect();
+    let (levels, dates) = compute_generations(&oids, &commits);
+
+    let mut oidf = Vec::with_capacity(256 * 4);
+    for byte in 0..256usize {
+        let count = oids
+            .iter()
+            .take_while(|o| usize::from_str_radix(&o[0..2], 16).unwrap() <= byte)
+            .count() as u32;
+        oidf.extend_from_slice(&count.to_be_bytes());
+    }
+
+    let mut oidl = Vec::with_capacity(oids.len() * 20);
+    let mut cdat = Vec::with_capacity(oids.len() * 36);
+    let mut gda2 = Vec::with_capacity(oids.len() * 4);
+    let mut gdo2 = Vec::new();
+    let mut edge = Vec::new();
+    for (i, sha) in oids.iter().enumerate() {
+        let commit = &commits[sha];
+        oidl.extend_from_slice(&hex::decode(sha)?);
+
+        cdat.extend_from_slice(&hex::decode(&commit.tree)?);
+        let parents: Vec<u32> = commit.parents.iter().map(|p| pos[p.as_str()]).collect();
+        let p1 = parents.first().copied().unwrap_or(GRAPH_PARENT_NONE);
+        let p2 = match parents.len() {
+            0 | 1 => GRAPH_PARENT_NONE,
+            2 => parents[1],
+            _ => {
+                let start = (edge.len() / 4) as u32;
+                for (j, p) in parents[1..].iter().enumerate() {
+                    let last = if j == parents.len() - 2 {
+                        GRAPH_LAST_EDGE
+                    } else {
+                        0
+                    };
+                    edge.extend_from_slice(&(p | last).to_be_bytes());
+                }
+                GRAPH_EXTRA_EDGES | start
+            }
+        };
+        cdat.extend_from_slice(&p1.to_be_bytes());
+        cdat.extend_from_slice(&p2.to_be_bytes());
+        let time = commit.time.max(0) as u64;
+        let word = (levels[i] << 2) | ((time >> 32) as u32 & 0x3);
+        cdat.extend_from_slice(&word.to_be_bytes());
+        cdat.extend_from_slice(&(time as u32).to_be_bytes());
+
+        let offset = dates[i] - time;
+        if offset > 0x7FFF_FFFF {
+            let idx = (gdo2.len() / 8) as u32;
+            gdo2.extend_from_slice(&offset.to_be_bytes());
+            gda2.extend_from_slice(&(GENERATION_OVERFLOW | idx).to_be_bytes());
+        } else {
+            gda2.extend_from_slice(&(offset as u32).to_be_bytes());
+        }
+    }
+
+    let mut chunks: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"OIDF", oidf),
+        (b"OIDL", oidl),
+        (b"CDAT", cdat),
+        (b"GDA2", gda2),
+    ];
+    if !gdo2.is_empty() {
+        chunks.push((b"GDO2", gdo2));
+    }
+    if !edge.is_empty() {
+        chunks.push((b"EDGE", edge));
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"CGPH");
+    out.push(1); // version
+    out.push(1); // hash version: SHA-1
+    out.push(chunks.len() as u8);
+    out.push(0); // base graphs
+    let mut offset = (8 + (chunks.len() + 1) * 12) as u64;
+    for (id, data) in &chunks {
+        out.extend_from_slice(*id);
+        out.extend_from_slice(&offset.to_be_bytes());
+        offset += data.len() as u64;
+    }
+    out.extend_from_slice(&[0; 4]);
+    out.extend_from_slice(&offset.to_be_bytes());
+    for (_, data) in &chunks {
+        out.extend_from_slice(data);
+    }
+    let checksum = Sha1::digest(&out);
+    out.extend_from_slice(&checksum);
+
+    file_write_locked(&commit_graph_path(repo)?, &out, false)?;
+    Ok(oids.len())
+}
+
+fn be_u32(raw: &[u8], at: usize) -> Result<u32> {
+    raw.get(at..at + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("Commit-graph truncated"))
+}
+
+impl CommitGraph {
+    /// Loads the repository's commit-graph, if it has one.
+    pub fn load(repo: &Repository) -> Result<Option<CommitGraph>> {
+        let path = repo_file(repo, PathBuf::from("objects/info/commit-graph"), false)?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        CommitGraph::parse(&fs::read(path)?).map(Some)
+    }
+
+    pub fn parse(raw: &[u8]) -> Result<CommitGraph> {
+        if raw.len() < 8 + 20 || &raw[0..4] != b"CGPH" {
+            bail!("Invalid commit-graph signature");
+        }
+        if raw[4] != 1 || raw[5] != 1 {
+            bail!("Unsupported commit-graph version {}/{}", raw[4], raw[5]);
+        }
+        let body = &raw[..raw.len() - 20];
+        if Sha1::digest(body).as_slice() != &raw[raw.len() - 20..] {
+            bail!("Commit-graph checksum mismatch");
+        }
+
+        let num_chunks = raw[6] as usize;
+        let mut chunks: HashMap<[u8; 4], &[u8]> = HashMap::new();
+        for i in 0..num_chunks {
+            let at = 8 + i * 12;
+            let id: [u8; 4] = raw[at..at + 4].try_into()?;
+            let start = u64::from_be_bytes(raw[at + 4..at + 12].try_into()?) as usize;
+            let end = u64::from_be_bytes(raw[at + 16..at + 24].try_into()?) as usize;
+            if start > end || end > body.len() {
+                bail!("Invalid commit-graph chunk offsets");
+            }
+            chunks.insert(id, &raw[start..end]);
+        }
+        let chunk = |id: &[u8; 4]| {
+            chunks.get(id).copied().ok_or_else(|| {
+                anyhow!("Commit-graph missing {} chunk", String::from_utf8_lossy(id))
+            })
+        };
+
+        let oidl = chunk(b"OIDL")?;
+        let cdat = chunk(b"CDAT")?;
+        let count = oidl.len() / 20;
+        if cdat.len() != count * 36 {
+            bail!("Commit-graph CDAT has the wrong size");
+        }
+        let oids: Vec<String> = oidl.chunks(20).map(hex::encode).collect();
+        let edge = chunks.get(b"EDGE").copied().unwrap_or(&[]);
+
+        let mut graph = CommitGraph {
+            oids,
+            trees: Vec::with_capacity(count),
+            parents: Vec::with_capacity(count),
+            topo_levels: Vec::with_capacity(count),
+            commit_times: Vec::with_capacity(count),
+            generations: Vec::with_capacity(count),
+        };
+        for i in 0..count {
+            let at = i * 36;
+            graph.trees.push(hex::encode(&cdat[at..at + 20]));
+
+            let mut parents = Vec::new();
+            let p1 = be_u32(cdat, at + 20)?;
+            let p2 = be_u32(cdat, at + 24)?;
+            if p1 != GRAPH_PARENT_NONE {
+                parents.push(p1);
+            }
+            if p2 & GRAPH_EXTRA_EDGES != 0 {
+                let mut e = (p2 & !GRAPH_EXTRA_EDGES) as usize;
+                loop {
+                    let v = be_u32(edge, e * 4)?;
+                    parents.push(v & !GRAPH_LAST_EDGE);
+                    if v & GRAPH_LAST_EDGE != 0 {
+                        break;
+                    }
+                    e += 1;
+                }
+            } else if p2 != GRAPH_PARENT_NONE {
+                parents.push(p2);
+            }
+            graph.parents.push(parents);
+
+            let word = be_u32(cdat, at + 28)?;
+            let low = be_u32(cdat, at + 32)?;
+            graph.topo_levels.push(word >> 2);
+            graph
+                .commit_times
+                .push((((word & 0x3) as i64) << 32) | low as i64);
+        }
+
+        match chunks.get(b"GDA2") {
+            Some(gda2) => {
+                let gdo2 = chunks.get(b"GDO2").copied().unwrap_or(&[]);
+                for i in 0..count {
+                    let v = be_u32(gda2, i * 4)?;
+                    let offset = if v & GENERATION_OVERFLOW != 0 {
+                        let at = (v & !GENERATION_OVERFLOW) as usize * 8;
+                        let bytes = gdo2
+                            .get(at..at + 8)
+                            .ok_or_else(|| anyhow!("Commit-graph GDO2 truncated"))?;
+                        u64::from_be_bytes(bytes.try_into()?)
+                    } else {
+                        v as u64
+                    };
+                    graph
+                        .generations
+                        .push(graph.commit_times[i] as u64 + offset);
+                }
+            }
+            None => {
+                graph.generations = graph.topo_levels.iter().map(|&l| l as u64).collect();
+            }
+        }
+        Ok(graph)
+    }
+
+    /// The position of `sha` in the graph.
+    pub fn lookup(&self, sha: &str) -> Option<usize> {
+        self.oids.binary_search_by(|o| o.as_str().cmp(sha)).ok()
+    }
+
+    /// The generation of `sha`, or infinity if it is not in the graph.
+    pub fn generation(&self, sha: &str) -> u64 {
+        self.lookup(sha)
+            .map(|i| self.generations[i])
+            .unwrap_or(GENERATION_NUMBER_INFINITY)
+    }
+}
+
+/// Checks the commit-graph against the object database.
+pub fn commit_graph_verify(repo: &Repository) -> Result<()> {
+    let graph = CommitGraph::load(repo)?.ok_or_else(|| anyhow!("No commit-graph file"))?;
+    let mut seen = HashSet::new();
+    for (i, sha) in graph.oids.iter().enumerate() {
+        if !seen.insert(sha) || (i > 0 && graph.oids[i - 1] >= *sha) {
+            bail!("Commit-graph oids are not sorted at {}", sha);
+        }
+        let obj = object_read(repo, sha)?;
+        let commit = obj
+            .as_any()
+            .downcast_ref::<Commit>()
+            .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+        if commit.tree().as_ref() != Some(&graph.trees[i]) {
+            bail!("Commit-graph has the wrong tree for {}", sha);
+        }
+        let parents: Vec<String> = graph.parents[i]
+            .iter()
+            .map(|&p| graph.oids[p as usize].clone())
+            .collect();
+        if parents != commit.parents() {
+            bail!("Commit-graph has the wrong parents for {}", sha);
+        }
+        for &p in &graph.parents[i] {
+            if graph.generations[p as usize] >= graph.generations[i] {
+                bail!("Commit-graph generation for {} is too small", sha);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        commit_graph_write, ref_create,
+        test_utils::{make_commit_at, test_repo},
+        CommitGraph,
+    };
+
+    #[test]
+    fn test_corrected_commit_dates() {
+        let repo = test_repo("commit-graph");
+        let root = make_commit_at(&repo, "root", &[], 1000);
+        // A skewed clock: the child claims to be older than its parent.
+        let skewed = make_commit_at(&repo, "skewed", &[&root], 500);
+        let side = make_commit_at(&repo, "side", &[&root], 2000);
+        let merge = make_commit_at(&repo, "merge", &[&skewed, &side], 1500);
+        ref_create(&repo, "heads/master", &merge).unwrap();
+
+        assert_eq!(commit_graph_write(&repo).unwrap(), 4);
+        let graph = CommitGraph::load(&repo).unwrap().unwrap();
+        assert_eq!(graph.generation(&root), 1000);
+        assert_eq!(graph.generation(&skewed), 1001);
+        assert_eq!(graph.generation(&side), 2000);
+        assert_eq!(graph.generation(&merge), 2001);
+
+        let i = graph.lookup(&merge).unwrap();
+        assert_eq!(graph.topo_levels[i], 3);
+        assert_eq!(graph.commit_times[i], 1500);
+        let parents: Vec<&str> = graph.parents[i]
+            .iter()
+            .map(|&p| graph.oids[p as usize].as_str())
+            .collect();
+        assert_eq!(parents, vec![skewed.as_str(), side.as_str()]);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
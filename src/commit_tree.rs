@@ -0,0 +1,42 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{commit_create, object_find, repo_find};
+
+/// Writes a commit of `tree` with `parents` and prints its sha, leaving
+/// every ref alone. The message is the `messages` given, each a paragraph
+/// of its own, or else the content of `file`, or of stdin. The author and
+/// committer come from the environment and config as for `commit`.
+pub fn commit_tree(
+    tree: &str,
+    parents: &[String],
+    messages: &[String],
+    file: Option<&PathBuf>,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let tree_sha = object_find(&repo, tree, Some(b"tree"), true)?
+        .ok_or_else(|| anyhow!("not a valid tree object: {}", tree))?;
+    let mut parent_shas: Vec<String> = Vec::new();
+    for parent in parents {
+        let sha = object_find(&repo, parent, Some(b"commit"), true)?
+            .ok_or_else(|| anyhow!("not a valid object name {}", parent))?;
+        match parent_shas.contains(&sha) {
+            true => eprintln!("error: duplicate parent {} ignored", sha),
+            false => parent_shas.push(sha),
+        }
+    }
+    let message = match (messages, file) {
+        ([], Some(file)) if file.as_os_str() != "-" => fs::read_to_string(file)?,
+        ([], _) => io::read_to_string(io::stdin())?,
+        (messages, _) => messages.join("\n\n"),
+    };
+    println!(
+        "{}",
+        commit_create(&repo, &tree_sha, &parent_shas, None, &message)?
+    );
+    Ok(())
+}
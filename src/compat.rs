@@ -0,0 +1,321 @@
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+
+use crate::{file_write_locked, object_read_raw, pack_files, repo_file, Repository};
+
+/// The first line of the loose object map, as git writes it.
+const LOOSE_MAP_HEADER: &str = "# loose-object-idx\n";
+
+/// The translation table between the SHA-1 names objects are stored under
+/// and their SHA-256 names, for repositories that set
+/// `extensions.compatObjectFormat = sha256`, as in git's hash function
+/// transition design. A SHA-256 name is that of the object with every name
+/// in it translated too, so a commit's depends on its whole history.
+///
+/// Loose objects are mapped in `objects/loose-object-idx`, one
+/// "<sha1> <sha256>" line each. Each pack gets `pack-<sha>.compat`, with the
+/// same lines for all its objects, sorted; it is written the first time the
+/// table is loaded after the pack appears.
+pub struct CompatMap<'a> {
+    repo: &'a Repository,
+    /// SHA-1 to SHA-256.
+    map: HashMap<String, String>,
+    /// SHA-256 to SHA-1.
+    reverse: HashMap<String, String>,
+}
+
+impl<'a> CompatMap<'a> {
+    /// Loads the table of `repo`, mapping the objects of any pack that has
+    /// no map yet. Fails unless the repository asks for SHA-256 names.
+    pub fn load(repo: &'a Repository) -> Result<CompatMap<'a>> {
+        match repo.config_get("extensions", "compatobjectformat") {
+            Some(format) if format.eq_ignore_ascii_case("sha256") => {}
+            _ => bail!("extensions.compatObjectFormat is not set to sha256"),
+        }
+        let mut compat = CompatMap {
+            repo,
+            map: HashMap::new(),
+            reverse: HashMap::new(),
+        };
+        compat.read(&compat_loose_path(repo)?)?;
+        let mut unmapped = Vec::new();
+        for pack in pack_files(repo)? {
+            let path = pack.path.with_extension("compat");
+            match path.is_file() {
+                true => compat.read(&path)?,
+                false => unmapped.push((pack, path)),
+            }
+        }
+        for (pack, path) in unmapped {
+            let mut lines = String::new();
+            for sha in pack.shas() {
+                let compat_sha = compat.compute(&sha, false)?;
+                lines.push_str(&format!("{} {}\n", sha, compat_sha));
+            }
+            file_write_locked(&path, lines.as_bytes(), repo.fsync.objects)?;
+        }
+        Ok(compat)
+    }
+
+    /// The SHA-256 name of the object named `sha` (SHA-1).
+    pub fn sha256(&mut self, sha: &str) -> Result<String> {
+        self.compute(sha, true)
+    }
+
+    /// The SHA-1 name of the object named `sha256`, if it is mapped.
+    pub fn sha1(&self, sha256: &str) -> Option<String> {
+        self.reverse.get(sha256).cloned()
+    }
+
+    /// The SHA-1 names of the mapped objects whose SHA-256 names start
+    /// with `prefix`.
+    pub fn sha1_prefixed(&self, prefix: &str) -> Vec<String> {
+        self.map
+            .iter()
+            .filter(|(_, s)| s.starts_with(prefix))
+            .map(|(sha, _)| sha.clone())
+            .collect()
+    }
+
+    fn insert(&mut self, sha: String, compat_sha: String) {
+        self.reverse.insert(compat_sha.clone(), sha.clone());
+        self.map.insert(sha, compat_sha);
+    }
+
+    fn read(&mut self, path: &Path) -> Result<()> {
+        let Ok(data) = fs::read_to_string(path) else {
+            return Ok(());
+        };
+        for line in data.lines().filter(|l| !l.starts_with('#')) {
+            let (sha, compat_sha) = line
+                .split_once(' ')
+                .ok_or_else(|| anyhow!("bad line in {}: {}", path.display(), line))?;
+            self.insert(sha.to_string(), compat_sha.to_string());
+        }
+        Ok(())
+    }
+
+    /// Maps `sha` and whatever it names that isn't mapped yet, children
+    /// first. With `record`, new mappings of loose objects are appended to
+    /// the loose map; packed ones go in their pack's map instead.
+    fn compute(&mut self, sha: &str, record: bool) -> Result<String> {
+        let mut added = Vec::new();
+        let mut stack = vec![sha.to_string()];
+        while let Some(top) = stack.last().cloned() {
+            if self.map.contains_key(&top) {
+                stack.pop();
+                continue;
+            }
+            let (fmt, data) = object_read_raw(self.repo, &top)?;
+            let missing: Vec<String> = compat_references(&fmt, &data)?
+                .into_iter()
+                .filter(|child| !self.map.contains_key(child))
+                .collect();
+            if !missing.is_empty() {
+                stack.extend(missing);
+                continue;
+            }
+            let converted = compat_convert(&fmt, &data, &self.map)?;
+            let mut hasher = Sha256::new();
+            hasher.update(&fmt);
+            hasher.update(format!(" {}\0", converted.len()));
+            hasher.update(&converted);
+            let compat_sha = hex::encode(hasher.finalize());
+            self.insert(top.clone(), compat_sha.clone());
+            added.push((top, compat_sha));
+            stack.pop();
+        }
+        if record && !added.is_empty() {
+            self.record_loose(&added)?;
+        }
+        Ok(self.map[sha].clone())
+    }
+
+    fn record_loose(&self, added: &[(String, String)]) -> Result<()> {
+        let packs = pack_files(self.repo)?;
+        let path = compat_loose_path(self.repo)?;
+        let mut lines = String::new();
+        if !path.is_file() {
+            lines.push_str(LOOSE_MAP_HEADER);
+        }
+        for (sha, compat_sha) in added {
+            if !packs.iter().any(|p| p.contains(sha)) {
+                lines.push_str(&format!("{} {}\n", sha, compat_sha));
+            }
+        }
+        let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+        f.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn compat_loose_path(repo: &Repository) -> Result<PathBuf> {
+    repo_file(repo, PathBuf::from("objects/loose-object-idx"), false)
+}
+
+/// The objects whose names appear in an object of type `fmt`: a tree's
+/// entries, but not submodule commits, a commit's tree and parents, and a
+/// tag's object.
+fn compat_references(fmt: &[u8], data: &[u8]) -> Result<Vec<String>> {
+    let mut ret = Vec::new();
+    match fmt {
+        b"tree" => {
+            for (mode, sha) in compat_tree_entries(data)? {
+                if mode != b"160000" {
+                    ret.push(hex::encode(sha));
+                }
+            }
+        }
+        b"commit" | b"tag" => {
+            for line in compat_header_lines(data) {
+                if let Some((key, sha)) = compat_header_name(fmt, line) {
+                    if key != b"mergetag" {
+                        ret.push(sha.to_string());
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(ret)
+}
+
+/// The content of an object with every name in it replaced by its
+/// SHA-256 name from `map`.
+fn compat_convert(fmt: &[u8], data: &[u8], map: &HashMap<String, String>) -> Result<Vec<u8>> {
+    let lookup = |sha: &str| -> Result<&String> {
+        map.get(sha)
+            .ok_or_else(|| anyhow!("no SHA-256 name is known for {}", sha))
+    };
+    let mut out = Vec::with_capacity(data.len());
+    match fmt {
+        b"tree" => {
+            let mut rest = data;
+            for (mode, sha) in compat_tree_entries(data)? {
+                let hex_sha = hex::encode(sha);
+                if mode == b"160000" {
+                    bail!(
+                        "cannot map submodule commit {}: its SHA-256 name is unknown",
+                        hex_sha
+                    );
+                }
+                let nul = rest.iter().position(|&b| b == 0).unwrap();
+                out.extend_from_slice(&rest[..=nul]);
+                out.extend_from_slice(&hex::decode(lookup(&hex_sha)?)?);
+                rest = &rest[nul + 21..];
+            }
+        }
+        b"commit" | b"tag" => {
+            let header_len: usize = compat_header_lines(data).map(|l| l.len() + 1).sum();
+            for line in compat_header_lines(data) {
+                match compat_header_name(fmt, line) {
+                    Some((key, sha)) if key != b"mergetag" => {
+                        out.extend_from_slice(key);
+                        out.push(b' ');
+                        out.extend_from_slice(lookup(sha)?.as_bytes());
+                    }
+                    _ => out.extend_from_slice(line),
+                }
+                out.push(b'\n');
+            }
+            out.extend_from_slice(&data[header_len.min(data.len())..]);
+        }
+        _ => out.extend_from_slice(data),
+    }
+    Ok(out)
+}
+
+/// The mode and raw name of each entry of a tree.
+fn compat_tree_entries(data: &[u8]) -> Result<Vec<(&[u8], &[u8])>> {
+    let mut ret = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ');
+        let nul = rest.iter().position(|&b| b == 0);
+        let (Some(space), Some(nul)) = (space, nul) else {
+            bail!("malformed tree");
+        };
+        if rest.len() < nul + 21 {
+            bail!("malformed tree");
+        }
+        ret.push((&rest[..space], &rest[nul + 1..nul + 21]));
+        rest = &rest[nul + 21..];
+    }
+    Ok(ret)
+}
+
+/// The header lines of a commit or tag, up to the blank line before its
+/// message.
+fn compat_header_lines(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    data.split(|&b| b == b'\n').take_while(|l| !l.is_empty())
+}
+
+/// The key and name of a header line naming an object: "tree" and
+/// "parent" in commits, "object" in tags.
+fn compat_header_name<'d>(fmt: &[u8], line: &'d [u8]) -> Option<(&'d [u8], &'d str)> {
+    let space = line.iter().position(|&b| b == b' ')?;
+    let (key, value) = (&line[..space], &line[space + 1..]);
+    let names = match fmt {
+        b"commit" => key == b"tree" || key == b"parent",
+        _ => key == b"object",
+    };
+    (names && value.len() == 40)
+        .then(|| std::str::from_utf8(value).ok())
+        .flatten()
+        .map(|value| (key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::CompatMap;
+    use crate::{
+        object_write, pack_store,
+        test_utils::{make_commit, test_repo},
+        Blob,
+    };
+
+    #[test]
+    fn test_compat_map() {
+        let mut repo = test_repo("compat-map");
+        assert!(CompatMap::load(&repo).is_err());
+        repo.config_set("extensions", "compatObjectFormat", "sha256")
+            .unwrap();
+
+        // The SHA-256 name of an empty blob, as `git hash-object` gives it
+        // in a SHA-256 repository.
+        let empty = object_write(&Blob::new(b""), Some(&repo)).unwrap();
+        let mut compat = CompatMap::load(&repo).unwrap();
+        assert_eq!(
+            compat.sha256(&empty).unwrap(),
+            "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813"
+        );
+        assert_eq!(
+            compat.sha1("473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813"),
+            Some(empty.clone())
+        );
+
+        let first = make_commit(&repo, "one", &[]);
+        let second = make_commit(&repo, "two", &[&first]);
+        let second_compat = compat.sha256(&second).unwrap();
+        let loose = fs::read_to_string(repo.gitdir.join("objects/loose-object-idx")).unwrap();
+        assert!(loose.starts_with("# loose-object-idx\n"));
+        assert!(loose.contains(&format!("{} {}\n", second, second_compat)));
+
+        // Packed objects are mapped in their pack's own map, to the same names.
+        let pack = pack_store(&repo, &[first.clone(), second.clone()]).unwrap();
+        fs::remove_file(repo.gitdir.join("objects/loose-object-idx")).unwrap();
+        let mut compat = CompatMap::load(&repo).unwrap();
+        let packed = fs::read_to_string(pack.with_extension("compat")).unwrap();
+        assert!(packed.contains(&format!("{} {}\n", second, second_compat)));
+        assert_eq!(compat.sha256(&second).unwrap(), second_compat);
+    }
+}
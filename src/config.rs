@@ -0,0 +1,176 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{config_global_path, config_global_paths, repo_find, ConfigFile};
+
+/// What `config` does and which files it looks at.
+#[derive(Default)]
+pub struct ConfigOptions {
+    /// Only the user's `~/.gitconfig`.
+    pub global: bool,
+    /// Only the repository's own config.
+    pub local: bool,
+    pub get: bool,
+    pub unset: bool,
+    pub list: bool,
+}
+
+/// Gets, sets, unsets or lists config values. Reads go through every file,
+/// the system's, the user's and the repository's, later ones winning,
+/// unless one is picked; writes go to the repository's config unless
+/// `--global`.
+pub fn config(key: Option<&str>, value: Option<&str>, opts: &ConfigOptions) -> Result<()> {
+    if opts.global && opts.local {
+        bail!("only one config file at a time");
+    }
+    let repo = repo_find(Path::new("."), false)?;
+    let local_path = repo.as_ref().map(|r| r.commondir.join("config"));
+    let global_path = config_global_path();
+    let files: Vec<PathBuf> = if opts.global {
+        Vec::from_iter(global_path.clone())
+    } else if opts.local {
+        vec![local_path
+            .clone()
+            .ok_or_else(|| anyhow!("--local can only be used inside a git repository"))?]
+    } else {
+        config_global_paths()
+            .into_iter()
+            .chain(local_path.clone())
+            .collect()
+    };
+
+    if opts.list {
+        for path in &files {
+            let Some(conf) = config_file_load(path)? else {
+                continue;
+            };
+            for (key, value) in conf.entries("") {
+                println!("{}={}", key, value);
+            }
+        }
+        return Ok(());
+    }
+
+    let key = key.ok_or_else(|| anyhow!("missing key"))?;
+    let (section, name) = config_key_parse(key)?;
+    // Writes without a scope go to the repository's own file.
+    let write_path = || match (opts.global, &local_path) {
+        (true, _) => global_path.clone().ok_or_else(|| anyhow!("$HOME not set")),
+        (false, Some(path)) => Ok(path.clone()),
+        (false, None) => Err(anyhow!("not in a git directory")),
+    };
+
+    if opts.unset {
+        let path = write_path()?;
+        let mut conf = config_file_load(&path)?.unwrap_or_default();
+        if !conf.unset(&section, &name) {
+            bail!("key not found: {}", key);
+        }
+        return conf.write(&path);
+    }
+    match value {
+        Some(value) if !opts.get => {
+            let path = write_path()?;
+            let mut conf = config_file_load(&path)?.unwrap_or_default();
+            conf.set(&section, &name, value);
+            conf.write(&path)
+        }
+        _ => {
+            let mut found = None;
+            for path in files.iter().rev() {
+                found = config_file_load(path)?.and_then(|conf| conf.get(&section, &name));
+                if found.is_some() {
+                    break;
+                }
+            }
+            match found {
+                Some(value) => {
+                    println!("{}", value);
+                    Ok(())
+                }
+                None => bail!("key not found: {}", key),
+            }
+        }
+    }
+}
+
+/// The config file at `path`, or None if there is none yet. A file that
+/// can't be read or parsed is an error: writing over it would lose it.
+fn config_file_load(path: &Path) -> Result<Option<ConfigFile>> {
+    match ConfigFile::load(path) {
+        Ok(conf) => Ok(Some(conf)),
+        Err(e)
+            if e.downcast_ref::<std::io::Error>()
+                .is_some_and(|e| e.kind() == ErrorKind::NotFound) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Splits `section[.subsection].key` into the ini section, as in
+/// `remote "origin"`, and the key. Section and key names are
+/// case-insensitive, so the section is lowercased; subsections are not.
+pub fn config_key_parse(key: &str) -> Result<(String, String)> {
+    let (Some(first), Some(last)) = (key.find('.'), key.rfind('.')) else {
+        bail!("key does not contain a section: {}", key);
+    };
+    let (section, name) = (&key[..first], &key[last + 1..]);
+    let valid = |s: &str| {
+        s.starts_with(|c: char| c.is_ascii_alphabetic())
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if !valid(section) || !valid(name) {
+        bail!("invalid key: {}", key);
+    }
+    let section = section.to_lowercase();
+    Ok(match first == last {
+        true => (section, name.to_string()),
+        false => (
+            format!("{} \"{}\"", section, &key[first + 1..last]),
+            name.to_string(),
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{config_file_load, config_key_parse};
+
+    #[test]
+    fn test_config_key_parse() {
+        assert_eq!(
+            config_key_parse("core.sshCommand").unwrap(),
+            ("core".to_string(), "sshCommand".to_string())
+        );
+        assert_eq!(
+            config_key_parse("Remote.Up.Stream.url").unwrap(),
+            ("remote \"Up.Stream\"".to_string(), "url".to_string())
+        );
+        assert!(config_key_parse("core").is_err());
+        assert!(config_key_parse("core.1x").is_err());
+        assert!(config_key_parse(".x").is_err());
+    }
+
+    #[test]
+    fn test_config_file_load() {
+        let dir = std::env::temp_dir().join(format!("rgit-config-load-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config");
+        assert!(config_file_load(&path).unwrap().is_none());
+        fs::write(&path, "[core]\n\tbare = false\n").unwrap();
+        let conf = config_file_load(&path).unwrap().unwrap();
+        assert_eq!(conf.get("core", "bare").as_deref(), Some("false"));
+        // A broken file is reported, not taken for an empty one.
+        fs::write(&path, "[core\n\tbare = false\n").unwrap();
+        assert!(config_file_load(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
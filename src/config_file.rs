@@ -0,0 +1,559 @@
+use std::{fmt, fs, path::Path};
+
+use anyhow::{anyhow, Result};
+
+use crate::file_write_locked;
+
+/// A git config file, kept line by line so that writing it back changes
+/// only the lines that were edited: comments, blank lines, indentation and
+/// the order of sections and keys all survive.
+///
+/// Sections are named as in the file's headers, `core` or
+/// `remote "origin"`: the section name is case-insensitive and kept
+/// lowercased, the subsection is case-sensitive. Keys are case-insensitive.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigFile {
+    lines: Vec<ConfigLine>,
+}
+
+#[derive(Clone, Debug)]
+enum ConfigLine {
+    Header {
+        section: String,
+        raw: String,
+    },
+    /// A key and its value, which may run over several lines through
+    /// trailing backslashes. A key without "=" is a boolean true.
+    Entry {
+        section: String,
+        key: String,
+        value: Option<String>,
+        raw: String,
+        /// Written on the line of the header before it, as in
+        /// `[core] bare = true`.
+        inline: bool,
+    },
+    /// Blank lines, comments and anything before the first section.
+    Other(String),
+}
+
+impl ConfigFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the config file at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|e| anyhow!("{} in file {}", e, path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = Vec::new();
+        let mut section: Option<String> = None;
+        let mut raw_lines = text.lines().enumerate();
+        while let Some((n, line)) = raw_lines.next() {
+            let bad = || anyhow!("bad config line {}", n + 1);
+            let mut raw = line.to_string();
+            // A value goes on past a line ending in an unescaped backslash.
+            while ends_in_continuation(&raw) {
+                let Some((_, next)) = raw_lines.next() else {
+                    break;
+                };
+                raw.push('\n');
+                raw.push_str(next);
+            }
+            let trimmed = raw.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with(['#', ';']) {
+                lines.push(ConfigLine::Other(raw));
+            } else if trimmed.starts_with('[') {
+                let (name, rest) = config_header_parse(trimmed).ok_or_else(bad)?;
+                section = Some(name.clone());
+                if rest.is_empty() || rest.starts_with(['#', ';']) {
+                    lines.push(ConfigLine::Header { section: name, raw });
+                    continue;
+                }
+                // A key may follow the header on its line.
+                let (key, value) = config_entry_parse(rest).ok_or_else(bad)?;
+                let header_len = raw.len() - rest.len();
+                lines.push(ConfigLine::Header {
+                    section: name.clone(),
+                    raw: raw[..header_len].to_string(),
+                });
+                lines.push(ConfigLine::Entry {
+                    section: name,
+                    key,
+                    value,
+                    raw: rest.to_string(),
+                    inline: true,
+                });
+            } else {
+                let section = section.clone().ok_or_else(bad)?;
+                let (key, value) = config_entry_parse(trimmed).ok_or_else(bad)?;
+                lines.push(ConfigLine::Entry {
+                    section,
+                    key,
+                    value,
+                    raw,
+                    inline: false,
+                });
+            }
+        }
+        Ok(Self { lines })
+    }
+
+    /// Writes the file to `path`, replacing it in one step.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        file_write_locked(path, self.to_string().as_bytes(), false)
+    }
+
+    /// Every key and value under `prefix`, in file order, with keys
+    /// written as `section[.subsection].key`, e.g. "remote.origin.fetch".
+    pub fn entries<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (String, String)> + 'a {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                ConfigLine::Entry {
+                    section,
+                    key,
+                    value,
+                    ..
+                } => Some((config_key_dotted(section, key), value_or_true(value))),
+                _ => None,
+            })
+            .filter(move |(name, _)| name.starts_with(prefix))
+    }
+
+    /// Every value of `key` in `section`, in file order.
+    pub fn get_all(&self, section: &str, key: &str) -> Vec<String> {
+        let section = config_section_canon(section);
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                ConfigLine::Entry {
+                    section: s,
+                    key: k,
+                    value,
+                    ..
+                } if *s == section && k.eq_ignore_ascii_case(key) => Some(value_or_true(value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The last value of `key` in `section`, the one that counts.
+    pub fn get(&self, section: &str, key: &str) -> Option<String> {
+        self.get_all(section, key).pop()
+    }
+
+    /// Sets `key` in `section` to `value`. The last existing value is
+    /// replaced where it stands and any others are removed; without one the
+    /// key is added.
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        let canon = config_section_canon(section);
+        let Some(last) = self.entry_positions(&canon, key).pop() else {
+            return self.add(section, key, value);
+        };
+        if let ConfigLine::Entry {
+            key: k,
+            value: v,
+            raw,
+            ..
+        } = &mut self.lines[last]
+        {
+            let indent_len = raw.len() - raw.trim_start().len();
+            *raw = format!(
+                "{}{} = {}",
+                &raw[..indent_len],
+                key,
+                config_value_quote(value)
+            );
+            *k = key.to_string();
+            *v = Some(value.to_string());
+        }
+        let mut i = 0;
+        self.lines.retain(|line| {
+            i += 1;
+            i - 1 == last || !line_is_entry(line, &canon, key)
+        });
+    }
+
+    /// Adds another value to `key` in `section`, after the last line of
+    /// the section, which is created at the end of the file if need be.
+    pub fn add(&mut self, section: &str, key: &str, value: &str) {
+        let canon = config_section_canon(section);
+        let line = ConfigLine::Entry {
+            section: canon.clone(),
+            key: key.to_string(),
+            value: Some(value.to_string()),
+            raw: format!("\t{} = {}", key, config_value_quote(value)),
+            inline: false,
+        };
+        // The section ends at its last key, not at comments after it.
+        let end = self.lines.iter().rposition(|l| match l {
+            ConfigLine::Header { section, .. } | ConfigLine::Entry { section, .. } => {
+                *section == canon
+            }
+            ConfigLine::Other(_) => false,
+        });
+        match end {
+            Some(end) => self.lines.insert(end + 1, line),
+            None => {
+                self.lines.push(ConfigLine::Header {
+                    section: canon.clone(),
+                    raw: format!("[{}]", canon),
+                });
+                self.lines.push(line);
+            }
+        }
+    }
+
+    /// Removes every value of `key` in `section`. Returns whether there
+    /// were any.
+    pub fn unset(&mut self, section: &str, key: &str) -> bool {
+        let canon = config_section_canon(section);
+        let before = self.lines.len();
+        self.lines.retain(|line| !line_is_entry(line, &canon, key));
+        self.lines.len() != before
+    }
+
+    /// The sections in the file, each once, in the order they first appear.
+    pub fn sections(&self) -> Vec<String> {
+        let mut ret: Vec<String> = Vec::new();
+        for line in &self.lines {
+            if let ConfigLine::Header { section, .. } = line {
+                if !ret.contains(section) {
+                    ret.push(section.clone());
+                }
+            }
+        }
+        ret
+    }
+
+    /// Removes `section` with everything up to the next section header.
+    /// Returns whether it existed.
+    pub fn section_remove(&mut self, section: &str) -> bool {
+        let canon = config_section_canon(section);
+        let before = self.lines.len();
+        let mut inside = false;
+        self.lines.retain(|line| {
+            if let ConfigLine::Header { section, .. } = line {
+                inside = *section == canon;
+            }
+            !inside
+        });
+        self.lines.len() != before
+    }
+
+    /// Renames section `old` to `new`, keeping its contents where they
+    /// are. Returns whether it existed.
+    pub fn section_rename(&mut self, old: &str, new: &str) -> bool {
+        let (old, new) = (config_section_canon(old), config_section_canon(new));
+        let mut found = false;
+        for line in &mut self.lines {
+            match line {
+                ConfigLine::Header { section, raw } if *section == old => {
+                    // Keep the space before a key on the same line.
+                    let space = &raw[raw.trim_end().len()..];
+                    *raw = format!("[{}]{}", new, space);
+                    *section = new.clone();
+                    found = true;
+                }
+                ConfigLine::Entry { section, .. } if *section == old => *section = new.clone(),
+                _ => {}
+            }
+        }
+        found
+    }
+
+    fn entry_positions(&self, section: &str, key: &str) -> Vec<usize> {
+        (0..self.lines.len())
+            .filter(|&i| line_is_entry(&self.lines[i], section, key))
+            .collect()
+    }
+}
+
+impl fmt::Display for ConfigFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            let (ConfigLine::Header { raw, .. }
+            | ConfigLine::Entry { raw, .. }
+            | ConfigLine::Other(raw)) = line;
+            write!(f, "{}", raw)?;
+            match self.lines.get(i + 1) {
+                Some(ConfigLine::Entry { inline: true, .. }) => {}
+                _ => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn line_is_entry(line: &ConfigLine, section: &str, key: &str) -> bool {
+    matches!(line, ConfigLine::Entry { section: s, key: k, .. }
+        if s == section && k.eq_ignore_ascii_case(key))
+}
+
+/// A key given without a value is a boolean true.
+fn value_or_true(value: &Option<String>) -> String {
+    value.clone().unwrap_or_else(|| "true".to_string())
+}
+
+/// Whether `line` goes on to the next: it ends in a backslash that isn't
+/// itself escaped or inside a comment.
+fn ends_in_continuation(line: &str) -> bool {
+    let mut quoted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.next().is_none() => return true,
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// `remote "origin"`, as callers write sections, with the section name
+/// lowercased.
+fn config_section_canon(section: &str) -> String {
+    match section.split_once(' ') {
+        Some((name, sub)) => format!("{} {}", name.to_lowercase(), sub),
+        None => section.to_lowercase(),
+    }
+}
+
+/// `remote "origin"` and `fetch` as `remote.origin.fetch`.
+fn config_key_dotted(section: &str, key: &str) -> String {
+    match section.split_once(" \"") {
+        Some((name, sub)) => format!(
+            "{}.{}.{}",
+            name,
+            sub.strip_suffix('"').unwrap_or(sub),
+            key.to_lowercase()
+        ),
+        None => format!("{}.{}", section, key.to_lowercase()),
+    }
+}
+
+/// Parses a section header, `[core]`, `[remote "origin"]` or the older
+/// `[remote.origin]`, into its canonical name and what follows it on the
+/// line: a comment, a key or nothing.
+fn config_header_parse(line: &str) -> Option<(String, &str)> {
+    let rest = line.strip_prefix('[')?;
+    let name_len = rest
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '.'))
+        .unwrap_or(rest.len());
+    let (name, rest) = rest.split_at(name_len);
+    if name.is_empty() {
+        return None;
+    }
+    let (section, rest) = if let Some(quoted) = rest.trim_start().strip_prefix('"') {
+        if name.contains('.') {
+            return None;
+        }
+        let mut sub = String::new();
+        let mut chars = quoted.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (_, '\\') => sub.push(chars.next()?.1),
+                (i, '"') => break i,
+                (_, c) => sub.push(c),
+            }
+        };
+        (
+            format!("{} \"{}\"", name.to_lowercase(), sub),
+            &quoted[end + 1..],
+        )
+    } else {
+        match name.split_once('.') {
+            Some((name, sub)) => (
+                format!("{} \"{}\"", name.to_lowercase(), sub.to_lowercase()),
+                rest,
+            ),
+            None => (name.to_lowercase(), rest),
+        }
+    };
+    Some((section, rest.strip_prefix(']')?.trim_start()))
+}
+
+/// Parses `key [= value]`, unquoting and unescaping the value and dropping
+/// a trailing comment.
+fn config_entry_parse(line: &str) -> Option<(String, Option<String>)> {
+    let key_len = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+        .unwrap_or(line.len());
+    let (key, rest) = line.split_at(key_len);
+    if !key.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with(['#', ';']) {
+        return Some((key.to_string(), None));
+    }
+    let rest = rest.strip_prefix('=')?.trim_start();
+
+    let mut value = String::new();
+    // Unquoted whitespace at the end is dropped, not the quoted kind.
+    let mut keep_len = 0;
+    let mut quoted = false;
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                '\n' => continue,
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'b' => value.push('\x08'),
+                c @ ('\\' | '"') => value.push(c),
+                _ => return None,
+            },
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => break,
+            c if c.is_whitespace() && !quoted => {
+                value.push(c);
+                continue;
+            }
+            c => value.push(c),
+        }
+        keep_len = value.len();
+    }
+    if quoted {
+        return None;
+    }
+    value.truncate(keep_len);
+    Some((key.to_string(), Some(value)))
+}
+
+/// `value` as it is written in a config file: escaped, and quoted if
+/// whitespace at either end or a comment character would be lost.
+fn config_value_quote(value: &str) -> String {
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+    match needs_quotes {
+        true => format!("\"{}\"", escaped),
+        false => escaped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigFile;
+
+    #[test]
+    fn test_config_file_round_trip() {
+        let text = "# top comment\n\
+            [core]\n\
+            \tbare = false ; not bare\n\
+            \n\
+            [remote \"Origin\"]\n\
+            \turl = \"/srv/a b\"\n\
+            \tfetch = +refs/heads/*:refs/remotes/Origin/*\n\
+            \tfetch = +refs/tags/*:refs/tags/*\n\
+            [Branch.Main]\n\
+            \tmerge = refs/heads/\\\n\
+            main\n\
+            \trebase\n";
+        let mut conf = ConfigFile::parse(text).unwrap();
+        assert_eq!(conf.to_string(), text);
+
+        assert_eq!(conf.get("Core", "BARE").as_deref(), Some("false"));
+        assert_eq!(
+            conf.get("remote \"Origin\"", "url").as_deref(),
+            Some("/srv/a b")
+        );
+        assert_eq!(conf.get_all("remote \"Origin\"", "fetch").len(), 2);
+        assert!(conf.get("remote \"origin\"", "url").is_none());
+        assert_eq!(
+            conf.get("branch \"main\"", "merge").as_deref(),
+            Some("refs/heads/main")
+        );
+        assert_eq!(
+            conf.get("branch \"main\"", "rebase").as_deref(),
+            Some("true")
+        );
+        let entries: Vec<_> = conf.entries("remote.").map(|(k, _)| k).collect();
+        assert_eq!(
+            entries,
+            [
+                "remote.Origin.url",
+                "remote.Origin.fetch",
+                "remote.Origin.fetch"
+            ]
+        );
+
+        conf.set("core", "bare", "true");
+        conf.add("core", "editor", "vi # really");
+        conf.set(
+            "remote \"Origin\"",
+            "fetch",
+            "+refs/heads/main:refs/remotes/Origin/main",
+        );
+        assert!(conf.unset("branch \"main\"", "rebase"));
+        conf.set("user", "name", "A U Thor");
+        assert_eq!(
+            conf.to_string(),
+            "# top comment\n\
+            [core]\n\
+            \tbare = true\n\
+            \teditor = \"vi # really\"\n\
+            \n\
+            [remote \"Origin\"]\n\
+            \turl = \"/srv/a b\"\n\
+            \tfetch = +refs/heads/main:refs/remotes/Origin/main\n\
+            [Branch.Main]\n\
+            \tmerge = refs/heads/\\\n\
+            main\n\
+            [user]\n\
+            \tname = A U Thor\n"
+        );
+        let reparsed = ConfigFile::parse(&conf.to_string()).unwrap();
+        assert_eq!(
+            reparsed.get("core", "editor").as_deref(),
+            Some("vi # really")
+        );
+
+        assert!(conf.section_remove("remote \"Origin\""));
+        assert!(conf.section_rename("user", "author"));
+        assert_eq!(conf.sections(), ["core", "branch \"main\"", "author"]);
+
+        assert!(ConfigFile::parse("key = value\n").is_err());
+        assert!(ConfigFile::parse("[core]\n\tx = \"open\n").is_err());
+    }
+
+    #[test]
+    fn test_config_file_same_line() {
+        let text = "[core] bare = true\n\
+            # a comment that ends in a backslash \\\n\
+            [user]\n\
+            \tname = A ; trailing \\\n\
+            \temail = a@example.com\n";
+        let mut conf = ConfigFile::parse(text).unwrap();
+        assert_eq!(conf.to_string(), text);
+        assert_eq!(conf.get("core", "bare").as_deref(), Some("true"));
+        assert_eq!(conf.get("user", "name").as_deref(), Some("A"));
+        assert_eq!(conf.get("user", "email").as_deref(), Some("a@example.com"));
+
+        conf.set("core", "bare", "false");
+        conf.add("core", "editor", "vi");
+        assert!(conf.section_rename("core", "Core2"));
+        assert_eq!(
+            conf.to_string().lines().take(2).collect::<Vec<_>>(),
+            ["[core2] bare = false", "\teditor = vi"]
+        );
+        assert!(conf.unset("core2", "bare"));
+        assert!(conf.to_string().starts_with("[core2] \n\teditor = vi\n"));
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    collections::HashSet,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{object_list_loose, pack_files, repo_dir, repo_find, Repository};
+
+/// What `.git/objects` holds. Sizes are in bytes: the disk usage of loose
+/// objects, and the file sizes of packs and garbage, as git counts them.
+#[derive(Debug, Default)]
+pub struct ObjectCounts {
+    pub count: usize,
+    pub size: u64,
+    pub in_pack: usize,
+    pub packs: usize,
+    pub size_pack: u64,
+    /// Loose objects that a pack holds too, which `prune` could remove.
+    pub prune_packable: usize,
+    /// Files that are neither loose objects nor part of a complete pack.
+    pub garbage: Vec<PathBuf>,
+    pub size_garbage: u64,
+}
+
+/// Prints how many loose objects there are and how much space they take;
+/// with `verbose` also the packs and garbage, in `human` readable units if
+/// asked.
+pub fn count_objects(verbose: bool, human: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let counts = count_objects_scan(&repo)?;
+    let size = |bytes: u64| match human {
+        true => bytes_humanise(bytes),
+        false => (bytes / 1024).to_string(),
+    };
+    for path in &counts.garbage {
+        eprintln!("warning: garbage found: {}", path.display());
+    }
+    if !verbose {
+        println!("{} objects, {} kilobytes", counts.count, counts.size / 1024);
+        return Ok(());
+    }
+    println!("count: {}", counts.count);
+    println!("size: {}", size(counts.size));
+    println!("in-pack: {}", counts.in_pack);
+    println!("packs: {}", counts.packs);
+    println!("size-pack: {}", size(counts.size_pack));
+    println!("prune-packable: {}", counts.prune_packable);
+    println!("garbage: {}", counts.garbage.len());
+    println!("size-garbage: {}", size(counts.size_garbage));
+    Ok(())
+}
+
+/// Walks `.git/objects`, counting loose objects, packs and garbage.
+pub fn count_objects_scan(repo: &Repository) -> Result<ObjectCounts> {
+    let mut counts = ObjectCounts::default();
+    let Some(objects) = repo_dir(repo, PathBuf::from("objects"), false)? else {
+        return Ok(counts);
+    };
+    let disk_usage = |path: &Path| -> Result<u64> { Ok(fs::metadata(path)?.blocks() * 512) };
+    let file_size = |path: &Path| -> Result<u64> { Ok(fs::metadata(path)?.len()) };
+
+    let packs = pack_files(repo)?;
+    let mut packed = HashSet::new();
+    for pack in &packs {
+        counts.packs += 1;
+        counts.size_pack += file_size(&pack.path)? + file_size(&pack.path.with_extension("idx"))?;
+        for sha in pack.shas() {
+            counts.in_pack += 1;
+            packed.insert(sha);
+        }
+    }
+
+    let loose = object_list_loose(repo)?;
+    let loose_set: HashSet<&String> = loose.iter().collect();
+    for sha in &loose {
+        counts.count += 1;
+        counts.size += disk_usage(&objects.join(&sha[..2]).join(&sha[2..]))?;
+        if packed.contains(sha) {
+            counts.prune_packable += 1;
+        }
+    }
+
+    // Anything else in the fan-out directories is garbage.
+    for dir in fs::read_dir(&objects)? {
+        let dir = dir?;
+        let prefix = dir.file_name().to_string_lossy().to_string();
+        let is_fanout = prefix.len() == 2 && prefix.chars().all(|c| c.is_ascii_hexdigit());
+        if !is_fanout || !dir.path().is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(dir.path())? {
+            let file = file?;
+            let sha = format!("{}{}", prefix, file.file_name().to_string_lossy());
+            if !loose_set.contains(&sha) {
+                counts.garbage.push(file.path());
+            }
+        }
+    }
+
+    // So is any pack file that isn't part of a loaded pack.
+    let pack_names: HashSet<PathBuf> = packs.iter().map(|p| p.path.with_extension("")).collect();
+    if let Some(pack_dir) = repo_dir(repo, PathBuf::from("objects/pack"), false)? {
+        let mut files: Vec<PathBuf> = fs::read_dir(pack_dir)?
+            .map(|e| Ok(e?.path()))
+            .collect::<Result<_>>()?;
+        files.sort();
+        for path in files {
+            let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+            let known = matches!(extension, "pack" | "idx" | "keep" | "bitmap" | "rev");
+            if !known || !pack_names.contains(&path.with_extension("")) {
+                counts.garbage.push(path);
+            }
+        }
+    }
+    counts.garbage.sort();
+    for path in &counts.garbage {
+        counts.size_garbage += file_size(path)?;
+    }
+    Ok(counts)
+}
+
+/// Formats `bytes` the way git's `-H` does, as in "1.50 MiB".
+pub fn bytes_humanise(bytes: u64) -> String {
+    const UNITS: [(u64, &str); 3] = [(1 << 30, "GiB"), (1 << 20, "MiB"), (1 << 10, "KiB")];
+    for (unit, name) in UNITS {
+        if bytes >= unit {
+            let hundredths = bytes % unit * 100 / unit;
+            return format!("{}.{:02} {}", bytes / unit, hundredths, name);
+        }
+    }
+    match bytes {
+        1 => "1 byte".to_string(),
+        _ => format!("{} bytes", bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{bytes_humanise, count_objects_scan};
+    use crate::test_utils::{make_commit, test_repo};
+
+    #[test]
+    fn test_count_objects() {
+        assert_eq!(bytes_humanise(1), "1 byte");
+        assert_eq!(bytes_humanise(1536), "1.50 KiB");
+        assert_eq!(bytes_humanise(3 << 30), "3.00 GiB");
+
+        let repo = test_repo("count-objects");
+        make_commit(&repo, "one", &[]);
+        let objects = repo.gitdir.join("objects");
+        fs::create_dir_all(objects.join("ab")).unwrap();
+        fs::write(objects.join("ab").join("tmp_obj_1"), "x").unwrap();
+        fs::create_dir_all(objects.join("pack")).unwrap();
+        fs::write(objects.join("pack").join("pack-1.pack"), "x").unwrap();
+
+        let counts = count_objects_scan(&repo).unwrap();
+        assert_eq!(counts.count, 3);
+        assert!(counts.size > 0);
+        assert_eq!((counts.packs, counts.in_pack), (0, 0));
+        assert_eq!(counts.garbage.len(), 2);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
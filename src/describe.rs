@@ -0,0 +1,165 @@
+use std::{
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    abbrev_len, object_find, object_read, repo_find, sha_abbrev, tag_list, Ancestry, Repository,
+    Tag, TagFilter,
+};
+
+/// How many tagged commits the walk collects before settling on the
+/// closest, as git's default `--candidates=10`.
+const DESCRIBE_CANDIDATES: usize = 10;
+
+pub fn describe(tags: bool, abbrev: Option<usize>, rev: &str) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let abbrev = abbrev_len(&repo, abbrev);
+    let sha = object_find(&repo, rev, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("Not a valid object name {}", rev))?;
+    println!("{}", describe_commit(&repo, &sha, tags, abbrev)?);
+    Ok(())
+}
+
+/// Names `sha` after the nearest tag it contains: the tag itself if it
+/// points at `sha`, otherwise `<tag>-<n>-g<abbrev>`, where `n` counts the
+/// commits `sha` has on top of the tag and `abbrev` is `sha` shortened to
+/// that many digits. As in git, an `abbrev` of zero leaves just the tag.
+/// Only annotated tags are used unless `all_tags` is set.
+pub fn describe_commit(
+    repo: &Repository,
+    sha: &str,
+    all_tags: bool,
+    abbrev: usize,
+) -> Result<String> {
+    let names = describe_names(repo, all_tags)?;
+    if names.is_empty() {
+        bail!("No names found, cannot describe anything.");
+    }
+    if let Some(name) = names.get(sha) {
+        return Ok(name.clone());
+    }
+
+    // Walk back newest first, like git, collecting the tagged commits met.
+    let mut ancestry = Ancestry::new(repo)?;
+    let mut candidates = Vec::new();
+    let mut seen = HashSet::from([sha.to_string()]);
+    let mut queue = BinaryHeap::from([(ancestry.time(sha)?, sha.to_string())]);
+    while let Some((_, commit)) = queue.pop() {
+        if names.contains_key(&commit) {
+            candidates.push(commit);
+            if candidates.len() == DESCRIBE_CANDIDATES {
+                break;
+            }
+            continue;
+        }
+        for parent in ancestry.parents(&commit)? {
+            if seen.insert(parent.clone()) {
+                queue.push((ancestry.time(&parent)?, parent));
+            }
+        }
+    }
+
+    let mut best: Option<(usize, &String)> = None;
+    for candidate in &candidates {
+        let depth = describe_depth(&mut ancestry, sha, candidate)?;
+        if best.is_none_or(|(d, _)| depth < d) {
+            best = Some((depth, candidate));
+        }
+    }
+    match best {
+        Some((_, tagged)) if abbrev == 0 => Ok(names[tagged].clone()),
+        Some((depth, tagged)) => Ok(format!(
+            "{}-{}-g{}",
+            names[tagged],
+            depth,
+            sha_abbrev(repo, sha, abbrev)
+        )),
+        None if all_tags => bail!("No tags can describe '{}'.", sha),
+        None => bail!(
+            "No annotated tags can describe '{}'.\nHowever, there were unannotated tags: try --tags.",
+            sha
+        ),
+    }
+}
+
+/// The tag name to describe each tagged commit by. A commit with several
+/// tags goes by an annotated one if it has any, then by the first name.
+fn describe_names(repo: &Repository, all_tags: bool) -> Result<HashMap<String, String>> {
+    let mut names: HashMap<String, (bool, String)> = HashMap::new();
+    for (name, sha) in tag_list(repo, &TagFilter::default())? {
+        let annotated = object_read(repo, &sha)?
+            .as_any()
+            .downcast_ref::<Tag>()
+            .is_some();
+        if !annotated && !all_tags {
+            continue;
+        }
+        // Tags of trees and blobs describe nothing.
+        let Some(commit) = object_find(repo, &sha, Some(b"commit"), true)? else {
+            continue;
+        };
+        match names.get(&commit) {
+            Some((true, _)) => {}
+            Some((false, _)) if !annotated => {}
+            _ => {
+                names.insert(commit, (annotated, name));
+            }
+        }
+    }
+    Ok(names.into_iter().map(|(c, (_, n))| (c, n)).collect())
+}
+
+/// The number of commits reachable from `sha` but not from `tagged`.
+fn describe_depth(ancestry: &mut Ancestry, sha: &str, tagged: &str) -> Result<usize> {
+    let mut excluded = HashSet::new();
+    let mut queue = VecDeque::from([tagged.to_string()]);
+    while let Some(c) = queue.pop_front() {
+        if excluded.insert(c.clone()) {
+            queue.extend(ancestry.parents(&c)?);
+        }
+    }
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::from([sha.to_string()]);
+    while let Some(c) = queue.pop_front() {
+        if !excluded.contains(&c) && seen.insert(c.clone()) {
+            queue.extend(ancestry.parents(&c)?);
+        }
+    }
+    Ok(seen.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        describe_commit, ref_create,
+        test_utils::{make_commit_at, test_repo},
+    };
+
+    #[test]
+    fn test_describe_commit() {
+        let repo = test_repo("describe");
+        let first = make_commit_at(&repo, "1", &[], 1000);
+        let second = make_commit_at(&repo, "2", &[&first], 2000);
+        let third = make_commit_at(&repo, "3", &[&second], 3000);
+        fs::create_dir_all(repo.gitdir.join("refs/tags")).unwrap();
+        ref_create(&repo, "tags/v1", &first).unwrap();
+
+        assert!(describe_commit(&repo, &third, false, 7).is_err());
+        assert_eq!(describe_commit(&repo, &first, true, 7).unwrap(), "v1");
+        assert_eq!(
+            describe_commit(&repo, &third, true, 7).unwrap(),
+            format!("v1-2-g{}", &third[..7])
+        );
+        assert_eq!(
+            describe_commit(&repo, &third, true, 10).unwrap(),
+            format!("v1-2-g{}", &third[..10])
+        );
+        assert_eq!(describe_commit(&repo, &third, true, 0).unwrap(), "v1");
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,964 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::Write,
+    ops::Range,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    abbrev_len, attr_query, index_entry_modified, index_read, object_read, repo_find, sha_abbrev,
+    submodule_status, tree_to_leaves, worktree_file_hash, worktree_file_mode, AttrValue, Blob,
+    IgnoreSubmodules, Repository, Tree, TreeLeaf, ZERO_SHA,
+};
+
+/// One step of an edit script. Indices point into the old (`a`) and new
+/// (`b`) sequences.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffOp {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Computes a shortest edit script from `a` to `b` with Myers' O(ND)
+/// algorithm. The common prefix and suffix are stripped first, which keeps
+/// the search small for the usual case of a few local edits.
+pub fn myers_diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<DiffOp> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+
+    let mut ops: Vec<DiffOp> = (0..prefix).map(|i| DiffOp::Equal(i, i)).collect();
+    for op in myers_middle(a_mid, b_mid) {
+        ops.push(match op {
+            DiffOp::Equal(i, j) => DiffOp::Equal(i + prefix, j + prefix),
+            DiffOp::Delete(i) => DiffOp::Delete(i + prefix),
+            DiffOp::Insert(j) => DiffOp::Insert(j + prefix),
+        });
+    }
+    for k in 0..suffix {
+        ops.push(DiffOp::Equal(a.len() - suffix + k, b.len() - suffix + k));
+    }
+    ops
+}
+
+fn myers_middle<T: PartialEq>(a: &[T], b: &[T]) -> Vec<DiffOp> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max = (n + m) as usize;
+    if max == 0 {
+        return Vec::new();
+    }
+    let offset = max as isize;
+    let mut v = vec![0isize; 2 * max + 2];
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        trace.push(v.clone());
+        let mut k = -d;
+        while k <= d {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+            k += 2;
+        }
+    }
+
+    // Walk the saved frontiers backwards to recover the path.
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..trace.len() as isize).rev() {
+        let v = &trace[d as usize];
+        let k = x - y;
+        let prev_k =
+            if k == -d || (k != d && v[(k - 1 + offset) as usize] < v[(k + 1 + offset) as usize]) {
+                k + 1
+            } else {
+                k - 1
+            };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(DiffOp::Equal(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                ops.push(DiffOp::Insert(prev_y as usize));
+            } else {
+                ops.push(DiffOp::Delete(prev_x as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    ops
+}
+
+/// Splits content into lines, keeping the line terminators.
+pub fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Git's heuristic: content with a NUL in the first 8000 bytes is binary.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.iter().take(8000).any(|&b| b == 0)
+}
+
+fn hunk_range(start: usize, count: usize) -> String {
+    // An empty range names the line before it, as git does.
+    let first = if count == 0 { start } else { start + 1 };
+    if count == 1 {
+        format!("{}", first)
+    } else {
+        format!("{},{}", first, count)
+    }
+}
+
+/// Renders the hunks of a unified diff between `old` and `new` with
+/// `context` lines of context. Returns an empty buffer if they are equal.
+pub fn unified_hunks(old: &[u8], new: &[u8], context: usize) -> Vec<u8> {
+    let a = split_lines(old);
+    let b = split_lines(new);
+    let ops = myers_diff(&a, &b);
+    let mut out = Vec::new();
+    for hunk in hunk_ranges(&ops, context) {
+        out.extend(hunk_render(&ops, hunk, &a, &b));
+    }
+    out
+}
+
+/// Splits an edit script into the hunks of a unified diff with `context`
+/// lines of context, as ranges of `ops`. Changes whose context would
+/// touch or overlap share a hunk.
+pub fn hunk_ranges(ops: &[DiffOp], context: usize) -> Vec<Range<usize>> {
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for (c, op) in ops.iter().enumerate() {
+        if matches!(op, DiffOp::Equal(..)) {
+            continue;
+        }
+        match groups.last_mut() {
+            Some((_, end)) if c <= *end + 2 * context + 1 => *end = c,
+            _ => groups.push((c, c)),
+        }
+    }
+    groups
+        .into_iter()
+        .map(|(first, last)| first.saturating_sub(context)..(last + context + 1).min(ops.len()))
+        .collect()
+}
+
+/// Renders the hunk `ops[range]` of the diff between the lines `a` and
+/// `b`, header included.
+pub fn hunk_render(ops: &[DiffOp], range: Range<usize>, a: &[&[u8]], b: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let hunk = &ops[range.clone()];
+
+    // Where the hunk starts in each file.
+    let (mut old_start, mut new_start) = (0, 0);
+    for op in &ops[..range.start] {
+        match op {
+            DiffOp::Equal(..) => {
+                old_start += 1;
+                new_start += 1;
+            }
+            DiffOp::Delete(_) => old_start += 1,
+            DiffOp::Insert(_) => new_start += 1,
+        }
+    }
+    let old_count = hunk
+        .iter()
+        .filter(|op| !matches!(op, DiffOp::Insert(_)))
+        .count();
+    let new_count = hunk
+        .iter()
+        .filter(|op| !matches!(op, DiffOp::Delete(_)))
+        .count();
+    out.extend_from_slice(
+        format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_count),
+            hunk_range(new_start, new_count)
+        )
+        .as_bytes(),
+    );
+
+    for op in hunk {
+        let (prefix, line) = match *op {
+            DiffOp::Equal(i, _) => (b' ', a[i]),
+            DiffOp::Delete(i) => (b'-', a[i]),
+            DiffOp::Insert(j) => (b'+', b[j]),
+        };
+        out.push(prefix);
+        out.extend_from_slice(line);
+        if !line.ends_with(b"\n") {
+            out.extend_from_slice(b"\n\\ No newline at end of file\n");
+        }
+    }
+    out
+}
+
+/// One side of a file in a diff: its mode, blob id and where to read it.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DiffEntry {
+    pub mode: Vec<u8>,
+    pub sha: String,
+    /// Read the content from the worktree rather than the object database.
+    pub worktree: bool,
+    /// For a submodule checkout: whether it has changes of its own, which
+    /// git shows as a "-dirty" suffix on the commit.
+    pub dirty: bool,
+}
+
+/// A snapshot to compare: repository-relative path -> entry.
+pub type DiffMap = BTreeMap<String, DiffEntry>;
+
+pub fn diff(
+    staged: bool,
+    context: usize,
+    commits: &[String],
+    ignore: IgnoreSubmodules,
+    ext_diff: bool,
+    raw: bool,
+    abbrev: Option<usize>,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let (old, new) = diff_snapshots(&repo, staged, commits, ignore)?;
+    let out = match raw {
+        true => diff_raw(&repo, &old, &new, abbrev_len(&repo, abbrev)),
+        false => diff_maps(&repo, &old, &new, context, ext_diff)?,
+    };
+    std::io::stdout().write_all(&out)?;
+    Ok(())
+}
+
+/// The two snapshots `diff` compares: the index and the worktree by
+/// default, a commit instead of the index with one given (of the worktree
+/// with `staged`), or two commits.
+pub fn diff_snapshots(
+    repo: &Repository,
+    staged: bool,
+    commits: &[String],
+    ignore: IgnoreSubmodules,
+) -> Result<(DiffMap, DiffMap)> {
+    let worktree = || diff_map_worktree(repo, ignore);
+    let (mut old, mut new) = match (commits, staged) {
+        ([], false) => (diff_map_index(repo)?, worktree()?),
+        ([], true) => (diff_map_tree(repo, "HEAD")?, diff_map_index(repo)?),
+        ([c], true) => (diff_map_tree(repo, c)?, diff_map_index(repo)?),
+        ([c], false) => (diff_map_tree(repo, c)?, worktree()?),
+        ([a, b], false) => (diff_map_tree(repo, a)?, diff_map_tree(repo, b)?),
+        _ => bail!("Usage: rit diff [--staged] [<commit> [<commit>]]"),
+    };
+    if ignore == IgnoreSubmodules::All {
+        old.retain(|_, e| !e.mode.starts_with(b"16"));
+        new.retain(|_, e| !e.mode.starts_with(b"16"));
+    }
+    Ok((old, new))
+}
+
+/// A file that differs between two trees, with its leaf on each side
+/// (`None` where it does not exist).
+pub struct TreeChange {
+    pub path: String,
+    pub old: Option<TreeLeaf>,
+    pub new: Option<TreeLeaf>,
+}
+
+/// Lists the files that differ between two trees, either of which may be
+/// absent (the empty tree). Subtrees with the same id on both sides are
+/// skipped without being read, which makes this much cheaper than
+/// comparing two full `diff_map_tree` snapshots when walking history.
+pub fn diff_trees(
+    repo: &Repository,
+    old: Option<&str>,
+    new: Option<&str>,
+) -> Result<Vec<TreeChange>> {
+    let mut ret = Vec::new();
+    diff_trees_inner(repo, old, new, "", &mut ret)?;
+    Ok(ret)
+}
+
+fn diff_trees_inner(
+    repo: &Repository,
+    old: Option<&str>,
+    new: Option<&str>,
+    prefix: &str,
+    out: &mut Vec<TreeChange>,
+) -> Result<()> {
+    if old == new {
+        return Ok(());
+    }
+    let items = |sha: Option<&str>| -> Result<BTreeMap<String, TreeLeaf>> {
+        let Some(sha) = sha else {
+            return Ok(BTreeMap::new());
+        };
+        let obj = object_read(repo, sha)?;
+        let tree = obj
+            .as_any()
+            .downcast_ref::<Tree>()
+            .ok_or_else(|| anyhow!("Object {} is not a tree", sha))?;
+        Ok(tree
+            .items
+            .iter()
+            .map(|l| (l.path.clone(), l.clone()))
+            .collect())
+    };
+    let (old_items, new_items) = (items(old)?, items(new)?);
+    let names: BTreeSet<&String> = old_items.keys().chain(new_items.keys()).collect();
+
+    for name in names {
+        let path = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        let (a, b) = (old_items.get(name), new_items.get(name));
+        if let (Some(a), Some(b)) = (a, b) {
+            if a.sha == b.sha && a.mode == b.mode {
+                continue;
+            }
+        }
+        let is_tree = |l: &&TreeLeaf| l.mode.starts_with(b"04");
+        let (old_tree, new_tree) = (a.filter(is_tree), b.filter(is_tree));
+        if old_tree.is_some() || new_tree.is_some() {
+            diff_trees_inner(
+                repo,
+                old_tree.map(|l| l.sha.as_str()),
+                new_tree.map(|l| l.sha.as_str()),
+                &path,
+                out,
+            )?;
+        }
+        let file = |l: Option<&TreeLeaf>| {
+            l.filter(|l| !is_tree(l)).map(|l| TreeLeaf {
+                path: path.clone(),
+                ..l.clone()
+            })
+        };
+        let (old_file, new_file) = (file(a), file(b));
+        if old_file.is_some() || new_file.is_some() {
+            out.push(TreeChange {
+                path,
+                old: old_file,
+                new: new_file,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The files of a tree-ish.
+pub fn diff_map_tree(repo: &Repository, tree_ish: &str) -> Result<DiffMap> {
+    Ok(tree_to_leaves(repo, tree_ish, "")?
+        .into_iter()
+        .map(|leaf| {
+            let entry = DiffEntry {
+                mode: leaf.mode,
+                sha: leaf.sha,
+                worktree: false,
+                dirty: false,
+            };
+            (leaf.path, entry)
+        })
+        .collect())
+}
+
+/// The files staged in the index.
+pub fn diff_map_index(repo: &Repository) -> Result<DiffMap> {
+    Ok(index_read(repo)?
+        .entries
+        .into_iter()
+        .map(|e| {
+            let entry = DiffEntry {
+                mode: e.tree_mode(),
+                sha: e.sha.clone(),
+                worktree: false,
+                dirty: false,
+            };
+            (e.name, entry)
+        })
+        .collect())
+}
+
+/// The worktree copies of tracked files. Files whose stat data matches the
+/// index are not rehashed. Submodules are represented by the commit they
+/// have checked out, marked dirty as far as `ignore` allows. As in git,
+/// untracked files never make a submodule dirty in a diff.
+pub fn diff_map_worktree(repo: &Repository, ignore: IgnoreSubmodules) -> Result<DiffMap> {
+    let ignore = ignore.max(IgnoreSubmodules::Untracked);
+    let mut ret = DiffMap::new();
+    for e in index_read(repo)?.entries {
+        if e.mode_type == 0b1110 {
+            let sub = submodule_status(repo, &e.name, &e.sha, ignore)?;
+            let entry = DiffEntry {
+                mode: e.tree_mode(),
+                sha: sub.head.unwrap_or_else(|| e.sha.clone()),
+                worktree: false,
+                dirty: sub.modified_content,
+            };
+            ret.insert(e.name, entry);
+            continue;
+        }
+        let full_path = repo.worktree.join(&e.name);
+        // A file sparse checkout left out isn't deleted, but unchanged.
+        if fs::symlink_metadata(&full_path).is_err() && !e.flag_skip_worktree {
+            continue;
+        }
+        let entry = if index_entry_modified(repo, &e)? {
+            DiffEntry {
+                mode: worktree_file_mode(&fs::symlink_metadata(&full_path)?),
+                sha: worktree_file_hash(repo, &e.name)?,
+                worktree: true,
+                dirty: false,
+            }
+        } else {
+            DiffEntry {
+                mode: e.tree_mode(),
+                sha: e.sha.clone(),
+                worktree: false,
+                dirty: false,
+            }
+        };
+        ret.insert(e.name, entry);
+    }
+    Ok(ret)
+}
+
+/// Reads the content of one side of a diff. A submodule reads as the
+/// commit it is at, like git shows it.
+pub fn diff_entry_content(repo: &Repository, path: &str, entry: &DiffEntry) -> Result<Vec<u8>> {
+    if entry.mode.starts_with(b"16") {
+        let dirty = if entry.dirty { "-dirty" } else { "" };
+        return Ok(format!("Subproject commit {}{}\n", entry.sha, dirty).into_bytes());
+    }
+    if entry.worktree {
+        let full_path = repo.worktree.join(path);
+        if fs::symlink_metadata(&full_path)?.file_type().is_symlink() {
+            return Ok(fs::read_link(full_path)?
+                .to_string_lossy()
+                .as_bytes()
+                .to_vec());
+        }
+        return Ok(fs::read(full_path)?);
+    }
+    let obj = object_read(repo, &entry.sha)?;
+    let blob = obj
+        .as_any()
+        .downcast_ref::<Blob>()
+        .ok_or_else(|| anyhow!("Object {} is not a blob", entry.sha))?;
+    Ok(blob.blobdata.clone())
+}
+
+/// Renders a git-style diff of every path that differs between two
+/// snapshots. With `ext_diff`, files with an external diff driver are
+/// handed to it instead.
+pub fn diff_maps(
+    repo: &Repository,
+    old: &DiffMap,
+    new: &DiffMap,
+    context: usize,
+    ext_diff: bool,
+) -> Result<Vec<u8>> {
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let mut out = Vec::new();
+    for path in paths {
+        let a = old.get(path);
+        let b = new.get(path);
+        if let (Some(a), Some(b)) = (a, b) {
+            if a.sha == b.sha && a.mode == b.mode && a.dirty == b.dirty {
+                continue;
+            }
+        }
+        out.extend(diff_file(repo, path, a, b, context, ext_diff)?);
+    }
+    Ok(out)
+}
+
+/// Lists every path that differs between two snapshots in git's `--raw`
+/// format: both modes, both object names shortened to `abbrev` digits, and
+/// a status letter. Worktree content that was never hashed into an object
+/// shows as zeros, as does a missing side.
+pub fn diff_raw(repo: &Repository, old: &DiffMap, new: &DiffMap, abbrev: usize) -> Vec<u8> {
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let mut out = Vec::new();
+    for path in paths {
+        let (a, b) = (old.get(path), new.get(path));
+        if let (Some(a), Some(b)) = (a, b) {
+            if a.sha == b.sha && a.mode == b.mode && a.dirty == b.dirty {
+                continue;
+            }
+        }
+        let status = match (a, b) {
+            (None, _) => 'A',
+            (_, None) => 'D',
+            (Some(a), Some(b)) if a.mode[..2] != b.mode[..2] => 'T',
+            _ => 'M',
+        };
+        fn side(e: Option<&DiffEntry>) -> (String, &str) {
+            match e {
+                Some(e) if !e.worktree => {
+                    (String::from_utf8_lossy(&e.mode).to_string(), e.sha.as_str())
+                }
+                Some(e) => (String::from_utf8_lossy(&e.mode).to_string(), ZERO_SHA),
+                None => ("000000".to_string(), ZERO_SHA),
+            }
+        }
+        let ((old_mode, old_sha), (new_mode, new_sha)) = (side(a), side(b));
+        out.extend(
+            format!(
+                ":{} {} {} {} {}\t{}\n",
+                old_mode,
+                new_mode,
+                sha_abbrev(repo, old_sha, abbrev),
+                sha_abbrev(repo, new_sha, abbrev),
+                status,
+                path
+            )
+            .into_bytes(),
+        );
+    }
+    out
+}
+
+/// The paths that differ between two snapshots, with their entry on each
+/// side.
+pub fn diff_changed<'a>(
+    old: &'a DiffMap,
+    new: &'a DiffMap,
+) -> Vec<(&'a String, Option<&'a DiffEntry>, Option<&'a DiffEntry>)> {
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    paths
+        .into_iter()
+        .map(|path| (path, old.get(path), new.get(path)))
+        .filter(|(_, a, b)| match (a, b) {
+            (Some(a), Some(b)) => a.sha != b.sha || a.mode != b.mode || a.dirty != b.dirty,
+            _ => true,
+        })
+        .collect()
+}
+
+/// Summarizes what changed between two snapshots in git's `--stat`
+/// format, fitting the graph into `width` columns: a line per file with
+/// its count of changed lines, or its sizes if binary, then the totals.
+pub fn diff_stat(repo: &Repository, old: &DiffMap, new: &DiffMap, width: usize) -> Result<Vec<u8>> {
+    // Path, lines added and deleted, and for binary files their sizes.
+    let mut stats = Vec::new();
+    for (path, a, b) in diff_changed(old, new) {
+        let content = |e: Option<&DiffEntry>| match e {
+            Some(e) => diff_entry_content(repo, path, e),
+            None => Ok(Vec::new()),
+        };
+        let (old, new) = (content(a)?, content(b)?);
+        if is_binary(&old) || is_binary(&new) {
+            stats.push((path, 0, 0, Some((old.len(), new.len()))));
+            continue;
+        }
+        let ops = myers_diff(&split_lines(&old), &split_lines(&new));
+        let count = |f: fn(&DiffOp) -> bool| ops.iter().filter(|op| f(op)).count();
+        let added = count(|op| matches!(op, DiffOp::Insert(_)));
+        let deleted = count(|op| matches!(op, DiffOp::Delete(_)));
+        stats.push((path, added, deleted, None));
+    }
+    if stats.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Widths as git works them out: the names get what the graph leaves.
+    let max_len = stats.iter().map(|s| s.0.chars().count()).max().unwrap_or(0);
+    let max_change = stats.iter().map(|s| s.1 + s.2).max().unwrap_or(0);
+    let bin_width = stats
+        .iter()
+        .filter_map(|s| {
+            s.3.map(|(a, b)| 14 + a.to_string().len() + b.to_string().len())
+        })
+        .max();
+    let number_width = match bin_width {
+        Some(_) => max_change.to_string().len().max(3),
+        None => max_change.to_string().len(),
+    };
+    let width = width.max(16 + 6 + number_width);
+    let mut graph_width = match bin_width {
+        Some(bin_width) if max_change + 4 <= bin_width => bin_width - 4,
+        _ => max_change,
+    };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        let limit = (width * 3 / 8).saturating_sub(number_width + 6);
+        if graph_width > limit {
+            graph_width = limit.max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+    let scale = |n: usize| match n {
+        0 => 0,
+        n => 1 + n * (graph_width - 1) / max_change,
+    };
+
+    let mut out = String::new();
+    let (mut insertions, mut deletions) = (0, 0);
+    for (path, added, deleted, binary) in &stats {
+        // A name too long keeps its end, from a directory boundary if it can.
+        let len = path.chars().count();
+        let (prefix, name, pad) = if len > name_width {
+            let tail: String = path.chars().skip(len - (name_width - 3)).collect();
+            let tail = match tail.find('/') {
+                Some(slash) => tail[slash..].to_string(),
+                None => tail,
+            };
+            ("...", tail, name_width - 3)
+        } else {
+            ("", path.to_string(), name_width)
+        };
+        out.push_str(&format!(" {}{:<pad$} |", prefix, name, pad = pad));
+        if let Some((old_size, new_size)) = binary {
+            out.push_str(&format!(
+                " {:>w$} {} -> {} bytes\n",
+                "Bin",
+                old_size,
+                new_size,
+                w = number_width
+            ));
+            continue;
+        }
+        let total = added + deleted;
+        let (mut plus, mut minus) = (*added, *deleted);
+        if graph_width <= max_change {
+            let scaled = match scale(total) {
+                1 if plus > 0 && minus > 0 => 2,
+                scaled => scaled,
+            };
+            if plus < minus {
+                plus = scale(plus);
+                minus = scaled - plus;
+            } else {
+                minus = scale(minus);
+                plus = scaled - minus;
+            }
+        }
+        out.push_str(&format!(
+            " {:>w$}{}{}{}\n",
+            total,
+            if total > 0 { " " } else { "" },
+            "+".repeat(plus),
+            "-".repeat(minus),
+            w = number_width
+        ));
+        insertions += added;
+        deletions += deleted;
+    }
+
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    out.push_str(&format!(
+        " {} file{} changed",
+        stats.len(),
+        plural(stats.len())
+    ));
+    if insertions > 0 || deletions == 0 {
+        out.push_str(&format!(
+            ", {} insertion{}(+)",
+            insertions,
+            plural(insertions)
+        ));
+    }
+    if deletions > 0 || insertions == 0 {
+        out.push_str(&format!(", {} deletion{}(-)", deletions, plural(deletions)));
+    }
+    out.push('\n');
+    Ok(out.into_bytes())
+}
+
+/// Lists the files created and deleted between two snapshots and those
+/// whose mode changed, as git's `--summary` does.
+pub fn diff_summary(old: &DiffMap, new: &DiffMap) -> Vec<u8> {
+    let mode = |e: &DiffEntry| String::from_utf8_lossy(&e.mode).to_string();
+    let mut out = String::new();
+    for (path, a, b) in diff_changed(old, new) {
+        match (a, b) {
+            (None, Some(b)) => out.push_str(&format!(" create mode {} {}\n", mode(b), path)),
+            (Some(a), None) => out.push_str(&format!(" delete mode {} {}\n", mode(a), path)),
+            (Some(a), Some(b)) if a.mode != b.mode => out.push_str(&format!(
+                " mode change {} => {} {}\n",
+                mode(a),
+                mode(b),
+                path
+            )),
+            _ => {}
+        }
+    }
+    out.into_bytes()
+}
+
+/// How the `diff` attribute and the diff.<driver>.* config say a file
+/// should be diffed.
+enum DiffDriver {
+    /// Let the content decide whether it is binary.
+    Auto,
+    Text,
+    Binary,
+    /// Run this command with git's 7-argument external diff interface.
+    External(String),
+}
+
+fn diff_driver(repo: &Repository, path: &str, ext_diff: bool) -> Result<DiffDriver> {
+    let attrs = attr_query(repo, path)?;
+    let section = match attrs.get("diff") {
+        Some(AttrValue::Value(driver)) => Some(format!("diff \"{}\"", driver)),
+        _ => None,
+    };
+    if ext_diff {
+        // A driver's own command wins over the catch-all ones, which apply
+        // even to files marked -diff.
+        let command = section
+            .as_ref()
+            .and_then(|s| repo.config_get(s, "command"))
+            .or_else(|| std::env::var("GIT_EXTERNAL_DIFF").ok())
+            .or_else(|| repo.config_get("diff", "external"))
+            .filter(|c| !c.is_empty());
+        if let Some(command) = command {
+            return Ok(DiffDriver::External(command));
+        }
+    }
+    Ok(match attrs.get("diff") {
+        Some(AttrValue::Set) => DiffDriver::Text,
+        Some(AttrValue::Unset) => DiffDriver::Binary,
+        _ => match section
+            .and_then(|s| repo.config_get(&s, "binary"))
+            .as_deref()
+        {
+            Some("true") => DiffDriver::Binary,
+            _ => DiffDriver::Auto,
+        },
+    })
+}
+
+/// Runs an external diff command as git does:
+/// `<command> path old-file old-hex old-mode new-file new-hex new-mode`,
+/// with /dev/null and "." standing in for a missing side, and a zero id
+/// for worktree content. Returns what the command printed.
+fn diff_external(
+    repo: &Repository,
+    command: &str,
+    path: &str,
+    a: Option<&DiffEntry>,
+    b: Option<&DiffEntry>,
+) -> Result<Vec<u8>> {
+    let mut temps = Vec::new();
+    let mut args = vec![path.to_string()];
+    for (side, entry) in [("old", a), ("new", b)] {
+        let Some(entry) = entry else {
+            args.extend(["/dev/null", ".", "."].map(String::from));
+            continue;
+        };
+        if entry.worktree {
+            args.extend([path.to_string(), "0".repeat(40)]);
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            let temp =
+                std::env::temp_dir().join(format!("rit-{}-{}-{}", std::process::id(), side, name));
+            fs::write(&temp, diff_entry_content(repo, path, entry)?)?;
+            args.extend([temp.to_string_lossy().to_string(), entry.sha.clone()]);
+            temps.push(temp);
+        }
+        args.push(String::from_utf8_lossy(&entry.mode).to_string());
+    }
+
+    // Like git, the command goes through the shell so it may carry its own
+    // arguments.
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", command))
+        .arg(command)
+        .args(&args)
+        .current_dir(&repo.worktree)
+        .stderr(Stdio::inherit())
+        .output();
+    for temp in temps {
+        let _ = fs::remove_file(temp);
+    }
+    let output = output?;
+    if !output.status.success() {
+        bail!("external diff died, stopping at {}", path);
+    }
+    Ok(output.stdout)
+}
+
+/// Renders the diff of a single file. Either side may be missing, for
+/// added and deleted files.
+pub fn diff_file(
+    repo: &Repository,
+    path: &str,
+    a: Option<&DiffEntry>,
+    b: Option<&DiffEntry>,
+    context: usize,
+    ext_diff: bool,
+) -> Result<Vec<u8>> {
+    let is_gitlink = |e: Option<&DiffEntry>| e.is_some_and(|e| e.mode.starts_with(b"16"));
+    let driver = if is_gitlink(a) || is_gitlink(b) {
+        DiffDriver::Text
+    } else {
+        diff_driver(repo, path, ext_diff)?
+    };
+    if let DiffDriver::External(command) = &driver {
+        return diff_external(repo, command, path, a, b);
+    }
+
+    let mut out = Vec::new();
+    let mode_str = |e: &DiffEntry| String::from_utf8_lossy(&e.mode).to_string();
+    let abbrev = abbrev_len(repo, None);
+    let zero = &ZERO_SHA[..if abbrev == 0 { ZERO_SHA.len() } else { abbrev }];
+    let short = |e: Option<&DiffEntry>| {
+        e.map(|e| sha_abbrev(repo, &e.sha, abbrev))
+            .unwrap_or(zero.to_string())
+    };
+
+    out.extend_from_slice(format!("diff --git a/{} b/{}\n", path, path).as_bytes());
+    match (a, b) {
+        (None, Some(b)) => {
+            out.extend_from_slice(format!("new file mode {}\n", mode_str(b)).as_bytes());
+            out.extend_from_slice(
+                format!("index {}..{}\n", short(None), short(Some(b))).as_bytes(),
+            );
+        }
+        (Some(a), None) => {
+            out.extend_from_slice(format!("deleted file mode {}\n", mode_str(a)).as_bytes());
+            out.extend_from_slice(
+                format!("index {}..{}\n", short(Some(a)), short(None)).as_bytes(),
+            );
+        }
+        (Some(a), Some(b)) => {
+            if a.mode != b.mode {
+                out.extend_from_slice(format!("old mode {}\n", mode_str(a)).as_bytes());
+                out.extend_from_slice(format!("new mode {}\n", mode_str(b)).as_bytes());
+            }
+            if a.sha == b.sha {
+                // Only a submodule's worktree can change without its id.
+                if a.dirty == b.dirty {
+                    return Ok(out);
+                }
+            } else {
+                let mode = if a.mode == b.mode {
+                    format!(" {}", mode_str(a))
+                } else {
+                    String::new()
+                };
+                out.extend_from_slice(
+                    format!("index {}..{}{}\n", short(Some(a)), short(Some(b)), mode).as_bytes(),
+                );
+            }
+        }
+        (None, None) => return Ok(out),
+    }
+
+    let old = match a {
+        Some(e) => diff_entry_content(repo, path, e)?,
+        None => Vec::new(),
+    };
+    let new = match b {
+        Some(e) => diff_entry_content(repo, path, e)?,
+        None => Vec::new(),
+    };
+
+    let old_name = if a.is_some() {
+        format!("a/{}", path)
+    } else {
+        "/dev/null".to_string()
+    };
+    let new_name = if b.is_some() {
+        format!("b/{}", path)
+    } else {
+        "/dev/null".to_string()
+    };
+
+    let binary = match driver {
+        DiffDriver::Binary => true,
+        DiffDriver::Text => false,
+        _ => is_binary(&old) || is_binary(&new),
+    };
+    if binary {
+        out.extend_from_slice(
+            format!("Binary files {} and {} differ\n", old_name, new_name).as_bytes(),
+        );
+        return Ok(out);
+    }
+
+    let hunks = unified_hunks(&old, &new, context);
+    if !hunks.is_empty() {
+        out.extend_from_slice(format!("--- {}\n+++ {}\n", old_name, new_name).as_bytes());
+        out.extend(hunks);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{myers_diff, unified_hunks, DiffOp};
+
+    #[test]
+    fn test_myers_diff_is_minimal() {
+        let a: Vec<char> = "ABCABBA".chars().collect();
+        let b: Vec<char> = "CBABAC".chars().collect();
+        let ops = myers_diff(&a, &b);
+        let edits = ops
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Equal(..)))
+            .count();
+        assert_eq!(edits, 5);
+
+        // Replaying the script must reproduce `b`.
+        let mut out = Vec::new();
+        for op in ops {
+            match op {
+                DiffOp::Equal(i, _) => out.push(a[i]),
+                DiffOp::Insert(j) => out.push(b[j]),
+                DiffOp::Delete(_) => {}
+            }
+        }
+        assert_eq!(out, b);
+    }
+
+    #[test]
+    fn test_unified_hunks() {
+        let old = b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let new = b"1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\neleven";
+        let out = String::from_utf8(unified_hunks(old, new, 1)).unwrap();
+        assert_eq!(
+            out,
+            "@@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n\
+             @@ -10 +10,2 @@\n 10\n+eleven\n\\ No newline at end of file\n"
+        );
+        assert_eq!(
+            String::from_utf8(unified_hunks(b"", b"a\n", 3)).unwrap(),
+            "@@ -0,0 +1 @@\n+a\n"
+        );
+        assert!(unified_hunks(old, old, 3).is_empty());
+    }
+}
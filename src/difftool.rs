@@ -0,0 +1,221 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    diff_changed, diff_entry_content, diff_snapshots, prompt_available, prompt_yes_no, repo_find,
+    DiffEntry, IgnoreSubmodules, Repository,
+};
+
+/// How `difftool` picks what to compare and how it shows it.
+#[derive(Default)]
+pub struct DifftoolOptions {
+    /// Compare the index against HEAD (or the given commit).
+    pub staged: bool,
+    /// The tool to run instead of `diff.tool`.
+    pub tool: Option<String>,
+    /// Run the tool once on two directories holding every changed file,
+    /// rather than once per file.
+    pub dir_diff: bool,
+    /// Whether to ask before each file; by default `difftool.prompt`.
+    pub prompt: Option<bool>,
+}
+
+/// Shows what `diff` would with an external tool: each changed file has
+/// its two sides written to temporary files, worktree content used in
+/// place, and `difftool.<tool>.cmd` (or a known tool's usual command) run
+/// with `$LOCAL` and `$REMOTE` naming them.
+pub fn difftool(commits: &[String], opts: &DifftoolOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let tool = opts
+        .tool
+        .clone()
+        .or_else(|| repo.config_get("diff", "tool"))
+        .or_else(|| repo.config_get("merge", "tool"))
+        .ok_or_else(|| anyhow!("no diff tool configured; set diff.tool or use --tool"))?;
+    let command = difftool_command(&repo, &tool)?;
+    let trust_exit_code = repo
+        .config_get_bool("difftool", "trustExitCode")?
+        .unwrap_or(false);
+    let (old, new) = diff_snapshots(&repo, opts.staged, commits, IgnoreSubmodules::None)?;
+    let changed = diff_changed(&old, &new);
+
+    if opts.dir_diff {
+        return difftool_dir(&repo, &command, &changed);
+    }
+    let prompt = match opts.prompt {
+        Some(prompt) => prompt,
+        None => repo.config_get_bool("difftool", "prompt")?.unwrap_or(true),
+    };
+    for (i, &(path, a, b)) in changed.iter().enumerate() {
+        if prompt && prompt_available() {
+            let question = format!(
+                "\nViewing ({}/{}): '{}'\nLaunch '{}' [Y/n]? ",
+                i + 1,
+                changed.len(),
+                path,
+                tool
+            );
+            if !prompt_yes_no(&question, true)? {
+                continue;
+            }
+        }
+        let mut temps = Vec::new();
+        let mut sides = Vec::new();
+        for (side, entry) in [("LOCAL", a), ("REMOTE", b)] {
+            match entry {
+                Some(entry) if entry.worktree => sides.push(repo.worktree.join(path)),
+                _ => {
+                    let temp = difftool_temp_file(&repo, path, side, entry)?;
+                    sides.push(temp.clone());
+                    temps.push(temp);
+                }
+            }
+        }
+        let ok = difftool_launch(&repo, &command, &sides[0], &sides[1], path);
+        for temp in temps {
+            let _ = fs::remove_file(temp);
+        }
+        if !ok? && trust_exit_code {
+            bail!("external diff tool '{}' failed on {}", tool, path);
+        }
+    }
+    Ok(())
+}
+
+/// The shell command to run for `tool`: its `difftool.<tool>.cmd`, or
+/// the usual way to compare two files with the tools git knows about.
+fn difftool_command(repo: &Repository, tool: &str) -> Result<String> {
+    if let Some(cmd) = repo.config_get(&format!("difftool \"{}\"", tool), "cmd") {
+        return Ok(cmd);
+    }
+    let path = repo
+        .config_get(&format!("difftool \"{}\"", tool), "path")
+        .unwrap_or_else(|| tool.to_string());
+    Ok(match tool {
+        "vimdiff" | "nvimdiff" | "gvimdiff" => format!("{} -R -f -d \"$LOCAL\" \"$REMOTE\"", path),
+        "meld" | "kdiff3" | "kompare" | "diffuse" | "tkdiff" | "xxdiff" => {
+            format!("{} \"$LOCAL\" \"$REMOTE\"", path)
+        }
+        "opendiff" => format!("{} \"$LOCAL\" \"$REMOTE\" | cat", path),
+        "vscode" => "code --wait --diff \"$LOCAL\" \"$REMOTE\"".to_string(),
+        _ => bail!("unknown diff tool '{}': set difftool.{}.cmd", tool, tool),
+    })
+}
+
+/// Writes one side of `path` to a temporary file keeping its name, so that
+/// tools still recognize the file type. A missing side is an empty file.
+fn difftool_temp_file(
+    repo: &Repository,
+    path: &str,
+    side: &str,
+    entry: Option<&DiffEntry>,
+) -> Result<PathBuf> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let temp = std::env::temp_dir().join(format!("rit-{}-{}-{}", std::process::id(), side, name));
+    let content = match entry {
+        Some(entry) => diff_entry_content(repo, path, entry)?,
+        None => Vec::new(),
+    };
+    fs::write(&temp, content)?;
+    Ok(temp)
+}
+
+/// Compares two trees in one go: the old and new side of every changed
+/// file are written below `left/` and `right/` of a temporary directory,
+/// given to the tool as `$LOCAL` and `$REMOTE`. Edits made in `right/` to
+/// files that come from the worktree are copied back there.
+fn difftool_dir(
+    repo: &Repository,
+    command: &str,
+    changed: &[(&String, Option<&DiffEntry>, Option<&DiffEntry>)],
+) -> Result<()> {
+    if changed.is_empty() {
+        return Ok(());
+    }
+    let dir = std::env::temp_dir().join(format!("rit-difftool-{}", std::process::id()));
+    let (left, right) = (dir.join("left"), dir.join("right"));
+    let result = (|| -> Result<()> {
+        let mut worktree_files = Vec::new();
+        for &(path, a, b) in changed {
+            for (root, entry) in [(&left, a), (&right, b)] {
+                let Some(entry) = entry else {
+                    continue;
+                };
+                let file = root.join(path);
+                fs::create_dir_all(file.parent().unwrap())?;
+                let content = diff_entry_content(repo, path, entry)?;
+                fs::write(&file, &content)?;
+                if entry.worktree && root == &right {
+                    worktree_files.push((path, content));
+                }
+            }
+        }
+        fs::create_dir_all(&left)?;
+        fs::create_dir_all(&right)?;
+        difftool_launch(repo, command, &left, &right, "")?;
+        for (path, content) in worktree_files {
+            let edited = fs::read(right.join(path))?;
+            if edited != content {
+                fs::write(repo.worktree.join(path), edited)?;
+            }
+        }
+        Ok(())
+    })();
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// Runs the tool through the shell, as git does, with `$LOCAL`, `$REMOTE`
+/// and `$MERGED`/`$BASE` (the path being compared) set. Returns whether
+/// it exited successfully.
+fn difftool_launch(
+    repo: &Repository,
+    command: &str,
+    local: &Path,
+    remote: &Path,
+    path: &str,
+) -> Result<bool> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("LOCAL", local)
+        .env("REMOTE", remote)
+        .env("MERGED", path)
+        .env("BASE", path)
+        .current_dir(&repo.worktree)
+        .status()?;
+    Ok(status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::difftool_command;
+    use crate::test_utils::test_repo;
+
+    #[test]
+    fn test_difftool_command() {
+        let mut repo = test_repo("difftool");
+        assert_eq!(
+            difftool_command(&repo, "meld").unwrap(),
+            "meld \"$LOCAL\" \"$REMOTE\""
+        );
+        assert!(difftool_command(&repo, "mine").is_err());
+        repo.config_set("difftool \"mine\"", "cmd", "cmp $LOCAL $REMOTE")
+            .unwrap();
+        assert_eq!(
+            difftool_command(&repo, "mine").unwrap(),
+            "cmp $LOCAL $REMOTE"
+        );
+        repo.config_set("difftool \"meld\"", "path", "/opt/meld")
+            .unwrap();
+        assert_eq!(
+            difftool_command(&repo, "meld").unwrap(),
+            "/opt/meld \"$LOCAL\" \"$REMOTE\""
+        );
+    }
+}
@@ -0,0 +1,485 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_get_active, fetch_pack, file_write_locked, gc_auto, object_find, object_read,
+    ref_list_flat, ref_resolve, reflog_append, repo_file, repo_find, sha_short, url_rewrite,
+    Ancestry, RemoteRefs, Repository, Transport,
+};
+
+/// A `[+]<src>[:<dst>]` refspec. Either side may hold one `*`, standing
+/// for the same text on both.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Refspec {
+    /// Whether non-fast-forward updates of `dst` are allowed.
+    pub force: bool,
+    pub src: String,
+    pub dst: Option<String>,
+}
+
+impl Refspec {
+    pub fn parse(spec: &str) -> Result<Refspec> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, spec),
+        };
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (src, Some(dst.to_string()).filter(|d| !d.is_empty())),
+            None => (spec, None),
+        };
+        let globs = |s: &str| s.matches('*').count();
+        if globs(src) > 1 || dst.as_deref().is_some_and(|d| globs(d) != globs(src)) {
+            bail!("invalid refspec '{}'", spec);
+        }
+        Ok(Refspec {
+            force,
+            src: src.to_string(),
+            dst,
+        })
+    }
+
+    /// Whether the source side matches `name`, and if so the name it maps
+    /// to on the destination side.
+    pub fn map(&self, name: &str) -> Option<Option<String>> {
+        let Some((prefix, suffix)) = self.src.split_once('*') else {
+            return (self.src == name).then(|| self.dst.clone());
+        };
+        let middle = name
+            .strip_prefix(prefix)?
+            .strip_suffix(suffix)
+            .filter(|m| !m.is_empty())?;
+        Some(self.dst.as_ref().map(|d| d.replacen('*', middle, 1)))
+    }
+}
+
+/// One remote ref brought in by a fetch.
+struct FetchedRef {
+    name: String,
+    sha: String,
+    /// The local ref it updates, if any.
+    local: Option<String>,
+    force: bool,
+    for_merge: bool,
+}
+
+pub fn fetch(remote: Option<&str>, refspecs: &[String]) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let remote = match remote {
+        Some(remote) => remote.to_string(),
+        None => remote_default(&repo)?,
+    };
+    fetch_remote(&repo, &remote, refspecs)
+}
+
+/// The remote of the current branch's upstream, or "origin".
+pub fn remote_default(repo: &Repository) -> Result<String> {
+    let configured = branch_get_active(repo)?
+        .and_then(|branch| repo.config_get(&format!("branch \"{}\"", branch), "remote"));
+    Ok(configured.unwrap_or_else(|| "origin".to_string()))
+}
+
+/// Fetches `remote`, either the name of a configured remote or a URL,
+/// updating the refs its fetch refspecs map to and writing FETCH_HEAD.
+/// A bare URL only fetches its HEAD. Given `refspecs` are fetched instead
+/// of the configured ones, which still say where the refs they fetch are
+/// tracked.
+pub fn fetch_remote(repo: &Repository, remote: &str, refspecs: &[String]) -> Result<()> {
+    let section = format!("remote \"{}\"", remote);
+    let (url, mut specs) = match repo.config_get(&section, "url") {
+        Some(url) => {
+            let specs = repo
+                .config_get_all(&section, "fetch")
+                .iter()
+                .map(|s| Refspec::parse(s))
+                .collect::<Result<Vec<_>>>()?;
+            (url, specs)
+        }
+        None if remote.contains(['/', ':']) || Path::new(remote).exists() => {
+            (remote.to_string(), vec![Refspec::parse("HEAD")?])
+        }
+        None => bail!(
+            "'{}' does not appear to be a git repository\n\
+             Could not read from remote repository.",
+            remote
+        ),
+    };
+
+    let tracking = match refspecs.is_empty() {
+        true => Vec::new(),
+        false => std::mem::replace(
+            &mut specs,
+            refspecs
+                .iter()
+                .map(|s| Refspec::parse(s))
+                .collect::<Result<Vec<_>>>()?,
+        ),
+    };
+
+    let url = url_rewrite(Some(repo), &url, false);
+    let mut transport = Transport::open(&url, Some(repo))?;
+    let advertised = transport.advertise("git-upload-pack")?;
+    let mut fetched = fetch_select(repo, remote, &advertised, &specs)?;
+    for r in fetched.iter_mut().filter(|r| r.local.is_none()) {
+        if let Some((spec, local)) = tracking
+            .iter()
+            .find_map(|s| s.map(&r.name).flatten().map(|local| (s, local)))
+        {
+            r.local = Some(local);
+            r.force = spec.force;
+        }
+    }
+    let haves: Vec<String> = ref_list_flat(repo, None, Some("refs"))?
+        .into_values()
+        .collect();
+    let mut ancestry = Ancestry::new(repo)?;
+    let mut wants: Vec<String> = Vec::new();
+    for r in &fetched {
+        if !wants.contains(&r.sha) && !fetch_have_complete(repo, &mut ancestry, &r.sha, &haves) {
+            wants.push(r.sha.clone());
+        }
+    }
+    fetch_pack(repo, &mut transport, &wants, &haves)?;
+
+    // Follow tags that point into the history we now have.
+    let mut tags = Vec::new();
+    for (name, sha) in &advertised.refs {
+        if !name.starts_with("refs/tags/") || name.ends_with("^{}") {
+            continue;
+        }
+        if ref_resolve(repo, name)?.is_some() || fetched.iter().any(|r| r.name == *name) {
+            continue;
+        }
+        let target = advertised.get(&format!("{}^{{}}", name)).unwrap_or(sha);
+        if object_read(repo, target).is_ok() {
+            tags.push((name.clone(), sha.clone()));
+        }
+    }
+    let missing: Vec<String> = tags
+        .iter()
+        .map(|(_, sha)| sha.clone())
+        .filter(|sha| object_read(repo, sha).is_err())
+        .collect();
+    fetch_pack(repo, &mut transport, &missing, &haves)?;
+    fetched.extend(tags.into_iter().map(|(name, sha)| FetchedRef {
+        local: Some(name.clone()),
+        name,
+        sha,
+        force: false,
+        for_merge: false,
+    }));
+
+    fetch_refs_update(repo, &url, &fetched)?;
+    fetch_head_write(repo, &url, &fetched)?;
+    gc_auto(repo);
+    Ok(())
+}
+
+/// Whether `sha` is here with all of its history: it or the commit it
+/// tags is reachable from one of our refs (the `haves`), which only ever
+/// point at complete history. An object that is merely present may be
+/// left from a fetch cut short, its ancestors still missing, and is asked
+/// for again; the remote then sends what the `haves` don't cover.
+fn fetch_have_complete(
+    repo: &Repository,
+    ancestry: &mut Ancestry,
+    sha: &str,
+    haves: &[String],
+) -> bool {
+    if haves.iter().any(|have| have == sha) {
+        return true;
+    }
+    let peel = |sha: &str| object_find(repo, sha, Some(b"commit"), true).ok().flatten();
+    let Some(commit) = peel(sha) else {
+        return false;
+    };
+    haves
+        .iter()
+        .filter_map(|have| peel(have))
+        .any(|have| ancestry.is_ancestor(&commit, &have).unwrap_or(false))
+}
+
+/// The advertised refs that `specs` select, with the ref of the current
+/// branch's upstream marked for merging.
+fn fetch_select(
+    repo: &Repository,
+    remote: &str,
+    advertised: &RemoteRefs,
+    specs: &[Refspec],
+) -> Result<Vec<FetchedRef>> {
+    let merge = branch_get_active(repo)?.and_then(|branch| {
+        let section = format!("branch \"{}\"", branch);
+        (repo.config_get(&section, "remote").as_deref() == Some(remote))
+            .then(|| repo.config_get(&section, "merge"))
+            .flatten()
+    });
+    let mut fetched = Vec::new();
+    for spec in specs {
+        let spec = &refspec_dwim(spec, advertised);
+        let mut matched = false;
+        for (name, sha) in &advertised.refs {
+            if name.ends_with("^{}") {
+                continue;
+            }
+            let Some(local) = spec.map(name) else {
+                continue;
+            };
+            matched = true;
+            fetched.push(FetchedRef {
+                name: name.clone(),
+                sha: sha.clone(),
+                local,
+                force: spec.force,
+                // A single ref fetched only into FETCH_HEAD is meant for merging.
+                for_merge: merge.as_deref() == Some(name.as_str())
+                    || !spec.src.contains('*') && spec.dst.is_none(),
+            });
+        }
+        if !matched && !spec.src.contains('*') {
+            bail!("couldn't find remote ref {}", spec.src);
+        }
+    }
+    Ok(fetched)
+}
+
+/// Spells out the source of a refspec without a glob, like "main", as the
+/// advertised ref it stands for, trying the prefixes git tries for local
+/// names.
+fn refspec_dwim(spec: &Refspec, advertised: &RemoteRefs) -> Refspec {
+    let src = match spec.src.contains('*') {
+        true => None,
+        false => ["", "refs/", "refs/tags/", "refs/heads/", "refs/remotes/"]
+            .iter()
+            .map(|prefix| format!("{}{}", prefix, spec.src))
+            .find(|name| advertised.get(name).is_some()),
+    };
+    Refspec {
+        force: spec.force,
+        src: src.unwrap_or_else(|| spec.src.clone()),
+        dst: spec.dst.clone(),
+    }
+}
+
+/// Moves the local refs to what was fetched, refusing non-fast-forward
+/// updates unless forced, and reports each change as git does.
+fn fetch_refs_update(repo: &Repository, url: &str, fetched: &[FetchedRef]) -> Result<()> {
+    let mut ancestry = Ancestry::new(repo)?;
+    let mut lines = Vec::new();
+    let mut rejected = false;
+    for r in fetched {
+        let Some(local) = &r.local else {
+            if r.for_merge {
+                lines.push(('*', "branch".to_string(), r.name.as_str(), "FETCH_HEAD", ""));
+            }
+            continue;
+        };
+        let old = ref_resolve(repo, local)?;
+        let abbrev = |sha: &str| sha_short(repo, sha);
+        let (flag, summary, message, suffix) = match &old {
+            Some(old) if *old == r.sha => continue,
+            None if local.starts_with("refs/tags/") => {
+                ('*', "[new tag]".to_string(), "storing head", "")
+            }
+            None if r.name.starts_with("refs/heads/") => {
+                ('*', "[new branch]".to_string(), "storing head", "")
+            }
+            None => ('*', "[new ref]".to_string(), "storing head", ""),
+            Some(old) if ancestry.is_ancestor(old, &r.sha)? => (
+                ' ',
+                format!("{}..{}", abbrev(old), abbrev(&r.sha)),
+                "fast-forward",
+                "",
+            ),
+            Some(old) if r.force => (
+                '+',
+                format!("{}...{}", abbrev(old), abbrev(&r.sha)),
+                "forced-update",
+                "  (forced update)",
+            ),
+            Some(_) => {
+                rejected = true;
+                lines.push((
+                    '!',
+                    "[rejected]".to_string(),
+                    &r.name,
+                    local,
+                    "  (non-fast-forward)",
+                ));
+                continue;
+            }
+        };
+        let path = repo_file(repo, PathBuf::from(local), true)?;
+        file_write_locked(&path, format!("{}\n", r.sha).as_bytes(), repo.fsync.refs)?;
+        reflog_append(
+            repo,
+            local,
+            old.as_deref(),
+            &r.sha,
+            &format!("fetch: {}", message),
+        )?;
+        lines.push((flag, summary, &r.name, local, suffix));
+    }
+
+    if !lines.is_empty() {
+        let short = |name: &str| {
+            ["refs/heads/", "refs/tags/", "refs/remotes/"]
+                .iter()
+                .find_map(|p| name.strip_prefix(p))
+                .unwrap_or(name)
+                .to_string()
+        };
+        let width = lines
+            .iter()
+            .map(|(_, _, name, _, _)| short(name).len())
+            .max()
+            .unwrap_or(0)
+            .max(10);
+        eprintln!("From {}", url.trim_end_matches('/'));
+        for (flag, summary, name, local, suffix) in lines {
+            eprintln!(
+                " {} {:<17} {:<width$} -> {}{}",
+                flag,
+                summary,
+                short(name),
+                short(local),
+                suffix,
+                width = width
+            );
+        }
+    }
+    if rejected {
+        bail!("some local refs could not be updated");
+    }
+    Ok(())
+}
+
+/// Records what was fetched in FETCH_HEAD, the refs to merge first, for
+/// `pull` to pick up.
+fn fetch_head_write(repo: &Repository, url: &str, fetched: &[FetchedRef]) -> Result<()> {
+    let url = url.trim_end_matches('/');
+    let mut out = String::new();
+    for for_merge in [true, false] {
+        for r in fetched.iter().filter(|r| r.for_merge == for_merge) {
+            let what = if let Some(branch) = r.name.strip_prefix("refs/heads/") {
+                format!("branch '{}' of {}", branch, url)
+            } else if let Some(tag) = r.name.strip_prefix("refs/tags/") {
+                format!("tag '{}' of {}", tag, url)
+            } else if r.name == "HEAD" {
+                url.to_string()
+            } else {
+                format!("'{}' of {}", r.name, url)
+            };
+            let marker = if for_merge { "" } else { "not-for-merge" };
+            out.push_str(&format!("{}\t{}\t{}\n", r.sha, marker, what));
+        }
+    }
+    file_write_locked(
+        &repo_file(repo, PathBuf::from("FETCH_HEAD"), false)?,
+        out.as_bytes(),
+        false,
+    )
+    .map_err(|e| anyhow!("cannot write FETCH_HEAD: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{fetch_remote, Refspec};
+    use crate::{
+        object_list_loose, object_read, object_write, ref_create, ref_resolve,
+        test_utils::{make_commit, test_repo},
+        Blob, Repository,
+    };
+
+    #[test]
+    fn test_refspec_map() {
+        let spec = Refspec::parse("+refs/heads/*:refs/remotes/origin/*").unwrap();
+        assert!(spec.force);
+        assert_eq!(
+            spec.map("refs/heads/topic/x"),
+            Some(Some("refs/remotes/origin/topic/x".to_string()))
+        );
+        assert_eq!(spec.map("refs/tags/v1"), None);
+
+        let spec = Refspec::parse("refs/heads/main:refs/heads/upstream").unwrap();
+        assert!(!spec.force);
+        assert_eq!(
+            spec.map("refs/heads/main"),
+            Some(Some("refs/heads/upstream".to_string()))
+        );
+        assert_eq!(Refspec::parse("HEAD").unwrap().map("HEAD"), Some(None));
+        assert!(Refspec::parse("refs/heads/*:refs/x").is_err());
+    }
+
+    #[test]
+    fn test_fetch_gc_auto() {
+        let source = test_repo("fetch-gc-source");
+        // gc.auto is estimated from objects/17, so one must land there.
+        let content = (0..)
+            .map(|i| format!("content {}", i))
+            .find(|c| {
+                object_write(&Blob::new(c.as_bytes()), None)
+                    .unwrap()
+                    .starts_with("17")
+            })
+            .unwrap();
+        let sha = make_commit(&source, &content, &[]);
+        ref_create(&source, "heads/master", &sha).unwrap();
+
+        let mut repo = test_repo("fetch-gc");
+        let section = "remote \"origin\"";
+        repo.config_set(section, "url", &source.worktree.display().to_string())
+            .unwrap();
+        repo.config_set(section, "fetch", "+refs/heads/*:refs/remotes/origin/*")
+            .unwrap();
+        repo.config_set("gc", "auto", "1").unwrap();
+        repo.config_set("gc", "autoDetach", "false").unwrap();
+
+        fetch_remote(&repo, "origin", &[]).unwrap();
+        assert_eq!(
+            ref_resolve(&repo, "refs/remotes/origin/master").unwrap(),
+            Some(sha.clone())
+        );
+        assert!(object_list_loose(&repo).unwrap().is_empty());
+        assert!(object_read(&repo, &sha).is_ok());
+        let packs = fs::read_dir(repo.gitdir.join("objects/pack")).unwrap();
+        assert!(packs
+            .filter_map(|e| e.ok())
+            .any(|e| e.path().extension().is_some_and(|x| x == "pack")));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+        fs::remove_dir_all(&source.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_present_tip_missing_history() {
+        let source = test_repo("fetch-partial-source");
+        let first = make_commit(&source, "one", &[]);
+        let second = make_commit(&source, "two", &[&first]);
+        ref_create(&source, "heads/master", &second).unwrap();
+
+        let mut repo = test_repo("fetch-partial");
+        let section = "remote \"origin\"";
+        repo.config_set(section, "url", &source.worktree.display().to_string())
+            .unwrap();
+        repo.config_set(section, "fetch", "+refs/heads/*:refs/remotes/origin/*")
+            .unwrap();
+        // Only the tip made it here, as from a fetch that was cut short.
+        let loose = |repo: &Repository| repo.gitdir.join("objects").join(&second[..2]);
+        fs::create_dir_all(loose(&repo)).unwrap();
+        fs::copy(
+            loose(&source).join(&second[2..]),
+            loose(&repo).join(&second[2..]),
+        )
+        .unwrap();
+
+        fetch_remote(&repo, "origin", &[]).unwrap();
+        assert!(object_read(&repo, &first).is_ok());
+        assert_eq!(
+            ref_resolve(&repo, "refs/remotes/origin/master").unwrap(),
+            Some(second)
+        );
+        fs::remove_dir_all(&repo.worktree).unwrap();
+        fs::remove_dir_all(&source.worktree).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    date_format_rfc2822, diff_map_tree, diff_maps, diff_stat, diff_summary, ident_parse,
+    kvlm_get_str, object_read, repo_find, rev_walk, Commit, DiffMap, Repository,
+};
+
+/// How `format_patch` names and writes its patches.
+#[derive(Default)]
+pub struct FormatPatchOptions {
+    /// Write every patch to stdout rather than a file each.
+    pub stdout: bool,
+    /// Whether subjects say "[PATCH n/m]"; by default only in a series of
+    /// more than one.
+    pub numbered: Option<bool>,
+    /// Where the files go, instead of the current directory.
+    pub output_dir: Option<PathBuf>,
+}
+
+/// Writes the commits `range` selects as patch mails that `am` applies,
+/// oldest first: `<since>` picks those after it up to HEAD, `a..b` those
+/// in b but not a. Merges are left out, since they have no single diff.
+pub fn format_patch(range: &str, opts: &FormatPatchOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let range = match range.contains("..") {
+        true => range.to_string(),
+        false => format!("{}..HEAD", range),
+    };
+    let mut commits = Vec::new();
+    for sha in rev_walk(&repo, &[range])?.into_iter().rev() {
+        let obj = object_read(&repo, &sha)?;
+        let commit = obj
+            .as_any()
+            .downcast_ref::<Commit>()
+            .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+        if commit.parents().len() <= 1 {
+            commits.push((sha, obj));
+        }
+    }
+
+    let numbered = opts.numbered.unwrap_or(commits.len() > 1);
+    let dir = opts.output_dir.clone().unwrap_or_default();
+    if !opts.stdout && opts.output_dir.is_some() {
+        fs::create_dir_all(&dir)?;
+    }
+    let mut stdout = std::io::stdout();
+    for (i, (sha, obj)) in commits.iter().enumerate() {
+        let commit = obj.as_any().downcast_ref::<Commit>().unwrap();
+        let prefix = match numbered {
+            true => format!("[PATCH {}/{}]", i + 1, commits.len()),
+            false => "[PATCH]".to_string(),
+        };
+        let mail = format_patch_mail(&repo, sha, commit, &prefix)?;
+        if opts.stdout {
+            if i > 0 {
+                stdout.write_all(b"\n")?;
+            }
+            stdout.write_all(&mail)?;
+            continue;
+        }
+        let (subject, _) = format_patch_message(commit);
+        let path = dir.join(format_patch_file_name(i + 1, &subject));
+        fs::write(&path, &mail)?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+/// The mail for one commit: headers from its author and subject, the
+/// rest of its message, a diffstat and the diff against its parent.
+fn format_patch_mail(
+    repo: &Repository,
+    sha: &str,
+    commit: &Commit,
+    prefix: &str,
+) -> Result<Vec<u8>> {
+    let author = kvlm_get_str(&commit.kvlm, b"author")
+        .ok_or_else(|| anyhow!("commit {} has no author", sha))?;
+    let Some((who, time, tz)) = ident_parse(&author) else {
+        bail!("commit {} has a malformed author", sha);
+    };
+    let (subject, body) = format_patch_message(commit);
+
+    let mut out = format!("From {} Mon Sep 17 00:00:00 2001\n", sha);
+    let (name, email) = who.rsplit_once(" <").unwrap_or(("", who));
+    match name.is_ascii() {
+        true => out.push_str(&format!("From: {}\n", who)),
+        false => out.push_str(&format!("From: {} <{}\n", mail_header_encode(name), email)),
+    }
+    out.push_str(&format!("Date: {}\n", date_format_rfc2822(time, tz)));
+    let subject = match subject.is_ascii() {
+        true => subject,
+        false => mail_header_encode(&subject),
+    };
+    out.push_str(&mail_header_wrap(&format!(
+        "Subject: {} {}",
+        prefix, subject
+    )));
+    if !(who.is_ascii() && commit.message().is_ascii()) {
+        out.push_str(
+            "MIME-Version: 1.0\n\
+             Content-Type: text/plain; charset=UTF-8\n\
+             Content-Transfer-Encoding: 8bit\n",
+        );
+    }
+    out.push('\n');
+    out.push_str(&body);
+    out.push_str("---\n");
+
+    let old = match commit.parents().first() {
+        Some(parent) => diff_map_tree(repo, parent)?,
+        None => DiffMap::new(),
+    };
+    let new = diff_map_tree(repo, sha)?;
+    let mut out = out.into_bytes();
+    out.extend(diff_stat(repo, &old, &new, 72)?);
+    out.extend(diff_summary(&old, &new));
+    out.push(b'\n');
+    out.extend(diff_maps(repo, &old, &new, 3, false)?);
+    out.extend(format!("-- \nrit {}\n\n", env!("CARGO_PKG_VERSION")).into_bytes());
+    Ok(out)
+}
+
+/// Splits a commit message into the subject, its first paragraph joined
+/// into one line, and the rest.
+fn format_patch_message(commit: &Commit) -> (String, String) {
+    let message = commit.message();
+    let message = message.trim_start_matches('\n');
+    let (subject, body) = message.split_once("\n\n").unwrap_or((message, ""));
+    let subject = subject.lines().map(str::trim).collect::<Vec<_>>().join(" ");
+    (subject, body.trim_start_matches('\n').to_string())
+}
+
+/// "0001-Fix-the-thing.patch": the number, and the subject with runs of
+/// anything but letters, digits, '.' and '_' turned into a dash, cut to
+/// fit git's limit on the name.
+fn format_patch_file_name(number: usize, subject: &str) -> String {
+    let mut slug = String::new();
+    let mut dash = false;
+    let mut chars = subject.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !(c.is_ascii_alphanumeric() || c == '.' || c == '_') {
+            dash = !slug.is_empty();
+            continue;
+        }
+        if dash {
+            slug.push('-');
+        }
+        dash = false;
+        slug.push(c);
+        while c == '.' && chars.peek() == Some(&'.') {
+            chars.next();
+        }
+    }
+    let mut name = format!("{:04}-{}", number, slug.trim_end_matches(['.', '-']));
+    name.truncate(57);
+    format!("{}.patch", name)
+}
+
+/// Folds a header onto continuation lines at spaces, so that lines stay
+/// within 78 columns where the words allow.
+fn mail_header_wrap(header: &str) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    for word in header.split(' ') {
+        if column > 0 && column + 1 + word.len() > 78 {
+            out.push('\n');
+            column = 0;
+        }
+        if !out.is_empty() {
+            out.push(' ');
+            column += 1;
+        }
+        out.push_str(word);
+        column += word.len();
+    }
+    out.push('\n');
+    out
+}
+
+/// Encodes a header value that isn't ASCII as one RFC 2047 encoded word
+/// in the Q encoding, as `am` decodes it.
+fn mail_header_encode(value: &str) -> String {
+    let mut out = String::from("=?UTF-8?q?");
+    for b in value.bytes() {
+        match b {
+            b' ' | b'=' | b'?' | b'_' | b'"' => out.push_str(&format!("={:02X}", b)),
+            b if b.is_ascii_graphic() => out.push(b as char),
+            b => out.push_str(&format!("={:02X}", b)),
+        }
+    }
+    out.push_str("?=");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_patch_file_name, mail_header_encode, mail_header_wrap};
+
+    #[test]
+    fn test_format_patch_names() {
+        assert_eq!(
+            format_patch_file_name(1, "Fix the  [thing]... again."),
+            "0001-Fix-the-thing-.-again.patch"
+        );
+        assert_eq!(format_patch_file_name(12, "--"), "0012-.patch");
+        assert_eq!(
+            format_patch_file_name(3, &"word ".repeat(20)),
+            "0003-word-word-word-word-word-word-word-word-word-word-wo.patch"
+        );
+        assert_eq!(mail_header_encode("Café: x"), "=?UTF-8?q?Caf=C3=A9:=20x?=");
+        let subject = "Subject: [PATCH 1/2] Change five and add a fairly long subject line \
+            that should wrap around somewhere, right?";
+        assert_eq!(
+            mail_header_wrap(subject),
+            "Subject: [PATCH 1/2] Change five and add a fairly long subject line that\n \
+             should wrap around somewhere, right?\n"
+        );
+    }
+}
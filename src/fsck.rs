@@ -0,0 +1,402 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    gc_roots, object_iter, object_parse, object_read, object_read_raw, object_write_raw,
+    pack_files, repo_dir, repo_find, tree_parse, Blob, Commit, Object, Repository, Tag, Tree,
+    TreeLeaf,
+};
+
+/// Outcome of a check of the object database.
+#[derive(Default)]
+pub struct FsckReport {
+    /// Objects or packs that can't be read or don't hash to their name,
+    /// with the reason.
+    pub corrupt: Vec<(String, String)>,
+    /// Objects that parse but break the format: (type, sha, problem).
+    pub invalid: Vec<(String, String, String)>,
+    /// Referenced objects that don't exist, as (type, sha).
+    pub missing: Vec<(String, String)>,
+    /// Unreachable objects nothing else points to, as (type, sha).
+    pub dangling: Vec<(String, String)>,
+}
+
+/// Checks every object, loose and packed, and its connectivity from the
+/// refs. Fails after reporting if anything is corrupt, invalid or missing;
+/// dangling objects are only reported.
+pub fn fsck(lost_found: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let report = fsck_check(&repo)?;
+
+    for (sha, err) in &report.corrupt {
+        println!("error: object {} is corrupt: {}", sha, err);
+    }
+    for (typ, sha, problem) in &report.invalid {
+        println!("error in {} {}: {}", typ, sha, problem);
+    }
+    for (typ, sha) in &report.missing {
+        println!("missing {} {}", typ, sha);
+    }
+    for (typ, sha) in &report.dangling {
+        println!("dangling {} {}", typ, sha);
+    }
+
+    if lost_found {
+        fsck_lost_found(&repo, &report.dangling)?;
+    }
+    let errors = report.corrupt.len() + report.invalid.len() + report.missing.len();
+    if errors > 0 {
+        bail!(
+            "fsck found {} error{}",
+            errors,
+            if errors == 1 { "" } else { "s" }
+        );
+    }
+    Ok(())
+}
+
+/// Returns the objects referenced by `obj`, each paired with the type the
+/// reference implies.
+pub fn object_references(obj: &dyn Object) -> Vec<(String, String)> {
+    let mut ret = Vec::new();
+    match obj.fmt() {
+        b"commit" => {
+            let commit = obj.as_any().downcast_ref::<Commit>().unwrap();
+            if let Some(tree) = commit.tree() {
+                ret.push(("tree".to_string(), tree));
+            }
+            for parent in commit.parents() {
+                ret.push(("commit".to_string(), parent));
+            }
+        }
+        b"tree" => {
+            let tree = obj.as_any().downcast_ref::<Tree>().unwrap();
+            for leaf in &tree.items {
+                // Gitlinks point into another repository.
+                if leaf.mode.starts_with(b"16") {
+                    continue;
+                }
+                let typ = if leaf.mode.starts_with(b"04") {
+                    "tree"
+                } else {
+                    "blob"
+                };
+                ret.push((typ.to_string(), leaf.sha.clone()));
+            }
+        }
+        b"tag" => {
+            let tag = obj.as_any().downcast_ref::<Tag>().unwrap();
+            if let Some(sha) = tag.object() {
+                let typ = tag
+                    .kvlm
+                    .get(&Some(b"type".to_vec()))
+                    .and_then(|v| v.first())
+                    .map(|v| String::from_utf8_lossy(v).to_string())
+                    .unwrap_or_else(|| "commit".to_string());
+                ret.push((typ, sha));
+            }
+        }
+        _ => {}
+    }
+    ret
+}
+
+pub fn fsck_check(repo: &Repository) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+
+    for pack in pack_files(repo)? {
+        if let Err(e) = pack.verify() {
+            report
+                .corrupt
+                .push((pack.path.display().to_string(), e.to_string()));
+        }
+    }
+
+    // Load every object once, remembering its type and outgoing links.
+    let mut types: HashMap<String, String> = HashMap::new();
+    let mut links: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    for (sha, info) in object_iter(repo)? {
+        if let Err(e) = info {
+            report.corrupt.push((sha, e.to_string()));
+            continue;
+        }
+        let (fmt, data) = match object_read_raw(repo, &sha) {
+            Ok(raw) => raw,
+            Err(e) => {
+                report.corrupt.push((sha, e.to_string()));
+                continue;
+            }
+        };
+        let actual = object_write_raw(&fmt, &data, None)?;
+        if actual != sha {
+            report
+                .corrupt
+                .push((sha, format!("its content hashes to {}", actual)));
+            continue;
+        }
+        let typ = String::from_utf8_lossy(&fmt).to_string();
+        if let Err(problem) = fsck_object(&fmt, &data) {
+            report.invalid.push((typ.clone(), sha.clone(), problem));
+        }
+        match object_parse(&fmt, &data) {
+            Ok(obj) => {
+                links.insert(sha.clone(), object_references(obj.as_ref()));
+                types.insert(sha, typ);
+            }
+            Err(e) => report.corrupt.push((sha, e.to_string())),
+        }
+    }
+
+    let mut reachable = HashSet::new();
+    let mut missing = HashSet::new();
+    let mut stack: Vec<(String, String)> = gc_roots(repo)?
+        .into_iter()
+        .map(|sha| ("object".to_string(), sha))
+        .collect();
+    while let Some((typ, sha)) = stack.pop() {
+        if !reachable.insert(sha.clone()) {
+            continue;
+        }
+        match links.get(&sha) {
+            Some(children) => stack.extend(children.iter().cloned()),
+            None => {
+                if !types.contains_key(&sha) && missing.insert(sha.clone()) {
+                    report.missing.push((typ, sha));
+                }
+            }
+        }
+    }
+
+    // Dangling objects are unreachable and not pointed to by anything else.
+    let referenced: HashSet<&String> = links
+        .iter()
+        .filter(|(sha, _)| !reachable.contains(*sha))
+        .flat_map(|(_, children)| children.iter().map(|(_, c)| c))
+        .collect();
+    let mut shas: Vec<&String> = types.keys().collect();
+    shas.sort();
+    for sha in shas {
+        if !reachable.contains(sha) && !referenced.contains(sha) {
+            report.dangling.push((types[sha].clone(), sha.clone()));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Checks that an object's content follows the format of its type, naming
+/// the problem the way git's fsck message ids do.
+pub fn fsck_object(fmt: &[u8], data: &[u8]) -> Result<(), String> {
+    match fmt {
+        b"commit" => fsck_commit(data),
+        b"tree" => fsck_tree(data),
+        b"tag" => fsck_tag(data),
+        _ => Ok(()),
+    }
+}
+
+/// The header lines of a commit or tag, without continuation lines, each
+/// split into key and value.
+fn fsck_header(data: &[u8]) -> Vec<(String, String)> {
+    let end = data
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end])
+        .lines()
+        .filter(|l| !l.starts_with(' '))
+        .map(|l| match l.split_once(' ') {
+            Some((k, v)) => (k.to_string(), v.to_string()),
+            None => (l.to_string(), String::new()),
+        })
+        .collect()
+}
+
+fn fsck_sha(s: &str) -> bool {
+    s.len() == 40 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Checks "Name <email> 1262304123 +0100".
+fn fsck_ident(ident: &str) -> Result<(), String> {
+    let Some((_, rest)) = ident.split_once('<') else {
+        return Err("missingEmail: invalid author/committer line - missing email".to_string());
+    };
+    let Some((_, date)) = rest.split_once("> ") else {
+        return Err("badEmail: invalid author/committer line - bad email".to_string());
+    };
+    let Some((time, tz)) = date.split_once(' ') else {
+        return Err(
+            "missingSpaceBeforeDate: invalid author/committer line - missing space before date"
+                .to_string(),
+        );
+    };
+    if time.is_empty() || !time.bytes().all(|b| b.is_ascii_digit()) {
+        return Err("badDate: invalid author/committer line - bad date".to_string());
+    }
+    let tz_ok = tz.len() == 5
+        && (tz.starts_with('+') || tz.starts_with('-'))
+        && tz[1..].bytes().all(|b| b.is_ascii_digit());
+    if !tz_ok {
+        return Err("badTimezone: invalid author/committer line - bad time zone".to_string());
+    }
+    Ok(())
+}
+
+fn fsck_commit(data: &[u8]) -> Result<(), String> {
+    let header = fsck_header(data);
+    let mut lines = header.iter().peekable();
+    match lines.next() {
+        Some((k, v)) if k == "tree" => {
+            if !fsck_sha(v) {
+                return Err("badTreeSha1: invalid 'tree' line format - bad sha1".to_string());
+            }
+        }
+        _ => return Err("missingTree: invalid format - expected 'tree' line".to_string()),
+    }
+    while let Some((_, v)) = lines.next_if(|(k, _)| k == "parent") {
+        if !fsck_sha(v) {
+            return Err("badParentSha1: invalid 'parent' line format - bad sha1".to_string());
+        }
+    }
+    for key in ["author", "committer"] {
+        match lines.next() {
+            Some((k, v)) if k == key => fsck_ident(v)?,
+            _ => {
+                return Err(format!(
+                    "missing{}{}: invalid format - expected '{}' line",
+                    key[..1].to_uppercase(),
+                    &key[1..],
+                    key
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn fsck_tag(data: &[u8]) -> Result<(), String> {
+    let header = fsck_header(data);
+    let mut lines = header.iter();
+    match lines.next() {
+        Some((k, v)) if k == "object" => {
+            if !fsck_sha(v) {
+                return Err("badObjectSha1: invalid 'object' line format - bad sha1".to_string());
+            }
+        }
+        _ => return Err("missingObject: invalid format - expected 'object' line".to_string()),
+    }
+    match lines.next() {
+        Some((k, v)) if k == "type" => {
+            if !["commit", "tree", "blob", "tag"].contains(&v.as_str()) {
+                return Err("badType: invalid 'type' value".to_string());
+            }
+        }
+        _ => return Err("missingTypeEntry: invalid format - expected 'type' line".to_string()),
+    }
+    match lines.next() {
+        Some((k, _)) if k == "tag" => {}
+        _ => return Err("missingTagEntry: invalid format - expected 'tag' line".to_string()),
+    }
+    // Very old tags have no tagger.
+    match lines.next() {
+        Some((k, v)) if k == "tagger" => fsck_ident(v),
+        _ => Ok(()),
+    }
+}
+
+fn fsck_tree(data: &[u8]) -> Result<(), String> {
+    let leaves = tree_parse(data).map_err(|e| format!("badTree: {}", e))?;
+    // Trees are sorted with subtrees named as if they ended with a slash.
+    let key = |leaf: &TreeLeaf| {
+        let mut key = leaf.path.clone();
+        if leaf.mode == b"040000" {
+            key.push('/');
+        }
+        key
+    };
+    for (i, leaf) in leaves.iter().enumerate() {
+        let mode_ok = [&b"040000"[..], b"100644", b"100755", b"120000", b"160000"]
+            .contains(&leaf.mode.as_slice());
+        if !mode_ok {
+            return Err("badFilemode: contains bad file modes".to_string());
+        }
+        match leaf.path.as_str() {
+            "" => return Err("emptyName: contains empty pathname".to_string()),
+            "." => return Err("hasDot: contains '.'".to_string()),
+            ".." => return Err("hasDotdot: contains '..'".to_string()),
+            name if name.eq_ignore_ascii_case(".git") => {
+                return Err("hasDotgit: contains '.git'".to_string())
+            }
+            name if name.contains('/') => {
+                return Err("fullPathname: contains full pathnames".to_string())
+            }
+            _ => {}
+        }
+        if let Some(prev) = i.checked_sub(1).map(|p| &leaves[p]) {
+            if prev.path == leaf.path {
+                return Err("duplicateEntries: contains duplicate file entries".to_string());
+            }
+            if key(prev) > key(leaf) {
+                return Err("treeNotSorted: not properly sorted".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes dangling objects into `.git/lost-found`. Commits go to `commit/`,
+/// everything else to `other/`; blobs are written with their content so they
+/// can be copied back into the worktree directly.
+pub fn fsck_lost_found(repo: &Repository, dangling: &[(String, String)]) -> Result<()> {
+    for (typ, sha) in dangling {
+        let sub = if typ == "commit" { "commit" } else { "other" };
+        let dir = repo_dir(repo, PathBuf::from("lost-found").join(sub), true)?.unwrap();
+        let obj = object_read(repo, sha)?;
+        let content = match obj.as_any().downcast_ref::<Blob>() {
+            Some(blob) => blob.blobdata.clone(),
+            None => format!("{}\n", sha).into_bytes(),
+        };
+        fs::write(dir.join(sha), content)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fsck_object;
+
+    #[test]
+    fn test_fsck_object() {
+        let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+        let commit = format!(
+            "tree {}\nauthor A <a@x> 1 +0100\ncommitter A <a@x> 1 +0100\n\nmsg\n",
+            tree
+        );
+        assert!(fsck_object(b"commit", commit.as_bytes()).is_ok());
+        let no_author = format!("tree {}\ncommitter A <a@x> 1 +0100\n\nmsg\n", tree);
+        assert!(fsck_object(b"commit", no_author.as_bytes())
+            .unwrap_err()
+            .starts_with("missingAuthor"));
+        let bad_tz = commit.replace("1 +0100\ncommitter", "1 0100\ncommitter");
+        assert!(fsck_object(b"commit", bad_tz.as_bytes())
+            .unwrap_err()
+            .starts_with("badTimezone"));
+
+        let entry =
+            |mode: &str, name: &str| [format!("{} {}\0", mode, name).as_bytes(), &[0; 20]].concat();
+        let unsorted = [entry("40000", "a"), entry("100644", "a.b")].concat();
+        assert!(fsck_object(b"tree", &unsorted)
+            .unwrap_err()
+            .starts_with("treeNotSorted"));
+        let sorted = [entry("100644", "a.b"), entry("40000", "a")].concat();
+        assert_eq!(fsck_object(b"tree", &sorted), Ok(()));
+        assert!(fsck_object(b"tree", &entry("100666", "a"))
+            .unwrap_err()
+            .starts_with("badFilemode"));
+    }
+}
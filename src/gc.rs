@@ -0,0 +1,265 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    gc_roots, object_iter_loose, object_list_loose, object_write_raw, pack_files, pack_store,
+    prune_unreachable, reachable_objects, reflog_expire_all, repo_dir, repo_file, repo_find,
+    rerere_gc, Repository,
+};
+
+/// Loose objects above which `gc --auto` runs (git's `gc.auto`).
+const GC_AUTO_DEFAULT: usize = 6700;
+/// Packs above which `gc --auto` runs (git's `gc.autoPackLimit`).
+const GC_AUTO_PACK_LIMIT_DEFAULT: usize = 50;
+/// How long unreachable loose objects are kept, like `gc.pruneExpire`.
+pub const GC_PRUNE_EXPIRE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+/// A `gc.pid` lock older than this is assumed to belong to a gc that died.
+const GC_LOCK_EXPIRE: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Expires old reflog entries, packs every reachable object into a single
+/// pack and prunes unreachable loose objects older than the grace period.
+pub fn gc(auto: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    gc_repo(&repo, auto)
+}
+
+fn gc_repo(repo: &Repository, auto: bool) -> Result<()> {
+    if auto && !gc_auto_needed(repo)? {
+        return Ok(());
+    }
+    if !gc_lock(repo)? {
+        if auto {
+            return Ok(());
+        }
+        bail!("gc is already running on this repository (gc.pid exists)");
+    }
+    let result = gc_run(repo);
+    fs::remove_file(repo_file(repo, PathBuf::from("gc.pid"), false)?)?;
+    result
+}
+
+fn gc_run(repo: &Repository) -> Result<()> {
+    let now = SystemTime::now();
+    // Expired entries must not keep their objects alive below.
+    reflog_expire_all(repo, now)?;
+    let prune_expire = gc_expiry(repo, "pruneexpire", GC_PRUNE_EXPIRE, now)?;
+    rerere_gc(repo)?;
+    gc_repack(repo, prune_expire)?;
+    if let Some(expire) = prune_expire {
+        prune_unreachable(repo, expire, false)?;
+    }
+    gc_remove_empty_dirs(repo)
+}
+
+/// The cutoff `gc.<key>` sets, as "never", "now" or "<n>.<unit>.ago", or
+/// `default` before now. None means nothing ever expires.
+fn gc_expiry(
+    repo: &Repository,
+    key: &str,
+    default: Duration,
+    now: SystemTime,
+) -> Result<Option<SystemTime>> {
+    match repo.config_get("gc", key) {
+        Some(value) => {
+            expiry_parse(&value, now).map_err(|_| anyhow!("invalid gc.{} value '{}'", key, value))
+        }
+        None => Ok(Some(now - default)),
+    }
+}
+
+/// Parses an expiry like "2.weeks.ago" into the time before which things
+/// expire. "never" means nothing does, "now" everything.
+pub fn expiry_parse(value: &str, now: SystemTime) -> Result<Option<SystemTime>> {
+    let age = match value {
+        "never" | "false" => return Ok(None),
+        "now" | "all" => Duration::ZERO,
+        _ => {
+            let invalid = || anyhow!("invalid expiry '{}'", value);
+            let mut parts = value.split('.');
+            let (Some(n), Some(unit), Some("ago"), None) =
+                (parts.next(), parts.next(), parts.next(), parts.next())
+            else {
+                return Err(invalid());
+            };
+            let n: u64 = n.parse().map_err(|_| invalid())?;
+            let secs = match unit.trim_end_matches('s') {
+                "second" => 1,
+                "minute" => 60,
+                "hour" => 60 * 60,
+                "day" => 24 * 60 * 60,
+                "week" => 7 * 24 * 60 * 60,
+                "month" => 30 * 24 * 60 * 60,
+                "year" => 365 * 24 * 60 * 60,
+                _ => return Err(invalid()),
+            };
+            Duration::from_secs(n * secs)
+        }
+    };
+    Ok(Some(now - age))
+}
+
+/// Writes every reachable object into one new pack, replacing the old
+/// packs and the loose copies. Unreachable objects of old packs younger
+/// than `expire` are kept as loose objects, for pruning to decide on later.
+fn gc_repack(repo: &Repository, expire: Option<SystemTime>) -> Result<()> {
+    let reachable = reachable_objects(repo, &gc_roots(repo)?)?;
+    let old_packs = pack_files(repo)?;
+    let loose: Vec<String> = object_iter_loose(repo)?
+        .filter(|(sha, info)| info.is_ok() && reachable.contains(sha))
+        .map(|(sha, _)| sha)
+        .collect();
+    if reachable.is_empty() || (loose.is_empty() && old_packs.len() == 1) {
+        return Ok(());
+    }
+
+    for pack in &old_packs {
+        let mtime = fs::metadata(&pack.path)?.modified()?;
+        if expire.is_some_and(|expire| mtime < expire) {
+            continue;
+        }
+        for sha in pack.shas().filter(|sha| !reachable.contains(sha)) {
+            let (fmt, data) = pack.read(repo, &sha)?.unwrap();
+            object_write_raw(&fmt, &data, Some(repo))?;
+            // Keep the age of the pack, so the grace period isn't restarted.
+            File::options()
+                .write(true)
+                .open(gc_loose_path(repo, &sha)?)?
+                .set_modified(mtime)?;
+        }
+    }
+
+    let mut shas: Vec<String> = reachable.into_iter().collect();
+    shas.sort();
+    let new_pack = pack_store(repo, &shas)?;
+    for pack in old_packs.iter().filter(|p| p.path != new_pack) {
+        fs::remove_file(pack.path.with_extension("idx"))?;
+        fs::remove_file(&pack.path)?;
+    }
+    for sha in loose {
+        fs::remove_file(gc_loose_path(repo, &sha)?)?;
+    }
+    Ok(())
+}
+
+fn gc_loose_path(repo: &Repository, sha: &str) -> Result<PathBuf> {
+    repo_file(
+        repo,
+        PathBuf::from("objects").join(&sha[..2]).join(&sha[2..]),
+        false,
+    )
+}
+
+/// Removes the fan-out directories of `objects` that packing emptied.
+fn gc_remove_empty_dirs(repo: &Repository) -> Result<()> {
+    let Some(objects) = repo_dir(repo, PathBuf::from("objects"), false)? else {
+        return Ok(());
+    };
+    for entry in fs::read_dir(objects)? {
+        let path = entry?.path();
+        let fanout = path.file_name().is_some_and(|n| n.len() == 2);
+        if fanout && path.is_dir() && fs::read_dir(&path)?.next().is_none() {
+            fs::remove_dir(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether there are more loose objects than `gc.auto` or more packs than
+/// `gc.autoPackLimit`. Either limit set to 0 disables it.
+pub fn gc_auto_needed(repo: &Repository) -> Result<bool> {
+    let limit = config_usize(repo, "auto", GC_AUTO_DEFAULT)?;
+    let pack_limit = config_usize(repo, "autopacklimit", GC_AUTO_PACK_LIMIT_DEFAULT)?;
+    Ok((limit > 0 && loose_object_estimate(repo, limit)? > limit)
+        || (pack_limit > 0 && pack_count(repo)? > pack_limit))
+}
+
+/// Called after commands that create objects: if the repository needs it,
+/// runs `rit gc --auto` in a detached background process (or right here
+/// with `gc.autoDetach=false`). Failures only warn, since the command that
+/// triggered it already succeeded.
+pub fn gc_auto(repo: &Repository) {
+    let result = gc_auto_needed(repo).and_then(|needed| {
+        if !needed {
+            return Ok(());
+        }
+        if repo.config_get("gc", "autodetach").as_deref() == Some("false") {
+            eprintln!("Auto packing the repository for optimum performance.");
+            return gc_repo(repo, true);
+        }
+        eprintln!("Auto packing the repository in background for optimum performance.");
+        Command::new(std::env::current_exe()?)
+            .args(["gc", "--auto"])
+            .current_dir(&repo.worktree)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        eprintln!("warning: failed to run gc --auto: {}", e);
+    }
+}
+
+/// Takes the `gc.pid` lock, returning false if another gc holds it. A
+/// stale lock left behind by a gc that died is taken over.
+fn gc_lock(repo: &Repository) -> Result<bool> {
+    let path = repo_file(repo, PathBuf::from("gc.pid"), false)?;
+    if let Ok(meta) = fs::metadata(&path) {
+        if meta.modified()?.elapsed().unwrap_or_default() < GC_LOCK_EXPIRE {
+            return Ok(false);
+        }
+        fs::remove_file(&path)?;
+    }
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    writeln!(file, "{}", std::process::id())?;
+    Ok(true)
+}
+
+/// `gc.<key>` as a count; a negative one counts as zero.
+fn config_usize(repo: &Repository, key: &str, default: usize) -> Result<usize> {
+    Ok(match repo.config_get_int("gc", key)? {
+        Some(n) => usize::try_from(n).unwrap_or(0),
+        None => default,
+    })
+}
+
+/// Estimates the number of loose objects from a single fan-out directory,
+/// as git does, instead of listing all 256 of them.
+fn loose_object_estimate(repo: &Repository, limit: usize) -> Result<usize> {
+    let dir = repo.repo_path(PathBuf::from("objects/17"));
+    let count = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().len() == 38)
+            .count(),
+        Err(_) => return Ok(0),
+    };
+    if count * 256 > limit {
+        // Worth the exact count when the estimate says it is large.
+        return Ok(object_list_loose(repo)?.len());
+    }
+    Ok(count * 256)
+}
+
+fn pack_count(repo: &Repository) -> Result<usize> {
+    let dir = match repo_dir(repo, PathBuf::from("objects/pack"), false)? {
+        Some(d) => d,
+        None => return Ok(0),
+    };
+    Ok(fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|x| x == "pack"))
+        .count())
+}
@@ -0,0 +1,137 @@
+use std::{io::Write, path::Path};
+
+use anyhow::{anyhow, Result};
+use regex::bytes::RegexBuilder;
+
+use crate::{blob_read, index_read, is_binary, object_find, repo_find, tree_to_leaves, Repository};
+
+/// Searches tracked content for `pattern`: the blobs staged in the index,
+/// or those of `tree_ish` when given. Matches print as `path:line`, or
+/// `path:lineno:line` with `line_numbers`; with a tree-ish, paths are
+/// prefixed by it as in git.
+///
+/// `--cached` is accepted for familiarity, but the index is what is
+/// searched anyway: the worktree is never read.
+pub fn grep(
+    pattern: &str,
+    tree_ish: Option<&str>,
+    ignore_case: bool,
+    line_numbers: bool,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let out = grep_repo(&repo, pattern, tree_ish, ignore_case, line_numbers)?;
+    std::io::stdout().write_all(&out)?;
+    Ok(())
+}
+
+/// The output of `grep` in `repo`.
+fn grep_repo(
+    repo: &Repository,
+    pattern: &str,
+    tree_ish: Option<&str>,
+    ignore_case: bool,
+    line_numbers: bool,
+) -> Result<Vec<u8>> {
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()?;
+
+    let (prefix, files): (String, Vec<(String, String)>) = match tree_ish {
+        Some(t) => {
+            object_find(repo, t, Some(b"tree"), true)?
+                .ok_or_else(|| anyhow!("invalid tree-ish '{}'", t))?;
+            let files = tree_to_leaves(repo, t, "")?
+                .into_iter()
+                .filter(|l| !l.mode.starts_with(b"16"))
+                .map(|l| (l.path, l.sha))
+                .collect();
+            (format!("{}:", t), files)
+        }
+        None => {
+            // Unmerged paths are searched in our version.
+            let files = index_read(repo)?
+                .entries
+                .into_iter()
+                .filter(|e| e.mode_type != 0b1110 && matches!(e.flag_stage >> 12, 0 | 2))
+                .map(|e| (e.name, e.sha))
+                .collect();
+            (String::new(), files)
+        }
+    };
+
+    let mut out = Vec::new();
+    for (path, sha) in files {
+        let data = blob_read(repo, &sha)?;
+        if is_binary(&data) {
+            if regex.is_match(&data) {
+                writeln!(out, "Binary file {}{} matches", prefix, path)?;
+            }
+            continue;
+        }
+        for (i, line) in data.split_inclusive(|&b| b == b'\n').enumerate() {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            if !regex.is_match(line) {
+                continue;
+            }
+            write!(out, "{}{}:", prefix, path)?;
+            if line_numbers {
+                write!(out, "{}:", i + 1)?;
+            }
+            out.extend_from_slice(line);
+            out.push(b'\n');
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::grep_repo;
+    use crate::{
+        checkout_tree, index_read, index_write, ref_create,
+        test_utils::{make_commit, test_repo},
+        tree_to_leaves, IndexEntry,
+    };
+
+    #[test]
+    fn test_grep() {
+        let repo = test_repo("grep");
+        let base = make_commit(&repo, "alpha\nBeta\ngamma beta\n", &[]);
+        checkout_tree(&repo, &base, true).unwrap();
+        ref_create(&repo, "heads/master", &base).unwrap();
+        // The worktree is never searched.
+        fs::write(repo.worktree.join("file"), "beta only here\n").unwrap();
+        let grep = |pattern: &str, tree_ish: Option<&str>, ignore_case: bool| {
+            let out = grep_repo(&repo, pattern, tree_ish, ignore_case, true).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(grep("beta", None, false), "file:3:gamma beta\n");
+        assert_eq!(grep("beta", None, true), "file:2:Beta\nfile:3:gamma beta\n");
+        assert_eq!(grep("only", None, false), "");
+        let out = grep_repo(&repo, "^al", None, false, false).unwrap();
+        assert_eq!(out, b"file:alpha\n");
+
+        let ours = make_commit(&repo, "ours beta\n", &[&base]);
+        let theirs = make_commit(&repo, "theirs beta\n", &[&base]);
+        assert_eq!(
+            grep("beta", Some(&theirs), false),
+            format!("{}:file:1:theirs beta\n", theirs)
+        );
+
+        // Of a conflicted path, only our version is searched.
+        let mut index = index_read(&repo).unwrap();
+        index.entries.clear();
+        for (stage, commit) in [(1, &base), (2, &ours), (3, &theirs)] {
+            let leaf = &tree_to_leaves(&repo, commit, "").unwrap()[0];
+            index
+                .entries
+                .push(IndexEntry::from_leaf(leaf, stage).unwrap());
+        }
+        index_write(&repo, &index).unwrap();
+        assert_eq!(grep("beta", None, false), "file:1:ours beta\n");
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -5,7 +5,9 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use crate::{index_read, object_read, repo_find, Blob, Repository};
+use crate::{
+    config_global_path, index_from_head, index_read, object_read, repo_find, Blob, Repository,
+};
 
 pub fn check_ignore(paths: &Vec<PathBuf>) -> Result<()> {
     let repo = repo_find(Path::new("."), true)?.unwrap();
@@ -37,50 +39,120 @@ pub fn check_ignore_path(ignore: &Ignore, path: &Path) -> bool {
 
 pub fn gitignore_read(repo: &Repository) -> Result<Ignore> {
     let mut absolute = Vec::new();
-    let mut scoped = HashMap::new();
-
-    let repo_exclude = repo.gitdir.join("info").join("exclude");
-    if repo_exclude.exists() {
-        let content = fs::read_to_string(&repo_exclude)?;
-        let lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
-        absolute.extend(gitignore_parse(lines));
-    }
 
-    let global_ignore = if let Ok(xdg) = std::env::var("XDG_CONFIG_HOME") {
-        PathBuf::from(xdg).join("git").join("ignore")
-    } else {
-        PathBuf::from("~/.config").join("git").join("ignore")
-    };
-
-    if global_ignore.exists() {
-        let content = fs::read_to_string(&global_ignore)?;
-        let lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
-        absolute.extend(gitignore_parse(lines));
+    // Later rules win: info/exclude over the user's excludes file.
+    for path in [
+        ignore_global_path(repo),
+        Some(repo.gitdir.join("info/exclude")),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        if path.exists() {
+            let content = fs::read_to_string(&path)?;
+            absolute.extend(gitignore_parse(
+                content.lines().map(|l| l.to_string()).collect(),
+            ));
+        }
     }
 
-    let index = index_read(repo)?;
-
+    // With a damaged index, HEAD's .gitignore files are the best guess.
+    let index = index_read(repo).or_else(|_| index_from_head(repo))?;
+    let mut staged = HashMap::new();
     for entry in index.entries {
         if entry.name == ".gitignore" || entry.name.ends_with("/.gitignore") {
-            let obj = object_read(repo, &entry.sha)?;
-            let blob = obj
-                .as_any()
-                .downcast_ref::<Blob>()
-                .ok_or_else(|| anyhow!(".gitignore is not a blob"))?;
-            let content = String::from_utf8(blob.blobdata.clone())?;
-
-            let lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
-
-            let dir = std::path::Path::new(&entry.name)
+            let dir = Path::new(&entry.name)
                 .parent()
                 .map(|p| p.to_string_lossy().to_string())
                 .unwrap_or_default();
+            staged.insert(dir, entry.sha);
+        }
+    }
 
-            scoped.insert(dir, gitignore_parse(lines));
+    let mut ignore = Ignore {
+        absolute,
+        scoped: HashMap::new(),
+    };
+    gitignore_walk(repo, &staged, "", &mut ignore)?;
+    // Directories that aren't checked out keep their rules from the index.
+    for (dir, sha) in &staged {
+        if !ignore.scoped.contains_key(dir) {
+            let rules = gitignore_parse(gitignore_blob(repo, sha)?);
+            ignore.scoped.insert(dir.clone(), rules);
         }
     }
+    Ok(ignore)
+}
+
+/// `core.excludesFile`, defaulting to $XDG_CONFIG_HOME/git/ignore.
+fn ignore_global_path(repo: &Repository) -> Option<PathBuf> {
+    if let Some(path) = repo.config_get("core", "excludesFile") {
+        return Some(match path.strip_prefix("~/") {
+            Some(rest) => config_global_path()?.parent()?.join(rest),
+            None => PathBuf::from(path),
+        });
+    }
+    let config = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    Some(config.join("git/ignore"))
+}
+
+/// Reads the .gitignore of the worktree directory `dir` into `ignore`,
+/// from the worktree or, if it isn't there, from `staged`, then does the
+/// same for the subdirectories its rules and those above don't ignore.
+/// Nested repositories have rules of their own and are skipped.
+fn gitignore_walk(
+    repo: &Repository,
+    staged: &HashMap<String, String>,
+    dir: &str,
+    ignore: &mut Ignore,
+) -> Result<()> {
+    let path = repo.worktree.join(dir);
+    let lines = match fs::read_to_string(path.join(".gitignore")) {
+        Ok(content) => Some(content.lines().map(|l| l.to_string()).collect()),
+        Err(_) => staged
+            .get(dir)
+            .map(|sha| gitignore_blob(repo, sha))
+            .transpose()?,
+    };
+    if let Some(lines) = lines {
+        ignore
+            .scoped
+            .insert(dir.to_string(), gitignore_parse(lines));
+    }
+
+    let Ok(entries) = fs::read_dir(&path) else {
+        return Ok(());
+    };
+    let mut subdirs: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()) && e.file_name() != ".git")
+        .filter(|e| !e.path().join(".git").exists())
+        .map(|e| match dir {
+            "" => e.file_name().to_string_lossy().to_string(),
+            _ => format!("{}/{}", dir, e.file_name().to_string_lossy()),
+        })
+        .collect();
+    subdirs.sort();
+    for sub in subdirs {
+        if !check_ignore_path(ignore, Path::new(&sub)) {
+            gitignore_walk(repo, staged, &sub, ignore)?;
+        }
+    }
+    Ok(())
+}
 
-    Ok(Ignore { absolute, scoped })
+/// The lines of the .gitignore blob `sha`.
+fn gitignore_blob(repo: &Repository, sha: &str) -> Result<Vec<String>> {
+    let obj = object_read(repo, sha)?;
+    let blob = obj
+        .as_any()
+        .downcast_ref::<Blob>()
+        .ok_or_else(|| anyhow!(".gitignore is not a blob"))?;
+    let content = String::from_utf8(blob.blobdata.clone())?;
+    Ok(content.lines().map(|l| l.to_string()).collect())
 }
 
 fn gitignore_parse1(raw: &str) -> Option<(String, bool)> {
@@ -1,15 +1,31 @@
 use std::{
+    borrow::Cow,
+    cmp::Ordering,
     fs::{self, File},
     io::Write,
-    path::PathBuf,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
 use anyhow::{anyhow, bail, Result};
+use sha1::{Digest, Sha1};
 
-use crate::{repo_file, Repository};
+use crate::{
+    expiry_parse, file_write_locked, head_leaves, object_hash, object_read_raw, profile_phase,
+    repo_file, repo_find, submodule_head, tree_from_leaves, Repository, TreeLeaf,
+};
+
+/// How far, in percent of its entries, a split index may differ from its
+/// shared index before a new shared index is written, like git's
+/// `splitIndex.maxPercentChange`.
+const SPLIT_INDEX_MAX_PERCENT_CHANGE: i64 = 20;
+/// How long a shared index no longer in use is kept, like
+/// `splitIndex.sharedIndexExpire`.
+const SHARED_INDEX_EXPIRE: Duration = Duration::from_secs(14 * 24 * 60 * 60);
 
 /// An entry in the index file holds metadata about a tracked file.
-#[derive(Default)]
+#[derive(Default, Clone, PartialEq)]
 pub struct IndexEntry {
     pub ctime: (u32, u32), // (seconds, nanoseconds)
     pub mtime: (u32, u32),
@@ -22,13 +38,208 @@ pub struct IndexEntry {
     pub fsize: u32,
     pub sha: String, // stored as 40-digit lowercase hex
     pub flag_assume_valid: bool,
+    /// Left out of the worktree by sparse checkout: the file is absent
+    /// there and taken to be unchanged. An extended flag, which needs index
+    /// version 3.
+    pub flag_skip_worktree: bool,
     pub flag_stage: u16, // bits indicating the stage
     pub name: String,    // path relative to worktree
 }
 
+impl IndexEntry {
+    /// Builds an entry for the worktree file `name`, taking its stat data from
+    /// the filesystem. `mode` is the tree mode, e.g. b"100644".
+    pub fn from_file(repo: &Repository, name: &str, sha: &str, mode: &[u8]) -> Result<Self> {
+        let metadata = fs::symlink_metadata(repo.worktree.join(name))?;
+        let (mode_type, mode_perms) = tree_mode_split(mode)?;
+        Ok(Self {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode_type,
+            mode_perms,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            fsize: metadata.size() as u32,
+            sha: sha.to_string(),
+            flag_assume_valid: false,
+            flag_skip_worktree: false,
+            flag_stage: 0,
+            name: name.to_string(),
+        })
+    }
+
+    /// Builds an entry for `leaf` at merge `stage` (0 when merged), without
+    /// stat data: its file is taken to be changed until it is read again.
+    pub fn from_leaf(leaf: &TreeLeaf, stage: u16) -> Result<Self> {
+        let (mode_type, mode_perms) = tree_mode_split(&leaf.mode)?;
+        Ok(Self {
+            mode_type,
+            mode_perms,
+            sha: leaf.sha.clone(),
+            name: leaf.path.clone(),
+            flag_stage: stage << 12,
+            ..Default::default()
+        })
+    }
+
+    /// The mode of this entry as it would appear in a tree, e.g. b"100644".
+    pub fn tree_mode(&self) -> Vec<u8> {
+        format!("{:02o}{:04o}", self.mode_type, self.mode_perms).into_bytes()
+    }
+}
+
+/// Splits a tree mode like b"100755" into the index's (type, perms) pair.
+pub fn tree_mode_split(mode: &[u8]) -> Result<(u16, u16)> {
+    let mode = u32::from_str_radix(std::str::from_utf8(mode)?, 8)?;
+    Ok(((mode >> 12) as u16, (mode & 0o777) as u16))
+}
+
+/// Returns whether the worktree copy of `entry` differs from what the index
+/// records. The stat data is compared first; the file is only hashed when
+/// that is inconclusive, as it is for a racily clean entry (see
+/// `index_entry_stat_clean`). A submodule is modified when it has another commit
+/// checked out; see `submodule_status` for changes inside it. An entry
+/// sparse checkout leaves out of the worktree, or that is marked
+/// assume-unchanged, is never modified.
+pub fn index_entry_modified(repo: &Repository, entry: &IndexEntry) -> Result<bool> {
+    let _phase = profile_phase("worktree walk");
+    if entry.flag_skip_worktree || entry.flag_assume_valid {
+        return Ok(false);
+    }
+    if entry.mode_type == 0b1110 {
+        let head = submodule_head(repo, &entry.name)?;
+        return Ok(head.is_some_and(|h| h != entry.sha));
+    }
+    let full_path = repo.worktree.join(&entry.name);
+    let metadata = match fs::symlink_metadata(&full_path) {
+        Ok(m) => m,
+        Err(_) => return Ok(true),
+    };
+    if index_entry_stat_clean(repo, entry, &metadata) {
+        return Ok(false);
+    }
+    Ok(worktree_file_hash(repo, &entry.name)? != entry.sha)
+}
+
+/// Whether `metadata` shows `entry` unchanged without reading the file. Its
+/// stat data must match, and the entry must not be racily clean: a file
+/// modified no earlier than the index was written may have changed again
+/// within the same timestamp, so like git it is hashed. An entry recorded
+/// with size 0 is hashed as well, as that is how racy entries get smudged
+/// when the index is written.
+pub fn index_entry_stat_clean(
+    repo: &Repository,
+    entry: &IndexEntry,
+    metadata: &fs::Metadata,
+) -> bool {
+    index_entry_stat_matches(entry, metadata)
+        && entry.fsize != 0
+        && !index_entry_racy(entry, index_mtime(repo))
+}
+
+/// The modification time of the index file, as (seconds, nanoseconds).
+fn index_mtime(repo: &Repository) -> Option<(u32, u32)> {
+    let meta = fs::metadata(repo.repo_path(PathBuf::from("index"))).ok()?;
+    Some((meta.mtime() as u32, meta.mtime_nsec() as u32))
+}
+
+/// Whether `entry` was modified no earlier than an index written at
+/// `index_mtime`, so its stat data cannot tell later changes apart.
+fn index_entry_racy(entry: &IndexEntry, index_mtime: Option<(u32, u32)>) -> bool {
+    index_mtime.is_some_and(|m| entry.mtime >= m)
+}
+
+/// Smudges the racily clean entries about to be written over the index: those
+/// whose files have changed get their size set to 0, so that they no longer
+/// pass for unchanged once the new index is older than their files.
+fn index_smudge_racy<'a>(
+    repo: &Repository,
+    entries: &'a [IndexEntry],
+) -> Result<Cow<'a, [IndexEntry]>> {
+    let mut smudged = Cow::Borrowed(entries);
+    let index_mtime = index_mtime(repo);
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.flag_stage != 0
+            || entry.flag_skip_worktree
+            || entry.flag_assume_valid
+            || entry.mode_type == 0b1110
+            || entry.fsize == 0
+            || !index_entry_racy(entry, index_mtime)
+        {
+            continue;
+        }
+        let meta = fs::symlink_metadata(repo.worktree.join(&entry.name));
+        if meta.is_ok_and(|m| index_entry_stat_matches(entry, &m))
+            && worktree_file_hash(repo, &entry.name)? != entry.sha
+        {
+            smudged.to_mut()[i].fsize = 0;
+        }
+    }
+    Ok(smudged)
+}
+
+/// Whether `metadata` is the stat data the index recorded for `entry`, so
+/// that the file can be taken to be unchanged without reading it.
+pub fn index_entry_stat_matches(entry: &IndexEntry, metadata: &fs::Metadata) -> bool {
+    metadata.ctime() as u32 == entry.ctime.0
+        && metadata.ctime_nsec() as u32 == entry.ctime.1
+        && metadata.mtime() as u32 == entry.mtime.0
+        && metadata.mtime_nsec() as u32 == entry.mtime.1
+        && metadata.size() as u32 == entry.fsize
+}
+
+/// Hashes a worktree file as a blob without writing it. Symlinks are hashed
+/// by their target, like git does.
+pub fn worktree_file_hash(repo: &Repository, name: &str) -> Result<String> {
+    let full_path = repo.worktree.join(name);
+    if fs::symlink_metadata(&full_path)?.file_type().is_symlink() {
+        let target = fs::read_link(&full_path)?;
+        object_hash(target.to_string_lossy().as_bytes(), b"blob", None)
+    } else {
+        object_hash(File::open(&full_path)?, b"blob", None)
+    }
+}
+
+/// The tree mode git records for a worktree file with this metadata.
+pub fn worktree_file_mode(meta: &fs::Metadata) -> Vec<u8> {
+    if meta.file_type().is_symlink() {
+        b"120000".to_vec()
+    } else if meta.mode() & 0o111 != 0 {
+        b"100755".to_vec()
+    } else {
+        b"100644".to_vec()
+    }
+}
+
+/// Writes the trees recorded by the index and returns the root tree's sha.
+/// Fails if the index still has unmerged entries.
+pub fn index_write_tree(repo: &Repository, index: &Index) -> Result<String> {
+    if let Some(e) = index.entries.iter().find(|e| e.flag_stage != 0) {
+        bail!("{}: unmerged (index has conflicts to resolve)", e.name);
+    }
+    let leaves: Vec<TreeLeaf> = index
+        .entries
+        .iter()
+        .map(|e| TreeLeaf {
+            mode: e.tree_mode(),
+            path: e.name.clone(),
+            sha: e.sha.clone(),
+        })
+        .collect();
+    tree_from_leaves(repo, &leaves)
+}
+
 pub struct Index {
     pub version: u32,
     pub entries: Vec<IndexEntry>,
+    /// Whether it is written as a split index: only how it differs from a
+    /// shared index that holds the other entries, so that writing it stays
+    /// cheap however many entries there are. `core.splitIndex` overrides it.
+    pub split: bool,
+    /// The shared index it was read on top of, if it was split.
+    pub shared: Option<SharedIndex>,
 }
 
 impl Default for Index {
@@ -36,22 +247,94 @@ impl Default for Index {
         Self {
             version: 2,
             entries: Default::default(),
+            split: false,
+            shared: None,
         }
     }
 }
 
+/// A `sharedindex.<checksum>` file, an index of its own that split
+/// indexes record their changes against.
+pub struct SharedIndex {
+    pub sha: String,
+    pub entries: Vec<IndexEntry>,
+}
+
+/// The `link` extension of a split index: its shared index, which of that
+/// one's entries it deletes, and which it replaces with its own first
+/// entries, in order.
+struct IndexLink {
+    sha: String,
+    delete: Vec<usize>,
+    replace: Vec<usize>,
+}
+
+/// Writes the index. A split one gets only its changes against its shared
+/// index written, unless they have grown past `splitIndex.maxPercentChange`
+/// of the entries: then every entry goes into a new shared index.
 pub fn index_write(repo: &Repository, index: &Index) -> Result<()> {
     let path = repo.repo_path(PathBuf::from("index"));
-    let mut f = File::create(&path)?;
+    let entries = index_smudge_racy(repo, &index.entries)?;
+    let all: Vec<&IndexEntry> = entries.iter().collect();
+    if !repo
+        .config_get_bool("core", "splitindex")?
+        .unwrap_or(index.split)
+    {
+        let raw = index_serialize(index.version, &all, 0, &[])?;
+        return file_write_locked(&path, &raw, repo.fsync.index);
+    }
+
+    let max_change = repo
+        .config_get_int("splitindex", "maxpercentchange")?
+        .unwrap_or(SPLIT_INDEX_MAX_PERCENT_CHANGE)
+        .clamp(0, 100) as usize;
+    let shared = (index.shared.as_ref()).filter(|s| shared_index_path(repo, &s.sha).exists());
+    if let Some(shared) = shared {
+        let (link, own) = index_link_make(shared, &entries);
+        let changed = link.delete.len() + own.len();
+        if changed * 100 <= max_change * index.entries.len() {
+            let raw = index_serialize(index.version, &own, link.replace.len(), &link.encode()?)?;
+            return file_write_locked(&path, &raw, repo.fsync.index);
+        }
+    }
+
+    let raw = index_serialize(index.version, &all, 0, &[])?;
+    let sha = hex::encode(&raw[raw.len() - 20..]);
+    file_write_locked(&shared_index_path(repo, &sha), &raw, repo.fsync.index)?;
+    let link = IndexLink {
+        sha: sha.clone(),
+        delete: Vec::new(),
+        replace: Vec::new(),
+    };
+    let raw = index_serialize(index.version, &[], 0, &link.encode()?)?;
+    file_write_locked(&path, &raw, repo.fsync.index)?;
+    shared_index_expire(repo, &sha)
+}
+
+/// An index file holding `entries`, the first `stripped` of them without
+/// their names, and the extension data `extensions`.
+fn index_serialize(
+    version: u32,
+    entries: &[&IndexEntry],
+    stripped: usize,
+    extensions: &[u8],
+) -> Result<Vec<u8>> {
+    let mut f = Vec::new();
+    // Extended flags only exist from version 3 on; like git, write version
+    // 2 when no entry needs them.
+    let extended = entries.iter().any(|e| e.flag_skip_worktree);
+    let version = match version {
+        2 | 3 if extended => 3,
+        3 => 2,
+        v => v,
+    };
 
     // HEADER: Write "DIRC", version (4 bytes), and entry count (4 bytes)
     f.write_all(b"DIRC")?;
-    f.write_all(&index.version.to_be_bytes())?;
-    f.write_all(&(index.entries.len() as u32).to_be_bytes())?;
+    f.write_all(&version.to_be_bytes())?;
+    f.write_all(&(entries.len() as u32).to_be_bytes())?;
 
-    let mut idx: usize = 12;
-
-    for entry in &index.entries {
+    for (i, entry) in entries.iter().enumerate() {
         // Write fixed-length fields (total 62 bytes):
         f.write_all(&entry.ctime.0.to_be_bytes())?;
         f.write_all(&entry.ctime.1.to_be_bytes())?;
@@ -68,7 +351,6 @@ pub fn index_write(repo: &Repository, index: &Index) -> Result<()> {
         f.write_all(&entry.gid.to_be_bytes())?;
         f.write_all(&entry.fsize.to_be_bytes())?;
 
-        let sha_int = u128::from_str_radix(&entry.sha[..32], 16).unwrap_or(0); // For simplicity; real code must handle full 160 bits.
         let sha_bytes = hex::decode(&entry.sha)?;
         if sha_bytes.len() != 20 {
             bail!("Invalid SHA length");
@@ -77,30 +359,250 @@ pub fn index_write(repo: &Repository, index: &Index) -> Result<()> {
 
         let flag_assume_valid: u16 = if entry.flag_assume_valid { 1 << 15 } else { 0 };
         // We assume flag_stage fits into bits 12-13 (0 or 0x1000, for example)
-        let name_bytes = entry.name.as_bytes();
+        let name_bytes = match i < stripped {
+            true => &[],
+            false => entry.name.as_bytes(),
+        };
         let bytes_len = name_bytes.len();
         let name_length: u16 = if bytes_len >= 0xFFF {
             0xFFF
         } else {
             bytes_len as u16
         };
-        let flags: u16 = flag_assume_valid | entry.flag_stage | name_length;
+        let flag_extended: u16 = if entry.flag_skip_worktree { 1 << 14 } else { 0 };
+        let flags: u16 = flag_assume_valid | flag_extended | entry.flag_stage | name_length;
         f.write_all(&flags.to_be_bytes())?;
+        // The extended flags, of which only skip-worktree is written.
+        let extended_len = match entry.flag_skip_worktree {
+            true => {
+                f.write_all(&(1u16 << 14).to_be_bytes())?;
+                2
+            }
+            false => 0,
+        };
 
         f.write_all(name_bytes)?;
         f.write_all(&[0])?;
-        idx += 62 + name_bytes.len() + 1;
-
-        let pad = (8 - (idx % 8)) % 8;
+        // Entries are NUL-padded to a multiple of eight bytes, counted from
+        // the start of the entry.
+        let entry_len = 62 + extended_len + name_bytes.len() + 1;
+        let pad = (8 - (entry_len % 8)) % 8;
         if pad > 0 {
             f.write_all(&vec![0; pad])?;
-            idx += pad;
+        }
+    }
+    f.write_all(extensions)?;
+
+    // TRAILER: SHA-1 over everything written so far.
+    let checksum = Sha1::digest(&f);
+    f.extend_from_slice(&checksum);
+    Ok(f)
+}
+
+fn shared_index_path(repo: &Repository, sha: &str) -> PathBuf {
+    repo.repo_path(PathBuf::from(format!("sharedindex.{}", sha)))
+}
+
+/// Deletes the shared indexes other than `keep` not written to for
+/// `splitIndex.sharedIndexExpire`, two weeks by default.
+fn shared_index_expire(repo: &Repository, keep: &str) -> Result<()> {
+    let now = SystemTime::now();
+    let expire = match repo.config_get("splitindex", "sharedindexexpire") {
+        Some(value) => expiry_parse(&value, now)?,
+        None => Some(now - SHARED_INDEX_EXPIRE),
+    };
+    let Some(expire) = expire else {
+        return Ok(());
+    };
+    for entry in fs::read_dir(repo.repo_path(PathBuf::new()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let old = entry.metadata()?.modified()? < expire;
+        if name
+            .strip_prefix("sharedindex.")
+            .is_some_and(|sha| sha != keep)
+            && old
+        {
+            fs::remove_file(entry.path())?;
         }
     }
     Ok(())
 }
 
+/// How `entries` differ from those of `shared`: the link recording the
+/// shared entries deleted and replaced, and the entries to write, the
+/// replacements first and then those added.
+fn index_link_make<'a>(
+    shared: &SharedIndex,
+    entries: &'a [IndexEntry],
+) -> (IndexLink, Vec<&'a IndexEntry>) {
+    let mut link = IndexLink {
+        sha: shared.sha.clone(),
+        delete: Vec::new(),
+        replace: Vec::new(),
+    };
+    let (mut replaced, mut added) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    let key = |e: &IndexEntry| (e.name.clone(), e.flag_stage);
+    while i < entries.len() || j < shared.entries.len() {
+        let order = match (entries.get(i), shared.entries.get(j)) {
+            (Some(own), Some(base)) => key(own).cmp(&key(base)),
+            (Some(_), None) => Ordering::Less,
+            _ => Ordering::Greater,
+        };
+        match order {
+            Ordering::Less => {
+                added.push(&entries[i]);
+                i += 1;
+            }
+            Ordering::Greater => {
+                link.delete.push(j);
+                j += 1;
+            }
+            Ordering::Equal => {
+                if entries[i] != shared.entries[j] {
+                    link.replace.push(j);
+                    replaced.push(&entries[i]);
+                }
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    replaced.extend(added);
+    (link, replaced)
+}
+
+impl IndexLink {
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut data = hex::decode(&self.sha)?;
+        data.extend(ewah_encode(&self.delete));
+        data.extend(ewah_encode(&self.replace));
+        let mut out = b"link".to_vec();
+        out.extend((data.len() as u32).to_be_bytes());
+        out.extend(data);
+        Ok(out)
+    }
+
+    fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < 20 {
+            bail!("link extension too short");
+        }
+        let mut link = IndexLink {
+            sha: hex::encode(&data[..20]),
+            delete: Vec::new(),
+            replace: Vec::new(),
+        };
+        // Without bitmaps the index is the shared one unchanged.
+        if data.len() > 20 {
+            let (delete, used) = ewah_decode(&data[20..])?;
+            let (replace, _) = ewah_decode(&data[20 + used..])?;
+            link.delete = delete;
+            link.replace = replace;
+        }
+        Ok(link)
+    }
+
+    /// The entries of the split index: those of `shared` less the ones
+    /// deleted, those replaced swapped for the first of `own` (which take
+    /// their names) and the rest of `own` added.
+    fn apply(&self, shared: &[IndexEntry], own: Vec<IndexEntry>) -> Result<Vec<IndexEntry>> {
+        let mut base: Vec<Option<IndexEntry>> = shared.iter().cloned().map(Some).collect();
+        let mut own = own.into_iter();
+        for &pos in &self.replace {
+            let mut entry = own
+                .next()
+                .ok_or_else(|| anyhow!("split index is missing replaced entries"))?;
+            let Some(Some(old)) = base.get_mut(pos) else {
+                bail!("split index replaces entry {} that does not exist", pos);
+            };
+            entry.name = std::mem::take(&mut old.name);
+            *old = entry;
+        }
+        for &pos in &self.delete {
+            match base.get_mut(pos) {
+                Some(old) => *old = None,
+                None => bail!("split index deletes entry {} that does not exist", pos),
+            }
+        }
+        let mut entries: Vec<IndexEntry> = base.into_iter().flatten().chain(own).collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name).then(a.flag_stage.cmp(&b.flag_stage)));
+        Ok(entries)
+    }
+}
+
+/// Encodes the ascending bit positions `bits` as an EWAH compressed bitmap,
+/// git's format: runs of empty 64-bit words, each followed by the words
+/// with bits set.
+fn ewah_encode(bits: &[usize]) -> Vec<u8> {
+    let size = bits.last().map_or(0, |&b| b + 1);
+    let mut words = vec![0u64; size.div_ceil(64)];
+    for &b in bits {
+        words[b / 64] |= 1 << (b % 64);
+    }
+    let mut buffer = Vec::new();
+    let mut k = 0;
+    let rlw = loop {
+        let empty = words[k..].iter().take_while(|&&w| w == 0).count();
+        let empty = empty.min(u32::MAX as usize);
+        k += empty;
+        let literal = words[k..].iter().take_while(|&&w| w != 0).count();
+        let literal = literal.min((1 << 31) - 1);
+        let rlw = buffer.len();
+        buffer.push(((empty as u64) << 1) | ((literal as u64) << 33));
+        buffer.extend_from_slice(&words[k..k + literal]);
+        k += literal;
+        if k >= words.len() {
+            break rlw;
+        }
+    };
+    let mut out = Vec::new();
+    out.extend((size as u32).to_be_bytes());
+    out.extend((buffer.len() as u32).to_be_bytes());
+    for word in buffer {
+        out.extend(word.to_be_bytes());
+    }
+    out.extend((rlw as u32).to_be_bytes());
+    out
+}
+
+/// Decodes an EWAH bitmap into the positions of its set bits, returning
+/// them with the number of bytes it took up.
+fn ewah_decode(data: &[u8]) -> Result<(Vec<usize>, usize)> {
+    let truncated = || anyhow!("EWAH bitmap truncated");
+    let size = u32::from_be_bytes(data.get(0..4).ok_or_else(truncated)?.try_into()?) as usize;
+    let count = u32::from_be_bytes(data.get(4..8).ok_or_else(truncated)?.try_into()?) as usize;
+    let end = 8 + count * 8 + 4;
+    if data.len() < end {
+        return Err(truncated());
+    }
+    let word = |k: usize| u64::from_be_bytes(data[8 + k * 8..16 + k * 8].try_into().unwrap());
+    let (mut bits, mut pos, mut k) = (Vec::new(), 0, 0);
+    while k < count {
+        let rlw = word(k);
+        let run = ((rlw >> 1) & 0xFFFF_FFFF) as usize * 64;
+        let literal = (rlw >> 33) as usize;
+        if rlw & 1 == 1 {
+            bits.extend(pos..pos + run);
+        }
+        pos += run;
+        if k + literal >= count {
+            return Err(truncated());
+        }
+        for l in 1..=literal {
+            let w = word(k + l);
+            bits.extend((0..64).filter(|b| w >> b & 1 == 1).map(|b| pos + b));
+            pos += 64;
+        }
+        k += 1 + literal;
+    }
+    bits.retain(|&b| b < size);
+    Ok((bits, end))
+}
+
+/// Reads the index. A damaged one fails with a hint at `rit index rebuild`.
 pub fn index_read(repo: &Repository) -> Result<Index> {
+    let _phase = profile_phase("index read");
     let index_file = repo_file(repo, PathBuf::from("index"), false)?;
 
     if !index_file.exists() {
@@ -108,9 +610,45 @@ pub fn index_read(repo: &Repository) -> Result<Index> {
     }
 
     let raw = fs::read(index_file)?;
-    if raw.len() < 12 {
+    index_read_split(repo, &raw).map_err(|e| {
+        anyhow!(
+            "index file corrupt: {}\nhint: run 'rit index rebuild' to rebuild it from HEAD",
+            e
+        )
+    })
+}
+
+/// Parses the index file `raw`, and if it is split, the shared index it
+/// links to, combining the two.
+fn index_read_split(repo: &Repository, raw: &[u8]) -> Result<Index> {
+    let (mut index, link) = index_parse(raw)?;
+    let Some(link) = link else {
+        return Ok(index);
+    };
+    let path = shared_index_path(repo, &link.sha);
+    let raw = fs::read(&path).map_err(|e| anyhow!("cannot read {}: {}", path.display(), e))?;
+    let (shared, _) = index_parse(&raw)?;
+    index.entries = link.apply(&shared.entries, index.entries)?;
+    index.split = true;
+    index.shared = Some(SharedIndex {
+        sha: link.sha,
+        entries: shared.entries,
+    });
+    Ok(index)
+}
+
+/// Parses an index file into its entries and, for a split index, its link
+/// to the shared index. Extensions other than the link are skipped unless
+/// they are required ones, which begin with a lowercase letter.
+fn index_parse(raw: &[u8]) -> Result<(Index, Option<IndexLink>)> {
+    if raw.len() < 32 {
         bail!("Index file too short");
     }
+    // git may skip the hash (index.skipHash) and leave the trailer zeroed.
+    let (body, trailer) = raw.split_at(raw.len() - 20);
+    if trailer.iter().any(|&b| b != 0) && Sha1::digest(body).as_slice() != trailer {
+        bail!("Index checksum mismatch");
+    }
 
     let signature = &raw[0..4];
     if signature != b"DIRC" {
@@ -118,15 +656,16 @@ pub fn index_read(repo: &Repository) -> Result<Index> {
     }
 
     let version = u32::from_be_bytes(raw[4..8].try_into()?);
-    if version != 2 {
-        bail!("Only index version 2 is support");
+    if version != 2 && version != 3 {
+        bail!("Only index versions 2 and 3 are supported");
     }
     let count = u32::from_be_bytes(raw[8..12].try_into()?);
 
     let mut entries = Vec::new();
     let mut idx = 12;
     for _ in 0..count {
-        if idx + 62 > raw.len() {
+        let start = idx;
+        if idx + 62 > body.len() {
             bail!("Index entry truncated");
         }
 
@@ -139,14 +678,14 @@ pub fn index_read(repo: &Repository) -> Result<Index> {
         let dev = u32::from_be_bytes(raw[idx + 16..idx + 20].try_into()?);
         let ino = u32::from_be_bytes(raw[idx + 20..idx + 24].try_into()?);
 
-        let unused = u32::from_be_bytes(raw[idx + 24..idx + 26].try_into()?);
+        let unused = u16::from_be_bytes(raw[idx + 24..idx + 26].try_into()?);
         if unused != 0 {
             bail!("Unsed field non-zero");
         }
 
         let mode = u16::from_be_bytes(raw[idx + 26..idx + 28].try_into()?);
         let mode_type = mode >> 12;
-        if mode_type != 0b1000 || mode_type != 0b1010 || mode_type != 0b1110 {
+        if mode_type != 0b1000 && mode_type != 0b1010 && mode_type != 0b1110 {
             bail!("Invalid mode type: {}", mode_type);
         }
         let mode_perms = mode & 0x01FF;
@@ -160,24 +699,34 @@ pub fn index_read(repo: &Repository) -> Result<Index> {
 
         let flag_assume_valid = (flags & 0b1000000000000000) != 0;
         let flag_extended = (flags & 0b0100000000000000) != 0;
-        if !flag_extended {
-            bail!("Extended flag not support");
-        }
         let flag_stage = flags & 0b0011000000000000;
         let name_length = flags & 0b0000111111111111;
 
         idx += 62;
 
+        let mut flag_skip_worktree = false;
+        if flag_extended {
+            if version < 3 || idx + 2 > body.len() {
+                bail!("Invalid extended flags");
+            }
+            let extended = u16::from_be_bytes(raw[idx..idx + 2].try_into()?);
+            if extended & 0b0010000000000000 != 0 {
+                bail!("Intent-to-add entries are not supported");
+            }
+            flag_skip_worktree = (extended & 0b0100000000000000) != 0;
+            idx += 2;
+        }
+
         let name: String;
         if name_length < 0xFFF {
-            if (idx + name_length as usize) >= raw.len() || raw[idx + name_length as usize] != 0x00
+            if (idx + name_length as usize) >= body.len() || raw[idx + name_length as usize] != 0x00
             {
                 bail!("Invalid name format");
             }
             name = String::from_utf8(raw[idx..idx + name_length as usize].to_vec())?;
             idx += name_length as usize + 1;
         } else {
-            let null_idx = raw[idx..]
+            let null_idx = body[idx..]
                 .iter()
                 .position(|&b| b == 0)
                 .ok_or_else(|| anyhow!("No null terminator for long name in index"))?
@@ -186,11 +735,8 @@ pub fn index_read(repo: &Repository) -> Result<Index> {
             idx = null_idx + 1;
         }
 
-        idx = if idx % 8 == 0 {
-            idx
-        } else {
-            idx + (8 - (idx % 8))
-        };
+        let entry_len = idx - start;
+        idx = start + entry_len.div_ceil(8) * 8;
 
         entries.push(IndexEntry {
             ctime: (ctime_s, ctime_ns),
@@ -204,10 +750,213 @@ pub fn index_read(repo: &Repository) -> Result<Index> {
             fsize,
             sha,
             flag_assume_valid,
+            flag_skip_worktree,
             flag_stage,
             name,
         });
     }
 
-    Ok(Index { version, entries })
+    if idx > body.len() {
+        bail!("Index entry truncated");
+    }
+
+    let mut link = None;
+    while idx + 8 <= body.len() {
+        let signature = &body[idx..idx + 4];
+        let size = u32::from_be_bytes(body[idx + 4..idx + 8].try_into()?) as usize;
+        let data = body
+            .get(idx + 8..idx + 8 + size)
+            .ok_or_else(|| anyhow!("Index extension truncated"))?;
+        match signature {
+            b"link" => link = Some(IndexLink::decode(data)?),
+            [b'A'..=b'Z', ..] => {}
+            _ => bail!(
+                "unsupported index extension '{}'",
+                String::from_utf8_lossy(signature)
+            ),
+        }
+        idx += 8 + size;
+    }
+    let index = Index {
+        version,
+        entries,
+        ..Default::default()
+    };
+    Ok((index, link))
+}
+
+/// Checks or rebuilds the index: `rit index verify|rebuild`.
+pub fn index(rebuild: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    if rebuild {
+        let index = index_from_head(&repo)?;
+        index_write(&repo, &index)?;
+        println!(
+            "Rebuilt the index from HEAD with {} entries; staged changes were lost.",
+            index.entries.len()
+        );
+        return Ok(());
+    }
+    index_verify(&repo)
+}
+
+/// Checks that the index parses, that its entries are sorted and unique,
+/// and that the objects they name exist.
+pub fn index_verify(repo: &Repository) -> Result<()> {
+    let index = index_read(repo)?;
+    for (i, entry) in index.entries.iter().enumerate() {
+        if let Some(prev) = i.checked_sub(1).map(|p| &index.entries[p]) {
+            if (&prev.name, prev.flag_stage) >= (&entry.name, entry.flag_stage) {
+                bail!("Index entries are not sorted at {}", entry.name);
+            }
+        }
+        // Submodule commits live in the submodule's repository.
+        if entry.mode_type != 0b1110 && object_read_raw(repo, &entry.sha).is_err() {
+            bail!(
+                "Index entry {} points to missing object {}",
+                entry.name,
+                entry.sha
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The index HEAD's tree gives, without stat data so every file gets
+/// hashed when compared against the worktree. Empty on an unborn branch.
+pub fn index_from_head(repo: &Repository) -> Result<Index> {
+    let mut index = Index::default();
+    for leaf in head_leaves(repo)?.into_values() {
+        let (mode_type, mode_perms) = tree_mode_split(&leaf.mode)?;
+        index.entries.push(IndexEntry {
+            mode_type,
+            mode_perms,
+            sha: leaf.sha,
+            name: leaf.path,
+            ..Default::default()
+        });
+    }
+    index.entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{ewah_decode, ewah_encode};
+    use crate::{
+        head_update, index_entry_modified, index_from_head, index_read, index_verify, index_write,
+        object_hash,
+        test_utils::{make_commit, test_repo},
+        Index, IndexEntry,
+    };
+
+    #[test]
+    fn test_index_corrupt() {
+        let repo = test_repo("index-corrupt");
+        let commit = make_commit(&repo, "content", &[]);
+        head_update(&repo, &commit, "commit (initial): first").unwrap();
+        index_write(&repo, &index_from_head(&repo).unwrap()).unwrap();
+        index_verify(&repo).unwrap();
+
+        let path = repo.gitdir.join("index");
+        let mut raw = fs::read(&path).unwrap();
+        raw[20] ^= 1;
+        fs::write(&path, &raw).unwrap();
+        let err = index_read(&repo).err().unwrap().to_string();
+        assert!(err.contains("checksum mismatch"), "{}", err);
+
+        fs::write(&path, &raw[..40]).unwrap();
+        assert!(index_read(&repo).is_err());
+
+        let rebuilt = index_from_head(&repo).unwrap();
+        assert_eq!(rebuilt.entries.len(), 1);
+        assert_eq!(rebuilt.entries[0].name, "file");
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_split_index() {
+        let bits = vec![0, 3, 63, 64, 1000, 1001, 70000];
+        let (decoded, used) = ewah_decode(&ewah_encode(&bits)).unwrap();
+        assert_eq!((decoded, used), (bits.clone(), ewah_encode(&bits).len()));
+        assert_eq!(
+            ewah_decode(&ewah_encode(&[])).unwrap().0,
+            Vec::<usize>::new()
+        );
+
+        let repo = test_repo("split-index");
+        let entry = |name: &str, sha: &str| IndexEntry {
+            mode_type: 0b1000,
+            mode_perms: 0o644,
+            sha: sha.repeat(40),
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let mut index = Index {
+            entries: (0..20).map(|i| entry(&format!("f{:02}", i), "a")).collect(),
+            split: true,
+            ..Default::default()
+        };
+        index_write(&repo, &index).unwrap();
+        let mut read = index_read(&repo).unwrap();
+        assert!(read.split && read.shared.is_some());
+        assert!(read.entries == index.entries);
+
+        // One replaced, one deleted and one added is within 20% of the entries.
+        read.entries[2].sha = "b".repeat(40);
+        read.entries.remove(5);
+        read.entries.push(entry("g", "c"));
+        index_write(&repo, &read).unwrap();
+        let raw = fs::read(repo.gitdir.join("index")).unwrap();
+        assert_eq!(u32::from_be_bytes(raw[8..12].try_into().unwrap()), 2);
+        index = index_read(&repo).unwrap();
+        assert!(index.entries == read.entries);
+        assert_eq!(index.entries[2].name, "f02");
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_index_racy_clean() {
+        let repo = test_repo("index-racy");
+        let set_mtime = |path: &std::path::Path, mtime: SystemTime| {
+            let file = fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(mtime).unwrap();
+        };
+        // The stat data of "file" is taken after it changed to "new" within
+        // the same timestamp, while the index still records "old".
+        let old = object_hash(&b"old"[..], b"blob", Some(&repo)).unwrap();
+        fs::write(repo.worktree.join("file"), "new").unwrap();
+        let entry = IndexEntry::from_file(&repo, "file", &old, b"100644").unwrap();
+        let index = Index {
+            entries: vec![entry.clone()],
+            ..Default::default()
+        };
+        let file_mtime = fs::metadata(repo.worktree.join("file"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        let index_path = repo.gitdir.join("index");
+        fs::write(&index_path, "").unwrap();
+        set_mtime(&index_path, file_mtime);
+        assert!(index_entry_modified(&repo, &entry).unwrap());
+
+        // Once the index is newer than the file, the stat data is trusted.
+        set_mtime(&index_path, file_mtime + Duration::from_secs(3600));
+        assert!(!index_entry_modified(&repo, &entry).unwrap());
+
+        // Writing over an index no newer than the file smudges the entry, so
+        // it still shows as modified when the new index is newer.
+        set_mtime(&index_path, file_mtime);
+        index_write(&repo, &index).unwrap();
+        let read = index_read(&repo).unwrap();
+        assert_eq!(read.entries[0].fsize, 0);
+        set_mtime(&index_path, file_mtime + Duration::from_secs(3600));
+        assert!(index_entry_modified(&repo, &read.entries[0]).unwrap());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
 }
@@ -1,12 +1,14 @@
 mod cli;
 use std::{
     fs::{self, File},
-    io::Write,
+    io::{self, BufRead, Write},
     path::{Path, PathBuf},
 };
 
 use anyhow::{anyhow, bail, Result};
 pub use cli::*;
+mod profile;
+pub use profile::*;
 mod repository;
 use indexmap::IndexMap;
 pub use repository::*;
@@ -28,11 +30,172 @@ mod ignore;
 pub use ignore::*;
 mod status;
 pub use status::*;
-
-pub fn rm(paths: &[PathBuf]) -> Result<()> {
-    let repo = repo_find(Path::new("."), true)?.unwrap();
-    Ok(())
-}
+mod fsck;
+pub use fsck::*;
+mod reachable;
+pub use reachable::*;
+mod switch;
+pub use switch::*;
+mod restore;
+pub use restore::*;
+mod commit_graph;
+pub use commit_graph::*;
+mod config;
+pub use config::*;
+mod ancestry;
+pub use ancestry::*;
+mod reset;
+pub use reset::*;
+mod branch;
+pub use branch::*;
+mod diff;
+pub use diff::*;
+mod pkt_line;
+pub use pkt_line::*;
+mod pack;
+pub use pack::*;
+mod packfile;
+pub use packfile::*;
+mod upload_pack;
+pub use upload_pack::*;
+mod receive_pack;
+pub use receive_pack::*;
+mod serve;
+pub use serve::*;
+mod merge;
+pub use merge::*;
+mod add;
+pub use add::*;
+mod cherry_pick;
+pub use cherry_pick::*;
+mod revert;
+pub use revert::*;
+mod rebase;
+pub use rebase::*;
+mod maintenance;
+pub use maintenance::*;
+mod gc;
+pub use gc::*;
+mod reflog;
+pub use reflog::*;
+mod stash;
+pub use stash::*;
+mod pickaxe;
+pub use pickaxe::*;
+mod blame;
+pub use blame::*;
+mod submodule;
+pub use submodule::*;
+mod bisect;
+pub use bisect::*;
+mod attr;
+pub use attr::*;
+mod grep;
+pub use grep::*;
+mod show;
+pub use show::*;
+mod describe;
+pub use describe::*;
+mod shortlog;
+pub use shortlog::*;
+mod archive;
+pub use archive::*;
+mod undo;
+pub use undo::*;
+mod notes;
+pub use notes::*;
+mod worktree;
+pub use worktree::*;
+mod rm;
+pub use rm::*;
+mod transport;
+pub use transport::*;
+mod ssh;
+pub use ssh::*;
+mod url;
+pub use url::*;
+mod clone;
+pub use clone::*;
+mod fetch;
+pub use fetch::*;
+mod pull;
+mod remote;
+pub use pull::*;
+pub use remote::*;
+mod push;
+pub use push::*;
+mod menu;
+pub use menu::*;
+mod clean;
+pub use clean::*;
+mod add_interactive;
+pub use add_interactive::*;
+mod prune;
+pub use prune::*;
+mod count_objects;
+pub use count_objects::*;
+mod config_file;
+pub use config_file::*;
+mod verify_pack;
+pub use verify_pack::*;
+mod apply;
+pub use apply::*;
+mod bundle;
+pub use bundle::*;
+mod stripspace;
+pub use stripspace::*;
+mod am;
+pub use am::*;
+mod prompt;
+pub use prompt::*;
+mod format_patch;
+pub use format_patch::*;
+mod range_diff;
+pub use range_diff::*;
+mod rerere;
+pub use rerere::*;
+mod update_index;
+pub use update_index::*;
+mod difftool;
+pub use difftool::*;
+mod sparse_checkout;
+pub use sparse_checkout::*;
+mod compat;
+pub use compat::*;
+mod bridge;
+pub use bridge::*;
+mod mailmap;
+pub use mailmap::*;
+mod stats;
+pub use stats::*;
+mod trailer;
+pub use trailer::*;
+mod name_rev;
+pub use name_rev::*;
+mod merge_base;
+pub use merge_base::*;
+mod rev_list;
+pub use rev_list::*;
+mod symbolic_ref;
+pub use symbolic_ref::*;
+mod update_ref;
+pub use update_ref::*;
+mod read_tree;
+pub use read_tree::*;
+mod write_tree;
+pub use write_tree::*;
+mod commit_tree;
+pub use commit_tree::*;
+mod ls_remote;
+pub use ls_remote::*;
+mod cat_file;
+pub use cat_file::*;
+mod checkout_index;
+pub use checkout_index::*;
+mod check_ref_format;
+pub use check_ref_format::*;
+#[cfg(test)]
+mod test_utils;
 
 pub fn ls_files(verbose: bool) -> Result<()> {
     let repo = repo_find(Path::new("."), true)?.unwrap();
@@ -61,8 +224,8 @@ pub fn ls_files(verbose: bool) -> Result<()> {
             println!("  device: {}, inode: {}", entry.dev, entry.ino);
             println!("  user: {}  group: {}", entry.uid, entry.gid);
             println!(
-                "  flags: stage={} assume_valid={}",
-                entry.flag_stage, entry.flag_assume_valid
+                "  flags: stage={} assume_valid={} skip_worktree={}",
+                entry.flag_stage, entry.flag_assume_valid, entry.flag_skip_worktree
             );
         }
     }
@@ -70,27 +233,44 @@ pub fn ls_files(verbose: bool) -> Result<()> {
     Ok(())
 }
 
-pub fn rev_parse(name: &str, fmt: Option<&[u8]>) -> Result<()> {
+/// Prints the object `name` names. With `sha256`, the name printed is its
+/// SHA-256 one, and a full SHA-256 name is accepted for `name`; both need
+/// `extensions.compatObjectFormat = sha256`.
+pub fn rev_parse(name: &str, fmt: Option<&[u8]>, sha256: bool) -> Result<()> {
     let repo = repo_find(Path::new("."), true).unwrap().unwrap();
-    let obj_sha = object_find(&repo, name, fmt, true)?;
-    if let Some(sha) = obj_sha {
-        println!("{}", sha);
-    } else {
-        println!("None");
+    let compat_on = repo
+        .config_get("extensions", "compatobjectformat")
+        .is_some_and(|f| f.eq_ignore_ascii_case("sha256"));
+    let mut compat = match sha256 || (compat_on && name.len() == 64) {
+        true => Some(CompatMap::load(&repo)?),
+        false => None,
+    };
+    let translated = match &compat {
+        Some(compat) if name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            compat.sha1(&name.to_ascii_lowercase())
+        }
+        _ => None,
+    };
+    let obj_sha = object_find(&repo, translated.as_deref().unwrap_or(name), fmt, true)?;
+    match (obj_sha, compat.as_mut().filter(|_| sha256)) {
+        (Some(sha), Some(compat)) => println!("{}", compat.sha256(&sha)?),
+        (Some(sha), None) => println!("{}", sha),
+        (None, _) => println!("None"),
     }
     Ok(())
 }
 
-pub fn cat_file(object: &str, fmt: Option<&[u8]>) -> Result<()> {
+pub fn checkout(commit: &str, target: Option<&PathBuf>) -> Result<()> {
     let repo = repo_find(Path::new("."), true)?.unwrap();
-    let sha = object_find(&repo, object, fmt, true)?.unwrap();
-    let obj = object_read(&repo, &sha)?;
-    std::io::stdout().write_all(&obj.serialize())?;
-    Ok(())
-}
 
-pub fn checkout(commit: &str, target: &PathBuf) -> Result<()> {
-    let repo = repo_find(Path::new("."), true)?.unwrap();
+    // Without a directory, update the current worktree in place.
+    let target = match target {
+        Some(t) => t,
+        None => {
+            let is_branch = ref_resolve(&repo, &format!("refs/heads/{}", commit))?.is_some();
+            return switch_to(&repo, commit, is_branch);
+        }
+    };
 
     let mut obj = object_read(&repo, &object_find(&repo, commit, None, true)?.unwrap())?;
     if obj.fmt() == b"commit" {
@@ -126,13 +306,14 @@ fn tree_checkout(repo: &Repository, tree_obj: &dyn Object, path: &Path) -> Resul
         .downcast_ref::<Tree>()
         .ok_or_else(|| anyhow!("Object is not a tree"))?;
 
-    for item in tree.items.iter() {
-        let obj = object_read(repo, &item.sha)?;
-        let dest = path.join(&item.path);
-        if obj.fmt() == b"tree" {
+    tree_walk(repo, tree, "", &mut |name, item| {
+        let dest = path.join(name);
+        if item.mode.starts_with(b"04") {
             fs::create_dir(&dest)?;
-            tree_checkout(repo, obj.as_ref(), &dest)?;
-        } else if obj.fmt() == b"blob" {
+            return Ok(());
+        }
+        let obj = object_read(repo, &item.sha)?;
+        if obj.fmt() == b"blob" {
             let blob = obj
                 .as_any()
                 .downcast_ref::<Blob>()
@@ -146,52 +327,144 @@ fn tree_checkout(repo: &Repository, tree_obj: &dyn Object, path: &Path) -> Resul
                 std::str::from_utf8(obj.fmt())?
             );
         }
-    }
-    Ok(())
+        Ok(())
+    })
 }
 
+/// Resolves `refname`, following symbolic refs, to the sha it points at. A
+/// loose ref file wins over the same ref in packed-refs.
 fn ref_resolve(repo: &Repository, refname: &str) -> Result<Option<String>> {
     let path = repo_file(repo, PathBuf::from(refname), false)?;
     if !path.is_file() {
-        return Ok(None);
+        return Ok(packed_refs_read(repo)?.shift_remove(refname));
     }
 
     let data = fs::read_to_string(&path)?;
     let data = data.trim_end();
     if let Some(p) = data.strip_prefix("ref: ") {
         ref_resolve(repo, p)
-    // if data.starts_with("ref: ") {
-    // ref_resolve(repo, &data[5..])
     } else {
         Ok(Some(data.to_string()))
     }
 }
 
+/// The refs in packed-refs, by full name. The peeled values of annotated
+/// tags ("^<sha>" lines) are left out.
+fn packed_refs_read(repo: &Repository) -> Result<IndexMap<String, String>> {
+    let path = repo_file(repo, PathBuf::from("packed-refs"), false)?;
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(IndexMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut ret = IndexMap::new();
+    for line in data.lines() {
+        if line.starts_with('#') || line.starts_with('^') {
+            continue;
+        }
+        let Some((sha, name)) = line.split_once(' ') else {
+            bail!("unexpected line in packed-refs: {}", line);
+        };
+        ret.insert(name.to_string(), sha.to_string());
+    }
+    Ok(ret)
+}
+
+/// Deletes `refname`, both its loose file and its entry in packed-refs,
+/// whichever exist. Its reflog is left alone.
+pub fn ref_delete(repo: &Repository, refname: &str) -> Result<()> {
+    let path = repo_file(repo, PathBuf::from(refname), false)?;
+    if path.is_file() {
+        fs::remove_file(path)?;
+    }
+    let path = repo_file(repo, PathBuf::from("packed-refs"), false)?;
+    let Ok(data) = fs::read_to_string(&path) else {
+        return Ok(());
+    };
+    let mut kept = String::new();
+    let mut removed = false;
+    let mut dropping = false;
+    for line in data.lines() {
+        // A peeled line belongs to the ref before it.
+        if line.starts_with('^') && dropping {
+            continue;
+        }
+        dropping = line
+            .split_once(' ')
+            .is_some_and(|(_, name)| name == refname);
+        if dropping {
+            removed = true;
+            continue;
+        }
+        kept.push_str(line);
+        kept.push('\n');
+    }
+    if removed {
+        file_write_locked(&path, kept.as_bytes(), repo.fsync.refs)?;
+    }
+    Ok(())
+}
+
+/// Lists the refs under the directory `path` (the refs directory by
+/// default), loose and packed, sorted by name. They are named relative to
+/// `path`, after `prefix` if given.
 fn ref_list_flat(
     repo: &Repository,
     path: Option<PathBuf>,
     prefix: Option<&str>,
 ) -> Result<IndexMap<String, String>> {
-    let mut ret = IndexMap::new();
-
     let path = match path {
         Some(p) => p,
-        None => repo_dir(repo, PathBuf::from("refs"), false)?.unwrap(),
+        None => repo.repo_path(PathBuf::from("refs")),
+    };
+    let mut ret = match path.is_dir() {
+        true => ref_list_loose(repo, &path, prefix)?,
+        false => IndexMap::new(),
     };
 
+    let base = path
+        .strip_prefix(&repo.commondir)
+        .or_else(|_| path.strip_prefix(&repo.gitdir))
+        .map(|p| p.to_string_lossy().replace('\\', "/"))
+        .unwrap_or_default();
+    for (name, sha) in packed_refs_read(repo)? {
+        let Some(rest) = name.strip_prefix(&format!("{}/", base)) else {
+            continue;
+        };
+        let key = match prefix {
+            Some(pref) => format!("{}/{}", pref, rest),
+            None => rest.to_string(),
+        };
+        ret.entry(key).or_insert(sha);
+    }
+    ret.sort_keys();
+    Ok(ret)
+}
+
+fn ref_list_loose(
+    repo: &Repository,
+    path: &Path,
+    prefix: Option<&str>,
+) -> Result<IndexMap<String, String>> {
+    let mut ret = IndexMap::new();
+
     let mut entries: Vec<_> = fs::read_dir(path)?.filter_map(|e| e.ok()).collect();
     entries.sort_by_key(|e| e.file_name());
 
     for entry in entries {
         let p = entry.path();
         let name = entry.file_name().to_string_lossy().to_string();
+        // A ref being written, see file_write_locked.
+        if name.ends_with(".lock") {
+            continue;
+        }
         let full_name = match prefix {
             Some(pref) => format!("{}/{}", pref, name),
             None => name,
         };
 
         if p.is_dir() {
-            let sub = ref_list_flat(repo, Some(p), Some(&full_name))?;
+            let sub = ref_list_loose(repo, &p, Some(&full_name))?;
             ret.extend(sub);
         } else if let Some(sha) = ref_resolve(repo, &p.to_string_lossy())? {
             ret.insert(full_name, sha);
@@ -201,20 +474,142 @@ fn ref_list_flat(
     Ok(ret)
 }
 
-pub fn show_ref() -> Result<()> {
+/// Which refs `show-ref` lists and how.
+#[derive(Default)]
+pub struct ShowRefOptions {
+    /// Only branches, or with `tags` branches and tags.
+    pub heads: bool,
+    pub tags: bool,
+    /// Only the object names.
+    pub hash: bool,
+    /// Filter refs read from stdin instead, dropping those that exist here
+    /// and, if not empty, those not starting with the pattern.
+    pub exclude_existing: Option<String>,
+}
+
+/// Lists the refs matching any of `patterns`, or all of them. A pattern
+/// matches a ref it ends, counting whole path components only, so "main"
+/// matches refs/heads/main but not refs/heads/domain.
+pub fn show_ref(patterns: &[String], opts: &ShowRefOptions) -> Result<()> {
     let repo = repo_find(Path::new("."), true)?.unwrap();
     let refs = ref_list_flat(&repo, None, Some("refs"))?;
+    if let Some(prefix) = &opts.exclude_existing {
+        return show_ref_exclude_existing(&refs, prefix, io::stdin().lock());
+    }
+
+    for (name, sha) in &refs {
+        let kind_ok = match (opts.heads, opts.tags) {
+            (false, false) => true,
+            (heads, tags) => {
+                (heads && name.starts_with("refs/heads/"))
+                    || (tags && name.starts_with("refs/tags/"))
+            }
+        };
+        if !kind_ok || !(patterns.is_empty() || patterns.iter().any(|p| show_ref_match(name, p))) {
+            continue;
+        }
+        match opts.hash {
+            true => println!("{}", sha),
+            false => println!("{} {}", sha, name),
+        }
+    }
+    Ok(())
+}
+
+fn show_ref_match(refname: &str, pattern: &str) -> bool {
+    refname == pattern
+        || refname
+            .strip_suffix(pattern)
+            .is_some_and(|rest| rest.ends_with('/'))
+}
 
-    show_ref_print(&refs, true);
+/// Echoes the lines of `input`, each a ref optionally preceded by an object
+/// name, whose ref is not in `refs`: what a mirror still has to fetch. A
+/// trailing "^{}" is dropped and malformed ref names are skipped.
+fn show_ref_exclude_existing(
+    refs: &IndexMap<String, String>,
+    prefix: &str,
+    input: impl BufRead,
+) -> Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        let line = line.strip_suffix("^{}").unwrap_or(&line);
+        let refname = line.rsplit([' ', '\t']).next().unwrap_or_default();
+        if !refname.starts_with(prefix) {
+            continue;
+        }
+        if !refname_valid(refname, false) {
+            eprintln!("warning: ref '{}' ignored", refname);
+            continue;
+        }
+        if !refs.contains_key(refname) {
+            println!("{}", line);
+        }
+    }
     Ok(())
 }
 
-fn show_ref_print(refs: &IndexMap<String, String>, with_hash: bool) {
-    for (k, _v) in refs {
-        if with_hash {
-            println!(" {}", k);
-        } else {
-            println!("{}", k);
+/// Whether `name` is a well-formed ref name, by git's rules: no component
+/// empty, starting with "." or ending with ".lock", no "..", "@{", control
+/// characters or any of ` ~^:?*[\`, and not ending with "/" or ".". Unless
+/// `allow_onelevel`, it also needs at least two components, as in
+/// "refs/foo".
+pub fn refname_valid(name: &str, allow_onelevel: bool) -> bool {
+    let forbidden = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    name != "@"
+        && !name.contains(forbidden)
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.ends_with('.')
+        && (allow_onelevel || name.contains('/'))
+        && name
+            .split('/')
+            .all(|c| !c.is_empty() && !c.starts_with('.') && !c.ends_with(".lock"))
+}
+
+/// Whether `name` can name a branch: its ref under refs/heads/ must be
+/// valid, and it can't be "HEAD" or start with "-", which would read as an
+/// option.
+pub fn branch_name_valid(name: &str) -> bool {
+    name != "HEAD"
+        && !name.starts_with('-')
+        && refname_valid(&format!("refs/heads/{}", name), false)
+}
+
+fn show_ref_print(refs: &IndexMap<String, String>) {
+    for name in refs.keys() {
+        println!("{}", name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{branch_name_valid, refname_valid, show_ref_match};
+
+    #[test]
+    fn test_refnames() {
+        assert!(show_ref_match("refs/heads/main", "main"));
+        assert!(show_ref_match("refs/heads/main", "heads/main"));
+        assert!(!show_ref_match("refs/heads/domain", "main"));
+
+        assert!(refname_valid("refs/heads/feature/x", false));
+        assert!(refname_valid("HEAD", true));
+        assert!(!refname_valid("HEAD", false));
+        assert!(branch_name_valid("feature/x"));
+        assert!(!branch_name_valid("HEAD"));
+        assert!(!branch_name_valid("-x"));
+        for bad in [
+            "refs/heads/a..b",
+            "refs/heads/.hidden",
+            "refs/heads/x.lock",
+            "refs/heads//x",
+            "refs/heads/x/",
+            "refs/heads/x.",
+            "refs/heads/a b",
+            "refs/heads/a@{1}",
+            "refs/heads/a:b",
+        ] {
+            assert!(!refname_valid(bad, true), "{}", bad);
         }
     }
 }
@@ -1,29 +1,321 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
 use std::path::Path;
 
 use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Result;
 
+use crate::branch_get_active;
+use crate::commit_subject;
+use crate::date_format_default;
+use crate::date_format_iso;
 use crate::object_find;
 use crate::object_read;
+use crate::pickaxe_matches;
+use crate::ref_list_flat;
 use crate::repo_find;
+use crate::show_commit_header;
+use crate::tag_peel;
+use crate::Ancestry;
 use crate::Commit;
+use crate::Pickaxe;
 use crate::Repository;
+use crate::RevWalk;
 use crate::Tree;
+use crate::{abbrev_len, sha_abbrev};
 
-pub fn log(commit: &str) -> Result<()> {
+/// What `log` shows, and how.
+#[derive(Default)]
+pub struct LogOptions {
+    /// Draw the history as a Graphviz graph instead of listing it.
+    pub graphviz: bool,
+    pub format: LogFormat,
+    pub max_count: Option<usize>,
+    /// Only commits whose changes match.
+    pub pickaxe: Option<Pickaxe>,
+    /// How many hex digits short object names have; None for as many as
+    /// `core.abbrev` says.
+    pub abbrev: Option<usize>,
+}
+
+/// How `log` prints each commit.
+#[derive(Default)]
+pub enum LogFormat {
+    /// Its header, author, date and indented message, as `git log` does.
+    #[default]
+    Medium,
+    /// One line: its abbreviated id and subject.
+    Oneline,
+    /// `format` with its placeholders, see `log_format`, filled in. With
+    /// `terminator` each commit's output ends in a newline, otherwise one
+    /// goes between commits.
+    Custom { format: String, terminator: bool },
+}
+
+/// Lists the commits reachable from `commit`, newest first, with their
+/// author, date and message as `git log` does.
+pub fn log(commit: &str, opts: &LogOptions) -> Result<()> {
     let repo = repo_find(Path::new("."), true)?.unwrap();
+    let abbrev = abbrev_len(&repo, opts.abbrev);
+    if opts.graphviz {
+        return log_graphviz_print(&repo, commit, opts.pickaxe.as_ref(), abbrev);
+    }
+    let mut out = io::stdout().lock();
+    let walk = RevWalk::new(&repo, &[commit.to_string()])?;
+    let mut shown = 0;
+    let mut decorations = None;
+    for sha in walk {
+        if opts.max_count.is_some_and(|max| shown >= max) {
+            break;
+        }
+        let sha = sha?;
+        if let Some(pickaxe) = &opts.pickaxe {
+            if !pickaxe_matches(&repo, &sha, pickaxe)? {
+                continue;
+            }
+        }
+        let obj = object_read(&repo, &sha)?;
+        let commit = obj
+            .as_any()
+            .downcast_ref::<Commit>()
+            .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+        match &opts.format {
+            LogFormat::Medium => {
+                if shown > 0 {
+                    writeln!(out)?;
+                }
+                out.write_all(&show_commit_header(&sha, commit))?;
+            }
+            LogFormat::Oneline => {
+                let message = commit.message();
+                let subject = message.lines().next().unwrap_or_default();
+                writeln!(out, "{} {}", sha_abbrev(&repo, &sha, abbrev), subject)?;
+            }
+            LogFormat::Custom { format, terminator } => {
+                if shown > 0 && !terminator {
+                    writeln!(out)?;
+                }
+                let decorations = match decorations {
+                    Some(ref decorations) => decorations,
+                    None => decorations.insert(log_decorations(&repo)?),
+                };
+                let line = log_format(&repo, format, &sha, commit, decorations, abbrev);
+                write!(out, "{}", line)?;
+                if *terminator {
+                    writeln!(out)?;
+                }
+            }
+        }
+        shown += 1;
+    }
+    Ok(())
+}
+
+/// Fills in the placeholders of `format` for the commit `sha`:
+///
+/// - `%H`, `%h`: the commit's id, full and abbreviated
+/// - `%T`, `%t`, `%P`, `%p`: the same for its tree and parents
+/// - `%an`, `%ae`, `%ad`, `%at`, `%ai`: the author's name, email, and date,
+///   as a timestamp or in ISO format
+/// - `%cn`, `%ce`, `%cd`, `%ct`, `%ci`: the same for the committer
+/// - `%s`: the subject, the message's first paragraph on one line
+/// - `%b`: the body, the rest of the message
+/// - `%d`: the refs pointing at the commit, as " (HEAD -> main, tag: v1)"
+/// - `%n` and `%%`: a newline and a percent sign
+///
+/// Anything else is left as it is.
+pub fn log_format(
+    repo: &Repository,
+    format: &str,
+    sha: &str,
+    commit: &Commit,
+    decorations: &HashMap<String, Vec<String>>,
+    abbrev: usize,
+) -> String {
+    let message = commit.message();
+    let (subject, body) = match message.split_once("\n\n") {
+        Some((subject, body)) => (subject, body.trim_start_matches('\n')),
+        None => (message.trim_end_matches('\n'), ""),
+    };
+    let author = commit.author();
+    let committer = commit.committer();
+    let parents = commit.parents();
+
+    let mut out = String::new();
+    let mut rest = format;
+    while let Some(pos) = rest.find('%') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let (who, spec) = match rest.as_bytes().get(1) {
+            Some(b'a') => (Some(&author), rest.get(2..3)),
+            Some(b'c') => (Some(&committer), rest.get(2..3)),
+            _ => (None, rest.get(1..2)),
+        };
+        let expanded = match (who, spec) {
+            (Some(who), Some(spec)) => who.as_ref().and_then(|who| match spec {
+                "n" => Some(who.name.clone()),
+                "e" => Some(who.email.clone()),
+                "d" => Some(date_format_default(who.time, &who.tz)),
+                "t" => Some(who.time.to_string()),
+                "i" => Some(date_format_iso(who.time, &who.tz)),
+                _ => None,
+            }),
+            (Some(_), None) => None,
+            (None, spec) => match spec.unwrap_or_default() {
+                "H" => Some(sha.to_string()),
+                "h" => Some(sha_abbrev(repo, sha, abbrev)),
+                "T" => commit.tree(),
+                "t" => commit.tree().map(|t| sha_abbrev(repo, &t, abbrev)),
+                "P" => Some(parents.join(" ")),
+                "p" => Some(
+                    parents
+                        .iter()
+                        .map(|p| sha_abbrev(repo, p, abbrev))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+                "s" => Some(subject.lines().collect::<Vec<_>>().join(" ")),
+                "b" => Some(body.to_string()),
+                "d" => Some(match decorations.get(sha) {
+                    Some(names) => format!(" ({})", names.join(", ")),
+                    None => String::new(),
+                }),
+                "n" => Some("\n".to_string()),
+                "%" => Some("%".to_string()),
+                _ => None,
+            },
+        };
+        let len = 1 + who.map_or(0, |_| 1) + spec.map_or(0, str::len);
+        match expanded {
+            Some(expanded) => {
+                out.push_str(&expanded);
+                rest = &rest[len..];
+            }
+            // An unknown placeholder: keep the '%' and go on after it.
+            None => {
+                out.push('%');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The names `%d` shows for each commit that refs point at: HEAD, as
+/// "HEAD -> main" when on a branch, then tags as "tag: v1", then branches
+/// and remote-tracking branches by their short names.
+fn log_decorations(repo: &Repository) -> Result<HashMap<String, Vec<String>>> {
+    let mut decorations: HashMap<String, Vec<String>> = HashMap::new();
+    let active = branch_get_active(repo)?;
+    if let Some(head) = object_find(repo, "HEAD", None, false).ok().flatten() {
+        let name = match &active {
+            Some(branch) => format!("HEAD -> {}", branch),
+            None => "HEAD".to_string(),
+        };
+        decorations.entry(head).or_default().push(name);
+    }
+    let refs = ref_list_flat(repo, None, Some("refs"))?;
+    let (tags, others): (Vec<_>, Vec<_>) = refs
+        .iter()
+        .partition(|(name, _)| name.starts_with("refs/tags/"));
+    for (name, sha) in tags.into_iter().chain(others) {
+        let short = match name.strip_prefix("refs/tags/") {
+            Some(tag) => format!("tag: {}", tag),
+            None => {
+                let short = name
+                    .strip_prefix("refs/heads/")
+                    .or_else(|| name.strip_prefix("refs/remotes/"))
+                    .unwrap_or(name);
+                if active.as_deref() == Some(short) && name.starts_with("refs/heads/") {
+                    continue;
+                }
+                short.to_string()
+            }
+        };
+        let target = tag_peel(repo, sha)?.unwrap_or_else(|| sha.clone());
+        decorations.entry(target).or_default().push(short);
+    }
+    Ok(decorations)
+}
+
+fn log_graphviz_print(
+    repo: &Repository,
+    commit: &str,
+    pickaxe: Option<&Pickaxe>,
+    abbrev: usize,
+) -> Result<()> {
     println!("digraph wyaglog{{");
     println!("  node[shape=rect]");
     let mut seen = HashSet::new();
-    let sha = object_find(&repo, commit, None, false)?.unwrap();
-    log_graphviz(&repo, &sha, &mut seen)?;
+    let sha = object_find(repo, commit, None, false)?.unwrap();
+    match pickaxe {
+        Some(pickaxe) => log_graphviz_pickaxe(repo, &sha, pickaxe, abbrev)?,
+        None => log_graphviz(repo, &sha, abbrev, &mut seen)?,
+    }
     println!("}}");
     Ok(())
 }
 
-fn log_graphviz(repo: &Repository, sha: &str, seen: &mut HashSet<String>) -> Result<()> {
+/// Like `log_graphviz`, but only draws the commits matching `pickaxe`,
+/// each linked to its nearest matching ancestors.
+fn log_graphviz_pickaxe(
+    repo: &Repository,
+    tip: &str,
+    pickaxe: &Pickaxe,
+    abbrev: usize,
+) -> Result<()> {
+    let mut ancestry = Ancestry::new(repo)?;
+    let mut matches = HashMap::new();
+    let mut stack = vec![tip.to_string()];
+    while let Some(sha) = stack.pop() {
+        if matches.contains_key(&sha) {
+            continue;
+        }
+        matches.insert(sha.clone(), pickaxe_matches(repo, &sha, pickaxe)?);
+        stack.extend(ancestry.parents(&sha)?);
+    }
+
+    let mut shas: Vec<&String> = matches
+        .iter()
+        .filter(|(_, m)| **m)
+        .map(|(s, _)| s)
+        .collect();
+    shas.sort();
+    for sha in shas {
+        let subject = commit_subject(repo, sha)?
+            .replace("\\", "\\\\")
+            .replace("\"", "\\\"");
+        println!(
+            "  c_{} [label=\"{}: {}\"];",
+            sha,
+            sha_abbrev(repo, sha, abbrev),
+            subject
+        );
+
+        let mut seen = HashSet::new();
+        let mut stack = ancestry.parents(sha)?;
+        while let Some(parent) = stack.pop() {
+            if !seen.insert(parent.clone()) {
+                continue;
+            }
+            if matches[&parent] {
+                println!("  c_{} -> c_{};", sha, parent);
+            } else {
+                stack.extend(ancestry.parents(&parent)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn log_graphviz(
+    repo: &Repository,
+    sha: &str,
+    abbrev: usize,
+    seen: &mut HashSet<String>,
+) -> Result<()> {
     if seen.contains(sha) {
         return Ok(());
     }
@@ -48,7 +340,12 @@ fn log_graphviz(repo: &Repository, sha: &str, seen: &mut HashSet<String>) -> Res
         message = message[..pos].to_string();
     }
     let message = message.replace("\\", "\\\\").replace("\"", "\\\"");
-    println!("  c_{} [label=\"{}: {}\"];", sha, &sha[..7], message);
+    println!(
+        "  c_{} [label=\"{}: {}\"];",
+        sha,
+        sha_abbrev(repo, sha, abbrev),
+        message
+    );
 
     let parent_key = Some(b"parent".to_vec());
     if !commit.kvlm.contains_key(&parent_key) {
@@ -61,19 +358,28 @@ fn log_graphviz(repo: &Repository, sha: &str, seen: &mut HashSet<String>) -> Res
     for parent in parents {
         let parent_str = String::from_utf8_lossy(parent).to_string();
         println!("  c_{} -> c_{};", sha, parent_str);
-        log_graphviz(repo, &parent_str, seen)?;
+        log_graphviz(repo, &parent_str, abbrev, seen)?;
     }
 
     Ok(())
 }
 
-pub fn ls_tree(tree_ref: &str, recursive: bool) -> Result<()> {
+/// Lists the entries of `tree_ref`, with full object names unless
+/// `abbrev` says otherwise.
+pub fn ls_tree(tree_ref: &str, recursive: bool, abbrev: Option<usize>) -> Result<()> {
     let repo = repo_find(Path::new("."), true)?.unwrap();
-    ls_tree_inner(&repo, tree_ref, recursive, "")?;
+    let abbrev = abbrev_len(&repo, abbrev);
+    ls_tree_inner(&repo, tree_ref, recursive, abbrev, "")?;
     Ok(())
 }
 
-fn ls_tree_inner(repo: &Repository, tree_ref: &str, recursive: bool, prefix: &str) -> Result<()> {
+pub fn ls_tree_inner(
+    repo: &Repository,
+    tree_ref: &str,
+    recursive: bool,
+    abbrev: usize,
+    prefix: &str,
+) -> Result<()> {
     let sha = object_find(repo, tree_ref, Some(b"tree"), true)?.unwrap();
     let obj = object_read(repo, &sha)?;
     let tree = obj
@@ -111,14 +417,20 @@ fn ls_tree_inner(repo: &Repository, tree_ref: &str, recursive: bool, prefix: &st
             } else {
                 format!("{}/{}", prefix, item.path)
             };
-            println!("{} {} {}\t{}", padded_mode, typ, item.sha, full_path);
+            println!(
+                "{} {} {}\t{}",
+                padded_mode,
+                typ,
+                sha_abbrev(repo, &item.sha, abbrev),
+                full_path
+            );
         } else {
             let prefix = if prefix.is_empty() {
                 item.path.clone()
             } else {
                 format!("{}/{}", prefix, item.path)
             };
-            ls_tree_inner(repo, &item.sha, recursive, &prefix)?;
+            ls_tree_inner(repo, &item.sha, recursive, abbrev, &prefix)?;
         }
     }
     Ok(())
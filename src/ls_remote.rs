@@ -0,0 +1,92 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::{remote_default, repo_find, show_ref_match, url_rewrite, Transport};
+
+/// Which of the refs a remote advertises `ls_remote` lists.
+#[derive(Default)]
+pub struct LsRemoteOptions {
+    /// Only branches, or with `tags` branches and tags.
+    pub heads: bool,
+    pub tags: bool,
+    /// Leave out peeled tags and HEAD: only the refs themselves.
+    pub refs: bool,
+    /// Show which ref HEAD points to as well.
+    pub symref: bool,
+}
+
+/// Lists the refs `remote` (the name of a configured remote or a URL, the
+/// current branch's remote by default) advertises, matching any of
+/// `patterns` as `show_ref` matches them. Annotated tags are followed by
+/// the objects they peel to, on `<tag>^{}` lines. Nothing is fetched.
+pub fn ls_remote(remote: Option<&str>, patterns: &[String], opts: &LsRemoteOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), false)?;
+    let name = match (remote, &repo) {
+        (Some(remote), _) => remote.to_string(),
+        (None, Some(repo)) => remote_default(repo)?,
+        (None, None) => bail!("No remote configured to list refs from."),
+    };
+    let url = repo
+        .as_ref()
+        .and_then(|r| r.config_get(&format!("remote \"{}\"", name), "url"))
+        .unwrap_or(name);
+    let url = url_rewrite(repo.as_ref(), &url, false);
+    if remote.is_none() {
+        eprintln!("From {}", url);
+    }
+    let advertised = Transport::open(&url, repo.as_ref())?.advertise("git-upload-pack")?;
+
+    let head_target = advertised
+        .caps
+        .iter()
+        .find_map(|c| c.strip_prefix("symref=HEAD:"));
+    for (name, sha) in &advertised.refs {
+        let kind_ok = match (opts.heads, opts.tags) {
+            (false, false) => true,
+            (heads, tags) => {
+                (heads && name.starts_with("refs/heads/"))
+                    || (tags && name.starts_with("refs/tags/"))
+            }
+        };
+        let refs_ok = !opts.refs || (name.starts_with("refs/") && !name.ends_with("^{}"));
+        if !kind_ok
+            || !refs_ok
+            || !(patterns.is_empty() || patterns.iter().any(|p| show_ref_match(name, p)))
+        {
+            continue;
+        }
+        if let Some(target) = head_target.filter(|_| opts.symref && name == "HEAD") {
+            println!("ref: {}\tHEAD", target);
+        }
+        println!("{}\t{}", sha, name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ref_create,
+        test_utils::{make_commit, pack_refs, test_repo},
+        Transport,
+    };
+
+    #[test]
+    fn test_ls_remote_packed_refs() {
+        let repo = test_repo("ls-remote-packed");
+        let sha = make_commit(&repo, "one", &[]);
+        ref_create(&repo, "heads/master", &sha).unwrap();
+        ref_create(&repo, "tags/v1", &sha).unwrap();
+        pack_refs(&repo);
+
+        let url = repo.worktree.display().to_string();
+        let advertised = Transport::open(&url, None)
+            .unwrap()
+            .advertise("git-upload-pack")
+            .unwrap();
+        let names: Vec<&str> = advertised.refs.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["HEAD", "refs/heads/master", "refs/tags/v1"]);
+        assert!(advertised.refs.iter().all(|(_, s)| *s == sha));
+    }
+}
@@ -0,0 +1,143 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+
+use crate::Repository;
+
+/// One .mailmap line: the canonical name and/or email for commits made
+/// with `email`, and, if given, only with `name` too.
+struct MailmapEntry {
+    proper_name: Option<String>,
+    proper_email: Option<String>,
+    name: Option<String>,
+    email: String,
+}
+
+/// The identities a .mailmap folds together.
+#[derive(Default)]
+pub struct Mailmap {
+    entries: Vec<MailmapEntry>,
+}
+
+impl Mailmap {
+    /// Parses .mailmap lines of the forms
+    ///
+    /// ```text
+    /// Proper Name <commit@email>
+    /// <proper@email> <commit@email>
+    /// Proper Name <proper@email> <commit@email>
+    /// Proper Name <proper@email> Commit Name <commit@email>
+    /// ```
+    ///
+    /// skipping comments and lines that fit none of them.
+    pub fn parse(content: &str) -> Self {
+        let mut entries = Vec::new();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut parts = Vec::new();
+            let mut rest = line;
+            while let Some((before, after)) = rest.split_once('<') {
+                let Some((email, after)) = after.split_once('>') else {
+                    break;
+                };
+                let name = before.trim();
+                parts.push((
+                    (!name.is_empty()).then(|| name.to_string()),
+                    email.to_string(),
+                ));
+                rest = after;
+            }
+            let entry = match parts.as_slice() {
+                [(proper_name, email)] if proper_name.is_some() => MailmapEntry {
+                    proper_name: proper_name.clone(),
+                    proper_email: None,
+                    name: None,
+                    email: email.clone(),
+                },
+                [(proper_name, proper_email), (name, email)] => MailmapEntry {
+                    proper_name: proper_name.clone(),
+                    proper_email: Some(proper_email.clone()),
+                    name: name.clone(),
+                    email: email.clone(),
+                },
+                _ => continue,
+            };
+            entries.push(entry);
+        }
+        Self { entries }
+    }
+
+    /// The canonical name and email for `name <email>`. Entries naming the
+    /// commit name as well win over those that only give the email, and a
+    /// later entry over an earlier one; both compare case-insensitively.
+    pub fn lookup(&self, name: &str, email: &str) -> (String, String) {
+        let matching = |with_name: bool| {
+            self.entries.iter().rev().find(|e| {
+                e.email.eq_ignore_ascii_case(email)
+                    && match &e.name {
+                        Some(n) => with_name && n.eq_ignore_ascii_case(name),
+                        None => !with_name,
+                    }
+            })
+        };
+        match matching(true).or_else(|| matching(false)) {
+            Some(entry) => (
+                entry
+                    .proper_name
+                    .clone()
+                    .unwrap_or_else(|| name.to_string()),
+                entry
+                    .proper_email
+                    .clone()
+                    .unwrap_or_else(|| email.to_string()),
+            ),
+            None => (name.to_string(), email.to_string()),
+        }
+    }
+
+    /// `lookup` on an identity as commits record it, "Name <email>".
+    pub fn lookup_ident(&self, ident: &str) -> (String, String) {
+        let (name, email) = ident.split_once('<').unwrap_or((ident, ""));
+        self.lookup(name.trim(), email.trim_end_matches('>'))
+    }
+}
+
+/// The .mailmap at the top of the worktree followed by the file
+/// `mailmap.file` names, so that the latter's entries win.
+pub fn mailmap_read(repo: &Repository) -> Result<Mailmap> {
+    let mut content = fs::read_to_string(repo.worktree.join(".mailmap")).unwrap_or_default();
+    if let Some(path) = repo.config_get("mailmap", "file") {
+        content.push('\n');
+        content.push_str(&fs::read_to_string(PathBuf::from(path))?);
+    }
+    Ok(Mailmap::parse(&content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mailmap;
+
+    #[test]
+    fn test_mailmap_lookup() {
+        let mailmap = Mailmap::parse(
+            "Jane Doe <jane@example.com>\n\
+             <jane@example.com> <jd@old.example>\n\
+             Jane Doe <jane@example.com> Janie <janie@example.com> # nickname\n",
+        );
+        let canonical = ("Jane Doe".to_string(), "jane@example.com".to_string());
+        // A name alone keeps the email as it was.
+        assert_eq!(
+            mailmap.lookup("Jane", "JANE@example.com"),
+            ("Jane Doe".to_string(), "JANE@example.com".to_string())
+        );
+        assert_eq!(
+            mailmap.lookup("J", "jd@old.example"),
+            ("J".to_string(), "jane@example.com".to_string())
+        );
+        assert_eq!(mailmap.lookup_ident("janie <janie@example.com>"), canonical);
+        assert_eq!(
+            mailmap.lookup("Other", "janie@example.com"),
+            ("Other".to_string(), "janie@example.com".to_string())
+        );
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Result};
+use walkdir::WalkDir;
+
+use crate::{
+    commit_graph_write, config_global_path, object_list_loose, pack_files, pack_store,
+    reflog_expire_all, repo_find, repo_open, ConfigFile, Repository,
+};
+
+const CRON_BEGIN: &str = "# BEGIN RIT MAINTENANCE SCHEDULE";
+const CRON_END: &str = "# END RIT MAINTENANCE SCHEDULE";
+const SYSTEMD_UNIT: &str = "rit-maintenance";
+const LAUNCHD_LABEL: &str = "org.rit.maintenance";
+
+/// The maintenance tasks, in the order they run when none are chosen.
+const TASKS: [&str; 4] = [
+    "loose-objects",
+    "reflog-expire",
+    "commit-graph",
+    "lock-cleanup",
+];
+/// Loose objects at most packed by one run of the loose-objects task, like
+/// `maintenance.loose-objects.batchSize`.
+const LOOSE_OBJECTS_BATCH: usize = 50000;
+/// Loose objects above which `--auto` packs them, like
+/// `maintenance.loose-objects.auto`.
+const LOOSE_OBJECTS_AUTO: usize = 100;
+/// A lock file older than this is assumed to be left behind by a command
+/// that died.
+const LOCK_EXPIRE: Duration = Duration::from_secs(60 * 60);
+
+/// Adds the current repository to the global `maintenance.repo` list and
+/// installs an hourly `rit maintenance run --auto` job with `scheduler`
+/// (`auto`, `crontab`, `systemd-timer` or `launchctl`).
+pub fn maintenance_start(scheduler: &str) -> Result<()> {
+    maintenance_register()?;
+    let scheduler = match scheduler {
+        "auto" => scheduler_detect(),
+        "crontab" | "systemd-timer" | "launchctl" => scheduler,
+        _ => bail!("unrecognized --scheduler argument '{}'", scheduler),
+    };
+    let exe = std::env::current_exe()?;
+    match scheduler {
+        "crontab" => crontab_install(&exe),
+        "systemd-timer" => systemd_install(&exe),
+        _ => launchd_install(&exe),
+    }
+}
+
+/// Removes the maintenance job from every scheduler it was installed with.
+/// Registered repositories stay registered.
+pub fn maintenance_stop() -> Result<()> {
+    crontab_uninstall()?;
+    systemd_uninstall()?;
+    launchd_uninstall()
+}
+
+pub fn maintenance_register() -> Result<()> {
+    let path = maintenance_repo_path()?;
+    let mut repos = maintenance_repos()?;
+    if !repos.contains(&path) {
+        repos.push(path);
+        maintenance_repos_write(&repos)?;
+    }
+    Ok(())
+}
+
+pub fn maintenance_unregister() -> Result<()> {
+    let path = maintenance_repo_path()?;
+    let mut repos = maintenance_repos()?;
+    repos.retain(|r| *r != path);
+    maintenance_repos_write(&repos)
+}
+
+/// Runs the maintenance `tasks` (all of them if empty) in the current
+/// repository, or in every registered one. With `auto`, only the tasks
+/// whose heuristics say they are worth it run.
+pub fn maintenance_run(tasks: &[String], auto: bool, registered: bool) -> Result<()> {
+    for (i, task) in tasks.iter().enumerate() {
+        if !TASKS.contains(&task.as_str()) {
+            bail!("'{}' is not a valid task", task);
+        }
+        if tasks[..i].contains(task) {
+            bail!("task '{}' cannot be selected multiple times", task);
+        }
+    }
+    let tasks: Vec<&str> = match tasks.is_empty() {
+        true => TASKS.to_vec(),
+        false => tasks.iter().map(String::as_str).collect(),
+    };
+    if !registered {
+        let repo = repo_find(Path::new("."), true)?.unwrap();
+        return maintenance_run_repo(&repo, &tasks, auto);
+    }
+    for path in maintenance_repos()? {
+        // A vanished repository must not stop the others from being
+        // maintained.
+        let result =
+            repo_open(Path::new(&path)).and_then(|r| maintenance_run_repo(&r, &tasks, auto));
+        if let Err(e) = result {
+            eprintln!("rit maintenance: {}: {}", path, e);
+        }
+    }
+    Ok(())
+}
+
+fn maintenance_run_repo(repo: &Repository, tasks: &[&str], auto: bool) -> Result<()> {
+    // Another maintenance run, say from the scheduler, already takes care
+    // of the repository.
+    let lock = repo.repo_path(PathBuf::from("objects/maintenance.lock"));
+    match OpenOptions::new().write(true).create_new(true).open(&lock) {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            eprintln!(
+                "lock file '{}' exists, skipping maintenance",
+                lock.display()
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    }
+    let result = maintenance_run_tasks(repo, tasks, auto);
+    fs::remove_file(lock)?;
+    result
+}
+
+fn maintenance_run_tasks(repo: &Repository, tasks: &[&str], auto: bool) -> Result<()> {
+    for &task in tasks {
+        if auto && !maintenance_task_needed(repo, task)? {
+            continue;
+        }
+        match task {
+            "loose-objects" => maintenance_loose_objects(repo)?,
+            "reflog-expire" => reflog_expire_all(repo, SystemTime::now())?,
+            "commit-graph" => {
+                commit_graph_write(repo)?;
+            }
+            _ => maintenance_lock_cleanup(repo)?,
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the loose objects that a pack already holds and packs a batch
+/// of the others, so that they stop piling up between full gcs. Unlike gc
+/// this leaves the existing packs alone and keeps unreachable objects.
+fn maintenance_loose_objects(repo: &Repository) -> Result<()> {
+    let packs = pack_files(repo)?;
+    let mut batch = Vec::new();
+    for sha in object_list_loose(repo)? {
+        if packs.iter().any(|pack| pack.contains(&sha)) {
+            fs::remove_file(maintenance_loose_path(repo, &sha))?;
+        } else if batch.len() < LOOSE_OBJECTS_BATCH {
+            batch.push(sha);
+        }
+    }
+    if batch.is_empty() {
+        return Ok(());
+    }
+    pack_store(repo, &batch)?;
+    for sha in batch {
+        fs::remove_file(maintenance_loose_path(repo, &sha))?;
+    }
+    Ok(())
+}
+
+fn maintenance_loose_path(repo: &Repository, sha: &str) -> PathBuf {
+    repo.repo_path(PathBuf::from("objects").join(&sha[..2]).join(&sha[2..]))
+}
+
+/// Removes the `*.lock` files under the repository directory, those of
+/// every worktree included, older than `LOCK_EXPIRE`, which would
+/// otherwise make every later update of the file they guard fail.
+fn maintenance_lock_cleanup(repo: &Repository) -> Result<()> {
+    let walker = WalkDir::new(&repo.commondir)
+        .into_iter()
+        .filter_entry(|e| !(e.depth() == 1 && e.file_name() == "objects"));
+    for entry in walker.filter_map(|e| e.ok()) {
+        let is_lock = entry.path().extension().is_some_and(|x| x == "lock");
+        if !is_lock || !entry.file_type().is_file() {
+            continue;
+        }
+        let age = entry.metadata()?.modified()?.elapsed().unwrap_or_default();
+        if age > LOCK_EXPIRE {
+            eprintln!("Removing stale lock file '{}'", entry.path().display());
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// The `--auto` heuristics. Loose objects are packed once there are more
+/// than `LOOSE_OBJECTS_AUTO` of them; the commit-graph is rewritten once a
+/// ref moved since it was last written. Expiring reflogs and cleaning up
+/// locks are cheap enough to always run.
+fn maintenance_task_needed(repo: &Repository, task: &str) -> Result<bool> {
+    match task {
+        "loose-objects" => Ok(object_list_loose(repo)?.len() > LOOSE_OBJECTS_AUTO),
+        "commit-graph" => {
+            let graph = repo.repo_path(PathBuf::from("objects/info/commit-graph"));
+            let written = match fs::metadata(graph) {
+                Ok(meta) => meta.modified()?,
+                Err(_) => return Ok(true),
+            };
+            let refs = repo.repo_path(PathBuf::from("refs"));
+            for entry in WalkDir::new(refs).into_iter().filter_map(|e| e.ok()) {
+                if entry.file_type().is_file() && entry.metadata()?.modified()? > written {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        _ => Ok(true),
+    }
+}
+
+fn maintenance_repo_path() -> Result<String> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    Ok(fs::canonicalize(&repo.worktree)?
+        .to_string_lossy()
+        .to_string())
+}
+
+/// The repositories registered in the global `maintenance.repo` list.
+fn maintenance_repos() -> Result<Vec<String>> {
+    let path = config_global_path().ok_or_else(|| anyhow!("HOME is not set"))?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(ConfigFile::load(&path)?.get_all("maintenance", "repo"))
+}
+
+fn maintenance_repos_write(repos: &[String]) -> Result<()> {
+    let path = config_global_path().ok_or_else(|| anyhow!("HOME is not set"))?;
+    let mut conf = if path.exists() {
+        ConfigFile::load(&path)?
+    } else {
+        ConfigFile::new()
+    };
+    conf.unset("maintenance", "repo");
+    for repo in repos {
+        conf.add("maintenance", "repo", repo);
+    }
+    conf.write(&path)
+}
+
+fn scheduler_detect() -> &'static str {
+    if cfg!(target_os = "macos") {
+        return "launchctl";
+    }
+    let systemd = Command::new("systemctl")
+        .args(["--user", "list-timers"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success());
+    if systemd {
+        "systemd-timer"
+    } else {
+        "crontab"
+    }
+}
+
+fn run_command(program: &str, args: &[&str]) -> Result<()> {
+    let status = Command::new(program).args(args).status()?;
+    if !status.success() {
+        bail!("'{} {}' failed", program, args.join(" "));
+    }
+    Ok(())
+}
+
+/// The current crontab with our block removed, or None if there is no
+/// crontab command.
+fn crontab_read() -> Option<String> {
+    let output = Command::new("crontab").arg("-l").output().ok()?;
+    // `crontab -l` fails when the user has no crontab yet.
+    let current = String::from_utf8_lossy(&output.stdout).to_string();
+    let mut kept = String::new();
+    let mut in_block = false;
+    for line in current.lines() {
+        if line == CRON_BEGIN {
+            in_block = true;
+        } else if line == CRON_END {
+            in_block = false;
+        } else if !in_block {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    Some(kept)
+}
+
+fn crontab_write(content: &str) -> Result<()> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(content.as_bytes())?;
+    if !child.wait()?.success() {
+        bail!("failed to install the crontab");
+    }
+    Ok(())
+}
+
+fn crontab_install(exe: &Path) -> Result<()> {
+    let mut content = crontab_read().ok_or_else(|| anyhow!("crontab is not available"))?;
+    content.push_str(&format!(
+        "{}\n0 * * * * \"{}\" maintenance run --auto --registered\n{}\n",
+        CRON_BEGIN,
+        exe.display(),
+        CRON_END
+    ));
+    crontab_write(&content)
+}
+
+fn crontab_uninstall() -> Result<()> {
+    let output = match Command::new("crontab").arg("-l").output() {
+        Ok(o) => o,
+        Err(_) => return Ok(()),
+    };
+    if !String::from_utf8_lossy(&output.stdout).contains(CRON_BEGIN) {
+        return Ok(());
+    }
+    crontab_write(&crontab_read().unwrap_or_default())
+}
+
+fn systemd_unit_dir() -> Result<PathBuf> {
+    let config = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(std::env::var("HOME")?).join(".config"),
+    };
+    Ok(config.join("systemd/user"))
+}
+
+fn systemd_install(exe: &Path) -> Result<()> {
+    let dir = systemd_unit_dir()?;
+    fs::create_dir_all(&dir)?;
+    fs::write(
+        dir.join(format!("{}.service", SYSTEMD_UNIT)),
+        format!(
+            "[Unit]\n\
+             Description=Optimize rit repositories data\n\n\
+             [Service]\n\
+             Type=oneshot\n\
+             ExecStart=\"{}\" maintenance run --auto --registered\n",
+            exe.display()
+        ),
+    )?;
+    fs::write(
+        dir.join(format!("{}.timer", SYSTEMD_UNIT)),
+        "[Unit]\n\
+         Description=Optimize rit repositories data\n\n\
+         [Timer]\n\
+         OnCalendar=hourly\n\
+         Persistent=true\n\n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+    )?;
+    run_command("systemctl", &["--user", "daemon-reload"])?;
+    run_command(
+        "systemctl",
+        &[
+            "--user",
+            "enable",
+            "--now",
+            &format!("{}.timer", SYSTEMD_UNIT),
+        ],
+    )
+}
+
+fn systemd_uninstall() -> Result<()> {
+    let dir = systemd_unit_dir()?;
+    let timer = dir.join(format!("{}.timer", SYSTEMD_UNIT));
+    if !timer.exists() {
+        return Ok(());
+    }
+    run_command(
+        "systemctl",
+        &[
+            "--user",
+            "disable",
+            "--now",
+            &format!("{}.timer", SYSTEMD_UNIT),
+        ],
+    )?;
+    fs::remove_file(timer)?;
+    fs::remove_file(dir.join(format!("{}.service", SYSTEMD_UNIT)))?;
+    Ok(())
+}
+
+fn launchd_plist_path() -> Result<PathBuf> {
+    Ok(PathBuf::from(std::env::var("HOME")?)
+        .join("Library/LaunchAgents")
+        .join(format!("{}.plist", LAUNCHD_LABEL)))
+}
+
+fn launchd_install(exe: &Path) -> Result<()> {
+    let plist = launchd_plist_path()?;
+    fs::create_dir_all(plist.parent().unwrap())?;
+    fs::write(
+        &plist,
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+             \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n<dict>\n\
+             <key>Label</key><string>{}</string>\n\
+             <key>ProgramArguments</key>\n<array>\n\
+             <string>{}</string>\n\
+             <string>maintenance</string>\n\
+             <string>run</string>\n\
+             <string>--auto</string>\n\
+             <string>--registered</string>\n\
+             </array>\n\
+             <key>StartInterval</key><integer>3600</integer>\n\
+             </dict>\n</plist>\n",
+            LAUNCHD_LABEL,
+            exe.display()
+        ),
+    )?;
+    run_command("launchctl", &["load", "-w", &plist.to_string_lossy()])
+}
+
+fn launchd_uninstall() -> Result<()> {
+    let plist = launchd_plist_path()?;
+    if !plist.exists() {
+        return Ok(());
+    }
+    run_command("launchctl", &["unload", "-w", &plist.to_string_lossy()])?;
+    fs::remove_file(plist)?;
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use anyhow::Result;
+
+use crate::prompt_line;
+
+/// Applies a selection to `selected`, the marks of `items`. The input
+/// holds words separated by spaces or commas: a number, a range `3-5` or
+/// `3-`, `*` for everything, or the unique prefix of an item; a leading
+/// `-` unselects instead. Returns the word that made no sense, if any.
+pub fn menu_select(input: &str, items: &[&str], selected: &mut [bool]) -> Result<(), String> {
+    for word in input.split([' ', ',']).filter(|w| !w.is_empty()) {
+        let (mark, choice) = match word.strip_prefix('-') {
+            Some(rest) if !rest.is_empty() => (false, rest),
+            _ => (true, word),
+        };
+        let bad = || word.to_string();
+        let range = if choice == "*" {
+            1..=items.len()
+        } else if let Some((lo, hi)) = choice.split_once('-') {
+            let lo: usize = lo.parse().map_err(|_| bad())?;
+            let hi: usize = match hi {
+                "" => items.len(),
+                hi => hi.parse().map_err(|_| bad())?,
+            };
+            lo..=hi
+        } else if let Ok(n) = choice.parse::<usize>() {
+            n..=n
+        } else {
+            let matching: Vec<usize> = items
+                .iter()
+                .enumerate()
+                .filter(|(_, item)| item.starts_with(choice))
+                .map(|(i, _)| i + 1)
+                .collect();
+            match matching[..] {
+                [n] => n..=n,
+                _ => return Err(bad()),
+            }
+        };
+        if *range.start() == 0 || *range.end() > items.len() {
+            return Err(bad());
+        }
+        for n in range {
+            selected[n - 1] = mark;
+        }
+    }
+    Ok(())
+}
+
+/// Lets the user pick any number of `items`, shown one per line below
+/// `header` with the chosen ones starred, until an empty line. Returns
+/// the chosen indices.
+pub fn menu_choose(header: &str, items: &[String], prompt: &str) -> Result<Vec<usize>> {
+    let names: Vec<&str> = items.iter().map(String::as_str).collect();
+    let mut selected = vec![false; items.len()];
+    loop {
+        let star = |on: bool| if on { '*' } else { ' ' };
+        if !header.is_empty() {
+            println!("{}{}", star(false), header);
+        }
+        for (i, item) in items.iter().enumerate() {
+            println!("{}{:>2}: {}", star(selected[i]), i + 1, item);
+        }
+        let Some(input) = prompt_line(&format!("{}>> ", prompt))? else {
+            return Ok(Vec::new());
+        };
+        if input.is_empty() {
+            break;
+        }
+        if input == "?" {
+            menu_help_select();
+            continue;
+        }
+        if let Err(word) = menu_select(&input, &names, &mut selected) {
+            println!("Huh ({})?", word);
+        }
+    }
+    Ok((0..items.len()).filter(|&i| selected[i]).collect())
+}
+
+/// Shows `commands` in columns and asks for one, by number or prefix.
+/// Returns its index, or None once the input ends.
+pub fn menu_command(commands: &[&str], columns: usize, prompt: &str) -> Result<Option<usize>> {
+    loop {
+        println!("*** Commands ***");
+        for (r, names) in commands.chunks(columns).enumerate() {
+            let line: Vec<String> = names
+                .iter()
+                .enumerate()
+                .map(|(c, name)| format!("{:>3}: {:<18}", r * columns + c + 1, name))
+                .collect();
+            println!("{}", line.concat().trim_end());
+        }
+        let Some(input) = prompt_line(&format!("{}> ", prompt))? else {
+            return Ok(None);
+        };
+        let mut selected = vec![false; commands.len()];
+        match menu_select(&input, commands, &mut selected) {
+            Ok(()) if selected.iter().filter(|&&s| s).count() == 1 => {
+                return Ok(selected.iter().position(|&s| s));
+            }
+            _ if input.is_empty() => {}
+            _ => println!("Huh ({})?", input),
+        }
+    }
+}
+
+fn menu_help_select() {
+    println!(
+        "Prompt help:\n\
+         1          - select a numbered item\n\
+         foo        - select item based on unique prefix\n\
+         3-5        - select a range of items\n\
+         2-3,6-9    - select multiple ranges\n\
+         -...       - unselect specified items\n\
+         *          - choose all items\n\
+         (empty)    - finish selecting"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::menu_select;
+
+    #[test]
+    fn test_menu_select() {
+        let items = ["status", "update", "revert", "untracked", "unused"];
+        let mut selected = vec![false; items.len()];
+        menu_select("1,3-4", &items, &mut selected).unwrap();
+        assert_eq!(selected, [true, false, true, true, false]);
+        menu_select("-3 upd", &items, &mut selected).unwrap();
+        assert_eq!(selected, [true, true, false, true, false]);
+        menu_select("-*", &items, &mut selected).unwrap();
+        assert_eq!(selected, [false; 5]);
+        menu_select("4-", &items, &mut selected).unwrap();
+        assert_eq!(selected, [false, false, false, true, true]);
+        assert_eq!(menu_select("un", &items, &mut selected), Err("un".into()));
+        assert_eq!(menu_select("6", &items, &mut selected), Err("6".into()));
+    }
+}
@@ -0,0 +1,474 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    blob_read, checkout_tree, commit_create, commit_subject, file_write_locked, gc_auto,
+    head_update, index_read, index_write, myers_diff, object_find, object_write, ref_resolve,
+    repo_file, repo_find, rerere_apply, sha_short, split_lines, tree_from_leaves, tree_to_leaves,
+    Ancestry, Blob, DiffOp, IndexEntry, Repository, TreeLeaf,
+};
+
+/// The result of a three-way content merge.
+pub struct FileMerge {
+    pub content: Vec<u8>,
+    /// Number of conflicting regions written with markers.
+    pub conflicts: usize,
+}
+
+/// A region of the base that both sides kept unchanged, as
+/// (base_start, base_end, ours_start, ours_end, theirs_start, theirs_end).
+type SyncRegion = (usize, usize, usize, usize, usize, usize);
+
+/// Runs of lines that `a` and `b` have in common, as
+/// (a_start, b_start, len).
+fn matching_blocks<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize, usize)> {
+    let mut blocks: Vec<(usize, usize, usize)> = Vec::new();
+    for op in myers_diff(a, b) {
+        if let DiffOp::Equal(i, j) = op {
+            match blocks.last_mut() {
+                Some((ai, bj, len)) if *ai + *len == i && *bj + *len == j => *len += 1,
+                _ => blocks.push((i, j, 1)),
+            }
+        }
+    }
+    blocks
+}
+
+/// Finds the regions of `base` that neither side changed, ending with an
+/// empty region at the end of all three files.
+fn sync_regions<T: PartialEq>(base: &[T], ours: &[T], theirs: &[T]) -> Vec<SyncRegion> {
+    let ours_matches = matching_blocks(base, ours);
+    let theirs_matches = matching_blocks(base, theirs);
+    let mut regions = Vec::new();
+    let (mut ia, mut ib) = (0, 0);
+    while ia < ours_matches.len() && ib < theirs_matches.len() {
+        let (abase, amatch, alen) = ours_matches[ia];
+        let (bbase, bmatch, blen) = theirs_matches[ib];
+        let start = abase.max(bbase);
+        let end = (abase + alen).min(bbase + blen);
+        if start < end {
+            let asub = amatch + (start - abase);
+            let bsub = bmatch + (start - bbase);
+            let len = end - start;
+            regions.push((start, end, asub, asub + len, bsub, bsub + len));
+        }
+        if abase + alen < bbase + blen {
+            ia += 1;
+        } else {
+            ib += 1;
+        }
+    }
+    regions.push((
+        base.len(),
+        base.len(),
+        ours.len(),
+        ours.len(),
+        theirs.len(),
+        theirs.len(),
+    ));
+    regions
+}
+
+/// Merges the changes from `base` to `ours` and from `base` to `theirs`.
+/// Where both sides changed the same lines differently, the region is
+/// written between conflict markers labelled with `ours_label` and
+/// `theirs_label`.
+pub fn merge_file(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    ours_label: &str,
+    theirs_label: &str,
+) -> FileMerge {
+    let base = split_lines(base);
+    let ours = split_lines(ours);
+    let theirs = split_lines(theirs);
+
+    let mut out = Vec::new();
+    let mut conflicts = 0;
+    let push_lines = |out: &mut Vec<u8>, lines: &[&[u8]]| {
+        for line in lines {
+            out.extend_from_slice(line);
+        }
+    };
+
+    let (mut iz, mut ia, mut ib) = (0, 0, 0);
+    for (zstart, zend, astart, aend, bstart, bend) in sync_regions(&base, &ours, &theirs) {
+        let base_part = &base[iz..zstart];
+        let ours_part = &ours[ia..astart];
+        let theirs_part = &theirs[ib..bstart];
+        if !ours_part.is_empty() || !theirs_part.is_empty() {
+            if ours_part == theirs_part || theirs_part == base_part {
+                push_lines(&mut out, ours_part);
+            } else if ours_part == base_part {
+                push_lines(&mut out, theirs_part);
+            } else {
+                // Lines both sides added alike stay outside the markers.
+                let prefix = ours_part
+                    .iter()
+                    .zip(theirs_part)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let suffix = ours_part[prefix..]
+                    .iter()
+                    .rev()
+                    .zip(theirs_part[prefix..].iter().rev())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                let ours_mid = &ours_part[prefix..ours_part.len() - suffix];
+                let theirs_mid = &theirs_part[prefix..theirs_part.len() - suffix];
+                let end_line = |out: &mut Vec<u8>| {
+                    if !out.ends_with(b"\n") {
+                        out.push(b'\n');
+                    }
+                };
+                push_lines(&mut out, &ours_part[..prefix]);
+                out.extend_from_slice(format!("<<<<<<< {}\n", ours_label).as_bytes());
+                push_lines(&mut out, ours_mid);
+                end_line(&mut out);
+                out.extend_from_slice(b"=======\n");
+                push_lines(&mut out, theirs_mid);
+                end_line(&mut out);
+                out.extend_from_slice(format!(">>>>>>> {}\n", theirs_label).as_bytes());
+                push_lines(&mut out, &ours_part[ours_part.len() - suffix..]);
+                conflicts += 1;
+            }
+        }
+        push_lines(&mut out, &base[zstart..zend]);
+        iz = zend;
+        ia = aend;
+        ib = bend;
+    }
+    FileMerge {
+        content: out,
+        conflicts,
+    }
+}
+
+/// A path the tree merge could not resolve, with its version in each of
+/// the base, ours and theirs.
+pub struct MergeConflict {
+    pub path: String,
+    pub base: Option<TreeLeaf>,
+    pub ours: Option<TreeLeaf>,
+    pub theirs: Option<TreeLeaf>,
+}
+
+/// The result of a three-way tree merge. `leaves` is the tree to check out:
+/// conflicted files hold their conflict markers, or the surviving side of a
+/// modify/delete conflict.
+pub struct TreeMerge {
+    pub leaves: Vec<TreeLeaf>,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// The content of the blob `leaf` points to, empty for no leaf.
+pub fn blob_content(repo: &Repository, leaf: Option<&TreeLeaf>) -> Result<Vec<u8>> {
+    let Some(leaf) = leaf else {
+        return Ok(Vec::new());
+    };
+    blob_read(repo, &leaf.sha)
+}
+
+/// Merges the trees `ours` and `theirs` against their common ancestor
+/// `base` (None for an empty base), path by path.
+pub fn merge_trees(
+    repo: &Repository,
+    base: Option<&str>,
+    ours: &str,
+    theirs: &str,
+    ours_label: &str,
+    theirs_label: &str,
+) -> Result<TreeMerge> {
+    let leaves = |tree: Option<&str>| -> Result<HashMap<String, TreeLeaf>> {
+        Ok(match tree {
+            Some(t) => tree_to_leaves(repo, t, "")?
+                .into_iter()
+                .map(|l| (l.path.clone(), l))
+                .collect(),
+            None => HashMap::new(),
+        })
+    };
+    let base = leaves(base)?;
+    let ours = leaves(Some(ours))?;
+    let theirs = leaves(Some(theirs))?;
+
+    let same = |a: Option<&TreeLeaf>, b: Option<&TreeLeaf>| match (a, b) {
+        (Some(a), Some(b)) => a.sha == b.sha && a.mode == b.mode,
+        (None, None) => true,
+        _ => false,
+    };
+    let is_file = |l: &TreeLeaf| l.mode.starts_with(b"10");
+
+    let paths: BTreeSet<&String> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    let mut result = TreeMerge {
+        leaves: Vec::new(),
+        conflicts: Vec::new(),
+    };
+    for path in paths {
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+        let resolved = if same(o, t) || same(b, t) {
+            o.cloned()
+        } else if same(b, o) {
+            t.cloned()
+        } else {
+            match (o, t) {
+                (Some(o), Some(t)) if is_file(o) && is_file(t) => {
+                    // Take a mode change from whichever side made it.
+                    let base_mode = b.map(|b| b.mode.clone());
+                    let mode_clash = o.mode != t.mode
+                        && base_mode
+                            .as_ref()
+                            .is_none_or(|m| *m != o.mode && *m != t.mode);
+                    let mode = if Some(&o.mode) == base_mode.as_ref() {
+                        t.mode.clone()
+                    } else {
+                        o.mode.clone()
+                    };
+                    let merged = merge_file(
+                        &blob_content(repo, b.filter(|b| is_file(b)))?,
+                        &blob_content(repo, Some(o))?,
+                        &blob_content(repo, Some(t))?,
+                        ours_label,
+                        theirs_label,
+                    );
+                    let sha = object_write(&Blob::new(&merged.content), Some(repo))?;
+                    let leaf = TreeLeaf {
+                        mode,
+                        path: path.clone(),
+                        sha,
+                    };
+                    if merged.conflicts == 0 && !mode_clash {
+                        Some(leaf)
+                    } else {
+                        result.leaves.push(leaf);
+                        result.conflicts.push(MergeConflict {
+                            path: path.clone(),
+                            base: b.cloned(),
+                            ours: Some(o.clone()),
+                            theirs: Some(t.clone()),
+                        });
+                        continue;
+                    }
+                }
+                _ => {
+                    // Modify/delete, or changes to symlinks and submodules:
+                    // keep our side (or the surviving one) in the worktree.
+                    if let Some(keep) = o.or(t) {
+                        result.leaves.push(keep.clone());
+                    }
+                    result.conflicts.push(MergeConflict {
+                        path: path.clone(),
+                        base: b.cloned(),
+                        ours: o.cloned(),
+                        theirs: t.cloned(),
+                    });
+                    continue;
+                }
+            }
+        };
+        if let Some(leaf) = resolved {
+            result.leaves.push(leaf);
+        }
+    }
+
+    // A file in one tree where the other has a directory cannot be
+    // represented in a single tree.
+    let names: BTreeSet<&str> = result.leaves.iter().map(|l| l.path.as_str()).collect();
+    for leaf in &result.leaves {
+        let mut dir = leaf.path.as_str();
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            if names.contains(parent) {
+                bail!("Directory/file conflict at {} is not supported", parent);
+            }
+            dir = parent;
+        }
+    }
+    Ok(result)
+}
+
+/// Checks out a tree merge: the merged tree goes into the index and
+/// worktree, then each conflicted path is replaced in the index by its
+/// stage 1 (base), 2 (ours) and 3 (theirs) entries, and conflicts resolved
+/// before are resolved again in the worktree. Returns the sha of the
+/// merged tree.
+pub fn merge_checkout(repo: &Repository, merged: &TreeMerge) -> Result<String> {
+    let tree = tree_from_leaves(repo, &merged.leaves)?;
+    checkout_tree(repo, &tree, false)?;
+    if merged.conflicts.is_empty() {
+        return Ok(tree);
+    }
+
+    let mut index = index_read(repo)?;
+    let conflicted: BTreeSet<&str> = merged.conflicts.iter().map(|c| c.path.as_str()).collect();
+    index
+        .entries
+        .retain(|e| !conflicted.contains(e.name.as_str()));
+    for conflict in &merged.conflicts {
+        for (stage, leaf) in [
+            (1, &conflict.base),
+            (2, &conflict.ours),
+            (3, &conflict.theirs),
+        ] {
+            if let Some(leaf) = leaf {
+                index.entries.push(IndexEntry::from_leaf(leaf, stage)?);
+            }
+        }
+    }
+    index
+        .entries
+        .sort_by(|a, b| a.name.cmp(&b.name).then(a.flag_stage.cmp(&b.flag_stage)));
+    index_write(repo, &index)?;
+    rerere_apply(repo, &conflicted.into_iter().collect::<Vec<_>>())?;
+    Ok(tree)
+}
+
+/// Reports each conflict the way git does, e.g.
+/// "CONFLICT (content): Merge conflict in file".
+pub fn merge_conflicts_print(conflicts: &[MergeConflict]) {
+    for conflict in conflicts {
+        let kind = match (&conflict.ours, &conflict.theirs) {
+            (Some(_), None) | (None, Some(_)) => "modify/delete",
+            _ => "content",
+        };
+        println!("CONFLICT ({}): Merge conflict in {}", kind, conflict.path);
+    }
+}
+
+pub fn merge(name: &str) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let theirs = object_find(&repo, name, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("{} - not something we can merge", name))?;
+    let what = if ref_resolve(&repo, &format!("refs/heads/{}", name))?.is_some() {
+        "branch"
+    } else {
+        "commit"
+    };
+    let message = format!("Merge {} '{}'\n", what, name);
+    merge_into_head(&repo, &theirs, name, &message, &format!("merge {}", name))
+}
+
+/// Merges the commit `theirs` into HEAD, fast-forwarding when possible.
+/// `label` marks their side of conflicts, `message` is the merge commit's
+/// and `reflog` prefixes the reflog entry, e.g. "merge topic".
+pub fn merge_into_head(
+    repo: &Repository,
+    theirs: &str,
+    label: &str,
+    message: &str,
+    reflog: &str,
+) -> Result<()> {
+    if repo_file(repo, PathBuf::from("MERGE_HEAD"), false)?.exists() {
+        bail!("You have not concluded your merge (MERGE_HEAD exists).");
+    }
+
+    let head = ref_resolve(repo, "HEAD")?.ok_or_else(|| anyhow!("No commits yet on HEAD"))?;
+    let theirs = theirs.to_string();
+
+    let mut ancestry = Ancestry::new(repo)?;
+    if ancestry.is_ancestor(&theirs, &head)? {
+        println!("Already up to date.");
+        return Ok(());
+    }
+
+    file_write_locked(
+        &repo_file(repo, PathBuf::from("ORIG_HEAD"), false)?,
+        format!("{}\n", head).as_bytes(),
+        repo.fsync.refs,
+    )?;
+    if ancestry.is_ancestor(&head, &theirs)? {
+        println!(
+            "Updating {}..{}",
+            sha_short(repo, &head),
+            sha_short(repo, &theirs)
+        );
+        println!("Fast-forward");
+        checkout_tree(repo, &theirs, false)?;
+        return head_update(repo, &theirs, &format!("{}: Fast-forward", reflog));
+    }
+
+    // With several merge bases, merge against the first one.
+    let base = ancestry
+        .merge_bases(&head, &theirs)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("refusing to merge unrelated histories"))?;
+
+    let tree_of = |sha: &str| -> Result<String> {
+        object_find(repo, sha, Some(b"tree"), true)?.ok_or_else(|| anyhow!("{} has no tree", sha))
+    };
+    let merged = merge_trees(
+        repo,
+        Some(&tree_of(&base)?),
+        &tree_of(&head)?,
+        &tree_of(&theirs)?,
+        "HEAD",
+        label,
+    )?;
+    let tree = merge_checkout(repo, &merged)?;
+
+    if !merged.conflicts.is_empty() {
+        merge_conflicts_print(&merged.conflicts);
+        file_write_locked(
+            &repo_file(repo, PathBuf::from("MERGE_HEAD"), false)?,
+            format!("{}\n", theirs).as_bytes(),
+            repo.fsync.refs,
+        )?;
+        file_write_locked(
+            &repo_file(repo, PathBuf::from("MERGE_MSG"), false)?,
+            message.as_bytes(),
+            false,
+        )?;
+        bail!("Automatic merge failed; fix conflicts and then commit the result.");
+    }
+
+    let commit = commit_create(repo, &tree, &[head, theirs], None, message)?;
+    head_update(
+        repo,
+        &commit,
+        &format!("{}: Merge made by the 'three-way' strategy.", reflog),
+    )?;
+    println!(
+        "Merge made by the 'three-way' strategy: {} {}",
+        sha_short(repo, &commit),
+        commit_subject(repo, &commit)?
+    );
+    gc_auto(repo);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::merge_file;
+
+    #[test]
+    fn test_merge_file_clean() {
+        let base = b"a\nb\nc\nd\ne\n";
+        let ours = b"A\nb\nc\nd\ne\n";
+        let theirs = b"a\nb\nc\nd\nE\nf\n";
+        let merged = merge_file(base, ours, theirs, "ours", "theirs");
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(merged.content, b"A\nb\nc\nd\nE\nf\n");
+    }
+
+    #[test]
+    fn test_merge_file_conflict() {
+        let base = b"a\nb\nc\n";
+        let ours = b"a\nx\ny\nc\n";
+        let theirs = b"a\nx\nz\nc\n";
+        let merged = merge_file(base, ours, theirs, "HEAD", "feature");
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            String::from_utf8(merged.content).unwrap(),
+            "a\nx\n<<<<<<< HEAD\ny\n=======\nz\n>>>>>>> feature\nc\n"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{object_find, repo_find, Ancestry, Repository};
+
+/// Prints the best common ancestor of the first commit and the others (as
+/// if they were merged together first), or every one with `all`.
+pub fn merge_base(commits: &[String], all: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let shas = commits
+        .iter()
+        .map(|c| merge_base_commit(&repo, c))
+        .collect::<Result<Vec<_>>>()?;
+    let mut ancestry = Ancestry::new(&repo)?;
+
+    let Some((first, others)) = shas.split_first().filter(|(_, o)| !o.is_empty()) else {
+        bail!("merge-base takes at least two commits");
+    };
+    let bases = ancestry.merge_bases_many(first, others)?;
+    if bases.is_empty() {
+        bail!("no merge base found");
+    }
+    let shown = if all { bases.len() } else { 1 };
+    for base in &bases[..shown] {
+        println!("{}", base);
+    }
+    Ok(())
+}
+
+/// Whether `ancestor` is an ancestor of `descendant` (or the same commit),
+/// for `--is-ancestor`, which answers with its exit status alone.
+pub fn merge_base_is_ancestor(ancestor: &str, descendant: &str) -> Result<bool> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let ancestor = merge_base_commit(&repo, ancestor)?;
+    let descendant = merge_base_commit(&repo, descendant)?;
+    Ancestry::new(&repo)?.is_ancestor(&ancestor, &descendant)
+}
+
+fn merge_base_commit(repo: &Repository, name: &str) -> Result<String> {
+    object_find(repo, name, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("Not a valid commit name {}", name))
+}
@@ -0,0 +1,153 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Result;
+
+use crate::{object_find, ref_list_flat, repo_find, Ancestry, Repository};
+
+/// What following a merge's other parents costs against walking first
+/// parents, as in git: a name through one is only taken when nothing
+/// else reaches the commit.
+const NAME_REV_MERGE_WEIGHT: usize = 65535;
+
+/// The name found for a commit so far: the name of a ref tip with the
+/// generations back from it.
+struct RevName {
+    tip: String,
+    generation: usize,
+    distance: usize,
+    from_tag: bool,
+}
+
+impl RevName {
+    fn better_than(&self, other: &RevName) -> bool {
+        match (self.from_tag, other.from_tag) {
+            (true, false) => true,
+            (false, true) => false,
+            _ => self.distance < other.distance,
+        }
+    }
+
+    fn format(&self) -> String {
+        match self.generation {
+            0 => self.tip.clone(),
+            n => format!("{}~{}", self.tip.trim_end_matches("^0"), n),
+        }
+    }
+}
+
+/// Prints each of `revs` with a name for it relative to a ref, such as
+/// `master~2` or `tags/v1.0^0`, or "undefined" if no ref reaches it.
+pub fn name_rev(revs: &[String], tags_only: bool, name_only: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let names = name_rev_names(&repo, tags_only)?;
+    for rev in revs {
+        let Ok(Some(sha)) = object_find(&repo, rev, Some(b"commit"), true) else {
+            eprintln!("Could not get sha1 for {}. Skipping.", rev);
+            continue;
+        };
+        let name = names
+            .get(&sha)
+            .map_or("undefined".to_string(), RevName::format);
+        match name_only {
+            true => println!("{}", name),
+            false => println!("{} {}", rev, name),
+        }
+    }
+    Ok(())
+}
+
+/// Names every commit reachable from a ref, or only from a tag with
+/// `tags_only`. Names from tags win over others, then those with fewer
+/// steps back, merges counting for many.
+fn name_rev_names(repo: &Repository, tags_only: bool) -> Result<HashMap<String, RevName>> {
+    let mut names: HashMap<String, RevName> = HashMap::new();
+    let mut ancestry = Ancestry::new(repo)?;
+    for (refname, sha) in ref_list_flat(repo, None, None)? {
+        let from_tag = refname.starts_with("tags/");
+        if tags_only && !from_tag {
+            continue;
+        }
+        let Ok(Some(commit)) = object_find(repo, &sha, Some(b"commit"), true) else {
+            continue;
+        };
+        let mut tip = refname
+            .strip_prefix("heads/")
+            .unwrap_or(&refname)
+            .to_string();
+        // An annotated tag names the tag object; "^0" names its commit.
+        if commit != sha {
+            tip.push_str("^0");
+        }
+
+        let mut stack = vec![(
+            commit,
+            RevName {
+                tip,
+                generation: 0,
+                distance: 0,
+                from_tag,
+            },
+        )];
+        while let Some((sha, name)) = stack.pop() {
+            if names.get(&sha).is_some_and(|old| !name.better_than(old)) {
+                continue;
+            }
+            let parents = ancestry.parents(&sha)?;
+            // Pushed last, the first parent is walked first.
+            for (i, parent) in parents.iter().enumerate().rev() {
+                let parent_name = match i {
+                    0 => RevName {
+                        tip: name.tip.clone(),
+                        generation: name.generation + 1,
+                        distance: name.distance + 1,
+                        from_tag,
+                    },
+                    _ => RevName {
+                        tip: format!("{}^{}", name.format(), i + 1),
+                        generation: 0,
+                        distance: name.distance + NAME_REV_MERGE_WEIGHT,
+                        from_tag,
+                    },
+                };
+                stack.push((parent.clone(), parent_name));
+            }
+            names.insert(sha, name);
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::name_rev_names;
+    use crate::{
+        ref_create,
+        test_utils::{make_commit_at, test_repo},
+    };
+
+    #[test]
+    fn test_name_rev_names() {
+        let repo = test_repo("name-rev");
+        let root = make_commit_at(&repo, "root", &[], 1000);
+        let side = make_commit_at(&repo, "side", &[&root], 2000);
+        let main = make_commit_at(&repo, "main", &[&root], 2000);
+        let merge = make_commit_at(&repo, "merge", &[&main, &side], 3000);
+        let tip = make_commit_at(&repo, "tip", &[&merge], 4000);
+        ref_create(&repo, "heads/master", &tip).unwrap();
+        ref_create(&repo, "tags/v1", &main).unwrap();
+
+        let names = name_rev_names(&repo, false).unwrap();
+        let name = |sha: &str| names[sha].format();
+        assert_eq!(name(&tip), "master");
+        assert_eq!(name(&merge), "master~1");
+        assert_eq!(name(&side), "master~1^2");
+        assert_eq!(name(&main), "tags/v1");
+        assert_eq!(name(&root), "tags/v1~1");
+
+        let names = name_rev_names(&repo, true).unwrap();
+        assert!(!names.contains_key(&tip));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::BTreeMap, io::Write, path::Path, path::PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    blob_read, commit_create, object_find, object_write, ref_create, ref_resolve, repo_dir,
+    repo_find, stripspace_text, tree_from_leaves, tree_to_leaves, Blob, Repository, TreeLeaf,
+};
+
+/// The ref whose commits hold the notes tree, as in git.
+const NOTES_REF: &str = "refs/notes/commits";
+
+/// Attaches a note made of the `-m` paragraphs to `object`. An existing
+/// note is only replaced with `force`.
+pub fn notes_add(messages: &[String], force: bool, object: &str) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let sha = notes_resolve(&repo, object)?;
+    if messages.is_empty() {
+        bail!("Please supply the note contents using -m.");
+    }
+    let mut notes = notes_read(&repo)?;
+    if notes.contains_key(&sha) {
+        if !force {
+            bail!(
+                "Cannot add notes. Found existing notes for object {}. Use '-f' to overwrite existing notes",
+                sha
+            );
+        }
+        println!("Overwriting existing notes for object {}", sha);
+    }
+    let content = messages
+        .iter()
+        .map(|m| stripspace_text(m, None))
+        .filter(|m| !m.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let blob = object_write(&Blob::new(content.as_bytes()), Some(&repo))?;
+    notes.insert(sha, blob);
+    notes_write(&repo, &notes, "Notes added by 'rit notes add'")
+}
+
+pub fn notes_show(object: &str) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let sha = notes_resolve(&repo, object)?;
+    let content =
+        note_get(&repo, &sha)?.ok_or_else(|| anyhow!("no note found for object {}.", sha))?;
+    std::io::stdout().write_all(&content)?;
+    Ok(())
+}
+
+/// Lists every note as "<note blob> <annotated object>", or only the blob of
+/// the note of `object`.
+pub fn notes_list(object: Option<&str>) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let notes = notes_read(&repo)?;
+    match object {
+        Some(object) => {
+            let sha = notes_resolve(&repo, object)?;
+            let blob = notes
+                .get(&sha)
+                .ok_or_else(|| anyhow!("no note found for object {}.", sha))?;
+            println!("{}", blob);
+        }
+        None => {
+            for (sha, blob) in &notes {
+                println!("{} {}", blob, sha);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn notes_remove(object: &str) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let sha = notes_resolve(&repo, object)?;
+    let mut notes = notes_read(&repo)?;
+    if notes.remove(&sha).is_none() {
+        bail!("Object {} has no note", sha);
+    }
+    println!("Removing note for object {}", sha);
+    notes_write(&repo, &notes, "Notes removed by 'rit notes remove'")
+}
+
+fn notes_resolve(repo: &Repository, object: &str) -> Result<String> {
+    object_find(repo, object, None, true)?
+        .ok_or_else(|| anyhow!("failed to resolve '{}' as a valid ref.", object))
+}
+
+/// The notes, as annotated object -> note blob. Paths in the notes tree
+/// are the annotated sha, possibly split into fan-out directories
+/// ("ab/cdef...") as git does for large notes trees.
+pub fn notes_read(repo: &Repository) -> Result<BTreeMap<String, String>> {
+    let Some(commit) = ref_resolve(repo, NOTES_REF)? else {
+        return Ok(BTreeMap::new());
+    };
+    Ok(tree_to_leaves(repo, &commit, "")?
+        .into_iter()
+        .map(|leaf| (leaf.path.replace('/', ""), leaf.sha))
+        .filter(|(sha, _)| sha.len() == 40)
+        .collect())
+}
+
+/// The content of the note attached to `sha`, if any.
+pub fn note_get(repo: &Repository, sha: &str) -> Result<Option<Vec<u8>>> {
+    match notes_read(repo)?.get(sha) {
+        Some(blob) => Ok(Some(blob_read(repo, blob)?)),
+        None => Ok(None),
+    }
+}
+
+/// Commits `notes` as the new state of the notes ref, on top of the last.
+fn notes_write(repo: &Repository, notes: &BTreeMap<String, String>, message: &str) -> Result<()> {
+    let leaves: Vec<TreeLeaf> = notes
+        .iter()
+        .map(|(sha, blob)| TreeLeaf {
+            mode: b"100644".to_vec(),
+            path: sha.clone(),
+            sha: blob.clone(),
+        })
+        .collect();
+    let tree = tree_from_leaves(repo, &leaves)?;
+    let parents: Vec<String> = ref_resolve(repo, NOTES_REF)?.into_iter().collect();
+    let commit = commit_create(repo, &tree, &parents, None, message)?;
+    repo_dir(repo, PathBuf::from("refs/notes"), true)?;
+    ref_create(repo, "notes/commits", &commit)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        note_get, notes_read, object_write,
+        test_utils::{make_commit, test_repo},
+        Blob,
+    };
+
+    #[test]
+    fn test_notes_roundtrip() {
+        let repo = test_repo("notes");
+        let commit = make_commit(&repo, "annotated", &[]);
+        assert!(notes_read(&repo).unwrap().is_empty());
+
+        let blob = object_write(&Blob::new(b"a note\n"), Some(&repo)).unwrap();
+        let notes = [(commit.clone(), blob)].into_iter().collect();
+        super::notes_write(&repo, &notes, "add").unwrap();
+        assert_eq!(note_get(&repo, &commit).unwrap().unwrap(), b"a note\n");
+
+        super::notes_write(&repo, &Default::default(), "remove").unwrap();
+        assert!(note_get(&repo, &commit).unwrap().is_none());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
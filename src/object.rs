@@ -1,8 +1,10 @@
 use std::{
     any::Any,
+    collections::HashSet,
     fs::{self, File},
-    io::{Read, Write},
+    io::{self, BufRead, Read, Write},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use anyhow::{anyhow, bail, Result};
@@ -10,7 +12,10 @@ use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 use regex::Regex;
 use sha1::{Digest, Sha1};
 
-use crate::{ref_resolve, repo_dir, repo_file, repo_find, Blob, Commit, Repository, Tag};
+use crate::{
+    pack_files, pack_object_info, pack_object_list, pack_object_read, profile_phase, ref_resolve,
+    refname_valid, repo_dir, repo_file, repo_find, Blob, Commit, Repository, Tag, Tree,
+};
 
 pub trait Object {
     /// Returns the object type as bytes (e.g. b"blob").
@@ -25,16 +30,21 @@ pub fn object_find(
     fmt: Option<&[u8]>,
     follow: bool,
 ) -> Result<Option<String>> {
-    let mut shas = object_resolve(repo, name)?;
+    let mut sha = match revision_split(name) {
+        Some((base, ops)) => revision_walk(repo, base, ops)?,
+        None => {
+            let mut shas = object_resolve(repo, name)?;
 
-    if shas.is_empty() {
-        bail!("No such reference {}", name);
-    }
-    if shas.len() > 1 {
-        bail!("Ambiguous reference {}: Candidates are: {:?}", name, shas);
-    }
+            if shas.is_empty() {
+                bail!("No such reference {}", name);
+            }
+            if shas.len() > 1 {
+                bail!("Ambiguous reference {}: Candidates are: {:?}", name, shas);
+            }
+            shas.pop().unwrap()
+        }
+    };
 
-    let mut sha = shas.pop().unwrap();
     if fmt.is_none() {
         return Ok(Some(sha));
     }
@@ -84,19 +94,116 @@ pub fn object_find(
     }
 }
 
+/// Splits "HEAD~2^2" into ("HEAD", "~2^2"). Returns None if `name` has no
+/// ancestry suffix.
+fn revision_split(name: &str) -> Option<(&str, &str)> {
+    let pos = name.find(['~', '^'])?;
+    if pos == 0 {
+        return None;
+    }
+    Some((&name[..pos], &name[pos..]))
+}
+
+/// Applies ancestry operators to `base`: `~n` follows the first parent n
+/// times, `^n` selects the n-th parent (`^0` is the commit itself).
+fn revision_walk(repo: &Repository, base: &str, ops: &str) -> Result<String> {
+    let mut sha = object_find(repo, base, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("{} is not a commit", base))?;
+
+    let op_re = Regex::new(r"([~^])(\d*)").unwrap();
+    let mut consumed = 0;
+    for cap in op_re.captures_iter(ops) {
+        let whole = cap.get(0).unwrap();
+        if whole.start() != consumed {
+            bail!("Invalid revision {}{}", base, ops);
+        }
+        consumed = whole.end();
+
+        let n: usize = cap[2].parse().unwrap_or(1);
+        let parents = |sha: &str| -> Result<Vec<String>> {
+            let obj = object_read(repo, sha)?;
+            let commit = obj
+                .as_any()
+                .downcast_ref::<Commit>()
+                .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+            Ok(commit.parents())
+        };
+        if &cap[1] == "~" {
+            for _ in 0..n {
+                sha = parents(&sha)?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| anyhow!("Revision {}{} goes past the root", base, ops))?;
+            }
+        } else if n > 0 {
+            sha = parents(&sha)?
+                .into_iter()
+                .nth(n - 1)
+                .ok_or_else(|| anyhow!("Commit {} has no parent {}", sha, n))?;
+        }
+    }
+    if consumed != ops.len() {
+        bail!("Invalid revision {}{}", base, ops);
+    }
+    Ok(sha)
+}
+
 pub fn object_read(repo: &Repository, sha: &str) -> Result<Box<dyn Object>> {
+    let (fmt, data) = object_read_raw(repo, sha)?;
+    object_parse(&fmt, &data)
+}
+
+/// Builds the object of type `fmt` from its serialized content.
+pub fn object_parse(fmt: &[u8], data: &[u8]) -> Result<Box<dyn Object>> {
+    match fmt {
+        b"commit" => Ok(Box::new(Commit::deserialize(data))),
+        b"tree" => Ok(Box::new(Tree::deserialize(data))),
+        b"tag" => Ok(Box::new(Tag::deserialize(data))),
+        b"blob" => Ok(Box::new(Blob::deserialize(data))),
+        _ => bail!("Unknown object type: {}", String::from_utf8_lossy(fmt)),
+    }
+}
+
+/// Reads an object's type and serialized content without parsing it.
+pub fn object_read_raw(repo: &Repository, sha: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+    let _phase = profile_phase("odb read");
     // e.g. .git/objects/e6/73d1b7eaa0aa01b5bc2442d570a765bdaae751
+    if sha.len() < 3 {
+        bail!("Object {} does not exist", sha);
+    }
     let dir = &sha[0..2];
     let file = &sha[2..];
     let object_path = repo_file(repo, PathBuf::from("objects").join(dir).join(file), false)?;
     if !object_path.is_file() {
-        bail!("Object {} does not exist", sha);
+        let (fmt, data) =
+            pack_object_read(repo, sha)?.ok_or_else(|| anyhow!("Object {} does not exist", sha))?;
+        if repo.strict_hash {
+            let actual = object_write_raw(&fmt, &data, None)?;
+            if actual != sha {
+                bail!(
+                    "Object {} is corrupt: its content hashes to {}",
+                    sha,
+                    actual
+                );
+            }
+        }
+        return Ok((fmt, data));
     }
 
     let compressed = fs::read(&object_path)?;
     let mut decoder = ZlibDecoder::new(&compressed[..]);
     let mut raw = Vec::new();
     decoder.read_to_end(&mut raw)?;
+    if repo.strict_hash {
+        let actual = hex::encode(Sha1::digest(&raw));
+        if actual != sha {
+            bail!(
+                "Object {} is corrupt: its content hashes to {}",
+                sha,
+                actual
+            );
+        }
+    }
 
     // b"<type> <size>\x00<data>"
 
@@ -105,7 +212,7 @@ pub fn object_read(repo: &Repository, sha: &str) -> Result<Box<dyn Object>> {
         .iter()
         .position(|&b| b == b' ')
         .ok_or_else(|| anyhow!("Malformed object header"))?;
-    let fmt = &raw[0..space_pos];
+    let fmt = raw[0..space_pos].to_vec();
 
     // read size
     let null_pos = raw
@@ -118,25 +225,134 @@ pub fn object_read(repo: &Repository, sha: &str) -> Result<Box<dyn Object>> {
         bail!("Malformed object {}: bad length", sha);
     }
 
-    let data = &raw[null_pos + 1..];
+    Ok((fmt, raw[null_pos + 1..].to_vec()))
+}
 
-    match fmt {
-        b"commit" => bail!("commit type not implemented"),
-        b"tree" => bail!("tree type not implemented"),
-        b"tag" => bail!("tag type not implemented"),
-        b"blob" => Ok(Box::new(Blob::deserialize(data))),
-        _ => bail!("Unknown object type: {}", std::str::from_utf8(fmt)?),
+/// An object's type and the size of its content, as enumerating the
+/// database reports them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub fmt: &'static [u8],
+    pub size: usize,
+}
+
+/// The type and size of `sha`, loose or packed, from its header alone.
+pub fn object_info(repo: &Repository, sha: &str) -> Result<ObjectInfo> {
+    if sha.len() < 3 {
+        bail!("Object {} does not exist", sha);
+    }
+    let path = repo_file(
+        repo,
+        PathBuf::from("objects").join(&sha[..2]).join(&sha[2..]),
+        false,
+    )?;
+    if !path.is_file() {
+        return pack_object_info(repo, sha)?
+            .ok_or_else(|| anyhow!("Object {} does not exist", sha));
+    }
+    // "<type> <size>\0" fits in the first few inflated bytes.
+    let mut header = Vec::new();
+    ZlibDecoder::new(File::open(&path)?)
+        .take(32)
+        .read_to_end(&mut header)?;
+    let malformed = || anyhow!("Malformed object {}: bad header", sha);
+    let null_pos = header.iter().position(|&b| b == 0).ok_or_else(malformed)?;
+    let (fmt, size) = std::str::from_utf8(&header[..null_pos])?
+        .split_once(' ')
+        .ok_or_else(malformed)?;
+    let fmt: &'static [u8] = match fmt {
+        "commit" => b"commit",
+        "tree" => b"tree",
+        "blob" => b"blob",
+        "tag" => b"tag",
+        _ => bail!("Object {} has unknown type {}", sha, fmt),
+    };
+    Ok(ObjectInfo {
+        fmt,
+        size: size.parse().map_err(|_| malformed())?,
+    })
+}
+
+/// Enumerates the loose objects with their type and size. An object whose
+/// header can't be read comes with the error instead.
+pub fn object_iter_loose(
+    repo: &Repository,
+) -> Result<impl Iterator<Item = (String, Result<ObjectInfo>)> + '_> {
+    Ok(object_list_loose(repo)?.into_iter().map(|sha| {
+        let info = object_info(repo, &sha);
+        (sha, info)
+    }))
+}
+
+/// Enumerates the packed objects with their type and size, each once even
+/// when several packs hold it.
+pub fn object_iter_packed(
+    repo: &Repository,
+) -> Result<impl Iterator<Item = (String, Result<ObjectInfo>)> + '_> {
+    let mut seen = HashSet::new();
+    let packs = pack_files(repo)?;
+    Ok(packs.into_iter().flat_map(move |pack| {
+        let shas: Vec<String> = pack.shas().filter(|sha| seen.insert(sha.clone())).collect();
+        shas.into_iter().map(move |sha| {
+            let info = pack.info(repo, &sha).and_then(|info| {
+                info.ok_or_else(|| {
+                    anyhow!("{} is listed in {} but not found", sha, pack.path.display())
+                })
+            });
+            (sha, info)
+        })
+    }))
+}
+
+/// Enumerates every object in the database once: the loose ones, then
+/// those only found in packs.
+pub fn object_iter(
+    repo: &Repository,
+) -> Result<impl Iterator<Item = (String, Result<ObjectInfo>)> + '_> {
+    let loose: HashSet<String> = object_list_loose(repo)?.into_iter().collect();
+    let packed = object_iter_packed(repo)?.filter(move |(sha, _)| !loose.contains(sha));
+    Ok(object_iter_loose(repo)?.chain(packed))
+}
+
+/// Lists the sha of every loose object in the database.
+pub fn object_list_loose(repo: &Repository) -> Result<Vec<String>> {
+    let mut ret = Vec::new();
+    let objects_dir = match repo_dir(repo, PathBuf::from("objects"), false)? {
+        Some(p) => p,
+        None => return Ok(ret),
+    };
+
+    let mut dirs: Vec<_> = fs::read_dir(objects_dir)?.filter_map(|e| e.ok()).collect();
+    dirs.sort_by_key(|e| e.file_name());
+    for dir in dirs {
+        let prefix = dir.file_name().to_string_lossy().to_string();
+        if prefix.len() != 2 || !dir.path().is_dir() {
+            continue;
+        }
+        let mut files: Vec<_> = fs::read_dir(dir.path())?.filter_map(|e| e.ok()).collect();
+        files.sort_by_key(|e| e.file_name());
+        for file in files {
+            let name = file.file_name().to_string_lossy().to_string();
+            if name.len() == 38 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+                ret.push(format!("{}{}", prefix, name));
+            }
+        }
     }
+    Ok(ret)
 }
 
 pub fn object_write(obj: &dyn Object, repo: Option<&Repository>) -> Result<String> {
-    let data = obj.serialize();
-    let header = format!("{} {}", std::str::from_utf8(obj.fmt())?, data.len());
+    object_write_raw(obj.fmt(), &obj.serialize(), repo)
+}
+
+/// Stores already serialized content as an object of type `fmt`.
+pub fn object_write_raw(fmt: &[u8], data: &[u8], repo: Option<&Repository>) -> Result<String> {
+    let header = format!("{} {}", std::str::from_utf8(fmt)?, data.len());
 
     let mut store = Vec::new();
     store.extend_from_slice(header.as_bytes());
     store.push(0);
-    store.extend_from_slice(&data);
+    store.extend_from_slice(data);
 
     let mut hasher = Sha1::new();
     hasher.update(&store);
@@ -147,25 +363,60 @@ pub fn object_write(obj: &dyn Object, repo: Option<&Repository>) -> Result<Strin
         let file = &sha[2..];
         let object_path = repo_file(repo, PathBuf::from("objects").join(dir).join(file), true)?;
         if !object_path.exists() {
-            let f = fs::File::create(object_path)?;
+            // Write under a name of our own and rename, so that readers
+            // and other writers of the same object never see it partial.
+            static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let temp = object_path.with_file_name(format!(
+                "tmp_obj_{}_{}",
+                std::process::id(),
+                TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let f = fs::File::create(&temp)?;
             let mut encoder = ZlibEncoder::new(f, Compression::default());
             encoder.write_all(&store)?;
-            encoder.finish()?;
+            let f = encoder.finish()?;
+            if repo.fsync.objects {
+                f.sync_all()?;
+            }
+            fs::rename(&temp, object_path)?;
         }
     }
 
     Ok(sha)
 }
 
-pub fn hash_object(path: &PathBuf, fmt: &[u8], write: bool) -> Result<String> {
+/// Prints the id of an object of type `fmt` holding the content read from
+/// stdin with `stdin`, then that of each of `paths`, writing each to the
+/// database with `write`. With `stdin_paths`, the paths are read from
+/// stdin instead, one per line.
+pub fn hash_object(
+    paths: &[PathBuf],
+    stdin: bool,
+    stdin_paths: bool,
+    fmt: &[u8],
+    write: bool,
+) -> Result<()> {
     let repo = if write {
         repo_find(Path::new("."), true)?
     } else {
         None
     };
 
-    let file = File::open(path)?;
-    object_hash(file, fmt, repo.as_ref())
+    if stdin {
+        println!("{}", object_hash(io::stdin().lock(), fmt, repo.as_ref())?);
+    }
+    let mut paths = paths.to_vec();
+    if stdin_paths {
+        for line in io::stdin().lock().lines() {
+            paths.push(PathBuf::from(line?));
+        }
+    }
+    for path in &paths {
+        let file =
+            File::open(path).map_err(|e| anyhow!("could not open '{}': {}", path.display(), e))?;
+        println!("{}", object_hash(file, fmt, repo.as_ref())?);
+    }
+    Ok(())
 }
 
 pub fn object_hash<R: Read>(
@@ -178,15 +429,63 @@ pub fn object_hash<R: Read>(
 
     let obj: Box<dyn Object> = match fmt {
         b"blob" => Box::new(Blob::deserialize(&data)),
-        b"commit" => bail!("commit type not implemented"),
-        b"tree" => bail!("tree type not implemented"),
-        b"tag" => bail!("tag type not implemented"),
+        b"commit" => Box::new(Commit::deserialize(&data)),
+        b"tree" => Box::new(Tree::deserialize(&data)),
+        b"tag" => Box::new(Tag::deserialize(&data)),
         _ => bail!("Unknown object type: {}", std::str::from_utf8(fmt)?),
     };
 
     object_write(obj.as_ref(), repo)
 }
 
+/// How many hex digits object names are shortened to by default, when
+/// `core.abbrev` doesn't say.
+pub const ABBREV_DEFAULT: usize = 7;
+
+/// How many hex digits object names are shortened to: `len` when given,
+/// as by `--abbrev=<n>`, otherwise as `core.abbrev` says, where "no" keeps
+/// full names. Zero stands for full names.
+pub fn abbrev_len(repo: &Repository, len: Option<usize>) -> usize {
+    let len = len.unwrap_or_else(|| match repo.config_get("core", "abbrev").as_deref() {
+        Some("no") => 0,
+        Some(value) => value.parse().unwrap_or(ABBREV_DEFAULT),
+        None => ABBREV_DEFAULT,
+    });
+    len.min(40)
+}
+
+/// Shortens `sha` for display to `len` hex digits, or to more where fewer
+/// would name another object of `repo` as well. Zero keeps the full name;
+/// like git, names are never shorter than four digits, the least
+/// `object_resolve` accepts.
+pub fn sha_abbrev(repo: &Repository, sha: &str, len: usize) -> String {
+    if len == 0 || !sha.is_ascii() || sha.len() < 4 {
+        return sha.to_string();
+    }
+    let mut shared = 0;
+    if let Ok(Some(dir)) = repo_dir(repo, PathBuf::from("objects").join(&sha[..2]), false) {
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.len() == sha.len() - 2 && name != sha[2..] {
+                let common = name
+                    .bytes()
+                    .zip(sha[2..].bytes())
+                    .take_while(|(a, b)| a == b);
+                shared = shared.max(2 + common.count());
+            }
+        }
+    }
+    for pack in pack_files(repo).unwrap_or_default() {
+        shared = shared.max(pack.shared_prefix_len(sha));
+    }
+    sha[..len.max(shared + 1).clamp(4, sha.len())].to_string()
+}
+
+/// `sha` shortened as `core.abbrev` says.
+pub fn sha_short(repo: &Repository, sha: &str) -> String {
+    sha_abbrev(repo, sha, abbrev_len(repo, None))
+}
+
 pub fn object_resolve(repo: &Repository, name: &str) -> Result<Vec<String>> {
     let mut candidates = Vec::new();
 
@@ -217,17 +516,84 @@ pub fn object_resolve(repo: &Repository, name: &str) -> Result<Vec<String>> {
                 }
             }
         }
+        for sha in pack_object_list(repo)? {
+            if sha.starts_with(&lower) && !candidates.contains(&sha) {
+                candidates.push(sha);
+            }
+        }
     }
 
-    // Try tags.
-    if let Some(tag_sha) = ref_resolve(repo, &format!("refs/tags/{}", name))? {
-        candidates.push(tag_sha);
+    // The first ref the name can stand for, in git's order. Only names
+    // under refs/ and pseudo-refs like FETCH_HEAD are taken as they are,
+    // so that other files in the git directory don't pass for refs.
+    if !refname_valid(name, true) {
+        return Ok(candidates);
     }
-
-    // Try branches.
-    if let Some(branch_sha) = ref_resolve(repo, &format!("refs/heads/{}", name))? {
-        candidates.push(branch_sha);
+    let full =
+        name.starts_with("refs/") || name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+    let rules = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ];
+    for refname in rules.iter().skip(if full { 0 } else { 1 }) {
+        if let Some(sha) = ref_resolve(repo, refname)? {
+            candidates.push(sha);
+            break;
+        }
     }
 
     Ok(candidates)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        object_find, ref_create, sha_abbrev, sha_short,
+        test_utils::{make_commit, test_repo},
+    };
+
+    #[test]
+    fn test_object_find_remote_refs() {
+        let repo = test_repo("object-find-dwim");
+        let first = make_commit(&repo, "one", &[]);
+        let second = make_commit(&repo, "two", &[&first]);
+        for dir in ["refs/remotes/origin", "refs/heads/origin"] {
+            std::fs::create_dir_all(repo.gitdir.join(dir)).unwrap();
+        }
+        ref_create(&repo, "remotes/origin/master", &first).unwrap();
+        ref_create(&repo, "remotes/origin/HEAD", &first).unwrap();
+        ref_create(&repo, "heads/origin/master", &second).unwrap();
+
+        let find = |name| object_find(&repo, name, None, false).unwrap();
+        assert_eq!(find("refs/remotes/origin/master"), Some(first.clone()));
+        assert_eq!(find("origin"), Some(first.clone()));
+        // Branches come before remote-tracking branches.
+        assert_eq!(find("origin/master"), Some(second));
+        assert!(object_find(&repo, "config", None, false).is_err());
+    }
+
+    #[test]
+    fn test_sha_abbrev() {
+        let mut repo = test_repo("sha-abbrev");
+        let sha = make_commit(&repo, "one", &[]);
+        assert_eq!(sha_short(&repo, &sha), sha[..7]);
+        assert_eq!(sha_abbrev(&repo, &sha, 2), sha[..4]);
+        assert_eq!(sha_abbrev(&repo, &sha, 0), sha);
+
+        // Another object sharing the first ten digits takes an eleventh.
+        let flip = if &sha[10..11] == "0" { "1" } else { "0" };
+        let other = format!("{}{}{}", &sha[2..10], flip, &sha[11..]);
+        std::fs::write(repo.gitdir.join("objects").join(&sha[..2]).join(other), b"").unwrap();
+        assert_eq!(sha_short(&repo, &sha), sha[..11]);
+
+        repo.config_set("core", "abbrev", "12").unwrap();
+        assert_eq!(sha_short(&repo, &sha), sha[..12]);
+        repo.config_set("core", "abbrev", "no").unwrap();
+        assert_eq!(sha_short(&repo, &sha), sha);
+        std::fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,378 @@
+use std::{collections::HashMap, io::Read, io::Write};
+
+use anyhow::{anyhow, bail, Result};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use sha1::{Digest, Sha1};
+
+use crate::{object_read_raw, object_write_raw, PackIndexEntry, Repository};
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// An object read out of a pack, with its deltas resolved.
+pub struct PackObject {
+    pub sha: String,
+    pub fmt: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+fn pack_type_code(fmt: &[u8]) -> Result<u8> {
+    match fmt {
+        b"commit" => Ok(OBJ_COMMIT),
+        b"tree" => Ok(OBJ_TREE),
+        b"blob" => Ok(OBJ_BLOB),
+        b"tag" => Ok(OBJ_TAG),
+        _ => bail!("Unknown object type: {}", String::from_utf8_lossy(fmt)),
+    }
+}
+
+fn pack_type_name(code: u8) -> Result<&'static [u8]> {
+    match code {
+        OBJ_COMMIT => Ok(b"commit"),
+        OBJ_TREE => Ok(b"tree"),
+        OBJ_BLOB => Ok(b"blob"),
+        OBJ_TAG => Ok(b"tag"),
+        _ => bail!("Bad object type {} in pack", code),
+    }
+}
+
+/// Builds a version 2 pack holding the given objects, stored whole.
+pub fn pack_write(repo: &Repository, shas: &[String]) -> Result<Vec<u8>> {
+    Ok(pack_write_indexed(repo, shas)?.0)
+}
+
+/// Like `pack_write`, also returning where each object starts in the pack
+/// and the CRC-32 of its entry, which the pack's index records.
+pub fn pack_write_indexed(
+    repo: &Repository,
+    shas: &[String],
+) -> Result<(Vec<u8>, Vec<PackIndexEntry>)> {
+    let mut out = Vec::new();
+    out.extend_from_slice(b"PACK");
+    out.extend_from_slice(&2u32.to_be_bytes());
+    out.extend_from_slice(&(shas.len() as u32).to_be_bytes());
+
+    let mut entries = Vec::with_capacity(shas.len());
+    for sha in shas {
+        let start = out.len();
+        let (fmt, data) = object_read_raw(repo, sha)?;
+
+        // Type and size: 4 bits of size in the first byte, then 7 per byte.
+        let mut size = data.len();
+        let mut byte = (pack_type_code(&fmt)? << 4) | (size & 0x0f) as u8;
+        size >>= 4;
+        while size != 0 {
+            out.push(byte | 0x80);
+            byte = (size & 0x7f) as u8;
+            size >>= 7;
+        }
+        out.push(byte);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data)?;
+        out.extend(encoder.finish()?);
+        entries.push(PackIndexEntry {
+            sha: sha.clone(),
+            offset: start as u64,
+            crc: crc32fast::hash(&out[start..]),
+        });
+    }
+
+    let checksum = Sha1::digest(&out);
+    out.extend_from_slice(&checksum);
+    Ok((out, entries))
+}
+
+/// How an entry of a pack is stored.
+pub enum DeltaBase {
+    /// A whole object of this type.
+    None(&'static [u8]),
+    /// A delta against the object at this pack offset.
+    Offset(usize),
+    /// A delta against the object with this sha.
+    Sha(String),
+}
+
+/// Reads the entry of `pack` at `start`: how it is stored, its inflated
+/// data and where the next entry begins. `end` is where the entries stop.
+pub fn pack_entry_read(
+    pack: &[u8],
+    start: usize,
+    end: usize,
+) -> Result<(DeltaBase, Vec<u8>, usize)> {
+    let (base, size, mut pos) = pack_entry_header(pack, start)?;
+    let mut decoder = ZlibDecoder::new(
+        pack.get(pos..end)
+            .ok_or_else(|| anyhow!("Truncated pack"))?,
+    );
+    let mut data = Vec::with_capacity(size);
+    decoder.read_to_end(&mut data)?;
+    if data.len() != size {
+        bail!("Pack object at offset {} has the wrong size", start);
+    }
+    pos += decoder.total_in() as usize;
+    Ok((base, data, pos))
+}
+
+/// Parses the header of the entry of `pack` at `start`: how it is stored,
+/// the inflated size of its data and where that data begins.
+pub fn pack_entry_header(pack: &[u8], start: usize) -> Result<(DeltaBase, usize, usize)> {
+    let mut pos = start;
+    let mut byte = *pack.get(pos).ok_or_else(|| anyhow!("Truncated pack"))?;
+    pos += 1;
+    let code = (byte >> 4) & 0x07;
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = *pack.get(pos).ok_or_else(|| anyhow!("Truncated pack"))?;
+        pos += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+    }
+
+    let base = match code {
+        OBJ_OFS_DELTA => {
+            let mut byte = *pack.get(pos).ok_or_else(|| anyhow!("Truncated pack"))?;
+            pos += 1;
+            let mut offset = (byte & 0x7f) as usize;
+            while byte & 0x80 != 0 {
+                byte = *pack.get(pos).ok_or_else(|| anyhow!("Truncated pack"))?;
+                pos += 1;
+                offset = ((offset + 1) << 7) | (byte & 0x7f) as usize;
+            }
+            let base = start
+                .checked_sub(offset)
+                .ok_or_else(|| anyhow!("Bad delta base offset"))?;
+            DeltaBase::Offset(base)
+        }
+        OBJ_REF_DELTA => {
+            let base = pack
+                .get(pos..pos + 20)
+                .ok_or_else(|| anyhow!("Truncated pack"))?;
+            pos += 20;
+            DeltaBase::Sha(hex::encode(base))
+        }
+        _ => DeltaBase::None(pack_type_name(code)?),
+    };
+
+    Ok((base, size, pos))
+}
+
+/// Parses a pack and resolves its deltas. Bases of REF_DELTA objects may
+/// also come from the repository, as in the thin packs git pushes.
+pub fn pack_read(repo: Option<&Repository>, pack: &[u8]) -> Result<Vec<PackObject>> {
+    if pack.len() < 32 || &pack[..4] != b"PACK" {
+        bail!("Not a pack file");
+    }
+    let version = u32::from_be_bytes(pack[4..8].try_into()?);
+    if version != 2 && version != 3 {
+        bail!("Unsupported pack version {}", version);
+    }
+    let count = u32::from_be_bytes(pack[8..12].try_into()?) as usize;
+
+    let body_end = pack.len() - 20;
+    if Sha1::digest(&pack[..body_end]).as_slice() != &pack[body_end..] {
+        bail!("Pack checksum mismatch");
+    }
+
+    let mut entries: Vec<(usize, DeltaBase, Vec<u8>)> = Vec::with_capacity(count);
+    let mut pos = 12;
+    for _ in 0..count {
+        let (base, data, next) = pack_entry_read(pack, pos, body_end)?;
+        entries.push((pos, base, data));
+        pos = next;
+    }
+    if pos != body_end {
+        bail!("Pack has trailing data");
+    }
+
+    // Resolve deltas against earlier results until nothing changes, then
+    // fall back to the repository for the bases of a thin pack.
+    let mut by_offset: HashMap<usize, usize> = HashMap::new();
+    let mut by_sha: HashMap<String, usize> = HashMap::new();
+    let mut resolved: Vec<Option<PackObject>> = (0..entries.len()).map(|_| None).collect();
+    let mut pending = entries.len();
+    let mut use_repo = false;
+    while pending > 0 {
+        let before = pending;
+        for (i, (offset, base, data)) in entries.iter().enumerate() {
+            if resolved[i].is_some() {
+                continue;
+            }
+            let base_obj = match base {
+                DeltaBase::None(fmt) => Some((fmt.to_vec(), None)),
+                DeltaBase::Offset(base) => by_offset
+                    .get(base)
+                    .map(|&b| resolved[b].as_ref().unwrap())
+                    .map(|o| (o.fmt.clone(), Some(o.data.clone()))),
+                DeltaBase::Sha(base) => match by_sha.get(base) {
+                    Some(&b) => resolved[b]
+                        .as_ref()
+                        .map(|o| (o.fmt.clone(), Some(o.data.clone()))),
+                    None if use_repo => match repo {
+                        Some(repo) => object_read_raw(repo, base)
+                            .ok()
+                            .map(|(fmt, data)| (fmt, Some(data))),
+                        None => None,
+                    },
+                    None => None,
+                },
+            };
+            let (fmt, data) = match base_obj {
+                Some((fmt, None)) => (fmt, data.clone()),
+                Some((fmt, Some(base))) => (fmt, delta_apply(&base, data)?),
+                None => continue,
+            };
+            let sha = object_write_raw(&fmt, &data, None)?;
+            by_offset.insert(*offset, i);
+            by_sha.insert(sha.clone(), i);
+            resolved[i] = Some(PackObject { sha, fmt, data });
+            pending -= 1;
+        }
+        if pending == before {
+            if use_repo || repo.is_none() {
+                bail!("Pack has deltas with missing bases");
+            }
+            use_repo = true;
+        }
+    }
+    Ok(resolved.into_iter().map(|o| o.unwrap()).collect())
+}
+
+/// Writes every object of a pack into the repository as loose objects and
+/// returns their shas.
+pub fn pack_unpack(repo: &Repository, pack: &[u8]) -> Result<Vec<String>> {
+    let mut ret = Vec::new();
+    for obj in pack_read(Some(repo), pack)? {
+        ret.push(object_write_raw(&obj.fmt, &obj.data, Some(repo))?);
+    }
+    Ok(ret)
+}
+
+fn delta_varint(delta: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut ret = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*pos).ok_or_else(|| anyhow!("Truncated delta"))?;
+        *pos += 1;
+        ret |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(ret);
+        }
+    }
+}
+
+/// The size of the object a delta is applied to and of the result.
+pub fn delta_sizes(delta: &[u8]) -> Result<(usize, usize)> {
+    let mut pos = 0;
+    Ok((
+        delta_varint(delta, &mut pos)?,
+        delta_varint(delta, &mut pos)?,
+    ))
+}
+
+/// Applies a git delta to `base`.
+pub fn delta_apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+    if delta_varint(delta, &mut pos)? != base.len() {
+        bail!("Delta base size mismatch");
+    }
+    let size = delta_varint(delta, &mut pos)?;
+    let mut out = Vec::with_capacity(size);
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            // Copy from the base: bits 0-3 select offset bytes, 4-6 size
+            // bytes.
+            let mut offset = 0usize;
+            let mut len = 0usize;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= (*delta.get(pos).ok_or_else(|| anyhow!("Truncated delta"))? as usize)
+                        << (8 * i);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    len |= (*delta.get(pos).ok_or_else(|| anyhow!("Truncated delta"))? as usize)
+                        << (8 * i);
+                    pos += 1;
+                }
+            }
+            if len == 0 {
+                len = 0x10000;
+            }
+            let chunk = base
+                .get(offset..offset + len)
+                .ok_or_else(|| anyhow!("Delta copies past the end of its base"))?;
+            out.extend_from_slice(chunk);
+        } else if op != 0 {
+            // Insert the next `op` bytes.
+            let chunk = delta
+                .get(pos..pos + op as usize)
+                .ok_or_else(|| anyhow!("Truncated delta"))?;
+            out.extend_from_slice(chunk);
+            pos += op as usize;
+        } else {
+            bail!("Bad delta opcode 0");
+        }
+    }
+    if out.len() != size {
+        bail!("Delta result size mismatch");
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        delta_apply, pack_read, pack_write, reachable_objects,
+        test_utils::{make_commit, test_repo},
+    };
+
+    #[test]
+    fn test_pack_round_trip() {
+        let repo = test_repo("pack");
+        let a = make_commit(&repo, "a", &[]);
+        let b = make_commit(&repo, "b", &[&a]);
+        let mut shas: Vec<String> = reachable_objects(&repo, &[b])
+            .unwrap()
+            .into_iter()
+            .collect();
+        shas.sort();
+
+        let pack = pack_write(&repo, &shas).unwrap();
+        let mut read: Vec<String> = pack_read(None, &pack)
+            .unwrap()
+            .into_iter()
+            .map(|o| o.sha)
+            .collect();
+        read.sort();
+        assert_eq!(read, shas);
+
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_delta_apply() {
+        // Base of 11 bytes, result of 12: copy 6 bytes from offset 0,
+        // insert "rust", copy 2 bytes from offset 9.
+        let base = b"hello world";
+        let delta = [11, 12, 0x90, 6, 4, b'r', b'u', b's', b't', 0x91, 9, 2];
+        assert_eq!(delta_apply(base, &delta).unwrap(), b"hello rustld");
+
+        // The declared result size must match.
+        let delta = [11, 14, 0x90, 6, 4, b'r', b'u', b's', b't', 0x91, 9, 2];
+        assert!(delta_apply(base, &delta).is_err());
+    }
+}
@@ -0,0 +1,490 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, bail, Result};
+use flate2::read::ZlibDecoder;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    delta_apply, delta_sizes, object_info, object_read_raw, object_write_raw, pack_entry_header,
+    pack_entry_read, pack_write_indexed, repo_dir, DeltaBase, ObjectInfo, Repository,
+};
+
+/// Where an object sits in a pack, as its `.idx` records it.
+pub struct PackIndexEntry {
+    pub sha: String,
+    pub offset: u64,
+    /// CRC-32 of the object's entry in the pack, header included.
+    pub crc: u32,
+}
+
+/// One object of a pack as `verify-pack -v` describes it.
+pub struct PackEntryStat {
+    pub sha: String,
+    /// The type of the object, deltas resolved.
+    pub fmt: String,
+    /// The size of the entry's inflated data, which for a delta is the
+    /// delta's size.
+    pub size: usize,
+    pub packed_size: usize,
+    pub offset: u64,
+    /// For a delta, how long its chain is and the object it applies to.
+    pub delta: Option<(usize, String)>,
+}
+
+/// A pack under `objects/pack` with its index, both read whole. Packs never
+/// change once named, so they are loaded once per process.
+pub struct PackFile {
+    pub path: PathBuf,
+    /// Object names in sorted order and the offset of each in `data` and
+    /// the CRC-32 of its entry.
+    shas: Vec<[u8; 20]>,
+    offsets: Vec<u64>,
+    crcs: Vec<u32>,
+    data: Vec<u8>,
+}
+
+impl PackFile {
+    /// Loads `pack-<sha>.pack` by way of its version 2 index.
+    pub fn load(idx_path: &Path) -> Result<PackFile> {
+        let idx = fs::read(idx_path)?;
+        if idx.len() < 8 + 256 * 4 + 40 || &idx[..8] != b"\xfftOc\x00\x00\x00\x02" {
+            bail!("{} is not a version 2 pack index", idx_path.display());
+        }
+        let be32 = |pos: usize| u32::from_be_bytes(idx[pos..pos + 4].try_into().unwrap());
+        let count = be32(8 + 255 * 4) as usize;
+        let shas_at = 8 + 256 * 4;
+        let crcs_at = shas_at + count * 20;
+        let offsets_at = shas_at + count * 24;
+        let large_at = offsets_at + count * 4;
+        if idx.len() < large_at + 40 {
+            bail!("Pack index {} is truncated", idx_path.display());
+        }
+
+        let shas = (0..count)
+            .map(|i| {
+                idx[shas_at + i * 20..shas_at + i * 20 + 20]
+                    .try_into()
+                    .unwrap()
+            })
+            .collect();
+        let crcs = (0..count).map(|i| be32(crcs_at + i * 4)).collect();
+        let offsets = (0..count)
+            .map(|i| {
+                let offset = be32(offsets_at + i * 4);
+                if offset & 0x8000_0000 == 0 {
+                    return Ok(offset as u64);
+                }
+                // Offsets past 2GiB live in a table of 8-byte ones.
+                let pos = large_at + (offset & 0x7fff_ffff) as usize * 8;
+                let large = idx
+                    .get(pos..pos + 8)
+                    .ok_or_else(|| anyhow!("Pack index {} is truncated", idx_path.display()))?;
+                Ok(u64::from_be_bytes(large.try_into().unwrap()))
+            })
+            .collect::<Result<_>>()?;
+
+        let path = idx_path.with_extension("pack");
+        let data = fs::read(&path)?;
+        if data.len() < 32 || &data[..4] != b"PACK" {
+            bail!("{} is not a pack file", path.display());
+        }
+        // The index ends with the checksum of the pack it describes.
+        if data[data.len() - 20..] != idx[idx.len() - 40..idx.len() - 20] {
+            bail!("{} does not match its index", path.display());
+        }
+        Ok(PackFile {
+            path,
+            shas,
+            offsets,
+            crcs,
+            data,
+        })
+    }
+
+    /// Checks the pack against the checksum it ends with.
+    pub fn verify(&self) -> Result<()> {
+        let (body, trailer) = self.data.split_at(self.data.len() - 20);
+        if Sha1::digest(body).as_slice() != trailer {
+            bail!("{}: pack checksum mismatch", self.path.display());
+        }
+        Ok(())
+    }
+
+    /// Checks every object in the pack: its entry against the CRC the
+    /// index has for it, that its data inflates to the size its header
+    /// gives and that, deltas applied, it hashes to its name. Returns the
+    /// objects in the order they are stored.
+    pub fn verify_objects(&self, repo: &Repository) -> Result<Vec<PackEntryStat>> {
+        let mut order: Vec<usize> = (0..self.shas.len()).collect();
+        order.sort_by_key(|&i| self.offsets[i]);
+        let end = (self.data.len() - 20) as u64;
+        let mut ret = Vec::with_capacity(order.len());
+        for (n, &i) in order.iter().enumerate() {
+            let sha = hex::encode(self.shas[i]);
+            let (offset, next) = (
+                self.offsets[i],
+                order.get(n + 1).map_or(end, |&j| self.offsets[j]),
+            );
+            let entry = self
+                .data
+                .get(offset as usize..next as usize)
+                .ok_or_else(|| anyhow!("{}: bad offset for object {}", self.path.display(), sha))?;
+            if crc32fast::hash(entry) != self.crcs[i] {
+                bail!("{}: CRC mismatch for object {}", self.path.display(), sha);
+            }
+            let bad = |e: anyhow::Error| anyhow!("{}: object {}: {}", self.path.display(), sha, e);
+            let (_, data, _) =
+                pack_entry_read(&self.data, offset as usize, next as usize).map_err(bad)?;
+            let (fmt, obj) = self.read_at(repo, offset as usize).map_err(bad)?;
+            if object_write_raw(&fmt, &obj, None)? != sha {
+                bail!("{}: object {} is corrupt", self.path.display(), sha);
+            }
+            ret.push(PackEntryStat {
+                sha,
+                fmt: String::from_utf8_lossy(&fmt).to_string(),
+                size: data.len(),
+                packed_size: (next - offset) as usize,
+                offset,
+                delta: self.delta_chain(offset as usize)?,
+            });
+        }
+        Ok(ret)
+    }
+
+    /// How many deltas lead from the entry at `offset` to a whole object,
+    /// and the base the entry applies to; None if it isn't a delta. A base
+    /// outside the pack counts as a whole object.
+    fn delta_chain(&self, offset: usize) -> Result<Option<(usize, String)>> {
+        let (base, _, _) = pack_entry_header(&self.data, offset)?;
+        let base_offset = match &base {
+            DeltaBase::None(_) => return Ok(None),
+            DeltaBase::Offset(base) => Some(*base),
+            DeltaBase::Sha(base) => self.position(base).map(|i| self.offsets[i] as usize),
+        };
+        let base_sha = match base {
+            DeltaBase::Sha(sha) => sha,
+            _ => {
+                let i = self
+                    .offsets
+                    .iter()
+                    .position(|&o| Some(o as usize) == base_offset)
+                    .ok_or_else(|| anyhow!("Bad delta base offset"))?;
+                hex::encode(self.shas[i])
+            }
+        };
+        let depth = match base_offset {
+            Some(base) => self.delta_chain(base)?.map_or(0, |(depth, _)| depth),
+            None => 0,
+        };
+        Ok(Some((depth + 1, base_sha)))
+    }
+
+    pub fn contains(&self, sha: &str) -> bool {
+        self.position(sha).is_some()
+    }
+
+    fn position(&self, sha: &str) -> Option<usize> {
+        let raw: [u8; 20] = hex::decode(sha).ok()?.try_into().ok()?;
+        self.shas.binary_search(&raw).ok()
+    }
+
+    /// How many leading hex digits `sha` shares with the object of this
+    /// pack closest to it, not counting itself.
+    pub fn shared_prefix_len(&self, sha: &str) -> usize {
+        let Some(raw) = hex::decode(sha)
+            .ok()
+            .and_then(|raw| <[u8; 20]>::try_from(raw).ok())
+        else {
+            return 0;
+        };
+        // In sorted order the closest names are the neighbours.
+        let (before, after) = match self.shas.binary_search(&raw) {
+            Ok(i) => (i.checked_sub(1), i + 1),
+            Err(i) => (i.checked_sub(1), i),
+        };
+        [before, Some(after)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| self.shas.get(i))
+            .map(|other| hex_prefix_len(&raw, other))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The names of the objects in this pack, sorted.
+    pub fn shas(&self) -> impl Iterator<Item = String> + '_ {
+        self.shas.iter().map(hex::encode)
+    }
+
+    /// Reads `sha` out of this pack, if it is there.
+    pub fn read(&self, repo: &Repository, sha: &str) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        match self.position(sha) {
+            Some(i) => Ok(Some(self.read_at(repo, self.offsets[i] as usize)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The type and size of `sha`, if this pack has it, read from the
+    /// entry headers without inflating whole objects.
+    pub fn info(&self, repo: &Repository, sha: &str) -> Result<Option<ObjectInfo>> {
+        match self.position(sha) {
+            Some(i) => Ok(Some(self.info_at(repo, self.offsets[i] as usize)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn info_at(&self, repo: &Repository, offset: usize) -> Result<ObjectInfo> {
+        let (base, size, pos) = pack_entry_header(&self.data, offset)?;
+        // A delta starts with the sizes of its base and of its result; the
+        // type is the base's.
+        let mut head = Vec::new();
+        if !matches!(base, DeltaBase::None(_)) {
+            ZlibDecoder::new(&self.data[pos..self.data.len() - 20])
+                .take(20)
+                .read_to_end(&mut head)?;
+        }
+        let fmt = match base {
+            DeltaBase::None(fmt) => return Ok(ObjectInfo { fmt, size }),
+            DeltaBase::Offset(base) => self.info_at(repo, base)?.fmt,
+            DeltaBase::Sha(base) => match self.info(repo, &base)? {
+                Some(info) => info.fmt,
+                None => object_info(repo, &base)?.fmt,
+            },
+        };
+        Ok(ObjectInfo {
+            fmt,
+            size: delta_sizes(&head)?.1,
+        })
+    }
+
+    /// Reads the object at `offset`, applying its chain of deltas. Bases
+    /// named by sha may live anywhere in the repository.
+    fn read_at(&self, repo: &Repository, offset: usize) -> Result<(Vec<u8>, Vec<u8>)> {
+        let end = self.data.len() - 20;
+        let (base, data, _) = pack_entry_read(&self.data, offset, end)?;
+        let (fmt, base) = match base {
+            DeltaBase::None(fmt) => return Ok((fmt.to_vec(), data)),
+            DeltaBase::Offset(base) => self.read_at(repo, base)?,
+            DeltaBase::Sha(base) => match self.read(repo, &base)? {
+                Some(obj) => obj,
+                None => object_read_raw(repo, &base)?,
+            },
+        };
+        Ok((fmt, delta_apply(&base, &data)?))
+    }
+}
+
+/// Checks a pack index against the checksum it ends with.
+pub fn pack_index_verify(idx_path: &Path) -> Result<()> {
+    let idx = fs::read(idx_path)?;
+    if idx.len() < 20 {
+        bail!("{} is truncated", idx_path.display());
+    }
+    let (body, trailer) = idx.split_at(idx.len() - 20);
+    if Sha1::digest(body).as_slice() != trailer {
+        bail!("{}: index checksum mismatch", idx_path.display());
+    }
+    Ok(())
+}
+
+/// How many leading hex digits two object names have in common.
+fn hex_prefix_len(a: &[u8; 20], b: &[u8; 20]) -> usize {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(i) if (a[i] ^ b[i]) & 0xf0 == 0 => i * 2 + 1,
+        Some(i) => i * 2,
+        None => 40,
+    }
+}
+
+/// The packs of the repository, oldest first.
+pub fn pack_files(repo: &Repository) -> Result<Vec<Arc<PackFile>>> {
+    static LOADED: OnceLock<Mutex<HashMap<PathBuf, Arc<PackFile>>>> = OnceLock::new();
+
+    let Some(dir) = repo_dir(repo, PathBuf::from("objects/pack"), false)? else {
+        return Ok(Vec::new());
+    };
+    let is_index = |p: &PathBuf| p.extension().is_some_and(|x| x == "idx");
+    let mut idx_paths: Vec<(SystemTime, PathBuf)> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| is_index(p) && p.with_extension("pack").is_file())
+        .map(|p| Ok((fs::metadata(&p)?.modified()?, p)))
+        .collect::<Result<_>>()?;
+    idx_paths.sort();
+
+    let mut loaded = LOADED.get_or_init(Default::default).lock().unwrap();
+    let mut ret = Vec::with_capacity(idx_paths.len());
+    for (_, path) in idx_paths {
+        let pack = match loaded.get(&path) {
+            Some(pack) => pack.clone(),
+            None => {
+                let pack = Arc::new(PackFile::load(&path)?);
+                loaded.insert(path, pack.clone());
+                pack
+            }
+        };
+        ret.push(pack);
+    }
+    Ok(ret)
+}
+
+/// Reads `sha` from whichever pack holds it.
+pub fn pack_object_read(repo: &Repository, sha: &str) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+    for pack in pack_files(repo)? {
+        if let Some(obj) = pack.read(repo, sha)? {
+            return Ok(Some(obj));
+        }
+    }
+    Ok(None)
+}
+
+/// The type and size of `sha` from whichever pack holds it.
+pub fn pack_object_info(repo: &Repository, sha: &str) -> Result<Option<ObjectInfo>> {
+    for pack in pack_files(repo)? {
+        if let Some(info) = pack.info(repo, sha)? {
+            return Ok(Some(info));
+        }
+    }
+    Ok(None)
+}
+
+/// Lists the names of all packed objects, each once.
+pub fn pack_object_list(repo: &Repository) -> Result<Vec<String>> {
+    let mut ret: Vec<String> = pack_files(repo)?.iter().flat_map(|p| p.shas()).collect();
+    ret.sort();
+    ret.dedup();
+    Ok(ret)
+}
+
+/// Writes the objects `shas` as a new pack with its index and returns the
+/// pack's path. The index is written last, so the pack is never seen
+/// without it.
+pub fn pack_store(repo: &Repository, shas: &[String]) -> Result<PathBuf> {
+    let (pack, mut entries) = pack_write_indexed(repo, shas)?;
+    let checksum = &pack[pack.len() - 20..];
+    let dir = repo_dir(repo, PathBuf::from("objects/pack"), true)?.unwrap();
+    let name = format!("pack-{}", hex::encode(checksum));
+
+    entries.sort_by(|a, b| a.sha.cmp(&b.sha));
+    let idx = pack_index_write(&entries, checksum)?;
+    let pack_path = dir.join(format!("{}.pack", name));
+    for (path, content) in [
+        (&pack_path, &pack),
+        (&dir.join(format!("{}.idx", name)), &idx),
+    ] {
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, content)?;
+        if repo.fsync.objects {
+            fs::File::open(&temp)?.sync_all()?;
+        }
+        fs::rename(&temp, path)?;
+    }
+    Ok(pack_path)
+}
+
+/// Builds a version 2 pack index for `entries`, which must be sorted by
+/// name, of the pack whose checksum is `pack_checksum`.
+pub fn pack_index_write(entries: &[PackIndexEntry], pack_checksum: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    out.extend_from_slice(b"\xfftOc");
+    out.extend_from_slice(&2u32.to_be_bytes());
+
+    // Entry i of the fan-out counts the objects whose first byte is <= i.
+    let mut fanout = [0u32; 256];
+    for entry in entries {
+        let first = u8::from_str_radix(&entry.sha[..2], 16)?;
+        fanout[first as usize] += 1;
+    }
+    let mut total = 0;
+    for count in fanout {
+        total += count;
+        out.extend_from_slice(&total.to_be_bytes());
+    }
+
+    for entry in entries {
+        out.extend(hex::decode(&entry.sha)?);
+    }
+    for entry in entries {
+        out.extend_from_slice(&entry.crc.to_be_bytes());
+    }
+    let mut large = Vec::new();
+    for entry in entries {
+        let offset = match u32::try_from(entry.offset) {
+            Ok(offset) if offset & 0x8000_0000 == 0 => offset,
+            _ => {
+                large.push(entry.offset);
+                0x8000_0000 | (large.len() as u32 - 1)
+            }
+        };
+        out.extend_from_slice(&offset.to_be_bytes());
+    }
+    for offset in large {
+        out.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    out.extend_from_slice(pack_checksum);
+    let checksum = Sha1::digest(&out);
+    out.extend_from_slice(&checksum);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        object_iter, object_iter_packed, object_read_raw, pack_files, pack_object_list, pack_store,
+        reachable_objects,
+        test_utils::{make_commit, test_repo},
+    };
+
+    #[test]
+    fn test_pack_store() {
+        let repo = test_repo("packfile");
+        let a = make_commit(&repo, "a", &[]);
+        let b = make_commit(&repo, "b", &[&a]);
+        let mut shas: Vec<String> = reachable_objects(&repo, &[b])
+            .unwrap()
+            .into_iter()
+            .collect();
+        shas.sort();
+        let loose: Vec<_> = shas
+            .iter()
+            .map(|sha| object_read_raw(&repo, sha).unwrap())
+            .collect();
+
+        pack_store(&repo, &shas).unwrap();
+        // Loose and packed copies are enumerated once.
+        let mut listed: Vec<String> = object_iter(&repo).unwrap().map(|(sha, _)| sha).collect();
+        listed.sort();
+        assert_eq!(listed, shas);
+        for dir in fs::read_dir(repo.gitdir.join("objects")).unwrap() {
+            let path = dir.unwrap().path();
+            if path.file_name().unwrap().len() == 2 {
+                fs::remove_dir_all(path).unwrap();
+            }
+        }
+
+        assert_eq!(pack_object_list(&repo).unwrap(), shas);
+        for (sha, obj) in shas.iter().zip(&loose) {
+            assert_eq!(&object_read_raw(&repo, sha).unwrap(), obj);
+        }
+        for (sha, info) in object_iter_packed(&repo).unwrap() {
+            let (fmt, data) = &loose[shas.binary_search(&sha).unwrap()];
+            let info = info.unwrap();
+            assert_eq!((info.fmt, info.size), (fmt.as_slice(), data.len()));
+        }
+        assert!(object_read_raw(&repo, &"0".repeat(40)).is_err());
+        let stats = pack_files(&repo).unwrap()[0].verify_objects(&repo).unwrap();
+        assert_eq!(stats.len(), shas.len());
+        assert!(stats.iter().all(|stat| stat.delta.is_none()));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
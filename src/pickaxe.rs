@@ -0,0 +1,118 @@
+use anyhow::{anyhow, Result};
+use regex::bytes::Regex;
+
+use crate::{
+    blob_content, diff_trees, is_binary, myers_diff, object_read, split_lines, Commit, DiffOp,
+    Repository, TreeLeaf,
+};
+
+/// A `log` content filter.
+pub enum Pickaxe {
+    /// `-S`: the number of occurrences of the string changed.
+    Count(String),
+    /// `-G`: an added or removed line matches the regex.
+    Regex(Regex),
+}
+
+/// Whether the change `sha` made relative to its first parent matches
+/// `pickaxe`. Like git without `-m`, merge commits never match.
+pub fn pickaxe_matches(repo: &Repository, sha: &str, pickaxe: &Pickaxe) -> Result<bool> {
+    let obj = object_read(repo, sha)?;
+    let commit = obj
+        .as_any()
+        .downcast_ref::<Commit>()
+        .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+    let parents = commit.parents();
+    if parents.len() > 1 {
+        return Ok(false);
+    }
+    let parent_tree = match parents.first() {
+        Some(parent) => {
+            let obj = object_read(repo, parent)?;
+            let parent = obj
+                .as_any()
+                .downcast_ref::<Commit>()
+                .ok_or_else(|| anyhow!("{} is not a commit", parent))?;
+            parent.tree()
+        }
+        None => None,
+    };
+
+    let is_blob = |l: &&TreeLeaf| !l.mode.starts_with(b"16");
+    for change in diff_trees(repo, parent_tree.as_deref(), commit.tree().as_deref())? {
+        let old = blob_content(repo, change.old.as_ref().filter(is_blob))?;
+        let new = blob_content(repo, change.new.as_ref().filter(is_blob))?;
+        // The first matching file decides; the rest need not be read.
+        if pickaxe_file_matches(&old, &new, pickaxe) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn pickaxe_file_matches(old: &[u8], new: &[u8], pickaxe: &Pickaxe) -> bool {
+    match pickaxe {
+        Pickaxe::Count(needle) => {
+            count_occurrences(old, needle.as_bytes()) != count_occurrences(new, needle.as_bytes())
+        }
+        Pickaxe::Regex(re) => {
+            if is_binary(old) || is_binary(new) {
+                return false;
+            }
+            let (a, b) = (split_lines(old), split_lines(new));
+            let line_matches = |l: &[u8]| re.is_match(l.strip_suffix(b"\n").unwrap_or(l));
+            // Scanning for a matching line is much cheaper than the diff,
+            // which is only computed when some line could match.
+            if !a.iter().chain(&b).any(|l| line_matches(l)) {
+                return false;
+            }
+            myers_diff(&a, &b).into_iter().any(|op| match op {
+                DiffOp::Delete(i) => line_matches(a[i]),
+                DiffOp::Insert(j) => line_matches(b[j]),
+                DiffOp::Equal(..) => false,
+            })
+        }
+    }
+}
+
+/// Counts the non-overlapping occurrences of `needle` in `haystack`.
+fn count_occurrences(haystack: &[u8], needle: &[u8]) -> usize {
+    if needle.is_empty() {
+        return 0;
+    }
+    let mut count = 0;
+    let mut i = 0;
+    while i + needle.len() <= haystack.len() {
+        if &haystack[i..i + needle.len()] == needle {
+            count += 1;
+            i += needle.len();
+        } else {
+            i += 1;
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::bytes::Regex;
+
+    use super::{pickaxe_file_matches, Pickaxe};
+
+    #[test]
+    fn test_pickaxe_file_matches() {
+        let old = b"fn foo() {}\nfn bar() {}\n";
+        let moved = b"fn bar() {}\nfn foo() {}\n";
+        let count = Pickaxe::Count("foo".to_string());
+        // Moving a line keeps the count, but shows up as a changed line.
+        assert!(!pickaxe_file_matches(old, moved, &count));
+        assert!(pickaxe_file_matches(old, b"fn bar() {}\n", &count));
+        let regex = Pickaxe::Regex(Regex::new("fo+").unwrap());
+        assert!(pickaxe_file_matches(old, moved, &regex));
+        assert!(!pickaxe_file_matches(
+            old,
+            b"fn foo() {}\nfn baz() {}\n",
+            &regex
+        ));
+    }
+}
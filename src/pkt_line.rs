@@ -0,0 +1,85 @@
+use anyhow::{anyhow, bail, Result};
+
+/// Largest payload of a single pkt-line (65520 bytes minus the length).
+pub const PKT_MAX_DATA: usize = 65516;
+
+/// One packet of the pkt-line framing used by git's wire protocols.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Pkt<'a> {
+    /// The `0000` packet that ends a section.
+    Flush,
+    Data(&'a [u8]),
+}
+
+/// Appends `data` framed as a pkt-line.
+pub fn pkt_write(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(format!("{:04x}", data.len() + 4).as_bytes());
+    out.extend_from_slice(data);
+}
+
+pub fn pkt_flush(out: &mut Vec<u8>) {
+    out.extend_from_slice(b"0000");
+}
+
+/// Appends `data` on side-band channel `band` (1 for data, 2 for progress,
+/// 3 for errors), split into as many packets as it takes.
+pub fn pkt_write_sideband(out: &mut Vec<u8>, band: u8, data: &[u8]) {
+    for chunk in data.chunks(PKT_MAX_DATA - 1) {
+        let mut pkt = Vec::with_capacity(chunk.len() + 1);
+        pkt.push(band);
+        pkt.extend_from_slice(chunk);
+        pkt_write(out, &pkt);
+    }
+}
+
+/// Reads pkt-lines out of a buffer.
+pub struct PktReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PktReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Returns the next packet, or None at the end of the buffer.
+    pub fn read(&mut self) -> Result<Option<Pkt<'a>>> {
+        if self.pos == self.data.len() {
+            return Ok(None);
+        }
+        let len_hex = self
+            .data
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| anyhow!("Truncated pkt-line"))?;
+        let len = usize::from_str_radix(std::str::from_utf8(len_hex)?, 16)
+            .map_err(|_| anyhow!("Bad pkt-line length {:?}", String::from_utf8_lossy(len_hex)))?;
+        if len == 0 {
+            self.pos += 4;
+            return Ok(Some(Pkt::Flush));
+        }
+        if len < 4 {
+            bail!("Bad pkt-line length {}", len);
+        }
+        let data = self
+            .data
+            .get(self.pos + 4..self.pos + len)
+            .ok_or_else(|| anyhow!("Truncated pkt-line"))?;
+        self.pos += len;
+        Ok(Some(Pkt::Data(data)))
+    }
+
+    /// Reads a data packet as text, without its trailing newline. Returns
+    /// None on a flush or at the end of the buffer.
+    pub fn read_line(&mut self) -> Result<Option<&'a [u8]>> {
+        match self.read()? {
+            Some(Pkt::Data(data)) => Ok(Some(data.strip_suffix(b"\n").unwrap_or(data))),
+            _ => Ok(None),
+        }
+    }
+
+    /// The bytes after the last packet read, e.g. a packfile.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use indexmap::IndexMap;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Time spent in each phase and how often it was entered, in the order the
+/// phases first ran, plus the phases currently running.
+#[derive(Default)]
+struct Profile {
+    started: Option<Instant>,
+    phases: IndexMap<&'static str, (Duration, usize)>,
+    /// The running phases, innermost last, with the time their children
+    /// took so far.
+    stack: Vec<(&'static str, Instant, Duration)>,
+}
+
+thread_local! {
+    static PROFILE: RefCell<Profile> = RefCell::new(Profile::default());
+}
+
+/// Starts collecting the timings `--profile` prints.
+pub fn profile_enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+    PROFILE.with(|p| p.borrow_mut().started = Some(Instant::now()));
+}
+
+/// Marks the rest of the caller's scope as `phase`. Time spent in phases
+/// entered from inside counts for those, not for this one. Does nothing
+/// unless profiling is enabled.
+pub fn profile_phase(phase: &'static str) -> ProfilePhase {
+    let enabled = ENABLED.load(Ordering::Relaxed);
+    if enabled {
+        PROFILE.with(|p| {
+            p.borrow_mut()
+                .stack
+                .push((phase, Instant::now(), Duration::ZERO))
+        });
+    }
+    ProfilePhase { enabled }
+}
+
+pub struct ProfilePhase {
+    enabled: bool,
+}
+
+impl Drop for ProfilePhase {
+    fn drop(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        PROFILE.with(|p| {
+            let mut p = p.borrow_mut();
+            let Some((phase, start, children)) = p.stack.pop() else {
+                return;
+            };
+            let elapsed = start.elapsed();
+            if let Some(parent) = p.stack.last_mut() {
+                parent.2 += elapsed;
+            }
+            let entry = p.phases.entry(phase).or_default();
+            entry.0 += elapsed.saturating_sub(children);
+            entry.1 += 1;
+        });
+    }
+}
+
+/// Prints the time each phase took to stderr. What no phase accounts for,
+/// including writing the output, is shown as "other".
+pub fn profile_report() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    PROFILE.with(|p| {
+        let p = p.borrow();
+        let total = p.started.map(|s| s.elapsed()).unwrap_or_default();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        eprintln!("profile: {:.3} ms total", ms(total));
+        let mut accounted = Duration::ZERO;
+        for (phase, (time, calls)) in &p.phases {
+            accounted += *time;
+            eprintln!(
+                "  {:<16} {:>10.3} ms {:>8} call{}",
+                phase,
+                ms(*time),
+                calls,
+                if *calls == 1 { "" } else { "s" }
+            );
+        }
+        eprintln!(
+            "  {:<16} {:>10.3} ms",
+            "other",
+            ms(total.saturating_sub(accounted))
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread::sleep, time::Duration};
+
+    use super::{profile_enable, profile_phase, PROFILE};
+
+    #[test]
+    fn test_profile_self_time() {
+        profile_enable();
+        {
+            let _outer = profile_phase("outer");
+            sleep(Duration::from_millis(5));
+            let _inner = profile_phase("inner");
+            sleep(Duration::from_millis(20));
+        }
+        PROFILE.with(|p| {
+            let p = p.borrow();
+            let (outer, inner) = (p.phases["outer"], p.phases["inner"]);
+            assert_eq!((outer.1, inner.1), (1, 1));
+            assert!(inner.0 >= Duration::from_millis(20));
+            assert!(outer.0 < Duration::from_millis(20));
+            assert!(p.stack.is_empty());
+        });
+    }
+}
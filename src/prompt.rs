@@ -0,0 +1,121 @@
+use std::{
+    io::{self, BufRead, IsTerminal, Write},
+    process::Command,
+};
+
+use anyhow::{bail, Result};
+
+use crate::config_bool_parse;
+
+/// Whether the user can be asked anything: stdin is a terminal and
+/// GIT_TERMINAL_PROMPT doesn't forbid it.
+pub fn prompt_available() -> bool {
+    prompt_check("").is_ok()
+}
+
+/// Fails the way git does when `prompt` can't be put to the user, rather
+/// than reading a script's input or waiting forever.
+fn prompt_check(prompt: &str) -> Result<()> {
+    let setting = std::env::var("GIT_TERMINAL_PROMPT").ok();
+    prompt_allowed(prompt, setting.as_deref(), io::stdin().is_terminal())
+}
+
+/// `prompt_check` given the value of GIT_TERMINAL_PROMPT and whether stdin
+/// is a terminal.
+fn prompt_allowed(prompt: &str, setting: Option<&str>, terminal: bool) -> Result<()> {
+    let what = prompt.trim_end_matches([' ', ':', '?', '>']);
+    if setting.is_some_and(|value| config_bool_parse(value) == Some(false)) {
+        bail!("could not read {}: terminal prompts disabled", what);
+    }
+    if !terminal {
+        bail!("could not read {}: stdin is not a terminal", what);
+    }
+    Ok(())
+}
+
+/// Prints `prompt` and reads one line of input, trimmed. Returns None at
+/// the end of input, which interactive commands treat as quitting.
+pub fn prompt_line(prompt: &str) -> Result<Option<String>> {
+    prompt_check(prompt)?;
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        println!();
+        return Ok(None);
+    }
+    Ok(Some(line.trim().to_string()))
+}
+
+/// Asks a yes/no question until it gets an answer. An empty one, or the
+/// end of input, is `default`.
+pub fn prompt_yes_no(prompt: &str, default: bool) -> Result<bool> {
+    loop {
+        let Some(answer) = prompt_line(prompt)? else {
+            return Ok(default);
+        };
+        match answer.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+/// Asks until the first letter of the answer is one of `choices`. Returns
+/// None at the end of input.
+pub fn prompt_choice(prompt: &str, choices: &str) -> Result<Option<char>> {
+    loop {
+        let Some(answer) = prompt_line(prompt)? else {
+            return Ok(None);
+        };
+        match answer.chars().next() {
+            Some(c) if choices.contains(c) => return Ok(Some(c)),
+            _ => {}
+        }
+    }
+}
+
+/// Reads a user name or, `hidden`, a password without echoing it. The
+/// prompt goes to stderr, so that it shows even when stdout is redirected.
+pub fn prompt_credential(prompt: &str, hidden: bool) -> Result<String> {
+    prompt_check(prompt)?;
+    eprint!("{}", prompt);
+    io::stderr().flush()?;
+    let stty = |arg: &str| Command::new("stty").arg(arg).status();
+    if hidden {
+        stty("-echo")?;
+    }
+    let mut line = String::new();
+    let read = io::stdin().lock().read_line(&mut line);
+    if hidden {
+        stty("echo")?;
+        eprintln!();
+    }
+    if read? == 0 {
+        bail!("could not read {}", prompt.trim_end_matches([' ', ':']));
+    }
+    Ok(line.trim_end_matches(['\n', '\r']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prompt_allowed;
+
+    #[test]
+    fn test_prompt_disabled() {
+        let prompt = "Username for 'http://host': ";
+        let err = prompt_allowed(prompt, Some("0"), true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "could not read Username for 'http://host': terminal prompts disabled"
+        );
+        let err = prompt_allowed(prompt, Some("1"), false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "could not read Username for 'http://host': stdin is not a terminal"
+        );
+        assert!(prompt_allowed(prompt, None, true).is_ok());
+    }
+}
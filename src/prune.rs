@@ -0,0 +1,31 @@
+use std::{path::Path, time::SystemTime};
+
+use anyhow::Result;
+
+use crate::{expiry_parse, prune_unreachable, repo_find};
+
+/// Deletes the loose objects that no ref, reflog entry, pseudo-ref or index
+/// entry leads to. Without `expire` every such object goes; with it only
+/// those last modified before the cutoff. `dry_run` only lists them, as
+/// `verbose` does for what is deleted.
+pub fn prune(dry_run: bool, verbose: bool, expire: Option<&str>) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let now = SystemTime::now();
+    let expire = match expire {
+        Some(value) => match expiry_parse(value, now)? {
+            Some(expire) => expire,
+            None => return Ok(()),
+        },
+        None => now,
+    };
+    for (sha, info) in prune_unreachable(&repo, expire, dry_run)? {
+        if dry_run || verbose {
+            let typ = match info {
+                Ok(info) => String::from_utf8_lossy(info.fmt).to_string(),
+                Err(_) => "unknown".to_string(),
+            };
+            println!("{} {}", sha, typ);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_get_active, config_bool, fetch_remote, merge_into_head, rebase_start, ref_resolve,
+    repo_file, repo_find, PickDates, Repository,
+};
+
+/// Fetches the current branch's upstream and merges it in, or rebases onto
+/// it with `rebase` (or `branch.<name>.rebase` / `pull.rebase`). With a
+/// `remote`, a configured one, a URL or "." for this repository, the
+/// `branches` given are pulled from it instead.
+pub fn pull(remote: Option<&str>, branches: &[String], rebase: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    pull_into(&repo, remote, branches, rebase)
+}
+
+fn pull_into(
+    repo: &Repository,
+    remote: Option<&str>,
+    branches: &[String],
+    rebase: bool,
+) -> Result<()> {
+    let branch =
+        branch_get_active(repo)?.ok_or_else(|| anyhow!("You are not currently on a branch."))?;
+    let section = format!("branch \"{}\"", branch);
+    if branches.len() > 1 {
+        bail!("Pulling several branches at once is not supported.");
+    }
+    let rebase = rebase
+        || repo
+            .config_get(&section, "rebase")
+            .or_else(|| repo.config_get("pull", "rebase"))
+            .is_some_and(|v| config_bool(&v));
+
+    let upstream = (
+        repo.config_get(&section, "remote"),
+        repo.config_get(&section, "merge"),
+    );
+    let (remote, merge) = match (remote, branches.first(), upstream) {
+        (Some(remote), Some(branch), _) => (remote.to_string(), branch.clone()),
+        (None, _, (Some(remote), Some(merge))) => (remote, merge),
+        (Some(remote), None, (Some(upstream), Some(merge))) if upstream == remote => {
+            (upstream, merge)
+        }
+        (Some(remote), None, _) => bail!(
+            "You asked to pull from the remote '{}', but did not specify\n\
+             a branch. Because this is not the default configured remote\n\
+             for your current branch, you must specify a branch on the command line.",
+            remote
+        ),
+        (None, _, _) => bail!(
+            "There is no tracking information for the current branch.\n\
+             Please specify which branch you want to merge with."
+        ),
+    };
+
+    // A branch of this repository needs no fetching.
+    let (sha, what) = if remote == "." {
+        let name = match merge.starts_with("refs/") {
+            true => merge.clone(),
+            false => format!("refs/heads/{}", merge),
+        };
+        let sha = ref_resolve(repo, &name)?.ok_or_else(|| anyhow!("no such branch '{}'", merge))?;
+        (
+            sha,
+            format!("branch '{}'", name.trim_start_matches("refs/heads/")),
+        )
+    } else {
+        let refspecs = match branches {
+            [] => Vec::new(),
+            _ => vec![merge.clone()],
+        };
+        fetch_remote(repo, &remote, &refspecs)?;
+        fetch_head_merge(repo)?.ok_or_else(|| {
+            anyhow!(
+                "Your configuration specifies to merge with the ref '{}'\n\
+                 from the remote, but no such ref was fetched.",
+                merge
+            )
+        })?
+    };
+
+    if rebase {
+        return rebase_start(repo, &sha, PickDates::Keep, false);
+    }
+    let message = format!("Merge {}\n", what);
+    merge_into_head(repo, &sha, &sha, &message, "pull")
+}
+
+/// The first ref FETCH_HEAD marks for merging, with the description of it
+/// that goes into the merge message.
+fn fetch_head_merge(repo: &Repository) -> Result<Option<(String, String)>> {
+    let fetch_head = fs::read_to_string(repo_file(repo, PathBuf::from("FETCH_HEAD"), false)?)?;
+    Ok(fetch_head.lines().find_map(|line| {
+        let mut fields = line.splitn(3, '\t');
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(sha), Some(""), Some(what)) => Some((sha.to_string(), what.to_string())),
+            _ => None,
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::pull_into;
+    use crate::{
+        checkout_tree, ref_create, ref_resolve,
+        test_utils::{make_commit, test_repo},
+    };
+
+    #[test]
+    fn test_pull_without_upstream() {
+        let source = test_repo("pull-source");
+        let first = make_commit(&source, "one", &[]);
+        let second = make_commit(&source, "two", &[&first]);
+        ref_create(&source, "heads/master", &second).unwrap();
+
+        let dest = test_repo("pull-dest");
+        make_commit(&dest, "one", &[]);
+        ref_create(&dest, "heads/master", &first).unwrap();
+        checkout_tree(&dest, &first, true).unwrap();
+
+        let url = source.worktree.display().to_string();
+        assert!(pull_into(&dest, None, &[], false).is_err());
+        assert!(pull_into(&dest, Some(&url), &[], false).is_err());
+        pull_into(&dest, Some(&url), &["master".to_string()], false).unwrap();
+        assert_eq!(ref_resolve(&dest, "HEAD").unwrap(), Some(second));
+        assert_eq!(
+            fs::read_to_string(dest.worktree.join("file")).unwrap(),
+            "two"
+        );
+    }
+}
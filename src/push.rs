@@ -0,0 +1,468 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_get_active, file_write_locked, object_find, object_read, pack_write, pkt_flush,
+    pkt_write, prompt_available, prompt_yes_no, reachable_objects, ref_delete, ref_list_flat,
+    ref_resolve, reflog_append, remote_default, repo_file, repo_find, sha_short, url_rewrite,
+    Ancestry, PktReader, Refspec, RemoteRefs, Repository, Transport,
+};
+
+/// One ref a push wants to change on the remote.
+struct PushUpdate {
+    /// The local ref or object pushed, empty for a deletion.
+    src: String,
+    /// The remote ref it goes to.
+    dst: String,
+    old: Option<String>,
+    new: Option<String>,
+    force: bool,
+    /// Whether it rewinds the remote ref, which only force allows.
+    forced: bool,
+    /// Why the update is not sent, or why the remote refused it.
+    rejected: Option<String>,
+}
+
+/// Pushes `refspecs` to `remote`, by default the current branch to the
+/// branch of the same name on its upstream remote.
+pub fn push(remote: Option<&str>, refspecs: &[String], force: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let remote = match remote {
+        Some(remote) => remote.to_string(),
+        None => remote_default(&repo)?,
+    };
+    push_remote(&repo, &remote, refspecs, force)
+}
+
+/// Pushes to `remote`, either the name of a configured remote or a URL,
+/// and moves the remote-tracking refs of what it updated.
+pub fn push_remote(
+    repo: &Repository,
+    remote: &str,
+    refspecs: &[String],
+    force: bool,
+) -> Result<()> {
+    let section = format!("remote \"{}\"", remote);
+    // pushInsteadOf only rewrites URLs that aren't explicitly for pushing.
+    let url = match repo.config_get(&section, "pushurl") {
+        Some(url) => url_rewrite(Some(repo), &url, false),
+        None => {
+            let url = repo
+                .config_get(&section, "url")
+                .or_else(|| {
+                    (remote.contains(['/', ':']) || Path::new(remote).exists())
+                        .then(|| remote.to_string())
+                })
+                .ok_or_else(|| {
+                    anyhow!(
+                        "'{}' does not appear to be a git repository\n\
+                         Could not read from remote repository.",
+                        remote
+                    )
+                })?;
+            url_rewrite(Some(repo), &url, true)
+        }
+    };
+
+    let mut specs = refspecs
+        .iter()
+        .map(|s| Refspec::parse(s))
+        .collect::<Result<Vec<_>>>()?;
+    if specs.is_empty() {
+        specs = repo
+            .config_get_all(&section, "push")
+            .iter()
+            .map(|s| Refspec::parse(s))
+            .collect::<Result<Vec<_>>>()?;
+    }
+    if specs.is_empty() {
+        let branch = branch_get_active(repo)?.ok_or_else(|| {
+            anyhow!(
+                "You are not currently on a branch.\n\
+                 To push the history leading to the current (detached HEAD)\n\
+                 state now, use\n\n    rit push {} HEAD:<name-of-remote-branch>",
+                remote
+            )
+        })?;
+        specs.push(Refspec::parse(&format!("refs/heads/{}", branch))?);
+    }
+
+    let mut transport = Transport::open(&url, Some(repo))?;
+    let advertised = transport.advertise("git-receive-pack")?;
+    let mut updates = push_select(repo, &advertised, &specs, force)?;
+    push_check(repo, &mut updates)?;
+
+    let sending: Vec<&PushUpdate> = updates
+        .iter()
+        .filter(|u| u.rejected.is_none() && u.old != u.new)
+        .collect();
+    if updates.iter().all(|u| u.old == u.new) {
+        eprintln!("Everything up-to-date");
+        return Ok(());
+    }
+    // --force already says yes; someone at a terminal is asked once more
+    // before history on the remote is thrown away.
+    let rewinding: Vec<&str> = sending
+        .iter()
+        .filter(|u| u.forced)
+        .map(|u| u.dst.as_str())
+        .collect();
+    if !rewinding.is_empty() && prompt_available() {
+        let question = format!(
+            "Force-push rewinds {} on '{}'. Continue? [y/N] ",
+            rewinding.join(", "),
+            url
+        );
+        if !prompt_yes_no(&question, false)? {
+            bail!("push aborted");
+        }
+    }
+    if !sending.is_empty() {
+        if sending.iter().any(|u| u.new.is_none()) && !advertised.has_cap("delete-refs") {
+            bail!("the receiving end does not support deleting refs");
+        }
+        let request = push_request(repo, &advertised, &sending)?;
+        let reply = transport.request("git-receive-pack", &request)?;
+        let report = push_report_parse(&reply)?;
+        for update in updates.iter_mut() {
+            if let Some((_, reason)) = report.iter().find(|(name, _)| *name == update.dst) {
+                update.rejected = reason.as_ref().map(|r| format!("remote:{}", r));
+            }
+        }
+    }
+
+    push_tracking_update(repo, remote, &updates)?;
+    push_report_print(repo, &url, &updates);
+    if updates.iter().any(|u| u.rejected.is_some()) {
+        bail!("failed to push some refs to '{}'", url);
+    }
+    Ok(())
+}
+
+/// Expands `specs` into the updates they ask for. A source without a
+/// destination goes to the ref of the same name.
+fn push_select(
+    repo: &Repository,
+    advertised: &RemoteRefs,
+    specs: &[Refspec],
+    force: bool,
+) -> Result<Vec<PushUpdate>> {
+    let mut updates = Vec::new();
+    let mut add = |src: String, dst: String, new: Option<String>, spec_force: bool| {
+        if !updates.iter().any(|u: &PushUpdate| u.dst == dst) {
+            updates.push(PushUpdate {
+                old: advertised.get(&dst).map(str::to_string),
+                src,
+                dst,
+                new,
+                force: force || spec_force,
+                forced: false,
+                rejected: None,
+            });
+        }
+    };
+    for spec in specs {
+        if spec.src.is_empty() {
+            let dst = spec.dst.as_deref().unwrap_or_default();
+            let dst = match dst.starts_with("refs/") {
+                true => dst.to_string(),
+                false => format!("refs/heads/{}", dst),
+            };
+            if advertised.get(&dst).is_none() {
+                bail!("unable to delete '{}': remote ref does not exist", dst);
+            }
+            add(String::new(), dst, None, spec.force);
+            continue;
+        }
+        if spec.src.contains('*') {
+            let local = ref_list_flat(repo, None, Some("refs"))?;
+            for (name, sha) in local {
+                if let Some(Some(dst)) = spec.map(&name) {
+                    add(name, dst, Some(sha), spec.force);
+                }
+            }
+            continue;
+        }
+        let src_ref = push_src_ref(repo, &spec.src)?;
+        let new = match &src_ref {
+            Some(name) => ref_resolve(repo, name)?,
+            None => object_find(repo, &spec.src, None, false).ok().flatten(),
+        }
+        .ok_or_else(|| anyhow!("src refspec {} does not match any", spec.src))?;
+        let dst = match (&spec.dst, &src_ref) {
+            (Some(dst), _) if dst.starts_with("refs/") => dst.clone(),
+            (Some(dst), Some(src)) if src.starts_with("refs/tags/") => {
+                format!("refs/tags/{}", dst)
+            }
+            (Some(dst), _) => format!("refs/heads/{}", dst),
+            (None, Some(src)) => src.clone(),
+            (None, None) => bail!(
+                "The destination you provided is not a full refname (i.e.,\n\
+                 starting with \"refs/\") and '{}' is not a ref to push.",
+                spec.src
+            ),
+        };
+        add(spec.src.clone(), dst, Some(new), spec.force);
+    }
+    Ok(updates)
+}
+
+/// The full name of the local ref `name` stands for, if it is one.
+fn push_src_ref(repo: &Repository, name: &str) -> Result<Option<String>> {
+    if name == "HEAD" {
+        return Ok(branch_get_active(repo)?.map(|b| format!("refs/heads/{}", b)));
+    }
+    for candidate in [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+    ] {
+        if candidate.starts_with("refs/") && ref_resolve(repo, &candidate)?.is_some() {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Rejects the updates the remote would refuse anyway: replacing what we
+/// don't have, rewinding without force, and moving existing tags.
+fn push_check(repo: &Repository, updates: &mut [PushUpdate]) -> Result<()> {
+    let mut ancestry = Ancestry::new(repo)?;
+    for update in updates.iter_mut() {
+        let (Some(old), Some(new)) = (&update.old, &update.new) else {
+            continue;
+        };
+        if old == new {
+            continue;
+        }
+        let reason = if update.dst.starts_with("refs/tags/") {
+            Some("already exists")
+        } else if object_read(repo, old).is_err() {
+            Some("fetch first")
+        } else if !ancestry.is_ancestor(old, new)? {
+            Some("non-fast-forward")
+        } else {
+            None
+        };
+        match update.force {
+            true => update.forced = reason.is_some(),
+            false => update.rejected = reason.map(str::to_string),
+        }
+    }
+    Ok(())
+}
+
+/// The receive-pack request: one command per update, then a pack of what
+/// the remote lacks.
+fn push_request(
+    repo: &Repository,
+    advertised: &RemoteRefs,
+    updates: &[&PushUpdate],
+) -> Result<Vec<u8>> {
+    let zero = "0".repeat(40);
+    let mut out = Vec::new();
+    for (i, update) in updates.iter().enumerate() {
+        let mut line = format!(
+            "{} {} {}",
+            update.old.as_deref().unwrap_or(&zero),
+            update.new.as_deref().unwrap_or(&zero),
+            update.dst
+        );
+        if i == 0 {
+            line.push_str(&format!(
+                "\0report-status agent=rit/{}",
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        pkt_write(&mut out, format!("{}\n", line).as_bytes());
+    }
+    pkt_flush(&mut out);
+
+    let new: Vec<String> = updates.iter().filter_map(|u| u.new.clone()).collect();
+    if new.is_empty() {
+        return Ok(out);
+    }
+    // What the remote has that we know of needn't be sent again.
+    let theirs: Vec<String> = advertised
+        .refs
+        .iter()
+        .map(|(_, sha)| sha.clone())
+        .filter(|sha| object_read(repo, sha).is_ok())
+        .collect();
+    let known: HashSet<String> = reachable_objects(repo, &theirs)?;
+    let mut send: Vec<String> = reachable_objects(repo, &new)?
+        .into_iter()
+        .filter(|sha| !known.contains(sha))
+        .collect();
+    send.sort();
+    out.extend(pack_write(repo, &send)?);
+    Ok(out)
+}
+
+/// Reads a report-status reply: each ref with the remote's reason for
+/// refusing it, if it did.
+fn push_report_parse(reply: &[u8]) -> Result<Vec<(String, Option<String>)>> {
+    let mut reader = PktReader::new(reply);
+    let unpack = reader
+        .read_line()?
+        .ok_or_else(|| anyhow!("the remote end hung up unexpectedly"))?;
+    let unpack = String::from_utf8_lossy(unpack).to_string();
+    let unpack_error = unpack
+        .strip_prefix("unpack ")
+        .filter(|status| *status != "ok")
+        .map(str::to_string);
+    let mut report = Vec::new();
+    while let Some(line) = reader.read_line()? {
+        let line = String::from_utf8_lossy(line);
+        if let Some(name) = line.strip_prefix("ok ") {
+            report.push((name.to_string(), None));
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+            report.push((name.to_string(), Some(reason.to_string())));
+        }
+    }
+    if let Some(error) = unpack_error {
+        eprintln!("error: remote unpack failed: {}", error);
+    }
+    Ok(report)
+}
+
+/// Moves the remote-tracking refs of the pushed branches along, as a fetch
+/// would have.
+fn push_tracking_update(repo: &Repository, remote: &str, updates: &[PushUpdate]) -> Result<()> {
+    let specs = repo
+        .config_get_all(&format!("remote \"{}\"", remote), "fetch")
+        .iter()
+        .map(|s| Refspec::parse(s))
+        .collect::<Result<Vec<_>>>()?;
+    for update in updates {
+        if update.rejected.is_some() || update.old == update.new {
+            continue;
+        }
+        let Some(Some(local)) = specs.iter().find_map(|s| s.map(&update.dst)) else {
+            continue;
+        };
+        let path = repo_file(repo, PathBuf::from(&local), true)?;
+        let old = ref_resolve(repo, &local)?;
+        match &update.new {
+            Some(new) => {
+                file_write_locked(&path, format!("{}\n", new).as_bytes(), repo.fsync.refs)?;
+                reflog_append(repo, &local, old.as_deref(), new, "update by push")?;
+            }
+            None => ref_delete(repo, &local)?,
+        }
+    }
+    Ok(())
+}
+
+/// Reports each update as git does, under "To <url>".
+fn push_report_print(repo: &Repository, url: &str, updates: &[PushUpdate]) {
+    let short = |name: &str| {
+        ["refs/heads/", "refs/tags/"]
+            .iter()
+            .find_map(|p| name.strip_prefix(p))
+            .unwrap_or(name)
+            .to_string()
+    };
+    let abbrev = |sha: &str| sha_short(repo, sha);
+    eprintln!("To {}", url.trim_end_matches('/'));
+    for u in updates.iter().filter(|u| u.old != u.new) {
+        let what = match u.new {
+            Some(_) => format!("{} -> {}", short(&u.src), short(&u.dst)),
+            None => short(&u.dst),
+        };
+        let (flag, summary, suffix) = match (&u.rejected, &u.old, &u.new) {
+            (Some(reason), _, _) => match reason.strip_prefix("remote:") {
+                Some(reason) => (
+                    '!',
+                    "[remote rejected]".to_string(),
+                    format!(" ({})", reason),
+                ),
+                None => ('!', "[rejected]".to_string(), format!(" ({})", reason)),
+            },
+            (None, _, None) => ('-', "[deleted]".to_string(), String::new()),
+            (None, None, Some(_)) if u.dst.starts_with("refs/tags/") => {
+                ('*', "[new tag]".to_string(), String::new())
+            }
+            (None, None, Some(_)) if u.dst.starts_with("refs/heads/") => {
+                ('*', "[new branch]".to_string(), String::new())
+            }
+            (None, None, Some(_)) => ('*', "[new reference]".to_string(), String::new()),
+            (None, Some(old), Some(new)) if u.forced => (
+                '+',
+                format!("{}...{}", abbrev(old), abbrev(new)),
+                " (forced update)".to_string(),
+            ),
+            (None, Some(old), Some(new)) => (
+                ' ',
+                format!("{}..{}", abbrev(old), abbrev(new)),
+                String::new(),
+            ),
+        };
+        eprintln!(" {} {:<17} {}{}", flag, summary, what, suffix);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::push_remote;
+    use crate::{
+        ref_create, ref_resolve,
+        test_utils::{make_commit, pack_refs, test_repo},
+    };
+
+    #[test]
+    fn test_push() {
+        let source = test_repo("push-source");
+        let dest = test_repo("push-dest");
+        let url = dest.worktree.display().to_string();
+        let first = make_commit(&source, "one", &[]);
+        let second = make_commit(&source, "two", &[&first]);
+        ref_create(&source, "heads/topic", &second).unwrap();
+
+        push_remote(&source, &url, &["topic".to_string()], false).unwrap();
+        assert_eq!(
+            ref_resolve(&dest, "refs/heads/topic").unwrap(),
+            Some(second.clone())
+        );
+
+        // Rewinding needs force; deleting doesn't.
+        let spec = format!("{}:refs/heads/topic", first);
+        assert!(push_remote(&source, &url, std::slice::from_ref(&spec), false).is_err());
+        push_remote(&source, &url, &[spec], true).unwrap();
+        assert_eq!(ref_resolve(&dest, "refs/heads/topic").unwrap(), Some(first));
+        push_remote(&source, &url, &[":topic".to_string()], false).unwrap();
+        assert_eq!(ref_resolve(&dest, "refs/heads/topic").unwrap(), None);
+    }
+
+    #[test]
+    fn test_push_packed_refs() {
+        let source = test_repo("push-packed-source");
+        let dest = test_repo("push-packed-dest");
+        let url = dest.worktree.display().to_string();
+        let first = make_commit(&source, "one", &[]);
+        ref_create(&source, "heads/topic", &first).unwrap();
+        push_remote(&source, &url, &["topic".to_string()], false).unwrap();
+        pack_refs(&dest);
+
+        // The packed branch is seen, so a new root does not replace it.
+        let root = make_commit(&source, "root", &[]);
+        let spec = format!("{}:refs/heads/topic", root);
+        assert!(push_remote(&source, &url, std::slice::from_ref(&spec), false).is_err());
+        assert_eq!(
+            ref_resolve(&dest, "refs/heads/topic").unwrap(),
+            Some(first.clone())
+        );
+        push_remote(&source, &url, &[spec], true).unwrap();
+        assert_eq!(ref_resolve(&dest, "refs/heads/topic").unwrap(), Some(root));
+
+        // Deleting drops the packed entry along with the loose file.
+        push_remote(&source, &url, &[":topic".to_string()], false).unwrap();
+        assert_eq!(ref_resolve(&dest, "refs/heads/topic").unwrap(), None);
+    }
+}
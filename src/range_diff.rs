@@ -0,0 +1,357 @@
+use std::{io::Write, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    diff_map_tree, diff_maps, hunk_ranges, ident_parse, kvlm_get_str, myers_diff, object_read,
+    repo_find, rev_walk, sha_short, Commit, DiffMap, DiffOp, Repository,
+};
+
+/// What pairing two commits that don't correspond costs at most, as in git.
+const COST_MAX: i64 = 1 << 16;
+
+/// One commit of a range, with the text its patch is compared by.
+struct RangePatch {
+    sha: String,
+    subject: String,
+    /// Its author, message and diff, reworded as git's range-diff does so
+    /// that two versions of a patch compare well: no line numbers in hunk
+    /// headers, and a " ## <path> ##" line heading each file.
+    lines: Vec<String>,
+    /// Where the diff starts in `lines`.
+    diff_start: usize,
+    /// The commit on the other side that it corresponds to.
+    matching: Option<usize>,
+}
+
+impl RangePatch {
+    fn diff(&self) -> &[String] {
+        &self.lines[self.diff_start..]
+    }
+}
+
+/// Pairs up the commits of two versions of a series by how alike their
+/// patches are, and shows for each pair whether and how the patch
+/// changed, and which commits only one version has. `ranges` is
+/// `old-base..old-tip new-base..new-tip`, `old-tip...new-tip`, or
+/// `base old-tip new-tip`.
+pub fn range_diff(ranges: &[String], creation_factor: usize) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let (old, new) = match ranges {
+        [symmetric] if symmetric.contains("...") => {
+            let (a, b) = symmetric.split_once("...").unwrap();
+            (format!("{}..{}", b, a), format!("{}..{}", a, b))
+        }
+        [a, b] if a.contains("..") && b.contains("..") => (a.clone(), b.clone()),
+        [base, a, b] => (format!("{}..{}", base, a), format!("{}..{}", base, b)),
+        _ => bail!("need two commit ranges"),
+    };
+    let mut a = range_patches(&repo, &old)?;
+    let mut b = range_patches(&repo, &new)?;
+    range_match(&mut a, &mut b, creation_factor as i64);
+
+    let width = a.len().max(b.len()).to_string().len();
+    let mut out = String::new();
+    let mut shown = vec![false; a.len()];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && shown[i] {
+            i += 1;
+            continue;
+        }
+        // Commits dropped from the old series show where they were, once
+        // what came before them has been shown.
+        if i < a.len() && a[i].matching.is_none() {
+            range_pair_show(&repo, &mut out, width, Some((i, &a[i])), None);
+            i += 1;
+            continue;
+        }
+        while j < b.len() && b[j].matching.is_none() {
+            range_pair_show(&repo, &mut out, width, None, Some((j, &b[j])));
+            j += 1;
+        }
+        if j < b.len() {
+            let k = b[j].matching.unwrap();
+            range_pair_show(&repo, &mut out, width, Some((k, &a[k])), Some((j, &b[j])));
+            shown[k] = true;
+            j += 1;
+        }
+    }
+    std::io::stdout().write_all(out.as_bytes())?;
+    Ok(())
+}
+
+/// The non-merge commits of `range`, oldest first, with their patches.
+fn range_patches(repo: &Repository, range: &str) -> Result<Vec<RangePatch>> {
+    let mut ret = Vec::new();
+    for sha in rev_walk(repo, &[range.to_string()])?.into_iter().rev() {
+        let obj = object_read(repo, &sha)?;
+        let commit = obj
+            .as_any()
+            .downcast_ref::<Commit>()
+            .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+        let parents = commit.parents();
+        if parents.len() > 1 {
+            continue;
+        }
+
+        let mut lines = vec![" ## Metadata ##".to_string()];
+        let author = kvlm_get_str(&commit.kvlm, b"author").unwrap_or_default();
+        let who = ident_parse(&author).map_or(author.as_str(), |(who, _, _)| who);
+        lines.push(format!("Author: {}", who));
+        lines.push(String::new());
+        lines.push(" ## Commit message ##".to_string());
+        let message = commit.message();
+        for line in message.lines() {
+            lines.push(format!("    {}", line).trim_end().to_string());
+        }
+
+        let old = match parents.first() {
+            Some(parent) => diff_map_tree(repo, parent)?,
+            None => DiffMap::new(),
+        };
+        let diff = diff_maps(repo, &old, &diff_map_tree(repo, &sha)?, 3, false)?;
+        let mut diff_start = lines.len();
+        // The header of the file being read, until its content starts.
+        let mut header: Option<(String, String)> = None;
+        let mut old_mode = String::new();
+        for line in String::from_utf8_lossy(&diff).lines() {
+            if let Some(names) = line.strip_prefix("diff --git a/") {
+                range_file_header(&mut lines, &mut header);
+                lines.push(String::new());
+                if diff_start == lines.len() - 1 {
+                    diff_start = lines.len();
+                }
+                let path = &names[..names.len().saturating_sub(3) / 2];
+                header = Some((path.to_string(), String::new()));
+                continue;
+            }
+            if let Some((_, note)) = &mut header {
+                if line.starts_with("new file mode ") {
+                    *note = " (new)".to_string();
+                } else if line.starts_with("deleted file mode ") {
+                    *note = " (deleted)".to_string();
+                } else if let Some(mode) = line.strip_prefix("old mode ") {
+                    old_mode = mode.to_string();
+                } else if let Some(mode) = line.strip_prefix("new mode ") {
+                    *note = format!(" (mode change {} => {})", old_mode, mode);
+                }
+                if !(line.starts_with("@@") || line.starts_with("Binary files ")) {
+                    continue;
+                }
+                range_file_header(&mut lines, &mut header);
+            }
+            // Hunks are compared without their line numbers.
+            match line.strip_prefix("@@ ").and_then(|l| l.split_once(" @@")) {
+                Some((_, func)) => lines.push(format!("@@{}", func)),
+                None => lines.push(line.to_string()),
+            }
+        }
+        range_file_header(&mut lines, &mut header);
+
+        ret.push(RangePatch {
+            sha,
+            subject: message.lines().next().unwrap_or("").to_string(),
+            lines,
+            diff_start,
+            matching: None,
+        });
+    }
+    Ok(ret)
+}
+
+/// Adds the " ## <path> ##" line of the file whose header was read.
+fn range_file_header(lines: &mut Vec<String>, header: &mut Option<(String, String)>) {
+    if let Some((path, note)) = header.take() {
+        lines.push(format!(" ## {}{} ##", path, note));
+    }
+}
+
+/// The size of the change between two patches: the lines of their diff,
+/// context included, and a line for each hunk.
+fn range_diff_size(a: &[String], b: &[String]) -> i64 {
+    let hunks = hunk_ranges(&myers_diff(a, b), 3);
+    hunks.iter().map(|hunk| hunk.len() as i64 + 1).sum()
+}
+
+/// Pairs commits of `a` with those of `b`: first those whose diffs are the
+/// same, then the rest so that the total difference is least, where
+/// leaving a commit unpaired costs its own size scaled by
+/// `creation_factor` percent.
+fn range_match(a: &mut [RangePatch], b: &mut [RangePatch], creation_factor: i64) {
+    for (i, old) in a.iter_mut().enumerate() {
+        if let Some((j, new)) = b
+            .iter_mut()
+            .enumerate()
+            .find(|(_, new)| new.matching.is_none() && new.diff() == old.diff())
+        {
+            old.matching = Some(j);
+            new.matching = Some(i);
+        }
+    }
+
+    let n = a.len() + b.len();
+    let size = |p: &RangePatch| p.diff().len() as i64;
+    let mut cost = vec![vec![0; n]; n];
+    for (i, row) in cost.iter_mut().enumerate().take(a.len()) {
+        for (j, c) in row.iter_mut().enumerate().take(b.len()) {
+            *c = match (a[i].matching, b[j].matching) {
+                (Some(m), _) if m == j => 0,
+                (None, None) => range_diff_size(a[i].diff(), b[j].diff()),
+                _ => COST_MAX,
+            };
+        }
+        let unpaired = match a[i].matching {
+            None => size(&a[i]) * creation_factor / 100,
+            Some(_) => COST_MAX,
+        };
+        row[b.len()..].fill(unpaired);
+    }
+    for j in 0..b.len() {
+        let unpaired = match b[j].matching {
+            None => size(&b[j]) * creation_factor / 100,
+            Some(_) => COST_MAX,
+        };
+        for row in &mut cost[a.len()..] {
+            row[j] = unpaired;
+        }
+    }
+
+    for (i, j) in assignment(&cost).into_iter().enumerate() {
+        if i < a.len() && j < b.len() {
+            a[i].matching = Some(j);
+            b[j].matching = Some(i);
+        }
+    }
+}
+
+/// Solves the assignment problem for the square `cost` matrix with the
+/// Hungarian method: the column for each row, such that the total cost
+/// is least.
+fn assignment(cost: &[Vec<i64>]) -> Vec<usize> {
+    let n = cost.len();
+    let inf = i64::MAX / 2;
+    // Potentials of rows and columns, and the row each column is assigned,
+    // all counting from 1 so that 0 can stand for none.
+    let (mut u, mut v) = (vec![0; n + 1], vec![0; n + 1]);
+    let mut row_of = vec![0; n + 1];
+    let mut way = vec![0; n + 1];
+    for i in 1..=n {
+        row_of[0] = i;
+        let mut col = 0;
+        let mut min = vec![inf; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[col] = true;
+            let row = row_of[col];
+            let (mut delta, mut next) = (inf, 0);
+            for j in 1..=n {
+                if used[j] {
+                    continue;
+                }
+                let reduced = cost[row - 1][j - 1] - u[row] - v[j];
+                if reduced < min[j] {
+                    min[j] = reduced;
+                    way[j] = col;
+                }
+                if min[j] < delta {
+                    delta = min[j];
+                    next = j;
+                }
+            }
+            for j in 0..=n {
+                if used[j] {
+                    u[row_of[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min[j] -= delta;
+                }
+            }
+            col = next;
+            if row_of[col] == 0 {
+                break;
+            }
+        }
+        while col != 0 {
+            let prev = way[col];
+            row_of[col] = row_of[prev];
+            col = prev;
+        }
+    }
+    let mut ret = vec![0; n];
+    for j in 1..=n {
+        if row_of[j] > 0 {
+            ret[row_of[j] - 1] = j - 1;
+        }
+    }
+    ret
+}
+
+/// Shows one line of the output, "1:  abc1234 ! 1:  def5678 subject", and
+/// for a patch that changed, how it did.
+fn range_pair_show(
+    repo: &Repository,
+    out: &mut String,
+    width: usize,
+    a: Option<(usize, &RangePatch)>,
+    b: Option<(usize, &RangePatch)>,
+) {
+    let side = |patch: Option<(usize, &RangePatch)>| match patch {
+        Some((i, patch)) => format!("{:>w$}:  {}", i + 1, sha_short(repo, &patch.sha), w = width),
+        None => format!("{:>w$}:  -------", "-", w = width),
+    };
+    let status = match (a, b) {
+        (Some((_, a)), Some((_, b))) if a.lines == b.lines => '=',
+        (Some(_), Some(_)) => '!',
+        (Some(_), None) => '<',
+        _ => '>',
+    };
+    let subject = &a.or(b).unwrap().1.subject;
+    out.push_str(&format!("{} {} {} {}\n", side(a), status, side(b), subject));
+
+    let (Some((_, a)), Some((_, b)), '!') = (a, b, status) else {
+        return;
+    };
+    let ops = myers_diff(&a.lines, &b.lines);
+    for range in hunk_ranges(&ops, 3) {
+        let start = ops[..range.start]
+            .iter()
+            .filter(|op| !matches!(op, DiffOp::Insert(_)))
+            .count();
+        // Hunks are labelled with the section they are in.
+        let section = a.lines[..start].iter().rev().find_map(|line| {
+            let heading = line
+                .strip_prefix(" ## ")
+                .and_then(|l| l.strip_suffix(" ##"));
+            heading.or_else(|| {
+                let at = line.find("@@ ").filter(|&at| at <= 1)?;
+                Some(&line[at + 3..])
+            })
+        });
+        match section {
+            Some(section) => out.push_str(&format!("    @@ {}\n", section)),
+            None => out.push_str("    @@\n"),
+        }
+        for op in &ops[range] {
+            let (marker, line) = match *op {
+                DiffOp::Equal(i, _) => (' ', &a.lines[i]),
+                DiffOp::Delete(i) => ('-', &a.lines[i]),
+                DiffOp::Insert(j) => ('+', &b.lines[j]),
+            };
+            out.push_str(&format!("    {}{}\n", marker, line));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assignment;
+
+    #[test]
+    fn test_assignment() {
+        let cost = vec![vec![4, 1, 3], vec![2, 0, 5], vec![3, 2, 2]];
+        assert_eq!(assignment(&cost), vec![1, 0, 2]);
+        let cost = vec![vec![0, 9], vec![9, 0]];
+        assert_eq!(assignment(&cost), vec![0, 1]);
+    }
+}
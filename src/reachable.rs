@@ -0,0 +1,326 @@
+use std::{collections::HashSet, fs, path::PathBuf, time::SystemTime};
+
+use anyhow::{Context, Result};
+use walkdir::WalkDir;
+
+use crate::{
+    index_read, object_iter_loose, object_read, object_references, pack_object_info, ref_list_flat,
+    ref_resolve, repo_dir, repo_file, repo_open, worktrees_read, ObjectInfo, Repository,
+};
+
+/// Pseudo-refs that may hold the only reference to a commit while an
+/// operation is in progress or just after it finished.
+const PSEUDO_REFS: [&str; 5] = [
+    "ORIG_HEAD",
+    "MERGE_HEAD",
+    "CHERRY_PICK_HEAD",
+    "REVERT_HEAD",
+    "FETCH_HEAD",
+];
+
+/// Every object name that keeps objects alive: HEAD, all refs (including
+/// `refs/stash`), both sides of every reflog entry, the pseudo-refs left
+/// behind by merges and cherry-picks, and the blobs staged in the index.
+/// The HEAD and index of every linked worktree count too.
+pub fn gc_roots(repo: &Repository) -> Result<Vec<String>> {
+    let mut roots = Vec::new();
+
+    if let Some(sha) = ref_resolve(repo, "HEAD")? {
+        roots.push(sha);
+    }
+    if repo_dir(repo, PathBuf::from("refs"), false)?.is_some() {
+        roots.extend(ref_list_flat(repo, None, Some("refs"))?.into_values());
+    }
+
+    for name in PSEUDO_REFS {
+        let path = repo_file(repo, PathBuf::from(name), false)?;
+        if !path.is_file() {
+            continue;
+        }
+        // MERGE_HEAD and FETCH_HEAD may list several objects, one per line.
+        for line in fs::read_to_string(path)?.lines() {
+            if let Some(sha) = line.split_whitespace().next() {
+                if is_sha(sha) {
+                    roots.push(sha.to_string());
+                }
+            }
+        }
+    }
+
+    roots.extend(reflog_shas(repo)?);
+
+    for entry in index_read(repo)?.entries {
+        if entry.mode_type != 0b1110 {
+            roots.push(entry.sha);
+        }
+    }
+
+    for wt in worktrees_read(repo)? {
+        roots.extend(wt.head);
+        let Some(linked) = wt.admin.and_then(|_| repo_open(&wt.path).ok()) else {
+            continue;
+        };
+        for entry in index_read(&linked)?.entries {
+            if entry.mode_type != 0b1110 {
+                roots.push(entry.sha);
+            }
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Collects the old and new value of every entry in every reflog.
+fn reflog_shas(repo: &Repository) -> Result<Vec<String>> {
+    let mut ret = Vec::new();
+    let logs = match repo_dir(repo, PathBuf::from("logs"), false)? {
+        Some(p) => p,
+        None => return Ok(ret),
+    };
+
+    for entry in WalkDir::new(logs) {
+        let entry = entry?;
+        if !entry.path().is_file() {
+            continue;
+        }
+        for line in fs::read_to_string(entry.path())?.lines() {
+            for sha in line.split_whitespace().take(2) {
+                if is_sha(sha) && sha.bytes().any(|b| b != b'0') {
+                    ret.push(sha.to_string());
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+fn is_sha(s: &str) -> bool {
+    s.len() == 40 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Walks the object graph from `roots`, returning every object reached.
+/// Roots that do not exist, such as tips of another repository, are
+/// skipped. Any object reached that can't be read is an error, so that
+/// what it references is never taken for unreachable.
+pub fn reachable_objects(repo: &Repository, roots: &[String]) -> Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut stack: Vec<String> = Vec::new();
+    for root in roots {
+        if object_present(repo, root)? {
+            stack.push(root.clone());
+        }
+    }
+    while let Some(sha) = stack.pop() {
+        if seen.contains(&sha) {
+            continue;
+        }
+        let obj = object_read(repo, &sha).with_context(|| format!("unable to read {}", sha))?;
+        seen.insert(sha);
+        stack.extend(
+            object_references(obj.as_ref())
+                .into_iter()
+                .map(|(_, child)| child),
+        );
+    }
+    Ok(seen)
+}
+
+/// Whether `sha` is in the object store, loose or packed.
+fn object_present(repo: &Repository, sha: &str) -> Result<bool> {
+    if !is_sha(sha) {
+        return Ok(false);
+    }
+    let path = repo_file(
+        repo,
+        PathBuf::from("objects").join(&sha[0..2]).join(&sha[2..]),
+        false,
+    )?;
+    Ok(path.is_file() || pack_object_info(repo, sha)?.is_some())
+}
+
+/// Deletes unreachable loose objects last modified before `expire` and
+/// returns their names and types. Objects newer than that are kept, since a
+/// concurrent command may be about to reference them. With `dry_run`
+/// nothing is deleted.
+pub fn prune_unreachable(
+    repo: &Repository,
+    expire: SystemTime,
+    dry_run: bool,
+) -> Result<Vec<(String, Result<ObjectInfo>)>> {
+    let reachable = reachable_objects(repo, &gc_roots(repo)?)?;
+
+    let mut pruned = Vec::new();
+    for (sha, info) in object_iter_loose(repo)? {
+        if reachable.contains(&sha) {
+            continue;
+        }
+        let path = repo_file(
+            repo,
+            PathBuf::from("objects").join(&sha[0..2]).join(&sha[2..]),
+            false,
+        )?;
+        if fs::metadata(&path)?.modified()? >= expire {
+            continue;
+        }
+        if !dry_run {
+            fs::remove_file(&path)?;
+        }
+        pruned.push((sha, info));
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{
+        checkout_tree, index_read, index_write, object_iter_loose, object_list_loose, object_read,
+        object_write, object_write_raw, prune_unreachable, ref_create,
+        test_utils::{make_commit, test_repo},
+        Blob, Commit, Repository,
+    };
+
+    fn object_exists(repo: &Repository, sha: &str) -> bool {
+        repo.gitdir
+            .join("objects")
+            .join(&sha[0..2])
+            .join(&sha[2..])
+            .is_file()
+    }
+
+    /// Simulates `reset --hard HEAD~1` with a reflog recording the move.
+    fn reset_with_reflog(repo: &Repository, old: &str, new: &str) {
+        ref_create(repo, "heads/master", new).unwrap();
+        let log = repo.gitdir.join(PathBuf::from("logs/refs/heads/master"));
+        fs::create_dir_all(log.parent().unwrap()).unwrap();
+        fs::write(
+            log,
+            format!(
+                "{} {} Test <test@example.com> 1262304123 +0100\treset: moving to HEAD~1\n",
+                old, new
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_prune_keeps_commits_in_reflog() {
+        let repo = test_repo("prune-reflog");
+        let first = make_commit(&repo, "one", &[]);
+        let second = make_commit(&repo, "two", &[&first]);
+        reset_with_reflog(&repo, &second, &first);
+
+        let expire = SystemTime::now() + Duration::from_secs(60);
+        let pruned = prune_unreachable(&repo, expire, false).unwrap();
+        assert!(pruned.is_empty());
+        assert!(object_exists(&repo, &second));
+
+        // Once the reflog is gone nothing protects the old commit.
+        fs::remove_dir_all(repo.gitdir.join("logs")).unwrap();
+        let pruned = prune_unreachable(&repo, expire, true).unwrap();
+        assert!(pruned.iter().any(|(sha, _)| sha == &second));
+        assert!(object_exists(&repo, &second));
+        prune_unreachable(&repo, expire, false).unwrap();
+        assert!(!object_exists(&repo, &second));
+        assert!(object_exists(&repo, &first));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_prune_keeps_pseudo_refs_and_stash() {
+        let repo = test_repo("prune-pseudo");
+        let first = make_commit(&repo, "one", &[]);
+        let merged = make_commit(&repo, "merged", &[&first]);
+        let stashed = make_commit(&repo, "stashed", &[&first]);
+        ref_create(&repo, "heads/master", &first).unwrap();
+        fs::write(repo.gitdir.join("MERGE_HEAD"), format!("{}\n", merged)).unwrap();
+        ref_create(&repo, "stash", &stashed).unwrap();
+
+        let expire = SystemTime::now() + Duration::from_secs(60);
+        prune_unreachable(&repo, expire, false).unwrap();
+        assert!(object_exists(&repo, &merged));
+        assert!(object_exists(&repo, &stashed));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_prune_refuses_broken_links() {
+        let repo = test_repo("prune-broken");
+        let first = make_commit(&repo, "one", &[]);
+        ref_create(&repo, "heads/master", &first).unwrap();
+        let commit = object_read(&repo, &first).unwrap();
+        let tree = commit
+            .as_any()
+            .downcast_ref::<Commit>()
+            .unwrap()
+            .tree()
+            .unwrap();
+        let tree_path = repo
+            .gitdir
+            .join("objects")
+            .join(&tree[0..2])
+            .join(&tree[2..]);
+        let data = fs::read(&tree_path).unwrap();
+        fs::write(&tree_path, &data[..data.len() / 2]).unwrap();
+
+        // The blob the tree holds is still referenced; nothing may go.
+        let loose = object_iter_loose(&repo).unwrap().count();
+        let expire = SystemTime::now() + Duration::from_secs(60);
+        assert!(prune_unreachable(&repo, expire, false).is_err());
+        assert_eq!(object_iter_loose(&repo).unwrap().count(), loose);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_prune_respects_expiry() {
+        let repo = test_repo("prune-expiry");
+        let first = make_commit(&repo, "one", &[]);
+        let second = make_commit(&repo, "two", &[&first]);
+        ref_create(&repo, "heads/master", &first).unwrap();
+
+        let expire = SystemTime::now() - Duration::from_secs(3600);
+        assert!(prune_unreachable(&repo, expire, false).unwrap().is_empty());
+        assert!(object_exists(&repo, &second));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_prune_keeps_reachable() {
+        let repo = test_repo("prune-reachable");
+        let first = make_commit(&repo, "one", &[]);
+        let second = make_commit(&repo, "two", &[&first]);
+        checkout_tree(&repo, &second, true).unwrap();
+        ref_create(&repo, "heads/master", &second).unwrap();
+        // A commit only an annotated tag leads to.
+        let tagged = make_commit(&repo, "tagged", &[&first]);
+        let tag = format!(
+            "object {}\ntype commit\ntag v1\ntagger Test <test@example.com> 1262304123 +0000\n\nv1\n",
+            tagged
+        );
+        let tag = object_write_raw(b"tag", tag.as_bytes(), Some(&repo)).unwrap();
+        fs::create_dir_all(repo.gitdir.join("refs/tags")).unwrap();
+        ref_create(&repo, "tags/v1", &tag).unwrap();
+        // A blob only the index holds.
+        let mut index = index_read(&repo).unwrap();
+        let mut staged = index.entries[0].clone();
+        staged.name = "staged".to_string();
+        staged.sha = object_write(&Blob::new(b"staged"), Some(&repo)).unwrap();
+        index.entries.push(staged);
+        index_write(&repo, &index).unwrap();
+        let garbage = object_write(&Blob::new(b"garbage"), Some(&repo)).unwrap();
+
+        let mut kept = object_list_loose(&repo).unwrap();
+        kept.retain(|sha| *sha != garbage);
+        let expire = SystemTime::now() + Duration::from_secs(60);
+        let pruned = prune_unreachable(&repo, expire, false).unwrap();
+        let pruned: Vec<_> = pruned.into_iter().map(|(sha, _)| sha).collect();
+        assert_eq!(pruned, [garbage]);
+        assert_eq!(object_list_loose(&repo).unwrap(), kept);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    index_read, index_write, object_find, repo_find, tree_to_leaves, Index, IndexEntry, Repository,
+    TreeLeaf,
+};
+
+/// Reads `trees` into the index. A single tree replaces the index, or with
+/// `prefix` is added to it under that directory. With `merge`, two trees
+/// move the index from the first to the second, as switching branches
+/// does, and three merge the last two against the first, leaving
+/// conflicting paths unmerged at stages 1 to 3.
+pub fn read_tree(trees: &[String], merge: bool, prefix: Option<&str>) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    for tree in trees {
+        if object_find(&repo, tree, Some(b"tree"), true)?.is_none() {
+            bail!("Not a valid object name {}", tree);
+        }
+    }
+    let mut index = index_read(&repo)?;
+    match (trees, merge, prefix) {
+        ([tree], false, None) => index_read_tree(&repo, &mut index, tree, "")?,
+        ([tree], false, Some(prefix)) => index_read_tree(&repo, &mut index, tree, prefix)?,
+        ([from, to], true, None) => index_merge_two(&repo, &mut index, from, to)?,
+        ([base, ours, theirs], true, None) => {
+            index_merge_three(&repo, &mut index, base, ours, theirs)?
+        }
+        _ => bail!("usage: read-tree (<tree-ish> | --prefix=<dir> <tree-ish> | -m <tree-ish> <tree-ish> [<tree-ish>])"),
+    }
+    index_write(&repo, &index)
+}
+
+/// Replaces the index with the tree of `tree_ish`. Entries whose content is
+/// unchanged keep their stat data, so unmodified files stay clean. With a
+/// `prefix`, the tree is added under that directory instead, which must
+/// not be in the index yet.
+pub fn index_read_tree(
+    repo: &Repository,
+    index: &mut Index,
+    tree_ish: &str,
+    prefix: &str,
+) -> Result<()> {
+    let prefix = prefix.trim_end_matches('/');
+    if !prefix.is_empty() {
+        let dir = format!("{}/", prefix);
+        if let Some(e) = index
+            .entries
+            .iter()
+            .find(|e| e.name == prefix || e.name.starts_with(&dir))
+        {
+            bail!("subdirectory '{}' already exists (at {})", prefix, e.name);
+        }
+        for leaf in tree_to_leaves(repo, tree_ish, prefix)? {
+            index.entries.push(IndexEntry::from_leaf(&leaf, 0)?);
+        }
+    } else {
+        let old = std::mem::take(&mut index.entries);
+        for leaf in tree_to_leaves(repo, tree_ish, "")? {
+            index.entries.push(index_entry_keep(&old, &leaf)?);
+        }
+    }
+    index_sort(index);
+    Ok(())
+}
+
+/// Moves the index from the tree `from` to the tree `to`: paths the two
+/// trees agree on keep their index entries, and the others take those of
+/// `to`, provided the index had them as in `from` (or already as in `to`).
+pub fn index_merge_two(repo: &Repository, index: &mut Index, from: &str, to: &str) -> Result<()> {
+    index_check_merged(index)?;
+    let from = tree_leaves_map(repo, from)?;
+    let to = tree_leaves_map(repo, to)?;
+    let current: HashMap<String, IndexEntry> = std::mem::take(&mut index.entries)
+        .into_iter()
+        .map(|e| (e.name.clone(), e))
+        .collect();
+    let paths: BTreeSet<&String> = current.keys().chain(from.keys()).chain(to.keys()).collect();
+    for path in paths {
+        let (entry, f, t) = (current.get(path), from.get(path), to.get(path));
+        let kept = if leaf_same(f, t) || entry_matches(entry, t) {
+            entry.cloned()
+        } else if entry_matches(entry, f) {
+            t.map(|leaf| IndexEntry::from_leaf(leaf, 0)).transpose()?
+        } else {
+            bail!(
+                "Entry '{}' would be overwritten by merge. Cannot merge.",
+                path
+            );
+        };
+        index.entries.extend(kept);
+    }
+    index_sort(index);
+    Ok(())
+}
+
+/// Merges the trees `ours` and `theirs` into the index against their base
+/// tree `base`. A path changed on one side only takes that side; a path
+/// both sides changed differently, or either side deleted, is left
+/// unmerged with its base, ours and theirs versions at stages 1, 2 and 3. Unless the index is empty, it
+/// must match `ours`.
+pub fn index_merge_three(
+    repo: &Repository,
+    index: &mut Index,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+) -> Result<()> {
+    index_check_merged(index)?;
+    let fresh = index.entries.is_empty();
+    let base = tree_leaves_map(repo, base)?;
+    let ours = tree_leaves_map(repo, ours)?;
+    let theirs = tree_leaves_map(repo, theirs)?;
+    let current: HashMap<String, IndexEntry> = std::mem::take(&mut index.entries)
+        .into_iter()
+        .map(|e| (e.name.clone(), e))
+        .collect();
+    let paths: BTreeSet<&String> = current
+        .keys()
+        .chain(base.keys())
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    for path in paths {
+        let entry = current.get(path);
+        let (b, o, t) = (base.get(path), ours.get(path), theirs.get(path));
+        if !fresh && !entry_matches(entry, o) {
+            bail!(
+                "Entry '{}' would be overwritten by merge. Cannot merge.",
+                path
+            );
+        }
+        let resolved = if leaf_same(o, t) || leaf_same(b, t) {
+            Some(o)
+        } else if leaf_same(b, o) {
+            Some(t)
+        } else {
+            None
+        };
+        match resolved {
+            Some(Some(leaf)) => {
+                let old: Vec<IndexEntry> = entry.into_iter().cloned().collect();
+                index.entries.push(index_entry_keep(&old, leaf)?);
+            }
+            Some(None) if b.is_none() => {}
+            // As in git without --aggressive, deletions are left unmerged
+            // for the caller to resolve too.
+            _ => {
+                for (stage, leaf) in [(1, b), (2, o), (3, t)] {
+                    if let Some(leaf) = leaf {
+                        index.entries.push(IndexEntry::from_leaf(leaf, stage)?);
+                    }
+                }
+            }
+        }
+    }
+    index_sort(index);
+    Ok(())
+}
+
+fn tree_leaves_map(repo: &Repository, tree_ish: &str) -> Result<HashMap<String, TreeLeaf>> {
+    Ok(tree_to_leaves(repo, tree_ish, "")?
+        .into_iter()
+        .map(|leaf| (leaf.path.clone(), leaf))
+        .collect())
+}
+
+fn leaf_same(a: Option<&TreeLeaf>, b: Option<&TreeLeaf>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.sha == b.sha && a.mode == b.mode,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Whether the index has `entry` as the tree has `leaf`, both being absent
+/// included.
+fn entry_matches(entry: Option<&IndexEntry>, leaf: Option<&TreeLeaf>) -> bool {
+    match (entry, leaf) {
+        (Some(e), Some(l)) => e.sha == l.sha && e.tree_mode() == l.mode,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// The entry for `leaf`: the one of `old` that already has it, with its
+/// stat data and flags, or a new one.
+fn index_entry_keep(old: &[IndexEntry], leaf: &TreeLeaf) -> Result<IndexEntry> {
+    match old
+        .iter()
+        .find(|e| e.flag_stage == 0 && e.name == leaf.path && entry_matches(Some(e), Some(leaf)))
+    {
+        Some(entry) => Ok(entry.clone()),
+        None => IndexEntry::from_leaf(leaf, 0),
+    }
+}
+
+fn index_check_merged(index: &Index) -> Result<()> {
+    if let Some(e) = index.entries.iter().find(|e| e.flag_stage != 0) {
+        bail!(
+            "{}: needs merge\nyou need to resolve your current index first",
+            e.name
+        );
+    }
+    Ok(())
+}
+
+fn index_sort(index: &mut Index) {
+    index
+        .entries
+        .sort_by(|a, b| a.name.cmp(&b.name).then(a.flag_stage.cmp(&b.flag_stage)));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::index_merge_three;
+    use crate::{object_write, test_utils::test_repo, tree_from_leaves, Blob, Index, TreeLeaf};
+
+    #[test]
+    fn test_index_merge_three() {
+        let repo = test_repo("read-tree");
+        let blob = |s: &str| object_write(&Blob::new(s.as_bytes()), Some(&repo)).unwrap();
+        let tree = |files: &[(&str, &str)]| {
+            let leaves: Vec<TreeLeaf> = files
+                .iter()
+                .map(|(path, content)| TreeLeaf {
+                    mode: b"100644".to_vec(),
+                    path: path.to_string(),
+                    sha: blob(content),
+                })
+                .collect();
+            tree_from_leaves(&repo, &leaves).unwrap()
+        };
+        let base = tree(&[("a", "1"), ("b", "1"), ("c", "1")]);
+        let ours = tree(&[("a", "2"), ("b", "2"), ("c", "1")]);
+        let theirs = tree(&[("a", "1"), ("b", "3"), ("d", "1")]);
+
+        let mut index = Index::default();
+        index_merge_three(&repo, &mut index, &base, &ours, &theirs).unwrap();
+        let entries: Vec<(&str, u16)> = index
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.flag_stage >> 12))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("a", 0),
+                ("b", 1),
+                ("b", 2),
+                ("b", 3),
+                ("c", 1),
+                ("c", 2),
+                ("d", 0)
+            ]
+        );
+        assert_eq!(index.entries[0].sha, blob("2"));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,473 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_get_active, checkout_tree, commit_create_as, commit_subject, file_write_locked, gc_auto,
+    head_attach, head_detach, head_update, index_read, index_write_tree, kvlm_get_str,
+    merge_conflicts_print, object_find, object_read, pick_commit, pick_idents, ref_resolve,
+    reflog_append, repo_dir, repo_file, repo_find, rerere_clear, rerere_finish, status_dirty_paths,
+    stripspace_text, Ancestry, Commit, PickDates, PickOutcome, Repository,
+};
+
+const REBASE_DIR: &str = "rebase-merge";
+
+pub fn rebase(
+    upstream: Option<&str>,
+    abort: bool,
+    cont: bool,
+    dates: PickDates,
+    autosquash: bool,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let in_progress = repo_dir(&repo, PathBuf::from(REBASE_DIR), false)?.is_some();
+
+    if abort || cont {
+        if !in_progress {
+            bail!("No rebase in progress?");
+        }
+        return if abort {
+            rebase_abort(&repo)
+        } else {
+            rebase_continue(&repo)
+        };
+    }
+    if in_progress {
+        bail!(
+            "It seems that there is already a rebase-merge directory.\n\
+             Use \"rit rebase --continue\" or \"rit rebase --abort\"."
+        );
+    }
+    let upstream = upstream.ok_or_else(|| anyhow!("Usage: rit rebase <upstream>"))?;
+    rebase_start(&repo, upstream, dates, autosquash)
+}
+
+fn rebase_state_path(repo: &Repository, name: &str) -> Result<PathBuf> {
+    repo_file(repo, PathBuf::from(REBASE_DIR).join(name), true)
+}
+
+fn rebase_state_write(repo: &Repository, name: &str, value: &str) -> Result<()> {
+    file_write_locked(&rebase_state_path(repo, name)?, value.as_bytes(), false)
+}
+
+/// How the rebase in progress dates the commits it replays.
+fn rebase_dates(repo: &Repository) -> Result<PickDates> {
+    Ok(if rebase_state_path(repo, "cdate_is_adate")?.exists() {
+        PickDates::CommitterIsAuthor
+    } else if rebase_state_path(repo, "ignore_date")?.exists() {
+        PickDates::Ignore
+    } else {
+        PickDates::Keep
+    })
+}
+
+fn rebase_state_read(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let path = rebase_state_path(repo, name)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?.trim_end().to_string()))
+}
+
+/// Starts replaying the commits of HEAD that `upstream` lacks on top of it.
+/// Unless `dates` keeps them, every commit is replayed even when HEAD is
+/// already on top of `upstream`, as git does. With `autosquash`, fixup and
+/// squash commits are folded into the commits they name.
+pub fn rebase_start(
+    repo: &Repository,
+    upstream: &str,
+    dates: PickDates,
+    autosquash: bool,
+) -> Result<()> {
+    let dirty = status_dirty_paths(repo)?;
+    if !dirty.is_empty() {
+        bail!(
+            "cannot rebase: You have unstaged or uncommitted changes:\n\t{}",
+            dirty.join("\n\t")
+        );
+    }
+
+    let head = ref_resolve(repo, "HEAD")?.ok_or_else(|| anyhow!("No commits yet on HEAD"))?;
+    let onto = object_find(repo, upstream, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("invalid upstream '{}'", upstream))?;
+    let head_name = match branch_get_active(repo)? {
+        Some(branch) => format!("refs/heads/{}", branch),
+        None => "detached HEAD".to_string(),
+    };
+
+    let mut ancestry = Ancestry::new(repo)?;
+    let mut todo = Vec::new();
+    for sha in rebase_commits(&mut ancestry, &head, &onto)? {
+        let subject = commit_subject(repo, &sha)?;
+        todo.push(("pick", sha, subject));
+    }
+    if autosquash {
+        todo = rebase_autosquash(todo);
+    }
+    let squashing = todo.iter().any(|(action, _, _)| *action != "pick");
+    if dates == PickDates::Keep && !squashing && ancestry.is_ancestor(&onto, &head)? {
+        println!(
+            "Current branch {} is up to date.",
+            head_name.trim_start_matches("refs/heads/")
+        );
+        return Ok(());
+    }
+
+    rebase_state_write(repo, "head-name", &format!("{}\n", head_name))?;
+    rebase_state_write(repo, "onto", &format!("{}\n", onto))?;
+    rebase_state_write(repo, "orig-head", &format!("{}\n", head))?;
+    file_write_locked(
+        &repo_file(repo, PathBuf::from("ORIG_HEAD"), false)?,
+        format!("{}\n", head).as_bytes(),
+        repo.fsync.refs,
+    )?;
+    let todo: String = todo
+        .iter()
+        .map(|(action, sha, subject)| format!("{} {} {}\n", action, sha, subject))
+        .collect();
+    rebase_state_write(repo, "git-rebase-todo", &todo)?;
+    rebase_state_write(repo, "done", "")?;
+    // The same flag files git keeps.
+    match dates {
+        PickDates::Keep => {}
+        PickDates::CommitterIsAuthor => rebase_state_write(repo, "cdate_is_adate", "")?,
+        PickDates::Ignore => rebase_state_write(repo, "ignore_date", "")?,
+    }
+
+    checkout_tree(repo, &onto, false)?;
+    head_detach(repo, &onto)?;
+    reflog_append(
+        repo,
+        "HEAD",
+        Some(&head),
+        &onto,
+        &format!("rebase (start): checkout {}", upstream),
+    )?;
+    rebase_run(repo)
+}
+
+/// Lists the non-merge commits reachable from `head` but not from
+/// `upstream`, parents before children.
+fn rebase_commits(ancestry: &mut Ancestry, head: &str, upstream: &str) -> Result<Vec<String>> {
+    let mut seen = HashSet::new();
+    let mut order = Vec::new();
+    // Depth-first post-order visits parents before their children.
+    let mut stack = vec![(head.to_string(), false)];
+    while let Some((sha, expanded)) = stack.pop() {
+        if expanded {
+            order.push(sha);
+            continue;
+        }
+        if !seen.insert(sha.clone()) || ancestry.is_ancestor(&sha, upstream)? {
+            continue;
+        }
+        stack.push((sha.clone(), true));
+        for parent in ancestry.parents(&sha)?.into_iter().rev() {
+            stack.push((parent, false));
+        }
+    }
+    let mut ret = Vec::new();
+    for sha in order {
+        if ancestry.parents(&sha)?.len() <= 1 {
+            ret.push(sha);
+        }
+    }
+    Ok(ret)
+}
+
+/// Moves each "fixup! <subject>" or "squash! <subject>" commit of the todo
+/// list to just after the commit it names, by subject, a prefix of it or
+/// its id, marked to be folded into it. Those naming nothing stay put.
+fn rebase_autosquash(
+    todo: Vec<(&'static str, String, String)>,
+) -> Vec<(&'static str, String, String)> {
+    // For each commit, the fixups to fold into it, by position.
+    let mut folded: Vec<Vec<usize>> = vec![Vec::new(); todo.len()];
+    let mut root: Vec<usize> = (0..todo.len()).collect();
+    let mut actions: Vec<&str> = todo.iter().map(|(action, _, _)| *action).collect();
+    for i in 0..todo.len() {
+        let mut target = todo[i].2.as_str();
+        let mut action = None;
+        while let Some((kind, rest)) = ["fixup", "squash"]
+            .into_iter()
+            .find_map(|kind| Some(kind).zip(target.strip_prefix(kind)?.strip_prefix("! ")))
+        {
+            action.get_or_insert(kind);
+            target = rest;
+        }
+        let Some(action) = action else { continue };
+        let named = |j: &usize| {
+            let (_, sha, subject) = &todo[*j];
+            subject == target
+                || (target.len() >= 4 && sha.starts_with(target))
+                || subject.starts_with(target)
+        };
+        // An exact subject wins over an id or a prefix.
+        let found = (0..i)
+            .find(|j| todo[*j].2 == target)
+            .or_else(|| (0..i).find(named));
+        if let Some(j) = found {
+            root[i] = root[j];
+            folded[root[j]].push(i);
+            actions[i] = action;
+        }
+    }
+    let mut todo: Vec<Option<_>> = todo.into_iter().map(Some).collect();
+    let mut ret = Vec::new();
+    for i in 0..todo.len() {
+        if root[i] != i {
+            continue;
+        }
+        for j in std::iter::once(i).chain(folded[i].iter().copied()) {
+            let (_, sha, subject) = todo[j].take().unwrap();
+            ret.push((actions[j], sha, subject));
+        }
+    }
+    ret
+}
+
+/// Picks the remaining commits of the todo list one by one, stopping at the
+/// first conflict.
+fn rebase_run(repo: &Repository) -> Result<()> {
+    let dates = rebase_dates(repo)?;
+    let todo_path = rebase_state_path(repo, "git-rebase-todo")?;
+    let mut todo: VecDeque<String> = fs::read_to_string(&todo_path)?
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| l.to_string())
+        .collect();
+
+    while let Some(line) = todo.pop_front() {
+        let mut words = line.splitn(3, ' ');
+        let (Some(action), Some(sha)) = (words.next(), words.next()) else {
+            bail!("Malformed todo line '{}'", line);
+        };
+        let (action, sha) = (action.to_string(), sha.to_string());
+        let mut done = fs::read_to_string(rebase_state_path(repo, "done")?)?;
+        done.push_str(&line);
+        done.push('\n');
+        rebase_state_write(repo, "done", &done)?;
+        let remaining: String = todo.iter().map(|l| format!("{}\n", l)).collect();
+        file_write_locked(&todo_path, remaining.as_bytes(), false)?;
+
+        match pick_commit(repo, &sha, dates)? {
+            PickOutcome::Committed(_) if action != "pick" => rebase_meld(repo, &action)?,
+            PickOutcome::Committed(_) => {}
+            PickOutcome::Empty => {
+                println!("dropping {} -- patch contents already upstream", line);
+            }
+            PickOutcome::Conflicts(conflicts) => {
+                rebase_state_write(repo, "stopped-sha", &format!("{}\n", sha))?;
+                merge_conflicts_print(&conflicts);
+                bail!(
+                    "could not apply {}\n\
+                     Resolve all conflicts manually, mark them as resolved with\n\
+                     \"rit add <conflicted_files>\", then run \"rit rebase --continue\".\n\
+                     To abort and get back to the state before \"rit rebase\", run \"rit rebase --abort\".",
+                    line.split_once(' ').map_or(line.as_str(), |(_, rest)| rest)
+                );
+            }
+        }
+    }
+    rebase_finish(repo)
+}
+
+/// Folds the commit just picked into the one before it. A "fixup" keeps
+/// that commit's message; a "squash" adds its own below, without the
+/// "squash!" line.
+fn rebase_meld(repo: &Repository, action: &str) -> Result<()> {
+    let read = |sha: &str| -> Result<Commit> {
+        let obj = object_read(repo, sha)?;
+        let commit = obj
+            .as_any()
+            .downcast_ref::<Commit>()
+            .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+        Ok(Commit {
+            kvlm: commit.kvlm.clone(),
+        })
+    };
+    let head = ref_resolve(repo, "HEAD")?.unwrap();
+    let picked = read(&head)?;
+    let target = read(&picked.parents()[0])?;
+    let mut message = target.message();
+    if action == "squash" {
+        let own = picked.message();
+        let own = match own.split_once('\n') {
+            Some((subject, rest)) if subject.starts_with("squash! ") => rest.to_string(),
+            _ => own,
+        };
+        message = stripspace_text(&format!("{}\n{}", message, own), None);
+    }
+    let author = kvlm_get_str(&target.kvlm, b"author").unwrap_or_default();
+    let (author, committer) = pick_idents(repo, &author, rebase_dates(repo)?)?;
+    let new = commit_create_as(
+        repo,
+        &picked.tree().unwrap_or_default(),
+        &target.parents(),
+        Some(&author),
+        Some(&committer),
+        &message,
+    )?;
+    head_update(
+        repo,
+        &new,
+        &format!(
+            "rebase ({}): {}",
+            action,
+            message.lines().next().unwrap_or("")
+        ),
+    )
+}
+
+fn rebase_finish(repo: &Repository) -> Result<()> {
+    let head_name = rebase_state_read(repo, "head-name")?.unwrap_or_default();
+    if let Some(branch) = head_name.strip_prefix("refs/heads/") {
+        let head = ref_resolve(repo, "HEAD")?.unwrap();
+        head_attach(repo, branch)?;
+        head_update(
+            repo,
+            &head,
+            &format!("rebase (finish): returning to {}", head_name),
+        )?;
+    }
+    fs::remove_dir_all(repo.repo_path(PathBuf::from(REBASE_DIR)))?;
+    println!("Successfully rebased and updated {}.", head_name);
+    gc_auto(repo);
+    Ok(())
+}
+
+fn rebase_continue(repo: &Repository) -> Result<()> {
+    rerere_finish(repo)?;
+    let index = index_read(repo)?;
+    let tree = index_write_tree(repo, &index)?;
+
+    // Commit the resolution of the commit that stopped, unless it ended up
+    // empty.
+    if let Some(sha) = rebase_state_read(repo, "stopped-sha")? {
+        let head = ref_resolve(repo, "HEAD")?.unwrap();
+        let head_tree = object_find(repo, &head, Some(b"tree"), true)?.unwrap();
+        if tree != head_tree {
+            let obj = object_read(repo, &sha)?;
+            let commit = obj
+                .as_any()
+                .downcast_ref::<Commit>()
+                .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+            let author = kvlm_get_str(&commit.kvlm, b"author").unwrap_or_default();
+            let (author, committer) = pick_idents(repo, &author, rebase_dates(repo)?)?;
+            let message = commit.message();
+            let new = commit_create_as(
+                repo,
+                &tree,
+                &[head],
+                Some(&author),
+                Some(&committer),
+                &message,
+            )?;
+            head_update(
+                repo,
+                &new,
+                &format!(
+                    "rebase (continue): {}",
+                    message.lines().next().unwrap_or("")
+                ),
+            )?;
+            // A fixup or squash that stopped still has to be folded in.
+            let done = fs::read_to_string(rebase_state_path(repo, "done")?)?;
+            let action = done.lines().last().and_then(|l| l.split(' ').next());
+            if let Some(action @ ("fixup" | "squash")) = action {
+                rebase_meld(repo, action)?;
+            }
+        }
+        fs::remove_file(rebase_state_path(repo, "stopped-sha")?)?;
+    } else if !status_dirty_paths(repo)?.is_empty() {
+        bail!("You have uncommitted changes in your working tree.");
+    }
+    rebase_run(repo)
+}
+
+fn rebase_abort(repo: &Repository) -> Result<()> {
+    let orig_head = rebase_state_read(repo, "orig-head")?
+        .ok_or_else(|| anyhow!("Rebase state is missing orig-head"))?;
+    let head_name = rebase_state_read(repo, "head-name")?.unwrap_or_default();
+
+    let head = ref_resolve(repo, "HEAD")?;
+    checkout_tree(repo, &orig_head, true)?;
+    rerere_clear(repo)?;
+    match head_name.strip_prefix("refs/heads/") {
+        // The branch itself is only moved when the rebase finishes.
+        Some(branch) => head_attach(repo, branch)?,
+        None => head_detach(repo, &orig_head)?,
+    }
+    reflog_append(
+        repo,
+        "HEAD",
+        head.as_deref(),
+        &orig_head,
+        &format!("rebase (abort): returning to {}", head_name),
+    )?;
+    fs::remove_dir_all(repo.repo_path(PathBuf::from(REBASE_DIR)))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{rebase_autosquash, rebase_commits};
+    use crate::{
+        test_utils::{make_commit, test_repo},
+        Ancestry,
+    };
+
+    #[test]
+    fn test_rebase_commits_skips_upstream_and_merges() {
+        let repo = test_repo("rebase-commits");
+        let base = make_commit(&repo, "base", &[]);
+        let upstream = make_commit(&repo, "upstream", &[&base]);
+        let a = make_commit(&repo, "a", &[&base]);
+        let b = make_commit(&repo, "b", &[&a]);
+        let merge = make_commit(&repo, "merge", &[&b, &upstream]);
+        let c = make_commit(&repo, "c", &[&merge]);
+
+        let mut ancestry = Ancestry::new(&repo).unwrap();
+        let commits = rebase_commits(&mut ancestry, &c, &upstream).unwrap();
+        assert_eq!(commits, vec![a, b, c]);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_rebase_autosquash() {
+        let todo = [
+            ("1111", "add a"),
+            ("2222", "add b"),
+            ("3333", "fixup! add a"),
+            ("4444", "squash! 2222"),
+            ("5555", "fixup! fixup! add a"),
+            ("6666", "fixup! nothing"),
+        ];
+        let todo = todo
+            .iter()
+            .map(|(sha, subject)| ("pick", sha.to_string(), subject.to_string()))
+            .collect();
+        let order: Vec<(&str, String)> = rebase_autosquash(todo)
+            .into_iter()
+            .map(|(action, sha, _)| (action, sha))
+            .collect();
+        let expected = [
+            ("pick", "1111"),
+            ("fixup", "3333"),
+            ("fixup", "5555"),
+            ("pick", "2222"),
+            ("squash", "4444"),
+            ("pick", "6666"),
+        ];
+        assert_eq!(
+            order,
+            expected.map(|(action, sha)| (action, sha.to_string()))
+        );
+    }
+}
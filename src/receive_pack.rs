@@ -0,0 +1,128 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    branch_get_active, file_write_locked, object_read, pack_unpack, pkt_flush, pkt_write,
+    ref_delete, ref_is_hidden, ref_resolve, refs_advertise, repo_file, PktReader, Repository,
+};
+
+const RECEIVE_PACK_CAPS: &str = "report-status delete-refs ofs-delta";
+
+/// One `<old> <new> <ref>` command sent by a pushing client.
+pub struct RefUpdate {
+    pub old: String,
+    pub new: String,
+    pub name: String,
+}
+
+pub fn receive_pack_advertise(repo: &Repository) -> Result<Vec<u8>> {
+    refs_advertise(repo, RECEIVE_PACK_CAPS, false, "receive")
+}
+
+/// Answers a receive-pack request: ref update commands, then the pack with
+/// the new objects. Returns the report-status reply.
+pub fn receive_pack(repo: &Repository, request: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = PktReader::new(request);
+    let mut updates = Vec::new();
+    let mut report = false;
+    while let Some(line) = reader.read_line()? {
+        let (command, caps) = match line.iter().position(|&b| b == 0) {
+            Some(nul) => (&line[..nul], Some(&line[nul + 1..])),
+            None => (line, None),
+        };
+        if let Some(caps) = caps {
+            report = caps
+                .split(|&b| b == b' ')
+                .any(|cap| cap == b"report-status");
+        }
+        let command = std::str::from_utf8(command)?;
+        let parts: Vec<&str> = command.split(' ').collect();
+        if parts.len() != 3 {
+            bail!("Protocol error: bad command '{}'", command);
+        }
+        updates.push(RefUpdate {
+            old: parts[0].to_string(),
+            new: parts[1].to_string(),
+            name: parts[2].to_string(),
+        });
+    }
+
+    let mut out = Vec::new();
+    if updates.is_empty() {
+        return Ok(out);
+    }
+
+    let pack = reader.rest();
+    let unpacked = if pack.is_empty() {
+        Ok(Vec::new())
+    } else {
+        pack_unpack(repo, pack)
+    };
+
+    let mut results = Vec::new();
+    for update in &updates {
+        let result = match &unpacked {
+            Ok(_) => ref_update_checked(repo, update),
+            Err(_) => Err("unpacker error".to_string()),
+        };
+        results.push((update.name.clone(), result));
+    }
+
+    if report {
+        let status = match &unpacked {
+            Ok(_) => "unpack ok\n".to_string(),
+            Err(e) => format!("unpack {}\n", e),
+        };
+        pkt_write(&mut out, status.as_bytes());
+        for (name, result) in results {
+            let line = match result {
+                Ok(()) => format!("ok {}\n", name),
+                Err(reason) => format!("ng {} {}\n", name, reason),
+            };
+            pkt_write(&mut out, line.as_bytes());
+        }
+        pkt_flush(&mut out);
+    }
+    Ok(out)
+}
+
+/// Applies one pushed update, refusing it with a reason if the ref moved
+/// since the advertisement, the ref is hidden, the new object is missing, or
+/// the branch is checked out.
+fn ref_update_checked(repo: &Repository, update: &RefUpdate) -> Result<(), String> {
+    let zero = "0".repeat(40);
+    if !update.name.starts_with("refs/")
+        || update.name.split('/').any(|c| c.is_empty() || c == "..")
+    {
+        return Err("funny refname".to_string());
+    }
+    if ref_is_hidden(repo, "receive", &update.name) {
+        return Err("deny updating a hidden ref".to_string());
+    }
+    let current = ref_resolve(repo, &update.name)
+        .map_err(|e| e.to_string())?
+        .unwrap_or_else(|| zero.clone());
+    if current != update.old {
+        return Err("failed to lock".to_string());
+    }
+    if let Some(branch) = branch_get_active(repo).map_err(|e| e.to_string())? {
+        if update.name == format!("refs/heads/{}", branch) && !repo.is_bare() {
+            return Err("branch is currently checked out".to_string());
+        }
+    }
+
+    if update.new == zero {
+        return ref_delete(repo, &update.name).map_err(|e| e.to_string());
+    }
+    let path = repo_file(repo, PathBuf::from(&update.name), true).map_err(|e| e.to_string())?;
+    if object_read(repo, &update.new).is_err() {
+        return Err("missing necessary objects".to_string());
+    }
+    file_write_locked(
+        &path,
+        format!("{}\n", update.new).as_bytes(),
+        repo.fsync.refs,
+    )
+    .map_err(|e| e.to_string())
+}
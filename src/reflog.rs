@@ -0,0 +1,433 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Result};
+use walkdir::WalkDir;
+
+use crate::{
+    date_now, expiry_parse, file_write_locked, ident_default, ident_parse, ref_resolve, repo_dir,
+    repo_file, repo_find, sha_short, Ancestry, Repository,
+};
+
+pub const ZERO_SHA: &str = "0000000000000000000000000000000000000000";
+/// How long reflog entries are kept, like `gc.reflogExpire`.
+const REFLOG_EXPIRE: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+/// How long entries for commits the ref no longer reaches are kept, like
+/// `gc.reflogExpireUnreachable`.
+const REFLOG_EXPIRE_UNREACHABLE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// One line of `.git/logs/<ref>`: the ref moved from `old` to `new`.
+#[derive(Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    /// "Name <email> timestamp tz" of whoever moved the ref.
+    pub ident: String,
+    pub message: String,
+}
+
+/// Lists the reflog of `refname`, HEAD by default, newest entry first, as
+/// `<sha> <name>@{<n>}: <message>`.
+pub fn reflog(refname: Option<&str>) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let name = refname.unwrap_or("HEAD");
+    let full = reflog_ref_full(&repo, name)?
+        .ok_or_else(|| anyhow!("ambiguous argument '{}': unknown revision", name))?;
+    for (i, entry) in reflog_read(&repo, &full)?.iter().rev().enumerate() {
+        println!(
+            "{} {}@{{{}}}: {}",
+            sha_short(&repo, &entry.new),
+            name,
+            i,
+            entry.message
+        );
+    }
+    Ok(())
+}
+
+/// The times before which the entries of a reflog expire. None keeps
+/// them however old they are.
+#[derive(Clone, Copy)]
+pub struct ReflogExpiry {
+    pub reachable: Option<SystemTime>,
+    /// For entries that moved the ref from or to a commit its tip no
+    /// longer reaches.
+    pub unreachable: Option<SystemTime>,
+}
+
+impl ReflogExpiry {
+    /// The expiry config sets for `refname`. As in git, the first
+    /// `gc.<pattern>` section whose pattern matches the ref decides both
+    /// times with its `reflogExpire` and `reflogExpireUnreachable`, one it
+    /// leaves out being never. Other refs use `gc.reflogExpire` and
+    /// `gc.reflogExpireUnreachable`, 90 and 30 days by default, except the
+    /// stash, which is kept.
+    pub fn config(repo: &Repository, refname: &str, now: SystemTime) -> Result<Self> {
+        let mut sections = repo.config_global().sections();
+        sections.extend(repo.config_sections());
+        let section = sections.into_iter().find(|section| {
+            let pattern = section
+                .strip_prefix("gc \"")
+                .and_then(|s| s.strip_suffix('"'));
+            pattern
+                .and_then(|p| glob::Pattern::new(p).ok())
+                .is_some_and(|p| p.matches(refname))
+        });
+        let default = |age: Duration| match (&section, refname) {
+            (None, "refs/stash") | (Some(_), _) => None,
+            (None, _) => Some(now - age),
+        };
+        let cutoff = |key: &str, age: Duration| {
+            let value = repo.config_get(section.as_deref().unwrap_or("gc"), key);
+            match value {
+                Some(value) => expiry_parse(&value, now)
+                    .map_err(|_| anyhow!("invalid {} value '{}' for {}", key, value, refname)),
+                None => Ok(default(age)),
+            }
+        };
+        Ok(Self {
+            reachable: cutoff("reflogExpire", REFLOG_EXPIRE)?,
+            unreachable: cutoff("reflogExpireUnreachable", REFLOG_EXPIRE_UNREACHABLE)?,
+        })
+    }
+}
+
+/// Drops old entries from the reflogs of `refs`, or of every ref with
+/// `all`, as `ReflogExpiry::config` says for each; `expire` and
+/// `expire_unreachable` override it for all of them. With `dry_run` the
+/// entries that would go are listed instead.
+pub fn reflog_expire(
+    refs: &[String],
+    all: bool,
+    expire: Option<&str>,
+    expire_unreachable: Option<&str>,
+    dry_run: bool,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let now = SystemTime::now();
+    let refs = match all {
+        true => reflog_names(&repo)?,
+        false => refs
+            .iter()
+            .map(|name| {
+                reflog_ref_full(&repo, name)?
+                    .ok_or_else(|| anyhow!("{} points nowhere and has no reflog", name))
+            })
+            .collect::<Result<_>>()?,
+    };
+    if refs.is_empty() && !all {
+        bail!("no reflog specified to expire (use --all for every one)");
+    }
+    let expire = expire.map(|value| expiry_parse(value, now)).transpose()?;
+    let expire_unreachable = expire_unreachable
+        .map(|value| expiry_parse(value, now))
+        .transpose()?;
+
+    let mut ancestry = Ancestry::new(&repo)?;
+    for refname in refs {
+        let mut expiry = ReflogExpiry::config(&repo, &refname, now)?;
+        expiry.reachable = expire.unwrap_or(expiry.reachable);
+        expiry.unreachable = expire_unreachable.unwrap_or(expiry.unreachable);
+        for entry in reflog_expire_ref(&repo, &mut ancestry, &refname, &expiry, dry_run)? {
+            if dry_run {
+                println!(
+                    "would prune {} {}: {}",
+                    sha_short(&repo, &entry.new),
+                    refname,
+                    entry.message
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Drops the expired entries of every reflog, each as its config says.
+pub fn reflog_expire_all(repo: &Repository, now: SystemTime) -> Result<()> {
+    let mut ancestry = Ancestry::new(repo)?;
+    for refname in reflog_names(repo)? {
+        let expiry = ReflogExpiry::config(repo, &refname, now)?;
+        reflog_expire_ref(repo, &mut ancestry, &refname, &expiry, false)?;
+    }
+    Ok(())
+}
+
+/// Drops the entries of `refname`'s reflog that `expiry` says have
+/// expired, returning them. With `dry_run` the log is left as it is.
+pub fn reflog_expire_ref(
+    repo: &Repository,
+    ancestry: &mut Ancestry,
+    refname: &str,
+    expiry: &ReflogExpiry,
+    dry_run: bool,
+) -> Result<Vec<ReflogEntry>> {
+    let cutoff = |time: Option<SystemTime>| {
+        time.map(|t| {
+            t.duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64)
+        })
+    };
+    let (reachable, unreachable) = (cutoff(expiry.reachable), cutoff(expiry.unreachable));
+    let tip = ref_resolve(repo, refname)?;
+    let (mut kept, mut expired) = (Vec::new(), Vec::new());
+    for entry in reflog_read(repo, refname)? {
+        // An entry whose time can't be read is kept, to be safe.
+        let Some((_, time, _)) = ident_parse(&entry.ident) else {
+            kept.push(entry);
+            continue;
+        };
+        let expires = reachable.is_some_and(|cutoff| time < cutoff)
+            || (unreachable.is_some_and(|cutoff| time < cutoff)
+                && [&entry.old, &entry.new].into_iter().any(|sha| {
+                    let reachable = |tip: &String| ancestry.is_ancestor(sha, tip).unwrap_or(false);
+                    sha != ZERO_SHA && !tip.as_ref().is_some_and(reachable)
+                }));
+        match expires {
+            true => expired.push(entry),
+            false => kept.push(entry),
+        }
+    }
+    if !expired.is_empty() && !dry_run {
+        reflog_write(repo, refname, &kept)?;
+    }
+    Ok(expired)
+}
+
+/// The names of the refs that have a reflog, as "HEAD" or "refs/heads/main".
+fn reflog_names(repo: &Repository) -> Result<Vec<String>> {
+    let Some(logs) = repo_dir(repo, PathBuf::from("logs"), false)? else {
+        return Ok(Vec::new());
+    };
+    let mut names = Vec::new();
+    for entry in WalkDir::new(&logs).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let name = entry.path().strip_prefix(&logs)?.to_string_lossy();
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// The full name of the ref `name` means, trying the places git looks in
+/// order: `refs/<name>`, then tags, branches and remote branches.
+fn reflog_ref_full(repo: &Repository, name: &str) -> Result<Option<String>> {
+    if name == "HEAD" || name.starts_with("refs/") {
+        return Ok(Some(name.to_string()));
+    }
+    for prefix in ["refs/", "refs/tags/", "refs/heads/", "refs/remotes/"] {
+        let full = format!("{}{}", prefix, name);
+        if ref_resolve(repo, &full)?.is_some() || reflog_path(repo, &full)?.exists() {
+            return Ok(Some(full));
+        }
+    }
+    Ok(None)
+}
+
+fn reflog_path(repo: &Repository, refname: &str) -> Result<PathBuf> {
+    repo_file(repo, PathBuf::from("logs").join(refname), true)
+}
+
+/// Records that `refname` moved from `old` (None for a new ref) to `new`.
+pub fn reflog_append(
+    repo: &Repository,
+    refname: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> Result<()> {
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(reflog_path(repo, refname)?)?;
+    writeln!(
+        f,
+        "{} {} {}\t{}",
+        old.unwrap_or(ZERO_SHA),
+        new,
+        reflog_ident(repo)?,
+        message.lines().next().unwrap_or_default()
+    )?;
+    Ok(())
+}
+
+/// The committer identity, or without one the login name: a missing
+/// user.name shouldn't stop refs from moving.
+fn reflog_ident(repo: &Repository) -> Result<String> {
+    if let Ok(ident) = ident_default(repo, "COMMITTER") {
+        return Ok(ident);
+    }
+    let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+    Ok(format!("{} <{}@localhost> {}", user, user, date_now()?))
+}
+
+/// Reads the reflog of `refname`, oldest entry first. A ref without a log
+/// has no entries.
+pub fn reflog_read(repo: &Repository, refname: &str) -> Result<Vec<ReflogEntry>> {
+    let path = reflog_path(repo, refname)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|line| {
+            let malformed = || anyhow!("Malformed reflog line for {}: {}", refname, line);
+            let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+            let (old, rest) = head.split_once(' ').ok_or_else(malformed)?;
+            let (new, ident) = rest.split_once(' ').ok_or_else(malformed)?;
+            Ok(ReflogEntry {
+                old: old.to_string(),
+                new: new.to_string(),
+                ident: ident.to_string(),
+                message: message.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Replaces the reflog of `refname` with `entries`, removing the file when
+/// there are none left.
+pub fn reflog_write(repo: &Repository, refname: &str, entries: &[ReflogEntry]) -> Result<()> {
+    let path = reflog_path(repo, refname)?;
+    if entries.is_empty() {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    let mut content = String::new();
+    for e in entries {
+        content.push_str(&format!("{} {} {}\t{}\n", e.old, e.new, e.ident, e.message));
+    }
+    file_write_locked(&path, content.as_bytes(), false)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use super::reflog_ref_full;
+    use crate::{
+        checkout_tree, head_update, ref_create, reflog_append, reflog_expire_ref, reflog_read,
+        reflog_write, switch_to,
+        test_utils::{make_commit, test_repo},
+        Ancestry, ReflogEntry, ReflogExpiry, ZERO_SHA,
+    };
+
+    #[test]
+    fn test_reflog_roundtrip() {
+        let repo = test_repo("reflog");
+        let (a, b) = ("a".repeat(40), "b".repeat(40));
+        reflog_append(&repo, "refs/stash", None, &a, "first\nignored").unwrap();
+        reflog_append(&repo, "refs/stash", Some(&a), &b, "second").unwrap();
+
+        let entries = reflog_read(&repo, "refs/stash").unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].old, "0".repeat(40));
+        assert_eq!(entries[0].message, "first");
+        assert_eq!(entries[1].old, a);
+        assert_eq!(entries[1].new, b);
+        assert!(entries[1].ident.starts_with("Test <test@example.com> "));
+
+        reflog_write(&repo, "refs/stash", &entries[1..]).unwrap();
+        assert_eq!(reflog_read(&repo, "refs/stash").unwrap().len(), 1);
+        reflog_write(&repo, "refs/stash", &[]).unwrap();
+        assert!(!repo.gitdir.join("logs/refs/stash").exists());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_reflog_expire() {
+        let repo = test_repo("reflog-expire");
+        let one = make_commit(&repo, "one", &[]);
+        let two = make_commit(&repo, "two", &[&one]);
+        let dropped = make_commit(&repo, "dropped", &[&one]);
+        ref_create(&repo, "heads/main", &two).unwrap();
+        let day = 24 * 60 * 60;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let entry = |old: &str, new: &str, days: i64| ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            ident: format!("A <a@b> {} +0000", now - days * day),
+            message: format!("{} days ago", days),
+        };
+        let entries = [
+            entry(ZERO_SHA, &one, 100),
+            entry(&one, &dropped, 40),
+            entry(&dropped, &one, 40),
+            entry(&one, &two, 40),
+            entry(&two, &dropped, 1),
+            entry(&dropped, &two, 1),
+        ];
+        reflog_write(&repo, "refs/heads/main", &entries).unwrap();
+
+        let now = SystemTime::now();
+        let expiry = ReflogExpiry::config(&repo, "refs/heads/main", now).unwrap();
+        let mut ancestry = Ancestry::new(&repo).unwrap();
+        let expired =
+            reflog_expire_ref(&repo, &mut ancestry, "refs/heads/main", &expiry, false).unwrap();
+        let messages = |entries: &[ReflogEntry]| {
+            let messages = entries.iter().map(|e| e.message.clone());
+            messages.collect::<Vec<_>>().join(", ")
+        };
+        assert_eq!(messages(&expired), "100 days ago, 40 days ago, 40 days ago");
+        let kept = reflog_read(&repo, "refs/heads/main").unwrap();
+        assert_eq!(messages(&kept), "40 days ago, 1 days ago, 1 days ago");
+        assert_eq!(kept[0].new, two);
+
+        let stash = ReflogExpiry::config(&repo, "refs/stash", now).unwrap();
+        assert!(stash.reachable.is_none() && stash.unreachable.is_none());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_reflog_records_moves() {
+        let repo = test_repo("reflog-moves");
+        let one = make_commit(&repo, "one", &[]);
+        checkout_tree(&repo, &one, true).unwrap();
+        head_update(&repo, &one, "commit (initial): one").unwrap();
+        let two = make_commit(&repo, "two", &[&one]);
+        checkout_tree(&repo, &two, true).unwrap();
+        head_update(&repo, &two, "commit: two").unwrap();
+        ref_create(&repo, "heads/topic", &one).unwrap();
+        switch_to(&repo, "topic", true).unwrap();
+
+        let moves = |refname: &str| {
+            let entries = reflog_read(&repo, refname).unwrap();
+            let moves = entries.into_iter().map(|e| (e.old, e.new, e.message));
+            moves.collect::<Vec<_>>()
+        };
+        let zero = ZERO_SHA.to_string();
+        let initial = (zero, one.clone(), "commit (initial): one".to_string());
+        let second = (one.clone(), two.clone(), "commit: two".to_string());
+        let checkout = (
+            two.clone(),
+            one.clone(),
+            "checkout: moving from master to topic".to_string(),
+        );
+        assert_eq!(
+            moves("refs/heads/master"),
+            [initial.clone(), second.clone()]
+        );
+        assert_eq!(moves("HEAD"), [initial, second, checkout]);
+        assert!(moves("refs/heads/topic").is_empty());
+        assert_eq!(
+            reflog_ref_full(&repo, "master").unwrap().as_deref(),
+            Some("refs/heads/master")
+        );
+        assert!(reflog_ref_full(&repo, "nope").unwrap().is_none());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,345 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use indexmap::IndexMap;
+
+use crate::{
+    branch_list, file_write_locked, ref_delete, ref_list_flat, ref_resolve, repo_file, repo_find,
+    url_rewrite, Ancestry, BranchFilter, Refspec, Repository, Transport,
+};
+
+/// The names of the configured remotes, in config order.
+pub fn remote_names(repo: &Repository) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for section in repo.config_sections() {
+        if let Some(name) = remote_section_name(&section) {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// The remote a `[remote "<name>"]` section configures.
+fn remote_section_name(section: &str) -> Option<&str> {
+    section.strip_prefix("remote \"")?.strip_suffix('"')
+}
+
+fn remote_section(name: &str) -> String {
+    format!("remote \"{}\"", name)
+}
+
+fn remote_exists(repo: &Repository, name: &str) -> bool {
+    remote_names(repo).iter().any(|n| n == name)
+}
+
+/// Lists the remotes, with `verbose` their fetch and push URLs.
+pub fn remote_list(verbose: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    for name in remote_names(&repo) {
+        if !verbose {
+            println!("{}", name);
+            continue;
+        }
+        let section = remote_section(&name);
+        let url = repo.config_get(&section, "url").unwrap_or_default();
+        let push_urls = repo.config_get_all(&section, "pushurl");
+        println!("{}\t{} (fetch)", name, url);
+        if push_urls.is_empty() {
+            println!("{}\t{} (push)", name, url);
+        }
+        for push_url in push_urls {
+            println!("{}\t{} (push)", name, push_url);
+        }
+    }
+    Ok(())
+}
+
+pub fn remote_add(name: &str, url: &str) -> Result<()> {
+    let mut repo = repo_find(Path::new("."), true)?.unwrap();
+    if !remote_name_valid(name) {
+        bail!("'{}' is not a valid remote name", name);
+    }
+    if remote_exists(&repo, name) {
+        bail!("remote {} already exists.", name);
+    }
+    let section = remote_section(name);
+    repo.config_set(&section, "url", url)?;
+    repo.config_add(
+        &section,
+        "fetch",
+        &format!("+refs/heads/*:refs/remotes/{}/*", name),
+    )
+}
+
+/// Whether `name` can name a remote: it must make valid ref names under
+/// refs/remotes/.
+fn remote_name_valid(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '.'])
+        && !name.contains(['/', ' ', '"', ':', '~', '^', '?', '*', '[', '\\'])
+        && !name.contains("..")
+        && !name.ends_with(".lock")
+}
+
+/// Forgets remote `name`, along with its remote-tracking refs and the
+/// upstream settings of branches that tracked it.
+pub fn remote_remove(name: &str) -> Result<()> {
+    let mut repo = repo_find(Path::new("."), true)?.unwrap();
+    if !repo.config_section_remove(&remote_section(name))? {
+        bail!("No such remote: '{}'", name);
+    }
+    for (branch, _) in branch_list(&repo, &BranchFilter::default())? {
+        let section = format!("branch \"{}\"", branch);
+        if repo.config_get(&section, "remote").as_deref() == Some(name) {
+            repo.config_unset(&section, "remote")?;
+            repo.config_unset(&section, "merge")?;
+        }
+    }
+    for refname in remote_tracking_refs(&repo, name)?.keys() {
+        ref_remove(&repo, refname)?;
+    }
+    Ok(())
+}
+
+/// The refs under refs/remotes/<name>, with what they point at.
+fn remote_tracking_refs(repo: &Repository, name: &str) -> Result<IndexMap<String, String>> {
+    let prefix = format!("refs/remotes/{}", name);
+    ref_list_flat(
+        repo,
+        Some(repo.repo_path(PathBuf::from(&prefix))),
+        Some(&prefix),
+    )
+}
+
+/// Renames remote `old` to `new`, moving its remote-tracking refs and
+/// rewriting the default fetch refspec and the branches that track it.
+pub fn remote_rename(old: &str, new: &str) -> Result<()> {
+    let mut repo = repo_find(Path::new("."), true)?.unwrap();
+    if !remote_exists(&repo, old) {
+        bail!("No such remote: '{}'", old);
+    }
+    if !remote_name_valid(new) {
+        bail!("'{}' is not a valid remote name", new);
+    }
+    if remote_exists(&repo, new) {
+        bail!("remote {} already exists.", new);
+    }
+    let (old_section, new_section) = (remote_section(old), remote_section(new));
+    repo.config_section_rename(&old_section, &new_section)?;
+
+    let old_prefix = format!("refs/remotes/{}/", old);
+    let new_prefix = format!("refs/remotes/{}/", new);
+    let specs = repo.config_get_all(&new_section, "fetch");
+    if !specs.is_empty() {
+        repo.config_unset(&new_section, "fetch")?;
+        for spec in specs {
+            let spec = match spec.split_once(':') {
+                Some((src, dst)) => match dst.strip_prefix(&old_prefix) {
+                    Some(rest) => format!("{}:{}{}", src, new_prefix, rest),
+                    None => spec,
+                },
+                None => spec,
+            };
+            repo.config_add(&new_section, "fetch", &spec)?;
+        }
+    }
+    for (branch, _) in branch_list(&repo, &BranchFilter::default())? {
+        let section = format!("branch \"{}\"", branch);
+        if repo.config_get(&section, "remote").as_deref() == Some(old) {
+            repo.config_set(&section, "remote", new)?;
+        }
+    }
+
+    let head = repo_file(&repo, PathBuf::from(format!("{}HEAD", old_prefix)), false)?;
+    let head = fs::read_to_string(&head)
+        .ok()
+        .filter(|h| h.starts_with("ref: "));
+    for (refname, sha) in remote_tracking_refs(&repo, old)? {
+        if head.is_some() && refname == format!("{}HEAD", old_prefix) {
+            continue;
+        }
+        let renamed = format!("{}{}", new_prefix, &refname[old_prefix.len()..]);
+        let path = repo_file(&repo, PathBuf::from(&renamed), true)?;
+        file_write_locked(&path, format!("{}\n", sha).as_bytes(), repo.fsync.refs)?;
+        let log = repo_file(&repo, PathBuf::from("logs").join(&refname), false)?;
+        if log.exists() {
+            fs::rename(
+                log,
+                repo_file(&repo, PathBuf::from("logs").join(&renamed), true)?,
+            )?;
+        }
+        ref_remove(&repo, &refname)?;
+    }
+    // The symbolic <remote>/HEAD points into the remote's own namespace.
+    if let Some(target) = head {
+        let target = target.replace(&old_prefix, &new_prefix);
+        let path = repo_file(&repo, PathBuf::from(format!("{}HEAD", new_prefix)), true)?;
+        file_write_locked(&path, target.as_bytes(), repo.fsync.refs)?;
+        ref_remove(&repo, &format!("{}HEAD", old_prefix))?;
+    }
+    Ok(())
+}
+
+/// Changes the URL of remote `name`, or with `push` its push URL.
+pub fn remote_set_url(name: &str, url: &str, push: bool) -> Result<()> {
+    let mut repo = repo_find(Path::new("."), true)?.unwrap();
+    if !remote_exists(&repo, name) {
+        bail!("No such remote '{}'", name);
+    }
+    let key = if push { "pushurl" } else { "url" };
+    repo.config_set(&remote_section(name), key, url)
+}
+
+/// Describes remote `name`: its URLs, what the remote has and how the
+/// local branches relate to it.
+pub fn remote_show(name: &str) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    if !remote_exists(&repo, name) {
+        bail!("No such remote '{}'", name);
+    }
+    let section = remote_section(name);
+    let url = repo.config_get(&section, "url").unwrap_or_default();
+    let push_url = repo.config_get(&section, "pushurl").unwrap_or(url.clone());
+    let specs = repo
+        .config_get_all(&section, "fetch")
+        .iter()
+        .map(|s| Refspec::parse(s))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut transport = Transport::open(&url_rewrite(Some(&repo), &url, false), Some(&repo))?;
+    let advertised = transport.advertise("git-upload-pack")?;
+    println!("* remote {}", name);
+    println!("  Fetch URL: {}", url);
+    println!("  Push  URL: {}", push_url);
+    let head = advertised.head_branch();
+    println!(
+        "  HEAD branch: {}",
+        head.as_deref()
+            .map_or("(unknown)", |h| h.trim_start_matches("refs/heads/"))
+    );
+
+    let short = |name: &str| name.trim_start_matches("refs/heads/").to_string();
+    let mut branches: Vec<(String, String)> = Vec::new();
+    for (refname, _) in &advertised.refs {
+        if !refname.starts_with("refs/heads/") {
+            continue;
+        }
+        let status = match specs.iter().find_map(|s| s.map(refname)).flatten() {
+            Some(local) if ref_resolve(&repo, &local)?.is_some() => "tracked".to_string(),
+            Some(_) => format!("new (next fetch will store in remotes/{})", name),
+            None => "skipped".to_string(),
+        };
+        branches.push((short(refname), status));
+    }
+    // Remote-tracking refs whose branch is gone from the remote.
+    for local in remote_tracking_refs(&repo, name)?.keys() {
+        let gone = !advertised.refs.iter().any(|(refname, _)| {
+            specs.iter().find_map(|s| s.map(refname)).flatten().as_ref() == Some(local)
+        });
+        if gone && !local.ends_with("/HEAD") {
+            branches.push((local.clone(), "stale".to_string()));
+        }
+    }
+    if !branches.is_empty() {
+        let width = branches.iter().map(|(b, _)| b.len()).max().unwrap_or(0);
+        println!(
+            "  Remote branch{}:",
+            if branches.len() == 1 { "" } else { "es" }
+        );
+        for (branch, status) in &branches {
+            println!("    {:<width$} {}", branch, status, width = width);
+        }
+    }
+
+    let locals = branch_list(&repo, &BranchFilter::default())?;
+    let mut pulls = Vec::new();
+    for (branch, _) in &locals {
+        let section = format!("branch \"{}\"", branch);
+        if repo.config_get(&section, "remote").as_deref() != Some(name) {
+            continue;
+        }
+        if let Some(merge) = repo.config_get(&section, "merge") {
+            pulls.push((branch.clone(), short(&merge)));
+        }
+    }
+    if !pulls.is_empty() {
+        let width = pulls.iter().map(|(b, _)| b.len()).max().unwrap_or(0);
+        println!(
+            "  Local branch{} configured for 'rit pull':",
+            if pulls.len() == 1 { "" } else { "es" }
+        );
+        for (branch, merge) in pulls {
+            println!(
+                "    {:<width$} merges with remote {}",
+                branch,
+                merge,
+                width = width
+            );
+        }
+    }
+
+    // What a plain push of each branch of the same name would do.
+    let mut ancestry = Ancestry::new(&repo)?;
+    let mut pushes = Vec::new();
+    for (branch, sha) in &locals {
+        let Some(theirs) = advertised.get(&format!("refs/heads/{}", branch)) else {
+            continue;
+        };
+        let state = if theirs == sha {
+            "up to date"
+        } else if ancestry.is_ancestor(theirs, sha).unwrap_or(false) {
+            "fast-forwardable"
+        } else {
+            "local out of date"
+        };
+        pushes.push((branch.clone(), state));
+    }
+    if !pushes.is_empty() {
+        let width = pushes.iter().map(|(b, _)| b.len()).max().unwrap_or(0);
+        println!(
+            "  Local ref{} configured for 'rit push':",
+            if pushes.len() == 1 { "" } else { "s" }
+        );
+        for (branch, state) in pushes {
+            println!(
+                "    {:<width$} pushes to {:<width$} ({})",
+                branch,
+                branch,
+                state,
+                width = width
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Deletes the ref `refname` and its reflog, if they exist.
+fn ref_remove(repo: &Repository, refname: &str) -> Result<()> {
+    ref_delete(repo, refname)?;
+    let path = repo_file(repo, PathBuf::from("logs").join(refname), false)?;
+    if path.is_file() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::remote_name_valid;
+
+    #[test]
+    fn test_remote_name_valid() {
+        assert!(remote_name_valid("origin"));
+        assert!(remote_name_valid("up-stream.2"));
+        assert!(!remote_name_valid(""));
+        assert!(!remote_name_valid("-x"));
+        assert!(!remote_name_valid("a/b"));
+        assert!(!remote_name_valid("a..b"));
+        assert!(!remote_name_valid("x.lock"));
+    }
+}
@@ -1,16 +1,126 @@
 use std::{
     fs::{self, File},
     io::Write,
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
 };
 
-use anyhow::{bail, Result};
-use ini::Ini;
+use anyhow::{anyhow, bail, Result};
 
+use crate::{profile_phase, ConfigFile};
+
+/// The `extensions.*` keys understood here. A repository of format version
+/// 1 that sets any other may store data in a way this code would break, so
+/// it is not opened at all.
+const REPOSITORY_EXTENSIONS: &[&str] = &["noop", "objectformat", "compatobjectformat"];
+
+/// An opened repository. It holds no open files or caches, only paths and
+/// the parsed config, so one handle can be shared between threads; the
+/// writes that others may race with (objects, refs, HEAD and the index)
+/// replace files atomically.
 pub struct Repository {
     pub worktree: PathBuf,
     pub gitdir: PathBuf,
-    _conf: Ini,
+    /// Where objects, refs and config live. Same as `gitdir` except in a
+    /// linked worktree, whose gitdir (`$GIT_DIR/worktrees/<name>`) only
+    /// holds its own HEAD, index and in-progress state.
+    pub commondir: PathBuf,
+    /// Re-hash every object read and refuse those whose content does not
+    /// match their id, from `core.strictHashVerification` or
+    /// GIT_STRICT_HASH_VERIFICATION.
+    pub strict_hash: bool,
+    pub fsync: FsyncComponents,
+    conf: ConfigFile,
+    /// The system and user config, read once when the repository is opened.
+    global: GlobalConfig,
+    listeners: Vec<ConfigListener>,
+}
+
+/// Called with the section and key of each config value changed through a
+/// `Repository`, or with the section alone when a whole section was
+/// removed or renamed (once under each name).
+pub type ConfigListener = Box<dyn Fn(&str, Option<&str>) + Send + Sync>;
+
+/// Which writes are flushed to stable storage before they count as done,
+/// as `core.fsync` (or the older `core.fsyncObjectFiles`) asks. Nothing is
+/// by default.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct FsyncComponents {
+    /// Loose objects.
+    pub objects: bool,
+    /// Refs, HEAD included.
+    pub refs: bool,
+    pub index: bool,
+}
+
+impl FsyncComponents {
+    /// Parses a `core.fsync` list such as "committed,index", with git's
+    /// component names and aggregates. A leading `-` turns a component
+    /// off; unknown components are ignored.
+    pub fn parse(value: &str) -> Self {
+        let mut ret = Self::default();
+        for component in value.split(',').map(str::trim) {
+            let (on, name) = match component.strip_prefix('-') {
+                Some(name) => (false, name),
+                None => (true, component),
+            };
+            match name {
+                "loose-object" | "pack" | "objects" => ret.objects = on,
+                "reference" => ret.refs = on,
+                "index" => ret.index = on,
+                "committed" => (ret.objects, ret.refs) = (on, on),
+                "added" | "all" => (ret.objects, ret.refs, ret.index) = (on, on, on),
+                "none" => ret = Self::default(),
+                _ => {}
+            }
+        }
+        ret
+    }
+}
+
+/// Whether a config or environment value means true, as git reads them.
+pub fn config_bool(value: &str) -> bool {
+    matches!(
+        value.to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes" | "on"
+    )
+}
+
+/// Parses a boolean config value strictly: true, yes, on or a nonzero
+/// number, false, no, off, zero or empty. None for anything else.
+pub fn config_bool_parse(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" | "" => Some(false),
+        _ => config_int_parse(value).map(|n| n != 0),
+    }
+}
+
+/// Parses an integer config value, scaled by a k, m or g suffix.
+pub fn config_int_parse(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (digits, scale) = match value.char_indices().last()? {
+        (i, 'k' | 'K') => (&value[..i], 1 << 10),
+        (i, 'm' | 'M') => (&value[..i], 1 << 20),
+        (i, 'g' | 'G') => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    digits.parse::<i64>().ok()?.checked_mul(scale)
+}
+
+/// How `Repository::open_ext` looks for a repository.
+#[derive(Clone, Copy, Default)]
+pub struct RepositoryOpenFlags {
+    /// Only look at the given path, not at its parents.
+    pub no_search: bool,
+    /// Keep going up into parents on other filesystems, which git only does
+    /// when GIT_DISCOVERY_ACROSS_FILESYSTEM is set.
+    pub cross_fs: bool,
+    /// Only accept bare repositories, ignoring worktrees and their .git.
+    pub bare: bool,
+    /// Honor GIT_DIR, GIT_WORK_TREE, GIT_CEILING_DIRECTORIES and
+    /// GIT_DISCOVERY_ACROSS_FILESYSTEM.
+    pub from_env: bool,
 }
 
 impl Repository {
@@ -20,39 +130,421 @@ impl Repository {
         if !(force || gitdir.is_dir()) {
             bail!("Not a Git Repository {:?}", path)
         }
+        Self::with_gitdir(path, gitdir, force)
+    }
 
-        let conf = if gitdir.join("config").exists() {
-            Ini::load_from_file(gitdir.join("config"))?
+    fn with_gitdir(path: PathBuf, gitdir: PathBuf, force: bool) -> Result<Self> {
+        let commondir = match fs::read_to_string(gitdir.join("commondir")) {
+            Ok(content) => fs::canonicalize(gitdir.join(content.trim_end()))?,
+            Err(_) => gitdir.clone(),
+        };
+        let conf = if commondir.join("config").exists() {
+            ConfigFile::load(&commondir.join("config"))?
         } else if !force {
             bail!("Configuration file missing");
         } else {
-            Ini::new()
+            ConfigFile::new()
         };
 
         if !force {
             let vers = conf
-                .section(Some("core"))
-                .unwrap()
-                .get("repositoryformatversion")
-                .unwrap()
+                .get("core", "repositoryformatversion")
+                .unwrap_or_else(|| "0".to_string())
                 .parse::<i64>()?;
-            if vers != 0 {
+            // Objects are stored under SHA-1 names, so a repository using
+            // SHA-256 can't be read. Their SHA-256 names can be kept
+            // alongside, see CompatMap.
+            if let Some(format) = conf
+                .get("extensions", "objectformat")
+                .filter(|f| !f.eq_ignore_ascii_case("sha1"))
+            {
+                bail!(
+                    "unsupported object format '{}': only sha1 is supported",
+                    format
+                );
+            }
+            if let Some(format) = conf
+                .get("extensions", "compatobjectformat")
+                .filter(|f| !["sha1", "sha256"].iter().any(|o| f.eq_ignore_ascii_case(o)))
+            {
+                bail!("unsupported compatibility object format '{}'", format);
+            }
+            if vers != 0 && vers != 1 {
                 bail!("Unsupported repositoryformatversion:{}", vers);
             }
+            // Version 0 predates extensions, so git ignores them there.
+            if vers == 1 {
+                if let Some((key, _)) = conf.entries("extensions.").find(|(key, _)| {
+                    let name = &key["extensions.".len()..];
+                    !REPOSITORY_EXTENSIONS
+                        .iter()
+                        .any(|e| name.eq_ignore_ascii_case(e))
+                }) {
+                    bail!("unknown repository extension: {}", key);
+                }
+            }
         }
 
-        Ok(Self {
+        let mut repo = Self {
             gitdir,
             worktree: path,
-            _conf: conf,
-        })
+            commondir,
+            strict_hash: false,
+            fsync: FsyncComponents::default(),
+            conf,
+            global: config_global(),
+            listeners: Vec::new(),
+        };
+        repo.config_apply()?;
+        Ok(repo)
+    }
+
+    /// Sets the fields that mirror config values from the config.
+    fn config_apply(&mut self) -> Result<()> {
+        self.strict_hash = match std::env::var("GIT_STRICT_HASH_VERIFICATION") {
+            Ok(value) => config_bool(&value),
+            Err(_) => self
+                .config_get_bool("core", "strictHashVerification")?
+                .unwrap_or(false),
+        };
+        self.fsync = match self.config_get("core", "fsync") {
+            Some(fsync) => FsyncComponents::parse(&fsync),
+            None => FsyncComponents::default(),
+        };
+        if self.config_get_bool("core", "fsyncObjectFiles")? == Some(true) {
+            self.fsync.objects = true;
+        }
+        Ok(())
+    }
+
+    /// Finds the repository at or above `path` as `flags` say. The search
+    /// never goes up into any of `ceiling_dirs`, though it may start in
+    /// one. Returns None when there is no repository to be found.
+    pub fn open_ext(
+        path: &Path,
+        flags: RepositoryOpenFlags,
+        ceiling_dirs: &[PathBuf],
+    ) -> Result<Option<Self>> {
+        let mut flags = flags;
+        let mut ceilings: Vec<PathBuf> = ceiling_dirs
+            .iter()
+            .filter_map(|d| fs::canonicalize(d).ok())
+            .collect();
+        if flags.from_env {
+            if let Some(gitdir) = std::env::var_os("GIT_DIR") {
+                let gitdir = fs::canonicalize(gitdir)?;
+                let worktree = match std::env::var_os("GIT_WORK_TREE") {
+                    _ if flags.bare => gitdir.clone(),
+                    Some(worktree) => fs::canonicalize(worktree)?,
+                    None => fs::canonicalize(path)?,
+                };
+                return Self::with_gitdir(worktree, gitdir, false).map(Some);
+            }
+            if let Some(dirs) = std::env::var_os("GIT_CEILING_DIRECTORIES") {
+                ceilings
+                    .extend(std::env::split_paths(&dirs).filter_map(|d| fs::canonicalize(d).ok()));
+            }
+            let across = std::env::var("GIT_DISCOVERY_ACROSS_FILESYSTEM").unwrap_or_default();
+            if config_bool(&across) {
+                flags.cross_fs = true;
+            }
+        }
+
+        let start = fs::canonicalize(path)?;
+        let dev = fs::metadata(&start)?.dev();
+        let mut dir = start.as_path();
+        loop {
+            if !flags.bare {
+                if let Some(gitdir) = worktree_gitdir(dir) {
+                    return Self::with_gitdir(dir.to_path_buf(), gitdir, false).map(Some);
+                }
+            }
+            // A .git directory met on the way up belongs to the worktree
+            // above it.
+            let is_dotgit = dir.file_name().is_some_and(|n| n == ".git");
+            if is_gitdir(dir) && (flags.bare || !is_dotgit) {
+                return Self::with_gitdir(dir.to_path_buf(), dir.to_path_buf(), false).map(Some);
+            }
+            if flags.no_search {
+                return Ok(None);
+            }
+            let Some(parent) = dir.parent() else {
+                return Ok(None);
+            };
+            if ceilings.iter().any(|c| c == parent)
+                || (!flags.cross_fs && fs::metadata(parent)?.dev() != dev)
+            {
+                return Ok(None);
+            }
+            dir = parent;
+        }
+    }
+
+    /// Looks up `key` in `section` of the repository's config, falling back
+    /// to the user's and then the system's.
+    pub fn config_get(&self, section: &str, key: &str) -> Option<String> {
+        self.config_get_all(section, key).pop()
+    }
+
+    /// Reads `key` in `section` as a boolean, git style. A value that is
+    /// not one is an error.
+    pub fn config_get_bool(&self, section: &str, key: &str) -> Result<Option<bool>> {
+        self.config_get(section, key)
+            .map(|value| {
+                config_bool_parse(&value).ok_or_else(|| {
+                    anyhow!(
+                        "bad boolean config value '{}' for '{}.{}'",
+                        value,
+                        section,
+                        key
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Reads `key` in `section` as an integer, which may end in k, m or g.
+    pub fn config_get_int(&self, section: &str, key: &str) -> Result<Option<i64>> {
+        self.config_get(section, key)
+            .map(|value| {
+                config_int_parse(&value).ok_or_else(|| {
+                    anyhow!(
+                        "bad numeric config value '{}' for '{}.{}'",
+                        value,
+                        section,
+                        key
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    /// Sets `key` in `section` of the repository's own config file.
+    pub fn config_set(&mut self, section: &str, key: &str, value: &str) -> Result<()> {
+        self.conf.set(section, key, value);
+        self.config_write()?;
+        self.config_changed(section, Some(key))
+    }
+
+    /// Adds one more value to the multi-valued `key` in `section`.
+    pub fn config_add(&mut self, section: &str, key: &str, value: &str) -> Result<()> {
+        self.conf.add(section, key, value);
+        self.config_write()?;
+        self.config_changed(section, Some(key))
+    }
+
+    /// Removes every value of `key` in `section`. Returns whether there
+    /// were any.
+    pub fn config_unset(&mut self, section: &str, key: &str) -> Result<bool> {
+        if !self.conf.unset(section, key) {
+            return Ok(false);
+        }
+        self.config_write()?;
+        self.config_changed(section, Some(key))?;
+        Ok(true)
+    }
+
+    /// The names of the repository's own config sections.
+    pub fn config_sections(&self) -> Vec<String> {
+        self.conf.sections()
+    }
+
+    /// Removes `section` and everything in it. Returns whether it existed.
+    pub fn config_section_remove(&mut self, section: &str) -> Result<bool> {
+        if !self.conf.section_remove(section) {
+            return Ok(false);
+        }
+        self.config_write()?;
+        self.config_changed(section, None)?;
+        Ok(true)
+    }
+
+    /// Moves everything in section `old` to `new`.
+    pub fn config_section_rename(&mut self, old: &str, new: &str) -> Result<()> {
+        if !self.conf.section_rename(old, new) {
+            bail!("no such section: {}", old);
+        }
+        self.config_write()?;
+        self.config_changed(old, None)?;
+        self.config_changed(new, None)
+    }
+
+    /// Has `listener` told about every config change made through this
+    /// handle from now on.
+    pub fn config_watch(&mut self, listener: impl Fn(&str, Option<&str>) + Send + Sync + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    fn config_write(&self) -> Result<()> {
+        self.conf.write(&self.commondir.join("config"))
+    }
+
+    /// Brings the fields read from config up to date after a change, then
+    /// tells the listeners.
+    fn config_changed(&mut self, section: &str, key: Option<&str>) -> Result<()> {
+        self.config_apply()?;
+        for listener in &self.listeners {
+            listener(section, key);
+        }
+        Ok(())
+    }
+
+    /// The repository's own config file.
+    pub fn config_local(&self) -> &ConfigFile {
+        &self.conf
+    }
+
+    /// The system and user config, as read when the repository was opened.
+    pub fn config_global(&self) -> &GlobalConfig {
+        &self.global
+    }
+
+    /// Every value of a multi-valued `key` in `section`, the system's and
+    /// user's config first so that repository values come last.
+    pub fn config_get_all(&self, section: &str, key: &str) -> Vec<String> {
+        let mut values = self.global.get_all(section, key);
+        values.extend(self.conf.get_all(section, key));
+        values
+    }
+
+    /// Every key under `prefix`, as "remote.origin.url", with its value:
+    /// the system's and user's config first, then the repository's.
+    pub fn config_entries(&self, prefix: &str) -> Vec<(String, String)> {
+        let mut entries = self.global.entries(prefix);
+        entries.extend(self.conf.entries(prefix));
+        entries
+    }
+
+    /// Whether the repository has no worktree, its git directory being the
+    /// path it was opened at.
+    pub fn is_bare(&self) -> bool {
+        self.worktree == self.gitdir
     }
 
     pub fn repo_path(&self, p: PathBuf) -> PathBuf {
-        self.gitdir.join(p)
+        if path_is_common(&p) {
+            self.commondir.join(p)
+        } else {
+            self.gitdir.join(p)
+        }
+    }
+}
+
+/// Whether the git directory path `p` is shared by all worktrees rather
+/// than kept per worktree. As in git, HEAD, the index, HEAD's reflog and
+/// the state of in-progress operations are per worktree, and so are the
+/// refs under refs/bisect and refs/worktree.
+fn path_is_common(p: &Path) -> bool {
+    const COMMON: [&str; 14] = [
+        "branches",
+        "config",
+        "description",
+        "hooks",
+        "info",
+        "logs",
+        "modules",
+        "objects",
+        "packed-refs",
+        "refs",
+        "remotes",
+        "rr-cache",
+        "shallow",
+        "worktrees",
+    ];
+    let Some(first) = p.components().next() else {
+        return true;
+    };
+    if !COMMON.iter().any(|c| first.as_os_str() == *c) {
+        return false;
+    }
+    !(p.starts_with("logs/HEAD")
+        || p.starts_with("refs/bisect")
+        || p.starts_with("refs/worktree")
+        || p.starts_with("logs/refs/bisect")
+        || p.starts_with("logs/refs/worktree"))
+}
+
+/// The user's global config file, `~/.gitconfig` unless
+/// GIT_CONFIG_GLOBAL names another.
+pub fn config_global_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("GIT_CONFIG_GLOBAL") {
+        return Some(PathBuf::from(path));
+    }
+    let home = std::env::var("HOME").ok()?;
+    Some(Path::new(&home).join(".gitconfig"))
+}
+
+/// The config files read before a repository's own, lowest precedence
+/// first: the system's ($GIT_CONFIG_SYSTEM or /etc/gitconfig, neither with
+/// GIT_CONFIG_NOSYSTEM), $XDG_CONFIG_HOME/git/config (by default
+/// ~/.config/git/config) and then the user's global config file.
+pub fn config_global_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if !std::env::var("GIT_CONFIG_NOSYSTEM").is_ok_and(|v| config_bool(&v)) {
+        paths.push(match std::env::var("GIT_CONFIG_SYSTEM") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => PathBuf::from("/etc/gitconfig"),
+        });
+    }
+    let xdg = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => Some(PathBuf::from(dir)),
+        Err(_) => std::env::var("HOME")
+            .ok()
+            .map(|home| Path::new(&home).join(".config")),
+    };
+    paths.extend(xdg.map(|dir| dir.join("git/config")));
+    paths.extend(config_global_path());
+    paths
+}
+
+/// The system's and user's config, later files winning, as a repository
+/// sees it before its own.
+#[derive(Clone, Debug, Default)]
+pub struct GlobalConfig {
+    files: Vec<ConfigFile>,
+}
+
+impl GlobalConfig {
+    /// Every value of `key` in `section`, lowest precedence first.
+    pub fn get_all(&self, section: &str, key: &str) -> Vec<String> {
+        self.files
+            .iter()
+            .flat_map(|f| f.get_all(section, key))
+            .collect()
+    }
+
+    /// The value of `key` in `section` that counts.
+    pub fn get(&self, section: &str, key: &str) -> Option<String> {
+        self.get_all(section, key).pop()
+    }
+
+    /// Every key under `prefix` with its value, lowest precedence first.
+    pub fn entries(&self, prefix: &str) -> Vec<(String, String)> {
+        self.files.iter().flat_map(|f| f.entries(prefix)).collect()
+    }
+
+    /// The sections of all the files, each once.
+    pub fn sections(&self) -> Vec<String> {
+        let mut ret: Vec<String> = Vec::new();
+        for section in self.files.iter().flat_map(|f| f.sections()) {
+            if !ret.contains(&section) {
+                ret.push(section);
+            }
+        }
+        ret
     }
 }
 
+/// Reads the system's and user's config. Files that are missing or can't
+/// be read are left out.
+pub fn config_global() -> GlobalConfig {
+    let files = config_global_paths()
+        .iter()
+        .filter_map(|path| ConfigFile::load(path).ok())
+        .collect();
+    GlobalConfig { files }
+}
+
 pub fn repo_create(path: PathBuf) -> Result<Repository> {
     let repo = Repository::new(path, true)?;
 
@@ -89,28 +581,62 @@ pub fn repo_create(path: PathBuf) -> Result<Repository> {
     f.write_all(b"ref: refs/heads/master\n")?;
 
     let p = repo_file(&repo, PathBuf::from("config"), false).expect("create config: ");
-    let conf = repo_default_config();
-    conf.write_to_file(p).expect("write config: ");
+    repo_default_config().write(&p)?;
 
     Ok(repo)
 }
 
-fn repo_default_config() -> Ini {
-    let mut conf = Ini::new();
-    conf.with_section(Some("core"))
-        .set("repositoryformatversion", "0")
-        .set("filemode", "false")
-        .set("bare", "false");
+pub fn repo_default_config() -> ConfigFile {
+    let mut conf = ConfigFile::new();
+    conf.set("core", "repositoryformatversion", "0");
+    conf.set("core", "filemode", "false");
+    conf.set("core", "bare", "false");
     conf
 }
 
+/// Replaces `path` with `data` in one step, so that concurrent readers see
+/// either the old or the new content. As in git, the data goes to
+/// `<path>.lock` first, which also keeps out a second writer until the
+/// first one is done. With `fsync` the data is on disk before the rename.
+pub fn file_write_locked(path: &Path, data: &[u8], fsync: bool) -> Result<()> {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    let lock = PathBuf::from(lock);
+    let mut f = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+    {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            bail!("Unable to create '{}': File exists.", lock.display())
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if let Err(e) = f.write_all(data).and_then(|_| match fsync {
+        true => f.sync_all(),
+        false => Ok(()),
+    }) {
+        let _ = fs::remove_file(&lock);
+        return Err(e.into());
+    }
+    fs::rename(&lock, path)?;
+    Ok(())
+}
+
 pub fn repo_file(repo: &Repository, path: PathBuf, mkdir: bool) -> Result<PathBuf> {
-    if let Some(parent) = path.parent().map(|p| p.to_path_buf()) {
-        if parent != PathBuf::from("") {
-            repo_dir(repo, parent, mkdir)?;
+    // The parent is looked up from the file's own location: logs/HEAD is
+    // per worktree while logs/ is shared.
+    let p = repo.repo_path(path);
+    if let Some(parent) = p.parent() {
+        if parent.exists() && !parent.is_dir() {
+            bail!("Not a directory {:?}", parent);
+        }
+        if mkdir {
+            fs::create_dir_all(parent)?;
         }
     }
-    Ok(repo.repo_path(path))
+    Ok(p)
 }
 
 pub fn repo_dir(repo: &Repository, path: PathBuf, mkdir: bool) -> Result<Option<PathBuf>> {
@@ -132,20 +658,249 @@ pub fn repo_dir(repo: &Repository, path: PathBuf, mkdir: bool) -> Result<Option<
     }
 }
 
+/// Finds the repository containing `path` the way git does, honoring the
+/// GIT_DIR family of environment variables.
 pub fn repo_find(path: &Path, required: bool) -> Result<Option<Repository>> {
-    let path = fs::canonicalize(path)?;
+    let _phase = profile_phase("repo discovery");
+    let flags = RepositoryOpenFlags {
+        from_env: true,
+        ..Default::default()
+    };
+    match Repository::open_ext(path, flags, &[])? {
+        Some(repo) => Ok(Some(repo)),
+        None if required => bail!("No git directory."),
+        None => Ok(None),
+    }
+}
 
-    if path.join(".git").is_dir() {
-        return Ok(Some(Repository::new(path, false)?));
+/// The git directory of the worktree at `path`: either its .git directory
+/// or the directory named by a .git file ("gitdir: <path>"), which is how
+/// submodules point into their superproject's .git/modules.
+fn worktree_gitdir(path: &Path) -> Option<PathBuf> {
+    let dotgit = path.join(".git");
+    if dotgit.is_dir() {
+        return Some(dotgit);
     }
+    let content = fs::read_to_string(&dotgit).ok()?;
+    let target = content.trim_end().strip_prefix("gitdir: ")?;
+    Some(path.join(target))
+}
 
-    if let Some(parent) = path.parent() {
-        return repo_find(parent, required);
+/// Opens the repository at exactly `path`, which is either a worktree with
+/// a .git directory (or .git file) or a bare repository.
+pub fn repo_open(path: &Path) -> Result<Repository> {
+    let flags = RepositoryOpenFlags {
+        no_search: true,
+        ..Default::default()
+    };
+    match Repository::open_ext(path, flags, &[])? {
+        Some(repo) => Ok(repo),
+        None => bail!("Not a Git Repository {:?}", path),
     }
+}
 
-    if required {
-        bail!("No git directory.");
-    } else {
-        Ok(None)
+/// Whether `path` has the layout of a git directory.
+fn is_gitdir(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir()
+}
+
+/// Turns a path given on the command line, relative to the current
+/// directory, into a path relative to the repository's worktree. The path
+/// does not need to exist.
+pub fn repo_relative_path(repo: &Repository, path: &Path) -> Result<String> {
+    let abs = std::env::current_dir()?.join(path);
+    let mut parts: Vec<std::path::Component> = Vec::new();
+    for comp in abs.components() {
+        match comp {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                parts.pop();
+            }
+            c => parts.push(c),
+        }
+    }
+    let normalized: PathBuf = parts.iter().collect();
+    let worktree = fs::canonicalize(&repo.worktree)?;
+    match normalized.strip_prefix(&worktree) {
+        Ok(rel) => Ok(rel.to_string_lossy().to_string()),
+        Err(_) => bail!("{:?} is outside repository at {:?}", path, repo.worktree),
+    }
+}
+
+/// Returns whether the repository-relative `name` is selected by the
+/// pathspec `spec`: either the path itself or something underneath it.
+pub fn path_matches(spec: &str, name: &str) -> bool {
+    spec.is_empty()
+        || name == spec
+        || (name.starts_with(spec) && name.as_bytes().get(spec.len()) == Some(&b'/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::{Arc, Mutex},
+    };
+
+    use super::GlobalConfig;
+    use crate::{
+        object_read, object_write, ref_create, ref_resolve, repo_find, status_dirty_paths,
+        test_utils::{make_commit, test_repo},
+        Ancestry, Blob, ConfigFile, FsyncComponents, Repository, RepositoryOpenFlags,
+    };
+
+    #[test]
+    fn test_strict_hash_and_fsync() {
+        let mut repo = test_repo("strict-hash");
+        let good = object_write(&Blob::new(b"good"), Some(&repo)).unwrap();
+        let bad = object_write(&Blob::new(b"bad"), Some(&repo)).unwrap();
+        // Swap in another object's content, as bit-rot might.
+        let path = |sha: &str| repo.gitdir.join("objects").join(&sha[..2]).join(&sha[2..]);
+        fs::copy(path(&good), path(&bad)).unwrap();
+        assert!(object_read(&repo, &bad).is_ok());
+        repo.strict_hash = true;
+        assert!(object_read(&repo, &bad).is_err());
+        assert!(object_read(&repo, &good).is_ok());
+
+        let fsync = FsyncComponents::parse("committed, -loose-object,index");
+        assert_eq!(
+            fsync,
+            FsyncComponents {
+                objects: false,
+                refs: true,
+                index: true
+            }
+        );
+        assert_eq!(
+            FsyncComponents::parse("all,none"),
+            FsyncComponents::default()
+        );
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_config_layers_and_watch() {
+        let mut repo = test_repo("config-layers");
+        let file = |text: &str| ConfigFile::parse(text).unwrap();
+        repo.global = GlobalConfig {
+            files: vec![
+                file("[core]\n\teditor = ed\n\tpager = more\n[gc]\n\tauto = 0\n"),
+                file("[core]\n\teditor = vi\n"),
+            ],
+        };
+        repo.config_set("gc", "auto", "10").unwrap();
+        assert_eq!(repo.config_get("core", "editor").as_deref(), Some("vi"));
+        assert_eq!(repo.config_get("core", "pager").as_deref(), Some("more"));
+        assert_eq!(repo.config_get("gc", "auto").as_deref(), Some("10"));
+        assert_eq!(repo.config_get_all("gc", "auto"), ["0", "10"]);
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let seen = changes.clone();
+        repo.config_watch(move |section, key| {
+            let key = key.map(str::to_string);
+            seen.lock().unwrap().push((section.to_string(), key));
+        });
+        assert!(!repo.fsync.refs);
+        repo.config_set("core", "fsync", "reference").unwrap();
+        assert!(repo.fsync.refs);
+        repo.config_section_rename("gc", "maintenance").unwrap();
+        let key = |section: &str, key: Option<&str>| (section.to_string(), key.map(String::from));
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [
+                key("core", Some("fsync")),
+                key("gc", None),
+                key("maintenance", None)
+            ]
+        );
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_unknown_extension() {
+        let mut repo = test_repo("unknown-extension");
+        repo.config_set("extensions", "noop", "true").unwrap();
+        repo.config_set("extensions", "worktreeConfig", "true")
+            .unwrap();
+        // A version 0 repository ignores extensions.
+        assert!(repo_find(&repo.worktree, true).is_ok());
+
+        repo.config_set("core", "repositoryformatversion", "1")
+            .unwrap();
+        let err = repo_find(&repo.worktree, true).err().unwrap();
+        assert!(err.to_string().contains("worktreeconfig"), "{}", err);
+        repo.config_unset("extensions", "worktreeConfig").unwrap();
+        assert!(repo_find(&repo.worktree, true).is_ok());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_open_ext() {
+        let repo = test_repo("open-ext");
+        let nested = repo.worktree.join("a/b");
+        fs::create_dir_all(&nested).unwrap();
+        let found = Repository::open_ext(&nested, RepositoryOpenFlags::default(), &[])
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.gitdir, repo.gitdir);
+
+        let flags = RepositoryOpenFlags {
+            no_search: true,
+            ..Default::default()
+        };
+        assert!(Repository::open_ext(&nested, flags, &[]).unwrap().is_none());
+        let ceiling = [repo.worktree.join("a")];
+        let found = Repository::open_ext(&nested, RepositoryOpenFlags::default(), &ceiling);
+        assert!(found.unwrap().is_none());
+
+        // Only the git directory itself is a bare repository.
+        let flags = RepositoryOpenFlags {
+            bare: true,
+            ..Default::default()
+        };
+        assert!(Repository::open_ext(&nested, flags, &[]).unwrap().is_none());
+        let found = Repository::open_ext(&repo.gitdir, flags, &[])
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.worktree, repo.gitdir);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let repo = test_repo("threads");
+        let mut tip = make_commit(&repo, "0", &[]);
+        for i in 1..20 {
+            tip = make_commit(&repo, &i.to_string(), &[&tip]);
+        }
+        ref_create(&repo, "heads/master", &tip).unwrap();
+
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                let repo = &repo;
+                let tip = &tip;
+                s.spawn(move || {
+                    // Everyone writes the same object while the others
+                    // read it.
+                    let blob = object_write(&Blob::new(b"shared"), Some(repo)).unwrap();
+                    assert_eq!(object_read(repo, &blob).unwrap().fmt(), b"blob");
+
+                    let mut ancestry = Ancestry::new(repo).unwrap();
+                    let mut count = 1;
+                    let mut sha = tip.clone();
+                    while let Some(parent) = ancestry.parents(&sha).unwrap().pop() {
+                        sha = parent;
+                        count += 1;
+                    }
+                    assert_eq!(count, 20);
+                    assert_eq!(status_dirty_paths(repo).unwrap(), ["file"]);
+                    if i % 2 == 0 {
+                        ref_create(repo, &format!("heads/b{}", i), tip).unwrap();
+                    }
+                    assert_eq!(ref_resolve(repo, "HEAD").unwrap().as_ref(), Some(tip));
+                });
+            }
+        });
+        fs::remove_dir_all(&repo.worktree).unwrap();
     }
 }
@@ -0,0 +1,316 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use sha1::{Digest, Sha1};
+
+use crate::{merge_file, repo_dir, repo_file, repo_find, Repository};
+
+/// How long a recorded resolution is kept, like `gc.rerereResolved`.
+const RERERE_RESOLVED_DAYS: i64 = 60;
+/// How long a conflict never resolved is kept, like `gc.rerereUnresolved`.
+const RERERE_UNRESOLVED_DAYS: i64 = 15;
+
+/// Whether conflicts are recorded and resolved again: `rerere.enabled`,
+/// or without it whether `rr-cache` exists, as in git.
+pub fn rerere_enabled(repo: &Repository) -> Result<bool> {
+    Ok(match repo.config_get_bool("rerere", "enabled")? {
+        Some(enabled) => enabled,
+        None => repo.repo_path(PathBuf::from("rr-cache")).is_dir(),
+    })
+}
+
+/// A conflicted file with its markers written the same way whatever the
+/// labels, the base section dropped and the two sides of each conflict in
+/// sorted order, so that the same conflict hit again from the other side
+/// gets the same id.
+struct RerereImage {
+    content: Vec<u8>,
+    conflicts: usize,
+    /// The hash of the sides of every conflict, naming its `rr-cache` entry.
+    id: String,
+}
+
+impl RerereImage {
+    fn new(content: &[u8]) -> Self {
+        enum Part {
+            Outside,
+            Ours,
+            Base,
+            Theirs,
+        }
+        let marker = |line: &[u8], c: u8| {
+            line.len() >= 7
+                && line[..7].iter().all(|&b| b == c)
+                && matches!(line.get(7), None | Some(b' ' | b'\n' | b'\r'))
+        };
+        let mut image = Vec::new();
+        let mut hasher = Sha1::new();
+        let mut conflicts = 0;
+        let mut part = Part::Outside;
+        let (mut ours, mut theirs) = (Vec::new(), Vec::new());
+        for line in content.split_inclusive(|&b| b == b'\n') {
+            part = match part {
+                Part::Outside if marker(line, b'<') => Part::Ours,
+                Part::Outside => {
+                    image.extend_from_slice(line);
+                    Part::Outside
+                }
+                Part::Ours | Part::Base if marker(line, b'=') => Part::Theirs,
+                Part::Ours if marker(line, b'|') => Part::Base,
+                Part::Ours => {
+                    ours.extend_from_slice(line);
+                    Part::Ours
+                }
+                Part::Base => Part::Base,
+                Part::Theirs if marker(line, b'>') => {
+                    let (one, two) = match ours <= theirs {
+                        true => (&ours, &theirs),
+                        false => (&theirs, &ours),
+                    };
+                    for side in [one, two] {
+                        hasher.update(side);
+                        hasher.update([0]);
+                    }
+                    image.extend_from_slice(b"<<<<<<<\n");
+                    image.extend_from_slice(one);
+                    image.extend_from_slice(b"=======\n");
+                    image.extend_from_slice(two);
+                    image.extend_from_slice(b">>>>>>>\n");
+                    conflicts += 1;
+                    ours.clear();
+                    theirs.clear();
+                    Part::Outside
+                }
+                Part::Theirs => {
+                    theirs.extend_from_slice(line);
+                    Part::Theirs
+                }
+            };
+        }
+        Self {
+            content: image,
+            conflicts,
+            id: hex::encode(hasher.finalize()),
+        }
+    }
+}
+
+/// After a merge left `paths` conflicted: resolves each conflict seen
+/// before the way it was resolved then, and records the new ones so that
+/// their resolution can be saved by `rerere_record`.
+pub fn rerere_apply(repo: &Repository, paths: &[&str]) -> Result<()> {
+    if !rerere_enabled(repo)? {
+        return Ok(());
+    }
+    let mut merge_rr = rerere_merge_rr_read(repo)?;
+    for &path in paths {
+        let Ok(content) = fs::read(repo.worktree.join(path)) else {
+            continue;
+        };
+        let image = RerereImage::new(&content);
+        if image.conflicts == 0 {
+            continue;
+        }
+        let dir = rerere_dir(repo, &image.id);
+        let resolved = fs::read(dir.join("postimage")).ok().and_then(|post| {
+            // The conflict may sit in other surroundings than it did when
+            // recorded: carry the resolution over as a merge.
+            let pre = fs::read(dir.join("preimage")).unwrap_or_default();
+            let merged = merge_file(&pre, &image.content, &post, "", "");
+            (merged.conflicts == 0).then_some(merged.content)
+        });
+        match resolved {
+            Some(content) => {
+                fs::write(repo.worktree.join(path), content)?;
+                println!("Resolved '{}' using previous resolution.", path);
+            }
+            None => {
+                fs::create_dir_all(&dir)?;
+                fs::write(dir.join("preimage"), &image.content)?;
+                println!("Recorded preimage for '{}'", path);
+            }
+        }
+        merge_rr.retain(|(_, p)| p != path);
+        merge_rr.push((image.id, path.to_string()));
+    }
+    rerere_merge_rr_write(repo, &merge_rr)
+}
+
+/// Saves how each conflict recorded by `rerere_apply` was resolved, once
+/// its file no longer has conflict markers. Those still conflicted stay
+/// pending.
+pub fn rerere_record(repo: &Repository) -> Result<()> {
+    let mut merge_rr = rerere_merge_rr_read(repo)?;
+    let mut pending = Vec::new();
+    for (id, path) in merge_rr.drain(..) {
+        // A file that is gone was resolved by deleting it, which isn't
+        // worth remembering.
+        let Ok(content) = fs::read(repo.worktree.join(&path)) else {
+            continue;
+        };
+        if RerereImage::new(&content).conflicts > 0 {
+            pending.push((id, path));
+            continue;
+        }
+        let dir = rerere_dir(repo, &id);
+        if fs::read(dir.join("postimage")).ok().as_ref() != Some(&content) {
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("postimage"), &content)?;
+            println!("Recorded resolution for '{}'.", path);
+        }
+    }
+    rerere_merge_rr_write(repo, &pending)
+}
+
+/// Records the resolutions made and forgets the rest of the conflicts of
+/// the merge being concluded.
+pub fn rerere_finish(repo: &Repository) -> Result<()> {
+    if !rerere_enabled(repo)? {
+        return Ok(());
+    }
+    rerere_record(repo)?;
+    rerere_clear(repo)
+}
+
+/// Forgets the conflicts of the merge in progress that have no resolution
+/// recorded, as when it is abandoned.
+pub fn rerere_clear(repo: &Repository) -> Result<()> {
+    for (id, _) in rerere_merge_rr_read(repo)? {
+        let dir = rerere_dir(repo, &id);
+        if !dir.join("postimage").exists() && dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+    }
+    let path = repo_file(repo, PathBuf::from("MERGE_RR"), false)?;
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Drops resolutions not used for `gc.rerereResolved` days (60 by
+/// default) and conflicts left unresolved for `gc.rerereUnresolved` days
+/// (15).
+pub fn rerere_gc(repo: &Repository) -> Result<()> {
+    let Some(cache) = repo_dir(repo, PathBuf::from("rr-cache"), false)? else {
+        return Ok(());
+    };
+    let days = |key: &str, default: i64| -> Result<Duration> {
+        let days = repo.config_get_int("gc", key)?.unwrap_or(default).max(0);
+        Ok(Duration::from_secs(days as u64 * 24 * 60 * 60))
+    };
+    let resolved = days("rerereResolved", RERERE_RESOLVED_DAYS)?;
+    let unresolved = days("rerereUnresolved", RERERE_UNRESOLVED_DAYS)?;
+    let pending: Vec<String> = rerere_merge_rr_read(repo)?
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    let now = SystemTime::now();
+    for entry in fs::read_dir(cache)? {
+        let dir = entry?.path();
+        let id = dir.file_name().unwrap_or_default().to_string_lossy();
+        if pending.iter().any(|p| *p == id) {
+            continue;
+        }
+        let (image, keep) = match dir.join("postimage").exists() {
+            true => ("postimage", resolved),
+            false => ("preimage", unresolved),
+        };
+        let age = fs::metadata(dir.join(image))
+            .and_then(|m| m.modified())
+            .map(|modified| now.duration_since(modified).unwrap_or_default());
+        if age.map_or(true, |age| age > keep) {
+            fs::remove_dir_all(&dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// What `rerere` does.
+pub enum RerereAction {
+    /// Save the resolutions made so far.
+    Record,
+    /// List the conflicted paths being tracked.
+    Status,
+    /// Forget the conflicts being tracked.
+    Clear,
+    /// Prune old entries of `rr-cache`.
+    Gc,
+}
+
+pub fn rerere(action: RerereAction) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    match action {
+        RerereAction::Record if rerere_enabled(&repo)? => rerere_record(&repo),
+        RerereAction::Record => Ok(()),
+        RerereAction::Status => {
+            for (_, path) in rerere_merge_rr_read(&repo)? {
+                println!("{}", path);
+            }
+            Ok(())
+        }
+        RerereAction::Clear => rerere_clear(&repo),
+        RerereAction::Gc => rerere_gc(&repo),
+    }
+}
+
+fn rerere_dir(repo: &Repository, id: &str) -> PathBuf {
+    repo.repo_path(PathBuf::from("rr-cache").join(id))
+}
+
+/// The conflicts being tracked, from `MERGE_RR`: "<id>\t<path>\0" each.
+fn rerere_merge_rr_read(repo: &Repository) -> Result<Vec<(String, String)>> {
+    let path = repo_file(repo, PathBuf::from("MERGE_RR"), false)?;
+    let Ok(content) = fs::read_to_string(path) else {
+        return Ok(Vec::new());
+    };
+    Ok(content
+        .split('\0')
+        .filter_map(|entry| entry.split_once('\t'))
+        .map(|(id, path)| (id.to_string(), path.to_string()))
+        .collect())
+}
+
+fn rerere_merge_rr_write(repo: &Repository, entries: &[(String, String)]) -> Result<()> {
+    let path = repo_file(repo, PathBuf::from("MERGE_RR"), false)?;
+    if entries.is_empty() {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    let content: String = entries
+        .iter()
+        .map(|(id, p)| format!("{}\t{}\0", id, p))
+        .collect();
+    fs::write(path, content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RerereImage;
+
+    #[test]
+    fn test_rerere_image() {
+        let one = RerereImage::new(
+            b"a\n<<<<<<< HEAD\nx\n=======\ny\n>>>>>>> topic\nb\n<<<<<<< HEAD\n=======\nz\n>>>>>>> topic\n",
+        );
+        assert_eq!(one.conflicts, 2);
+        assert_eq!(
+            one.content,
+            b"a\n<<<<<<<\nx\n=======\ny\n>>>>>>>\nb\n<<<<<<<\n=======\nz\n>>>>>>>\n"
+        );
+        // The same conflicts merged the other way round, with a base.
+        let two = RerereImage::new(
+            b"a\n<<<<<<< ours\ny\n||||||| base\nw\n=======\nx\n>>>>>>> theirs\nb\n<<<<<<< ours\nz\n=======\n>>>>>>> theirs\n",
+        );
+        assert_eq!(two.content, one.content);
+        assert_eq!(two.id, one.id);
+        assert_eq!(RerereImage::new(b"a\n=======\n").conflicts, 0);
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    checkout_tree, commit_subject, file_write_locked, head_update, index_entry_modified,
+    index_read, index_read_tree, index_write, object_find, ref_resolve, repo_file, repo_find,
+    repo_relative_path, restore_paths, sha_short, Repository,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ResetMode {
+    /// Only move the branch.
+    Soft,
+    /// Move the branch and reset the index.
+    Mixed,
+    /// Move the branch and reset the index and the worktree.
+    Hard,
+}
+
+pub fn reset(mode: ResetMode, args: &[String]) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+
+    // Like git, the first argument is a commit unless it names a file.
+    let (commit, paths) = match args.split_first() {
+        Some((first, rest))
+            if !Path::new(first).exists()
+                && object_find(&repo, first, Some(b"commit"), true).is_ok() =>
+        {
+            (first.as_str(), rest)
+        }
+        _ => ("HEAD", args),
+    };
+
+    if !paths.is_empty() {
+        if mode != ResetMode::Mixed {
+            bail!("Cannot do a soft or hard reset with paths.");
+        }
+        let specs = paths
+            .iter()
+            .map(|p| repo_relative_path(&repo, Path::new(p)))
+            .collect::<Result<Vec<_>>>()?;
+        return restore_paths(&repo, &specs, Some(commit), true, false);
+    }
+
+    let sha = object_find(&repo, commit, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("Failed to resolve '{}' as a valid revision.", commit))?;
+    reset_to(&repo, &sha, mode, &format!("reset: moving to {}", commit))?;
+
+    match mode {
+        ResetMode::Hard => {
+            println!(
+                "HEAD is now at {} {}",
+                sha_short(&repo, &sha),
+                commit_subject(&repo, &sha)?
+            )
+        }
+        ResetMode::Mixed => {
+            let index = index_read(&repo)?;
+            let mut header = false;
+            for entry in &index.entries {
+                if index_entry_modified(&repo, entry)? {
+                    if !header {
+                        println!("Unstaged changes after reset:");
+                        header = true;
+                    }
+                    let status = if repo.worktree.join(&entry.name).exists() {
+                        "M"
+                    } else {
+                        "D"
+                    };
+                    println!("{}\t{}", status, entry.name);
+                }
+            }
+        }
+        ResetMode::Soft => {}
+    }
+    Ok(())
+}
+
+/// Moves the current branch (or the detached HEAD) to `sha`, resetting the
+/// index and worktree as `mode` asks. The previous HEAD is saved in
+/// ORIG_HEAD and the move is logged with `message`.
+pub fn reset_to(repo: &Repository, sha: &str, mode: ResetMode, message: &str) -> Result<()> {
+    match mode {
+        ResetMode::Hard => checkout_tree(repo, sha, true)?,
+        ResetMode::Mixed => index_reset(repo, sha)?,
+        ResetMode::Soft => {}
+    }
+
+    if let Some(old) = ref_resolve(repo, "HEAD")? {
+        file_write_locked(
+            &repo_file(repo, PathBuf::from("ORIG_HEAD"), false)?,
+            format!("{}\n", old).as_bytes(),
+            repo.fsync.refs,
+        )?;
+    }
+    head_update(repo, sha, message)
+}
+
+/// Rewrites the index to match the tree of `tree_ish`. Entries whose content
+/// is unchanged keep their stat data, so unmodified files stay clean.
+pub fn index_reset(repo: &Repository, tree_ish: &str) -> Result<()> {
+    let mut index = index_read(repo)?;
+    index_read_tree(repo, &mut index, tree_ish, "")?;
+    index_write(repo, &index)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{reset_to, ResetMode};
+    use crate::{
+        checkout_tree, index_read, ref_create, ref_resolve,
+        test_utils::{make_commit, test_repo},
+        tree_to_leaves,
+    };
+
+    #[test]
+    fn test_reset_modes() {
+        let repo = test_repo("reset");
+        let first = make_commit(&repo, "one", &[]);
+        let second = make_commit(&repo, "two", &[&first]);
+        checkout_tree(&repo, &second, true).unwrap();
+        ref_create(&repo, "heads/master", &second).unwrap();
+        let blob = |commit: &str| tree_to_leaves(&repo, commit, "").unwrap()[0].sha.clone();
+        let index_blob = || index_read(&repo).unwrap().entries[0].sha.clone();
+        let worktree = || fs::read_to_string(repo.worktree.join("file")).unwrap();
+
+        // --soft only moves the branch.
+        reset_to(&repo, &first, ResetMode::Soft, "reset: moving to HEAD~").unwrap();
+        assert_eq!(
+            ref_resolve(&repo, "refs/heads/master").unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(
+            ref_resolve(&repo, "ORIG_HEAD").unwrap(),
+            Some(second.clone())
+        );
+        assert_eq!(index_blob(), blob(&second));
+        assert_eq!(worktree(), "two");
+
+        // --mixed resets the index too, keeping the worktree.
+        reset_to(&repo, &second, ResetMode::Soft, "reset").unwrap();
+        reset_to(&repo, &first, ResetMode::Mixed, "reset").unwrap();
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(first.clone()));
+        assert_eq!(index_blob(), blob(&first));
+        assert_eq!(worktree(), "two");
+
+        // --hard throws local changes away.
+        reset_to(&repo, &second, ResetMode::Hard, "reset").unwrap();
+        assert_eq!(index_blob(), blob(&second));
+        assert_eq!(worktree(), "two");
+        fs::write(repo.worktree.join("file"), "scratch").unwrap();
+        reset_to(&repo, &first, ResetMode::Hard, "reset").unwrap();
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(first.clone()));
+        assert_eq!(index_blob(), blob(&first));
+        assert_eq!(worktree(), "one");
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
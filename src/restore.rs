@@ -0,0 +1,195 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    checkout_file, index_read, index_write, path_matches, remove_file, repo_find,
+    repo_relative_path, tree_mode_split, tree_to_leaves, IndexEntry, Repository, TreeLeaf,
+};
+
+pub fn restore(
+    paths: &[PathBuf],
+    source: Option<&str>,
+    staged: bool,
+    worktree: bool,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let specs = paths
+        .iter()
+        .map(|p| repo_relative_path(&repo, p))
+        .collect::<Result<Vec<_>>>()?;
+
+    // --staged alone only touches the index; otherwise the worktree is the
+    // default target.
+    let worktree = worktree || !staged;
+
+    // The index is restored from HEAD by default, the worktree from the index.
+    let source = match source {
+        Some(s) => Some(s),
+        None if staged => Some("HEAD"),
+        None => None,
+    };
+    restore_paths(&repo, &specs, source, staged, worktree)
+}
+
+/// Restores the entries selected by `specs`. The content comes from the
+/// tree-ish `source`, or from the index if it is `None`. Paths that are
+/// tracked but absent from the source are removed from the targets.
+pub fn restore_paths(
+    repo: &Repository,
+    specs: &[String],
+    source: Option<&str>,
+    staged: bool,
+    worktree: bool,
+) -> Result<()> {
+    let mut index = index_read(repo)?;
+
+    let wanted: BTreeMap<String, TreeLeaf> = match source {
+        Some(tree_ish) => tree_to_leaves(repo, tree_ish, "")?
+            .into_iter()
+            .map(|leaf| (leaf.path.clone(), leaf))
+            .collect(),
+        None => index
+            .entries
+            .iter()
+            .map(|e| {
+                let leaf = TreeLeaf {
+                    mode: e.tree_mode(),
+                    path: e.name.clone(),
+                    sha: e.sha.clone(),
+                };
+                (e.name.clone(), leaf)
+            })
+            .collect(),
+    };
+
+    let mut selected = BTreeSet::new();
+    for spec in specs {
+        let mut matched = false;
+        let candidates = wanted.keys().chain(index.entries.iter().map(|e| &e.name));
+        for name in candidates {
+            if path_matches(spec, name) {
+                selected.insert(name.clone());
+                matched = true;
+            }
+        }
+        if !matched && !spec.is_empty() {
+            bail!("pathspec '{}' did not match any file(s) known to git", spec);
+        }
+    }
+
+    for name in &selected {
+        match wanted.get(name) {
+            Some(leaf) => {
+                if worktree {
+                    checkout_file(repo, leaf)?;
+                }
+                if staged {
+                    let (mode_type, mode_perms) = tree_mode_split(&leaf.mode)?;
+                    // Without fresh stat data the next status rehashes the
+                    // worktree file, which is what we want after --staged.
+                    let entry = if worktree {
+                        IndexEntry::from_file(repo, name, &leaf.sha, &leaf.mode)?
+                    } else {
+                        IndexEntry {
+                            mode_type,
+                            mode_perms,
+                            sha: leaf.sha.clone(),
+                            name: name.clone(),
+                            ..Default::default()
+                        }
+                    };
+                    index.entries.retain(|e| &e.name != name);
+                    index.entries.push(entry);
+                } else if source.is_none() {
+                    // Restoring from the index: refresh its stat data.
+                    if let Some(pos) = index.entries.iter().position(|e| &e.name == name) {
+                        index.entries[pos] =
+                            IndexEntry::from_file(repo, name, &leaf.sha, &leaf.mode)?;
+                    }
+                }
+            }
+            None => {
+                if worktree {
+                    remove_file(repo, name)?;
+                }
+                if staged {
+                    index.entries.retain(|e| &e.name != name);
+                }
+            }
+        }
+    }
+
+    index.entries.sort_by(|a, b| a.name.cmp(&b.name));
+    index_write(repo, &index)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::restore_paths;
+    use crate::{
+        checkout_tree, index_read, index_write, object_write, ref_create,
+        test_utils::{make_commit, test_repo},
+        tree_to_leaves, Blob, IndexEntry,
+    };
+
+    #[test]
+    fn test_restore_staged_and_source() {
+        let repo = test_repo("restore");
+        let first = make_commit(&repo, "one", &[]);
+        let second = make_commit(&repo, "two", &[&first]);
+        checkout_tree(&repo, &second, true).unwrap();
+        ref_create(&repo, "heads/master", &second).unwrap();
+        let head_blob = tree_to_leaves(&repo, &second, "").unwrap()[0].sha.clone();
+        let index_sha = |name: &str| {
+            let index = index_read(&repo).unwrap();
+            index
+                .entries
+                .iter()
+                .find(|e| e.name == name)
+                .unwrap()
+                .sha
+                .clone()
+        };
+
+        // --staged puts HEAD's version back in the index only.
+        fs::write(repo.worktree.join("file"), "staged").unwrap();
+        let blob = object_write(&Blob::new(b"staged"), Some(&repo)).unwrap();
+        let mut index = index_read(&repo).unwrap();
+        index.entries = vec![IndexEntry::from_file(&repo, "file", &blob, b"100644").unwrap()];
+        index_write(&repo, &index).unwrap();
+        let specs = ["file".to_string()];
+        restore_paths(&repo, &specs, Some("HEAD"), true, false).unwrap();
+        assert_eq!(index_sha("file"), head_blob);
+        assert_eq!(
+            fs::read_to_string(repo.worktree.join("file")).unwrap(),
+            "staged"
+        );
+
+        // --source only writes the worktree unless --staged is given too.
+        restore_paths(&repo, &specs, Some(&first), false, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(repo.worktree.join("file")).unwrap(),
+            "one"
+        );
+        assert_eq!(index_sha("file"), head_blob);
+        restore_paths(&repo, &specs, Some(&first), true, true).unwrap();
+        assert_ne!(index_sha("file"), head_blob);
+
+        // From the index, by default.
+        fs::write(repo.worktree.join("file"), "scratch").unwrap();
+        restore_paths(&repo, &specs, None, false, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(repo.worktree.join("file")).unwrap(),
+            "one"
+        );
+
+        assert!(restore_paths(&repo, &["nope".to_string()], None, false, true).is_err());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
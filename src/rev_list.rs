@@ -0,0 +1,57 @@
+use std::{path::Path, time::SystemTime};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{date_parse_approx, object_find, ref_list_flat, repo_find, RevWalk};
+
+/// Which commits `rev-list` walks and what it prints of them.
+#[derive(Default)]
+pub struct RevListOptions {
+    /// Start from every ref and HEAD as well as the revisions given.
+    pub all: bool,
+    /// Print how many commits there are instead of listing them.
+    pub count: bool,
+    pub max_count: Option<usize>,
+    /// Only commits at or after this date.
+    pub since: Option<String>,
+    /// Only commits at or before this date.
+    pub until: Option<String>,
+}
+
+/// Lists the commits `revs` select, newest first, as `RevWalk` does.
+pub fn rev_list(revs: &[String], opts: &RevListOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let mut revs = revs.to_vec();
+    if opts.all {
+        // Tags of trees and blobs start nothing; neither does an unborn HEAD.
+        let refs = ref_list_flat(&repo, None, None)?.into_values();
+        revs.extend(
+            refs.chain(std::iter::once("HEAD".to_string()))
+                .filter(|r| matches!(object_find(&repo, r, Some(b"commit"), true), Ok(Some(_)))),
+        );
+    }
+    if revs.is_empty() {
+        bail!("usage: rev-list [<options>] <commit>... [--all]");
+    }
+
+    let now = SystemTime::now();
+    let date = |value: &str| {
+        date_parse_approx(value, now).ok_or_else(|| anyhow!("invalid date '{}'", value))
+    };
+    let mut walk = RevWalk::new(&repo, &revs)?;
+    if let Some(since) = &opts.since {
+        walk = walk.since(date(since)?);
+    }
+    if let Some(until) = &opts.until {
+        walk = walk.until(date(until)?);
+    }
+    let walk = walk.take(opts.max_count.unwrap_or(usize::MAX));
+    if opts.count {
+        println!("{}", walk.collect::<Result<Vec<_>>>()?.len());
+        return Ok(());
+    }
+    for sha in walk {
+        println!("{}", sha?);
+    }
+    Ok(())
+}
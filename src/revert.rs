@@ -0,0 +1,176 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_get_active, commit_create, commit_subject, file_write_locked, gc_auto, head_update,
+    merge_checkout, merge_conflicts_print, merge_trees, object_find, object_read, object_write,
+    ref_resolve, repo_file, repo_find, sha_short, status_dirty_paths, Commit, Repository, Tree,
+};
+
+pub fn revert(name: &str) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    revert_rev(&repo, name)
+}
+
+/// Undoes the change `name` made relative to its parent with a new commit
+/// on HEAD. On conflict, REVERT_HEAD and MERGE_MSG are left behind for
+/// `rit commit` to conclude the revert.
+pub fn revert_rev(repo: &Repository, name: &str) -> Result<()> {
+    for (state, what) in [
+        ("REVERT_HEAD", "revert"),
+        ("CHERRY_PICK_HEAD", "cherry-pick"),
+        ("MERGE_HEAD", "merge"),
+    ] {
+        if repo_file(repo, PathBuf::from(state), false)?.exists() {
+            bail!("You have not concluded your {} ({} exists).", what, state);
+        }
+    }
+    let dirty = status_dirty_paths(repo)?;
+    if !dirty.is_empty() {
+        bail!(
+            "Your local changes would be overwritten by revert:\n\t{}",
+            dirty.join("\n\t")
+        );
+    }
+
+    let sha = object_find(repo, name, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("bad revision '{}'", name))?;
+    let obj = object_read(repo, &sha)?;
+    let commit = obj
+        .as_any()
+        .downcast_ref::<Commit>()
+        .ok_or_else(|| anyhow!("{} is not a commit", sha))?;
+    let parents = commit.parents();
+    if parents.len() > 1 {
+        bail!("commit {} is a merge, which cannot be reverted", sha);
+    }
+    let head = ref_resolve(repo, "HEAD")?.ok_or_else(|| anyhow!("No commits yet on HEAD"))?;
+
+    let tree_of = |sha: &str| -> Result<String> {
+        object_find(repo, sha, Some(b"tree"), true)?.ok_or_else(|| anyhow!("{} has no tree", sha))
+    };
+    // Reverting a root commit goes back to the empty tree.
+    let parent_tree = match parents.first() {
+        Some(parent) => tree_of(parent)?,
+        None => object_write(&Tree { items: Vec::new() }, Some(repo))?,
+    };
+    let head_tree = tree_of(&head)?;
+    let subject = commit_subject(repo, &sha)?;
+    let label = match parents.first() {
+        Some(_) => format!("parent of {} ({})", sha_short(repo, &sha), subject),
+        None => format!("empty tree before {}", sha_short(repo, &sha)),
+    };
+
+    let merged = merge_trees(
+        repo,
+        Some(&tree_of(&sha)?),
+        &head_tree,
+        &parent_tree,
+        "HEAD",
+        &label,
+    )?;
+    let tree = merge_checkout(repo, &merged)?;
+    let message = format!("Revert \"{}\"\n\nThis reverts commit {}.\n", subject, sha);
+
+    if !merged.conflicts.is_empty() {
+        merge_conflicts_print(&merged.conflicts);
+        file_write_locked(
+            &repo_file(repo, PathBuf::from("REVERT_HEAD"), false)?,
+            format!("{}\n", sha).as_bytes(),
+            repo.fsync.refs,
+        )?;
+        file_write_locked(
+            &repo_file(repo, PathBuf::from("MERGE_MSG"), false)?,
+            message.as_bytes(),
+            false,
+        )?;
+        bail!(
+            "could not revert {}... {}\n\
+             After resolving the conflicts, mark them with \"rit add <paths>\"\n\
+             and run \"rit commit\".",
+            sha_short(repo, &sha),
+            subject
+        );
+    }
+    if tree == head_tree {
+        bail!(
+            "The changes of {} are not in HEAD; nothing to revert.",
+            sha_short(repo, &sha)
+        );
+    }
+
+    let new = commit_create(repo, &tree, &[head], None, &message)?;
+    head_update(
+        repo,
+        &new,
+        &format!("revert: {}", message.lines().next().unwrap_or("")),
+    )?;
+    let branch = branch_get_active(repo)?.unwrap_or_else(|| "detached HEAD".to_string());
+    println!(
+        "[{} {}] {}",
+        branch,
+        sha_short(repo, &new),
+        message.lines().next().unwrap_or_default()
+    );
+    gc_auto(repo);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::revert_rev;
+    use crate::{
+        checkout_tree, head_update, object_read, ref_create, ref_resolve,
+        test_utils::{make_commit, test_repo},
+        Commit,
+    };
+
+    #[test]
+    fn test_revert() {
+        let repo = test_repo("revert");
+        let base = make_commit(&repo, "one", &[]);
+        let change = make_commit(&repo, "two", &[&base]);
+        checkout_tree(&repo, &change, true).unwrap();
+        ref_create(&repo, "heads/master", &change).unwrap();
+
+        // A clean revert commits the old content back on HEAD.
+        revert_rev(&repo, &change).unwrap();
+        let reverted = ref_resolve(&repo, "HEAD").unwrap().unwrap();
+        let obj = object_read(&repo, &reverted).unwrap();
+        let commit = obj.as_any().downcast_ref::<Commit>().unwrap();
+        assert_eq!(commit.parents(), vec![change.clone()]);
+        assert_eq!(
+            commit.message(),
+            format!("Revert \"two\"\n\nThis reverts commit {}.\n", change)
+        );
+        assert_eq!(
+            fs::read_to_string(repo.worktree.join("file")).unwrap(),
+            "one"
+        );
+        assert!(revert_rev(&repo, &change).is_err());
+
+        // A conflicting one stops with the state for `rit commit`.
+        let other = make_commit(&repo, "three", &[&change]);
+        checkout_tree(&repo, &other, true).unwrap();
+        head_update(&repo, &other, "reset").unwrap();
+        let err = revert_rev(&repo, &change).unwrap_err();
+        assert!(err.to_string().starts_with("could not revert"));
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(other));
+        assert_eq!(
+            fs::read_to_string(repo.gitdir.join("REVERT_HEAD")).unwrap(),
+            format!("{}\n", change)
+        );
+        assert!(fs::read_to_string(repo.gitdir.join("MERGE_MSG"))
+            .unwrap()
+            .starts_with("Revert \"two\""));
+        let file = fs::read_to_string(repo.worktree.join("file")).unwrap();
+        assert!(file.starts_with("<<<<<<< HEAD\nthree"));
+        assert!(file.contains("one"));
+        let err = revert_rev(&repo, &change).unwrap_err();
+        assert!(err.to_string().contains("REVERT_HEAD exists"));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use glob::Pattern;
+
+use crate::{
+    head_leaves, index_entry_modified, index_read, index_write, path_matches, remove_file,
+    repo_find, repo_relative_path, Index, Repository,
+};
+
+/// How `rm` treats the paths it is given.
+#[derive(Default)]
+pub struct RmOptions {
+    /// Descend into directories.
+    pub recursive: bool,
+    /// Skip the safety checks on local and staged changes.
+    pub force: bool,
+    /// Only unstage, keeping the worktree files.
+    pub cached: bool,
+    /// Don't fail when a pathspec matches nothing.
+    pub ignore_unmatch: bool,
+    /// Only report what would be removed.
+    pub dry_run: bool,
+    pub quiet: bool,
+}
+
+pub fn rm(paths: &[PathBuf], opts: &RmOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let specs = paths
+        .iter()
+        .map(|p| repo_relative_path(&repo, p))
+        .collect::<Result<Vec<_>>>()?;
+    let mut index = index_read(&repo)?;
+    let names = rm_select(&index, &specs, opts)?;
+    if !opts.force {
+        rm_check(&repo, &index, &names, opts.cached)?;
+    }
+
+    for name in &names {
+        if !opts.quiet {
+            println!("rm '{}'", name);
+        }
+    }
+    if opts.dry_run {
+        return Ok(());
+    }
+    index.entries.retain(|e| !names.contains(&e.name));
+    if !opts.cached {
+        for name in &names {
+            rm_worktree(&repo, name)?;
+        }
+    }
+    index_write(&repo, &index)
+}
+
+/// The index entries the pathspecs `specs` select. A spec is a path, a
+/// directory (only with `recursive`) or a glob, whose wildcards also match
+/// slashes as in git.
+fn rm_select(index: &Index, specs: &[String], opts: &RmOptions) -> Result<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    for spec in specs {
+        let pattern = if spec.contains(['*', '?', '[']) {
+            Some(Pattern::new(spec)?)
+        } else {
+            None
+        };
+        let mut matched = false;
+        for entry in &index.entries {
+            let hit = match &pattern {
+                Some(pattern) => pattern.matches(&entry.name),
+                None => path_matches(spec, &entry.name),
+            };
+            if !hit {
+                continue;
+            }
+            if pattern.is_none() && entry.name != *spec && !opts.recursive {
+                bail!("not removing '{}' recursively without -r", spec);
+            }
+            names.insert(entry.name.clone());
+            matched = true;
+        }
+        if !matched && !opts.ignore_unmatch {
+            bail!("pathspec '{}' did not match any files", spec);
+        }
+    }
+    Ok(names)
+}
+
+/// Refuses to lose work: staged content that matches neither HEAD nor the
+/// worktree, and unless only unstaging, any staged change or local
+/// modification.
+fn rm_check(
+    repo: &Repository,
+    index: &Index,
+    names: &BTreeSet<String>,
+    cached: bool,
+) -> Result<()> {
+    let head = head_leaves(repo)?;
+    let (mut both, mut staged, mut local) = (Vec::new(), Vec::new(), Vec::new());
+    for entry in index.entries.iter().filter(|e| names.contains(&e.name)) {
+        let in_head = head
+            .get(&entry.name)
+            .is_some_and(|leaf| leaf.sha == entry.sha && leaf.mode == entry.tree_mode());
+        let exists = fs::symlink_metadata(repo.worktree.join(&entry.name)).is_ok();
+        let modified = exists && index_entry_modified(repo, entry)?;
+        if !in_head && modified {
+            both.push(entry.name.as_str());
+        } else if !cached && !in_head {
+            staged.push(entry.name.as_str());
+        } else if !cached && modified {
+            local.push(entry.name.as_str());
+        }
+    }
+
+    let mut errors = Vec::new();
+    let list = |what: &str, files: &[&str], hint: &str| {
+        let (subject, verb) = match files.len() {
+            1 => ("file", "has"),
+            _ => ("files", "have"),
+        };
+        format!(
+            "the following {} {} {}:\n    {}\n({})",
+            subject,
+            verb,
+            what,
+            files.join("\n    "),
+            hint
+        )
+    };
+    if !both.is_empty() {
+        errors.push(list(
+            "staged content different from both the\nfile and the HEAD",
+            &both,
+            "use -f to force removal",
+        ));
+    }
+    if !staged.is_empty() {
+        errors.push(list(
+            "changes staged in the index",
+            &staged,
+            "use --cached to keep the file, or -f to force removal",
+        ));
+    }
+    if !local.is_empty() {
+        errors.push(list(
+            "local modifications",
+            &local,
+            "use --cached to keep the file, or -f to force removal",
+        ));
+    }
+    if !errors.is_empty() {
+        bail!(errors.join("\n"));
+    }
+    Ok(())
+}
+
+/// Deletes a removed entry from the worktree. A submodule goes with its
+/// whole checkout, unless its history lives in its own .git directory
+/// rather than in the superproject's.
+fn rm_worktree(repo: &Repository, name: &str) -> Result<()> {
+    let full_path = repo.worktree.join(name);
+    if full_path.is_dir() && !full_path.is_symlink() {
+        if full_path.join(".git").is_dir() {
+            bail!(
+                "submodule '{}' uses a .git directory\n\
+                 (use 'rm -rf' if you really want to remove it including all of its history)",
+                name
+            );
+        }
+        // Leave it empty for remove_file, which also prunes the parents.
+        fs::remove_dir_all(&full_path)?;
+        fs::create_dir(&full_path)?;
+    }
+    remove_file(repo, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Index, IndexEntry};
+
+    use super::{rm_select, RmOptions};
+
+    #[test]
+    fn test_rm_select() {
+        let mut index = Index::default();
+        for name in ["a.txt", "dir/b.txt", "dir/sub/c.rs", "dirt"] {
+            index.entries.push(IndexEntry {
+                name: name.to_string(),
+                ..Default::default()
+            });
+        }
+        let select = |specs: &[&str], recursive: bool| {
+            let specs: Vec<String> = specs.iter().map(|s| s.to_string()).collect();
+            let opts = RmOptions {
+                recursive,
+                ..Default::default()
+            };
+            rm_select(&index, &specs, &opts).map(|names| names.into_iter().collect::<Vec<_>>())
+        };
+
+        assert_eq!(select(&["a.txt"], false).unwrap(), ["a.txt"]);
+        assert!(select(&["dir"], false).is_err());
+        assert_eq!(
+            select(&["dir"], true).unwrap(),
+            ["dir/b.txt", "dir/sub/c.rs"]
+        );
+        assert_eq!(select(&["*.txt"], false).unwrap(), ["a.txt", "dir/b.txt"]);
+        assert!(select(&["missing"], false).is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use anyhow::{anyhow, bail, Result};
+use flate2::read::GzDecoder;
+
+use crate::{
+    config_bool_parse, pkt_flush, pkt_write, receive_pack, receive_pack_advertise, repo_open,
+    upload_pack, upload_pack_advertise,
+};
+
+/// The largest request body accepted, after decompression, so that a
+/// client can't make the server allocate whatever it claims to send.
+const SERVE_MAX_BODY: usize = 256 << 20;
+
+/// Settings shared by every connection of `rit serve`.
+struct ServeConfig {
+    root: PathBuf,
+    /// The expected `Authorization` header value, if basic auth is on.
+    auth: Option<String>,
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+struct HttpResponse {
+    status: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn new(status: &'static str, content_type: &str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            headers: vec![("Content-Type", content_type.to_string())],
+            body,
+        }
+    }
+
+    fn text(status: &'static str, body: &str) -> Self {
+        Self::new(status, "text/plain", format!("{}\n", body).into_bytes())
+    }
+}
+
+/// Serves every repository under `root` over git's smart HTTP protocol,
+/// optionally behind basic auth given as `user:password`. Pushing is only
+/// allowed with auth, or to repositories that set http.receivepack.
+pub fn serve(http: bool, root: &Path, listen: &str, auth: Option<&str>) -> Result<()> {
+    if !http {
+        bail!("Only the HTTP transport is supported; pass --http");
+    }
+    let config = Arc::new(ServeConfig {
+        root: std::fs::canonicalize(root)?,
+        auth: auth.map(|a| format!("Basic {}", base64_encode(a.as_bytes()))),
+    });
+
+    let listener = TcpListener::bind(listen)?;
+    println!(
+        "Serving {:?} on http://{}",
+        config.root,
+        listener.local_addr()?
+    );
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let config = Arc::clone(&config);
+        thread::spawn(move || {
+            if let Err(e) = serve_connection(&config, stream) {
+                eprintln!("rit serve: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+fn serve_connection(config: &ServeConfig, mut stream: TcpStream) -> Result<()> {
+    let response = match http_read_request(&mut stream) {
+        Ok(request) => match http_route(config, &request) {
+            Ok(r) => r,
+            Err(e) => HttpResponse::text("500 Internal Server Error", &e.to_string()),
+        },
+        Err(e) => HttpResponse::text("400 Bad Request", &e.to_string()),
+    };
+
+    let mut head = format!("HTTP/1.1 {}\r\n", response.status);
+    for (k, v) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", k, v));
+    }
+    head.push_str(&format!(
+        "Content-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        response.body.len()
+    ));
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn http_read_request(stream: impl Read) -> Result<HttpRequest> {
+    // Headers can't be longer than a body could, either.
+    let mut reader = BufReader::new(stream.take(SERVE_MAX_BODY as u64));
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts
+        .next()
+        .ok_or_else(|| anyhow!("Empty request"))?
+        .to_string();
+    let target = parts
+        .next()
+        .ok_or_else(|| anyhow!("Missing request target"))?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let (path, query) = (path.to_string(), query.to_string());
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = header.split_once(':') {
+            headers.push((k.trim().to_string(), v.trim().to_string()));
+        }
+    }
+    let mut request = HttpRequest {
+        method,
+        path,
+        query,
+        headers,
+        body: Vec::new(),
+    };
+
+    // git sends large requests chunked, and may gzip upload-pack requests.
+    let mut body = Vec::new();
+    if request
+        .header("Transfer-Encoding")
+        .is_some_and(|v| v.eq_ignore_ascii_case("chunked"))
+    {
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let size_hex = line.trim_end().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size_hex, 16)
+                .map_err(|_| anyhow!("Bad chunk size {:?}", size_hex))?;
+            if size > SERVE_MAX_BODY - body.len() {
+                bail!("Request body is larger than {} bytes", SERVE_MAX_BODY);
+            }
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk)?;
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    } else if let Some(len) = request.header("Content-Length") {
+        let len: usize = len.parse()?;
+        if len > SERVE_MAX_BODY {
+            bail!("Request body is larger than {} bytes", SERVE_MAX_BODY);
+        }
+        body.resize(len, 0);
+        reader.read_exact(&mut body)?;
+    }
+    if request
+        .header("Content-Encoding")
+        .is_some_and(|v| v.contains("gzip"))
+    {
+        let mut decoded = Vec::new();
+        GzDecoder::new(&body[..])
+            .take(SERVE_MAX_BODY as u64 + 1)
+            .read_to_end(&mut decoded)?;
+        if decoded.len() > SERVE_MAX_BODY {
+            bail!("Request body is larger than {} bytes", SERVE_MAX_BODY);
+        }
+        body = decoded;
+    }
+    request.body = body;
+    Ok(request)
+}
+
+fn http_route(config: &ServeConfig, request: &HttpRequest) -> Result<HttpResponse> {
+    if let Some(expected) = &config.auth {
+        if request.header("Authorization") != Some(expected.as_str()) {
+            let mut response = HttpResponse::text("401 Unauthorized", "Authentication required");
+            response
+                .headers
+                .push(("WWW-Authenticate", "Basic realm=\"rit\"".to_string()));
+            return Ok(response);
+        }
+    }
+
+    let not_found = || HttpResponse::text("404 Not Found", "Not found");
+    let (repo_path, endpoint) = if let Some(p) = request.path.strip_suffix("/info/refs") {
+        (p, "info/refs")
+    } else if let Some(p) = request.path.strip_suffix("/git-upload-pack") {
+        (p, "git-upload-pack")
+    } else if let Some(p) = request.path.strip_suffix("/git-receive-pack") {
+        (p, "git-receive-pack")
+    } else {
+        return Ok(not_found());
+    };
+
+    // Never serve anything outside the root.
+    let rel = Path::new(repo_path.trim_start_matches('/'));
+    if rel.components().any(|c| !matches!(c, Component::Normal(_))) {
+        return Ok(not_found());
+    }
+    let repo = match repo_open(&config.root.join(rel)) {
+        Ok(r) => r,
+        Err(_) => return Ok(not_found()),
+    };
+
+    let service = request
+        .query
+        .split('&')
+        .find_map(|kv| kv.strip_prefix("service="));
+
+    // Like git http-backend, pushing needs http.receivepack, or failing
+    // that an authenticated user.
+    let pushing = endpoint == "git-receive-pack" || service == Some("git-receive-pack");
+    let push_allowed = match repo.config_get("http", "receivepack") {
+        Some(value) => config_bool_parse(&value) == Some(true),
+        None => config.auth.is_some(),
+    };
+    if pushing && !push_allowed {
+        return Ok(HttpResponse::text(
+            "403 Forbidden",
+            "Pushing is disabled: set http.receivepack or serve with --auth",
+        ));
+    }
+
+    match (request.method.as_str(), endpoint) {
+        ("GET", "info/refs") => {
+            let (service, adv) = match service {
+                Some("git-upload-pack") => ("git-upload-pack", upload_pack_advertise(&repo)?),
+                Some("git-receive-pack") => ("git-receive-pack", receive_pack_advertise(&repo)?),
+                _ => {
+                    return Ok(HttpResponse::text(
+                        "403 Forbidden",
+                        "Only the smart HTTP protocol is supported",
+                    ))
+                }
+            };
+            let mut body = Vec::new();
+            pkt_write(&mut body, format!("# service={}\n", service).as_bytes());
+            pkt_flush(&mut body);
+            body.extend(adv);
+            Ok(HttpResponse::new(
+                "200 OK",
+                &format!("application/x-{}-advertisement", service),
+                body,
+            ))
+        }
+        ("POST", "git-upload-pack") => Ok(HttpResponse::new(
+            "200 OK",
+            "application/x-git-upload-pack-result",
+            upload_pack(&repo, &request.body)?,
+        )),
+        ("POST", "git-receive-pack") => Ok(HttpResponse::new(
+            "200 OK",
+            "application/x-git-receive-pack-result",
+            receive_pack(&repo, &request.body)?,
+        )),
+        _ => Ok(HttpResponse::text(
+            "405 Method Not Allowed",
+            "Method not allowed",
+        )),
+    }
+}
+
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::{base64_encode, http_read_request, http_route, ServeConfig, SERVE_MAX_BODY};
+    use crate::test_utils::test_repo;
+
+    #[test]
+    fn test_http_request_limits() {
+        let request = b"POST /r/git-upload-pack HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody";
+        assert_eq!(http_read_request(&request[..]).unwrap().body, b"body");
+
+        let huge = format!(
+            "POST /r/git-upload-pack HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            SERVE_MAX_BODY + 1
+        );
+        assert!(http_read_request(huge.as_bytes()).is_err());
+        let chunked = format!(
+            "POST /r/git-upload-pack HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+            usize::MAX
+        );
+        assert!(http_read_request(chunked.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_receive_pack_needs_enabling() {
+        let mut repo = test_repo("serve-receive-pack");
+        let name = repo
+            .worktree
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let root: PathBuf = repo.worktree.parent().unwrap().to_path_buf();
+        let request = |auth: Option<&str>| {
+            let raw = format!(
+                "GET /{}/info/refs?service=git-receive-pack HTTP/1.1\r\n{}\r\n",
+                name,
+                auth.map(|a| format!("Authorization: {}\r\n", a))
+                    .unwrap_or_default()
+            );
+            http_read_request(raw.as_bytes()).unwrap()
+        };
+        let status = |config: &ServeConfig, auth: Option<&str>| {
+            http_route(config, &request(auth)).unwrap().status
+        };
+
+        let open = ServeConfig {
+            root: root.clone(),
+            auth: None,
+        };
+        assert_eq!(status(&open, None), "403 Forbidden");
+        let authed = ServeConfig {
+            root,
+            auth: Some("Basic dXNlcjpwYXNz".to_string()),
+        };
+        assert_eq!(status(&authed, Some("Basic dXNlcjpwYXNz")), "200 OK");
+
+        repo.config_set("http", "receivepack", "true").unwrap();
+        assert_eq!(status(&open, None), "200 OK");
+        repo.config_set("http", "receivepack", "false").unwrap();
+        assert_eq!(status(&authed, Some("Basic dXNlcjpwYXNz")), "403 Forbidden");
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b"user:pass"), "dXNlcjpwYXNz");
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_encode(b"a"), "YQ==");
+    }
+}
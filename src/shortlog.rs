@@ -0,0 +1,87 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{anyhow, Result};
+
+use crate::{ident_parse, kvlm_get_str, object_read, repo_find, rev_walk, Commit, Repository};
+
+/// Summarizes the commits `revs` select (HEAD if empty) by author: each
+/// author's commit count and, unless `summary`, their subjects oldest
+/// first. Authors are sorted by name, or by count with `numbered`.
+pub fn shortlog(numbered: bool, summary: bool, revs: &[String]) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let revs = match revs {
+        [] => vec!["HEAD".to_string()],
+        _ => revs.to_vec(),
+    };
+    let mut groups: Vec<(String, Vec<String>)> = shortlog_group(&repo, &rev_walk(&repo, &revs)?)?
+        .into_iter()
+        .collect();
+    if numbered {
+        // Stable, so equal counts stay in name order.
+        groups.sort_by_key(|(_, subjects)| std::cmp::Reverse(subjects.len()));
+    }
+
+    for (author, subjects) in &groups {
+        if summary {
+            println!("{:>6}\t{}", subjects.len(), author);
+            continue;
+        }
+        println!("{} ({}):", author, subjects.len());
+        for subject in subjects.iter().rev() {
+            println!("      {}", subject);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// The subjects of `commits` by author name, in the order given.
+pub fn shortlog_group(
+    repo: &Repository,
+    commits: &[String],
+) -> Result<BTreeMap<String, Vec<String>>> {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for sha in commits {
+        let obj = object_read(repo, sha)?;
+        let commit = obj
+            .as_any()
+            .downcast_ref::<Commit>()
+            .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+        let author = kvlm_get_str(&commit.kvlm, b"author").unwrap_or_default();
+        let name = match ident_parse(&author) {
+            Some((who, _, _)) => who.split(" <").next().unwrap_or(who).to_string(),
+            None => author,
+        };
+        let subject = commit.message().lines().next().unwrap_or("").to_string();
+        groups.entry(name).or_default().push(subject);
+    }
+    Ok(groups)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        ref_create, rev_walk, shortlog_group,
+        test_utils::{make_commit_at, test_repo},
+    };
+
+    #[test]
+    fn test_shortlog_group() {
+        let repo = test_repo("shortlog");
+        let first = make_commit_at(&repo, "first", &[], 1000);
+        let second = make_commit_at(&repo, "second", &[&first], 2000);
+        let third = make_commit_at(&repo, "third", &[&second], 3000);
+        ref_create(&repo, "heads/master", &third).unwrap();
+
+        let commits = rev_walk(&repo, &[format!("{}..", first)]).unwrap();
+        assert_eq!(commits, [third.clone(), second.clone()]);
+        let groups = shortlog_group(&repo, &commits).unwrap();
+        assert_eq!(groups["Test"], ["third", "second"]);
+
+        let commits = rev_walk(&repo, &["HEAD".to_string(), format!("^{}", second)]).unwrap();
+        assert_eq!(commits, [third]);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
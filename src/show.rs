@@ -0,0 +1,104 @@
+use std::{io::Write, path::Path};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    date_format_default, diff_map_tree, diff_maps, ident_parse, kvlm_get_str, ls_tree_inner,
+    object_find, object_read, repo_find, Blob, Commit, DiffMap, Kvlm, Repository, Tag,
+};
+
+/// Shows an object the way its type calls for: a commit with its diff
+/// against its first parent, a tag followed by what it points at, a tree's
+/// entries, or a blob's content.
+pub fn show(name: &str) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let sha =
+        object_find(&repo, name, None, true)?.ok_or_else(|| anyhow!("bad revision '{}'", name))?;
+    let mut out = Vec::new();
+    show_object(&repo, &sha, &mut out)?;
+    std::io::stdout().write_all(&out)?;
+    Ok(())
+}
+
+fn show_object(repo: &Repository, sha: &str, out: &mut Vec<u8>) -> Result<()> {
+    let obj = object_read(repo, sha)?;
+    match obj.fmt() {
+        b"commit" => {
+            let commit = obj.as_any().downcast_ref::<Commit>().unwrap();
+            out.extend(show_commit_header(sha, commit));
+            let parents = commit.parents();
+            let old = match parents.first() {
+                Some(parent) => diff_map_tree(repo, parent)?,
+                None => DiffMap::new(),
+            };
+            let diff = diff_maps(repo, &old, &diff_map_tree(repo, sha)?, 3, false)?;
+            if !diff.is_empty() {
+                out.push(b'\n');
+                out.extend(diff);
+            }
+        }
+        b"tag" => {
+            let tag = obj.as_any().downcast_ref::<Tag>().unwrap();
+            let name = kvlm_get_str(&tag.kvlm, b"tag").unwrap_or_default();
+            out.extend(format!("tag {}\n", name).into_bytes());
+            out.extend(show_ident(&tag.kvlm, b"tagger", "Tagger"));
+            out.push(b'\n');
+            if let Some(message) = tag.kvlm.get(&None).and_then(|v| v.first()) {
+                out.extend_from_slice(message);
+                if !message.ends_with(b"\n") {
+                    out.push(b'\n');
+                }
+            }
+            out.push(b'\n');
+            let target = tag
+                .object()
+                .ok_or_else(|| anyhow!("tag {} has no object", sha))?;
+            show_object(repo, &target, out)?;
+        }
+        b"tree" => {
+            std::io::stdout().write_all(out)?;
+            out.clear();
+            ls_tree_inner(repo, sha, false, 0, "")?;
+        }
+        b"blob" => {
+            let blob = obj.as_any().downcast_ref::<Blob>().unwrap();
+            out.extend_from_slice(&blob.blobdata);
+        }
+        fmt => bail!("unknown object type {}", String::from_utf8_lossy(fmt)),
+    }
+    Ok(())
+}
+
+/// The medium-format header git prints for a commit: its id, the parents
+/// of a merge, the author and date, then the message indented.
+pub fn show_commit_header(sha: &str, commit: &Commit) -> Vec<u8> {
+    let mut out = format!("commit {}\n", sha).into_bytes();
+    let parents = commit.parents();
+    if parents.len() > 1 {
+        let short: Vec<&str> = parents.iter().map(|p| &p[..7]).collect();
+        out.extend(format!("Merge: {}\n", short.join(" ")).into_bytes());
+    }
+    out.extend(show_ident(&commit.kvlm, b"author", "Author"));
+    out.push(b'\n');
+    for line in commit.message().lines() {
+        out.extend(format!("    {}\n", line).into_bytes());
+    }
+    out
+}
+
+/// "Author: Name <email>" and "Date:   ..." lines for an identity header.
+fn show_ident(kvlm: &Kvlm, key: &[u8], label: &str) -> Vec<u8> {
+    let Some(ident) = kvlm_get_str(kvlm, key) else {
+        return Vec::new();
+    };
+    match ident_parse(&ident) {
+        Some((who, time, tz)) => format!(
+            "{}: {}\nDate:   {}\n",
+            label,
+            who,
+            date_format_default(time, tz)
+        ),
+        None => format!("{}: {}\n", label, ident),
+    }
+    .into_bytes()
+}
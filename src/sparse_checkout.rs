@@ -0,0 +1,302 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{
+    checkout_file, index_entry_modified, index_read, index_write, remove_file, repo_find,
+    IndexEntry, Repository, TreeLeaf,
+};
+
+/// What `info/sparse-checkout` keeps in the worktree.
+pub enum SparsePatterns {
+    /// Cone mode: the files at the top, those directly in the parents of
+    /// the `recursive` directories, and everything below those.
+    Cone {
+        recursive: BTreeSet<String>,
+        parents: BTreeSet<String>,
+    },
+    /// gitignore-style patterns, (pattern, include) each: the last one
+    /// matching a file decides, or failing that the last one matching its
+    /// closest directory.
+    Full(Vec<(String, bool)>),
+}
+
+impl SparsePatterns {
+    /// Reads cone mode patterns back into directories, or None if `content`
+    /// holds other patterns than cone mode writes.
+    fn cone_parse(content: &str) -> Option<Self> {
+        let mut dirs = BTreeSet::new();
+        let mut parents = BTreeSet::new();
+        for line in content
+            .lines()
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        {
+            if line == "/*" || line == "!/*/" {
+                continue;
+            }
+            if let Some(dir) = line.strip_prefix("!/").and_then(|l| l.strip_suffix("/*/")) {
+                parents.insert(dir.to_string());
+            } else if let Some(dir) = line.strip_prefix('/').and_then(|l| l.strip_suffix('/')) {
+                if dir.is_empty() || dir.contains('*') {
+                    return None;
+                }
+                dirs.insert(dir.to_string());
+            } else {
+                return None;
+            }
+        }
+        let recursive = dirs.difference(&parents).cloned().collect();
+        Some(Self::Cone { recursive, parents })
+    }
+
+    fn full_parse(content: &str) -> Self {
+        let patterns = content
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| match l.strip_prefix('!') {
+                Some(pattern) => (pattern.to_string(), false),
+                None => (l.to_string(), true),
+            })
+            .collect();
+        Self::Full(patterns)
+    }
+
+    /// Whether the file `path` belongs in the worktree.
+    pub fn includes(&self, path: &str) -> bool {
+        let dirs = || path.match_indices('/').map(|(i, _)| &path[..i]);
+        match self {
+            Self::Cone { recursive, parents } => {
+                let parent = path.rsplit_once('/').map(|(dir, _)| dir);
+                parent.is_none_or(|dir| parents.contains(dir))
+                    || dirs().any(|dir| recursive.contains(dir))
+            }
+            Self::Full(patterns) => {
+                // The file itself decides first, then each directory up
+                // from it.
+                let candidates =
+                    std::iter::once((path, false)).chain(dirs().rev().map(|d| (d, true)));
+                for (candidate, is_dir) in candidates {
+                    let last = patterns
+                        .iter()
+                        .rev()
+                        .find(|(p, _)| sparse_pattern_matches(p, candidate, is_dir));
+                    if let Some((_, include)) = last {
+                        return *include;
+                    }
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Whether the gitignore-style `pattern` matches `path`: against the whole
+/// path when it holds a slash, otherwise against its last component. A
+/// trailing slash only matches directories.
+fn sparse_pattern_matches(pattern: &str, path: &str, is_dir: bool) -> bool {
+    let (pattern, dir_only) = match pattern.strip_suffix('/') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+    if dir_only && !is_dir {
+        return false;
+    }
+    let opts = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    let Ok(glob) = glob::Pattern::new(pattern.trim_start_matches('/')) else {
+        return false;
+    };
+    match pattern.contains('/') {
+        true => glob.matches_with(path, opts),
+        false => glob.matches_with(path.rsplit('/').next().unwrap_or(path), opts),
+    }
+}
+
+/// The patterns to check out with, or None when `core.sparseCheckout` is
+/// off. Patterns cone mode can't read fall back to full matching.
+pub fn sparse_patterns(repo: &Repository) -> Result<Option<SparsePatterns>> {
+    if !repo
+        .config_get_bool("core", "sparseCheckout")?
+        .unwrap_or(false)
+    {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(sparse_file(repo)).unwrap_or_default();
+    let cone = repo
+        .config_get_bool("core", "sparseCheckoutCone")?
+        .unwrap_or(true);
+    Ok(Some(match cone {
+        true => SparsePatterns::cone_parse(&content)
+            .unwrap_or_else(|| SparsePatterns::full_parse(&content)),
+        false => SparsePatterns::full_parse(&content),
+    }))
+}
+
+/// What `sparse-checkout` does.
+pub enum SparseCheckoutAction {
+    /// Turn sparse checkout on, keeping only the top directory's files
+    /// unless patterns are already set.
+    Init,
+    /// Turn sparse checkout on with these directories (cone mode) or
+    /// patterns.
+    Set(Vec<String>),
+    /// Print the directories, or patterns, being checked out.
+    List,
+    /// Turn sparse checkout off, checking every file out again.
+    Disable,
+}
+
+pub fn sparse_checkout(action: SparseCheckoutAction, cone: bool) -> Result<()> {
+    let mut repo = repo_find(Path::new("."), true)?.unwrap();
+    let path = sparse_file(&repo);
+    match action {
+        SparseCheckoutAction::Init => {
+            if !path.exists() {
+                sparse_file_write(&repo, "/*\n!/*/\n")?;
+            }
+            sparse_enable(&mut repo, cone)?;
+        }
+        SparseCheckoutAction::Set(patterns) => {
+            let content = match cone {
+                true => sparse_cone_content(&patterns),
+                false => patterns.iter().map(|p| format!("{}\n", p)).collect(),
+            };
+            sparse_file_write(&repo, &content)?;
+            sparse_enable(&mut repo, cone)?;
+        }
+        SparseCheckoutAction::List => {
+            match sparse_patterns(&repo)? {
+                Some(SparsePatterns::Cone { recursive, .. }) => {
+                    for dir in recursive {
+                        println!("{}", dir);
+                    }
+                }
+                Some(SparsePatterns::Full(_)) => {
+                    print!("{}", fs::read_to_string(&path).unwrap_or_default());
+                }
+                None => eprintln!("this worktree is not sparse"),
+            }
+            return Ok(());
+        }
+        SparseCheckoutAction::Disable => {
+            repo.config_set("core", "sparseCheckout", "false")?;
+        }
+    }
+    sparse_checkout_apply(&repo)
+}
+
+fn sparse_enable(repo: &mut Repository, cone: bool) -> Result<()> {
+    repo.config_set("core", "sparseCheckout", "true")?;
+    repo.config_set("core", "sparseCheckoutCone", &cone.to_string())
+}
+
+fn sparse_file(repo: &Repository) -> PathBuf {
+    repo.gitdir.join("info/sparse-checkout")
+}
+
+fn sparse_file_write(repo: &Repository, content: &str) -> Result<()> {
+    let path = sparse_file(repo);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// The cone mode patterns for `dirs`: the top directory's files, and for
+/// each directory the files of its parents and all of its own. A directory
+/// inside another one given adds nothing.
+fn sparse_cone_content(dirs: &[String]) -> String {
+    let dirs: BTreeSet<&str> = dirs
+        .iter()
+        .map(|d| d.trim_matches('/'))
+        .filter(|d| !d.is_empty())
+        .collect();
+    let recursive: BTreeSet<&str> = dirs
+        .iter()
+        .copied()
+        .filter(|d| !d.match_indices('/').any(|(i, _)| dirs.contains(&d[..i])))
+        .collect();
+    let parents: BTreeSet<&str> = recursive
+        .iter()
+        .flat_map(|d| d.match_indices('/').map(|(i, _)| &d[..i]))
+        .collect();
+    let mut out = String::from("/*\n!/*/\n");
+    for dir in parents.union(&recursive) {
+        out.push_str(&format!("/{}/\n", dir));
+        if !recursive.contains(dir) {
+            out.push_str(&format!("!/{}/*/\n", dir));
+        }
+    }
+    out
+}
+
+/// Brings the worktree in line with the sparse patterns: files left out
+/// are removed and flagged skip-worktree in the index, and those brought
+/// back in are checked out again. Files with local changes stay where
+/// they are, as do conflicted ones.
+pub fn sparse_checkout_apply(repo: &Repository) -> Result<()> {
+    let patterns = sparse_patterns(repo)?;
+    let mut index = index_read(repo)?;
+    let mut kept = Vec::new();
+    for entry in index.entries.iter_mut() {
+        let wanted =
+            entry.flag_stage != 0 || patterns.as_ref().is_none_or(|p| p.includes(&entry.name));
+        if wanted && entry.flag_skip_worktree {
+            let leaf = TreeLeaf {
+                mode: entry.tree_mode(),
+                path: entry.name.clone(),
+                sha: entry.sha.clone(),
+            };
+            checkout_file(repo, &leaf)?;
+            *entry = IndexEntry::from_file(repo, &leaf.path, &leaf.sha, &leaf.mode)?;
+        } else if !wanted && !entry.flag_skip_worktree {
+            let exists = fs::symlink_metadata(repo.worktree.join(&entry.name)).is_ok();
+            if exists && index_entry_modified(repo, entry)? {
+                kept.push(entry.name.clone());
+                continue;
+            }
+            remove_file(repo, &entry.name)?;
+            entry.flag_skip_worktree = true;
+        }
+    }
+    if !kept.is_empty() {
+        eprintln!(
+            "warning: The following paths are not up to date and were left despite \
+             sparse patterns:\n\t{}",
+            kept.join("\n\t")
+        );
+    }
+    index_write(repo, &index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sparse_cone_content, SparsePatterns};
+
+    #[test]
+    fn test_sparse_cone() {
+        let dirs = ["a/b/".to_string(), "a/b/c".to_string(), "d".to_string()];
+        let content = sparse_cone_content(&dirs);
+        assert_eq!(content, "/*\n!/*/\n/a/\n!/a/*/\n/a/b/\n/d/\n");
+        let cone = SparsePatterns::cone_parse(&content).unwrap();
+        assert!(cone.includes("top"));
+        assert!(cone.includes("a/file"));
+        assert!(!cone.includes("a/x/file"));
+        assert!(cone.includes("a/b/x/y/file"));
+        assert!(cone.includes("d/file"));
+        assert!(!cone.includes("e/file"));
+        assert!(SparsePatterns::cone_parse("*.c\n").is_none());
+
+        let full = SparsePatterns::full_parse("/*\n!/*/\ndocs/\n!*.png\n");
+        assert!(full.includes("top"));
+        assert!(!full.includes("src/main.rs"));
+        assert!(full.includes("docs/guide.md"));
+        assert!(!full.includes("docs/logo.png"));
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    io::{Read, Write},
+    path::Path,
+    process::{Child, ChildStdout, Command, Stdio},
+};
+
+use crate::{config_global, GitUrl, Repository};
+use anyhow::{anyhow, bail, Result};
+
+/// The flavours of ssh client git knows how to pass a port and batch mode
+/// to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SshVariant {
+    /// OpenSSH and compatible clients: `-p <port>`.
+    Ssh,
+    /// PuTTY's plink: `-P <port>`.
+    Plink,
+    Putty,
+    /// TortoisePlink, which also needs `-batch` to never prompt.
+    TortoisePlink,
+    /// Anything else: only `<host> <command>`, no options at all.
+    Simple,
+}
+
+impl SshVariant {
+    /// Names as in `ssh.variant` and GIT_SSH_VARIANT; "auto" means guess.
+    pub fn parse(name: &str) -> Option<SshVariant> {
+        match name {
+            "ssh" => Some(SshVariant::Ssh),
+            "plink" => Some(SshVariant::Plink),
+            "putty" => Some(SshVariant::Putty),
+            "tortoiseplink" => Some(SshVariant::TortoisePlink),
+            "simple" => Some(SshVariant::Simple),
+            _ => None,
+        }
+    }
+
+    /// Guesses the variant from the program a command runs.
+    pub fn detect(command: &str) -> SshVariant {
+        let program = command.split_whitespace().next().unwrap_or_default();
+        let base = Path::new(program)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let base = base.strip_suffix(".exe").unwrap_or(&base);
+        SshVariant::parse(base).unwrap_or(SshVariant::Simple)
+    }
+
+    /// The options that go before the host.
+    pub fn args(self, port: Option<&str>) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        if self == SshVariant::TortoisePlink {
+            args.push("-batch".to_string());
+        }
+        if let Some(port) = port {
+            match self {
+                SshVariant::Ssh => args.push("-p".to_string()),
+                SshVariant::Simple => bail!("ssh variant 'simple' does not support setting port"),
+                _ => args.push("-P".to_string()),
+            }
+            args.push(port.to_string());
+        }
+        Ok(args)
+    }
+}
+
+/// A repository reached by running git's services on another machine
+/// over ssh. Each service runs in its own ssh session, which speaks the
+/// stateful protocol: the advertisement, then one request and its reply.
+pub struct SshRemote {
+    /// "[user@]host".
+    host: String,
+    port: Option<String>,
+    path: String,
+    /// The ssh command and whether it is a shell snippet (GIT_SSH_COMMAND,
+    /// core.sshCommand) rather than a program (GIT_SSH).
+    command: (String, bool),
+    variant: SshVariant,
+    /// The session whose advertisement was read but not yet answered.
+    session: Option<(String, Child)>,
+}
+
+impl SshRemote {
+    /// Sets up sessions with the host of `url`, picking the ssh client
+    /// to run.
+    pub fn new(url: &GitUrl, repo: Option<&Repository>) -> Result<SshRemote> {
+        let config = |section: &str, key: &str| match repo {
+            Some(repo) => repo.config_get(section, key),
+            None => config_global().get(section, key),
+        };
+        let command = if let Ok(cmd) = std::env::var("GIT_SSH_COMMAND") {
+            (cmd, true)
+        } else if let Some(cmd) = config("core", "sshCommand") {
+            (cmd, true)
+        } else if let Ok(program) = std::env::var("GIT_SSH") {
+            (program, false)
+        } else {
+            ("ssh".to_string(), false)
+        };
+        let variant = std::env::var("GIT_SSH_VARIANT")
+            .ok()
+            .or_else(|| config("ssh", "variant"))
+            .and_then(|v| SshVariant::parse(&v))
+            .unwrap_or_else(|| SshVariant::detect(&command.0));
+        Ok(SshRemote {
+            host: url.user_host(),
+            port: url.port.map(|p| p.to_string()),
+            path: url.path.clone(),
+            command,
+            variant,
+            session: None,
+        })
+    }
+
+    /// Starts `service` on the remote host.
+    fn spawn(&self, service: &str) -> Result<Child> {
+        let mut args = self.variant.args(self.port.as_deref())?;
+        args.push(self.host.clone());
+        // The remote shell sees the path single-quoted, as git sends it.
+        args.push(format!(
+            "{} '{}'",
+            service,
+            self.path.replace('\'', "'\\''")
+        ));
+        let (command, shell) = &self.command;
+        let mut cmd = if *shell {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c")
+                .arg(format!("{} \"$@\"", command))
+                .arg(command);
+            cmd
+        } else {
+            Command::new(command)
+        };
+        cmd.args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("cannot run {}: {}", command, e))
+    }
+
+    /// Runs `service` and reads its ref advertisement, leaving the session
+    /// open for the request that follows.
+    pub fn advertise(&mut self, service: &str) -> Result<Vec<u8>> {
+        let mut child = self.spawn(service)?;
+        let adv = pkt_read_section(child.stdout.as_mut().unwrap())?;
+        if adv.is_empty() {
+            let _ = child.wait();
+            bail!("Could not read from remote repository.");
+        }
+        self.session = Some((service.to_string(), child));
+        Ok(adv)
+    }
+
+    /// Sends `body` to `service` and reads its reply to the end. Without a
+    /// session waiting for it, a new one is started and its advertisement
+    /// skipped.
+    pub fn request(&mut self, service: &str, body: &[u8]) -> Result<Vec<u8>> {
+        let mut child = match self.session.take() {
+            Some((s, child)) if s == service => child,
+            other => {
+                self.session = other;
+                let mut child = self.spawn(service)?;
+                pkt_read_section(child.stdout.as_mut().unwrap())?;
+                child
+            }
+        };
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(body)?;
+        drop(stdin);
+        let mut reply = Vec::new();
+        child.stdout.take().unwrap().read_to_end(&mut reply)?;
+        let status = child.wait()?;
+        if reply.is_empty() && !status.success() {
+            bail!("Could not read from remote repository.");
+        }
+        Ok(reply)
+    }
+}
+
+impl Drop for SshRemote {
+    /// Ends a session that never got a request with a flush, which tells
+    /// the service there's nothing to do.
+    fn drop(&mut self) {
+        if let Some((_, mut child)) = self.session.take() {
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(b"0000");
+            }
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Reads pkt-lines from `stream` up to and including the next flush, raw.
+fn pkt_read_section(stream: &mut ChildStdout) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let mut len_hex = [0u8; 4];
+        if stream.read_exact(&mut len_hex).is_err() {
+            return Ok(out);
+        }
+        out.extend_from_slice(&len_hex);
+        let len = usize::from_str_radix(std::str::from_utf8(&len_hex)?, 16).map_err(|_| {
+            anyhow!(
+                "Bad pkt-line length {:?}",
+                String::from_utf8_lossy(&len_hex)
+            )
+        })?;
+        if len == 0 {
+            return Ok(out);
+        }
+        let mut data = vec![0; len.saturating_sub(4)];
+        stream.read_exact(&mut data)?;
+        out.extend(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SshVariant;
+
+    #[test]
+    fn test_ssh_variants() {
+        assert_eq!(SshVariant::detect("/usr/bin/ssh -v"), SshVariant::Ssh);
+        assert_eq!(
+            SshVariant::detect("C:/tools/TortoisePlink.exe"),
+            SshVariant::TortoisePlink
+        );
+        assert_eq!(SshVariant::detect("my-wrapper"), SshVariant::Simple);
+        assert_eq!(SshVariant::Ssh.args(Some("22")).unwrap(), ["-p", "22"]);
+        assert_eq!(SshVariant::Plink.args(Some("22")).unwrap(), ["-P", "22"]);
+        assert_eq!(
+            SshVariant::TortoisePlink.args(Some("22")).unwrap(),
+            ["-batch", "-P", "22"]
+        );
+        assert!(SshVariant::Simple.args(Some("22")).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_get_active, checkout_tree, commit_create, commit_subject, gc_auto, head_leaves,
+    index_add_paths, index_read, index_reset, index_write, index_write_tree, merge_checkout,
+    merge_conflicts_print, merge_trees, object_find, object_read, ref_create, ref_delete,
+    ref_resolve, reflog_append, reflog_read, reflog_write, repo_find, sha_short,
+    status_dirty_paths, Commit, Repository,
+};
+
+const STASH_REF: &str = "refs/stash";
+
+/// Saves the index and the tracked worktree changes as a stash entry and
+/// resets the worktree to HEAD.
+///
+/// Like git, an entry is a commit W of the worktree whose parents are HEAD
+/// and a commit I of the index. `refs/stash` points at the newest entry and
+/// its reflog holds the whole stack.
+pub fn stash_push(message: Option<&str>) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let head = ref_resolve(&repo, "HEAD")?
+        .ok_or_else(|| anyhow!("You do not have the initial commit yet"))?;
+    if status_dirty_paths(&repo)?.is_empty() {
+        println!("No local changes to save");
+        return Ok(());
+    }
+
+    let mut index = index_read(&repo)?;
+    if index.entries.iter().any(|e| e.flag_stage != 0) {
+        bail!("Cannot save the current index state: there are unmerged paths");
+    }
+    let branch = branch_get_active(&repo)?.unwrap_or_else(|| "(no branch)".to_string());
+    let head_desc = format!(
+        "{}: {} {}",
+        branch,
+        sha_short(&repo, &head),
+        commit_subject(&repo, &head)?
+    );
+
+    let index_tree = index_write_tree(&repo, &index)?;
+    let index_commit = commit_create(
+        &repo,
+        &index_tree,
+        std::slice::from_ref(&head),
+        None,
+        &format!("index on {}", head_desc),
+    )?;
+
+    // The worktree commit records tracked files only, as `git stash` does
+    // without --include-untracked.
+    let mut tracked: Vec<String> = index.entries.iter().map(|e| e.name.clone()).collect();
+    tracked.extend(head_leaves(&repo)?.into_keys());
+    tracked.sort();
+    tracked.dedup();
+    index_add_paths(&repo, &mut index, &tracked)?;
+    let worktree_tree = index_write_tree(&repo, &index)?;
+    let message = match message {
+        Some(m) => format!("On {}: {}", branch, m),
+        None => format!("WIP on {}", head_desc),
+    };
+    let stash = commit_create(
+        &repo,
+        &worktree_tree,
+        &[head.clone(), index_commit],
+        None,
+        &message,
+    )?;
+
+    let old = ref_resolve(&repo, STASH_REF)?;
+    ref_create(&repo, "stash", &stash)?;
+    reflog_append(&repo, STASH_REF, old.as_deref(), &stash, &message)?;
+
+    checkout_tree(&repo, &head, true)?;
+    println!("Saved working directory and index state {}", message);
+    Ok(())
+}
+
+pub fn stash_list() -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    for (i, entry) in reflog_read(&repo, STASH_REF)?.iter().rev().enumerate() {
+        println!("stash@{{{}}}: {}", i, entry.message);
+    }
+    Ok(())
+}
+
+/// Applies a stash entry onto the worktree, then drops it if `pop` is set
+/// and it applied without conflicts.
+pub fn stash_apply(stash: Option<&str>, pop: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let n = stash_index(stash)?;
+    let sha = stash_entry(&repo, n)?;
+    let head = ref_resolve(&repo, "HEAD")?.ok_or_else(|| anyhow!("No commits yet on HEAD"))?;
+    let dirty = status_dirty_paths(&repo)?;
+    if !dirty.is_empty() {
+        bail!(
+            "Your local changes would be overwritten by stash apply:\n\t{}\n\
+             Please commit your changes or stash them before you apply.",
+            dirty.join("\n\t")
+        );
+    }
+
+    let obj = object_read(&repo, &sha)?;
+    let commit = obj
+        .as_any()
+        .downcast_ref::<Commit>()
+        .ok_or_else(|| anyhow!("stash@{{{}}} is not a commit", n))?;
+    let base = commit
+        .parents()
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("stash@{{{}}} is not a stash entry", n))?;
+
+    let tree_of = |sha: &str| -> Result<String> {
+        object_find(&repo, sha, Some(b"tree"), true)?.ok_or_else(|| anyhow!("{} has no tree", sha))
+    };
+    let merged = merge_trees(
+        &repo,
+        Some(&tree_of(&base)?),
+        &tree_of(&head)?,
+        &tree_of(&sha)?,
+        "Updated upstream",
+        "Stashed changes",
+    )?;
+    merge_checkout(&repo, &merged)?;
+    if !merged.conflicts.is_empty() {
+        merge_conflicts_print(&merged.conflicts);
+        bail!("The stash entry is kept in case you need it again.");
+    }
+
+    // The changes come back unstaged, except that new files stay added so
+    // they are not lost track of.
+    let staged = index_read(&repo)?;
+    let head_paths = head_leaves(&repo)?;
+    index_reset(&repo, &head)?;
+    let mut index = index_read(&repo)?;
+    index.entries.extend(
+        staged
+            .entries
+            .into_iter()
+            .filter(|e| !head_paths.contains_key(&e.name)),
+    );
+    index.entries.sort_by(|a, b| a.name.cmp(&b.name));
+    index_write(&repo, &index)?;
+
+    if pop {
+        stash_drop_entry(&repo, n)?;
+    }
+    gc_auto(&repo);
+    Ok(())
+}
+
+pub fn stash_drop(stash: Option<&str>) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    stash_drop_entry(&repo, stash_index(stash)?)
+}
+
+fn stash_drop_entry(repo: &Repository, n: usize) -> Result<()> {
+    let sha = stash_entry(repo, n)?;
+    let mut entries = reflog_read(repo, STASH_REF)?;
+    let pos = entries.len() - 1 - n;
+    entries.remove(pos);
+    // The entry after the dropped one now continues from its predecessor.
+    if pos < entries.len() {
+        entries[pos].old = match pos {
+            0 => "0".repeat(40),
+            _ => entries[pos - 1].new.clone(),
+        };
+    }
+    reflog_write(repo, STASH_REF, &entries)?;
+    match entries.last() {
+        Some(top) => ref_create(repo, "stash", &top.new)?,
+        None => ref_delete(repo, STASH_REF)?,
+    }
+    println!("Dropped stash@{{{}}} ({})", n, sha);
+    Ok(())
+}
+
+/// Parses `stash@{n}` or a bare `n`, defaulting to the newest entry.
+fn stash_index(stash: Option<&str>) -> Result<usize> {
+    let Some(stash) = stash else {
+        return Ok(0);
+    };
+    let n = stash
+        .strip_prefix("stash@{")
+        .and_then(|s| s.strip_suffix('}'))
+        .unwrap_or(stash);
+    n.parse()
+        .map_err(|_| anyhow!("'{}' is not a stash reference", stash))
+}
+
+fn stash_entry(repo: &Repository, n: usize) -> Result<String> {
+    let entries = reflog_read(repo, STASH_REF)?;
+    if entries.is_empty() {
+        bail!("No stash entries found.");
+    }
+    entries
+        .iter()
+        .rev()
+        .nth(n)
+        .map(|e| e.new.clone())
+        .ok_or_else(|| anyhow!("stash@{{{}}} does not exist", n))
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    bytes_humanise, kvlm_get_str, mailmap_read, object_find, object_iter_loose, object_iter_packed,
+    object_read, ref_list_flat, repo_find, rev_walk, sha_short, Commit, Repository,
+};
+
+/// What `stats` reports about a repository.
+#[derive(Debug, Default)]
+pub struct RepoStats {
+    /// Commits reachable from any branch or tag.
+    pub commits: usize,
+    /// Distinct authors of those commits, after .mailmap.
+    pub contributors: usize,
+    pub branches: usize,
+    pub tags: usize,
+    /// The number and total content size of the objects of each type.
+    pub objects: BTreeMap<&'static str, (usize, u64)>,
+    pub loose: usize,
+    /// Objects only found in packs.
+    pub packed: usize,
+    /// The biggest blobs, (size, sha), largest first.
+    pub largest_blobs: Vec<(u64, String)>,
+}
+
+/// Prints a summary of the repository: its history, who wrote it, and what
+/// its objects take up, with the `largest` biggest blobs.
+pub fn stats(largest: usize) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let stats = stats_collect(&repo, largest)?;
+    println!("commits: {}", stats.commits);
+    println!("contributors: {}", stats.contributors);
+    println!("branches: {}", stats.branches);
+    println!("tags: {}", stats.tags);
+    let total = stats.loose + stats.packed;
+    let percent = match total {
+        0 => 0,
+        _ => stats.packed * 100 / total,
+    };
+    println!(
+        "objects: {} ({} loose, {} packed, {}% packed)",
+        total, stats.loose, stats.packed, percent
+    );
+    for (fmt, (count, size)) in &stats.objects {
+        println!("  {}s: {} ({})", fmt, count, bytes_humanise(*size));
+    }
+    if !stats.largest_blobs.is_empty() {
+        println!("largest blobs:");
+        for (size, sha) in &stats.largest_blobs {
+            println!("  {}  {}", sha_short(&repo, sha), bytes_humanise(*size));
+        }
+    }
+    Ok(())
+}
+
+/// Gathers `RepoStats`, walking the history from every branch and tag and
+/// enumerating the whole object database.
+pub fn stats_collect(repo: &Repository, largest: usize) -> Result<RepoStats> {
+    let mut stats = RepoStats::default();
+    let refs = match repo.repo_path("refs".into()).is_dir() {
+        true => ref_list_flat(repo, None, None)?,
+        false => Default::default(),
+    };
+    let mut tips = Vec::new();
+    for (name, sha) in &refs {
+        if name.starts_with("heads/") {
+            stats.branches += 1;
+        } else if name.starts_with("tags/") {
+            stats.tags += 1;
+        } else {
+            continue;
+        }
+        // Tags of trees and blobs have no history.
+        if let Ok(Some(commit)) = object_find(repo, sha, Some(b"commit"), true) {
+            tips.push(commit);
+        }
+    }
+
+    let commits = match tips.is_empty() {
+        true => Vec::new(),
+        false => rev_walk(repo, &tips)?,
+    };
+    let mailmap = mailmap_read(repo)?;
+    let mut contributors = HashSet::new();
+    for sha in &commits {
+        let obj = object_read(repo, sha)?;
+        let commit = obj
+            .as_any()
+            .downcast_ref::<Commit>()
+            .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+        let author = kvlm_get_str(&commit.kvlm, b"author").unwrap_or_default();
+        // Drop the date; the identity is everything before it.
+        let ident = author.rsplitn(3, ' ').nth(2).unwrap_or(&author);
+        let (name, email) = mailmap.lookup_ident(ident);
+        contributors.insert((name, email.to_lowercase()));
+    }
+    stats.commits = commits.len();
+    stats.contributors = contributors.len();
+
+    let loose = object_iter_loose(repo)?;
+    let mut seen = HashSet::new();
+    let mut tally = |sha: String, fmt: &'static [u8], size: u64| {
+        let fmt = std::str::from_utf8(fmt).unwrap_or("unknown");
+        let entry = stats.objects.entry(fmt).or_default();
+        entry.0 += 1;
+        entry.1 += size;
+        if fmt == "blob" {
+            stats.largest_blobs.push((size, sha));
+        }
+    };
+    for (sha, info) in loose {
+        let info = info?;
+        seen.insert(sha.clone());
+        tally(sha, info.fmt, info.size as u64);
+        stats.loose += 1;
+    }
+    for (sha, info) in object_iter_packed(repo)? {
+        if seen.contains(&sha) {
+            continue;
+        }
+        let info = info?;
+        tally(sha, info.fmt, info.size as u64);
+        stats.packed += 1;
+    }
+    stats
+        .largest_blobs
+        .sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+    stats.largest_blobs.truncate(largest);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::stats_collect;
+    use crate::{
+        ref_create,
+        test_utils::{make_commit, test_repo},
+    };
+
+    #[test]
+    fn test_stats_collect() {
+        let repo = test_repo("stats");
+        let one = make_commit(&repo, "one", &[]);
+        let two = make_commit(&repo, "two", &[&one]);
+        ref_create(&repo, "heads/master", &two).unwrap();
+        ref_create(&repo, "tags/v1", &one).unwrap();
+        let stats = stats_collect(&repo, 1).unwrap();
+        assert_eq!((stats.commits, stats.contributors), (2, 1));
+        assert_eq!((stats.branches, stats.tags), (1, 1));
+        assert_eq!(stats.objects["commit"].0, 2);
+        assert_eq!(stats.loose, stats.objects.values().map(|o| o.0).sum());
+        assert_eq!(stats.largest_blobs.len(), 1);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
-    collections::HashMap,
-    fs::{self, File},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
     path::{Path, PathBuf},
 };
 
@@ -8,22 +8,45 @@ use anyhow::{anyhow, Result};
 use walkdir::WalkDir;
 
 use crate::{
-    check_ignore, check_ignore_path, gitignore_read, index_read, object_find, object_hash,
-    object_read, repo_file, repo_find, Index, Repository, Tree,
+    check_ignore_path, gitignore_read, head_leaves, index_entry_modified, index_from_head,
+    index_read, object_find, object_read, profile_phase, repo_file, repo_find, sparse_patterns,
+    submodule_status, IgnoreSubmodules, Index, Repository, Tree, TreeLeaf,
 };
 
-pub fn status() -> Result<()> {
+pub fn status(ignore: IgnoreSubmodules) -> Result<()> {
     let repo = repo_find(Path::new("."), true)?.unwrap();
-    let index = index_read(&repo)?;
+    // A damaged index shouldn't hide the worktree: compare it with HEAD.
+    let index = index_read(&repo).or_else(|e| {
+        eprintln!("warning: {:#}", e);
+        eprintln!("warning: showing changes against HEAD instead of the index");
+        index_from_head(&repo)
+    })?;
     status_branch(&repo)?;
-    status_head_index(&repo, &index)?;
+    if sparse_patterns(&repo)?.is_some() {
+        let present = index.entries.iter().filter(|e| !e.flag_skip_worktree);
+        let percent = present.count() * 100 / index.entries.len().max(1);
+        println!(
+            "You are in a sparse checkout with {}% of tracked files present.",
+            percent
+        );
+        println!();
+    }
+    status_head_index(&repo, &index, ignore)?;
     println!();
-    status_index_worktree(&repo, &index)?;
+    let unmerged = status_unmerged(&index);
+    if !unmerged.is_empty() {
+        println!("Unmerged paths:");
+        for (name, label) in unmerged {
+            println!("  {:<17}{}", format!("{}:", label), name);
+        }
+        println!();
+    }
+    status_index_worktree(&repo, &index, ignore)?;
     Ok(())
 }
 
 pub fn branch_get_active(repo: &Repository) -> Result<Option<String>> {
-    let head_path = repo_file(&repo, PathBuf::from("HEAD"), false)?;
+    let head_path = repo_file(repo, PathBuf::from("HEAD"), false)?;
     let content = fs::read_to_string(head_path)?;
     if let Some(strip) = content.strip_prefix("ref: refs/heads/") {
         Ok(Some(strip.trim().to_string()))
@@ -32,6 +55,29 @@ pub fn branch_get_active(repo: &Repository) -> Result<Option<String>> {
     }
 }
 
+/// Returns the tracked paths with staged changes (index differs from HEAD)
+/// or unstaged ones (worktree differs from the index).
+pub fn status_dirty_paths(repo: &Repository) -> Result<Vec<String>> {
+    let head = head_leaves(repo)?;
+    let index = index_read(repo)?;
+    let mut dirty = BTreeSet::new();
+    for entry in &index.entries {
+        let staged = entry.flag_stage != 0
+            || head
+                .get(&entry.name)
+                .is_none_or(|l| l.sha != entry.sha || l.mode != entry.tree_mode());
+        if staged || index_entry_modified(repo, entry)? {
+            dirty.insert(entry.name.clone());
+        }
+    }
+    for name in head.keys() {
+        if !index.entries.iter().any(|e| &e.name == name) {
+            dirty.insert(name.clone());
+        }
+    }
+    Ok(dirty.into_iter().collect())
+}
+
 pub fn status_branch(repo: &Repository) -> Result<()> {
     if let Some(branch) = branch_get_active(repo)? {
         println!("On branch {}.", branch);
@@ -73,12 +119,57 @@ pub fn tree_to_dict(
     Ok(ret)
 }
 
-pub fn status_head_index(repo: &Repository, index: &Index) -> Result<()> {
+/// Like `tree_to_dict`, but keeps the full leaves so modes are not lost.
+/// Each returned leaf's path is relative to the root of the tree.
+pub fn tree_to_leaves(repo: &Repository, tree_ref: &str, prefix: &str) -> Result<Vec<TreeLeaf>> {
+    let mut ret = Vec::new();
+
+    let tree_sha = object_find(repo, tree_ref, Some(b"tree"), true)?.unwrap();
+    let obj = object_read(repo, &tree_sha)?;
+    let tree = obj
+        .as_any()
+        .downcast_ref::<Tree>()
+        .ok_or_else(|| anyhow!("Not a tree object"))?;
+
+    for leaf in &tree.items {
+        let full_path = if prefix.is_empty() {
+            leaf.path.clone()
+        } else {
+            format!("{}/{}", prefix, leaf.path)
+        };
+        if leaf.mode.starts_with(b"04") {
+            ret.extend(tree_to_leaves(repo, &leaf.sha, &full_path)?);
+        } else {
+            ret.push(TreeLeaf {
+                mode: leaf.mode.clone(),
+                path: full_path,
+                sha: leaf.sha.clone(),
+            });
+        }
+    }
+    Ok(ret)
+}
+
+pub fn status_head_index(repo: &Repository, index: &Index, ignore: IgnoreSubmodules) -> Result<()> {
     println!("Changes to be committed:");
-    let head = tree_to_dict(repo, "HEAD", "")?;
+    let mut head_map = tree_to_dict(repo, "HEAD", "")?;
+    if ignore == IgnoreSubmodules::All {
+        for leaf in tree_to_leaves(repo, "HEAD", "")? {
+            if leaf.mode.starts_with(b"16") {
+                head_map.remove(&leaf.path);
+            }
+        }
+    }
 
-    let mut head_map = head.clone();
     for entry in &index.entries {
+        if entry.flag_stage != 0 {
+            // Conflicted paths are listed under "Unmerged paths" instead.
+            head_map.remove(&entry.name);
+            continue;
+        }
+        if ignore == IgnoreSubmodules::All && entry.mode_type == 0b1110 {
+            continue;
+        }
         if head_map.contains_key(&entry.name) {
             if head_map[&entry.name] != entry.sha {
                 println!("  modified:    {}", entry.name);
@@ -95,58 +186,133 @@ pub fn status_head_index(repo: &Repository, index: &Index) -> Result<()> {
     Ok(())
 }
 
-pub fn status_index_worktree(repo: &Repository, index: &Index) -> Result<()> {
+pub fn status_index_worktree(
+    repo: &Repository,
+    index: &Index,
+    ignore: IgnoreSubmodules,
+) -> Result<()> {
     println!("Changes not staged for commit:");
+    for entry in &index.entries {
+        let full_path = repo.worktree.join(&entry.name);
+        if entry.flag_stage != 0 {
+            continue;
+        }
+        if entry.mode_type == 0b1110 {
+            if ignore == IgnoreSubmodules::All {
+                continue;
+            }
+            let sub = submodule_status(repo, &entry.name, &entry.sha, ignore)?;
+            if sub.is_modified() {
+                println!("  modified:    {} ({})", entry.name, sub.describe());
+            }
+        } else if entry.flag_skip_worktree {
+            continue;
+        } else if !full_path.exists() {
+            println!("  deleted:     {}", entry.name);
+        } else if index_entry_modified(repo, entry)? {
+            println!("  modified:    {}", entry.name);
+        }
+    }
+    println!();
+    println!("Untracked files:");
+    for f in status_untracked(repo, index)? {
+        println!("  {}", f);
+    }
+    Ok(())
+}
 
-    let ignore = gitignore_read(repo)?;
+/// The conflicted paths of `index`, once each, with how they conflict as
+/// git describes it, from the merge stages they have entries at: 1 for the
+/// merge base, 2 for ours and 3 for theirs.
+pub fn status_unmerged(index: &Index) -> Vec<(String, &'static str)> {
+    let mut stages: BTreeMap<&str, [bool; 3]> = BTreeMap::new();
+    for entry in index.entries.iter().filter(|e| e.flag_stage != 0) {
+        let stage = (entry.flag_stage >> 12) as usize;
+        stages.entry(&entry.name).or_default()[stage - 1] = true;
+    }
+    stages
+        .into_iter()
+        .map(|(name, stages)| {
+            let label = match stages {
+                [true, true, true] => "both modified",
+                [true, true, false] => "deleted by them",
+                [true, false, true] => "deleted by us",
+                [false, true, true] => "both added",
+                [false, true, false] => "added by us",
+                [false, false, true] => "added by them",
+                _ => "both deleted",
+            };
+            (name.to_string(), label)
+        })
+        .collect()
+}
 
-    let mut all_files = Vec::new();
+/// The worktree files that are neither tracked nor ignored. Submodules are
+/// not looked into.
+pub fn status_untracked(repo: &Repository, index: &Index) -> Result<Vec<String>> {
+    let ignore = gitignore_read(repo)?;
+    let _phase = profile_phase("worktree walk");
+    let tracked: HashSet<&str> = index.entries.iter().map(|e| e.name.as_str()).collect();
 
-    // Walk the worktree (excluding .git)
-    for entry in WalkDir::new(&repo.worktree) {
+    let mut ret = Vec::new();
+    let walk = WalkDir::new(&repo.worktree).into_iter().filter_entry(|e| {
+        let rel = e.path().strip_prefix(&repo.worktree).unwrap_or(e.path());
+        // Skip .git, and the checkouts of submodules, which have their own.
+        !e.path().starts_with(&repo.gitdir)
+            && rel != Path::new(".git")
+            && !(e.file_type().is_dir() && tracked.contains(rel.to_string_lossy().as_ref()))
+    });
+    for entry in walk {
         let entry = entry?;
-        let path = entry.path();
-        if path.starts_with(&repo.gitdir) {
+        if entry.file_type().is_dir() {
             continue;
         }
-        if path.is_file() {
-            let rel = path
-                .strip_prefix(&repo.worktree)?
-                .to_string_lossy()
-                .to_string();
-            all_files.push(rel);
+        let rel = entry
+            .path()
+            .strip_prefix(&repo.worktree)?
+            .to_string_lossy()
+            .to_string();
+        if !tracked.contains(rel.as_str()) && !check_ignore_path(&ignore, &PathBuf::from(&rel)) {
+            ret.push(rel);
         }
     }
+    Ok(ret)
+}
 
-    use std::os::unix::fs::MetadataExt;
-    for entry in &index.entries {
-        let full_path = repo.worktree.join(&entry.name);
-        if !full_path.exists() {
-            println!("  deleted:     {}", entry.name);
-        } else {
-            let metadata = fs::metadata(&full_path)?;
-            let file_ctime_ns =
-                metadata.ctime() as u64 * 1_000_000_000 + metadata.ctime_nsec() as u64;
-            let file_mtime_ns =
-                metadata.mtime() as u64 * 1_000_000_000 + metadata.mtime_nsec() as u64;
-            let index_ctime = entry.ctime.0 as u64 * 1_000_000_000 + entry.ctime.1 as u64;
-            let index_mtime = entry.mtime.0 as u64 * 1_000_000_000 + entry.mtime.1 as u64;
-            if file_ctime_ns != index_ctime || file_mtime_ns != index_mtime {
-                let mut f = File::open(&full_path)?;
-                let new_sha = object_hash(&mut f, b"blob", None)?;
-                if new_sha != entry.sha {
-                    println!("  modified:    {}", entry.name);
-                }
-            }
-        }
-        all_files.retain(|f| f != &entry.name);
-    }
-    println!();
-    println!("Untracked files:");
-    for f in all_files {
-        if !check_ignore_path(&ignore, &PathBuf::from(&f)) {
-            println!("  {}", f);
-        }
+#[cfg(test)]
+mod tests {
+    use super::status_unmerged;
+    use crate::{Index, IndexEntry};
+
+    #[test]
+    fn test_status_unmerged() {
+        let entry = |name: &str, stage: u16| IndexEntry {
+            name: name.to_string(),
+            flag_stage: stage << 12,
+            ..Default::default()
+        };
+        let index = Index {
+            entries: vec![
+                entry("a", 0),
+                entry("both", 1),
+                entry("both", 2),
+                entry("both", 3),
+                entry("gone", 1),
+                entry("gone", 3),
+                entry("new", 2),
+                entry("new", 3),
+                entry("ours", 2),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            status_unmerged(&index),
+            vec![
+                ("both".to_string(), "both modified"),
+                ("gone".to_string(), "deleted by us"),
+                ("new".to_string(), "both added"),
+                ("ours".to_string(), "added by us"),
+            ]
+        );
     }
-    Ok(())
 }
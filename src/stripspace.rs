@@ -0,0 +1,93 @@
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use anyhow::Result;
+
+use crate::{config_global, repo_find, Repository};
+
+/// Filters stdin to stdout as `git stripspace` does, for hooks and scripts:
+/// cleans up whitespace, and with `strip_comments` drops comment lines;
+/// `comment_lines` instead comments out every line.
+pub fn stripspace(strip_comments: bool, comment_lines: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), false)?;
+    let comment = comment_char(repo.as_ref());
+    let mut text = String::new();
+    io::stdin().read_to_string(&mut text)?;
+    let out = match comment_lines {
+        true => stripspace_comment_lines(&text, &comment),
+        false => stripspace_text(&text, strip_comments.then_some(comment.as_str())),
+    };
+    io::stdout().write_all(out.as_bytes())?;
+    Ok(())
+}
+
+/// What starts a comment line in messages: `core.commentChar`, or "#".
+/// "auto" picks "#" too, since we never write a template to pick around.
+pub fn comment_char(repo: Option<&Repository>) -> String {
+    let value = match repo {
+        Some(repo) => repo.config_get("core", "commentChar"),
+        None => config_global().get("core", "commentChar"),
+    };
+    match value {
+        Some(value) if !value.is_empty() && value != "auto" => value,
+        _ => "#".to_string(),
+    }
+}
+
+/// Cleans up a message: strips trailing whitespace from each line, drops
+/// lines starting with `comment` if given, collapses runs of blank lines
+/// into one and removes them at the start and end. What is left ends in a
+/// newline, unless nothing is.
+pub fn stripspace_text(text: &str, comment: Option<&str>) -> String {
+    let mut out = String::new();
+    let mut blank = false;
+    for line in text.lines() {
+        if comment.is_some_and(|c| line.starts_with(c)) {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !out.is_empty() {
+            out.push('\n');
+        }
+        blank = false;
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Prefixes each line of `text` with `comment` and a space, or just the
+/// comment for empty lines.
+pub fn stripspace_comment_lines(text: &str, comment: &str) -> String {
+    let mut out = String::new();
+    for line in text.lines() {
+        out.push_str(comment);
+        if !line.is_empty() {
+            out.push(' ');
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{stripspace_comment_lines, stripspace_text};
+
+    #[test]
+    fn test_stripspace() {
+        let text = "\n  a  \n\n\n# c\nb\t\n\n";
+        assert_eq!(stripspace_text(text, None), "  a\n\n# c\nb\n");
+        assert_eq!(stripspace_text(text, Some("#")), "  a\n\nb\n");
+        assert_eq!(stripspace_text(" #c\n;d\nx", Some(";")), " #c\nx\n");
+        assert_eq!(stripspace_text("\n \n", None), "");
+        assert_eq!(stripspace_comment_lines("a\n\nb", "#"), "# a\n#\n# b\n");
+    }
+}
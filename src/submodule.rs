@@ -0,0 +1,441 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use walkdir::WalkDir;
+
+use crate::{
+    abbrev_len, checkout_tree, describe_commit, file_write_locked, head_detach, index_read,
+    object_read, path_matches, ref_list_flat, ref_resolve, repo_default_config, repo_file,
+    repo_find, repo_open, repo_relative_path, sha_abbrev, status_dirty_paths, status_untracked,
+    ConfigFile, Repository, ZERO_SHA,
+};
+
+/// A submodule as declared in `.gitmodules`.
+pub struct Submodule {
+    pub name: String,
+    /// Where it is checked out, relative to the superproject's worktree.
+    pub path: String,
+    pub url: Option<String>,
+}
+
+/// How much of a submodule's state `status` and `diff` look at, like git's
+/// `--ignore-submodules=<when>`. Each level ignores everything the previous
+/// ones do.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IgnoreSubmodules {
+    /// Report new commits, modified and untracked content.
+    #[default]
+    None,
+    /// Don't count untracked files as changes.
+    Untracked,
+    /// Only report a checked-out commit that differs from the recorded one.
+    Dirty,
+    /// Hide submodules altogether.
+    All,
+}
+
+/// How the checkout of a submodule differs from the commit the
+/// superproject records for it.
+#[derive(Default)]
+pub struct SubmoduleStatus {
+    /// The submodule's HEAD, or None when it is not checked out.
+    pub head: Option<String>,
+    pub new_commits: bool,
+    pub modified_content: bool,
+    pub untracked_content: bool,
+}
+
+impl SubmoduleStatus {
+    pub fn is_modified(&self) -> bool {
+        self.new_commits || self.is_dirty()
+    }
+
+    /// Whether the submodule's worktree has changes of its own.
+    pub fn is_dirty(&self) -> bool {
+        self.modified_content || self.untracked_content
+    }
+
+    /// The "new commits, modified content" note `status` shows.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.new_commits {
+            parts.push("new commits");
+        }
+        if self.modified_content {
+            parts.push("modified content");
+        }
+        if self.untracked_content {
+            parts.push("untracked content");
+        }
+        parts.join(", ")
+    }
+}
+
+/// Opens the submodule checked out at `path`, if there is one.
+pub fn submodule_open(repo: &Repository, path: &str) -> Option<Repository> {
+    repo_open(&repo.worktree.join(path)).ok()
+}
+
+/// The commit checked out in the submodule at `path`.
+pub fn submodule_head(repo: &Repository, path: &str) -> Result<Option<String>> {
+    match submodule_open(repo, path) {
+        Some(sub) => ref_resolve(&sub, "HEAD"),
+        None => Ok(None),
+    }
+}
+
+/// Compares the submodule at `path` with the commit `recorded` for it,
+/// looking only as deep as `ignore` allows. A submodule that is not checked
+/// out is unchanged, as in git.
+pub fn submodule_status(
+    repo: &Repository,
+    path: &str,
+    recorded: &str,
+    ignore: IgnoreSubmodules,
+) -> Result<SubmoduleStatus> {
+    let Some(sub) = submodule_open(repo, path) else {
+        return Ok(SubmoduleStatus::default());
+    };
+    let head = ref_resolve(&sub, "HEAD")?;
+    let mut status = SubmoduleStatus {
+        new_commits: head.as_deref().is_some_and(|h| h != recorded),
+        head,
+        ..Default::default()
+    };
+    if ignore < IgnoreSubmodules::Dirty {
+        status.modified_content = !status_dirty_paths(&sub)?.is_empty();
+    }
+    if ignore < IgnoreSubmodules::Untracked {
+        status.untracked_content = !status_untracked(&sub, &index_read(&sub)?)?.is_empty();
+    }
+    Ok(status)
+}
+
+/// Reads the submodules declared in the worktree's `.gitmodules`. Entries
+/// without a path are skipped.
+pub fn submodules_read(repo: &Repository) -> Result<Vec<Submodule>> {
+    let path = repo.worktree.join(".gitmodules");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let conf = ConfigFile::load(&path)?;
+    let mut ret = Vec::new();
+    for section in conf.sections() {
+        let name = section
+            .strip_prefix("submodule \"")
+            .and_then(|s| s.strip_suffix('"'));
+        let (Some(name), Some(path)) = (name, conf.get(&section, "path")) else {
+            continue;
+        };
+        ret.push(Submodule {
+            name: name.to_string(),
+            path: path.trim_end_matches('/').to_string(),
+            url: conf.get(&section, "url"),
+        });
+    }
+    Ok(ret)
+}
+
+/// The config section holding the settings of the submodule `name`.
+fn submodule_section(name: &str) -> String {
+    format!("submodule \"{}\"", name)
+}
+
+/// Turns the pathspecs given on the command line into worktree-relative
+/// ones for `path_matches`.
+fn submodule_specs(repo: &Repository, paths: &[PathBuf]) -> Result<Vec<String>> {
+    paths.iter().map(|p| repo_relative_path(repo, p)).collect()
+}
+
+fn submodule_selected(specs: &[String], path: &str) -> bool {
+    specs.is_empty() || specs.iter().any(|s| path_matches(s, path))
+}
+
+/// Prints one line per submodule in the index: the commit it has checked
+/// out and a name for it, prefixed with `-` if it is not checked out, `+`
+/// if that is not the recorded commit, and `U` if it has conflicts.
+pub fn submodule_status_print(paths: &[PathBuf]) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let specs = submodule_specs(&repo, paths)?;
+    let index = index_read(&repo)?;
+    let mut conflicted = BTreeSet::new();
+    for entry in &index.entries {
+        if entry.mode_type != 0b1110 || !submodule_selected(&specs, &entry.name) {
+            continue;
+        }
+        if entry.flag_stage != 0 {
+            if conflicted.insert(entry.name.as_str()) {
+                println!("U{} {}", ZERO_SHA, entry.name);
+            }
+            continue;
+        }
+        let sub = submodule_open(&repo, &entry.name);
+        let head = match &sub {
+            Some(sub) => ref_resolve(sub, "HEAD")?,
+            None => None,
+        };
+        match (sub, head) {
+            (Some(sub), Some(head)) => {
+                let prefix = if head == entry.sha { ' ' } else { '+' };
+                let name = submodule_describe(&sub, &head);
+                println!("{}{} {} ({})", prefix, head, entry.name, name);
+            }
+            _ => println!("-{} {}", entry.sha, entry.name),
+        }
+    }
+    Ok(())
+}
+
+/// Names the commit a submodule is at the way `submodule status` does:
+/// after an annotated tag, any tag, then any ref pointing at it, falling
+/// back to its abbreviated id.
+fn submodule_describe(sub: &Repository, sha: &str) -> String {
+    let abbrev = abbrev_len(sub, None);
+    if let Ok(name) = describe_commit(sub, sha, false, abbrev)
+        .or_else(|_| describe_commit(sub, sha, true, abbrev))
+    {
+        return name;
+    }
+    let refs = ref_list_flat(sub, None, Some("refs")).unwrap_or_default();
+    match refs.iter().find(|(_, tip)| *tip == sha) {
+        Some((name, _)) => name.trim_start_matches("refs/").to_string(),
+        None => sha_abbrev(sub, sha, abbrev),
+    }
+}
+
+/// Copies the url of each selected submodule from `.gitmodules` into the
+/// repository's config, which marks it as wanted by `update`. Submodules
+/// that are already initialized keep their configured url.
+pub fn submodule_init(paths: &[PathBuf]) -> Result<()> {
+    let mut repo = repo_find(Path::new("."), true)?.unwrap();
+    let specs = submodule_specs(&repo, paths)?;
+    for sub in submodules_read(&repo)? {
+        let section = submodule_section(&sub.name);
+        if !submodule_selected(&specs, &sub.path) || repo.config_get(&section, "url").is_some() {
+            continue;
+        }
+        let Some(url) = &sub.url else {
+            bail!(
+                "No url found for submodule path '{}' in .gitmodules",
+                sub.path
+            );
+        };
+        let url = submodule_url_resolve(&repo, url);
+        repo.config_set(&section, "active", "true")?;
+        repo.config_set(&section, "url", &url)?;
+        println!(
+            "Submodule '{}' ({}) registered for path '{}'",
+            sub.name, url, sub.path
+        );
+    }
+    Ok(())
+}
+
+/// Resolves a submodule url relative to the superproject (`./x`, `../x`)
+/// against the superproject's origin, or against its worktree when it has
+/// no origin. Other urls are returned as they are.
+pub fn submodule_url_resolve(repo: &Repository, url: &str) -> String {
+    if !(url.starts_with("./") || url.starts_with("../")) {
+        return url.to_string();
+    }
+    let mut base = repo
+        .config_get("remote \"origin\"", "url")
+        .unwrap_or_else(|| repo.worktree.display().to_string());
+    let mut rest = url;
+    loop {
+        if let Some(r) = rest.strip_prefix("./") {
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("../") {
+            let trimmed = base.trim_end_matches('/');
+            base.truncate(trimmed.rfind('/').unwrap_or(0));
+            rest = r;
+        } else {
+            break;
+        }
+    }
+    format!("{}/{}", base.trim_end_matches('/'), rest)
+}
+
+/// Checks out the recorded commit in every selected, initialized submodule,
+/// cloning those that are not there yet. HEAD is left detached, as in git.
+pub fn submodule_update(init: bool, paths: &[PathBuf]) -> Result<()> {
+    if init {
+        submodule_init(paths)?;
+    }
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let specs = submodule_specs(&repo, paths)?;
+    let index = index_read(&repo)?;
+    for sub in submodules_read(&repo)? {
+        if !submodule_selected(&specs, &sub.path) {
+            continue;
+        }
+        let Some(url) = repo.config_get(&submodule_section(&sub.name), "url") else {
+            continue;
+        };
+        let Some(entry) = index
+            .entries
+            .iter()
+            .find(|e| e.name == sub.path && e.mode_type == 0b1110)
+        else {
+            continue;
+        };
+        let checkout = match submodule_open(&repo, &sub.path) {
+            Some(checkout) => checkout,
+            None => submodule_clone(&repo, &sub, &url)?,
+        };
+        if ref_resolve(&checkout, "HEAD")?.as_deref() == Some(entry.sha.as_str()) {
+            continue;
+        }
+        if object_read(&checkout, &entry.sha).is_err() {
+            bail!(
+                "Fetched in submodule path '{}', but it did not contain {}",
+                sub.path,
+                entry.sha
+            );
+        }
+        checkout_tree(&checkout, &entry.sha, false)?;
+        head_detach(&checkout, &entry.sha)?;
+        println!("Submodule path '{}': checked out '{}'", sub.path, entry.sha);
+    }
+    Ok(())
+}
+
+/// Clones the repository at `url` for `sub`: its git directory goes to
+/// `.git/modules/<name>` and its worktree to the submodule's path, linked
+/// by a .git file. The origin's branches become remote-tracking refs and
+/// HEAD is left unborn for `update` to check out the recorded commit.
+/// Only repositories on the local filesystem can be cloned.
+fn submodule_clone(repo: &Repository, sub: &Submodule, url: &str) -> Result<Repository> {
+    let source_path = repo
+        .worktree
+        .join(url.strip_prefix("file://").unwrap_or(url));
+    let source =
+        repo_open(&source_path).map_err(|_| anyhow!("repository '{}' does not exist", url))?;
+    let worktree = repo.worktree.join(&sub.path);
+    let gitdir = repo.commondir.join("modules").join(&sub.name);
+    eprintln!("Cloning into '{}'...", worktree.display());
+
+    let objects = source.commondir.join("objects");
+    for entry in WalkDir::new(&objects) {
+        let entry = entry?;
+        let dest = gitdir
+            .join("objects")
+            .join(entry.path().strip_prefix(&objects)?);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(dest)?;
+        } else {
+            fs::copy(entry.path(), dest)?;
+        }
+    }
+    fs::create_dir_all(gitdir.join("refs/heads"))?;
+    fs::create_dir_all(gitdir.join("refs/tags"))?;
+    fs::write(gitdir.join("HEAD"), "ref: refs/heads/master\n")?;
+
+    // Both links are relative, so the superproject can be moved.
+    let up = |n: usize| "../".repeat(n);
+    let name_depth = Path::new(&sub.name).components().count();
+    let path_depth = Path::new(&sub.path).components().count();
+    let mut conf = repo_default_config();
+    let worktree_rel = format!("{}{}", up(name_depth + 2), sub.path);
+    conf.set("core", "worktree", &worktree_rel);
+    conf.set("remote \"origin\"", "url", url);
+    conf.set(
+        "remote \"origin\"",
+        "fetch",
+        "+refs/heads/*:refs/remotes/origin/*",
+    );
+    conf.write(&gitdir.join("config"))?;
+    fs::create_dir_all(&worktree)?;
+    let dotgit = if repo.commondir == repo.worktree.join(".git") {
+        format!("{}.git/modules/{}", up(path_depth), sub.name)
+    } else {
+        gitdir.display().to_string()
+    };
+    fs::write(worktree.join(".git"), format!("gitdir: {}\n", dotgit))?;
+
+    let clone = repo_open(&worktree)?;
+    for (name, sha) in ref_list_flat(&source, None, Some("refs"))? {
+        let local = match name.strip_prefix("refs/heads/") {
+            Some(branch) => format!("refs/remotes/origin/{}", branch),
+            None if name.starts_with("refs/tags/") => name,
+            None => continue,
+        };
+        let path = repo_file(&clone, PathBuf::from(local), true)?;
+        file_write_locked(&path, format!("{}\n", sha).as_bytes(), clone.fsync.refs)?;
+    }
+    Ok(clone)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        ref_create, repo_create, submodule_status, submodule_url_resolve, submodules_read,
+        test_utils::{make_commit, test_repo},
+        IgnoreSubmodules,
+    };
+
+    #[test]
+    fn test_submodules_read() {
+        let mut repo = test_repo("gitmodules");
+        fs::write(
+            repo.worktree.join(".gitmodules"),
+            "[submodule \"lib\"]\n\tpath = deps/lib/\n\turl = ../lib.git\n\
+             [submodule \"nopath\"]\n\turl = /srv/x\n",
+        )
+        .unwrap();
+        let subs = submodules_read(&repo).unwrap();
+        assert_eq!(subs.len(), 1);
+        assert_eq!(
+            (subs[0].name.as_str(), subs[0].path.as_str()),
+            ("lib", "deps/lib")
+        );
+
+        let url = subs[0].url.as_deref().unwrap();
+        let parent = repo.worktree.parent().unwrap().join("lib.git");
+        assert_eq!(
+            submodule_url_resolve(&repo, url),
+            parent.display().to_string()
+        );
+        repo.config_set("remote \"origin\"", "url", "https://example.com/org/app")
+            .unwrap();
+        assert_eq!(
+            submodule_url_resolve(&repo, url),
+            "https://example.com/org/lib.git"
+        );
+        assert_eq!(submodule_url_resolve(&repo, "/srv/x"), "/srv/x");
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_submodule_status() {
+        let repo = test_repo("submodule");
+        let sub = repo_create(repo.worktree.join("sub")).unwrap();
+        let first = make_commit(&sub, "first", &[]);
+        let second = make_commit(&sub, "second", &[&first]);
+        ref_create(&sub, "heads/master", &second).unwrap();
+
+        // Nothing is checked out in the submodule, so HEAD's file shows as
+        // deleted there.
+        let status = submodule_status(&repo, "sub", &first, IgnoreSubmodules::None).unwrap();
+        assert_eq!(status.head.as_deref(), Some(second.as_str()));
+        assert_eq!(status.describe(), "new commits, modified content");
+
+        fs::write(sub.worktree.join("new"), "new").unwrap();
+        let status = submodule_status(&repo, "sub", &second, IgnoreSubmodules::None).unwrap();
+        assert_eq!(status.describe(), "modified content, untracked content");
+        let status = submodule_status(&repo, "sub", &second, IgnoreSubmodules::Untracked).unwrap();
+        assert_eq!(status.describe(), "modified content");
+        let status = submodule_status(&repo, "sub", &second, IgnoreSubmodules::Dirty).unwrap();
+        assert!(!status.is_modified());
+
+        let status = submodule_status(&repo, "missing", &second, IgnoreSubmodules::None).unwrap();
+        assert!(status.head.is_none() && !status.is_modified());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_get_active, branch_name_valid, file_write_locked, index_entry_modified, index_read,
+    index_write, object_find, object_read, ref_create, ref_resolve, reflog_append, repo_file,
+    repo_find, sha_short, sparse_patterns, symref_write, tree_mode_split, tree_to_leaves,
+    worktree_branch_holder, worktree_file_hash, Blob, Commit, IndexEntry, Repository, TreeLeaf,
+};
+
+pub fn switch(name: &str, create: bool, detach: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+
+    if create {
+        if !branch_name_valid(name) {
+            bail!("'{}' is not a valid branch name", name);
+        }
+        if ref_resolve(&repo, &format!("refs/heads/{}", name))?.is_some() {
+            bail!("A branch named '{}' already exists", name);
+        }
+        let head = ref_resolve(&repo, "HEAD")?
+            .ok_or_else(|| anyhow!("Cannot create branch '{}': HEAD has no commits", name))?;
+        let refname = format!("refs/heads/{}", name);
+        ref_create(&repo, &format!("heads/{}", name), &head)?;
+        reflog_append(&repo, &refname, None, &head, "branch: Created from HEAD")?;
+    }
+
+    let is_branch = ref_resolve(&repo, &format!("refs/heads/{}", name))?.is_some();
+    if !is_branch && !detach {
+        bail!("A branch is expected, got '{}'", name);
+    }
+    switch_to(&repo, name, is_branch && !detach)
+}
+
+/// Moves HEAD, the index and the worktree to `name`. When `attach` is set
+/// HEAD becomes a symbolic ref to `refs/heads/<name>`, otherwise it is
+/// detached at the commit `name` resolves to.
+pub fn switch_to(repo: &Repository, name: &str, attach: bool) -> Result<()> {
+    let sha = object_find(repo, name, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("Reference is not a commit: {}", name))?;
+    if attach {
+        if let Some(holder) = worktree_branch_holder(repo, name)? {
+            let here = fs::canonicalize(&repo.worktree).unwrap_or(repo.worktree.clone());
+            if fs::canonicalize(&holder).unwrap_or(holder.clone()) != here {
+                bail!(
+                    "'{}' is already used by worktree at '{}'",
+                    name,
+                    holder.display()
+                );
+            }
+        }
+    }
+    let from = head_position(repo)?;
+    checkout_tree(repo, &sha, false)?;
+
+    if attach {
+        head_attach(repo, name)?;
+        head_log_checkout(repo, &from, name, &sha)?;
+        println!("Switched to branch '{}'", name);
+    } else {
+        head_detach(repo, &sha)?;
+        head_log_checkout(repo, &from, name, &sha)?;
+        println!(
+            "HEAD is now at {} {}",
+            sha_short(repo, &sha),
+            commit_subject(repo, &sha)?
+        );
+    }
+    Ok(())
+}
+
+/// Points HEAD at `refs/heads/<branch>`.
+pub fn head_attach(repo: &Repository, branch: &str) -> Result<()> {
+    symref_write(repo, "HEAD", &format!("refs/heads/{}", branch), None)
+}
+
+/// HEAD's branch, or its commit when detached, and the commit it is at:
+/// where a checkout moves HEAD from.
+pub fn head_position(repo: &Repository) -> Result<(String, Option<String>)> {
+    let sha = ref_resolve(repo, "HEAD")?;
+    let name = match branch_get_active(repo)? {
+        Some(branch) => branch,
+        None => sha.clone().unwrap_or_default(),
+    };
+    Ok((name, sha))
+}
+
+/// Records in HEAD's reflog that a checkout moved it from `from`, as
+/// `head_position` saw it before the move, to `new`, named `to`.
+pub fn head_log_checkout(
+    repo: &Repository,
+    from: &(String, Option<String>),
+    to: &str,
+    new: &str,
+) -> Result<()> {
+    reflog_append(
+        repo,
+        "HEAD",
+        from.1.as_deref(),
+        new,
+        &format!("checkout: moving from {} to {}", from.0, to),
+    )
+}
+
+/// Points HEAD directly at a commit.
+pub fn head_detach(repo: &Repository, sha: &str) -> Result<()> {
+    let path = repo_file(repo, PathBuf::from("HEAD"), false)?;
+    file_write_locked(&path, format!("{}\n", sha).as_bytes(), repo.fsync.refs)?;
+    Ok(())
+}
+
+/// Points whatever HEAD refers to at `sha`: the current branch if HEAD is
+/// symbolic, HEAD itself otherwise. The move is logged with `message` in
+/// the reflogs of HEAD and of the branch.
+pub fn head_update(repo: &Repository, sha: &str, message: &str) -> Result<()> {
+    let head = fs::read_to_string(repo_file(repo, PathBuf::from("HEAD"), false)?)?;
+    let target = match head.trim_end().strip_prefix("ref: ") {
+        Some(refname) => refname.to_string(),
+        None => "HEAD".to_string(),
+    };
+    let old = ref_resolve(repo, "HEAD")?;
+    let path = repo_file(repo, PathBuf::from(&target), true)?;
+    file_write_locked(&path, format!("{}\n", sha).as_bytes(), repo.fsync.refs)?;
+    reflog_append(repo, "HEAD", old.as_deref(), sha, message)?;
+    if target != "HEAD" {
+        reflog_append(repo, &target, old.as_deref(), sha, message)?;
+    }
+    Ok(())
+}
+
+/// Returns the first line of a commit's message.
+pub fn commit_subject(repo: &Repository, sha: &str) -> Result<String> {
+    let obj = object_read(repo, sha)?;
+    let commit = obj
+        .as_any()
+        .downcast_ref::<Commit>()
+        .ok_or_else(|| anyhow!("Object {} is not a commit", sha))?;
+    let msg = commit
+        .kvlm
+        .get(&None)
+        .and_then(|v| v.first())
+        .map(|m| String::from_utf8_lossy(m).to_string())
+        .unwrap_or_default();
+    Ok(msg.lines().next().unwrap_or("").to_string())
+}
+
+/// Returns HEAD's tree as a path -> leaf map, empty on an unborn branch.
+pub fn head_leaves(repo: &Repository) -> Result<HashMap<String, TreeLeaf>> {
+    match ref_resolve(repo, "HEAD")? {
+        Some(sha) => leaves_map(repo, &sha),
+        None => Ok(HashMap::new()),
+    }
+}
+
+fn leaves_map(repo: &Repository, tree_ref: &str) -> Result<HashMap<String, TreeLeaf>> {
+    Ok(tree_to_leaves(repo, tree_ref, "")?
+        .into_iter()
+        .map(|leaf| (leaf.path.clone(), leaf))
+        .collect())
+}
+
+/// Updates the index and worktree from HEAD's tree to the tree of `target`.
+///
+/// Only paths that differ between the two trees are touched, so staged and
+/// unstaged changes to other files are carried over. Unless `force` is set,
+/// the update is refused before anything is written if it would overwrite
+/// local modifications or untracked files. With `force`, the index and
+/// worktree are made to match `target` exactly. With sparse checkout, the
+/// files it leaves out are only updated in the index.
+pub fn checkout_tree(repo: &Repository, target: &str, force: bool) -> Result<()> {
+    let current = head_leaves(repo)?;
+    let wanted = leaves_map(repo, target)?;
+    let mut index = index_read(repo)?;
+
+    let same = |a: Option<&TreeLeaf>, b: Option<&TreeLeaf>| match (a, b) {
+        (Some(a), Some(b)) => a.sha == b.sha && a.mode == b.mode,
+        (None, None) => true,
+        _ => false,
+    };
+
+    let mut changed: BTreeSet<String> = current
+        .keys()
+        .chain(wanted.keys())
+        .filter(|p| !same(current.get(*p), wanted.get(*p)))
+        .cloned()
+        .collect();
+
+    if force {
+        for entry in &index.entries {
+            let matches = wanted
+                .get(&entry.name)
+                .is_some_and(|leaf| leaf.sha == entry.sha && leaf.mode == entry.tree_mode());
+            if !matches || index_entry_modified(repo, entry)? {
+                changed.insert(entry.name.clone());
+            }
+        }
+    } else {
+        let mut conflicts = Vec::new();
+        for path in &changed {
+            let want_sha = wanted.get(path).map(|l| &l.sha);
+            match index.entries.iter().find(|e| &e.name == path) {
+                Some(entry) => {
+                    let dirty = index_entry_modified(repo, entry)?;
+                    let staged = current.get(path).map(|l| &l.sha) != Some(&entry.sha);
+                    let already_there = want_sha == Some(&entry.sha) && !dirty;
+                    if (dirty || staged) && !already_there {
+                        conflicts.push(path.clone());
+                    }
+                }
+                None => {
+                    let full_path = repo.worktree.join(path);
+                    if want_sha.is_some()
+                        && fs::symlink_metadata(&full_path).is_ok()
+                        && Some(&worktree_file_hash(repo, path)?) != want_sha
+                    {
+                        conflicts.push(path.clone());
+                    }
+                }
+            }
+        }
+        if !conflicts.is_empty() {
+            bail!(
+                "Your local changes to the following files would be overwritten:\n\t{}\n\
+                 Please commit your changes or stash them before you switch branches.",
+                conflicts.join("\n\t")
+            );
+        }
+    }
+
+    // Files outside the sparse patterns only go into the index.
+    let sparse = sparse_patterns(repo)?;
+    index.entries.retain(|e| !changed.contains(&e.name));
+    for path in &changed {
+        match wanted.get(path) {
+            Some(leaf) if sparse.as_ref().is_some_and(|p| !p.includes(path)) => {
+                remove_file(repo, path)?;
+                let (mode_type, mode_perms) = tree_mode_split(&leaf.mode)?;
+                index.entries.push(IndexEntry {
+                    mode_type,
+                    mode_perms,
+                    sha: leaf.sha.clone(),
+                    name: path.clone(),
+                    flag_skip_worktree: true,
+                    ..Default::default()
+                });
+            }
+            Some(leaf) => {
+                checkout_file(repo, leaf)?;
+                index
+                    .entries
+                    .push(IndexEntry::from_file(repo, path, &leaf.sha, &leaf.mode)?);
+            }
+            None => remove_file(repo, path)?,
+        }
+    }
+    index.entries.sort_by(|a, b| a.name.cmp(&b.name));
+    index_write(repo, &index)
+}
+
+/// Writes the blob `leaf` points to into the worktree at `leaf.path`,
+/// replacing whatever was there.
+pub fn checkout_file(repo: &Repository, leaf: &TreeLeaf) -> Result<()> {
+    checkout_file_to(repo, leaf, &repo.worktree.join(&leaf.path))
+}
+
+/// Like `checkout_file`, writing the blob to `dest` instead.
+pub fn checkout_file_to(repo: &Repository, leaf: &TreeLeaf, dest: &Path) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // A submodule's directory is left as it is: moving its checkout to the
+    // recorded commit is up to `submodule update`.
+    if leaf.mode.starts_with(b"16") && dest.is_dir() {
+        return Ok(());
+    }
+    if let Ok(meta) = fs::symlink_metadata(dest) {
+        if meta.is_dir() && !meta.file_type().is_symlink() {
+            fs::remove_dir_all(dest)?;
+        } else {
+            fs::remove_file(dest)?;
+        }
+    }
+
+    // Gitlinks only get an empty directory for the submodule to live in.
+    if leaf.mode.starts_with(b"16") {
+        fs::create_dir_all(dest)?;
+        return Ok(());
+    }
+
+    let obj = object_read(repo, &leaf.sha)?;
+    let blob = obj
+        .as_any()
+        .downcast_ref::<Blob>()
+        .ok_or_else(|| anyhow!("Object {} is not a blob", leaf.sha))?;
+
+    if leaf.mode.starts_with(b"12") {
+        let target = String::from_utf8_lossy(&blob.blobdata).to_string();
+        std::os::unix::fs::symlink(target, dest)?;
+    } else {
+        fs::write(dest, &blob.blobdata)?;
+        let perms = if leaf.mode == b"100755" { 0o755 } else { 0o644 };
+        fs::set_permissions(dest, fs::Permissions::from_mode(perms))?;
+    }
+    Ok(())
+}
+
+/// Removes a tracked file (or an unpopulated submodule) from the worktree,
+/// along with any directories left empty by its removal.
+pub fn remove_file(repo: &Repository, path: &str) -> Result<()> {
+    let full_path = repo.worktree.join(path);
+    let Ok(meta) = fs::symlink_metadata(&full_path) else {
+        return Ok(());
+    };
+    if meta.is_dir() {
+        // A submodule that was never checked out leaves an empty directory.
+        // A populated one stays, as in git.
+        if fs::remove_dir(&full_path).is_err() {
+            eprintln!("warning: unable to rmdir '{}': Directory not empty", path);
+            return Ok(());
+        }
+    } else {
+        fs::remove_file(&full_path)?;
+    }
+
+    let mut dir = full_path.parent();
+    while let Some(d) = dir {
+        if d == repo.worktree || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{checkout_tree, switch_to};
+    use crate::{
+        branch_get_active, index_read, index_write, object_write, ref_create, ref_resolve,
+        test_utils::{make_commit, test_repo},
+        worktree_create, Blob, IndexEntry,
+    };
+
+    #[test]
+    fn test_switch_local_changes() {
+        let repo = test_repo("switch");
+        let first = make_commit(&repo, "one", &[]);
+        let second = make_commit(&repo, "two", &[&first]);
+        checkout_tree(&repo, &first, true).unwrap();
+        ref_create(&repo, "heads/master", &first).unwrap();
+        ref_create(&repo, "heads/side", &second).unwrap();
+
+        // A new file staged and an untracked one don't differ between the
+        // branches, so they are carried over.
+        fs::write(repo.worktree.join("staged"), "staged\n").unwrap();
+        let blob = object_write(&Blob::new(b"staged\n"), Some(&repo)).unwrap();
+        let mut index = index_read(&repo).unwrap();
+        index
+            .entries
+            .push(IndexEntry::from_file(&repo, "staged", &blob, b"100644").unwrap());
+        index_write(&repo, &index).unwrap();
+        fs::write(repo.worktree.join("untracked"), "untracked\n").unwrap();
+
+        // A modified file the switch would overwrite is refused, and nothing
+        // is touched.
+        fs::write(repo.worktree.join("file"), "mine\n").unwrap();
+        assert!(switch_to(&repo, "side", true).is_err());
+        assert_eq!(branch_get_active(&repo).unwrap().as_deref(), Some("master"));
+        assert_eq!(
+            fs::read_to_string(repo.worktree.join("file")).unwrap(),
+            "mine\n"
+        );
+
+        fs::write(repo.worktree.join("file"), "one").unwrap();
+        switch_to(&repo, "side", true).unwrap();
+        assert_eq!(branch_get_active(&repo).unwrap().as_deref(), Some("side"));
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(second));
+        assert_eq!(
+            fs::read_to_string(repo.worktree.join("file")).unwrap(),
+            "two"
+        );
+        let index = index_read(&repo).unwrap();
+        assert!(index.entries.iter().any(|e| e.name == "staged"));
+        assert!(repo.worktree.join("untracked").exists());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_switch_branch_in_other_worktree() {
+        let repo = test_repo("switch-worktree");
+        let first = make_commit(&repo, "one", &[]);
+        checkout_tree(&repo, &first, true).unwrap();
+        ref_create(&repo, "heads/master", &first).unwrap();
+        ref_create(&repo, "heads/topic", &first).unwrap();
+        let path = repo.worktree.join("linked");
+        let linked = worktree_create(&repo, &path, Some("topic"), &first).unwrap();
+
+        let err = switch_to(&repo, "topic", true).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("'topic' is already used by worktree"));
+        assert_eq!(branch_get_active(&repo).unwrap().as_deref(), Some("master"));
+        assert!(switch_to(&linked, "master", true).is_err());
+
+        // Its own branch, or the commit detached, is fine.
+        switch_to(&linked, "topic", true).unwrap();
+        switch_to(&repo, "topic", false).unwrap();
+        assert_eq!(branch_get_active(&repo).unwrap(), None);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
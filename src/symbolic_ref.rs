@@ -0,0 +1,83 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    file_write_locked, ref_resolve, reflog_append, refname_valid, repo_file, repo_find, Repository,
+};
+
+/// Prints the ref `name` points to, or with `target` points it there
+/// instead, logging `message` in its reflog if given. `short` prints the
+/// target without its `refs/heads/`-like prefix.
+pub fn symbolic_ref(
+    name: &str,
+    target: Option<&str>,
+    message: Option<&str>,
+    short: bool,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    if let Some(target) = target {
+        return symref_write(&repo, name, target, message);
+    }
+    let Some(target) = symref_read(&repo, name)? else {
+        bail!("ref {} is not a symbolic ref", name);
+    };
+    match short {
+        true => println!("{}", refname_shorten(&target)),
+        false => println!("{}", target),
+    }
+    Ok(())
+}
+
+/// The ref the symbolic ref `name` (such as "HEAD") points to, or None if
+/// `name` doesn't exist or points directly at an object.
+pub fn symref_read(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let path = repo_file(repo, PathBuf::from(name), false)?;
+    let Ok(content) = fs::read_to_string(path) else {
+        return Ok(None);
+    };
+    Ok(content.trim_end().strip_prefix("ref: ").map(str::to_string))
+}
+
+/// Points the symbolic ref `name` at the ref `target`, which must be a
+/// valid name under refs/, taking the ref's lock while writing. When
+/// `message` is given and the commit `name` resolves to changes, the move
+/// goes in its reflog.
+pub fn symref_write(
+    repo: &Repository,
+    name: &str,
+    target: &str,
+    message: Option<&str>,
+) -> Result<()> {
+    if !target.starts_with("refs/") || !refname_valid(target, false) {
+        bail!("refusing to point {} outside refs/: {}", name, target);
+    }
+    if !refname_valid(name, true) {
+        bail!("invalid ref name '{}'", name);
+    }
+    let old = ref_resolve(repo, name)?;
+    let path = repo_file(repo, PathBuf::from(name), true)?;
+    file_write_locked(
+        &path,
+        format!("ref: {}\n", target).as_bytes(),
+        repo.fsync.refs,
+    )?;
+    if let (Some(message), Some(new)) = (message, ref_resolve(repo, target)?) {
+        if old.as_deref() != Some(new.as_str()) {
+            reflog_append(repo, name, old.as_deref(), &new, message)?;
+        }
+    }
+    Ok(())
+}
+
+/// `refname` without the refs/heads/, refs/tags/ or refs/remotes/ that
+/// branch and tag names leave out.
+pub fn refname_shorten(refname: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| refname.strip_prefix(prefix))
+        .unwrap_or(refname)
+}
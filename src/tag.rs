@@ -1,16 +1,23 @@
 use std::{
-    fs::File,
+    cmp::Ordering,
+    fs,
     io::Write,
     path::{Path, PathBuf},
+    process::{Command, Stdio},
 };
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use indexmap::IndexMap;
 
 use crate::{
-    kvlm_parse, kvlm_serialize, object_find, object_write, ref_list_flat, repo_dir, repo_file,
-    repo_find, show_ref_print, Kvlm, Object, Repository,
+    file_write_locked, ident_default, kvlm_get_str, kvlm_parse, kvlm_serialize, object_find,
+    object_read, object_write, ref_delete, ref_list_flat, ref_resolve, refname_valid, repo_file,
+    repo_find, sha_short, show_ref_print, stripspace_text, Ancestry, Kvlm, Object, Repository,
 };
 
+/// Where the signature of a signed tag starts, at the end of its message.
+const TAG_SIGNATURE_BEGIN: &[u8] = b"-----BEGIN PGP SIGNATURE-----";
+
 #[derive(Default)]
 pub struct Tag {
     pub kvlm: Kvlm,
@@ -21,6 +28,11 @@ impl Tag {
         let kvlm = kvlm_parse(data);
         Self { kvlm }
     }
+
+    /// The sha of the tagged object.
+    pub fn object(&self) -> Option<String> {
+        kvlm_get_str(&self.kvlm, b"object")
+    }
 }
 
 impl Object for Tag {
@@ -37,49 +49,383 @@ impl Object for Tag {
     }
 }
 
-pub fn tag(create_tag_object: bool, name: Option<String>, obj: &str) -> Result<()> {
+/// Which tags to list and in what order, like the filters of
+/// `rit branch`.
+#[derive(Default)]
+pub struct TagFilter {
+    /// Only tags whose name matches one of these globs, if any are given.
+    pub patterns: Vec<String>,
+    /// Only tags whose commit contains this commit.
+    pub contains: Option<String>,
+    /// Only tags that point at this object, directly or through a tag
+    /// object.
+    pub points_at: Option<String>,
+    /// `refname` or `version:refname` (alias `v:refname`), with a leading
+    /// `-` to reverse.
+    pub sort: Option<String>,
+}
+
+/// How `tag` writes a new tag.
+#[derive(Default)]
+pub struct TagOptions {
+    /// Write a tag object rather than a lightweight tag; implied by
+    /// `messages`.
+    pub annotate: bool,
+    /// The tag object's message, a paragraph each.
+    pub messages: Vec<String>,
+    /// Replace a tag of the same name.
+    pub force: bool,
+}
+
+/// Tags the object `args` names (HEAD if only a name is given) with the
+/// name `args[0]`, with a tag object recording the tagger and a message
+/// when annotating.
+pub fn tag(args: &[String], opts: &TagOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let (name, obj) = match args {
+        [name] => (name, "HEAD"),
+        [name, obj] => (name, obj.as_str()),
+        _ => bail!("usage: rit tag [-a] [-m <msg>] [-f] <name> [<object>]"),
+    };
+    tag_create(&repo, name, obj, opts)
+}
+
+/// Prints the names of the tags that pass `filter`.
+pub fn tag_show_list(filter: &TagFilter) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    show_ref_print(&tag_list(&repo, filter)?);
+    Ok(())
+}
+
+/// Deletes the tags `names`. All of them are tried even when one does
+/// not exist.
+pub fn tag_delete(names: &[String]) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let mut missing = false;
+    for name in names {
+        let refname = format!("refs/tags/{}", name);
+        let Some(sha) = ref_resolve(&repo, &refname)? else {
+            eprintln!("error: tag '{}' not found.", name);
+            missing = true;
+            continue;
+        };
+        ref_delete(&repo, &refname)?;
+        println!("Deleted tag '{}' (was {})", name, sha_short(&repo, &sha));
+    }
+    if missing {
+        bail!("some tags could not be deleted");
+    }
+    Ok(())
+}
+
+/// Checks the GPG signature of the tag objects `names` with `gpg.program`,
+/// and prints what they say.
+pub fn tag_verify(names: &[String]) -> Result<()> {
     let repo = repo_find(Path::new("."), true)?.unwrap();
-    if let Some(name) = name {
-        tag_create(&repo, &name, obj, create_tag_object)?;
-    } else {
-        let refs = ref_list_flat(
-            &repo,
-            Some(repo_dir(&repo, PathBuf::from("refs/tags"), false)?.unwrap()),
-            None,
-        )?;
-        show_ref_print(&refs, false);
+    for name in names {
+        let sha = ref_resolve(&repo, &format!("refs/tags/{}", name))?
+            .ok_or_else(|| anyhow!("tag '{}' not found.", name))?;
+        let obj = object_read(&repo, &sha)?;
+        let tag = obj.as_any().downcast_ref::<Tag>().ok_or_else(|| {
+            anyhow!(
+                "{}: cannot verify a non-tag object of type {}",
+                name,
+                String::from_utf8_lossy(obj.fmt())
+            )
+        })?;
+        let raw = tag.serialize();
+        let Some(start) = raw
+            .windows(TAG_SIGNATURE_BEGIN.len())
+            .position(|w| w == TAG_SIGNATURE_BEGIN)
+        else {
+            bail!("{}: no signature found", name);
+        };
+        let (payload, signature) = raw.split_at(start);
+        // gpg reads the detached signature from a file and the signed
+        // payload from stdin.
+        let sig_path = std::env::temp_dir().join(format!("rit-tag-{}.sig", std::process::id()));
+        fs::write(&sig_path, signature)?;
+        let program = repo
+            .config_get("gpg", "program")
+            .unwrap_or_else(|| "gpg".to_string());
+        let child = Command::new(&program)
+            .args(["--verify"])
+            .arg(&sig_path)
+            .arg("-")
+            .stdin(Stdio::piped())
+            .spawn();
+        let status = child.and_then(|mut child| {
+            child.stdin.take().unwrap().write_all(payload)?;
+            child.wait()
+        });
+        let _ = fs::remove_file(&sig_path);
+        let status = status.map_err(|e| anyhow!("cannot run {}: {}", program, e))?;
+        if !status.success() {
+            bail!("could not verify the tag '{}'", name);
+        }
+        std::io::stdout().write_all(payload)?;
     }
     Ok(())
 }
 
-fn tag_create(repo: &Repository, name: &str, obj_ref: &str, create_tag_object: bool) -> Result<()> {
-    let sha = object_find(repo, obj_ref, None, true)?.unwrap();
-
-    if create_tag_object {
-        let mut tag = Tag::default();
-        tag.kvlm
-            .insert(Some(b"object".to_vec()), vec![sha.clone().into_bytes()]);
-        tag.kvlm
-            .insert(Some(b"type".to_vec()), vec![b"commit".to_vec()]);
-        tag.kvlm
-            .insert(Some(b"tag".to_vec()), vec![name.as_bytes().to_vec()]);
-        tag.kvlm.insert(
-            Some(b"tagger".to_vec()),
-            vec![b"Wyag <wyag@example.com>".to_vec()],
-        );
-        tag.kvlm
-            .insert(None, vec![b"A tag generated by wyag\n".to_vec()]);
-        let tag_sha = object_write(&tag, Some(repo))?;
-        ref_create(repo, &format!("tags/{}", name), &tag_sha)?;
-    } else {
-        ref_create(repo, &format!("tags/{}", name), &sha)?;
+/// Returns the tags (name -> ref value) that pass `filter`, in the order
+/// it asks for.
+pub fn tag_list(repo: &Repository, filter: &TagFilter) -> Result<IndexMap<String, String>> {
+    let tags = ref_list_flat(repo, Some(repo.repo_path("refs/tags".into())), None)?;
+
+    let contains = match &filter.contains {
+        Some(n) => Some(
+            object_find(repo, n, Some(b"commit"), true)?
+                .ok_or_else(|| anyhow!("malformed object name {}", n))?,
+        ),
+        None => None,
+    };
+    let patterns = filter
+        .patterns
+        .iter()
+        .map(|p| glob::Pattern::new(p).map_err(|e| anyhow!("invalid pattern '{}': {}", p, e)))
+        .collect::<Result<Vec<_>>>()?;
+    let points_at = match &filter.points_at {
+        Some(n) => Some(object_find(repo, n, None, true)?.unwrap()),
+        None => None,
+    };
+
+    let mut ancestry = Ancestry::new(repo)?;
+    let mut ret = IndexMap::new();
+    for (name, sha) in tags {
+        if !patterns.is_empty() && !patterns.iter().any(|p| p.matches(&name)) {
+            continue;
+        }
+        if let Some(target) = &points_at {
+            // Like git, match the ref itself or the object a tag object
+            // points at.
+            let peeled = match object_read(repo, &sha)?.as_any().downcast_ref::<Tag>() {
+                Some(tag) => tag.object(),
+                None => None,
+            };
+            if &sha != target && peeled.as_ref() != Some(target) {
+                continue;
+            }
+        }
+        if let Some(commit) = &contains {
+            // Tags of trees and blobs contain no commits.
+            match object_find(repo, &sha, Some(b"commit"), true)? {
+                Some(tip) if ancestry.contains(&tip, commit)? => {}
+                _ => continue,
+            }
+        }
+        ret.insert(name, sha);
+    }
+
+    if let Some(key) = &filter.sort {
+        let (reverse, key) = match key.strip_prefix('-') {
+            Some(k) => (true, k),
+            None => (false, key.as_str()),
+        };
+        match key {
+            "refname" => ret.sort_by(|a, _, b, _| a.cmp(b)),
+            "version:refname" | "v:refname" => ret.sort_by(|a, _, b, _| version_cmp(a, b)),
+            _ => bail!("unsupported sort key '{}'", key),
+        }
+        if reverse {
+            ret.reverse();
+        }
+    }
+    Ok(ret)
+}
+
+/// Compares two names treating runs of digits as numbers, so that `v1.9`
+/// sorts before `v1.10`.
+pub fn version_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chunks = version_chunks(a);
+    let mut b_chunks = version_chunks(b);
+    loop {
+        let (x, y) = match (a_chunks.next(), b_chunks.next()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => (x, y),
+        };
+        let x_num = x.starts_with(|c: char| c.is_ascii_digit());
+        let y_num = y.starts_with(|c: char| c.is_ascii_digit());
+        let ord = if x_num && y_num {
+            let x = x.trim_start_matches('0');
+            let y = y.trim_start_matches('0');
+            x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+        } else {
+            x.cmp(y)
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+/// Splits a name into alternating runs of digits and non-digits.
+fn version_chunks(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let digit = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != digit)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some(chunk)
+    })
+}
+
+fn tag_create(repo: &Repository, name: &str, obj_ref: &str, opts: &TagOptions) -> Result<()> {
+    if !refname_valid(&format!("tags/{}", name), false) {
+        bail!("'{}' is not a valid tag name.", name);
+    }
+    let sha = object_find(repo, obj_ref, None, true)?
+        .ok_or_else(|| anyhow!("Failed to resolve '{}' as a valid ref.", obj_ref))?;
+    let old = ref_resolve(repo, &format!("refs/tags/{}", name))?;
+    if old.is_some() && !opts.force {
+        bail!("tag '{}' already exists", name);
+    }
+
+    let target = match opts.annotate || !opts.messages.is_empty() {
+        true => {
+            // Without an editor to write one in, the message has to be given.
+            let message = opts
+                .messages
+                .iter()
+                .map(|m| stripspace_text(m, None))
+                .filter(|m| !m.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n");
+            if message.is_empty() {
+                bail!("no tag message given: use -m <message>");
+            }
+            let fmt = object_read(repo, &sha)?.fmt();
+            let mut tag = Tag::default();
+            tag.kvlm
+                .insert(Some(b"object".to_vec()), vec![sha.clone().into_bytes()]);
+            tag.kvlm.insert(Some(b"type".to_vec()), vec![fmt.to_vec()]);
+            tag.kvlm
+                .insert(Some(b"tag".to_vec()), vec![name.as_bytes().to_vec()]);
+            let tagger = ident_default(repo, "COMMITTER")?;
+            tag.kvlm
+                .insert(Some(b"tagger".to_vec()), vec![tagger.into_bytes()]);
+            tag.kvlm.insert(None, vec![message.into_bytes()]);
+            object_write(&tag, Some(repo))?
+        }
+        false => sha,
+    };
+    ref_create(repo, &format!("tags/{}", name), &target)?;
+    if let Some(old) = old.filter(|old| *old != target) {
+        println!("Updated tag '{}' (was {})", name, sha_short(repo, &old));
     }
     Ok(())
 }
 
 pub fn ref_create(repo: &Repository, ref_name: &str, sha: &str) -> Result<()> {
     let path = repo_file(repo, PathBuf::from(format!("refs/{}", ref_name)), false)?;
-    let mut f = File::create(&path)?;
-    f.write_all(format!("{}\n", sha).as_bytes())?;
-    Ok(())
+    file_write_locked(&path, format!("{}\n", sha).as_bytes(), repo.fsync.refs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cmp::Ordering, fs};
+
+    use super::{tag_create, Tag};
+    use crate::{
+        kvlm_get_str, object_read, ref_resolve, tag_list,
+        test_utils::{make_commit, test_repo},
+        version_cmp, Commit, TagFilter, TagOptions,
+    };
+
+    #[test]
+    fn test_tag_create_and_list() {
+        let repo = test_repo("tag");
+        let first = make_commit(&repo, "one", &[]);
+        let second = make_commit(&repo, "two", &[&first]);
+        let lightweight = TagOptions::default();
+        tag_create(&repo, "v1.9", &first, &lightweight).unwrap();
+        assert_eq!(
+            ref_resolve(&repo, "refs/tags/v1.9").unwrap(),
+            Some(first.clone())
+        );
+        assert!(tag_create(&repo, "v1.9", &second, &lightweight).is_err());
+        assert!(tag_create(&repo, "bad..name", &second, &lightweight).is_err());
+
+        // An annotated tag needs a message, and records the tagger.
+        let annotate = TagOptions {
+            annotate: true,
+            ..Default::default()
+        };
+        assert!(tag_create(&repo, "v1.10", &second, &annotate).is_err());
+        let annotated = TagOptions {
+            messages: vec!["Release  \n\n".to_string(), "notes".to_string()],
+            ..Default::default()
+        };
+        tag_create(&repo, "v1.10", &second, &annotated).unwrap();
+        let sha = ref_resolve(&repo, "refs/tags/v1.10").unwrap().unwrap();
+        let obj = object_read(&repo, &sha).unwrap();
+        let tag = obj.as_any().downcast_ref::<Tag>().unwrap();
+        assert_eq!(tag.object(), Some(second.clone()));
+        assert_eq!(kvlm_get_str(&tag.kvlm, b"type").as_deref(), Some("commit"));
+        assert_eq!(kvlm_get_str(&tag.kvlm, b"tag").as_deref(), Some("v1.10"));
+        let tagger = kvlm_get_str(&tag.kvlm, b"tagger").unwrap();
+        assert!(tagger.starts_with("Test <test@example.com> "), "{}", tagger);
+        assert_eq!(tag.kvlm[&None][0], b"Release\n\nnotes\n");
+
+        // A tag of a tree contains no commits.
+        let obj = object_read(&repo, &first).unwrap();
+        let tree = obj.as_any().downcast_ref::<Commit>().unwrap().tree();
+        tag_create(&repo, "tree", &tree.unwrap(), &lightweight).unwrap();
+
+        let names = |filter: TagFilter| -> Vec<String> {
+            tag_list(&repo, &filter).unwrap().into_keys().collect()
+        };
+        let sorted = |sort: &str| TagFilter {
+            sort: Some(sort.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(names(sorted("refname")), ["tree", "v1.10", "v1.9"]);
+        assert_eq!(names(sorted("-v:refname")), ["v1.10", "v1.9", "tree"]);
+        let filter = TagFilter {
+            patterns: vec!["v1.*".to_string()],
+            ..sorted("version:refname")
+        };
+        assert_eq!(names(filter), ["v1.9", "v1.10"]);
+        let filter = TagFilter {
+            contains: Some(first.clone()),
+            ..sorted("refname")
+        };
+        assert_eq!(names(filter), ["v1.10", "v1.9"]);
+        let filter = TagFilter {
+            contains: Some(second.clone()),
+            ..Default::default()
+        };
+        assert_eq!(names(filter), ["v1.10"]);
+        let filter = TagFilter {
+            points_at: Some(second.clone()),
+            ..Default::default()
+        };
+        assert_eq!(names(filter), ["v1.10"]);
+
+        // Forcing moves a tag.
+        let force = TagOptions {
+            force: true,
+            ..Default::default()
+        };
+        tag_create(&repo, "v1.9", &second, &force).unwrap();
+        assert_eq!(ref_resolve(&repo, "refs/tags/v1.9").unwrap(), Some(second));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_version_cmp() {
+        let mut tags = vec!["v1.10", "v1.9", "v1.9-rc1", "v2.0", "v1.2.3", "v01.9"];
+        tags.sort_by(|a, b| version_cmp(a, b));
+        assert_eq!(
+            tags,
+            ["v1.2.3", "v01.9", "v1.9", "v1.9-rc1", "v1.10", "v2.0"]
+        );
+        assert_eq!(version_cmp("a", "a"), Ordering::Equal);
+    }
 }
@@ -0,0 +1,64 @@
+use std::fs;
+
+use crate::{object_write, ref_list_flat, repo_create, Blob, Commit, Repository, Tree, TreeLeaf};
+
+/// Creates a fresh repository in the system temp directory. Its config
+/// holds the identity commits are made as, so that tests don't depend on
+/// the environment, or set it for every other test in the process.
+pub fn test_repo(name: &str) -> Repository {
+    let path = std::env::temp_dir().join(format!("rgit-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&path);
+    let mut repo = repo_create(path).unwrap();
+    repo.config_set("user", "name", "Test").unwrap();
+    repo.config_set("user", "email", "test@example.com")
+        .unwrap();
+    repo
+}
+
+/// Writes a commit whose tree holds a single file containing `content`.
+pub fn make_commit(repo: &Repository, content: &str, parents: &[&str]) -> String {
+    make_commit_at(repo, content, parents, 1262304123)
+}
+
+/// Like `make_commit`, with an explicit commit timestamp.
+pub fn make_commit_at(repo: &Repository, content: &str, parents: &[&str], time: i64) -> String {
+    let blob = object_write(&Blob::new(content.as_bytes()), Some(repo)).unwrap();
+    let tree = Tree {
+        items: vec![TreeLeaf {
+            mode: b"100644".to_vec(),
+            path: "file".to_string(),
+            sha: blob,
+        }],
+    };
+    let tree = object_write(&tree, Some(repo)).unwrap();
+    let mut commit = Commit::default();
+    commit
+        .kvlm
+        .insert(Some(b"tree".to_vec()), vec![tree.into_bytes()]);
+    if !parents.is_empty() {
+        commit.kvlm.insert(
+            Some(b"parent".to_vec()),
+            parents.iter().map(|p| p.as_bytes().to_vec()).collect(),
+        );
+    }
+    let ident = format!("Test <test@example.com> {} +0100", time).into_bytes();
+    commit
+        .kvlm
+        .insert(Some(b"author".to_vec()), vec![ident.clone()]);
+    commit.kvlm.insert(Some(b"committer".to_vec()), vec![ident]);
+    commit
+        .kvlm
+        .insert(None, vec![format!("{}\n", content).into_bytes()]);
+    object_write(&commit, Some(repo)).unwrap()
+}
+
+/// Moves every loose ref into packed-refs, as `git pack-refs --all` does.
+pub fn pack_refs(repo: &Repository) {
+    let refs = ref_list_flat(repo, None, Some("refs")).unwrap();
+    let mut packed = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+    for (name, sha) in &refs {
+        packed.push_str(&format!("{} {}\n", sha, name));
+        let _ = fs::remove_file(repo.repo_path(name.into()));
+    }
+    fs::write(repo.repo_path("packed-refs".into()), packed).unwrap();
+}
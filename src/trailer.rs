@@ -0,0 +1,257 @@
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{comment_char, repo_find};
+
+/// A "Key: value" line at the end of a commit message, such as
+/// Signed-off-by. The value has any continuation lines joined on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trailer {
+    pub key: String,
+    pub value: String,
+}
+
+impl Trailer {
+    /// Parses "Key: value" or, as given on the command line, "Key=value".
+    pub fn parse(arg: &str) -> Result<Self> {
+        trailer_split(arg, &[':', '=']).ok_or_else(|| anyhow!("invalid trailer '{}'", arg))
+    }
+}
+
+/// Lines that mark a paragraph as trailers even among other text, since
+/// git itself writes them.
+const TRAILER_GIT_PREFIXES: [&str; 2] = ["Signed-off-by: ", "(cherry picked from commit "];
+
+/// What sets the patch apart from the message in `format-patch` output.
+const TRAILER_DIVIDER: &str = "---";
+
+/// The trailers of `message`, in order.
+pub fn trailers_parse(message: &str, comment: Option<&str>) -> Vec<Trailer> {
+    let lines: Vec<&str> = message.lines().collect();
+    trailer_block_parse(trailer_block(&lines, comment), comment)
+}
+
+/// The lines of the trailer block, empty if there is none.
+fn trailer_block<'a>(lines: &'a [&'a str], comment: Option<&str>) -> &'a [&'a str] {
+    let end = trailer_content_end(lines, comment);
+    match trailer_block_start(&lines[..end], comment) {
+        Some(start) => &lines[start..end],
+        None => &[],
+    }
+}
+
+fn trailer_block_parse(lines: &[&str], comment: Option<&str>) -> Vec<Trailer> {
+    let mut trailers: Vec<Trailer> = Vec::new();
+    for line in lines {
+        if comment.is_some_and(|c| line.starts_with(c)) {
+            continue;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some(last) = trailers.last_mut() {
+                last.value.push(' ');
+                last.value.push_str(line.trim());
+            }
+            continue;
+        }
+        trailers.extend(trailer_split(line, &[':']));
+    }
+    trailers
+}
+
+/// Splits a trailer at the first separator, if what comes before is a
+/// valid key: letters, digits and dashes, maybe followed by spaces.
+fn trailer_split(line: &str, separators: &[char]) -> Option<Trailer> {
+    let (key, value) = line.split_once(separators)?;
+    let key = key.trim_end();
+    let valid = !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    valid.then(|| Trailer {
+        key: key.to_string(),
+        value: value.trim().to_string(),
+    })
+}
+
+/// Adds `trailers` to `message` as git does by default: at the end of its
+/// trailer block, or after its text in a new paragraph if it has none, but
+/// before any trailing comments. A trailer is left out when the one it
+/// would follow is the same.
+pub fn trailers_insert(message: &str, trailers: &[Trailer], comment: Option<&str>) -> String {
+    let lines: Vec<&str> = message.lines().collect();
+    let end = trailer_content_end(&lines, comment);
+    let block = trailer_block_start(&lines[..end], comment);
+    let mut existing = match block {
+        Some(start) => trailer_block_parse(&lines[start..end], comment),
+        None => Vec::new(),
+    };
+    let mut out: String = lines[..end].iter().map(|l| format!("{}\n", l)).collect();
+    if block.is_none() && !trailers.is_empty() {
+        out.push('\n');
+    }
+    for trailer in trailers {
+        let neighbor = existing.last();
+        if neighbor
+            .is_some_and(|n| n.key.eq_ignore_ascii_case(&trailer.key) && n.value == trailer.value)
+        {
+            continue;
+        }
+        out.push_str(&format!("{}: {}\n", trailer.key, trailer.value));
+        existing.push(trailer.clone());
+    }
+    for line in &lines[end..] {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Where the text of a message ends: before the trailing blank and
+/// comment lines.
+fn trailer_content_end(lines: &[&str], comment: Option<&str>) -> usize {
+    let ignored = |l: &str| l.trim().is_empty() || comment.is_some_and(|c| l.starts_with(c));
+    lines.iter().rposition(|l| !ignored(l)).map_or(0, |i| i + 1)
+}
+
+/// The line the trailer block of `lines` starts on, if it has one: the last
+/// paragraph, unless that is the subject, when every line in it is a
+/// trailer, or git wrote one of them and at least a quarter are.
+fn trailer_block_start(lines: &[&str], comment: Option<&str>) -> Option<usize> {
+    let subject_end = lines.iter().position(|l| l.trim().is_empty())?;
+    let start = lines
+        .iter()
+        .rposition(|l| l.trim().is_empty())
+        .map_or(0, |i| i + 1)
+        .max(subject_end + 1);
+    let (mut trailer_lines, mut other_lines, mut recognized) = (0, 0, false);
+    for line in &lines[start..] {
+        if comment.is_some_and(|c| line.starts_with(c)) || line.starts_with([' ', '\t']) {
+            continue;
+        }
+        if TRAILER_GIT_PREFIXES.iter().any(|p| line.starts_with(p)) {
+            trailer_lines += 1;
+            recognized = true;
+        } else if trailer_split(line, &[':']).is_some() {
+            trailer_lines += 1;
+        } else {
+            other_lines += 1;
+        }
+    }
+    let is_block =
+        trailer_lines > 0 && (other_lines == 0 || (recognized && trailer_lines * 3 >= other_lines));
+    is_block.then_some(start)
+}
+
+/// Where the "---" line dividing a message from its patch starts.
+fn trailer_divider_pos(text: &str) -> Option<usize> {
+    let mut pos = 0;
+    for line in text.split_inclusive('\n') {
+        let rest = line.strip_prefix(TRAILER_DIVIDER);
+        if rest.is_some_and(|r| r.chars().next().is_none_or(char::is_whitespace)) {
+            return Some(pos);
+        }
+        pos += line.len();
+    }
+    None
+}
+
+/// Adds `trailers` to the message in each file, or stdin, as
+/// `trailers_insert` does. With `parse` the trailers already there are
+/// listed instead, one per line; with `only_trailers` just the trailer
+/// lines after adding are, continuations and all. `in_place` rewrites the files rather than printing.
+pub fn interpret_trailers(
+    trailers: &[String],
+    files: &[String],
+    in_place: bool,
+    only_trailers: bool,
+    parse: bool,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), false)?;
+    let comment = comment_char(repo.as_ref());
+    let trailers = match parse {
+        true => Vec::new(),
+        false => trailers
+            .iter()
+            .map(|t| Trailer::parse(t))
+            .collect::<Result<Vec<_>>>()?,
+    };
+    let mut inputs = Vec::new();
+    if files.is_empty() {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        inputs.push((None, text));
+    }
+    for file in files {
+        inputs.push((Some(file), fs::read_to_string(file)?));
+    }
+    for (file, text) in inputs {
+        // Trailers go above the patch of a format-patch message.
+        let (message, patch) = text.split_at(trailer_divider_pos(&text).unwrap_or(text.len()));
+        let message = trailers_insert(message, &trailers, Some(&comment));
+        let lines: Vec<&str> = message.lines().collect();
+        let out = if parse {
+            trailers_parse(&message, Some(&comment))
+                .iter()
+                .map(|t| format!("{}: {}\n", t.key, t.value))
+                .collect()
+        } else if only_trailers {
+            trailer_block(&lines, Some(&comment))
+                .iter()
+                .filter(|l| !l.starts_with(comment.as_str()))
+                .map(|l| format!("{}\n", l))
+                .collect()
+        } else {
+            format!("{}{}", message, patch)
+        };
+        match (file, in_place) {
+            (Some(file), true) => fs::write(file, out)?,
+            _ => io::stdout().write_all(out.as_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{trailers_insert, trailers_parse, Trailer};
+
+    #[test]
+    fn test_trailers() {
+        let sob = Trailer::parse("Signed-off-by=A <a@b>").unwrap();
+        let add = |message: &str| trailers_insert(message, std::slice::from_ref(&sob), Some("#"));
+        assert_eq!(add(""), "\nSigned-off-by: A <a@b>\n");
+        assert_eq!(add("subject\n"), "subject\n\nSigned-off-by: A <a@b>\n");
+        // The subject is never a trailer block.
+        assert_eq!(add("Fix: x\n"), "Fix: x\n\nSigned-off-by: A <a@b>\n");
+        assert_eq!(
+            add("s\n\nFoo: y\n  more\n\n# comment\n"),
+            "s\n\nFoo: y\n  more\nSigned-off-by: A <a@b>\n\n# comment\n"
+        );
+        assert_eq!(
+            add("s\n\nSigned-off-by: A <a@b>\n"),
+            "s\n\nSigned-off-by: A <a@b>\n"
+        );
+        assert_eq!(
+            add("s\n\nbody\nmore body\n"),
+            "s\n\nbody\nmore body\n\nSigned-off-by: A <a@b>\n"
+        );
+
+        let trailers = trailers_parse("s\n\nFoo: y\n  more\nnot one\nSigned-off-by: b\n", None);
+        assert_eq!(
+            trailers,
+            [
+                Trailer {
+                    key: "Foo".to_string(),
+                    value: "y more".to_string()
+                },
+                Trailer {
+                    key: "Signed-off-by".to_string(),
+                    value: "b".to_string()
+                },
+            ]
+        );
+        assert!(trailers_parse("s\n\nFoo: y\nnot one\n", None).is_empty());
+    }
+}
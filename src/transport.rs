@@ -0,0 +1,573 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    base64_encode, pack_unpack, pkt_flush, pkt_write, prompt_credential, receive_pack,
+    receive_pack_advertise, repo_open, upload_pack, upload_pack_advertise, Bundle, GitUrl, Pkt,
+    PktReader, Repository, SshRemote, UrlScheme,
+};
+
+/// How to reach a remote repository. Every transport speaks the stateless
+/// protocol of smart HTTP: one advertisement, then one request per round.
+pub enum Transport {
+    /// A repository on this machine, served in-process.
+    Local(Repository),
+    /// A smart HTTP server.
+    Http(HttpRemote),
+    /// A repository on another machine, reached by running git over ssh.
+    Ssh(SshRemote),
+    /// A bundle file, which can only be fetched from, and only whole.
+    Bundle(Bundle),
+}
+
+pub struct HttpRemote {
+    /// "host:port" to connect to.
+    addr: String,
+    host: String,
+    /// The repository's path on the server, without a trailing slash.
+    path: String,
+    /// The `Authorization` header value for credentials in the URL.
+    auth: Option<String>,
+    user: Option<String>,
+    /// Whether the URL had a password or the user was asked for one, so
+    /// that a rejection is final.
+    auth_final: bool,
+    url: String,
+}
+
+/// How many times an HTTP request is repeated after a failure worth
+/// retrying, and the longest pause before one, in seconds.
+const HTTP_RETRIES: u32 = 5;
+const HTTP_RETRY_WAIT_MAX: u64 = 60;
+
+/// A failed HTTP request that may succeed if made again: the connection
+/// broke, or the server is rate limiting or briefly unavailable and said
+/// how many seconds to wait `after`, maybe.
+#[derive(Debug)]
+struct HttpRetry {
+    after: Option<u64>,
+    message: String,
+}
+
+impl fmt::Display for HttpRetry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for HttpRetry {}
+
+/// The refs a remote advertised, in the order it sent them.
+pub struct RemoteRefs {
+    pub refs: Vec<(String, String)>,
+    pub caps: Vec<String>,
+}
+
+impl RemoteRefs {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.refs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, sha)| sha.as_str())
+    }
+
+    pub fn has_cap(&self, cap: &str) -> bool {
+        self.caps.iter().any(|c| c == cap)
+    }
+
+    /// The branch the remote's HEAD points at. Servers that don't announce
+    /// the `symref` capability leave us to guess from HEAD's sha, preferring
+    /// master when several branches match.
+    pub fn head_branch(&self) -> Option<String> {
+        if let Some(target) = self
+            .caps
+            .iter()
+            .find_map(|c| c.strip_prefix("symref=HEAD:"))
+        {
+            return Some(target.to_string());
+        }
+        let head = self.get("HEAD")?;
+        let matching: Vec<&str> = self
+            .refs
+            .iter()
+            .filter(|(n, sha)| n.starts_with("refs/heads/") && sha == head)
+            .map(|(n, _)| n.as_str())
+            .collect();
+        matching
+            .iter()
+            .find(|n| **n == "refs/heads/master")
+            .or(matching.first())
+            .map(|n| n.to_string())
+    }
+}
+
+impl Transport {
+    /// Opens the repository at `url`: `http://` URLs go over the network,
+    /// `ssh://` and scp-like `host:path` ones over ssh, and local paths are
+    /// served in-process, or read as bundles if they are files. `repo` is
+    /// where the ssh settings are read from, if not only the global config.
+    pub fn open(url: &str, repo: Option<&Repository>) -> Result<Transport> {
+        let parsed = GitUrl::parse(url)?;
+        match parsed.scheme {
+            UrlScheme::Local if Bundle::is_bundle(Path::new(&parsed.path)) => {
+                Ok(Transport::Bundle(Bundle::read(Path::new(&parsed.path))?))
+            }
+            UrlScheme::Local => {
+                let repo = repo_open(Path::new(&parsed.path))
+                    .map_err(|_| anyhow!("repository '{}' does not exist", url))?;
+                Ok(Transport::Local(repo))
+            }
+            UrlScheme::Http => Ok(Transport::Http(HttpRemote::new(url, &parsed))),
+            UrlScheme::Https => bail!("Unable to find remote helper for 'https'"),
+            UrlScheme::Git => bail!("Unable to find remote helper for 'git'"),
+            UrlScheme::Ssh => Ok(Transport::Ssh(SshRemote::new(&parsed, repo)?)),
+        }
+    }
+
+    /// The ref advertisement of `service`, "git-upload-pack" or
+    /// "git-receive-pack".
+    pub fn advertise(&mut self, service: &str) -> Result<RemoteRefs> {
+        let adv = match self {
+            Transport::Local(repo) => match service {
+                "git-upload-pack" => upload_pack_advertise(repo)?,
+                _ => receive_pack_advertise(repo)?,
+            },
+            Transport::Http(remote) => {
+                let body =
+                    remote.request("GET", &format!("/info/refs?service={}", service), None)?;
+                // Smart HTTP prefixes the advertisement with the service.
+                let mut reader = PktReader::new(&body);
+                match reader.read_line()? {
+                    Some(line) if line == format!("# service={}", service).as_bytes() => {}
+                    _ => bail!("{} does not appear to be a smart HTTP server", remote.url),
+                }
+                if reader.read()? != Some(Pkt::Flush) {
+                    bail!("Protocol error: expected flush after service line");
+                }
+                reader.rest().to_vec()
+            }
+            Transport::Ssh(remote) => remote.advertise(service)?,
+            Transport::Bundle(bundle) => {
+                return Ok(RemoteRefs {
+                    refs: bundle.refs.clone(),
+                    caps: Vec::new(),
+                })
+            }
+        };
+        remote_refs_parse(&adv)
+    }
+
+    /// Sends one request to `service` and returns its whole response.
+    pub fn request(&mut self, service: &str, body: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Transport::Local(repo) => match service {
+                "git-upload-pack" => upload_pack(repo, body),
+                _ => receive_pack(repo, body),
+            },
+            Transport::Http(remote) => remote.request("POST", &format!("/{}", service), Some(body)),
+            Transport::Ssh(remote) => remote.request(service, body),
+            Transport::Bundle(_) => bail!("{} is not supported by bundles", service),
+        }
+    }
+}
+
+impl HttpRemote {
+    /// Sets up requests to the server `parsed` names, authenticating with
+    /// the credentials in it.
+    fn new(url: &str, parsed: &GitUrl) -> HttpRemote {
+        let auth = parsed.user.as_ref().map(|user| {
+            let creds = match &parsed.password {
+                Some(password) => format!("{}:{}", user, password),
+                None => user.clone(),
+            };
+            format!("Basic {}", base64_encode(creds.as_bytes()))
+        });
+        // IPv6 addresses keep their brackets in the Host header.
+        let host = match parsed.host.contains(':') {
+            true => format!("[{}]", parsed.host),
+            false => parsed.host.clone(),
+        };
+        HttpRemote {
+            addr: format!("{}:{}", host, parsed.port.unwrap_or(80)),
+            host: match parsed.port {
+                Some(port) => format!("{}:{}", host, port),
+                None => host,
+            },
+            path: parsed.path.trim_end_matches('/').to_string(),
+            auth,
+            user: parsed.user.clone(),
+            auth_final: parsed.password.is_some(),
+            url: url.to_string(),
+        }
+    }
+
+    /// Asks for the user name the URL lacks and a password, and sends them
+    /// from now on.
+    fn credentials_prompt(&mut self) -> Result<()> {
+        self.auth_final = true;
+        let user = match &self.user {
+            Some(user) => user.clone(),
+            None => prompt_credential(&format!("Username for 'http://{}': ", self.host), false)?,
+        };
+        let password = prompt_credential(
+            &format!("Password for 'http://{}@{}': ", user, self.host),
+            true,
+        )?;
+        let creds = format!("{}:{}", user, password);
+        self.auth = Some(format!("Basic {}", base64_encode(creds.as_bytes())));
+        Ok(())
+    }
+
+    /// Makes an HTTP/1.1 request and returns the body of a 200 response.
+    /// A connection that breaks or a server that is rate limiting or briefly
+    /// unavailable gets the request again, after a pause that doubles each
+    /// time unless the server says how long with `Retry-After`. Pushes are
+    /// not repeated, as the first may have been applied.
+    fn request(&mut self, method: &str, target: &str, body: Option<&[u8]>) -> Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            let e = match self.request_once(method, target, body) {
+                Ok(body) => return Ok(body),
+                Err(e) => e,
+            };
+            let Some(retry) = e.downcast_ref::<HttpRetry>() else {
+                return Err(e);
+            };
+            if attempt == HTTP_RETRIES || target == "/git-receive-pack" {
+                return Err(e);
+            }
+            let wait = retry.after.unwrap_or(1 << attempt).min(HTTP_RETRY_WAIT_MAX);
+            eprintln!("warning: {}; retrying in {} s", retry.message, wait);
+            thread::sleep(Duration::from_secs(wait));
+            attempt += 1;
+        }
+    }
+
+    /// Makes one request on a fresh connection. When the server wants
+    /// credentials the URL didn't have, the user is asked for them and the
+    /// request is made again.
+    fn request_once(&mut self, method: &str, target: &str, body: Option<&[u8]>) -> Result<Vec<u8>> {
+        let url = self.url.clone();
+        let fail = |e: io::Error| {
+            anyhow::Error::new(HttpRetry {
+                after: None,
+                message: format!("unable to access '{}': {}", url, e),
+            })
+        };
+        let eof = || io::Error::from(io::ErrorKind::UnexpectedEof);
+        // A server that can't be reached at all is not waited for.
+        let mut stream = TcpStream::connect(&self.addr)
+            .map_err(|e| anyhow!("unable to access '{}': {}", self.url, e))?;
+        let mut head = format!(
+            "{} {}{} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rit/{}\r\nConnection: close\r\n",
+            method,
+            self.path,
+            target,
+            self.host,
+            env!("CARGO_PKG_VERSION")
+        );
+        if let Some(auth) = &self.auth {
+            head.push_str(&format!("Authorization: {}\r\n", auth));
+        }
+        if let Some(body) = body {
+            let service = target.trim_start_matches('/');
+            head.push_str(&format!(
+                "Content-Type: application/x-{}-request\r\nAccept: application/x-{}-result\r\n\
+                 Content-Length: {}\r\n",
+                service,
+                service,
+                body.len()
+            ));
+        }
+        head.push_str("\r\n");
+        stream.write_all(head.as_bytes()).map_err(fail)?;
+        if let Some(body) = body {
+            stream.write_all(body).map_err(fail)?;
+        }
+        stream.flush().map_err(fail)?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        if reader.read_line(&mut line).map_err(fail)? == 0 {
+            return Err(fail(eof()));
+        }
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+        let mut chunked = false;
+        let mut length = None;
+        let mut retry_after = None;
+        loop {
+            line.clear();
+            reader.read_line(&mut line).map_err(fail)?;
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((k, v)) = header.split_once(':') {
+                let v = v.trim();
+                if k.eq_ignore_ascii_case("Transfer-Encoding") {
+                    chunked = v.eq_ignore_ascii_case("chunked");
+                } else if k.eq_ignore_ascii_case("Content-Length") {
+                    length = Some(v.parse::<usize>()?);
+                } else if k.eq_ignore_ascii_case("Retry-After") {
+                    // Only the delay-seconds form; a date gets the default.
+                    retry_after = v.parse::<u64>().ok();
+                }
+            }
+        }
+        match status.as_str() {
+            "200" => {}
+            "401" if !self.auth_final => {
+                self.credentials_prompt()?;
+                return self.request(method, target, body);
+            }
+            "401" => bail!("Authentication failed for '{}'", self.url),
+            "404" => bail!("repository '{}' not found", self.url),
+            "429" | "502" | "503" | "504" => {
+                return Err(anyhow::Error::new(HttpRetry {
+                    after: retry_after,
+                    message: format!(
+                        "unable to access '{}': The requested URL returned error: {}",
+                        self.url, status
+                    ),
+                }))
+            }
+            _ => bail!(
+                "unable to access '{}': The requested URL returned error: {}",
+                self.url,
+                status
+            ),
+        }
+
+        let mut body = Vec::new();
+        if chunked {
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).map_err(fail)? == 0 {
+                    return Err(fail(eof()));
+                }
+                let size_hex = line.trim_end().split(';').next().unwrap_or_default();
+                let size = usize::from_str_radix(size_hex, 16)
+                    .map_err(|_| anyhow!("Bad chunk size {:?}", size_hex))?;
+                let mut chunk = vec![0; size + 2];
+                reader.read_exact(&mut chunk).map_err(fail)?;
+                if size == 0 {
+                    break;
+                }
+                body.extend_from_slice(&chunk[..size]);
+            }
+        } else if let Some(len) = length {
+            body.resize(len, 0);
+            reader.read_exact(&mut body).map_err(fail)?;
+        } else {
+            reader.read_to_end(&mut body).map_err(fail)?;
+        }
+        Ok(body)
+    }
+}
+
+/// Parses a ref advertisement: "<sha> <name>" lines, the first carrying
+/// the capabilities after a NUL. An empty repository advertises only its
+/// capabilities, on a `capabilities^{}` line.
+pub fn remote_refs_parse(adv: &[u8]) -> Result<RemoteRefs> {
+    let mut reader = PktReader::new(adv);
+    let mut remote = RemoteRefs {
+        refs: Vec::new(),
+        caps: Vec::new(),
+    };
+    while let Some(line) = reader.read_line()? {
+        let line = std::str::from_utf8(line)?;
+        if let Some(msg) = line.strip_prefix("ERR ") {
+            bail!("remote error: {}", msg);
+        }
+        let (line, caps) = line.split_once('\0').unwrap_or((line, ""));
+        if !caps.is_empty() {
+            remote.caps = caps.split(' ').map(str::to_string).collect();
+        }
+        let (sha, name) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Protocol error: bad ref line '{}'", line))?;
+        if name != "capabilities^{}" {
+            remote.refs.push((name.to_string(), sha.to_string()));
+        }
+    }
+    Ok(remote)
+}
+
+/// Fetches everything reachable from `wants` that isn't reachable from
+/// `haves` into `repo`'s object store.
+pub fn fetch_pack(
+    repo: &Repository,
+    transport: &mut Transport,
+    wants: &[String],
+    haves: &[String],
+) -> Result<()> {
+    if wants.is_empty() {
+        return Ok(());
+    }
+    if let Transport::Bundle(bundle) = transport {
+        return bundle.unpack(repo);
+    }
+    let pack = fetch_pack_receive(transport, wants, haves)?;
+    pack_unpack(repo, &pack)?;
+    Ok(())
+}
+
+/// Asks the remote for everything reachable from `wants` that isn't
+/// reachable from `haves`, and returns the pack it sends without unpacking
+/// it. Bundles hold their pack already and are not asked.
+pub fn fetch_pack_receive(
+    transport: &mut Transport,
+    wants: &[String],
+    haves: &[String],
+) -> Result<Vec<u8>> {
+    Ok(fetch_pack_receive_deepen(transport, wants, haves, None)?.0)
+}
+
+/// How much history a fetch asks for: `depth` commits from the wants, on
+/// top of a client whose history stops at the `shallow` commits.
+pub struct FetchDepth<'a> {
+    pub depth: usize,
+    pub shallow: &'a [String],
+}
+
+/// What a fetch with a depth changed: commits now missing their parents,
+/// and formerly shallow commits whose parents were sent.
+#[derive(Debug, Default)]
+pub struct ShallowUpdate {
+    pub shallow: Vec<String>,
+    pub unshallow: Vec<String>,
+}
+
+/// Like `fetch_pack_receive`, limiting the history sent to `depth` when
+/// given, and returning how that moved the client's shallow commits.
+pub fn fetch_pack_receive_deepen(
+    transport: &mut Transport,
+    wants: &[String],
+    haves: &[String],
+    depth: Option<&FetchDepth>,
+) -> Result<(Vec<u8>, ShallowUpdate)> {
+    let mut request = Vec::new();
+    for (i, sha) in wants.iter().enumerate() {
+        let line = if i == 0 {
+            format!(
+                "want {} side-band-64k{} agent=rit/{}\n",
+                sha,
+                if depth.is_some() { " shallow" } else { "" },
+                env!("CARGO_PKG_VERSION")
+            )
+        } else {
+            format!("want {}\n", sha)
+        };
+        pkt_write(&mut request, line.as_bytes());
+    }
+    if let Some(depth) = depth {
+        for sha in depth.shallow {
+            pkt_write(&mut request, format!("shallow {}\n", sha).as_bytes());
+        }
+        pkt_write(&mut request, format!("deepen {}\n", depth.depth).as_bytes());
+    }
+    pkt_flush(&mut request);
+    for sha in haves {
+        pkt_write(&mut request, format!("have {}\n", sha).as_bytes());
+    }
+    pkt_write(&mut request, b"done\n");
+    let response = transport.request("git-upload-pack", &request)?;
+
+    let mut rest = &response[..];
+    let mut update = ShallowUpdate::default();
+    if depth.is_some() {
+        let mut reader = PktReader::new(rest);
+        while let Some(line) = reader.read_line()? {
+            let line = std::str::from_utf8(line)?;
+            match line.split_once(' ') {
+                Some(("shallow", sha)) => update.shallow.push(sha.to_string()),
+                Some(("unshallow", sha)) => update.unshallow.push(sha.to_string()),
+                _ => bail!("Protocol error: expected shallow list, got '{}'", line),
+            }
+        }
+        rest = reader.rest();
+    }
+
+    // Skip the negotiation's ACK/NAK lines; the pack follows in band 1.
+    loop {
+        let mut reader = PktReader::new(rest);
+        match reader.read_line()? {
+            Some(line) if line.starts_with(b"ACK ") || line == b"NAK" => rest = reader.rest(),
+            _ => break,
+        }
+    }
+    let mut pack = Vec::new();
+    // Progress arrives in arbitrary pieces; each line gets its prefix.
+    let mut progress_line = Vec::new();
+    let mut reader = PktReader::new(rest);
+    while let Some(Pkt::Data(data)) = reader.read()? {
+        match data.split_first() {
+            Some((1, chunk)) => pack.extend_from_slice(chunk),
+            Some((2, progress)) => {
+                for &b in progress {
+                    progress_line.push(b);
+                    if b == b'\r' || b == b'\n' {
+                        eprint!("remote: {}", String::from_utf8_lossy(&progress_line));
+                        progress_line.clear();
+                    }
+                }
+            }
+            Some((3, error)) => bail!(
+                "remote error: {}",
+                String::from_utf8_lossy(error).trim_end()
+            ),
+            _ => bail!("Protocol error: bad side-band packet"),
+        }
+    }
+    Ok((pack, update))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::Transport;
+
+    #[test]
+    fn test_http_retry() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let replies = [
+                "HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n",
+                // Cut off before the whole body is sent.
+                "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhel",
+                "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello",
+            ];
+            for reply in replies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                stream.write_all(reply.as_bytes()).unwrap();
+            }
+        });
+
+        let url = format!("http://127.0.0.1:{}/repo", port);
+        let Transport::Http(mut remote) = Transport::open(&url, None).unwrap() else {
+            panic!("not an http transport");
+        };
+        assert_eq!(remote.request("GET", "/info/refs", None).unwrap(), b"hello");
+        server.join().unwrap();
+    }
+}
@@ -1,7 +1,9 @@
+use std::collections::BTreeMap;
+
 use anyhow::{anyhow, bail, Result};
 use num_bigint::BigUint;
 
-use crate::Object;
+use crate::{object_read, object_write, Object, Repository};
 
 #[derive(Clone)]
 pub struct TreeLeaf {
@@ -16,7 +18,9 @@ fn tree_parse_one(raw: &[u8], start: usize) -> Result<(usize, TreeLeaf)> {
         .position(|&b| b == b' ')
         .ok_or_else(|| anyhow!("Malformed tree: cannot find space"))?
         + start;
-    assert!(spc - start == 5 || spc - start == 6);
+    if spc - start != 5 && spc - start != 6 {
+        bail!("Malformed tree: bad mode length");
+    }
 
     let mode = &raw[start..spc];
     let mode = if mode.len() == 5 {
@@ -45,7 +49,8 @@ fn tree_parse_one(raw: &[u8], start: usize) -> Result<(usize, TreeLeaf)> {
     Ok((sha_end, TreeLeaf { mode, path, sha }))
 }
 
-fn tree_parse(raw: &[u8]) -> Result<Vec<TreeLeaf>> {
+/// Parses the entries of a serialized tree, in the order they are stored.
+pub fn tree_parse(raw: &[u8]) -> Result<Vec<TreeLeaf>> {
     let mut pos = 0;
     let max = raw.len();
     let mut res = Vec::new();
@@ -60,9 +65,10 @@ fn tree_parse(raw: &[u8]) -> Result<Vec<TreeLeaf>> {
 
 pub fn tree_serialize(tree: &Tree) -> Vec<u8> {
     let mut items = tree.items.clone();
+    // Git sorts subtrees as if their names ended with a slash.
     items.sort_by_key(|leaf| {
         let mut key = leaf.path.clone();
-        if leaf.mode.starts_with(b"10") {
+        if leaf.mode.starts_with(b"04") {
             key.push('/');
         }
         key
@@ -70,7 +76,10 @@ pub fn tree_serialize(tree: &Tree) -> Vec<u8> {
 
     let mut ret = Vec::new();
     for leaf in items.iter() {
-        ret.extend_from_slice(&leaf.mode);
+        // Modes are normalized to six bytes when parsing; git writes
+        // "40000" for subtrees.
+        let mode = leaf.mode.strip_prefix(b"0").unwrap_or(&leaf.mode);
+        ret.extend_from_slice(mode);
         ret.push(b' ');
         ret.extend_from_slice(leaf.path.as_bytes());
         ret.push(0);
@@ -104,3 +113,50 @@ impl Object for Tree {
         self
     }
 }
+
+/// Calls `visit` with the full path of every entry below `tree`, in tree
+/// order, each directory before what it contains.
+pub fn tree_walk(
+    repo: &Repository,
+    tree: &Tree,
+    prefix: &str,
+    visit: &mut dyn FnMut(&str, &TreeLeaf) -> Result<()>,
+) -> Result<()> {
+    for item in &tree.items {
+        let path = format!("{}{}", prefix, item.path);
+        visit(&path, item)?;
+        if item.mode.starts_with(b"04") {
+            let obj = object_read(repo, &item.sha)?;
+            let sub = obj
+                .as_any()
+                .downcast_ref::<Tree>()
+                .ok_or_else(|| anyhow!("Object {} is not a tree", item.sha))?;
+            tree_walk(repo, sub, &format!("{}/", path), visit)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the trees needed to hold `leaves`, given with full paths, and
+/// returns the sha of the root tree.
+pub fn tree_from_leaves(repo: &Repository, leaves: &[TreeLeaf]) -> Result<String> {
+    let mut items = Vec::new();
+    let mut subdirs: BTreeMap<&str, Vec<TreeLeaf>> = BTreeMap::new();
+    for leaf in leaves {
+        match leaf.path.split_once('/') {
+            Some((dir, rest)) => subdirs.entry(dir).or_default().push(TreeLeaf {
+                path: rest.to_string(),
+                ..leaf.clone()
+            }),
+            None => items.push(leaf.clone()),
+        }
+    }
+    for (dir, sub) in subdirs {
+        items.push(TreeLeaf {
+            mode: b"040000".to_vec(),
+            path: dir.to_string(),
+            sha: tree_from_leaves(repo, &sub)?,
+        });
+    }
+    object_write(&Tree { items }, Some(repo))
+}
@@ -0,0 +1,170 @@
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_get_active, commit_subject, prompt_yes_no, ref_resolve, reflog_read, repo_find,
+    reset_to, sha_short, switch_to, ReflogEntry, Repository, ResetMode, ZERO_SHA,
+};
+
+/// Takes back the last move of HEAD recorded in its reflog (a commit,
+/// reset, merge, rebase...) by resetting to where HEAD was before it: the
+/// branch and the index, and the worktree too with `hard`. A checkout is
+/// taken back by switching to what was checked out before it instead, as
+/// it moved no branch. Unless `yes`, asks first.
+pub fn undo(yes: bool, hard: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let last = undo_target(&repo)?;
+    println!("Last action: {}", last.message);
+    match undo_checkout_from(&repo, &last)? {
+        Some((from, true)) => println!("Undoing it switches back to branch '{}'", from),
+        Some(_) => println!(
+            "Undoing it switches back to {} {}",
+            sha_short(&repo, &last.old),
+            commit_subject(&repo, &last.old)?
+        ),
+        None => {
+            let what = match branch_get_active(&repo)? {
+                Some(branch) => format!("branch '{}'", branch),
+                None => "HEAD".to_string(),
+            };
+            println!(
+                "Undoing it moves {} from {} back to {} {}",
+                what,
+                sha_short(&repo, &last.new),
+                sha_short(&repo, &last.old),
+                commit_subject(&repo, &last.old)?
+            );
+        }
+    }
+    if !yes && !prompt_yes_no("Proceed? [y/N] ", false)? {
+        println!("Nothing changed.");
+        return Ok(());
+    }
+    undo_entry(&repo, &last, hard)
+}
+
+/// Takes back the reflog entry `last`, as `undo` describes.
+pub fn undo_entry(repo: &Repository, last: &ReflogEntry, hard: bool) -> Result<()> {
+    match undo_checkout_from(repo, last)? {
+        Some((from, true)) => return switch_to(repo, &from, true),
+        Some(_) => return switch_to(repo, &last.old, false),
+        None => {}
+    }
+    let mode = if hard {
+        ResetMode::Hard
+    } else {
+        ResetMode::Mixed
+    };
+    reset_to(repo, &last.old, mode, &format!("undo: {}", last.message))?;
+    println!(
+        "HEAD is now at {} {}",
+        sha_short(repo, &last.old),
+        commit_subject(repo, &last.old)?
+    );
+    Ok(())
+}
+
+/// For a "checkout: moving from <from> to <to>" entry, what was checked out
+/// before it, and whether that is a branch that still exists.
+fn undo_checkout_from(repo: &Repository, entry: &ReflogEntry) -> Result<Option<(String, bool)>> {
+    let Some((from, _)) = entry
+        .message
+        .strip_prefix("checkout: moving from ")
+        .and_then(|rest| rest.split_once(" to "))
+    else {
+        return Ok(None);
+    };
+    let is_branch = ref_resolve(repo, &format!("refs/heads/{}", from))?.is_some();
+    Ok(Some((from.to_string(), is_branch)))
+}
+
+/// The reflog entry `undo` takes back: HEAD's latest, provided HEAD is still
+/// where it left it and there was a commit before it.
+pub fn undo_target(repo: &Repository) -> Result<ReflogEntry> {
+    let last = reflog_read(repo, "HEAD")?
+        .pop()
+        .ok_or_else(|| anyhow!("Nothing to undo: HEAD has no reflog."))?;
+    if ref_resolve(repo, "HEAD")?.as_deref() != Some(last.new.as_str()) {
+        bail!(
+            "HEAD has moved since its last reflog entry ({}); not undoing it.",
+            last.message
+        );
+    }
+    if last.old == ZERO_SHA {
+        bail!("Nothing to undo before '{}'.", last.message);
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        branch_get_active, checkout_tree, head_update, ref_create, ref_resolve, switch_to,
+        test_utils::{make_commit, test_repo},
+        undo_entry, undo_target,
+    };
+
+    #[test]
+    fn test_undo_target() {
+        let repo = test_repo("undo");
+        let first = make_commit(&repo, "first", &[]);
+        let second = make_commit(&repo, "second", &[&first]);
+        assert!(undo_target(&repo).is_err());
+
+        head_update(&repo, &first, "commit (initial): first").unwrap();
+        assert!(undo_target(&repo).is_err());
+        head_update(&repo, &second, "commit: second").unwrap();
+        let last = undo_target(&repo).unwrap();
+        assert_eq!(
+            (last.old.as_str(), last.new.as_str()),
+            (first.as_str(), second.as_str())
+        );
+        assert_eq!(last.message, "commit: second");
+
+        // Moves that went unlogged make the reflog unreliable.
+        ref_create(&repo, "heads/master", &first).unwrap();
+        assert!(undo_target(&repo).is_err());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+
+    #[test]
+    fn test_undo_checkout() {
+        let repo = test_repo("undo-checkout");
+        let first = make_commit(&repo, "first", &[]);
+        let second = make_commit(&repo, "second", &[&first]);
+        checkout_tree(&repo, &second, true).unwrap();
+        ref_create(&repo, "heads/master", &second).unwrap();
+        ref_create(&repo, "heads/side", &first).unwrap();
+
+        // Undoing a checkout goes back to the branch, moving no branch.
+        switch_to(&repo, "side", true).unwrap();
+        let last = undo_target(&repo).unwrap();
+        undo_entry(&repo, &last, false).unwrap();
+        assert_eq!(branch_get_active(&repo).unwrap().as_deref(), Some("master"));
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(second.clone()));
+        assert_eq!(
+            ref_resolve(&repo, "refs/heads/side").unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(
+            fs::read_to_string(repo.worktree.join("file")).unwrap(),
+            "second"
+        );
+
+        // From a detached HEAD, it goes back to that commit.
+        switch_to(&repo, &first, false).unwrap();
+        switch_to(&repo, "master", true).unwrap();
+        let last = undo_target(&repo).unwrap();
+        undo_entry(&repo, &last, false).unwrap();
+        assert_eq!(branch_get_active(&repo).unwrap(), None);
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(first));
+        assert_eq!(
+            ref_resolve(&repo, "refs/heads/master").unwrap(),
+            Some(second)
+        );
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    index_entry_modified, index_entry_stat_clean, index_read, index_write, object_find,
+    object_hash, repo_find, repo_relative_path, tree_mode_split, worktree_file_mode, Index,
+    IndexEntry, Repository,
+};
+
+/// What `update_index` changes.
+#[derive(Default)]
+pub struct UpdateIndexOptions {
+    /// Write the index split from now on, or whole again.
+    pub split_index: Option<bool>,
+    /// Let paths not in the index yet be added.
+    pub add: bool,
+    /// Let paths gone from the worktree be removed from the index.
+    pub remove: bool,
+    /// Bring the stat data of unchanged entries up to date.
+    pub refresh: bool,
+    /// Entries given as `<mode>,<sha>,<path>`, for objects that need not
+    /// be in the worktree at all.
+    pub cacheinfo: Vec<String>,
+    /// Set, or with false clear, the assume-unchanged flag of the paths
+    /// given instead of updating them.
+    pub assume_unchanged: Option<bool>,
+}
+
+/// Changes the index directly: updates the entries of `paths` from the
+/// worktree, as `opts` allows, and sets those given by `--cacheinfo`.
+/// Splitting the index, when it is already split, moves every entry into a
+/// new shared index.
+pub fn update_index(paths: &[PathBuf], opts: &UpdateIndexOptions) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let mut index = index_read(&repo)?;
+    if let Some(split) = opts.split_index {
+        if repo.config_get_bool("core", "splitindex")? == Some(!split) {
+            eprintln!(
+                "warning: core.splitIndex is set to {}; remove or change it, if you really want to {} split index",
+                !split,
+                if split { "enable" } else { "disable" }
+            );
+        }
+        index.split = split;
+        index.shared = None;
+    }
+
+    for info in &opts.cacheinfo {
+        let [mode, sha, path] = info.splitn(3, ',').collect::<Vec<_>>()[..] else {
+            bail!("option 'cacheinfo' expects <mode>,<sha1>,<path>");
+        };
+        let (mode_type, mode_perms) = tree_mode_split(mode.as_bytes())?;
+        let sha = object_find(&repo, sha, None, false)?
+            .ok_or_else(|| anyhow!("{}: not a valid object", sha))?;
+        let entry = IndexEntry {
+            mode_type,
+            mode_perms,
+            sha,
+            name: path.to_string(),
+            ..Default::default()
+        };
+        index_entry_set(&mut index, entry, opts.add)?;
+    }
+
+    for path in paths {
+        let name = repo_relative_path(&repo, path)?;
+        if let Some(assume) = opts.assume_unchanged {
+            let entry = index
+                .entries
+                .iter_mut()
+                .find(|e| e.name == name)
+                .ok_or_else(|| anyhow!("Unable to mark file {}", name))?;
+            entry.flag_assume_valid = assume;
+            continue;
+        }
+        let full_path = repo.worktree.join(&name);
+        let Ok(meta) = fs::symlink_metadata(&full_path) else {
+            if !opts.remove {
+                bail!("{}: does not exist and --remove not passed", name);
+            }
+            index.entries.retain(|e| e.name != name);
+            continue;
+        };
+        if meta.is_dir() {
+            bail!("{}: is a directory - add files inside instead", name);
+        }
+        let mode = worktree_file_mode(&meta);
+        let sha = if meta.file_type().is_symlink() {
+            let target = fs::read_link(&full_path)?;
+            object_hash(target.to_string_lossy().as_bytes(), b"blob", Some(&repo))?
+        } else {
+            object_hash(File::open(&full_path)?, b"blob", Some(&repo))?
+        };
+        let entry = IndexEntry::from_file(&repo, &name, &sha, &mode)?;
+        index_entry_set(&mut index, entry, opts.add)?;
+    }
+
+    let stale = match opts.refresh {
+        true => index_refresh(&repo, &mut index)?,
+        false => Vec::new(),
+    };
+    index_write(&repo, &index)?;
+    if !stale.is_empty() {
+        for name in &stale {
+            println!("{}: needs update", name);
+        }
+        bail!("the index is not up to date with the worktree");
+    }
+    Ok(())
+}
+
+/// Puts `entry` in the index in place of any entries of its path, which
+/// must be there already unless `add`.
+fn index_entry_set(index: &mut Index, entry: IndexEntry, add: bool) -> Result<()> {
+    let existing = index.entries.iter().position(|e| e.name == entry.name);
+    if existing.is_none() && !add {
+        bail!(
+            "{}: cannot add to the index - missing --add option?",
+            entry.name
+        );
+    }
+    index.entries.retain(|e| e.name != entry.name);
+    let at = index
+        .entries
+        .partition_point(|e| e.name.as_str() < entry.name.as_str());
+    index.entries.insert(at, entry);
+    Ok(())
+}
+
+/// Takes the current stat data of every entry whose file still has the
+/// content recorded, so that later commands needn't read it again. Returns
+/// the names of the entries whose files did change.
+fn index_refresh(repo: &Repository, index: &mut Index) -> Result<Vec<String>> {
+    let mut stale = Vec::new();
+    for entry in index.entries.iter_mut() {
+        if entry.flag_stage != 0 || entry.flag_assume_valid || entry.flag_skip_worktree {
+            continue;
+        }
+        let meta = fs::symlink_metadata(repo.worktree.join(&entry.name)).ok();
+        if meta.is_some_and(|m| index_entry_stat_clean(repo, entry, &m)) {
+            continue;
+        }
+        if index_entry_modified(repo, entry)? {
+            stale.push(entry.name.clone());
+            continue;
+        }
+        *entry = IndexEntry::from_file(repo, &entry.name, &entry.sha, &entry.tree_mode())?;
+    }
+    Ok(stale)
+}
@@ -0,0 +1,348 @@
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    object_find, ref_delete, ref_resolve, reflog_append, refname_valid, repo_file, repo_find,
+    symref_read, Repository, ZERO_SHA,
+};
+
+/// One change in a `RefTransaction`.
+pub struct RefChange {
+    pub refname: String,
+    /// What the ref becomes, `ZERO_SHA` to delete it. None leaves it as it
+    /// is, only checking `old`.
+    pub new: Option<String>,
+    /// What the ref must be before, if anything: `ZERO_SHA` requires that
+    /// it doesn't exist yet.
+    pub old: Option<String>,
+    /// Change a symbolic ref itself rather than the ref it points to.
+    pub no_deref: bool,
+}
+
+/// Ref changes made all together or not at all. Every ref is locked
+/// before any is checked against its old value, and none is written until
+/// all checks pass, so scripts can compare-and-swap several refs safely.
+#[derive(Default)]
+pub struct RefTransaction {
+    pub updates: Vec<RefChange>,
+    /// What the reflogs of the changed refs say about it.
+    pub message: String,
+}
+
+/// The `<ref>.lock` held while a ref is changed, removed again unless the
+/// change went through.
+struct RefLock {
+    path: PathBuf,
+    lock: PathBuf,
+    done: bool,
+}
+
+impl RefLock {
+    fn acquire(path: PathBuf) -> Result<Self> {
+        let mut lock = path.as_os_str().to_owned();
+        lock.push(".lock");
+        let lock = PathBuf::from(lock);
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock)
+        {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                bail!("Unable to create '{}': File exists.", lock.display())
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Self {
+            path,
+            lock,
+            done: false,
+        })
+    }
+}
+
+impl Drop for RefLock {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = fs::remove_file(&self.lock);
+        }
+    }
+}
+
+impl RefTransaction {
+    pub fn commit(&self, repo: &Repository) -> Result<()> {
+        // Find what each update really changes, with symbolic refs followed.
+        let mut targets: Vec<(String, &RefChange)> = Vec::new();
+        for update in &self.updates {
+            if !refname_valid(&update.refname, true)
+                || !(update.refname.starts_with("refs/")
+                    || update
+                        .refname
+                        .chars()
+                        .all(|c| c.is_ascii_uppercase() || c == '_'))
+            {
+                bail!("invalid ref name '{}'", update.refname);
+            }
+            let mut target = update.refname.clone();
+            // As deep as git follows symbolic refs, which also stops loops.
+            for _ in 0..5 {
+                match symref_read(repo, &target)? {
+                    Some(next) if !update.no_deref => target = next,
+                    _ => break,
+                }
+            }
+            if targets.iter().any(|(t, _)| *t == target) {
+                bail!("multiple updates for ref '{}' not allowed", target);
+            }
+            targets.push((target, update));
+        }
+
+        let mut locks = Vec::new();
+        for (target, _) in &targets {
+            locks.push(RefLock::acquire(repo_file(
+                repo,
+                PathBuf::from(target),
+                true,
+            )?)?);
+        }
+        let mut current = Vec::new();
+        for (target, update) in &targets {
+            let value = match update.no_deref && symref_read(repo, target)?.is_some() {
+                true => None,
+                false => ref_resolve(repo, target)?,
+            };
+            if let Some(old) = &update.old {
+                let expected = (old != ZERO_SHA).then_some(old);
+                if value.as_ref() != expected {
+                    bail!(
+                        "cannot lock ref '{}': is at {} but expected {}",
+                        update.refname,
+                        value.as_deref().unwrap_or(ZERO_SHA),
+                        old
+                    );
+                }
+            }
+            current.push(value);
+        }
+
+        for (((target, update), lock), old) in targets.iter().zip(&mut locks).zip(current) {
+            match update.new.as_deref() {
+                None => {}
+                Some(ZERO_SHA) => {
+                    ref_delete(repo, target)?;
+                    let _ = fs::remove_file(repo_file(
+                        repo,
+                        PathBuf::from("logs").join(target),
+                        false,
+                    )?);
+                }
+                Some(new) => {
+                    fs::write(&lock.lock, format!("{}\n", new))?;
+                    fs::rename(&lock.lock, &lock.path)?;
+                    lock.done = true;
+                    let mut logged = vec![target.as_str()];
+                    if update.refname != *target {
+                        logged.push(&update.refname);
+                    }
+                    for name in logged {
+                        if ref_logged(repo, name)? {
+                            reflog_append(repo, name, old.as_deref(), new, &self.message)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether changes to `refname` go in a reflog: those of HEAD, branches,
+/// remote-tracking branches and notes do, as do those of any ref with a
+/// reflog already.
+fn ref_logged(repo: &Repository, refname: &str) -> Result<bool> {
+    let auto = refname == "HEAD"
+        || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+            .iter()
+            .any(|prefix| refname.starts_with(prefix));
+    Ok(auto || repo_file(repo, PathBuf::from("logs").join(refname), false)?.is_file())
+}
+
+/// Sets `refname` to `new`, or deletes it with `delete`, provided that it
+/// is at `old` first if given. With `stdin` the updates are read from
+/// stdin instead, one command per line, as `update_ref_stdin` takes them.
+pub fn update_ref(
+    args: &[String],
+    delete: bool,
+    no_deref: bool,
+    stdin: bool,
+    message: Option<&str>,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let message = message.unwrap_or_default();
+    if stdin {
+        if !args.is_empty() {
+            bail!("update-ref --stdin takes no arguments");
+        }
+        return update_ref_stdin(&repo, io::stdin().lock(), no_deref, message);
+    }
+    let (refname, new, old) = match (delete, args) {
+        (false, [refname, new]) => (refname, Some(new.as_str()), None),
+        (false, [refname, new, old]) => (refname, Some(new.as_str()), Some(old)),
+        (true, [refname]) => (refname, Some(ZERO_SHA), None),
+        (true, [refname, old]) => (refname, Some(ZERO_SHA), Some(old)),
+        _ => bail!(
+            "usage: update-ref [-m <reason>] (-d <ref> [<old>] | <ref> <new> [<old>] | --stdin)"
+        ),
+    };
+    let update = RefChange {
+        refname: refname.clone(),
+        new: new.map(|v| ref_value_resolve(&repo, v)).transpose()?,
+        old: old.map(|v| ref_value_resolve(&repo, v)).transpose()?,
+        no_deref,
+    };
+    RefTransaction {
+        updates: vec![update],
+        message: message.to_string(),
+    }
+    .commit(&repo)
+}
+
+/// Runs the ref commands of `input` as transactions:
+///
+/// ```text
+/// update <ref> <new> [<old>]
+/// create <ref> <new>
+/// delete <ref> [<old>]
+/// verify <ref> [<old>]
+/// option no-deref
+/// start
+/// commit
+/// abort
+/// ```
+///
+/// Each `commit`, and the end of the input, applies the commands since the
+/// last one; `abort` drops them. `option no-deref` applies to the next
+/// command only.
+pub fn update_ref_stdin(
+    repo: &Repository,
+    input: impl BufRead,
+    no_deref: bool,
+    message: &str,
+) -> Result<()> {
+    let mut transaction = RefTransaction {
+        updates: Vec::new(),
+        message: message.to_string(),
+    };
+    let mut next_no_deref = no_deref;
+    let mut out = io::stdout().lock();
+    for line in input.lines() {
+        let line = line?;
+        let words: Vec<&str> = line.split(' ').collect();
+        let value = |i: usize| -> Result<Option<String>> {
+            words
+                .get(i)
+                .filter(|v| !v.is_empty())
+                .map(|v| ref_value_resolve(repo, v))
+                .transpose()
+        };
+        let (refname, new, old) = match words.as_slice() {
+            ["start"] => {
+                writeln!(out, "start: ok")?;
+                continue;
+            }
+            ["abort"] => {
+                transaction.updates.clear();
+                writeln!(out, "abort: ok")?;
+                continue;
+            }
+            ["commit"] => {
+                transaction.commit(repo)?;
+                transaction.updates.clear();
+                writeln!(out, "commit: ok")?;
+                continue;
+            }
+            ["option", "no-deref"] => {
+                next_no_deref = true;
+                continue;
+            }
+            ["update", refname, new] | ["update", refname, new, _] => {
+                (refname, Some(ref_value_resolve(repo, new)?), value(3)?)
+            }
+            ["create", refname, new] => (
+                refname,
+                Some(ref_value_resolve(repo, new)?),
+                Some(ZERO_SHA.to_string()),
+            ),
+            ["delete", refname] | ["delete", refname, _] => {
+                (refname, Some(ZERO_SHA.to_string()), value(2)?)
+            }
+            ["verify", refname] | ["verify", refname, _] => {
+                let old = value(2)?.unwrap_or_else(|| ZERO_SHA.to_string());
+                (refname, None, Some(old))
+            }
+            _ => bail!("unknown command: {}", line),
+        };
+        transaction.updates.push(RefChange {
+            refname: refname.to_string(),
+            new,
+            old,
+            no_deref: next_no_deref,
+        });
+        next_no_deref = no_deref;
+    }
+    transaction.commit(repo)
+}
+
+/// The object a value on the command line names; all zeros stays as is.
+fn ref_value_resolve(repo: &Repository, value: &str) -> Result<String> {
+    if value == ZERO_SHA {
+        return Ok(value.to_string());
+    }
+    object_find(repo, value, None, false)?.ok_or_else(|| anyhow!("{}: not a valid SHA1", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::update_ref_stdin;
+    use crate::{
+        ref_resolve,
+        test_utils::{make_commit, test_repo},
+    };
+
+    #[test]
+    fn test_update_ref_transaction() {
+        let repo = test_repo("update-ref");
+        let one = make_commit(&repo, "one", &[]);
+        let two = make_commit(&repo, "two", &[&one]);
+        let input = format!("create refs/heads/a {}\nupdate refs/heads/b {}\n", one, two);
+        update_ref_stdin(&repo, input.as_bytes(), false, "").unwrap();
+        assert_eq!(
+            ref_resolve(&repo, "refs/heads/a").unwrap(),
+            Some(one.clone())
+        );
+
+        // The stale old value of b fails the whole transaction.
+        let input = format!(
+            "update refs/heads/a {}\nupdate refs/heads/b {} {}\n",
+            two, one, one
+        );
+        assert!(update_ref_stdin(&repo, input.as_bytes(), false, "").is_err());
+        assert_eq!(
+            ref_resolve(&repo, "refs/heads/a").unwrap(),
+            Some(one.clone())
+        );
+        assert!(!repo.gitdir.join("refs/heads/a.lock").exists());
+
+        let input = format!("delete refs/heads/b {}\n", two);
+        update_ref_stdin(&repo, input.as_bytes(), false, "").unwrap();
+        assert_eq!(ref_resolve(&repo, "refs/heads/b").unwrap(), None);
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
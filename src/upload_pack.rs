@@ -0,0 +1,323 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    object_read, object_references, pack_write, pkt_flush, pkt_write, pkt_write_sideband,
+    ref_list_flat, ref_resolve, repo_dir, repo_file, Commit, PktReader, Repository, Tag,
+};
+
+/// Capabilities announced by upload-pack. Only whole objects are sent and
+/// negotiation follows the plain (non multi-ack) protocol; `deepen <n>`
+/// limits the history sent to n commits from the wants.
+const UPLOAD_PACK_CAPS: &str = "side-band-64k no-progress shallow";
+
+/// Writes the ref advertisement that opens both services: HEAD and every
+/// ref with its sha, capabilities on the first line, and peeled values for
+/// annotated tags. Refs hidden by `transfer.hideRefs` or the service's own
+/// `<service>.hideRefs` are left out.
+pub fn refs_advertise(
+    repo: &Repository,
+    caps: &str,
+    with_head: bool,
+    service: &str,
+) -> Result<Vec<u8>> {
+    let mut refs: Vec<(String, String)> = Vec::new();
+    let mut caps = format!("{} agent=rit/{}", caps, env!("CARGO_PKG_VERSION"));
+    if with_head {
+        if let Some(sha) =
+            ref_resolve(repo, "HEAD")?.filter(|_| !ref_is_hidden(repo, service, "HEAD"))
+        {
+            refs.push(("HEAD".to_string(), sha));
+        }
+        let head = fs::read_to_string(repo_file(repo, PathBuf::from("HEAD"), false)?)?;
+        if let Some(target) = head.trim_end().strip_prefix("ref: ") {
+            caps = format!("{} symref=HEAD:{}", caps, target);
+        }
+    }
+    if repo_dir(repo, PathBuf::from("refs"), false)?.is_some() {
+        for (name, sha) in ref_list_flat(repo, None, Some("refs"))? {
+            if ref_is_hidden(repo, service, &name) {
+                continue;
+            }
+            let peeled = tag_peel(repo, &sha)?;
+            refs.push((name.clone(), sha));
+            if let Some(peeled) = peeled {
+                refs.push((format!("{}^{{}}", name), peeled));
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    if refs.is_empty() {
+        let line = format!("{} capabilities^{{}}\0{}\n", "0".repeat(40), caps);
+        pkt_write(&mut out, line.as_bytes());
+    }
+    for (i, (name, sha)) in refs.iter().enumerate() {
+        let line = if i == 0 {
+            format!("{} {}\0{}\n", sha, name, caps)
+        } else {
+            format!("{} {}\n", sha, name)
+        };
+        pkt_write(&mut out, line.as_bytes());
+    }
+    pkt_flush(&mut out);
+    Ok(out)
+}
+
+/// Whether `name` is hidden from `service`'s advertisement. Patterns from
+/// `transfer.hideRefs` and `<service>.hideRefs` match a ref and everything
+/// below it; a leading `!` unhides, and the last matching pattern wins.
+pub fn ref_is_hidden(repo: &Repository, service: &str, name: &str) -> bool {
+    let mut patterns = repo.config_get_all("transfer", "hideRefs");
+    patterns.extend(repo.config_get_all(service, "hideRefs"));
+    for pattern in patterns.iter().rev() {
+        let (hide, pattern) = match pattern.strip_prefix('!') {
+            Some(rest) => (false, rest),
+            None => (true, pattern.as_str()),
+        };
+        let pattern = pattern.trim_start_matches('^').trim_end_matches('/');
+        let matches = name
+            .strip_prefix(pattern)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+        if matches {
+            return hide;
+        }
+    }
+    false
+}
+
+/// If `sha` is a tag object, returns the non-tag object it finally points
+/// to.
+pub fn tag_peel(repo: &Repository, sha: &str) -> Result<Option<String>> {
+    let mut peeled = None;
+    let mut current = sha.to_string();
+    while let Some(tag) = object_read(repo, &current)?.as_any().downcast_ref::<Tag>() {
+        let Some(target) = tag.object() else { break };
+        current = target.clone();
+        peeled = Some(target);
+    }
+    Ok(peeled)
+}
+
+pub fn upload_pack_advertise(repo: &Repository) -> Result<Vec<u8>> {
+    refs_advertise(repo, UPLOAD_PACK_CAPS, true, "uploadpack")
+}
+
+/// Answers one stateless upload-pack request: `want` lines, then `have`
+/// lines, optionally closed by `done`. Without `done` only the negotiation
+/// reply is sent; with it, the pack follows.
+///
+/// A client with shallow history names its `shallow` commits after the
+/// wants, and may ask to `deepen` to a depth. The reply then opens with the
+/// commits that become shallow at that depth and those that stop being so.
+pub fn upload_pack(repo: &Repository, request: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = PktReader::new(request);
+    let mut wants = Vec::new();
+    let mut sideband = false;
+    let mut client_shallow: HashSet<String> = HashSet::new();
+    let mut depth = None;
+    while let Some(line) = reader.read_line()? {
+        let line = std::str::from_utf8(line)?;
+        if let Some(sha) = line.strip_prefix("shallow ") {
+            client_shallow.insert(sha.to_string());
+            continue;
+        }
+        if let Some(n) = line.strip_prefix("deepen ") {
+            match n.parse::<usize>() {
+                Ok(n) if n > 0 => depth = Some(n),
+                _ => bail!("Protocol error: bad deepen '{}'", n),
+            }
+            continue;
+        }
+        let Some(rest) = line.strip_prefix("want ") else {
+            bail!("Protocol error: expected want, got '{}'", line);
+        };
+        let mut words = rest.split(' ');
+        let sha = words.next().unwrap_or_default().to_string();
+        if wants.is_empty() {
+            sideband = words.any(|cap| cap == "side-band-64k");
+        }
+        if object_read(repo, &sha).is_err() {
+            bail!("upload-pack: not our ref {}", sha);
+        }
+        wants.push(sha);
+    }
+
+    let mut out = Vec::new();
+    if wants.is_empty() {
+        return Ok(out);
+    }
+
+    // Commits whose parents the client won't have once this reply is in.
+    let mut cut = client_shallow.clone();
+    if let Some(depth) = depth {
+        let boundary = shallow_boundary(repo, &wants, depth)?;
+        let within = objects_reachable_cut(repo, &wants, &boundary)?;
+        for sha in &boundary {
+            if !client_shallow.contains(sha) {
+                pkt_write(&mut out, format!("shallow {}\n", sha).as_bytes());
+            }
+        }
+        for sha in &client_shallow {
+            if within.contains(sha) && !boundary.contains(sha) {
+                pkt_write(&mut out, format!("unshallow {}\n", sha).as_bytes());
+            }
+        }
+        pkt_flush(&mut out);
+        cut = boundary;
+    }
+    // A request of wants alone asks only for the shallow list.
+    if reader.rest().is_empty() {
+        return Ok(out);
+    }
+
+    let mut common = Vec::new();
+    let mut done = false;
+    loop {
+        match reader.read_line()? {
+            Some(b"done") => {
+                done = true;
+                break;
+            }
+            Some(line) => {
+                let line = std::str::from_utf8(line)?;
+                let Some(sha) = line.strip_prefix("have ") else {
+                    bail!("Protocol error: expected have, got '{}'", line);
+                };
+                if object_read(repo, sha).is_ok() {
+                    // Plain negotiation acknowledges only the first common
+                    // object.
+                    if common.is_empty() {
+                        pkt_write(&mut out, format!("ACK {}\n", sha).as_bytes());
+                    }
+                    common.push(sha.to_string());
+                }
+            }
+            None if reader.rest().is_empty() => break,
+            None => {}
+        }
+    }
+
+    if common.is_empty() {
+        pkt_write(&mut out, b"NAK\n");
+    }
+    if !done {
+        return Ok(out);
+    }
+
+    // What the client has stops at its shallow commits.
+    let known = objects_reachable_cut(repo, &common, &client_shallow)?;
+    let mut send: Vec<String> = objects_reachable_cut(repo, &wants, &cut)?
+        .into_iter()
+        .filter(|sha| !known.contains(sha))
+        .collect();
+    send.sort();
+    let pack = pack_write(repo, &send)?;
+
+    if sideband {
+        pkt_write_sideband(&mut out, 1, &pack);
+        pkt_flush(&mut out);
+    } else {
+        out.extend(pack);
+    }
+    Ok(out)
+}
+
+/// The commits at `depth` from `wants`, counting the wants as 1, that have
+/// parents: where history sent to that depth stops.
+fn shallow_boundary(repo: &Repository, wants: &[String], depth: usize) -> Result<HashSet<String>> {
+    let mut queue = VecDeque::new();
+    for sha in wants {
+        let sha = tag_peel(repo, sha)?.unwrap_or_else(|| sha.clone());
+        queue.push_back((sha, 1));
+    }
+    let mut seen = HashSet::new();
+    let mut boundary = HashSet::new();
+    // Breadth first, so that each commit is met at its smallest depth.
+    while let Some((sha, n)) = queue.pop_front() {
+        if !seen.insert(sha.clone()) {
+            continue;
+        }
+        let obj = object_read(repo, &sha)?;
+        let Some(commit) = obj.as_any().downcast_ref::<Commit>() else {
+            continue;
+        };
+        let parents = commit.parents();
+        if n >= depth {
+            if !parents.is_empty() {
+                boundary.insert(sha);
+            }
+            continue;
+        }
+        queue.extend(parents.into_iter().map(|parent| (parent, n + 1)));
+    }
+    Ok(boundary)
+}
+
+/// Every object reachable from `roots`, not going past the parents of the
+/// commits in `cut`. Roots that do not exist are skipped.
+fn objects_reachable_cut(
+    repo: &Repository,
+    roots: &[String],
+    cut: &HashSet<String>,
+) -> Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut stack: Vec<String> = roots
+        .iter()
+        .filter(|sha| object_read(repo, sha).is_ok())
+        .cloned()
+        .collect();
+    while let Some(sha) = stack.pop() {
+        if seen.contains(&sha) {
+            continue;
+        }
+        let obj = object_read(repo, &sha).with_context(|| format!("unable to read {}", sha))?;
+        let is_cut = obj.fmt() == b"commit" && cut.contains(&sha);
+        seen.insert(sha);
+        stack.extend(
+            object_references(obj.as_ref())
+                .into_iter()
+                .filter(|(typ, _)| !(is_cut && typ == "commit"))
+                .map(|(_, child)| child),
+        );
+    }
+    Ok(seen)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    use super::ref_is_hidden;
+    use crate::{repo_open, test_utils::test_repo};
+
+    #[test]
+    fn test_ref_is_hidden() {
+        let repo = test_repo("hide-refs");
+        let mut config = OpenOptions::new()
+            .append(true)
+            .open(repo.gitdir.join("config"))
+            .unwrap();
+        writeln!(
+            config,
+            "[transfer]\n\thideRefs = refs/pull\n\thideRefs = refs/changes/\n\
+             [receive]\n\thideRefs = !refs/pull/keep"
+        )
+        .unwrap();
+        let repo = repo_open(&repo.worktree).unwrap();
+
+        assert!(ref_is_hidden(&repo, "uploadpack", "refs/pull/1/head"));
+        assert!(ref_is_hidden(&repo, "uploadpack", "refs/changes/01/1"));
+        assert!(!ref_is_hidden(&repo, "uploadpack", "refs/pulls"));
+        assert!(!ref_is_hidden(&repo, "uploadpack", "refs/heads/main"));
+        assert!(ref_is_hidden(&repo, "uploadpack", "refs/pull/keep"));
+        assert!(!ref_is_hidden(&repo, "receive", "refs/pull/keep"));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
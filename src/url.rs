@@ -0,0 +1,252 @@
+use crate::{config_global, Repository};
+use anyhow::{anyhow, bail, Result};
+
+/// How a repository URL is reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UrlScheme {
+    /// A path on this machine, bare or behind `file://`.
+    Local,
+    Http,
+    Https,
+    /// The git daemon protocol, `git://`.
+    Git,
+    /// `ssh://` and the scp-like `[user@]host:path`.
+    Ssh,
+}
+
+/// A repository URL split into its parts.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GitUrl {
+    pub scheme: UrlScheme,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Empty for local paths.
+    pub host: String,
+    pub port: Option<u16>,
+    pub path: String,
+}
+
+impl GitUrl {
+    /// Parses `url` as git does: `<scheme>://[user[:password]@]host[:port]/path`,
+    /// then the scp-like `[user@]host:path` when a colon comes before any
+    /// slash, and anything else as a local path.
+    pub fn parse(url: &str) -> Result<GitUrl> {
+        let local = |path: &str| GitUrl {
+            scheme: UrlScheme::Local,
+            user: None,
+            password: None,
+            host: String::new(),
+            port: None,
+            path: path.to_string(),
+        };
+        if let Some((scheme, rest)) = url.split_once("://") {
+            let scheme = match scheme {
+                "file" => return Ok(local(rest)),
+                "http" => UrlScheme::Http,
+                "https" => UrlScheme::Https,
+                "git" => UrlScheme::Git,
+                "ssh" | "git+ssh" | "ssh+git" => UrlScheme::Ssh,
+                _ => bail!("Unable to find remote helper for '{}'", scheme),
+            };
+            let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            let (userinfo, hostport) = match authority.rsplit_once('@') {
+                Some((userinfo, hostport)) => (Some(userinfo), hostport),
+                None => (None, authority),
+            };
+            let (user, password) = match userinfo.map(|u| u.split_once(':')) {
+                Some(Some((user, password))) => (Some(user), Some(password)),
+                Some(None) => (userinfo, None),
+                None => (None, None),
+            };
+            let (host, port) = url_host_port(hostport)?;
+            // "ssh://host/~user/repo" is relative to a home directory.
+            let path = match (scheme, path.strip_prefix("/~")) {
+                (UrlScheme::Ssh | UrlScheme::Git, Some(home)) => format!("~{}", home),
+                _ if path.is_empty() => "/".to_string(),
+                _ => path.to_string(),
+            };
+            return GitUrl {
+                scheme,
+                user: user.map(str::to_string),
+                password: password.map(str::to_string),
+                host,
+                port,
+                path,
+            }
+            .checked(url);
+        }
+
+        match (url.find(':'), url.find('/')) {
+            (Some(colon), slash) if slash.is_none_or(|slash| colon < slash) => {
+                let (hostpart, path) = (&url[..colon], &url[colon + 1..]);
+                let (user, host) = match hostpart.rsplit_once('@') {
+                    Some((user, host)) => (Some(user.to_string()), host),
+                    None => (None, hostpart),
+                };
+                GitUrl {
+                    scheme: UrlScheme::Ssh,
+                    user,
+                    password: None,
+                    host: host
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .to_string(),
+                    port: None,
+                    path: path.to_string(),
+                }
+                .checked(url)
+            }
+            _ => Ok(local(url)),
+        }
+    }
+
+    /// Refuses hosts and paths that an ssh client would take for options.
+    fn checked(self, url: &str) -> Result<GitUrl> {
+        if self.host.is_empty() {
+            bail!("No host in URL '{}'", url);
+        }
+        if self.host.starts_with('-') {
+            bail!("strange hostname '{}' blocked", self.host);
+        }
+        if self.scheme == UrlScheme::Ssh && self.path.starts_with('-') {
+            bail!("strange pathname '{}' blocked", self.path);
+        }
+        Ok(self)
+    }
+
+    /// "[user@]host", as an ssh client takes it.
+    pub fn user_host(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{}@{}", user, self.host),
+            None => self.host.clone(),
+        }
+    }
+}
+
+/// Splits "host[:port]", where the host may be a bracketed IPv6 address.
+fn url_host_port(hostport: &str) -> Result<(String, Option<u16>)> {
+    let (host, port) = match hostport.strip_prefix('[') {
+        Some(rest) => {
+            let (host, after) = rest
+                .split_once(']')
+                .ok_or_else(|| anyhow!("bad IPv6 address '{}'", hostport))?;
+            (host, after.strip_prefix(':'))
+        }
+        None => match hostport.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (hostport, None),
+        },
+    };
+    let port = match port.filter(|p| !p.is_empty()) {
+        Some(port) => Some(
+            port.parse()
+                .map_err(|_| anyhow!("invalid port number '{}'", port))?,
+        ),
+        None => None,
+    };
+    Ok((host.to_string(), port))
+}
+
+/// Applies the `url.<base>.insteadOf` rewrites to `url`, or for a push
+/// `url.<base>.pushInsteadOf` first. The longest matching prefix wins, as
+/// in git. Without `repo` only the system's and user's config is read.
+pub fn url_rewrite(repo: Option<&Repository>, url: &str, push: bool) -> String {
+    let loaded;
+    let global = match repo {
+        Some(repo) => repo.config_global(),
+        None => {
+            loaded = config_global();
+            &loaded
+        }
+    };
+    let mut sections = global.sections();
+    if let Some(repo) = repo {
+        sections.extend(repo.config_sections());
+    }
+    let values = |section: &str, key: &str| match repo {
+        Some(repo) => repo.config_get_all(section, key),
+        None => global.get_all(section, key),
+    };
+
+    let keys: &[&str] = if push {
+        &["pushInsteadOf", "insteadOf"]
+    } else {
+        &["insteadOf"]
+    };
+    for key in keys {
+        let mut best: Option<(String, String)> = None;
+        for section in &sections {
+            let Some(base) = section
+                .strip_prefix("url \"")
+                .and_then(|s| s.strip_suffix('"'))
+            else {
+                continue;
+            };
+            for prefix in values(section, key) {
+                let longer = best.as_ref().is_none_or(|(p, _)| prefix.len() > p.len());
+                if url.starts_with(&prefix) && longer {
+                    best = Some((prefix, base.to_string()));
+                }
+            }
+        }
+        if let Some((prefix, base)) = best {
+            return format!("{}{}", base, &url[prefix.len()..]);
+        }
+    }
+    url.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{url_rewrite, GitUrl, UrlScheme};
+    use crate::test_utils::test_repo;
+
+    #[test]
+    fn test_git_url() {
+        let url = GitUrl::parse("ssh://git@example.com:2222/~me/app.git").unwrap();
+        assert_eq!(url.scheme, UrlScheme::Ssh);
+        assert_eq!(url.user_host(), "git@example.com");
+        assert_eq!(url.port, Some(2222));
+        assert_eq!(url.path, "~me/app.git");
+
+        let url = GitUrl::parse("https://me:secret@[::1]:8443/repo").unwrap();
+        assert_eq!(url.scheme, UrlScheme::Https);
+        assert_eq!(url.password.as_deref(), Some("secret"));
+        assert_eq!((url.host.as_str(), url.port), ("::1", Some(8443)));
+
+        let url = GitUrl::parse("git@github.com:org/app.git").unwrap();
+        assert_eq!(url.scheme, UrlScheme::Ssh);
+        assert_eq!(
+            (url.user_host().as_str(), url.path.as_str()),
+            ("git@github.com", "org/app.git")
+        );
+
+        assert_eq!(GitUrl::parse("./dir:x").unwrap().scheme, UrlScheme::Local);
+        assert_eq!(GitUrl::parse("file:///srv/a").unwrap().path, "/srv/a");
+        assert_eq!(
+            GitUrl::parse("git://host/a").unwrap().scheme,
+            UrlScheme::Git
+        );
+        assert!(GitUrl::parse("-oProxyCommand=x:path").is_err());
+        assert!(GitUrl::parse("http://host:port/").is_err());
+        assert!(GitUrl::parse("svn://host/a").is_err());
+
+        let mut repo = test_repo("url-rewrite");
+        repo.config_add("url \"git@github.com:\"", "insteadOf", "gh:")
+            .unwrap();
+        repo.config_add("url \"https://github.com/\"", "insteadOf", "g")
+            .unwrap();
+        repo.config_add("url \"ssh://push/\"", "pushInsteadOf", "gh:")
+            .unwrap();
+        assert_eq!(
+            url_rewrite(Some(&repo), "gh:a/b", false),
+            "git@github.com:a/b"
+        );
+        assert_eq!(url_rewrite(Some(&repo), "gh:a/b", true), "ssh://push/a/b");
+        assert_eq!(
+            url_rewrite(Some(&repo), "gx", false),
+            "https://github.com/x"
+        );
+        assert_eq!(url_rewrite(Some(&repo), "/local", false), "/local");
+    }
+}
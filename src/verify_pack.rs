@@ -0,0 +1,119 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{pack_index_verify, repo_find, PackFile};
+
+/// Checks each pack, given by its `.idx` or `.pack`: the checksums of
+/// both files and every object in it. `verbose` lists the objects, as
+/// `<sha> <type> <size> <size in pack> <offset> [<depth> <base>]`, followed
+/// by a histogram of delta chain lengths; `stat_only` prints just that.
+pub fn verify_pack(paths: &[PathBuf], verbose: bool, stat_only: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    for path in paths {
+        let idx_path = path.with_extension("idx");
+        pack_index_verify(&idx_path)?;
+        let pack = PackFile::load(&idx_path)?;
+        pack.verify()?;
+        let stats = pack.verify_objects(&repo)?;
+        if !(verbose || stat_only) {
+            continue;
+        }
+
+        let mut non_delta = 0;
+        let mut chains: BTreeMap<usize, usize> = BTreeMap::new();
+        for stat in &stats {
+            match &stat.delta {
+                Some((depth, _)) => *chains.entry(*depth).or_default() += 1,
+                None => non_delta += 1,
+            }
+            if stat_only {
+                continue;
+            }
+            let line = format!(
+                "{} {:<6} {} {} {}",
+                stat.sha, stat.fmt, stat.size, stat.packed_size, stat.offset
+            );
+            match &stat.delta {
+                Some((depth, base)) => println!("{} {} {}", line, depth, base),
+                None => println!("{}", line),
+            }
+        }
+        let objects = |n: usize| format!("{} object{}", n, if n == 1 { "" } else { "s" });
+        if non_delta > 0 {
+            println!("non delta: {}", objects(non_delta));
+        }
+        for (depth, n) in chains {
+            println!("chain length = {}: {}", depth, objects(n));
+        }
+        if verbose {
+            println!("{}: ok", path.with_extension("pack").display());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use sha1::{Digest, Sha1};
+
+    use crate::{
+        object_list_loose, pack_index_verify, pack_store,
+        test_utils::{make_commit, test_repo},
+        PackFile,
+    };
+
+    #[test]
+    fn test_verify_pack() {
+        let repo = test_repo("verify-pack");
+        let text = "line\n".repeat(100);
+        let first = make_commit(&repo, &text, &[]);
+        make_commit(&repo, &format!("{}changed\n", text), &[&first]);
+        let mut shas = object_list_loose(&repo).unwrap();
+        shas.sort();
+        let pack_path = pack_store(&repo, &shas).unwrap();
+        let idx_path = pack_path.with_extension("idx");
+
+        pack_index_verify(&idx_path).unwrap();
+        let pack = PackFile::load(&idx_path).unwrap();
+        pack.verify().unwrap();
+        let stats = pack.verify_objects(&repo).unwrap();
+        let mut names: Vec<_> = stats.iter().map(|s| s.sha.clone()).collect();
+        names.sort();
+        assert_eq!(names, shas);
+        assert!(stats.windows(2).all(|w| w[0].offset < w[1].offset));
+        let commit = stats.iter().find(|s| s.sha == first).unwrap();
+        assert_eq!(commit.fmt, "commit");
+
+        // Damage an object but fix up the pack's checksum, and the copy of
+        // it in the index, so that only the object's CRC can tell.
+        let mut data = fs::read(&pack_path).unwrap();
+        let victim = &stats[stats.len() / 2];
+        data[victim.offset as usize + victim.packed_size - 1] ^= 0xff;
+        let body_len = data.len() - 20;
+        let checksum = Sha1::digest(&data[..body_len]);
+        data[body_len..].copy_from_slice(&checksum);
+        fs::write(&pack_path, &data).unwrap();
+        let mut idx = fs::read(&idx_path).unwrap();
+        let idx_len = idx.len();
+        idx[idx_len - 40..idx_len - 20].copy_from_slice(&checksum);
+        fs::write(&idx_path, &idx).unwrap();
+
+        let pack = PackFile::load(&idx_path).unwrap();
+        pack.verify().unwrap();
+        let err = pack.verify_objects(&repo).err().unwrap().to_string();
+        assert!(err.contains(&format!("CRC mismatch for object {}", victim.sha)));
+        assert!(pack_index_verify(&idx_path).is_err());
+
+        // Without the fix-up the pack's own checksum gives it away.
+        data[body_len] ^= 0xff;
+        fs::write(&pack_path, &data).unwrap();
+        assert!(PackFile::load(&idx_path).is_err());
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,465 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::{
+    branch_name_valid, checkout_file, commit_subject, head_attach, index_read, index_write,
+    object_find, ref_create, ref_resolve, repo_find, repo_open, sha_short, status_dirty_paths,
+    status_untracked, tree_to_leaves, Index, IndexEntry, Repository, ZERO_SHA,
+};
+
+/// A checkout of the repository: the main worktree or a linked one.
+pub struct Worktree {
+    pub path: PathBuf,
+    /// The linked worktree's own git directory, `$GIT_DIR/worktrees/<name>`.
+    /// None for the main worktree.
+    pub admin: Option<PathBuf>,
+    /// The commit checked out, None on an unborn branch.
+    pub head: Option<String>,
+    /// The branch checked out, None when HEAD is detached.
+    pub branch: Option<String>,
+    /// The main worktree of a bare repository, which has no checkout.
+    pub bare: bool,
+    pub locked: bool,
+}
+
+pub fn worktree_add(
+    path: &Path,
+    new_branch: Option<&str>,
+    detach: bool,
+    force: bool,
+    commit_ish: Option<&str>,
+) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let path = std::env::current_dir()?.join(path);
+    let start = commit_ish.unwrap_or("HEAD");
+    let sha = object_find(&repo, start, Some(b"commit"), true)?
+        .ok_or_else(|| anyhow!("invalid reference: {}", start))?;
+
+    // Without a branch to create or check out, one named after the new
+    // directory is, unless a detached HEAD was asked for.
+    let is_branch = |name: &str| -> Result<bool> {
+        Ok(ref_resolve(&repo, &format!("refs/heads/{}", name))?.is_some())
+    };
+    let (branch, created) = match (new_branch, commit_ish) {
+        (Some(name), _) => {
+            if !branch_name_valid(name) {
+                bail!("'{}' is not a valid branch name", name);
+            }
+            if is_branch(name)? {
+                bail!("a branch named '{}' already exists", name);
+            }
+            (Some(name.to_string()), true)
+        }
+        (None, Some(name)) if !detach && is_branch(name)? => (Some(name.to_string()), false),
+        (None, None) if !detach => {
+            let name = path
+                .file_name()
+                .ok_or_else(|| anyhow!("invalid path '{}'", path.display()))?
+                .to_string_lossy()
+                .to_string();
+            let exists = is_branch(&name)?;
+            (Some(name), !exists)
+        }
+        _ => (None, false),
+    };
+
+    match &branch {
+        Some(name) if created => {
+            println!("Preparing worktree (new branch '{}')", name);
+            ref_create(&repo, &format!("heads/{}", name), &sha)?;
+        }
+        Some(name) => {
+            if let Some(holder) = worktree_branch_holder(&repo, name)? {
+                if !force {
+                    bail!(
+                        "'{}' is already used by worktree at '{}'",
+                        name,
+                        holder.display()
+                    );
+                }
+            }
+            println!("Preparing worktree (checking out '{}')", name);
+        }
+        None => println!(
+            "Preparing worktree (detached HEAD {})",
+            sha_short(&repo, &sha)
+        ),
+    }
+
+    worktree_create(&repo, &path, branch.as_deref(), &sha)?;
+    println!(
+        "HEAD is now at {} {}",
+        sha_short(&repo, &sha),
+        commit_subject(&repo, &sha)?
+    );
+    Ok(())
+}
+
+/// Sets up a linked worktree at the absolute `path` with `sha` checked out,
+/// on `branch` (which must already point at `sha`) or on a detached HEAD.
+/// Returns the repository as seen from the new worktree.
+pub fn worktree_create(
+    repo: &Repository,
+    path: &Path,
+    branch: Option<&str>,
+    sha: &str,
+) -> Result<Repository> {
+    if path.exists() && fs::read_dir(path)?.next().is_some() {
+        bail!("'{}' already exists", path.display());
+    }
+    fs::create_dir_all(path)?;
+    let path = fs::canonicalize(path)?;
+
+    let base = path
+        .file_name()
+        .ok_or_else(|| anyhow!("invalid path '{}'", path.display()))?
+        .to_string_lossy()
+        .to_string();
+    let worktrees = repo.commondir.join("worktrees");
+    let mut admin = worktrees.join(&base);
+    let mut counter = 1;
+    while admin.exists() {
+        admin = worktrees.join(format!("{}{}", base, counter));
+        counter += 1;
+    }
+    fs::create_dir_all(&admin)?;
+    fs::write(admin.join("commondir"), "../..\n")?;
+    fs::write(
+        admin.join("gitdir"),
+        format!("{}\n", path.join(".git").display()),
+    )?;
+    fs::write(admin.join("HEAD"), format!("{}\n", sha))?;
+    fs::write(path.join(".git"), format!("gitdir: {}\n", admin.display()))?;
+
+    let linked = repo_open(&path)?;
+    let mut index = Index::default();
+    for leaf in tree_to_leaves(&linked, sha, "")? {
+        checkout_file(&linked, &leaf)?;
+        index.entries.push(IndexEntry::from_file(
+            &linked, &leaf.path, &leaf.sha, &leaf.mode,
+        )?);
+    }
+    index.entries.sort_by(|a, b| a.name.cmp(&b.name));
+    index_write(&linked, &index)?;
+    if let Some(branch) = branch {
+        head_attach(&linked, branch)?;
+    }
+    Ok(linked)
+}
+
+pub fn worktree_list() -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let worktrees = worktrees_read(&repo)?;
+    // Paths are padded one past the longest, as git does.
+    let width = worktrees
+        .iter()
+        .map(|wt| wt.path.display().to_string().len() + 1)
+        .max()
+        .unwrap_or(0);
+    for wt in worktrees {
+        let mut line = format!("{:<width$} ", wt.path.display().to_string());
+        if wt.bare {
+            line.push_str("(bare)");
+        } else {
+            let head = wt.head.as_deref().unwrap_or(ZERO_SHA);
+            line.push_str(&sha_short(&repo, head));
+            match &wt.branch {
+                Some(branch) => line.push_str(&format!(" [{}]", branch)),
+                None => line.push_str(" (detached HEAD)"),
+            }
+        }
+        if wt.locked {
+            line.push_str(" locked");
+        }
+        if wt
+            .admin
+            .as_deref()
+            .and_then(worktree_prune_reason)
+            .is_some()
+        {
+            line.push_str(" prunable");
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+/// Every worktree of the repository, the main one first and then the
+/// linked ones by the name of their git directory.
+pub fn worktrees_read(repo: &Repository) -> Result<Vec<Worktree>> {
+    let bare = repo.commondir.file_name().is_none_or(|n| n != ".git");
+    let main_path = if bare {
+        repo.commondir.clone()
+    } else {
+        repo.commondir
+            .parent()
+            .unwrap_or(&repo.commondir)
+            .to_path_buf()
+    };
+    let (head, branch) = worktree_head(repo, &repo.commondir)?;
+    let mut ret = vec![Worktree {
+        path: main_path,
+        admin: None,
+        head,
+        branch,
+        bare,
+        locked: false,
+    }];
+
+    let Ok(entries) = fs::read_dir(repo.commondir.join("worktrees")) else {
+        return Ok(ret);
+    };
+    let mut admins: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.join("HEAD").is_file())
+        .collect();
+    admins.sort();
+    for admin in admins {
+        let path = fs::read_to_string(admin.join("gitdir"))
+            .ok()
+            .map(|gitdir| {
+                let dotgit = PathBuf::from(gitdir.trim_end());
+                dotgit.parent().map(Path::to_path_buf).unwrap_or(dotgit)
+            })
+            .unwrap_or_else(|| admin.clone());
+        let (head, branch) = worktree_head(repo, &admin)?;
+        ret.push(Worktree {
+            path,
+            locked: admin.join("locked").exists(),
+            admin: Some(admin),
+            head,
+            branch,
+            bare: false,
+        });
+    }
+    Ok(ret)
+}
+
+/// Reads the HEAD in the git directory `gitdir`: the commit it resolves to
+/// and the branch it is on, if any.
+fn worktree_head(repo: &Repository, gitdir: &Path) -> Result<(Option<String>, Option<String>)> {
+    let content = fs::read_to_string(gitdir.join("HEAD"))?;
+    let content = content.trim_end();
+    match content.strip_prefix("ref: ") {
+        Some(refname) => {
+            let branch = refname.strip_prefix("refs/heads/").map(str::to_string);
+            Ok((ref_resolve(repo, refname)?, branch))
+        }
+        None => Ok((Some(content.to_string()), None)),
+    }
+}
+
+/// The worktree that has `branch` checked out, if any.
+pub fn worktree_branch_holder(repo: &Repository, branch: &str) -> Result<Option<PathBuf>> {
+    Ok(worktrees_read(repo)?
+        .into_iter()
+        .find(|wt| !wt.bare && wt.branch.as_deref() == Some(branch))
+        .map(|wt| wt.path))
+}
+
+/// Why the linked worktree with git directory `admin` is stale, or None if
+/// it is still there (or locked, which keeps it either way).
+fn worktree_prune_reason(admin: &Path) -> Option<String> {
+    if admin.join("locked").exists() {
+        return None;
+    }
+    let Ok(gitdir) = fs::read_to_string(admin.join("gitdir")) else {
+        return Some("gitdir file does not exist".to_string());
+    };
+    if !Path::new(gitdir.trim_end()).exists() {
+        return Some("gitdir file points to non-existent location".to_string());
+    }
+    None
+}
+
+/// Deletes the linked worktree at `path` along with its git directory.
+/// Unless `force` is set, worktrees with local changes or untracked files
+/// and locked ones are refused.
+pub fn worktree_remove(path: &Path, force: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let (wt, admin) = worktree_find_linked(&repo, path)?;
+    if wt.locked && !force {
+        bail!("cannot remove a locked working tree");
+    }
+    if !force {
+        let linked = repo_open(&wt.path)?;
+        let index = index_read(&linked)?;
+        if !status_dirty_paths(&linked)?.is_empty()
+            || !status_untracked(&linked, &index)?.is_empty()
+        {
+            bail!(
+                "'{}' contains modified or untracked files, use --force to delete it",
+                path.display()
+            );
+        }
+    }
+    fs::remove_dir_all(&wt.path)?;
+    fs::remove_dir_all(admin)?;
+    Ok(())
+}
+
+/// The linked worktree at `path` and its git directory.
+fn worktree_find_linked(repo: &Repository, path: &Path) -> Result<(Worktree, PathBuf)> {
+    let target = fs::canonicalize(path)
+        .map_err(|_| anyhow!("'{}' is not a working tree", path.display()))?;
+    let wt = worktrees_read(repo)?
+        .into_iter()
+        .find(|wt| wt.path == target)
+        .ok_or_else(|| anyhow!("'{}' is not a working tree", path.display()))?;
+    match wt.admin.clone() {
+        Some(admin) => Ok((wt, admin)),
+        None => bail!("'{}' is a main working tree", path.display()),
+    }
+}
+
+/// Keeps the linked worktree at `path` from being pruned, moved or
+/// removed, e.g. while it lives on a drive that isn't mounted.
+pub fn worktree_lock(path: &Path, reason: Option<&str>) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let (_, admin) = worktree_find_linked(&repo, path)?;
+    if let Ok(existing) = fs::read_to_string(admin.join("locked")) {
+        match existing.trim_end() {
+            "" => bail!("'{}' is already locked", path.display()),
+            why => bail!("'{}' is already locked, reason: {}", path.display(), why),
+        }
+    }
+    fs::write(admin.join("locked"), reason.unwrap_or_default())?;
+    Ok(())
+}
+
+pub fn worktree_unlock(path: &Path) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let (wt, admin) = worktree_find_linked(&repo, path)?;
+    if !wt.locked {
+        bail!("'{}' is not locked", path.display());
+    }
+    fs::remove_file(admin.join("locked"))?;
+    Ok(())
+}
+
+/// Restores the links between linked worktrees and their git directories:
+/// each worktree's `.git` file must name its git directory, whose `gitdir`
+/// file must name the `.git` file back. `paths` are worktrees that were
+/// moved, found through their `.git` files; the others are checked from
+/// the repository's side.
+pub fn worktree_repair(paths: &[PathBuf]) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let worktrees = repo.commondir.join("worktrees");
+    for path in paths {
+        let path = fs::canonicalize(path)?;
+        let dotgit = path.join(".git");
+        let content = fs::read_to_string(&dotgit)
+            .map_err(|_| anyhow!("repair: .git file missing: {}", dotgit.display()))?;
+        let pointed = content
+            .trim_end()
+            .strip_prefix("gitdir: ")
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow!("repair: invalid .git file: {}", dotgit.display()))?;
+        // The git directory keeps its name when the repository moves.
+        let admin = worktrees.join(pointed.file_name().unwrap_or_default());
+        if !admin.join("HEAD").is_file() {
+            bail!("repair: not a valid worktree: {}", path.display());
+        }
+        if pointed != admin {
+            eprintln!("repair: .git file incorrect: {}", dotgit.display());
+            fs::write(&dotgit, format!("gitdir: {}\n", admin.display()))?;
+        }
+        let recorded = fs::read_to_string(admin.join("gitdir")).unwrap_or_default();
+        if Path::new(recorded.trim_end()) != dotgit {
+            eprintln!("repair: gitdir incorrect: {}", dotgit.display());
+            fs::write(admin.join("gitdir"), format!("{}\n", dotgit.display()))?;
+        }
+    }
+
+    for wt in worktrees_read(&repo)? {
+        let Some(admin) = wt.admin else { continue };
+        let dotgit = wt.path.join(".git");
+        let Ok(content) = fs::read_to_string(&dotgit) else {
+            continue;
+        };
+        let pointed = content.trim_end().strip_prefix("gitdir: ").map(Path::new);
+        if pointed != Some(admin.as_path()) {
+            eprintln!("repair: .git file broken: {}", wt.path.display());
+            fs::write(&dotgit, format!("gitdir: {}\n", admin.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes the git directories of linked worktrees that no longer exist,
+/// and anything in `$GIT_DIR/worktrees` that isn't a git directory.
+pub fn worktree_prune(dry_run: bool, verbose: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let Ok(entries) = fs::read_dir(repo.commondir.join("worktrees")) else {
+        return Ok(());
+    };
+    let mut admins: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+    admins.sort();
+    for admin in admins {
+        let reason = if !admin.join("HEAD").is_file() {
+            Some("not a valid directory".to_string())
+        } else {
+            worktree_prune_reason(&admin)
+        };
+        let Some(reason) = reason else {
+            continue;
+        };
+        if verbose || dry_run {
+            let name = admin.file_name().unwrap_or_default().to_string_lossy();
+            println!("Removing worktrees/{}: {}", name, reason);
+        }
+        if !dry_run {
+            match admin.is_dir() {
+                true => fs::remove_dir_all(&admin)?,
+                false => fs::remove_file(&admin)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        index_read, ref_create, ref_resolve, repo_find,
+        test_utils::{make_commit, test_repo},
+        worktree_create, worktrees_read,
+    };
+
+    #[test]
+    fn test_worktree_create() {
+        let repo = test_repo("worktree");
+        let first = make_commit(&repo, "one", &[]);
+        ref_create(&repo, "heads/master", &first).unwrap();
+        ref_create(&repo, "heads/topic", &first).unwrap();
+
+        let path = repo.worktree.join("linked");
+        let linked = worktree_create(&repo, &path, Some("topic"), &first).unwrap();
+        assert_eq!(fs::read_to_string(path.join("file")).unwrap(), "one");
+        assert_eq!(index_read(&linked).unwrap().entries.len(), 1);
+
+        // Refs are shared, HEAD is not.
+        let found = repo_find(&path, true).unwrap().unwrap();
+        assert_eq!(found.commondir, repo.commondir);
+        let second = make_commit(&found, "two", &[&first]);
+        ref_create(&found, "heads/topic", &second).unwrap();
+        assert_eq!(ref_resolve(&found, "HEAD").unwrap(), Some(second.clone()));
+        assert_eq!(ref_resolve(&repo, "HEAD").unwrap(), Some(first));
+        assert_eq!(
+            ref_resolve(&repo, "refs/heads/topic").unwrap(),
+            Some(second)
+        );
+
+        let worktrees = worktrees_read(&repo).unwrap();
+        assert_eq!(worktrees.len(), 2);
+        assert_eq!(worktrees[1].branch.as_deref(), Some("topic"));
+        fs::remove_dir_all(&repo.worktree).unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::{index_read, index_write_tree, object_info, repo_find};
+
+/// Writes the trees the index records and prints the root tree's sha, or
+/// with `prefix` that of the tree for the directory there. Unless
+/// `missing_ok`, every blob the index names must be in the object database.
+pub fn write_tree(prefix: Option<&str>, missing_ok: bool) -> Result<()> {
+    let repo = repo_find(Path::new("."), true)?.unwrap();
+    let mut index = index_read(&repo)?;
+    if !missing_ok {
+        // Submodule commits live in their own repositories.
+        for entry in index.entries.iter().filter(|e| e.mode_type != 0b1110) {
+            if object_info(&repo, &entry.sha).is_err() {
+                bail!(
+                    "invalid object {} {} for '{}'",
+                    String::from_utf8_lossy(&entry.tree_mode()),
+                    entry.sha,
+                    entry.name
+                );
+            }
+        }
+    }
+    if let Some(prefix) = prefix {
+        let dir = format!("{}/", prefix.trim_end_matches('/'));
+        let entries: Vec<_> = std::mem::take(&mut index.entries)
+            .into_iter()
+            .filter_map(|mut e| {
+                e.name = e.name.strip_prefix(&dir)?.to_string();
+                Some(e)
+            })
+            .collect();
+        if entries.is_empty() {
+            bail!("prefix {} not found", dir);
+        }
+        index.entries = entries;
+    }
+    println!("{}", index_write_tree(&repo, &index)?);
+    Ok(())
+}